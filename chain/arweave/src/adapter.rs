@@ -1,42 +1,48 @@
 use graph::bytes::Bytes;
-use graph::components::arweave::ArweaveAdapter as ArweaveAdapterTrait;
+use graph::components::arweave::{is_valid_tx_id, ArweaveAdapter as ArweaveAdapterTrait};
 use graph::prelude::*;
 use graph::url::Url;
 use reqwest::header;
+use serde::de::DeserializeOwned;
+
+use crate::types::{ArweaveBlock, ArweaveTransaction};
 
 pub struct ArweaveAdapter {
-    endpoint: Url,
+    gateways: Vec<Url>,
     http_client: reqwest::Client,
 }
 
 impl ArweaveAdapter {
     /// Panics if `endpoint` is not a valid URL.
-    pub fn new(mut endpoint: String) -> Self {
-        // Make sure the endpoint has a trailing slash so `Url::join` works.
-        if !endpoint.ends_with('/') {
-            endpoint.push('/')
-        }
+    pub fn new(endpoint: String) -> Self {
+        Self::with_gateways(vec![endpoint])
+    }
+
+    /// Fetch transaction data from the given gateways. Each request goes to
+    /// the first gateway, and to the next one if it fails. Panics if one of
+    /// the gateways is not a valid URL or if there are none.
+    pub fn with_gateways(gateways: Vec<String>) -> Self {
+        assert!(!gateways.is_empty(), "No Arweave gateways given");
+        let gateways = gateways
+            .into_iter()
+            .map(|mut gateway| {
+                // Make sure the gateway has a trailing slash so `Url::join` works.
+                if !gateway.ends_with('/') {
+                    gateway.push('/')
+                }
+                Url::parse(&gateway).expect("Invalid Arweave URL")
+            })
+            .collect();
 
         ArweaveAdapter {
-            endpoint: Url::parse(&endpoint).expect("Invalid Arweave URL"),
+            gateways,
             http_client: reqwest::Client::new(),
         }
     }
-}
-
-#[async_trait]
-impl ArweaveAdapterTrait for ArweaveAdapter {
-    async fn tx_data(&self, tx_id: &str) -> Result<Bytes, Error> {
-        // Check that the user input is encoded in base64url, and is therefore safe to interpolate.
-        if !tx_id
-            .chars()
-            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
-        {
-            return Err(format_err!("Invalid Arweave transaction id: `{}`", tx_id));
-        }
 
+    async fn tx_data_from(&self, gateway: &Url, tx_id: &str) -> Result<Bytes, Error> {
         self.http_client
-            .get(self.endpoint.join(&format!("tx/{}/data.", tx_id)).unwrap())
+            .get(gateway.join(&format!("tx/{}/data.", tx_id)).unwrap())
             .header(
                 header::CONTENT_TYPE,
                 header::HeaderValue::from_static("application/octet-stream"),
@@ -48,4 +54,107 @@ impl ArweaveAdapterTrait for ArweaveAdapter {
             .err_into()
             .await
     }
+
+    async fn json_from<T: DeserializeOwned>(&self, gateway: &Url, path: &str) -> Result<T, Error> {
+        let body = self
+            .http_client
+            .get(gateway.join(path).unwrap())
+            .timeout(Duration::from_secs(60))
+            .send()
+            .and_then(|res| async { res.error_for_status() })
+            .and_then(|res| res.bytes())
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Fetch `path` from the first gateway that returns it.
+    async fn json<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let mut errors = vec![];
+        for gateway in &self.gateways {
+            match self.json_from(gateway, path).await {
+                Ok(value) => return Ok(value),
+                Err(e) => errors.push(format!("{}: {}", gateway, e)),
+            }
+        }
+        Err(format_err!(
+            "Failed to fetch `{}` from Arweave: {}",
+            path,
+            errors.join(", ")
+        ))
+    }
+
+    /// The height of the latest block that the gateways know about.
+    pub async fn latest_height(&self) -> Result<u64, Error> {
+        #[derive(Deserialize)]
+        struct Info {
+            height: u64,
+        }
+
+        self.json::<Info>("info").await.map(|info| info.height)
+    }
+
+    pub async fn block_by_height(&self, height: u64) -> Result<ArweaveBlock, Error> {
+        self.json(&format!("block/height/{}", height)).await
+    }
+
+    /// The transaction with id `tx_id`, without its data.
+    pub async fn tx(&self, tx_id: &str) -> Result<ArweaveTransaction, Error> {
+        check_tx_id(tx_id)?;
+        self.json(&format!("tx/{}", tx_id)).await
+    }
+
+    /// Stream the blocks starting at height `from`, polling the gateways for
+    /// new blocks every `polling_interval` once the stream has caught up
+    /// with the chain head. Blocks are only streamed once they are
+    /// `confirmations` blocks behind the head, since blocks closer to the
+    /// head can still be replaced by a fork.
+    pub fn blocks(
+        self: Arc<Self>,
+        from: u64,
+        confirmations: u64,
+        polling_interval: Duration,
+    ) -> impl futures03::Stream<Item = Result<ArweaveBlock, Error>> {
+        futures03::stream::try_unfold(from, move |next| {
+            let adapter = self.clone();
+            async move {
+                loop {
+                    let head = adapter.latest_height().await?;
+                    if head.saturating_sub(confirmations) >= next {
+                        let block = adapter.block_by_height(next).await?;
+                        return Ok(Some((block, next + 1)));
+                    }
+                    tokio::time::delay_for(polling_interval).await;
+                }
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl ArweaveAdapterTrait for ArweaveAdapter {
+    async fn tx_data(&self, tx_id: &str) -> Result<Bytes, Error> {
+        check_tx_id(tx_id)?;
+
+        let mut errors = vec![];
+        for gateway in &self.gateways {
+            match self.tx_data_from(gateway, tx_id).await {
+                Ok(data) => return Ok(data),
+                Err(e) => errors.push(format!("{}: {}", gateway, e)),
+            }
+        }
+        Err(format_err!(
+            "Failed to fetch Arweave transaction `{}`: {}",
+            tx_id,
+            errors.join(", ")
+        ))
+    }
+}
+
+// Check that the user input is encoded in base64url, and is therefore safe to interpolate.
+fn check_tx_id(tx_id: &str) -> Result<(), Error> {
+    if is_valid_tx_id(tx_id) {
+        Ok(())
+    } else {
+        Err(format_err!("Invalid Arweave transaction id: `{}`", tx_id))
+    }
 }
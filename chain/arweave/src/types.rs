@@ -0,0 +1,116 @@
+use graph::prelude::*;
+use serde::de;
+
+/// A block as returned by the `/block/height/{height}` endpoint of a gateway.
+/// Hashes are 48 bytes long and, like all binary fields, base64url encoded
+/// without padding in the gateway's JSON.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ArweaveBlock {
+    #[serde(deserialize_with = "deserialize_base64url")]
+    pub indep_hash: Vec<u8>,
+    /// Empty for the genesis block
+    #[serde(deserialize_with = "deserialize_base64url")]
+    pub previous_block: Vec<u8>,
+    pub height: u64,
+    pub timestamp: u64,
+    /// The ids of the transactions in the block
+    pub txs: Vec<String>,
+}
+
+impl ArweaveBlock {
+    pub fn hash(&self) -> String {
+        encode_base64url(&self.indep_hash)
+    }
+
+    pub fn parent_hash(&self) -> Option<String> {
+        if self.previous_block.is_empty() {
+            None
+        } else {
+            Some(encode_base64url(&self.previous_block))
+        }
+    }
+}
+
+/// A transaction as returned by the `/tx/{id}` endpoint of a gateway. Its
+/// data is not included and has to be fetched separately.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ArweaveTransaction {
+    pub id: String,
+    /// The public key of the wallet that signed the transaction
+    #[serde(deserialize_with = "deserialize_base64url")]
+    pub owner: Vec<u8>,
+    /// The address of the recipient of `quantity`, empty if there is none
+    #[serde(deserialize_with = "deserialize_base64url")]
+    pub target: Vec<u8>,
+    /// The number of Winston transferred to `target`
+    pub quantity: String,
+    pub data_size: String,
+    #[serde(default)]
+    pub tags: Vec<ArweaveTag>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ArweaveTag {
+    #[serde(deserialize_with = "deserialize_base64url")]
+    pub name: Vec<u8>,
+    #[serde(deserialize_with = "deserialize_base64url")]
+    pub value: Vec<u8>,
+}
+
+pub fn encode_base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode_base64url(s: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+}
+
+fn deserialize_base64url<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let s: String = de::Deserialize::deserialize(deserializer)?;
+    decode_base64url(&s).map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_block_and_transaction() {
+        let block: ArweaveBlock = serde_json::from_str(
+            r#"{
+                "indep_hash": "AQID",
+                "previous_block": "",
+                "height": 0,
+                "timestamp": 1528500720,
+                "txs": ["AAE"],
+                "nonce": "AA"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(vec![1, 2, 3], block.indep_hash);
+        assert_eq!("AQID", block.hash());
+        assert_eq!(None, block.parent_hash());
+
+        let tx: ArweaveTransaction = serde_json::from_str(
+            r#"{
+                "id": "AAE",
+                "owner": "_-8",
+                "target": "",
+                "quantity": "0",
+                "data_size": "12",
+                "tags": [{ "name": "Q29udGVudC1UeXBl", "value": "dGV4dC9wbGFpbg" }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(vec![0xff, 0xef], tx.owner);
+        assert_eq!(b"Content-Type".to_vec(), tx.tags[0].name);
+        assert_eq!(b"text/plain".to_vec(), tx.tags[0].value);
+
+        assert!(serde_json::from_str::<ArweaveTag>(r#"{"name": "a+b=", "value": ""}"#).is_err());
+    }
+}
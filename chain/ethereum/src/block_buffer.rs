@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+
+use graph::prelude::*;
+
+lazy_static! {
+    /// Maximum number of triggers the block stream keeps in memory for blocks
+    /// that are waiting to be processed. The triggers of blocks past that
+    /// limit are written to a temporary file until their block is yielded.
+    static ref MAX_BUFFERED_TRIGGERS: usize = std::env::var("GRAPH_ETHEREUM_MAX_BUFFERED_TRIGGERS")
+        .unwrap_or("100000".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_ETHEREUM_MAX_BUFFERED_TRIGGERS");
+}
+
+enum Triggers {
+    InMemory(Vec<EthereumTrigger>),
+    /// The triggers were serialized to an anonymous temporary file, which
+    /// is removed when it is dropped
+    Spilled(File),
+}
+
+struct BufferedBlock {
+    ethereum_block: BlockFinality,
+    trigger_count: usize,
+    triggers: Triggers,
+}
+
+/// The blocks found in one block range that the block stream still has to
+/// yield, in order. Only up to `GRAPH_ETHEREUM_MAX_BUFFERED_TRIGGERS`
+/// triggers are kept in memory; the triggers of later blocks are spilled to
+/// disk and read back one block at a time, when that block is handed to the
+/// subgraph instance for processing.
+pub(crate) struct BlockBuffer {
+    blocks: VecDeque<BufferedBlock>,
+    max_in_memory: usize,
+    /// The number of triggers currently held in memory
+    in_memory: usize,
+}
+
+impl BlockBuffer {
+    pub fn new(
+        logger: &Logger,
+        blocks: impl IntoIterator<Item = EthereumBlockWithTriggers>,
+    ) -> Self {
+        Self::with_limit(logger, blocks, *MAX_BUFFERED_TRIGGERS)
+    }
+
+    fn with_limit(
+        logger: &Logger,
+        blocks: impl IntoIterator<Item = EthereumBlockWithTriggers>,
+        max_in_memory: usize,
+    ) -> Self {
+        let mut buffer = BlockBuffer {
+            blocks: VecDeque::new(),
+            max_in_memory,
+            in_memory: 0,
+        };
+        for block in blocks {
+            buffer.push(logger, block);
+        }
+
+        let spilled = buffer
+            .blocks
+            .iter()
+            .filter(|block| match block.triggers {
+                Triggers::Spilled(_) => true,
+                Triggers::InMemory(_) => false,
+            })
+            .count();
+        if spilled > 0 {
+            debug!(
+                logger,
+                "Wrote triggers of {} blocks to disk", spilled;
+                "triggers_in_memory" => buffer.in_memory,
+                "total_triggers" => buffer.total_triggers(),
+            );
+        }
+
+        buffer
+    }
+
+    fn push(&mut self, logger: &Logger, block: EthereumBlockWithTriggers) {
+        let EthereumBlockWithTriggers {
+            ethereum_block,
+            triggers,
+        } = block;
+        let trigger_count = triggers.len();
+
+        let triggers = if trigger_count > 0 && self.in_memory + trigger_count > self.max_in_memory {
+            match spill(&triggers) {
+                Ok(file) => Triggers::Spilled(file),
+                Err(e) => {
+                    warn!(
+                        logger,
+                        "Failed to write triggers to disk, keeping them in memory";
+                        "block_number" => ethereum_block.number(),
+                        "error" => e.to_string(),
+                    );
+                    self.in_memory += trigger_count;
+                    Triggers::InMemory(triggers)
+                }
+            }
+        } else {
+            self.in_memory += trigger_count;
+            Triggers::InMemory(triggers)
+        };
+
+        self.blocks.push_back(BufferedBlock {
+            ethereum_block,
+            trigger_count,
+            triggers,
+        });
+    }
+
+    pub fn total_triggers(&self) -> usize {
+        self.blocks.iter().map(|block| block.trigger_count).sum()
+    }
+
+    /// Take the next block out of the buffer, reading its triggers back from
+    /// disk if they were spilled.
+    pub fn pop_front(&mut self) -> Option<Result<EthereumBlockWithTriggers, Error>> {
+        let block = self.blocks.pop_front()?;
+        let triggers = match block.triggers {
+            Triggers::InMemory(triggers) => {
+                self.in_memory -= block.trigger_count;
+                Ok(triggers)
+            }
+            Triggers::Spilled(file) => unspill(file),
+        };
+        Some(triggers.map(|triggers| EthereumBlockWithTriggers {
+            ethereum_block: block.ethereum_block,
+            triggers,
+        }))
+    }
+}
+
+fn spill(triggers: &[EthereumTrigger]) -> Result<File, Error> {
+    let mut file = tempfile::tempfile()?;
+    {
+        let mut writer = BufWriter::new(&mut file);
+        serde_json::to_writer(&mut writer, triggers)?;
+        writer.flush()?;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+fn unspill(file: File) -> Result<Vec<EthereumTrigger>, Error> {
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format_err!("failed to read spilled triggers back from disk: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::web3::types::{H256, U64};
+
+    fn block(number: u64, trigger_count: usize) -> EthereumBlockWithTriggers {
+        let mut light_block = LightEthereumBlock::default();
+        light_block.number = Some(U64::from(number));
+        let ptr = EthereumBlockPointer {
+            hash: H256::from_low_u64_be(number),
+            number,
+        };
+        EthereumBlockWithTriggers {
+            ethereum_block: BlockFinality::Final(light_block),
+            triggers: (0..trigger_count)
+                .map(|_| EthereumTrigger::Block(ptr, EthereumBlockTriggerType::Every))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn spills_triggers_past_limit() {
+        let logger = Logger::root(slog::Discard, o!());
+        let blocks = vec![
+            block(1, 2),
+            block(2, 0),
+            block(3, 2),
+            block(4, 1),
+            block(5, 3),
+        ];
+        let mut buffer = BlockBuffer::with_limit(&logger, blocks.clone(), 3);
+
+        assert_eq!(8, buffer.total_triggers());
+        assert_eq!(3, buffer.in_memory);
+        let spilled: Vec<_> = buffer
+            .blocks
+            .iter()
+            .map(|block| match block.triggers {
+                Triggers::Spilled(_) => true,
+                Triggers::InMemory(_) => false,
+            })
+            .collect();
+        assert_eq!(vec![false, false, true, false, true], spilled);
+
+        for expected in blocks {
+            let actual = buffer.pop_front().unwrap().unwrap();
+            assert_eq!(
+                expected.ethereum_block.number(),
+                actual.ethereum_block.number()
+            );
+            assert_eq!(expected.triggers, actual.triggers);
+        }
+        assert!(buffer.pop_front().is_none());
+        assert_eq!(0, buffer.in_memory);
+    }
+}
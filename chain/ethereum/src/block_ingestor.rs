@@ -1,6 +1,6 @@
 use lazy_static;
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
 
 use graph::prelude::*;
 use web3::types::*;
@@ -10,6 +10,46 @@ lazy_static! {
         .ok()
         .map(|s| s.eq_ignore_ascii_case("true"))
         .unwrap_or(false);
+
+    /// How long the lease of the node that runs the block ingestor of a
+    /// network lasts, in seconds. Another node takes over once its lease
+    /// has expired
+    static ref LEASE_DURATION: Duration = Duration::from_secs(
+        std::env::var("GRAPH_BLOCK_INGESTOR_LEASE_DURATION")
+            .unwrap_or("60".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_BLOCK_INGESTOR_LEASE_DURATION")
+    );
+
+    /// How often the block ingestor checks that the cached blocks that
+    /// became final since its last check agree with the chain, in seconds.
+    /// `0` turns the check off
+    static ref BLOCK_CACHE_CHECK_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_ETHEREUM_BLOCK_CACHE_CHECK_INTERVAL")
+            .unwrap_or("300".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_ETHEREUM_BLOCK_CACHE_CHECK_INTERVAL")
+    );
+}
+
+/// Keeps track of the cached blocks that the block ingestor has checked
+/// against the chain
+struct CacheCheck {
+    reorg_threshold: u64,
+    /// The highest block number that has been checked
+    checked_up_to: Option<u64>,
+    checked_at: Option<Instant>,
+    inconsistencies: Box<Counter>,
+}
+
+/// Decides which of the index nodes that could ingest blocks for a network
+/// actually does, through a lease in the store
+struct Lease {
+    store: Arc<dyn NodeLivenessStore>,
+    node_id: NodeId,
+    held: bool,
+    /// When we last tried to acquire or renew the lease
+    checked_at: Option<Instant>,
 }
 
 pub struct BlockIngestorMetrics {
@@ -44,9 +84,12 @@ where
     chain_store: Arc<S>,
     eth_adapter: Arc<dyn EthereumAdapter>,
     ancestor_count: u64,
-    _network_name: String,
+    network_name: String,
     logger: Logger,
     polling_interval: Duration,
+    batch_size: u64,
+    lease: Option<Lease>,
+    cache_check: Option<CacheCheck>,
 }
 
 impl<S> BlockIngestor<S>
@@ -76,14 +119,86 @@ where
             chain_store,
             eth_adapter,
             ancestor_count,
-            _network_name: network_name,
+            network_name,
             logger,
             polling_interval,
+            batch_size: 1,
+            lease: None,
+            cache_check: None,
+        })
+    }
+
+    /// Fetch up to `batch_size` blocks at the same time when the chain head
+    /// has moved by more than one block since the last poll, rather than
+    /// walking back from the new chain head one parent at a time. Helps to
+    /// keep up with chains that produce blocks every few seconds.
+    pub fn with_batch_size(self, batch_size: u64) -> Self {
+        BlockIngestor {
+            batch_size: batch_size.max(1),
+            ..self
+        }
+    }
+
+    /// Only ingest blocks while `node_id` holds the network's lease in
+    /// `store`, so that when several index nodes are set up to ingest
+    /// blocks for the same network, only one of them writes blocks and
+    /// moves the chain head at any time. The others wait and take over
+    /// when the lease of the ingesting node expires.
+    pub fn with_lease(self, store: Arc<dyn NodeLivenessStore>, node_id: NodeId) -> Self {
+        BlockIngestor {
+            lease: Some(Lease {
+                store,
+                node_id,
+                held: false,
+                checked_at: None,
+            }),
+            ..self
+        }
+    }
+
+    /// Periodically check that the cached blocks agree with the chain once
+    /// they are more than `reorg_threshold` blocks behind the chain head,
+    /// and with that final. Where the cached blocks do not form a single
+    /// chain, e.g. after a reorg, only the block that the chain has at that
+    /// number is kept, and loaded again if it is not cached. Blocks that
+    /// are removed or loaded are counted in the
+    /// `ethereum_block_cache_inconsistencies` metric
+    pub fn with_cache_check(
+        self,
+        reorg_threshold: u64,
+        registry: Arc<dyn MetricsRegistry>,
+    ) -> Result<Self, Error> {
+        if *BLOCK_CACHE_CHECK_INTERVAL == Duration::from_secs(0) {
+            return Ok(self);
+        }
+        let inconsistencies = registry.new_counter(
+            String::from("ethereum_block_cache_inconsistencies"),
+            String::from(
+                "Number of cached blocks that were removed or loaded again \
+                 because they did not agree with the chain",
+            ),
+            vec![(String::from("network"), self.network_name.clone())]
+                .into_iter()
+                .collect(),
+        )?;
+        Ok(BlockIngestor {
+            cache_check: Some(CacheCheck {
+                reorg_threshold,
+                checked_up_to: None,
+                checked_at: None,
+                inconsistencies,
+            }),
+            ..self
         })
     }
 
-    pub async fn into_polling_stream(self) {
+    pub async fn into_polling_stream(mut self) {
         loop {
+            if !self.hold_lease() {
+                tokio::time::delay_for(self.polling_interval).await;
+                continue;
+            }
+
             match self.do_poll().await {
                 // Some polls will fail due to transient issues
                 Err(err @ EthereumAdapterError::BlockUnavailable(_)) => {
@@ -106,10 +221,54 @@ where
                 self.cleanup_cached_blocks()
             }
 
+            self.check_cached_blocks().await;
+
             tokio::time::delay_for(self.polling_interval).await;
         }
     }
 
+    /// Whether this ingestor may ingest blocks now. Renews the lease, or
+    /// tries to take it over, a few times per lease duration
+    fn hold_lease(&mut self) -> bool {
+        let logger = &self.logger;
+        let network_name = &self.network_name;
+        let lease = match self.lease.as_mut() {
+            Some(lease) => lease,
+            None => return true,
+        };
+        let due = lease
+            .checked_at
+            .map_or(true, |at| at.elapsed() >= *LEASE_DURATION / 3);
+        if !due {
+            return lease.held;
+        }
+        lease.checked_at = Some(Instant::now());
+
+        let result =
+            lease
+                .store
+                .acquire_ingestor_lease(network_name, &lease.node_id, *LEASE_DURATION);
+        let held = match result {
+            Ok(held) => held,
+            Err(e) => {
+                // We can not tell whether another node took over; stop
+                // ingesting rather than risk two nodes writing blocks
+                warn!(logger, "Failed to renew block ingestor lease"; "error" => e.to_string());
+                false
+            }
+        };
+        match (lease.held, held) {
+            (false, true) => info!(logger, "This node now ingests blocks for the network"),
+            (true, false) => info!(
+                logger,
+                "This node lost the block ingestor lease and stops ingesting blocks"
+            ),
+            _ => (),
+        }
+        lease.held = held;
+        held
+    }
+
     fn cleanup_cached_blocks(&self) {
         match self.chain_store.cleanup_cached_blocks(self.ancestor_count) {
             Ok((min_block, count)) => {
@@ -130,6 +289,126 @@ where
         }
     }
 
+    /// Check the cached blocks that became final since the last check, and
+    /// repair the numbers at which they do not form a single chain
+    async fn check_cached_blocks(&mut self) {
+        let due = match &self.cache_check {
+            Some(check) => check
+                .checked_at
+                .map_or(true, |at| at.elapsed() >= *BLOCK_CACHE_CHECK_INTERVAL),
+            None => false,
+        };
+        if !due {
+            return;
+        }
+
+        let (from, to) = match self.final_block_range() {
+            Ok(Some(range)) => range,
+            Ok(None) => return,
+            Err(e) => {
+                warn!(self.logger, "Failed to check cached blocks"; "error" => e.to_string());
+                return;
+            }
+        };
+        let numbers = match self.chain_store.inconsistent_block_numbers(from, to) {
+            Ok(numbers) => numbers,
+            Err(e) => {
+                warn!(self.logger, "Failed to check cached blocks"; "error" => e.to_string());
+                return;
+            }
+        };
+
+        // A cached block that is not the parent of the cached block after it
+        // may be the one that is wrong, or that one
+        let numbers: BTreeSet<u64> = numbers
+            .into_iter()
+            .flat_map(|number| vec![number, number + 1])
+            .filter(|number| *number <= to)
+            .collect();
+        let mut checked_up_to = Some(to);
+        for number in numbers {
+            if let Err(e) = self.repair_cached_block(number).await {
+                // Try again with the next check
+                warn!(
+                    self.logger,
+                    "Failed to repair cached block";
+                    "number" => number,
+                    "error" => e.to_string()
+                );
+                checked_up_to = number.checked_sub(1);
+                break;
+            }
+        }
+
+        let check = self.cache_check.as_mut().unwrap();
+        check.checked_up_to = checked_up_to;
+        check.checked_at = Some(Instant::now());
+    }
+
+    /// The range of block numbers that became final since the last check
+    /// of the cached blocks. The first check looks at the last
+    /// `ancestor_count` final blocks
+    fn final_block_range(&self) -> Result<Option<(u64, u64)>, Error> {
+        let check = self.cache_check.as_ref().unwrap();
+        let head = match self.chain_store.chain_head_ptr()? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let to = match head.number.checked_sub(check.reorg_threshold) {
+            Some(to) => to,
+            None => return Ok(None),
+        };
+        let from = check
+            .checked_up_to
+            .map_or(to.saturating_sub(self.ancestor_count), |number| number + 1);
+        Ok(if from <= to { Some((from, to)) } else { None })
+    }
+
+    /// Make sure that the only cached block with `number` is the one that
+    /// the chain has at that number
+    async fn repair_cached_block(&self, number: u64) -> Result<(), Error> {
+        let block = self
+            .eth_adapter
+            .block_by_number(&self.logger, number)
+            .compat()
+            .await?
+            .ok_or_else(|| format_err!("block {} is not available", number))?;
+        let hash = block
+            .hash
+            .ok_or_else(|| format_err!("block {} has no hash", number))?;
+
+        let cached = self.chain_store.block_hashes_by_block_number(number)?;
+        let loaded = !cached.contains(&hash);
+        if loaded {
+            let block = self
+                .eth_adapter
+                .load_full_block(&self.logger, block)
+                .compat()
+                .await?;
+            self.chain_store
+                .upsert_blocks(stream::once::<_, Error>(Ok(block)))
+                .compat()
+                .await?;
+        }
+        let removed = self.chain_store.confirm_block_hash(number, &hash)?;
+
+        if removed > 0 || loaded {
+            warn!(
+                self.logger,
+                "Repaired cached blocks that did not agree with the chain";
+                "number" => number,
+                "hash" => format!("{:x}", hash),
+                "removed" => removed,
+                "loaded" => loaded,
+            );
+            let check = self.cache_check.as_ref().unwrap();
+            check
+                .inconsistencies
+                .inc_by((removed + loaded as usize) as f64);
+        }
+        Ok(())
+    }
+
     async fn do_poll(&self) -> Result<(), EthereumAdapterError> {
         trace!(self.logger, "BlockIngestor::do_poll");
 
@@ -152,6 +431,7 @@ where
 
         // Ask for latest block again, but now with full transactions
         let latest_block = self.eth_adapter.latest_block(&self.logger).compat().await?;
+        let latest_number = latest_block.number.unwrap().as_u64();
 
         // Compare latest block with head ptr, alert user if far behind
         match head_block_ptr_opt {
@@ -163,7 +443,7 @@ where
                 );
             }
             Some(head_block_ptr) => {
-                let latest_number = latest_block.number.unwrap().as_u64() as i64;
+                let latest_number = latest_number as i64;
                 let head_number = head_block_ptr.number as i64;
                 let distance = latest_number - head_number;
                 let blocks_needed = (distance).min(self.ancestor_count as i64);
@@ -193,11 +473,27 @@ where
             .compat()
             .await?;
 
+        // Fetch the blocks right before the latest block along with it if
+        // we are behind by more than one block
+        let behind = match head_block_ptr_opt {
+            Some(head_block_ptr) => latest_number.saturating_sub(head_block_ptr.number),
+            None => self.ancestor_count,
+        };
+        let earlier_blocks = self.get_blocks_before(
+            latest_number,
+            behind
+                .min(self.ancestor_count)
+                .min(self.batch_size)
+                .saturating_sub(1),
+        );
+
         // Store latest block in block store.
         // Might be a no-op if latest block is one that we have seen.
         // ingest_blocks will return a (potentially incomplete) list of blocks that are
         // missing.
-        let mut missing_block_hashes = self.ingest_blocks(stream::once(Ok(latest_block))).await?;
+        let mut missing_block_hashes = self
+            .ingest_blocks(stream::once(Ok(latest_block)).chain(earlier_blocks))
+            .await?;
 
         // Repeatedly fetch missing parent blocks, and ingest them.
         // ingest_blocks will continue to tell us about more missing parent
@@ -268,4 +564,35 @@ where
 
         Box::new(stream::futures_unordered(block_futures))
     }
+
+    /// Requests the `count` blocks before block number `number` via web3, returning them in a
+    /// stream (potentially out of order).
+    fn get_blocks_before(
+        &self,
+        number: u64,
+        count: u64,
+    ) -> Box<dyn Stream<Item = EthereumBlock, Error = EthereumAdapterError> + Send + 'static> {
+        let logger = self.logger.clone();
+        let eth_adapter = self.eth_adapter.clone();
+
+        let block_futures = (number.saturating_sub(count)..number).map(move |block_number| {
+            let logger = logger.clone();
+            let eth_adapter = eth_adapter.clone();
+
+            eth_adapter
+                .block_by_number(&logger, block_number)
+                .from_err()
+                .and_then(move |block_opt| {
+                    block_opt.ok_or_else(|| {
+                        EthereumAdapterError::Unknown(format_err!(
+                            "block {} is not available",
+                            block_number
+                        ))
+                    })
+                })
+                .and_then(move |block| eth_adapter.load_full_block(&logger, block))
+        });
+
+        Box::new(stream::futures_unordered(block_futures))
+    }
 }
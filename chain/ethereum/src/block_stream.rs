@@ -7,6 +7,7 @@ use std::time::Duration;
 use graph::components::ethereum::{
     blocks_with_triggers, triggers_in_block, EthereumNetworks, NodeCapabilities,
 };
+use graph::components::subgraph::RETRIES;
 use graph::data::subgraph::schema::{
     SubgraphDeploymentEntity, SubgraphEntity, SubgraphVersionEntity,
 };
@@ -14,6 +15,8 @@ use graph::prelude::{
     BlockStream as BlockStreamTrait, BlockStreamBuilder as BlockStreamBuilderTrait, *,
 };
 
+use crate::block_buffer::BlockBuffer;
+
 lazy_static! {
     /// Maximum number of blocks to request in each chunk.
     static ref MAX_BLOCK_RANGE_SIZE: u64 = std::env::var("GRAPH_ETHEREUM_MAX_BLOCK_RANGE_SIZE")
@@ -29,7 +32,8 @@ lazy_static! {
 }
 
 enum BlockStreamState {
-    /// The BlockStream is new and has not yet been polled.
+    /// The BlockStream is new and has not yet been polled, or it has yielded all
+    /// blocks it found.
     ///
     /// Valid next states: Reconciliation
     New,
@@ -42,8 +46,8 @@ enum BlockStreamState {
     /// The BlockStream is emitting blocks that must be processed in order to bring the subgraph
     /// store up to date with the chain store.
     ///
-    /// Valid next states: Reconciliation
-    YieldingBlocks(VecDeque<EthereumBlockWithTriggers>),
+    /// Valid next states: New
+    YieldingBlocks(BlockBuffer),
 
     /// The BlockStream experienced an error and is pausing before attempting to produce
     /// blocks again.
@@ -57,10 +61,31 @@ enum BlockStreamState {
     /// Valid next states: Reconciliation
     Idle,
 
+    /// The BlockStream has reconciled the subgraph store and chain store states, and waits for
+    /// the deployment's polling interval to pass before it looks at chain head updates again.
+    ///
+    /// Valid next states: Idle
+    Resting(Box<dyn Future<Item = (), Error = Error> + Send>),
+
     /// Not a real state, only used when going from one state to another.
     Transition,
 }
 
+/// How closely the block stream of a deployment follows the chain head.
+/// By default, it processes every new block as soon as the block ingestor
+/// has seen it. Deployments that do not need to be that fresh, e.g. ones
+/// used for analytics, can save RPC calls and database work by catching up
+/// less often.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FollowSettings {
+    /// After catching up with the chain head, wait this long before looking
+    /// at new blocks again
+    pub polling_interval: Option<Duration>,
+    /// Only catch up with the chain head once the deployment is more than
+    /// this many blocks behind it
+    pub max_lag: u64,
+}
+
 /// A single next step to take in reconciling the state of the subgraph store with the state of the
 /// chain store.
 enum ReconciliationStep {
@@ -111,6 +136,9 @@ struct BlockStreamContext<S, C> {
     metrics: Arc<BlockStreamMetrics>,
     previous_triggers_per_block: f64,
     previous_block_range_size: u64,
+    /// Whether to make pending versions of this deployment current once it
+    /// has synced
+    promote_when_synced: bool,
 }
 
 impl<S, C> Clone for BlockStreamContext<S, C> {
@@ -131,6 +159,7 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
             metrics: self.metrics.clone(),
             previous_triggers_per_block: self.previous_triggers_per_block,
             previous_block_range_size: self.previous_block_range_size,
+            promote_when_synced: self.promote_when_synced,
         }
     }
 }
@@ -139,6 +168,7 @@ pub struct BlockStream<S, C> {
     state: Mutex<BlockStreamState>,
     consecutive_err_count: u32,
     chain_head_update_stream: ChainHeadUpdateStream,
+    follow: FollowSettings,
     ctx: BlockStreamContext<S, C>,
 }
 
@@ -168,11 +198,14 @@ where
         reorg_threshold: u64,
         logger: Logger,
         metrics: Arc<BlockStreamMetrics>,
+        follow: FollowSettings,
+        promote_when_synced: bool,
     ) -> Self {
         BlockStream {
             state: Mutex::new(BlockStreamState::New),
             consecutive_err_count: 0,
             chain_head_update_stream: chain_store.chain_head_updates(),
+            follow,
             ctx: BlockStreamContext {
                 subgraph_store,
                 chain_store,
@@ -191,6 +224,7 @@ where
                 // A high number here forces a slow start, with a range of 1.
                 previous_triggers_per_block: 1_000_000.0,
                 previous_block_range_size: 1,
+                promote_when_synced,
             },
         }
     }
@@ -617,6 +651,17 @@ where
         }
     }
 
+    /// Whether the subgraph is more than `max_lag` blocks behind the chain head
+    fn lags_behind(&self, max_lag: u64) -> Result<bool, Error> {
+        let head_ptr = self.chain_store.chain_head_ptr()?;
+        let subgraph_ptr = self.subgraph_store.block_ptr(self.subgraph_id.clone())?;
+        Ok(match (head_ptr, subgraph_ptr) {
+            (Some(head), Some(ptr)) => head.number > ptr.number + max_lag,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+    }
+
     /// Set subgraph deployment entity synced flag if and only if the subgraph block pointer is
     /// caught up to the head block pointer.
     fn update_subgraph_synced_status(&self) -> Result<(), Error> {
@@ -640,6 +685,14 @@ where
                 true,
             ));
 
+            // Pending versions wait for an explicit promotion
+            if !self.promote_when_synced {
+                return self
+                    .subgraph_store
+                    .apply_metadata_operations(ops)
+                    .map_err(|e| format_err!("Failed to set deployment synced flag: {}", e));
+            }
+
             // Find versions pointing to this deployment
             let versions = self
                 .subgraph_store
@@ -743,6 +796,7 @@ where
                         versions_before,
                         versions_after,
                         None, // no new assignments will be added
+                        None,
                     )
                     .into_iter()
                     .map(|op| op.into()),
@@ -784,7 +838,7 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
 
         let result = loop {
             match state {
-                // First time being polled
+                // First time being polled, or after yielding all blocks
                 BlockStreamState::New => {
                     // Start the reconciliation process by asking for blocks
                     let next_blocks_future = self.ctx.next_blocks();
@@ -799,6 +853,12 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                     match next_blocks_future.poll() {
                         // Reconciliation found blocks to process
                         Ok(Async::Ready(NextBlocks::Blocks(next_blocks, block_range_size))) => {
+                            // Reset error count
+                            if self.consecutive_err_count > 0 {
+                                self.consecutive_err_count = 0;
+                                RETRIES.remove(&self.ctx.subgraph_id);
+                            }
+
                             let total_triggers =
                                 next_blocks.iter().map(|b| b.triggers.len()).sum::<usize>();
                             self.ctx.previous_triggers_per_block =
@@ -809,7 +869,10 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                             }
 
                             // Switch to yielding state until next_blocks is depleted
-                            state = BlockStreamState::YieldingBlocks(next_blocks);
+                            state = BlockStreamState::YieldingBlocks(BlockBuffer::new(
+                                &self.ctx.logger,
+                                next_blocks,
+                            ));
 
                             // Yield the first block in next_blocks
                             continue;
@@ -818,10 +881,18 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                         // Reconciliation completed. We're caught up to chain head.
                         Ok(Async::Ready(NextBlocks::Done)) => {
                             // Reset error count
-                            self.consecutive_err_count = 0;
+                            if self.consecutive_err_count > 0 {
+                                self.consecutive_err_count = 0;
+                                RETRIES.remove(&self.ctx.subgraph_id);
+                            }
 
-                            // Switch to idle
-                            state = BlockStreamState::Idle;
+                            // Switch to idle, unless the deployment wants to rest first
+                            state = match self.follow.polling_interval {
+                                Some(interval) => BlockStreamState::Resting(Box::new(
+                                    tokio::time::delay_for(interval).map(Ok).compat(),
+                                )),
+                                None => BlockStreamState::Idle,
+                            };
 
                             // Poll for chain head update
                             continue;
@@ -841,12 +912,29 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                         Err(e) => {
                             self.consecutive_err_count += 1;
 
+                            let kind = RpcErrorKind::of(&e);
+                            self.ctx.metrics.add_error(kind);
+                            if kind == Some(RpcErrorKind::ProviderTimeout) {
+                                // The node may have been asked for too much at once. The
+                                // next range is at most ten times the previous one, so
+                                // this halves it
+                                self.ctx.previous_block_range_size =
+                                    (self.ctx.previous_block_range_size / 20).max(1);
+                            }
+
                             // Pause before trying again
-                            let secs = (5 * self.consecutive_err_count).max(120) as u64;
+                            let delay = kind
+                                .unwrap_or(RpcErrorKind::ProviderError)
+                                .retry_delay(self.consecutive_err_count);
+                            RETRIES.schedule(
+                                &self.ctx.subgraph_id,
+                                self.consecutive_err_count as u64,
+                                e.to_string(),
+                                kind,
+                                delay,
+                            );
                             state = BlockStreamState::RetryAfterDelay(Box::new(
-                                tokio::time::delay_for(Duration::from_secs(secs))
-                                    .map(Ok)
-                                    .compat(),
+                                tokio::time::delay_for(delay).map(Ok).compat(),
                             ));
                             break Err(e);
                         }
@@ -857,19 +945,24 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                 BlockStreamState::YieldingBlocks(mut next_blocks) => {
                     match next_blocks.pop_front() {
                         // Yield one block
-                        Some(next_block) => {
+                        Some(Ok(next_block)) => {
                             state = BlockStreamState::YieldingBlocks(next_blocks);
                             break Ok(Async::Ready(Some(BlockStreamEvent::Block(next_block))));
                         }
 
+                        // The triggers of the block could not be read back from disk;
+                        // drop the remaining blocks and look for them again
+                        Some(Err(e)) => {
+                            state = BlockStreamState::New;
+                            break Err(e);
+                        }
+
                         // Done yielding blocks
                         None => {
-                            // Restart reconciliation until more blocks or done
-                            let next_blocks_future = self.ctx.next_blocks();
-                            state = BlockStreamState::Reconciliation(next_blocks_future);
-
-                            // Poll the next_blocks() future
-                            continue;
+                            // Restart reconciliation until more blocks or done once the
+                            // consumer has written the blocks it processed
+                            state = BlockStreamState::New;
+                            break Ok(Async::Ready(Some(BlockStreamEvent::Drained)));
                         }
                     }
                 }
@@ -889,9 +982,33 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                     }
                 },
 
+                // Waiting for the polling interval to pass. Chain head updates that arrive
+                // in the meantime are handled once we are idle again
+                BlockStreamState::Resting(mut delay) => match delay.poll() {
+                    Ok(Async::Ready(())) | Err(_) => {
+                        state = BlockStreamState::Idle;
+                        continue;
+                    }
+
+                    Ok(Async::NotReady) => {
+                        state = BlockStreamState::Resting(delay);
+                        break Ok(Async::NotReady);
+                    }
+                },
+
                 // Waiting for a chain head update
                 BlockStreamState::Idle => {
                     match self.chain_head_update_stream.poll() {
+                        // Chain head was updated, but the deployment is close enough to it.
+                        // If we can not tell, reconciliation will run into the same error
+                        Ok(Async::Ready(Some(())))
+                            if self.follow.max_lag > 0
+                                && !self.ctx.lags_behind(self.follow.max_lag).unwrap_or(true) =>
+                        {
+                            state = BlockStreamState::Idle;
+                            continue;
+                        }
+
                         // Chain head was updated
                         Ok(Async::Ready(Some(()))) => {
                             // Start reconciliation process
@@ -940,6 +1057,9 @@ pub struct BlockStreamBuilder<S, C, M> {
     eth_networks: EthereumNetworks,
     node_id: NodeId,
     reorg_threshold: u64,
+    network_reorg_thresholds: HashMap<String, u64>,
+    deployment_follow: HashMap<SubgraphDeploymentId, FollowSettings>,
+    version_switching_mode: SubgraphVersionSwitchingMode,
     metrics_registry: Arc<M>,
 }
 
@@ -951,6 +1071,9 @@ impl<S, C, M> Clone for BlockStreamBuilder<S, C, M> {
             eth_networks: self.eth_networks.clone(),
             node_id: self.node_id.clone(),
             reorg_threshold: self.reorg_threshold,
+            network_reorg_thresholds: self.network_reorg_thresholds.clone(),
+            deployment_follow: self.deployment_follow.clone(),
+            version_switching_mode: self.version_switching_mode,
             metrics_registry: self.metrics_registry.clone(),
         }
     }
@@ -976,9 +1099,39 @@ where
             eth_networks,
             node_id,
             reorg_threshold,
+            network_reorg_thresholds: HashMap::new(),
+            deployment_follow: HashMap::new(),
+            version_switching_mode: SubgraphVersionSwitchingMode::Instant,
             metrics_registry,
         }
     }
+
+    /// Use `reorg_threshold` instead of the default for the block streams
+    /// of `network_name`
+    pub fn with_reorg_threshold(mut self, network_name: String, reorg_threshold: u64) -> Self {
+        self.network_reorg_thresholds
+            .insert(network_name, reorg_threshold);
+        self
+    }
+
+    /// Follow the chain head according to `follow` rather than at every
+    /// block in the block stream of `deployment`
+    pub fn with_follow_settings(
+        mut self,
+        deployment: SubgraphDeploymentId,
+        follow: FollowSettings,
+    ) -> Self {
+        self.deployment_follow.insert(deployment, follow);
+        self
+    }
+
+    /// Switch versions according to `mode` once their deployment has
+    /// synced. With `Manual`, pending versions are left for
+    /// `subgraph_promote`
+    pub fn with_version_switching_mode(mut self, mode: SubgraphVersionSwitchingMode) -> Self {
+        self.version_switching_mode = mode;
+        self
+    }
 }
 
 impl<S, C, M> BlockStreamBuilderTrait for BlockStreamBuilder<S, C, M>
@@ -1027,6 +1180,18 @@ where
                 &network_name, &requirements
             ));
 
+        let reorg_threshold = self
+            .network_reorg_thresholds
+            .get(&network_name)
+            .cloned()
+            .unwrap_or(self.reorg_threshold);
+
+        let follow = self
+            .deployment_follow
+            .get(&deployment_id)
+            .cloned()
+            .unwrap_or_default();
+
         // Create the actual subgraph-specific block stream
         BlockStream::new(
             self.subgraph_store.clone(),
@@ -1039,9 +1204,11 @@ where
             block_filter,
             start_blocks,
             include_calls_in_blocks,
-            self.reorg_threshold,
+            reorg_threshold,
             logger,
             metrics,
+            follow,
+            self.version_switching_mode != SubgraphVersionSwitchingMode::Manual,
         )
     }
 }
@@ -6,21 +6,27 @@ use serde::{Deserialize, Deserializer};
 
 use graph::prelude::*;
 
+/// Parses the HTTP headers to send with every request to an Ethereum node.
+pub fn http_headers(kvs: &HashMap<String, String>) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    for (k, v) in kvs {
+        let name = k
+            .parse::<HeaderName>()
+            .map_err(|_| format!("invalid HTTP header name: {}", k))?;
+        let value = v
+            .parse::<HeaderValue>()
+            .map_err(|_| format!("invalid HTTP header value: {}: {}", k, v))?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
 fn deserialize_http_headers<'de, D>(deserializer: D) -> Result<HeaderMap, D::Error>
 where
     D: Deserializer<'de>,
 {
     let kvs: HashMap<String, String> = Deserialize::deserialize(deserializer)?;
-    let mut headers = HeaderMap::new();
-    for (k, v) in kvs.into_iter() {
-        headers.insert(
-            k.parse::<HeaderName>()
-                .expect(&format!("invalid HTTP header name: {}", k)),
-            v.parse::<HeaderValue>()
-                .expect(&format!("knvalid HTTP header value: {}: {}", k, v)),
-        );
-    }
-    Ok(headers)
+    http_headers(&kvs).map_err(serde::de::Error::custom)
 }
 
 #[derive(Deserialize, Debug)]
@@ -2,28 +2,37 @@ use ethabi::Token;
 use futures::future;
 use futures::prelude::*;
 use lazy_static::lazy_static;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::iter::FromIterator;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use ethabi::ParamType;
 use graph::components::ethereum::{EthereumAdapter as EthereumAdapterTrait, *};
 use graph::prelude::{
     debug, err_msg, error, ethabi, format_err,
-    futures03::{self, compat::Future01CompatExt, FutureExt, StreamExt, TryStreamExt},
-    hex, retry, stream, tiny_keccak, trace, warn, web3, ChainStore, CheapClone, DynTryFuture,
-    Error, EthereumCallCache, Logger, TimeoutError,
+    futures03::{
+        self, channel::oneshot, compat::Future01CompatExt, FutureExt, StreamExt, TryStreamExt,
+    },
+    hex, retry, serde_json, stream, tiny_keccak, trace, warn, web3, ChainStore, CheapClone,
+    DynTryFuture, Error, EthereumCallCache, Logger, RetryObserver, TimeoutError,
 };
 use web3::api::Web3;
 use web3::transports::batch::Batch;
 use web3::types::{Filter, *};
 
+use crate::multicall::{decode_try_aggregate, encode_try_aggregate, Multicall, PendingCall};
+
 #[derive(Clone)]
 pub struct EthereumAdapter<T: web3::Transport> {
     url_hostname: Arc<String>,
+    network: Arc<String>,
     web3: Arc<Web3<T>>,
     metrics: Arc<ProviderEthRpcMetrics>,
+    receipt_batch_size: Option<usize>,
+    shared_logs: Arc<Mutex<SharedLogs>>,
+    custom_rpc_methods: Arc<HashSet<String>>,
+    multicall: Option<Arc<Multicall>>,
 }
 
 lazy_static! {
@@ -62,14 +71,126 @@ lazy_static! {
             .unwrap_or("10".into())
             .parse::<usize>()
             .expect("invalid GRAPH_ETHEREUM_REQUEST_RETRIES env var");
+
+    /// The number of recent `eth_getLogs` results that are kept so that
+    /// deployments which index the same contracts can share them.
+    static ref SHARED_LOGS_CACHE_SIZE: usize = std::env::var("GRAPH_ETHEREUM_SHARED_LOGS_CACHE_SIZE")
+            .unwrap_or("100".into())
+            .parse::<usize>()
+            .expect("invalid GRAPH_ETHEREUM_SHARED_LOGS_CACHE_SIZE env var");
+}
+
+type SharedLogsFuture =
+    futures03::future::Shared<futures03::future::BoxFuture<'static, Result<Arc<Vec<Log>>, String>>>;
+
+/// A block range and `eth_getLogs` filter, with contracts and event
+/// signatures sorted.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct LogsKey {
+    from: u64,
+    to: u64,
+    contracts: Vec<Address>,
+    event_signatures: Vec<H256>,
+}
+
+impl LogsKey {
+    fn new(from: u64, to: u64, filter: &EthGetLogsFilter) -> Self {
+        let mut contracts = filter.contracts.clone();
+        contracts.sort();
+        contracts.dedup();
+        let mut event_signatures = filter.event_signatures.clone();
+        event_signatures.sort();
+        event_signatures.dedup();
+        LogsKey {
+            from,
+            to,
+            contracts,
+            event_signatures,
+        }
+    }
+
+    /// Whether the logs for `self` include all logs for `other`. An empty
+    /// list of contracts matches all contracts, and an empty list of event
+    /// signatures all events.
+    fn covers(&self, other: &LogsKey) -> bool {
+        self.from == other.from
+            && self.to == other.to
+            && (self.contracts.is_empty()
+                || (!other.contracts.is_empty()
+                    && other
+                        .contracts
+                        .iter()
+                        .all(|c| self.contracts.binary_search(c).is_ok())))
+            && (self.event_signatures.is_empty()
+                || (!other.event_signatures.is_empty()
+                    && other
+                        .event_signatures
+                        .iter()
+                        .all(|sig| self.event_signatures.binary_search(sig).is_ok())))
+    }
+
+    fn matches(&self, log: &Log) -> bool {
+        (self.contracts.is_empty() || self.contracts.binary_search(&log.address).is_ok())
+            && (self.event_signatures.is_empty()
+                || log.topics.first().map_or(false, |sig| {
+                    self.event_signatures.binary_search(sig).is_ok()
+                }))
+    }
+}
+
+/// The `eth_getLogs` requests that were made recently through an adapter,
+/// including the ones that are still running. Deployments that index the
+/// same contracts request the same logs for the same blocks; they share one
+/// request to the Ethereum node, and a request whose filter covers another
+/// one provides the logs for both.
+#[derive(Default)]
+struct SharedLogs {
+    requests: HashMap<LogsKey, SharedLogsFuture>,
+    /// The keys of `requests` from oldest to newest
+    order: VecDeque<LogsKey>,
+}
+
+impl SharedLogs {
+    /// A request that covers `key`, and whether it is exactly for `key`
+    fn find(&self, key: &LogsKey) -> Option<(SharedLogsFuture, bool)> {
+        if let Some(request) = self.requests.get(key) {
+            return Some((request.clone(), true));
+        }
+        self.requests
+            .iter()
+            .find(|(other, _)| other.covers(key))
+            .map(|(_, request)| (request.clone(), false))
+    }
+
+    fn insert(&mut self, key: LogsKey, request: SharedLogsFuture) {
+        if self.requests.insert(key.clone(), request).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > *SHARED_LOGS_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.requests.remove(&oldest);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &LogsKey) {
+        if self.requests.remove(key).is_some() {
+            self.order.retain(|other| other != key);
+        }
+    }
 }
 
 impl<T: web3::Transport> CheapClone for EthereumAdapter<T> {
     fn cheap_clone(&self) -> Self {
         Self {
             url_hostname: self.url_hostname.cheap_clone(),
+            network: self.network.cheap_clone(),
             web3: self.web3.cheap_clone(),
             metrics: self.metrics.cheap_clone(),
+            receipt_batch_size: self.receipt_batch_size,
+            shared_logs: self.shared_logs.cheap_clone(),
+            custom_rpc_methods: self.custom_rpc_methods.cheap_clone(),
+            multicall: self.multicall.cheap_clone(),
         }
     }
 }
@@ -89,8 +210,317 @@ where
             .to_string();
         EthereumAdapter {
             url_hostname: Arc::new(hostname),
+            network: Arc::new(String::new()),
             web3: Arc::new(Web3::new(transport)),
             metrics: provider_metrics,
+            receipt_batch_size: None,
+            shared_logs: Arc::new(Mutex::new(SharedLogs::default())),
+            custom_rpc_methods: Arc::new(HashSet::new()),
+            multicall: None,
+        }
+    }
+
+    /// Request the receipts of a block in batches of at most `size`
+    /// receipts that are sent at the same time, rather than in one batch.
+    /// Large batches take long for the node to answer, and some providers
+    /// reject them.
+    pub fn with_receipt_batch_size(self, size: Option<usize>) -> Self {
+        EthereumAdapter {
+            receipt_batch_size: size,
+            ..self
+        }
+    }
+
+    /// The number and timestamp of block `number`, or of the latest block
+    fn block_timestamp(
+        &self,
+        logger: &Logger,
+        number: Option<u64>,
+    ) -> impl Future<Item = (u64, u64), Error = Error> + Send {
+        let web3 = self.web3.clone();
+        let block_id: BlockId = match number {
+            Some(number) => BlockId::Number(number.into()),
+            None => BlockNumber::Latest.into(),
+        };
+
+        retry("eth_getBlockByNumber RPC call", logger)
+            .observe(self.retries("eth_getBlockByNumber"))
+            .no_limit()
+            .timeout_secs(*JSON_RPC_TIMEOUT)
+            .run(move || {
+                web3.eth()
+                    .block(block_id.clone())
+                    .from_err()
+                    .and_then(move |block| {
+                        let block = block.ok_or_else(|| {
+                            format_err!("Ethereum node did not find block {:?}", number)
+                        })?;
+                        let block_number = block
+                            .number
+                            .ok_or_else(|| format_err!("block {:?} is pending", number))?;
+                        Ok((block_number.as_u64(), block.timestamp.as_u64()))
+                    })
+            })
+            .map_err(move |e| {
+                EthereumRpcError::from_timeout(e, move || {
+                    format!("Ethereum node took too long to return block {:?}", number)
+                })
+            })
+    }
+
+    async fn find_block_number_by_timestamp(
+        self,
+        logger: Logger,
+        chain_store: Option<Arc<dyn ChainStore>>,
+        timestamp: u64,
+        up_to: Option<u64>,
+    ) -> Result<Option<u64>, Error> {
+        let (cached_before, cached_after) = match chain_store {
+            Some(chain_store) => chain_store.cached_blocks_around_timestamp(timestamp)?,
+            None => (None, None),
+        };
+        let in_range = |block: &(u64, u64)| up_to.map_or(true, |up_to| block.0 <= up_to);
+
+        // Block `high` is always after `timestamp`, and block `low` at or
+        // before it
+        let mut high = match cached_after.filter(in_range) {
+            Some((number, _)) => number,
+            None => {
+                let (number, block_timestamp) =
+                    self.block_timestamp(&logger, up_to).compat().await?;
+                if block_timestamp <= timestamp {
+                    // Whether a later block is still at or before
+                    // `timestamp` is not known yet
+                    return Ok(Some(number).filter(|_| block_timestamp == timestamp));
+                }
+                number
+            }
+        };
+        let mut low = match cached_before.filter(in_range) {
+            Some((number, _)) => number,
+            None => {
+                let (number, block_timestamp) =
+                    self.block_timestamp(&logger, Some(0)).compat().await?;
+                if block_timestamp > timestamp {
+                    return Ok(None);
+                }
+                number
+            }
+        };
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            let (_, block_timestamp) = self.block_timestamp(&logger, Some(mid)).compat().await?;
+            if block_timestamp <= timestamp {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(Some(low))
+    }
+
+    /// Allow `custom_rpc` to call these provider-specific JSON-RPC methods,
+    /// which the provider has to support.
+    pub fn with_custom_rpc_methods(self, methods: Vec<String>) -> Self {
+        EthereumAdapter {
+            custom_rpc_methods: Arc::new(methods.into_iter().collect()),
+            ..self
+        }
+    }
+
+    /// Batch the calls that are made against the same block through the
+    /// Multicall contract at `address`, which has to have Multicall2's
+    /// `tryAggregate`. Calls for blocks before the contract was deployed
+    /// are made one by one.
+    pub fn with_multicall(self, address: Option<Address>) -> Self {
+        EthereumAdapter {
+            multicall: address.map(|address| Arc::new(Multicall::new(address))),
+            ..self
+        }
+    }
+
+    /// Label the metrics of the adapter with the `network` its provider
+    /// serves.
+    pub fn with_network(self, network: &str) -> Self {
+        EthereumAdapter {
+            network: Arc::new(network.to_owned()),
+            ..self
+        }
+    }
+
+    /// Counts the retries of the requests for `operation`, usually the
+    /// JSON-RPC method, in the metrics of the provider
+    fn retries(&self, operation: &str) -> Arc<dyn RetryObserver> {
+        Arc::new(ProviderRetries {
+            metrics: self.metrics.cheap_clone(),
+            provider: self.url_hostname.cheap_clone(),
+            network: self.network.cheap_clone(),
+            operation: operation.to_owned(),
+        })
+    }
+
+    /// Make a call against `block_ptr`, together with the other calls for
+    /// that block if a Multicall contract is configured.
+    fn block_call(
+        &self,
+        logger: &Logger,
+        contract_address: Address,
+        call_data: Vec<u8>,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = EthereumContractCallError> + Send> {
+        let multicall = match &self.multicall {
+            Some(multicall) => multicall.cheap_clone(),
+            None => {
+                return Box::new(
+                    self.call(
+                        logger,
+                        contract_address,
+                        Bytes(call_data),
+                        BlockId::Hash(block_ptr.hash),
+                    )
+                    .map(|output| output.0),
+                )
+            }
+        };
+
+        let (sender, receiver) = oneshot::channel();
+        let call = PendingCall {
+            address: contract_address,
+            data: call_data,
+            result: sender,
+        };
+        if let Some(batch) = multicall.queue(block_ptr.hash, call) {
+            graph::spawn(self.cheap_clone().send_batches(
+                logger.clone(),
+                multicall,
+                block_ptr,
+                batch,
+            ));
+        }
+        Box::new(futures03::TryFutureExt::compat(
+            receiver
+                .map(|result| result.expect("batches send the results of all their calls"))
+                .boxed(),
+        ))
+    }
+
+    /// Send `batch`, and then the batches that were queued for the same
+    /// block while it was in flight
+    async fn send_batches(
+        self,
+        logger: Logger,
+        multicall: Arc<Multicall>,
+        block_ptr: EthereumBlockPointer,
+        mut batch: Vec<PendingCall>,
+    ) {
+        loop {
+            self.send_batch(&logger, &multicall, block_ptr, batch).await;
+            batch = match multicall.returned(block_ptr.hash) {
+                Some(batch) => batch,
+                None => return,
+            };
+        }
+    }
+
+    async fn send_batch(
+        &self,
+        logger: &Logger,
+        multicall: &Multicall,
+        block_ptr: EthereumBlockPointer,
+        batch: Vec<PendingCall>,
+    ) {
+        let results = if batch.len() > 1
+            && self
+                .multicall_deployed(logger, multicall, block_ptr.number)
+                .await
+        {
+            let output = self
+                .call(
+                    logger,
+                    multicall.address,
+                    Bytes(encode_try_aggregate(&batch)),
+                    BlockId::Hash(block_ptr.hash),
+                )
+                .compat()
+                .await;
+            match output {
+                Ok(output) => decode_try_aggregate(&output.0, batch.len()),
+                Err(e) => {
+                    debug!(logger, "Multicall failed, making the calls one by one";
+                           "block" => block_ptr.number,
+                           "error" => e.to_string());
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // The calls that have to be made one by one
+        let batch = match results {
+            Some(results) => {
+                let mut unresolved = vec![];
+                for (call, result) in batch.into_iter().zip(results) {
+                    match result {
+                        Some(result) => {
+                            let _ = call.result.send(result);
+                        }
+                        None => unresolved.push(call),
+                    }
+                }
+                unresolved
+            }
+            None => batch,
+        };
+
+        let calls = batch.into_iter().map(|call| {
+            let output = self
+                .call(
+                    logger,
+                    call.address,
+                    Bytes(call.data),
+                    BlockId::Hash(block_ptr.hash),
+                )
+                .compat();
+            async move {
+                let _ = call.result.send(output.await.map(|output| output.0));
+            }
+        });
+        futures03::future::join_all(calls).await;
+    }
+
+    /// Whether the Multicall contract exists in block `number`. If that can
+    /// not be found out, the calls are made one by one
+    async fn multicall_deployed(
+        &self,
+        logger: &Logger,
+        multicall: &Multicall,
+        number: u64,
+    ) -> bool {
+        if let Some(deployed) = multicall.deployed_at(number) {
+            return deployed;
+        }
+
+        let web3 = self.web3.cheap_clone();
+        let address = multicall.address;
+        let code = retry("eth_getCode RPC call", logger)
+            .observe(self.retries("eth_getCode"))
+            .limit(*REQUEST_RETRIES)
+            .timeout_secs(*JSON_RPC_TIMEOUT)
+            .run(move || {
+                web3.eth()
+                    .code(address, Some(BlockNumber::Number(number.into())))
+                    .from_err::<Error>()
+            })
+            .compat()
+            .await;
+        match code {
+            Ok(code) => {
+                let deployed = !code.0.is_empty();
+                multicall.set_deployed_at(number, deployed);
+                deployed
+            }
+            Err(_) => false,
         }
     }
 
@@ -106,6 +536,7 @@ where
         let logger = logger.to_owned();
 
         retry("trace_filter RPC call", &logger)
+            .observe(self.retries("trace_filter"))
             .limit(*REQUEST_RETRIES)
             .timeout_secs(*JSON_RPC_TIMEOUT)
             .run(move || {
@@ -170,12 +601,11 @@ where
                     })
             })
             .map_err(move |e| {
-                e.into_inner().unwrap_or_else(move || {
-                    format_err!(
+                EthereumRpcError::from_timeout(e, move || {
+                    format!(
                         "Ethereum node took too long to respond to trace_filter \
                          (from block {}, to block {})",
-                        from,
-                        to
+                        from, to
                     )
                 })
             })
@@ -193,6 +623,7 @@ where
         let eth_adapter = self.clone();
 
         retry("eth_getLogs RPC call", &logger)
+            .observe(self.retries("eth_getLogs"))
             .when(move |res: &Result<_, web3::error::Error>| match res {
                 Ok(_) => false,
                 Err(e) => !too_many_logs_fingerprints
@@ -206,12 +637,17 @@ where
                 let subgraph_metrics = subgraph_metrics.clone();
                 let provider_metrics = eth_adapter.metrics.clone();
 
-                // Create a log filter
+                // Create a log filter; without event signatures, it must not have a topic0
+                // filter at all since that would not match logs without topics
+                let event_signatures = match filter.event_signatures.is_empty() {
+                    true => None,
+                    false => Some(filter.event_signatures.clone()),
+                };
                 let log_filter: Filter = FilterBuilder::default()
                     .from_block(from.into())
                     .to_block(to.into())
                     .address(filter.contracts.clone())
-                    .topics(Some(filter.event_signatures.clone()), None, None, None)
+                    .topics(event_signatures, None, None, None)
                     .build();
 
                 // Request logs from client
@@ -228,6 +664,63 @@ where
             })
     }
 
+    /// Like `logs_with_sigs`, but shares the request with other deployments
+    /// that need the same logs, see `SharedLogs`.
+    async fn shared_logs_with_sigs(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        filter: Arc<EthGetLogsFilter>,
+        too_many_logs_fingerprints: &'static [&'static str],
+    ) -> Result<Vec<Log>, TimeoutError<String>> {
+        let key = LogsKey::new(from, to, &filter);
+        let found = self.shared_logs.lock().unwrap().find(&key);
+        let (request, exact) = match found {
+            Some(found) => found,
+            None => {
+                let request = self
+                    .logs_with_sigs(
+                        logger,
+                        subgraph_metrics,
+                        from,
+                        to,
+                        filter,
+                        too_many_logs_fingerprints,
+                    )
+                    .compat()
+                    .map(|res| {
+                        res.map(Arc::new).map_err(|e| match e {
+                            TimeoutError::Inner(e) => TimeoutError::Inner(e.to_string()),
+                            TimeoutError::Elapsed => TimeoutError::Elapsed,
+                        })
+                    })
+                    .boxed()
+                    .shared();
+                self.shared_logs
+                    .lock()
+                    .unwrap()
+                    .insert(key.clone(), request.clone());
+                (request, true)
+            }
+        };
+
+        match request.await {
+            Ok(logs) if exact => Ok((*logs).clone()),
+            Ok(logs) => Ok(logs
+                .iter()
+                .filter(|log| key.matches(log))
+                .cloned()
+                .collect()),
+            Err(e) => {
+                // Don't hand out failures to later requests
+                self.shared_logs.lock().unwrap().remove(&key);
+                Err(e)
+            }
+        }
+    }
+
     fn trace_stream(
         self,
         logger: &Logger,
@@ -331,7 +824,7 @@ where
                     "Requesting logs for blocks [{}, {}], {}", start, end, filter
                 );
                 let res = eth
-                    .logs_with_sigs(
+                    .shared_logs_with_sigs(
                         &logger,
                         subgraph_metrics.cheap_clone(),
                         start,
@@ -339,13 +832,10 @@ where
                         filter.cheap_clone(),
                         TOO_MANY_LOGS_FINGERPRINTS,
                     )
-                    .compat()
                     .await;
 
                 match res {
-                    Err(e) => {
-                        let string_err = e.to_string();
-
+                    Err(TimeoutError::Inner(string_err)) => {
                         // If the step is already 0, the request is too heavy even for a single
                         // block. We hope this never happens, but if it does, make sure to error.
                         if TOO_MANY_LOGS_FINGERPRINTS
@@ -361,9 +851,14 @@ where
                             Ok(Some((vec![], (start, new_step))))
                         } else {
                             warn!(logger, "Unexpected RPC error"; "error" => &string_err);
-                            Err(err_msg(string_err))
+                            Err(EthereumRpcError::Provider(err_msg(string_err)).into())
                         }
                     }
+                    Err(TimeoutError::Elapsed) => Err(EthereumRpcError::Timeout(format!(
+                        "Ethereum node took too long to return logs for blocks [{}, {}]",
+                        start, end
+                    ))
+                    .into()),
                     Ok(logs) => Ok(Some((logs, (end + 1, step)))),
                 }
             }
@@ -377,11 +872,11 @@ where
         logger: &Logger,
         contract_address: Address,
         call_data: Bytes,
-        block_number_opt: Option<BlockNumber>,
+        block: BlockId,
     ) -> impl Future<Item = Bytes, Error = EthereumContractCallError> + Send {
-        let block_number_opt = block_number_opt.map(Into::into);
         let web3 = self.web3.clone();
         let logger = logger.clone();
+        let retries = self.retries("eth_call");
 
         // Outer retry used only for 0-byte responses,
         // where we can't guarantee the problem is temporary.
@@ -405,8 +900,9 @@ where
                 let call_data = call_data.clone();
 
                 retry("eth_call RPC call", &logger)
+                    .observe(retries.cheap_clone())
                     .when(|result| match result {
-                        Ok(_) | Err(EthereumContractCallError::Revert(_)) => false,
+                        Ok(_) | Err(EthereumContractCallError::Revert(..)) => false,
                         Err(_) => true,
                     })
                     .no_limit()
@@ -420,7 +916,7 @@ where
                             value: None,
                             data: Some(call_data.clone()),
                         };
-                        web3.eth().call(req, block_number_opt).then(|result| {
+                        web3.eth().call(req, Some(block)).then(|result| {
                             // Try to check if the call was reverted. The JSON-RPC response for
                             // reverts is not standardized, the current situation for the tested
                             // clients is:
@@ -475,7 +971,9 @@ where
                                 // Check for old Geth revert with reason.
                                 Ok(bytes) => match as_solidity_revert_with_reason(&bytes.0) {
                                     None => Ok(bytes),
-                                    Some(reason) => Err(EthereumContractCallError::Revert(reason)),
+                                    Some(reason) => {
+                                        Err(EthereumContractCallError::Revert(reason, bytes.0))
+                                    }
                                 },
 
                                 // Check for Geth revert.
@@ -484,7 +982,16 @@ where
                                         .iter()
                                         .any(|e| rpc_error.message.contains(e)) =>
                                 {
-                                    Err(EthereumContractCallError::Revert(rpc_error.message))
+                                    // Newer versions of Geth return the revert data
+                                    let data = rpc_error
+                                        .data
+                                        .as_ref()
+                                        .and_then(|data| data.as_str())
+                                        .and_then(|data| {
+                                            hex::decode(data.trim_start_matches("0x")).ok()
+                                        })
+                                        .unwrap_or_default();
+                                    Err(EthereumContractCallError::Revert(rpc_error.message, data))
                                 }
 
                                 // Check for Parity revert.
@@ -498,19 +1005,22 @@ where
                                                 || data == PARITY_BAD_INSTRUCTION_FE
                                                 || data == PARITY_BAD_INSTRUCTION_FD =>
                                         {
+                                            let payload = if data.starts_with(PARITY_REVERT_PREFIX)
+                                            {
+                                                hex::decode(
+                                                    data.trim_start_matches(PARITY_REVERT_PREFIX),
+                                                )
+                                                .unwrap_or_default()
+                                            } else {
+                                                vec![]
+                                            };
                                             let reason = if data == PARITY_BAD_INSTRUCTION_FE {
                                                 PARITY_BAD_INSTRUCTION_FE.to_owned()
                                             } else {
-                                                let payload =
-                                                    data.trim_start_matches(PARITY_REVERT_PREFIX);
-                                                hex::decode(payload)
-                                                    .ok()
-                                                    .and_then(|payload| {
-                                                        as_solidity_revert_with_reason(&payload)
-                                                    })
+                                                as_solidity_revert_with_reason(&payload)
                                                     .unwrap_or("no reason".to_owned())
                                             };
-                                            Err(EthereumContractCallError::Revert(reason))
+                                            Err(EthereumContractCallError::Revert(reason, payload))
                                         }
 
                                         // The VM execution error was not identified as a revert.
@@ -529,6 +1039,7 @@ where
                                 {
                                     Err(EthereumContractCallError::Revert(
                                         rpc_error.message.clone(),
+                                        vec![],
                                     ))
                                 }
 
@@ -548,10 +1059,12 @@ where
         ids: Vec<H256>,
     ) -> impl Stream<Item = LightEthereumBlock, Error = Error> + Send {
         let web3 = self.web3.clone();
+        let retries = self.retries("eth_getBlockByHash");
 
         stream::iter_ok::<_, Error>(ids.into_iter().map(move |hash| {
             let web3 = web3.clone();
             retry(format!("load block {}", hash), &logger)
+                .observe(retries.cheap_clone())
                 .limit(*REQUEST_RETRIES)
                 .timeout_secs(*JSON_RPC_TIMEOUT)
                 .run(move || {
@@ -579,10 +1092,12 @@ where
         block_nums: Vec<u64>,
     ) -> impl Stream<Item = EthereumBlockPointer, Error = Error> + Send {
         let web3 = self.web3.clone();
+        let retries = self.retries("eth_getBlockByNumber");
 
         stream::iter_ok::<_, Error>(block_nums.into_iter().map(move |block_num| {
             let web3 = web3.clone();
             retry(format!("load block ptr {}", block_num), &logger)
+                .observe(retries.cheap_clone())
                 .no_limit()
                 .timeout_secs(*JSON_RPC_TIMEOUT)
                 .run(move || {
@@ -614,6 +1129,59 @@ where
         &self.url_hostname
     }
 
+    fn supports_custom_rpc(&self, method: &str) -> bool {
+        self.custom_rpc_methods.contains(method)
+    }
+
+    fn custom_rpc(
+        &self,
+        logger: &Logger,
+        method: &str,
+        params: Vec<serde_json::Value>,
+    ) -> Box<dyn Future<Item = serde_json::Value, Error = Error> + Send> {
+        if !self.supports_custom_rpc(method) {
+            return Box::new(future::err(format_err!(
+                "the provider {} does not support the custom JSON-RPC method `{}`",
+                self.url_hostname,
+                method
+            )));
+        }
+
+        let web3 = self.web3.clone();
+        let metrics = self.metrics.clone();
+        let method = method.to_owned();
+        let method_for_error = method.clone();
+
+        Box::new(
+            retry(format!("{} RPC call", method), logger)
+                .observe(self.retries(&method))
+                .limit(*REQUEST_RETRIES)
+                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .run(move || {
+                    let start = Instant::now();
+                    let metrics = metrics.clone();
+                    let method = method.clone();
+                    web3::Transport::execute(web3.transport(), &method, params.clone())
+                        .from_err()
+                        .then(move |result| {
+                            metrics.observe_request(start.elapsed().as_secs_f64(), &method);
+                            if result.is_err() {
+                                metrics.add_error(&method);
+                            }
+                            result
+                        })
+                })
+                .map_err(move |e| {
+                    EthereumRpcError::from_timeout(e, move || {
+                        format!(
+                            "Ethereum node took too long to respond to {}",
+                            method_for_error
+                        )
+                    })
+                }),
+        )
+    }
+
     fn net_identifiers(
         &self,
         logger: &Logger,
@@ -622,12 +1190,14 @@ where
 
         let web3 = self.web3.clone();
         let net_version_future = retry("net_version RPC call", &logger)
+            .observe(self.retries("net_version"))
             .no_limit()
             .timeout_secs(20)
             .run(move || web3.net().version().from_err());
 
         let web3 = self.web3.clone();
         let gen_block_hash_future = retry("eth_getBlockByNumber(0, false) RPC call", &logger)
+            .observe(self.retries("eth_getBlockByNumber"))
             .no_limit()
             .timeout_secs(30)
             .run(move || {
@@ -670,6 +1240,7 @@ where
 
         Box::new(
             retry("eth_getBlockByNumber(latest) no txs RPC call", logger)
+                .observe(self.retries("eth_getBlockByNumber"))
                 .no_limit()
                 .timeout_secs(*JSON_RPC_TIMEOUT)
                 .run(move || {
@@ -685,7 +1256,12 @@ where
                 })
                 .map_err(move |e| {
                     e.into_inner().unwrap_or_else(move || {
-                        format_err!("Ethereum node took too long to return latest block").into()
+                        EthereumAdapterError::Unknown(
+                            EthereumRpcError::Timeout(
+                                "Ethereum node took too long to return latest block".to_owned(),
+                            )
+                            .into(),
+                        )
                     })
                 }),
         )
@@ -700,6 +1276,7 @@ where
 
         Box::new(
             retry("eth_getBlockByNumber(latest) with txs RPC call", logger)
+                .observe(self.retries("eth_getBlockByNumber"))
                 .no_limit()
                 .timeout_secs(*JSON_RPC_TIMEOUT)
                 .run(move || {
@@ -715,7 +1292,12 @@ where
                 })
                 .map_err(move |e| {
                     e.into_inner().unwrap_or_else(move || {
-                        format_err!("Ethereum node took too long to return latest block").into()
+                        EthereumAdapterError::Unknown(
+                            EthereumRpcError::Timeout(
+                                "Ethereum node took too long to return latest block".to_owned(),
+                            )
+                            .into(),
+                        )
                     })
                 }),
         )
@@ -730,10 +1312,11 @@ where
             self.block_by_hash(&logger, block_hash)
                 .and_then(move |block_opt| {
                     block_opt.ok_or_else(move || {
-                        format_err!(
+                        EthereumRpcError::Inconsistent(format!(
                             "Ethereum node could not find block with hash {}",
                             block_hash
-                        )
+                        ))
+                        .into()
                     })
                 }),
         )
@@ -749,6 +1332,7 @@ where
 
         Box::new(
             retry("eth_getBlockByHash RPC call", &logger)
+                .observe(self.retries("eth_getBlockByHash"))
                 .limit(*REQUEST_RETRIES)
                 .timeout_secs(*JSON_RPC_TIMEOUT)
                 .run(move || {
@@ -757,8 +1341,8 @@ where
                         .from_err()
                 })
                 .map_err(move |e| {
-                    e.into_inner().unwrap_or_else(move || {
-                        format_err!("Ethereum node took too long to return block {}", block_hash)
+                    EthereumRpcError::from_timeout(e, move || {
+                        format!("Ethereum node took too long to return block {}", block_hash)
                     })
                 }),
         )
@@ -774,6 +1358,7 @@ where
 
         Box::new(
             retry("eth_getBlockByNumber RPC call", &logger)
+                .observe(self.retries("eth_getBlockByNumber"))
                 .no_limit()
                 .timeout_secs(*JSON_RPC_TIMEOUT)
                 .run(move || {
@@ -782,8 +1367,8 @@ where
                         .from_err()
                 })
                 .map_err(move |e| {
-                    e.into_inner().unwrap_or_else(move || {
-                        format_err!(
+                    EthereumRpcError::from_timeout(e, move || {
+                        format!(
                             "Ethereum node took too long to return block {}",
                             block_number
                         )
@@ -810,103 +1395,120 @@ where
             }));
         }
         let web3 = self.web3.clone();
+        let batch_size = self
+            .receipt_batch_size
+            .unwrap_or(block.transactions.len())
+            .max(1);
 
         // Retry, but eventually give up.
         // A receipt might be missing because the block was uncled, and the
         // transaction never made it back into the main chain.
         Box::new(
             retry("batch eth_getTransactionReceipt RPC call", &logger)
+                .observe(self.retries("eth_getTransactionReceipt"))
                 .limit(16)
                 .no_logging()
                 .timeout_secs(*JSON_RPC_TIMEOUT)
                 .run(move || {
                     let block = block.clone();
-                    let batching_web3 = Web3::new(Batch::new(web3.transport().clone()));
 
-                    let receipt_futures = block
+                    // Request the receipts in batches of `batch_size`, all at the same time
+                    let batches = block
                         .transactions
-                        .iter()
-                        .map(|tx| {
-                            let logger = logger.clone();
-                            let tx_hash = tx.hash;
+                        .chunks(batch_size)
+                        .map(|transactions| {
+                            let batching_web3 = Web3::new(Batch::new(web3.transport().clone()));
+
+                            let receipt_futures = transactions
+                                .iter()
+                                .map(|tx| {
+                                    let logger = logger.clone();
+                                    let tx_hash = tx.hash;
+                                    batching_web3
+                                        .eth()
+                                        .transaction_receipt(tx_hash)
+                                        .from_err()
+                                        .map_err(EthereumAdapterError::Unknown)
+                                        .and_then(move |receipt_opt| {
+                                            receipt_opt.ok_or_else(move || {
+                                                // No receipt was returned.
+                                                //
+                                                // This can be because the Ethereum node no longer
+                                                // considers this block to be part of the main
+                                                // chain, and so the transaction is no longer in the
+                                                // main chain.  Nothing we can do from here except
+                                                // give up trying to ingest this block.
+                                                //
+                                                // This could also be because the receipt is simply
+                                                // not available yet.  For that case, we should
+                                                // retry until it becomes available.
+                                                EthereumAdapterError::BlockUnavailable(block_hash)
+                                            })
+                                        })
+                                        .and_then(move |receipt| {
+                                            // Parity nodes seem to return receipts with no block
+                                            // hash when a transaction is no longer in the main
+                                            // chain, so treat that case the same as a receipt being
+                                            // absent entirely.
+                                            let receipt_block_hash =
+                                                receipt.block_hash.ok_or_else(|| {
+                                                    EthereumAdapterError::BlockUnavailable(
+                                                        block_hash,
+                                                    )
+                                                })?;
+
+                                            // Check if receipt is for the right block
+                                            if receipt_block_hash != block_hash {
+                                                trace!(
+                                                    logger, "receipt block mismatch";
+                                                    "receipt_block_hash" =>
+                                                        receipt_block_hash.to_string(),
+                                                    "block_hash" =>
+                                                        block_hash.to_string(),
+                                                    "tx_hash" => tx_hash.to_string(),
+                                                );
+
+                                                // If the receipt came from a different block, then
+                                                // the Ethereum node no longer considers this block
+                                                // to be in the main chain.  Nothing we can do from
+                                                // here except give up trying to ingest this block.
+                                                // There is no way to get the transaction receipt
+                                                // from this block.
+                                                Err(EthereumAdapterError::BlockUnavailable(
+                                                    block_hash,
+                                                ))
+                                            } else {
+                                                Ok(receipt)
+                                            }
+                                        })
+                                })
+                                .collect::<Vec<_>>();
 
                             batching_web3
-                                .eth()
-                                .transaction_receipt(tx_hash)
+                                .transport()
+                                .submit_batch()
                                 .from_err()
                                 .map_err(EthereumAdapterError::Unknown)
-                                .and_then(move |receipt_opt| {
-                                    receipt_opt.ok_or_else(move || {
-                                        // No receipt was returned.
-                                        //
-                                        // This can be because the Ethereum node no longer
-                                        // considers this block to be part of the main chain,
-                                        // and so the transaction is no longer in the main
-                                        // chain.  Nothing we can do from here except give up
-                                        // trying to ingest this block.
-                                        //
-                                        // This could also be because the receipt is simply not
-                                        // available yet.  For that case, we should retry until
-                                        // it becomes available.
-                                        EthereumAdapterError::BlockUnavailable(block_hash)
-                                    })
-                                })
-                                .and_then(move |receipt| {
-                                    // Parity nodes seem to return receipts with no block hash
-                                    // when a transaction is no longer in the main chain, so
-                                    // treat that case the same as a receipt being absent
-                                    // entirely.
-                                    let receipt_block_hash =
-                                        receipt.block_hash.ok_or_else(|| {
-                                            EthereumAdapterError::BlockUnavailable(block_hash)
-                                        })?;
-
-                                    // Check if receipt is for the right block
-                                    if receipt_block_hash != block_hash {
-                                        trace!(
-                                            logger, "receipt block mismatch";
-                                            "receipt_block_hash" =>
-                                                receipt_block_hash.to_string(),
-                                            "block_hash" =>
-                                                block_hash.to_string(),
-                                            "tx_hash" => tx_hash.to_string(),
-                                        );
-
-                                        // If the receipt came from a different block, then the
-                                        // Ethereum node no longer considers this block to be
-                                        // in the main chain.  Nothing we can do from here
-                                        // except give up trying to ingest this block.
-                                        // There is no way to get the transaction receipt from
-                                        // this block.
-                                        Err(EthereumAdapterError::BlockUnavailable(block_hash))
-                                    } else {
-                                        Ok(receipt)
-                                    }
+                                .and_then(move |_| {
+                                    stream::futures_ordered(receipt_futures).collect()
                                 })
                         })
                         .collect::<Vec<_>>();
 
-                    batching_web3
-                        .transport()
-                        .submit_batch()
-                        .from_err()
-                        .map_err(EthereumAdapterError::Unknown)
-                        .and_then(move |_| {
-                            stream::futures_ordered(receipt_futures).collect().map(
-                                move |transaction_receipts| EthereumBlock {
-                                    block,
-                                    transaction_receipts,
-                                },
-                            )
-                        })
+                    future::join_all(batches).map(move |receipts| EthereumBlock {
+                        block,
+                        transaction_receipts: receipts.into_iter().flatten().collect(),
+                    })
                 })
                 .map_err(move |e| {
                     e.into_inner().unwrap_or_else(move || {
-                        format_err!(
-                            "Ethereum node took too long to return receipts for block {}",
-                            block_hash
+                        EthereumAdapterError::Unknown(
+                            EthereumRpcError::Timeout(format!(
+                                "Ethereum node took too long to return receipts for block {}",
+                                block_hash
+                            ))
+                            .into(),
                         )
-                        .into()
                     })
                 }),
         )
@@ -939,6 +1541,20 @@ where
         )
     }
 
+    fn block_number_by_timestamp(
+        &self,
+        logger: &Logger,
+        chain_store: Option<Arc<dyn ChainStore>>,
+        timestamp: u64,
+        up_to: Option<u64>,
+    ) -> Box<dyn Future<Item = Option<u64>, Error = Error> + Send> {
+        Box::new(futures03::TryFutureExt::compat(
+            self.cheap_clone()
+                .find_block_number_by_timestamp(logger.clone(), chain_store, timestamp, up_to)
+                .boxed(),
+        ))
+    }
+
     fn block_hash_by_block_number(
         &self,
         logger: &Logger,
@@ -982,6 +1598,7 @@ where
         } else {
             Box::new(
                 retry("eth_getBlockByNumber RPC call", &logger)
+                    .observe(self.retries("eth_getBlockByNumber"))
                     .no_limit()
                     .timeout_secs(*JSON_RPC_TIMEOUT)
                     .run(move || {
@@ -992,8 +1609,8 @@ where
                     })
                     .inspect(confirm_block_hash)
                     .map_err(move |e| {
-                        e.into_inner().unwrap_or_else(move || {
-                            format_err!(
+                        EthereumRpcError::from_timeout(e, move || {
+                            format!(
                                 "Ethereum node took too long to return data for block #{}",
                                 block_number
                             )
@@ -1016,6 +1633,7 @@ where
                 let web3 = self.web3.clone();
 
                 retry("eth_getUncleByBlockHashAndIndex RPC call", &logger)
+                    .observe(self.retries("eth_getUncleByBlockHashAndIndex"))
                     .no_limit()
                     .timeout_secs(60)
                     .run(move || {
@@ -1032,8 +1650,8 @@ where
                             })
                     })
                     .map_err(move |e| {
-                        e.into_inner().unwrap_or_else(move || {
-                            format_err!("Ethereum node took too long to return uncle")
+                        EthereumRpcError::from_timeout(e, move || {
+                            "Ethereum node took too long to return uncle".to_owned()
                         })
                     })
             }))
@@ -1053,7 +1671,11 @@ where
                 .and_then(move |block_hash_opt| {
                     block_hash_opt
                         .ok_or_else(|| {
-                            format_err!("Ethereum node is missing block #{}", block_ptr.number)
+                            EthereumRpcError::Inconsistent(format!(
+                                "Ethereum node is missing block #{}",
+                                block_ptr.number
+                            ))
+                            .into()
                         })
                         .map(|block_hash| block_hash == block_ptr.hash)
                 }),
@@ -1083,23 +1705,27 @@ where
                 // includes a trace for the block reward which every block should have.
                 // If there are no traces something has gone wrong.
                 if traces.is_empty() {
-                    return future::err(format_err!(
-                        "Trace stream returned no traces for block: number = `{}`, hash = `{}`",
-                        block_number,
-                        block_hash,
-                    ));
+                    return future::err(
+                        EthereumRpcError::Inconsistent(format!(
+                            "Trace stream returned no traces for block: number = `{}`, hash = `{}`",
+                            block_number, block_hash,
+                        ))
+                        .into(),
+                    );
                 }
                 // Since we can only pull traces by block number and we have
                 // all the traces for the block, we need to ensure that the
                 // block hash for the traces is equal to the desired block hash.
                 // Assume all traces are for the same block.
                 if traces.iter().nth(0).unwrap().block_hash != block_hash {
-                    return future::err(format_err!(
-                        "Trace stream returned traces for an unexpected block: \
-                         number = `{}`, hash = `{}`",
-                        block_number,
-                        block_hash,
-                    ));
+                    return future::err(
+                        EthereumRpcError::Inconsistent(format!(
+                            "Trace stream returned traces for an unexpected block: \
+                             number = `{}`, hash = `{}`",
+                            block_number, block_hash,
+                        ))
+                        .into(),
+                    );
                 }
                 future::ok(traces)
             })
@@ -1192,10 +1818,31 @@ where
         // Encode the call parameters according to the ABI
         let call_data = call.function.encode_input(&call.args).unwrap();
 
+        // Calls against the latest block can not be cached since the
+        // latest block changes all the time
+        let block_ptr = match call.block_ptr {
+            Some(block_ptr) => block_ptr,
+            None => {
+                return Box::new(
+                    self.call(
+                        logger,
+                        call.address,
+                        Bytes(call_data),
+                        BlockId::Number(BlockNumber::Latest),
+                    )
+                    .and_then(move |output| decode_call_output(&call.function, output.0)),
+                )
+            }
+        };
+
         // Check if we have it cached, if not do the call and cache.
+        let cached = if call.immutable {
+            cache.get_immutable_call(call.address, &call_data)
+        } else {
+            cache.get_call(call.address, &call_data, block_ptr)
+        };
         Box::new(
-            match cache
-                .get_call(call.address, &call_data, call.block_ptr)
+            match cached
                 .map_err(|e| error!(logger, "call cache get error"; "error" => e.to_string()))
                 .ok()
                 .and_then(|x| x)
@@ -1209,39 +1856,34 @@ where
                     let call_data = call_data.clone();
                     let logger = logger.clone();
                     Box::new(
-                        self.call(
-                            &logger,
-                            call.address,
-                            Bytes(call_data.clone()),
-                            Some(call.block_ptr.number.into()),
-                        )
-                        .map(move |result| {
-                            let _ = cache
-                                .set_call(call.address, &call_data, call.block_ptr, &result.0)
-                                .map_err(|e| {
+                        self.block_call(&logger, call.address, call_data.clone(), block_ptr)
+                            .map(move |result| {
+                                // A block that is not final may still be
+                                // reorged away, so its results are never
+                                // cached, not even those of immutable calls
+                                let cached = if !call.block_is_final {
+                                    Ok(())
+                                } else if call.immutable {
+                                    cache.set_immutable_call(
+                                        call.address,
+                                        &call_data,
+                                        block_ptr,
+                                        &result,
+                                    )
+                                } else {
+                                    cache.set_call(call.address, &call_data, block_ptr, &result)
+                                };
+                                let _ = cached.map_err(|e| {
                                     error!(logger, "call cache set error";
                                                    "error" => e.to_string())
                                 });
-                            result.0
-                        }),
+                                result
+                            }),
                     )
                 }
             }
             // Decode the return values according to the ABI
-            .and_then(move |output| {
-                if output.is_empty() {
-                    // We got a `0x` response. For old Geth, this can mean a revert. It can also be
-                    // that the contract actually returned an empty response. A view call is meant
-                    // to return something, so we treat empty responses the same as reverts.
-                    Err(EthereumContractCallError::Revert("empty response".into()))
-                } else {
-                    // Decode failures are reverts. The reasoning is that if Solidity fails to
-                    // decode an argument, that's a revert, so the same goes for the output.
-                    call.function.decode_output(&output).map_err(|e| {
-                        EthereumContractCallError::Revert(format!("failed to decode output: {}", e))
-                    })
-                }
-            }),
+            .and_then(move |output| decode_call_output(&call.function, output)),
         )
     }
 
@@ -1297,3 +1939,192 @@ where
         )
     }
 }
+
+/// Counts the retries of one kind of request to a provider, see
+/// `EthereumAdapter::retries`
+struct ProviderRetries {
+    metrics: Arc<ProviderEthRpcMetrics>,
+    provider: Arc<String>,
+    network: Arc<String>,
+    operation: String,
+}
+
+impl RetryObserver for ProviderRetries {
+    fn retried(&self) {
+        self.metrics
+            .add_retry(&self.provider, &self.network, &self.operation);
+    }
+
+    fn exhausted(&self) {
+        self.metrics
+            .add_retries_exhausted(&self.provider, &self.network, &self.operation);
+    }
+}
+
+/// Decode the return values of a call according to the ABI of `function`.
+fn decode_call_output(
+    function: &ethabi::Function,
+    output: Vec<u8>,
+) -> Result<Vec<Token>, EthereumContractCallError> {
+    if output.is_empty() {
+        // We got a `0x` response. For old Geth, this can mean a revert. It can also be
+        // that the contract actually returned an empty response. A view call is meant
+        // to return something, so we treat empty responses the same as reverts.
+        Err(EthereumContractCallError::Revert(
+            "empty response".into(),
+            vec![],
+        ))
+    } else {
+        // Decode failures are reverts. The reasoning is that if Solidity fails to
+        // decode an argument, that's a revert, so the same goes for the output.
+        function.decode_output(&output).map_err(|e| {
+            EthereumContractCallError::Revert(format!("failed to decode output: {}", e), vec![])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::serde_json::Value;
+    use graph::prelude::tokio::runtime::Runtime;
+    use jsonrpc_core::types::Call;
+    use mock::MockMetricsRegistry;
+    use web3::RequestId;
+
+    /// Answers every request with a `uint256` of 1, like an `eth_call` of
+    /// a function that returns it
+    #[derive(Clone, Debug)]
+    struct CallTransport;
+
+    fn one() -> Value {
+        Value::String(format!("0x{:064x}", 1))
+    }
+
+    impl web3::Transport for CallTransport {
+        type Out = Box<dyn Future<Item = Value, Error = web3::error::Error> + Send>;
+
+        fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+            (0, web3::helpers::build_request(0, method, params))
+        }
+
+        fn send(&self, _id: RequestId, _request: Call) -> Self::Out {
+            Box::new(future::ok(one()))
+        }
+    }
+
+    impl web3::BatchTransport for CallTransport {
+        type Batch = Box<
+            dyn Future<Item = Vec<Result<Value, web3::error::Error>>, Error = web3::error::Error>
+                + Send,
+        >;
+
+        fn send_batch<T>(&self, requests: T) -> Self::Batch
+        where
+            T: IntoIterator<Item = (RequestId, Call)>,
+        {
+            Box::new(future::ok(
+                requests.into_iter().map(|_| Ok(one())).collect(),
+            ))
+        }
+    }
+
+    /// Remembers the blocks of the calls whose results were cached, and
+    /// whether they were cached as immutable calls
+    #[derive(Default)]
+    struct RecordingCache {
+        cached: Mutex<Vec<(EthereumBlockPointer, bool)>>,
+    }
+
+    impl EthereumCallCache for RecordingCache {
+        fn get_call(
+            &self,
+            _contract_address: Address,
+            _encoded_call: &[u8],
+            _block: EthereumBlockPointer,
+        ) -> Result<Option<Vec<u8>>, Error> {
+            Ok(None)
+        }
+
+        fn set_call(
+            &self,
+            _contract_address: Address,
+            _encoded_call: &[u8],
+            block: EthereumBlockPointer,
+            _return_value: &[u8],
+        ) -> Result<(), Error> {
+            self.cached.lock().unwrap().push((block, false));
+            Ok(())
+        }
+
+        fn get_immutable_call(
+            &self,
+            _contract_address: Address,
+            _encoded_call: &[u8],
+        ) -> Result<Option<Vec<u8>>, Error> {
+            Ok(None)
+        }
+
+        fn set_immutable_call(
+            &self,
+            _contract_address: Address,
+            _encoded_call: &[u8],
+            block: EthereumBlockPointer,
+            _return_value: &[u8],
+        ) -> Result<(), Error> {
+            self.cached.lock().unwrap().push((block, true));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn only_calls_against_final_blocks_are_cached() {
+        const ABI: &str = r#"[{
+            "type": "function",
+            "name": "total",
+            "inputs": [],
+            "outputs": [{ "name": "", "type": "uint256" }]
+        }]"#;
+        let contract = ethabi::Contract::load(ABI.as_bytes()).unwrap();
+        let function = contract.function("total").unwrap().clone();
+
+        let adapter = EthereumAdapter::new(
+            "http://localhost:8545",
+            CallTransport,
+            Arc::new(ProviderEthRpcMetrics::new(Arc::new(
+                MockMetricsRegistry::new(),
+            ))),
+        );
+        let cache = Arc::new(RecordingCache::default());
+        let logger = graph::log::logger(false);
+        let mut runtime = Runtime::new().unwrap();
+
+        let final_block = EthereumBlockPointer::from((H256::from_low_u64_be(1), 1u64));
+        let recent_block = EthereumBlockPointer::from((H256::from_low_u64_be(2), 2u64));
+        let calls = vec![
+            (recent_block, false, false),
+            (final_block, true, false),
+            (recent_block, false, true),
+            (final_block, true, true),
+        ];
+        for (block_ptr, block_is_final, immutable) in calls {
+            let call = EthereumContractCall {
+                address: Address::from_low_u64_be(7),
+                block_ptr: Some(block_ptr),
+                block_is_final,
+                function: function.clone(),
+                args: vec![],
+                immutable,
+            };
+            let tokens = runtime
+                .block_on(adapter.contract_call(&logger, call, cache.clone()).compat())
+                .unwrap();
+            assert_eq!(vec![Token::Uint(U256::from(1))], tokens);
+        }
+
+        assert_eq!(
+            vec![(final_block, false), (final_block, true)],
+            *cache.cached.lock().unwrap()
+        );
+    }
+}
@@ -1,14 +1,17 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod block_buffer;
 mod block_ingestor;
 mod block_stream;
 mod config;
 mod ethereum_adapter;
+mod multicall;
 pub mod network_indexer;
 mod transport;
 
 pub use self::block_ingestor::{BlockIngestor, BlockIngestorMetrics};
-pub use self::block_stream::{BlockStream, BlockStreamBuilder};
+pub use self::block_stream::{BlockStream, BlockStreamBuilder, FollowSettings};
+pub use self::config::http_headers;
 pub use self::ethereum_adapter::EthereumAdapter;
 pub use self::transport::{EventLoopHandle, Transport};
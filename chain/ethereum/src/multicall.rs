@@ -0,0 +1,270 @@
+//! Batching of the `eth_call`s that deployments make against the same
+//! block into one call of the `tryAggregate` function of a Multicall
+//! contract, like Multicall2 or Multicall3, which makes the calls and
+//! returns all of their results.
+//!
+//! Calls are not held back to wait for others: a call for a block that no
+//! batch is in flight for is sent right away, and calls for the block that
+//! come in while a batch is in flight are sent together once it returns,
+//! or as soon as there are enough of them to fill a batch.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use graph::components::ethereum::EthereumContractCallError;
+use graph::prelude::ethabi::{self, ParamType, Token};
+use graph::prelude::futures03::channel::oneshot;
+use graph::prelude::tiny_keccak;
+use graph::prelude::web3::types::{Address, H256};
+use graph::util::ethereum::decode_revert_reason;
+
+lazy_static! {
+    /// The most calls that are sent in one call of the Multicall contract
+    static ref MULTICALL_BATCH_SIZE: usize = std::env::var("GRAPH_ETHEREUM_MULTICALL_BATCH_SIZE")
+        .unwrap_or("50".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_ETHEREUM_MULTICALL_BATCH_SIZE env var");
+}
+
+/// A call that waits to be sent, and where its result goes
+pub(crate) struct PendingCall {
+    pub address: Address,
+    pub data: Vec<u8>,
+    pub result: oneshot::Sender<Result<Vec<u8>, EthereumContractCallError>>,
+}
+
+#[derive(Default)]
+struct BlockCalls {
+    /// The number of batches for the block that are being sent
+    in_flight: usize,
+    queued: Vec<PendingCall>,
+}
+
+/// What is known about the blocks that the Multicall contract exists in:
+/// it does in all blocks from `deployed` on, and it does not in any block
+/// up to `missing`
+#[derive(Default)]
+struct Deployment {
+    missing: Option<u64>,
+    deployed: Option<u64>,
+}
+
+/// The calls that wait to be sent through the Multicall contract at
+/// `address`, by the hash of their block
+pub(crate) struct Multicall {
+    pub address: Address,
+    blocks: Mutex<HashMap<H256, BlockCalls>>,
+    deployment: Mutex<Deployment>,
+}
+
+impl Multicall {
+    pub fn new(address: Address) -> Self {
+        Multicall {
+            address,
+            blocks: Mutex::new(HashMap::new()),
+            deployment: Mutex::new(Deployment::default()),
+        }
+    }
+
+    /// Queue `call` for the block with `hash`. Returns the batch that has
+    /// to be sent now, if any
+    pub fn queue(&self, hash: H256, call: PendingCall) -> Option<Vec<PendingCall>> {
+        let mut blocks = self.blocks.lock().unwrap();
+        let calls = blocks.entry(hash).or_default();
+        calls.queued.push(call);
+        if calls.in_flight == 0 || calls.queued.len() >= *MULTICALL_BATCH_SIZE {
+            calls.in_flight += 1;
+            Some(calls.queued.drain(..).collect())
+        } else {
+            None
+        }
+    }
+
+    /// Note that a batch for the block with `hash` returned. Returns the
+    /// calls that were queued for the block in the meantime, which have to
+    /// be sent next
+    pub fn returned(&self, hash: H256) -> Option<Vec<PendingCall>> {
+        let mut blocks = self.blocks.lock().unwrap();
+        let calls = blocks
+            .get_mut(&hash)
+            .expect("blocks with batches in flight have calls");
+        calls.in_flight -= 1;
+        if !calls.queued.is_empty() {
+            calls.in_flight += 1;
+            let size = calls.queued.len().min(*MULTICALL_BATCH_SIZE);
+            Some(calls.queued.drain(..size).collect())
+        } else {
+            if calls.in_flight == 0 {
+                blocks.remove(&hash);
+            }
+            None
+        }
+    }
+
+    /// Whether the contract exists in block `number`, if that is known
+    pub fn deployed_at(&self, number: u64) -> Option<bool> {
+        let deployment = self.deployment.lock().unwrap();
+        if deployment
+            .deployed
+            .map_or(false, |deployed| number >= deployed)
+        {
+            Some(true)
+        } else if deployment
+            .missing
+            .map_or(false, |missing| number <= missing)
+        {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Remember whether the contract exists in block `number`
+    pub fn set_deployed_at(&self, number: u64, deployed: bool) {
+        let mut deployment = self.deployment.lock().unwrap();
+        if deployed {
+            deployment.deployed = Some(deployment.deployed.map_or(number, |n| n.min(number)));
+        } else {
+            deployment.missing = Some(deployment.missing.map_or(number, |n| n.max(number)));
+        }
+    }
+}
+
+fn try_aggregate_result_type() -> ParamType {
+    ParamType::Array(Box::new(ParamType::Tuple(vec![
+        Box::new(ParamType::Bool),
+        Box::new(ParamType::Bytes),
+    ])))
+}
+
+/// The call data for `tryAggregate(bool,(address,bytes)[])` with `calls`,
+/// which does not require the calls to succeed
+pub(crate) fn encode_try_aggregate(calls: &[PendingCall]) -> Vec<u8> {
+    let selector = &tiny_keccak::keccak256(b"tryAggregate(bool,(address,bytes)[])")[..4];
+    let calls = calls
+        .iter()
+        .map(|call| {
+            Token::Tuple(vec![
+                Token::Address(call.address),
+                Token::Bytes(call.data.clone()),
+            ])
+        })
+        .collect();
+    let mut data = selector.to_vec();
+    data.extend(ethabi::encode(&[Token::Bool(false), Token::Array(calls)]));
+    data
+}
+
+/// The results of the `count` calls from the output of `tryAggregate`, or
+/// `None` if the output is not what `tryAggregate` returns.
+///
+/// A call that failed without revert data has no result: it may only have
+/// failed because it was made through the Multicall contract, for example
+/// because it ran out of the gas that the contract passed on to it, so it
+/// has to be made on its own to find out
+pub(crate) fn decode_try_aggregate(
+    output: &[u8],
+    count: usize,
+) -> Option<Vec<Option<Result<Vec<u8>, EthereumContractCallError>>>> {
+    let results = match ethabi::decode(&[try_aggregate_result_type()], output)
+        .ok()?
+        .pop()?
+    {
+        Token::Array(results) => results,
+        _ => return None,
+    };
+    if results.len() != count {
+        return None;
+    }
+    results
+        .into_iter()
+        .map(|result| match result {
+            Token::Tuple(result) => match result.as_slice() {
+                [Token::Bool(true), Token::Bytes(data)] => Some(Some(Ok(data.clone()))),
+                [Token::Bool(false), Token::Bytes(data)] if data.is_empty() => Some(None),
+                [Token::Bool(false), Token::Bytes(data)] => {
+                    let reason =
+                        decode_revert_reason(data, &[]).unwrap_or_else(|| "no reason".to_owned());
+                    Some(Some(Err(EthereumContractCallError::Revert(
+                        reason,
+                        data.clone(),
+                    ))))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(n: u8) -> PendingCall {
+        let (result, _) = oneshot::channel();
+        PendingCall {
+            address: Address::from([n; 20]),
+            data: vec![n],
+            result,
+        }
+    }
+
+    #[test]
+    fn calls_are_queued_while_a_batch_is_in_flight() {
+        let multicall = Multicall::new(Address::zero());
+        let block = H256::from([1; 32]);
+
+        assert_eq!(1, multicall.queue(block, call(1)).unwrap().len());
+        assert!(multicall.queue(block, call(2)).is_none());
+        assert!(multicall.queue(block, call(3)).is_none());
+
+        // Other blocks have their own batches
+        assert_eq!(
+            1,
+            multicall.queue(H256::from([2; 32]), call(4)).unwrap().len()
+        );
+
+        let next = multicall.returned(block).unwrap();
+        assert_eq!(
+            vec![Address::from([2; 20]), Address::from([3; 20])],
+            next.iter().map(|call| call.address).collect::<Vec<_>>()
+        );
+        assert!(multicall.returned(block).is_none());
+        assert!(multicall.blocks.lock().unwrap().get(&block).is_none());
+    }
+
+    #[test]
+    fn deployment() {
+        let multicall = Multicall::new(Address::zero());
+        assert_eq!(None, multicall.deployed_at(100));
+
+        multicall.set_deployed_at(100, true);
+        multicall.set_deployed_at(10, false);
+        assert_eq!(Some(true), multicall.deployed_at(1000));
+        assert_eq!(Some(false), multicall.deployed_at(5));
+        assert_eq!(None, multicall.deployed_at(50));
+    }
+
+    #[test]
+    fn decode_results() {
+        let output = ethabi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![Token::Bool(true), Token::Bytes(vec![1, 2])]),
+            Token::Tuple(vec![Token::Bool(false), Token::Bytes(vec![0xde, 0xad])]),
+            Token::Tuple(vec![Token::Bool(false), Token::Bytes(vec![])]),
+        ])]);
+
+        let results = decode_try_aggregate(&output, 3).unwrap();
+        assert_eq!(vec![1, 2], *results[0].as_ref().unwrap().as_ref().unwrap());
+        match &results[1] {
+            Some(Err(EthereumContractCallError::Revert(reason, data))) => {
+                assert_eq!("no reason", reason);
+                assert_eq!(&vec![0xde, 0xad], data);
+            }
+            _ => panic!("expected a revert"),
+        }
+        // Calls that fail without revert data are made again on their own
+        assert!(results[2].is_none());
+
+        assert!(decode_try_aggregate(&output, 2).is_none());
+    }
+}
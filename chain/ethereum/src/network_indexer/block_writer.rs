@@ -167,6 +167,7 @@ impl WriteContext {
                                 block_ptr.clone(),
                                 modifications,
                                 stopwatch,
+                                vec![],
                             )
                             .map_err(|e| e.into())
                             .map(move |_| {
@@ -1,4 +1,5 @@
 use futures::future::FutureResult;
+use std::collections::BTreeSet;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use super::*;
@@ -92,7 +93,10 @@ fn create_subgraph(
             .expect("valid Ethereum network subgraph schema"),
         data_sources: vec![],
         graft: None,
+        migrate: None,
         templates: vec![],
+        features: BTreeSet::new(),
+        indexer_hints: IndexerHints::default(),
     };
 
     ops.extend(
@@ -110,7 +114,7 @@ fn create_subgraph(
 
     future::result(
         store
-            .create_subgraph_deployment(&manifest.schema, ops)
+            .create_subgraph_deployment(&manifest.schema, &manifest.indexer_hints, ops)
             .map_err(|e| e.into()),
     )
 }
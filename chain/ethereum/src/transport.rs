@@ -1,36 +1,119 @@
+use hyper::header::HeaderMap;
 use jsonrpc_core::types::Call;
 use serde_json::Value;
+use std::collections::HashSet;
 use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 
 pub use web3::transports::EventLoopHandle;
 use web3::transports::{http, ipc, ws};
 use web3::RequestId;
 
+use graph::components::ethereum::rpc_trace;
 use graph::prelude::*;
+use graph::util::budget::PriorityBudget;
 
 use super::config::ETHEREUM_CONFIG;
 
+lazy_static! {
+    /// The fraction of JSON-RPC calls whose requests and responses are
+    /// logged, between 0 and 1
+    static ref TRACE_SAMPLE_RATE: f64 = env::var("GRAPH_ETHEREUM_TRACE_RPC_SAMPLE_RATE")
+        .unwrap_or("0".into())
+        .parse::<f64>()
+        .expect("invalid GRAPH_ETHEREUM_TRACE_RPC_SAMPLE_RATE");
+
+    /// Deployments all of whose JSON-RPC calls are logged
+    static ref TRACE_DEPLOYMENTS: HashSet<String> =
+        env::var("GRAPH_ETHEREUM_TRACE_RPC_DEPLOYMENTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_owned)
+            .collect();
+}
+
 /// Abstraction over the different web3 transports.
+#[derive(Clone)]
+pub struct Transport {
+    kind: Kind,
+    tracer: Option<Arc<Tracer>>,
+    /// Shares the requests that a JSON-RPC over HTTP transport makes at
+    /// once among deployments according to their priority
+    budget: Option<Arc<PriorityBudget>>,
+}
+
+impl std::fmt::Debug for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Transport")
+            .field("kind", &self.kind)
+            .field("tracer", &self.tracer)
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug)]
-pub enum Transport {
+enum Kind {
     RPC(http::Http),
     IPC(ipc::Ipc),
     WS(ws::WebSocket),
 }
 
+impl From<Kind> for Transport {
+    fn from(kind: Kind) -> Self {
+        Transport {
+            kind,
+            tracer: None,
+            budget: None,
+        }
+    }
+}
+
+impl Kind {
+    fn send(&self, id: RequestId, request: Call) -> <Transport as web3::Transport>::Out {
+        match self {
+            Kind::RPC(http) => Box::new(http.send(id, request)),
+            Kind::IPC(ipc) => Box::new(ipc.send(id, request)),
+            Kind::WS(ws) => Box::new(ws.send(id, request)),
+        }
+    }
+
+    fn send_batch(
+        &self,
+        requests: Vec<(RequestId, Call)>,
+    ) -> <Transport as web3::BatchTransport>::Batch {
+        match self {
+            Kind::RPC(http) => Box::new(http.send_batch(requests)),
+            Kind::IPC(ipc) => Box::new(ipc.send_batch(requests)),
+            Kind::WS(ws) => Box::new(ws.send_batch(requests)),
+        }
+    }
+}
+
 impl Transport {
     /// Creates an IPC transport.
     pub fn new_ipc(ipc: &str) -> (EventLoopHandle, Self) {
-        ipc::Ipc::new(ipc)
-            .map(|(event_loop, transport)| (event_loop, Transport::IPC(transport)))
-            .expect("Failed to connect to Ethereum IPC")
+        Self::try_new_ipc(ipc).expect("Failed to connect to Ethereum IPC")
+    }
+
+    /// Creates an IPC transport, or returns the error of connecting to
+    /// `ipc`.
+    pub fn try_new_ipc(ipc: &str) -> Result<(EventLoopHandle, Self), web3::error::Error> {
+        ipc::Ipc::new(ipc).map(|(event_loop, transport)| (event_loop, Kind::IPC(transport).into()))
     }
 
     /// Creates a WebSocket transport.
     pub fn new_ws(ws: &str) -> (EventLoopHandle, Self) {
+        Self::try_new_ws(ws).expect("Failed to connect to Ethereum WS")
+    }
+
+    /// Creates a WebSocket transport, or returns the error of connecting to
+    /// `ws`.
+    pub fn try_new_ws(ws: &str) -> Result<(EventLoopHandle, Self), web3::error::Error> {
         ws::WebSocket::new(ws)
-            .map(|(event_loop, transport)| (event_loop, Transport::WS(transport)))
-            .expect("Failed to connect to Ethereum WS")
+            .map(|(event_loop, transport)| (event_loop, Kind::WS(transport).into()))
     }
 
     /// Creates a JSON-RPC over HTTP transport.
@@ -45,28 +128,166 @@ impl Transport {
         let cfg = ETHEREUM_CONFIG.rpc.get(rpc);
         let headers = cfg.map(|cfg| cfg.http_headers.clone()).unwrap_or_default();
 
-        http::Http::with_max_parallel_and_headers(rpc, max_parallel_http, headers)
-            .map(|(event_loop, transport)| (event_loop, Transport::RPC(transport)))
+        Self::new_rpc_with(rpc, max_parallel_http, headers)
+    }
+
+    /// Creates a JSON-RPC over HTTP transport that makes at most
+    /// `max_parallel` requests at a time and sends `headers` with each of
+    /// them, e.g. for authentication.
+    pub fn new_rpc_with(
+        rpc: &str,
+        max_parallel: usize,
+        headers: HeaderMap,
+    ) -> (EventLoopHandle, Self) {
+        Self::try_new_rpc_with(rpc, max_parallel, headers)
             .expect("Failed to connect to Ethereum RPC")
     }
+
+    /// Like `new_rpc_with`, but returns the error of setting up the
+    /// transport instead of panicking. When deployments compete for the
+    /// `max_parallel` requests, they get them according to their priority.
+    pub fn try_new_rpc_with(
+        rpc: &str,
+        max_parallel: usize,
+        headers: HeaderMap,
+    ) -> Result<(EventLoopHandle, Self), web3::error::Error> {
+        http::Http::with_max_parallel_and_headers(rpc, max_parallel, headers).map(
+            |(event_loop, transport)| {
+                let transport = Transport {
+                    budget: Some(PriorityBudget::new(max_parallel)),
+                    ..Kind::RPC(transport).into()
+                };
+                (event_loop, transport)
+            },
+        )
+    }
+
+    /// Log the requests and responses of the calls to the provider at `url`
+    /// that `GRAPH_ETHEREUM_TRACE_RPC_SAMPLE_RATE` and
+    /// `GRAPH_ETHEREUM_TRACE_RPC_DEPLOYMENTS` ask for. Only the host of the
+    /// provider is logged since its url often contains an API key.
+    pub fn with_tracing(self, logger: &Logger, url: &str) -> Self {
+        if *TRACE_SAMPLE_RATE <= 0.0 && TRACE_DEPLOYMENTS.is_empty() {
+            return self;
+        }
+        let provider = graph::url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_else(|| "ipc".to_owned());
+        Transport {
+            tracer: Some(Arc::new(Tracer {
+                logger: logger.new(o!("component" => "RpcTrace", "provider" => provider)),
+                calls: AtomicU64::new(0),
+            })),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Tracer {
+    logger: Logger,
+    /// The number of calls made so far, to sample them
+    calls: AtomicU64,
+}
+
+impl Tracer {
+    /// Start tracing a call with `request` if it is sampled or made for one
+    /// of the deployments that are traced
+    fn start(&self, request: impl FnOnce() -> Value) -> Option<Trace> {
+        let deployment = rpc_trace::current_deployment();
+        let traced = deployment
+            .as_ref()
+            .map_or(false, |id| TRACE_DEPLOYMENTS.contains(id.as_str()))
+            || sampled(
+                self.calls.fetch_add(1, Ordering::Relaxed),
+                *TRACE_SAMPLE_RATE,
+            );
+        if !traced {
+            return None;
+        }
+        let logger = match deployment {
+            Some(id) => self.logger.new(o!("subgraph_id" => id.to_string())),
+            None => self.logger.clone(),
+        };
+        Some(Trace {
+            logger,
+            request: request().to_string(),
+            start: Instant::now(),
+        })
+    }
+}
+
+/// Whether the `n`-th call is sampled when sampling the fraction `rate` of
+/// all calls. The sampled calls are spread evenly, e.g. every tenth call
+/// for a rate of 0.1
+fn sampled(n: u64, rate: f64) -> bool {
+    ((n + 1) as f64 * rate).floor() > (n as f64 * rate).floor()
+}
+
+/// A call that is being traced
+struct Trace {
+    logger: Logger,
+    request: String,
+    start: Instant,
+}
+
+impl Trace {
+    fn finish(self, response: Result<Value, String>) {
+        let elapsed_ms = self.start.elapsed().as_millis();
+        match response {
+            Ok(response) => trace!(self.logger, "JSON-RPC call";
+                                   "request" => self.request,
+                                   "response" => response.to_string(),
+                                   "time_ms" => elapsed_ms),
+            Err(e) => trace!(self.logger, "JSON-RPC call failed";
+                             "request" => self.request,
+                             "error" => e,
+                             "time_ms" => elapsed_ms),
+        }
+    }
 }
 
 impl web3::Transport for Transport {
     type Out = Box<dyn Future<Item = Value, Error = web3::error::Error> + Send>;
 
     fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
-        match self {
-            Transport::RPC(http) => http.prepare(method, params),
-            Transport::IPC(ipc) => ipc.prepare(method, params),
-            Transport::WS(ws) => ws.prepare(method, params),
+        match &self.kind {
+            Kind::RPC(http) => http.prepare(method, params),
+            Kind::IPC(ipc) => ipc.prepare(method, params),
+            Kind::WS(ws) => ws.prepare(method, params),
         }
     }
 
     fn send(&self, id: RequestId, request: Call) -> Self::Out {
-        match self {
-            Transport::RPC(http) => Box::new(http.send(id, request)),
-            Transport::IPC(ipc) => Box::new(ipc.send(id, request)),
-            Transport::WS(ws) => Box::new(ws.send(id, request)),
+        let trace = self.tracer.as_ref().and_then(|tracer| {
+            tracer.start(|| serde_json::to_value(&request).unwrap_or(Value::Null))
+        });
+        let out: Self::Out = match &self.budget {
+            Some(budget) => {
+                let budget = budget.clone();
+                let priority = rpc_trace::current_priority();
+                let kind = self.kind.clone();
+                Box::new(
+                    async move { Ok::<_, web3::error::Error>(budget.acquire(priority).await) }
+                        .boxed()
+                        .compat()
+                        .and_then(move |permit| {
+                            kind.send(id, request).then(move |result| {
+                                drop(permit);
+                                result
+                            })
+                        }),
+                )
+            }
+            None => self.kind.send(id, request),
+        };
+        match trace {
+            Some(trace) => Box::new(out.then(move |result| {
+                trace.finish(result.as_ref().map(Clone::clone).map_err(|e| e.to_string()));
+                result
+            })),
+            None => out,
         }
     }
 }
@@ -81,10 +302,67 @@ impl web3::BatchTransport for Transport {
     where
         T: IntoIterator<Item = (RequestId, Call)>,
     {
-        match self {
-            Transport::RPC(http) => Box::new(http.send_batch(requests)),
-            Transport::IPC(ipc) => Box::new(ipc.send_batch(requests)),
-            Transport::WS(ws) => Box::new(ws.send_batch(requests)),
+        let requests: Vec<_> = requests.into_iter().collect();
+        let trace = self.tracer.as_ref().and_then(|tracer| {
+            tracer.start(|| {
+                let calls: Vec<_> = requests.iter().map(|(_, call)| call).collect();
+                serde_json::to_value(calls).unwrap_or(Value::Null)
+            })
+        });
+        let out: Self::Batch = match &self.budget {
+            Some(budget) => {
+                let budget = budget.clone();
+                let priority = rpc_trace::current_priority();
+                let kind = self.kind.clone();
+                Box::new(
+                    async move { Ok::<_, web3::error::Error>(budget.acquire(priority).await) }
+                        .boxed()
+                        .compat()
+                        .and_then(move |permit| {
+                            kind.send_batch(requests).then(move |result| {
+                                drop(permit);
+                                result
+                            })
+                        }),
+                )
+            }
+            None => self.kind.send_batch(requests),
+        };
+        match trace {
+            Some(trace) => Box::new(out.then(move |result| {
+                let response = match &result {
+                    Ok(responses) => Ok(Value::Array(
+                        responses
+                            .iter()
+                            .map(|response| match response {
+                                Ok(value) => value.clone(),
+                                Err(e) => serde_json::json!({ "error": e.to_string() }),
+                            })
+                            .collect(),
+                    )),
+                    Err(e) => Err(e.to_string()),
+                };
+                trace.finish(response);
+                result
+            })),
+            None => out,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::sampled;
+
+    #[test]
+    fn sampling() {
+        let count = |rate| (0..1000).filter(|n| sampled(*n, rate)).count();
+        assert_eq!(0, count(0.0));
+        assert_eq!(100, count(0.1));
+        assert_eq!(250, count(0.25));
+        assert_eq!(1000, count(1.0));
+        assert!(!sampled(0, 0.1));
+        assert!(sampled(9, 0.1));
+        assert!(sampled(10 + 9, 0.1));
+    }
+}
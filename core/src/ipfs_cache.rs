@@ -0,0 +1,269 @@
+//! A cache of IPFS files on local disk, so that manifests, mappings and the
+//! files mappings read do not have to be fetched from IPFS again, not even
+//! after the node restarts.
+//!
+//! Each file is stored under its IPFS path, prefixed with the size IPFS
+//! reported for it and the Keccak-256 hash of its contents. The hash is
+//! checked whenever the file is read, and files that fail the check are
+//! removed. When the files take up more than the size limit, the least
+//! recently used ones are removed.
+
+use lazy_static::lazy_static;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use graph::prelude::tiny_keccak::keccak256;
+use graph::prelude::*;
+
+lazy_static! {
+    /// The disk cache, if `GRAPH_IPFS_CACHE_DIR` is set
+    pub(crate) static ref IPFS_DISK_CACHE: Option<DiskCache> =
+        env::var_os("GRAPH_IPFS_CACHE_DIR").map(|dir| {
+            let dir = PathBuf::from(dir);
+            DiskCache::open(dir.clone(), *MAX_SIZE, *MAX_FILE_SIZE).unwrap_or_else(|e| {
+                panic!("failed to open IPFS cache directory {}: {}", dir.display(), e)
+            })
+        });
+
+    /// The default size limit of the disk cache is 1GiB.
+    static ref MAX_SIZE: u64 = env::var("GRAPH_MAX_IPFS_DISK_CACHE_SIZE")
+        .unwrap_or((1024 * 1024 * 1024).to_string())
+        .parse()
+        .expect("invalid GRAPH_MAX_IPFS_DISK_CACHE_SIZE");
+
+    /// The default size limit for files in the disk cache is 64MiB.
+    static ref MAX_FILE_SIZE: u64 = env::var("GRAPH_MAX_IPFS_DISK_CACHE_FILE_SIZE")
+        .unwrap_or((64 * 1024 * 1024).to_string())
+        .parse()
+        .expect("invalid GRAPH_MAX_IPFS_DISK_CACHE_FILE_SIZE");
+}
+
+/// The size IPFS reported for the file, followed by the hash of its contents
+const HEADER_LEN: usize = 8 + 32;
+
+pub(crate) struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    max_file_size: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The names of the cached files with their size on disk, least
+    /// recently used first
+    files: VecDeque<(String, u64)>,
+    size: u64,
+}
+
+impl State {
+    fn remove(&mut self, name: &str) {
+        if let Some(pos) = self.files.iter().position(|(other, _)| other == name) {
+            let (_, size) = self.files.remove(pos).unwrap();
+            self.size -= size;
+        }
+    }
+
+    fn touch(&mut self, name: &str) {
+        if let Some(pos) = self.files.iter().position(|(other, _)| other == name) {
+            let entry = self.files.remove(pos).unwrap();
+            self.files.push_back(entry);
+        }
+    }
+}
+
+impl DiskCache {
+    /// Use the files that are already in `dir`, which is created if it does
+    /// not exist, as the least recently used ones.
+    pub fn open(dir: PathBuf, max_size: u64, max_file_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        let mut files = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Leftovers of writes that did not finish
+            if name.ends_with(".tmp") {
+                fs::remove_file(entry.path())?;
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, name, metadata.len()));
+        }
+        files.sort();
+
+        let mut state = State::default();
+        for (_, name, size) in files {
+            state.size += size;
+            state.files.push_back((name, size));
+        }
+        let cache = DiskCache {
+            dir,
+            max_size,
+            max_file_size,
+            state: Mutex::new(state),
+        };
+        cache.evict(&mut cache.state.lock().unwrap());
+        Ok(cache)
+    }
+
+    /// The name of the file for an IPFS path. Paths of files in directories
+    /// contain slashes and are hashed instead.
+    fn file_name(path: &str) -> String {
+        if path.chars().all(|c| c.is_ascii_alphanumeric()) {
+            path.to_owned()
+        } else {
+            keccak256(path.as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        }
+    }
+
+    /// The size IPFS reported for the file at `path` and its contents, if
+    /// they are in the cache and intact.
+    pub fn get(&self, logger: &Logger, path: &str) -> Option<(u64, Vec<u8>)> {
+        let name = Self::file_name(path);
+        let mut state = self.state.lock().unwrap();
+        let mut data = fs::read(self.dir.join(&name)).ok()?;
+
+        if data.len() < HEADER_LEN || keccak256(&data[HEADER_LEN..])[..] != data[8..HEADER_LEN] {
+            warn!(
+                logger,
+                "Removing corrupt file from the IPFS disk cache";
+                "hash" => path,
+            );
+            fs::remove_file(self.dir.join(&name)).ok();
+            state.remove(&name);
+            return None;
+        }
+
+        state.touch(&name);
+        let ipfs_size = u64::from_le_bytes(data[..8].try_into().unwrap());
+        Some((ipfs_size, data.split_off(HEADER_LEN)))
+    }
+
+    /// Store the file at `path` unless it is too large.
+    pub fn insert(&self, logger: &Logger, path: &str, ipfs_size: u64, data: &[u8]) {
+        let size = (HEADER_LEN + data.len()) as u64;
+        if size > self.max_file_size || size > self.max_size {
+            return;
+        }
+
+        let name = Self::file_name(path);
+        let mut state = self.state.lock().unwrap();
+        // Write to a temporary file first, so that readers never see a
+        // partial file
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        let mut contents = Vec::with_capacity(size as usize);
+        contents.extend_from_slice(&ipfs_size.to_le_bytes());
+        contents.extend_from_slice(&keccak256(data));
+        contents.extend_from_slice(data);
+        if let Err(e) =
+            fs::write(&tmp, contents).and_then(|()| fs::rename(&tmp, self.dir.join(&name)))
+        {
+            warn!(
+                logger,
+                "Failed to write to the IPFS disk cache: {}", e;
+                "hash" => path,
+            );
+            fs::remove_file(&tmp).ok();
+            return;
+        }
+
+        state.remove(&name);
+        state.files.push_back((name, size));
+        state.size += size;
+        self.evict(&mut state);
+    }
+
+    fn evict(&self, state: &mut State) {
+        while state.size > self.max_size {
+            let (name, size) = match state.files.pop_front() {
+                Some(file) => file,
+                None => break,
+            };
+            fs::remove_file(self.dir.join(&name)).ok();
+            state.size -= size;
+        }
+    }
+
+    /// The size of the largest file that can be cached.
+    pub fn max_file_size(&self) -> u64 {
+        self.max_file_size
+            .min(self.max_size)
+            .saturating_sub(HEADER_LEN as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("graph-ipfs-cache-{}-{}", test, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn survives_reopening() {
+        let logger = Logger::root(slog::Discard, o!());
+        let dir = cache_dir("reopen");
+
+        let cache = DiskCache::open(dir.clone(), 1000, 1000).unwrap();
+        cache.insert(&logger, "QmA", 12, b"file a");
+        cache.insert(&logger, "QmB/index.json", 13, b"file b");
+        drop(cache);
+
+        let cache = DiskCache::open(dir.clone(), 1000, 1000).unwrap();
+        assert_eq!(Some((12, b"file a".to_vec())), cache.get(&logger, "QmA"));
+        assert_eq!(
+            Some((13, b"file b".to_vec())),
+            cache.get(&logger, "QmB/index.json")
+        );
+        assert_eq!(None, cache.get(&logger, "QmC"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let logger = Logger::root(slog::Discard, o!());
+        let dir = cache_dir("evict");
+
+        // Room for two files of 10 bytes
+        let cache = DiskCache::open(dir.clone(), 2 * (HEADER_LEN as u64 + 10), 1000).unwrap();
+        cache.insert(&logger, "QmA", 10, b"0123456789");
+        cache.insert(&logger, "QmB", 10, b"0123456789");
+        assert!(cache.get(&logger, "QmA").is_some());
+        cache.insert(&logger, "QmC", 10, b"0123456789");
+
+        assert!(cache.get(&logger, "QmA").is_some());
+        assert!(cache.get(&logger, "QmB").is_none());
+        assert!(cache.get(&logger, "QmC").is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removes_corrupt_files() {
+        let logger = Logger::root(slog::Discard, o!());
+        let dir = cache_dir("corrupt");
+
+        let cache = DiskCache::open(dir.clone(), 1000, 1000).unwrap();
+        cache.insert(&logger, "QmA", 6, b"file a");
+        let mut data = fs::read(dir.join("QmA")).unwrap();
+        *data.last_mut().unwrap() = b'b';
+        fs::write(dir.join("QmA"), data).unwrap();
+
+        assert_eq!(None, cache.get(&logger, "QmA"));
+        assert!(!dir.join("QmA").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
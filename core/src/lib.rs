@@ -1,3 +1,4 @@
+mod ipfs_cache;
 mod link_resolver;
 mod metrics;
 mod subgraph;
@@ -6,5 +7,7 @@ pub mod three_box;
 pub use crate::link_resolver::LinkResolver;
 pub use crate::metrics::MetricsRegistry;
 pub use crate::subgraph::{
-    DataSourceLoader, SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar,
+    replay_blocks, DataSourceLoader, IndexingShutdown, NodeFailover, ReplayCallCache,
+    ReplayedBlock, ReplayedCall, SubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar, TriggerDryRun,
 };
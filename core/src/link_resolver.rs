@@ -1,5 +1,7 @@
 use std::env;
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -13,6 +15,9 @@ use lru_time_cache::LruCache;
 use serde_json::Value;
 
 use graph::prelude::{LinkResolver as LinkResolverTrait, *};
+use graph::util::security::SafeDisplay;
+
+use crate::ipfs_cache::{DiskCache, IPFS_DISK_CACHE};
 
 /// Environment variable for limiting the `ipfs.map` file size limit.
 const MAX_IPFS_MAP_FILE_SIZE_VAR: &'static str = "GRAPH_MAX_IPFS_MAP_FILE_SIZE";
@@ -36,8 +41,18 @@ lazy_static! {
     static ref IPFS_TIMEOUT: Duration = Duration::from_secs(
         read_u64_from_env("GRAPH_IPFS_TIMEOUT").unwrap_or(60)
     );
+
+    /// How long to wait for an IPFS node to find a file before asking the
+    /// next one as well, in milliseconds
+    static ref IPFS_HEDGE_DELAY: Duration = Duration::from_millis(
+        read_u64_from_env("GRAPH_IPFS_HEDGE_DELAY").unwrap_or(500)
+    );
 }
 
+/// How many requests in a row an IPFS node has to fail before it is only
+/// asked after the healthy ones.
+const UNHEALTHY_AFTER_FAILURES: usize = 3;
+
 fn read_u64_from_env(name: &str) -> Option<u64> {
     env::var(name).ok().map(|s| {
         u64::from_str(&s).unwrap_or_else(|_| {
@@ -49,6 +64,64 @@ fn read_u64_from_env(name: &str) -> Option<u64> {
     })
 }
 
+/// An IPFS node and how its requests have been going lately.
+struct IpfsEndpoint {
+    address: String,
+    client: IpfsClient,
+    /// The number of requests in a row that failed
+    failures: AtomicUsize,
+}
+
+impl IpfsEndpoint {
+    fn is_healthy(&self) -> bool {
+        self.failures.load(Ordering::SeqCst) < UNHEALTHY_AFTER_FAILURES
+    }
+
+    fn record_success(&self, logger: &Logger) {
+        if self.failures.swap(0, Ordering::SeqCst) >= UNHEALTHY_AFTER_FAILURES {
+            info!(logger, "IPFS node is healthy again"; "ipfs" => SafeDisplay(&self.address));
+        }
+    }
+
+    fn record_failure(&self, logger: &Logger) {
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures == UNHEALTHY_AFTER_FAILURES {
+            warn!(
+                logger,
+                "IPFS node is unhealthy, asking the other nodes first";
+                "ipfs" => SafeDisplay(&self.address),
+                "failures" => failures,
+            );
+        }
+    }
+}
+
+/// The endpoints in the order in which they should be asked for a file:
+/// healthy ones first, and otherwise in the order they were configured in.
+fn by_health(endpoints: &[IpfsEndpoint]) -> Vec<&IpfsEndpoint> {
+    let mut endpoints: Vec<_> = endpoints.iter().collect();
+    endpoints.sort_by_key(|endpoint| !endpoint.is_healthy());
+    endpoints
+}
+
+/// Counts a request to an endpoint as failed unless it succeeded, so that
+/// requests that time out and are dropped count as failures, too.
+struct Attempt<'a> {
+    endpoint: &'a IpfsEndpoint,
+    logger: &'a Logger,
+    succeeded: bool,
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if self.succeeded {
+            self.endpoint.record_success(self.logger);
+        } else {
+            self.endpoint.record_failure(self.logger);
+        }
+    }
+}
+
 /// The IPFS APIs don't have a quick "do you have the file" function. Instead, we
 /// just rely on whether an API times out. That makes sense for IPFS, but not for
 /// our application. We want to be able to quickly select from a potential list
@@ -61,19 +134,23 @@ fn read_u64_from_env(name: &str) -> Option<u64> {
 /// the file from each client, which would be wasteful of bandwidth and memory in
 /// the case multiple clients respond in a timely manner. In addition, we may
 /// make good use of the stat returned.
+///
+/// The requests are hedged: the healthiest endpoint is asked first, and each
+/// further endpoint `GRAPH_IPFS_HEDGE_DELAY` after the previous one, unless an
+/// endpoint has answered by then.
 async fn select_fastest_client_with_stat<'a>(
-    clients: &'a [IpfsClient],
+    endpoints: &'a [IpfsEndpoint],
     logger: &'a Logger,
     path: &'_ str,
     timeout: Duration,
     do_retry: bool,
-) -> Result<(ObjectStatResponse, &'a IpfsClient), failure::Error> {
+) -> Result<(ObjectStatResponse, &'a IpfsEndpoint), failure::Error> {
     let mut err: Option<failure::Error> = None;
 
-    let mut stats: FuturesUnordered<_> = clients
-        .iter()
+    let mut stats: FuturesUnordered<_> = by_health(endpoints)
+        .into_iter()
         .enumerate()
-        .map(|(i, c)| {
+        .map(|(position, endpoint)| {
             let retry_fut = if do_retry {
                 retry("object.stat", logger).no_limit()
             } else {
@@ -81,18 +158,27 @@ async fn select_fastest_client_with_stat<'a>(
             }
             .timeout(timeout);
 
-            retry_fut
-                .run(move || c.object_stat(path).map_ok(move |s| (s, i)).boxed().compat())
-                .compat()
+            async move {
+                tokio::time::delay_for(*IPFS_HEDGE_DELAY * position as u32).await;
+                let result = retry_fut
+                    .run(move || endpoint.client.object_stat(path).boxed().compat())
+                    .compat()
+                    .await;
+                (endpoint, result)
+            }
         })
         .collect();
 
-    while let Some(result) = stats.next().await {
+    while let Some((endpoint, result)) = stats.next().await {
         match result {
-            Ok((stat, index)) => {
-                return Ok((stat, &clients[index]));
+            Ok(stat) => {
+                endpoint.record_success(logger);
+                return Ok((stat, endpoint));
+            }
+            Err(e) => {
+                endpoint.record_failure(logger);
+                err = Some(e.into());
             }
-            Err(e) => err = Some(e.into()),
         }
     }
 
@@ -107,28 +193,130 @@ async fn select_fastest_client_with_stat<'a>(
 // Returns an error if the stat is bigger than `max_file_bytes`
 fn restrict_file_size(
     path: &str,
-    stat: &ObjectStatResponse,
+    size: u64,
     max_file_bytes: &Option<u64>,
 ) -> Result<(), failure::Error> {
     if let Some(max_file_bytes) = max_file_bytes {
-        if stat.cumulative_size > *max_file_bytes {
-            return Err(format_err!(
-                "IPFS file {} is too large. It can be at most {} bytes but is {} bytes",
-                path,
-                max_file_bytes,
-                stat.cumulative_size
-            ));
+        if size > *max_file_bytes {
+            return Err(FileTooLargeError {
+                path: path.to_owned(),
+                max_file_size: *max_file_bytes,
+                size,
+            }
+            .into());
         }
     }
     Ok(())
 }
 
+type ChunkStream = Pin<Box<dyn futures03::Stream<Item = Result<Vec<u8>, failure::Error>> + Send>>;
+
+/// Fail `chunks` if the next chunk takes longer than `timeout` to arrive, so
+/// that an IPFS node that stops sending a file can not stall `ipfs.map`
+/// forever.
+fn with_chunk_timeout(chunks: ChunkStream, timeout: Duration, path: String) -> ChunkStream {
+    Box::pin(futures03::stream::unfold(Some(chunks), move |chunks| {
+        let path = path.clone();
+        async move {
+            let mut chunks = chunks?;
+            match tokio::time::timeout(timeout, chunks.next()).await {
+                Ok(Some(chunk)) => Some((chunk, Some(chunks))),
+                Ok(None) => None,
+                Err(_) => Some((
+                    Err(format_err!(
+                        "Timed out after {}s reading IPFS file {}",
+                        timeout.as_secs(),
+                        path
+                    )),
+                    None,
+                )),
+            }
+        }
+    }))
+}
+
+/// Pass `chunks` through, and store the file they make up in the disk cache
+/// once all of them have been read, unless it is too large for the cache.
+fn cache_on_disk(
+    disk: &'static DiskCache,
+    logger: Logger,
+    path: String,
+    ipfs_size: u64,
+    chunks: ChunkStream,
+) -> ChunkStream {
+    Box::pin(futures03::stream::unfold(
+        (chunks, Some(Vec::new())),
+        move |(mut chunks, mut file)| {
+            let logger = logger.clone();
+            let path = path.clone();
+            async move {
+                match chunks.next().await {
+                    Some(Ok(chunk)) => {
+                        file = file.and_then(|mut file: Vec<u8>| {
+                            if (file.len() + chunk.len()) as u64 > disk.max_file_size() {
+                                return None;
+                            }
+                            file.extend_from_slice(&chunk);
+                            Some(file)
+                        });
+                        Some((Ok(chunk), (chunks, file)))
+                    }
+                    Some(Err(e)) => Some((Err(e), (chunks, None))),
+                    None => {
+                        if let Some(file) = file {
+                            disk.insert(&logger, &path, ipfs_size, &file);
+                        }
+                        None
+                    }
+                }
+            }
+        },
+    ))
+}
+
 #[derive(Clone)]
 pub struct LinkResolver {
-    clients: Arc<Vec<IpfsClient>>,
+    endpoints: Arc<Vec<IpfsEndpoint>>,
     cache: Arc<Mutex<LruCache<String, Vec<u8>>>>,
     timeout: Duration,
+    max_file_size: Option<u64>,
     retry: bool,
+    /// Where the files of subgraphs deployed from local files are kept
+    files: Option<Arc<dyn SubgraphFileStore>>,
+}
+
+impl LinkResolver {
+    /// Fetch files from the IPFS nodes at the given addresses. Requests fail
+    /// over to the other nodes when one of them does not respond.
+    pub fn new(clients: Vec<(String, IpfsClient)>) -> Self {
+        let endpoints = clients
+            .into_iter()
+            .map(|(address, client)| IpfsEndpoint {
+                address,
+                client,
+                failures: AtomicUsize::new(0),
+            })
+            .collect();
+        Self {
+            endpoints: Arc::new(endpoints),
+            cache: Arc::new(Mutex::new(LruCache::with_capacity(
+                *MAX_IPFS_CACHE_SIZE as usize,
+            ))),
+            timeout: *IPFS_TIMEOUT,
+            // FIXME: Having an env variable here is a problem for consensus.
+            // Index Nodes should not disagree on whether the file should be read.
+            max_file_size: read_u64_from_env(MAX_IPFS_FILE_SIZE_VAR),
+            retry: false,
+            files: None,
+        }
+    }
+
+    /// Look up links to files that were deployed from local files in
+    /// `files` instead of in IPFS.
+    pub fn with_file_store(mut self, files: Arc<dyn SubgraphFileStore>) -> Self {
+        self.files = Some(files);
+        self
+    }
 }
 
 impl From<IpfsClient> for LinkResolver {
@@ -139,14 +327,13 @@ impl From<IpfsClient> for LinkResolver {
 
 impl From<Vec<IpfsClient>> for LinkResolver {
     fn from(clients: Vec<IpfsClient>) -> Self {
-        Self {
-            clients: Arc::new(clients),
-            cache: Arc::new(Mutex::new(LruCache::with_capacity(
-                *MAX_IPFS_CACHE_SIZE as usize,
-            ))),
-            timeout: *IPFS_TIMEOUT,
-            retry: false,
-        }
+        LinkResolver::new(
+            clients
+                .into_iter()
+                .enumerate()
+                .map(|(i, client)| (format!("ipfs node {}", i), client))
+                .collect(),
+        )
     }
 }
 
@@ -157,6 +344,11 @@ impl LinkResolverTrait for LinkResolver {
         self
     }
 
+    fn with_max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
     fn with_retries(mut self) -> Self {
         self.retry = true;
         self
@@ -173,33 +365,83 @@ impl LinkResolverTrait for LinkResolver {
         }
         trace!(logger, "IPFS cache miss"; "hash" => &path);
 
-        let (stat, client) =
-            select_fastest_client_with_stat(&self.clients, logger, &path, self.timeout, self.retry)
-                .await?;
+        if path.starts_with(SUBGRAPH_FILE_PREFIX) {
+            if let Some(files) = &self.files {
+                return files
+                    .subgraph_file(&path)?
+                    .ok_or_else(|| format_err!("Subgraph file {} is not in the database", path));
+            }
+        }
+        let max_file_size = self.max_file_size;
+
+        if let Some((size, data)) = IPFS_DISK_CACHE
+            .as_ref()
+            .and_then(|disk| disk.get(logger, &path))
+        {
+            trace!(logger, "IPFS disk cache hit"; "hash" => &path);
+            restrict_file_size(&path, size, &max_file_size)?;
+            if data.len() <= *MAX_IPFS_CACHE_FILE_SIZE as usize {
+                let mut cache = self.cache.lock().unwrap();
+                if !cache.contains_key(&path) {
+                    cache.insert(path.clone(), data.clone());
+                }
+            }
+            return Ok(data);
+        }
 
-        // FIXME: Having an env variable here is a problem for consensus.
-        // Index Nodes should not disagree on whether the file should be read.
-        let max_file_size: Option<u64> = read_u64_from_env(MAX_IPFS_FILE_SIZE_VAR);
-        restrict_file_size(&path, &stat, &max_file_size)?;
+        let (stat, endpoint) = select_fastest_client_with_stat(
+            &self.endpoints,
+            logger,
+            &path,
+            self.timeout,
+            self.retry,
+        )
+        .await?;
+        let size = stat.cumulative_size;
+        restrict_file_size(&path, size, &max_file_size)?;
+
+        // Fetch the file from the endpoint that found it; if that fails, each
+        // further attempt fails over to the next endpoint
+        let candidates: Vec<_> = std::iter::once(endpoint)
+            .chain(
+                by_health(&self.endpoints)
+                    .into_iter()
+                    .filter(|other| !std::ptr::eq(*other, endpoint)),
+            )
+            .collect();
+        let attempts = AtomicUsize::new(0);
 
         let path = path.clone();
         let retry_fut = if self.retry {
             retry("ipfs.cat", &logger).no_limit()
         } else {
-            retry("ipfs.cat", &logger).limit(1)
+            retry("ipfs.cat", &logger).limit(candidates.len())
         }
         .timeout(self.timeout);
 
         let data = retry_fut
-            .run(move || {
+            .run(|| {
                 let path = path.clone();
+                let endpoint =
+                    candidates[attempts.fetch_add(1, Ordering::SeqCst) % candidates.len()];
                 async move {
-                    let data = client
+                    let mut attempt = Attempt {
+                        endpoint,
+                        logger,
+                        succeeded: false,
+                    };
+                    let data = endpoint
+                        .client
                         .cat(&path)
                         .map_ok(|b| BytesMut::from_iter(b.into_iter()))
                         .try_concat()
                         .await?
                         .to_vec();
+                    attempt.succeeded = true;
+
+                    if let Some(disk) = IPFS_DISK_CACHE.as_ref() {
+                        disk.insert(logger, &path, size, &data);
+                    }
 
                     // Only cache files if they are not too large
                     if data.len() <= *MAX_IPFS_CACHE_FILE_SIZE as usize {
@@ -223,15 +465,48 @@ impl LinkResolverTrait for LinkResolver {
         // Discard the `/ipfs/` prefix (if present) to get the hash.
         let path = link.link.trim_start_matches("/ipfs/");
 
-        let (stat, client) =
-            select_fastest_client_with_stat(&self.clients, logger, path, self.timeout, self.retry)
-                .await?;
-
         let max_file_size =
             read_u64_from_env(MAX_IPFS_MAP_FILE_SIZE_VAR).or(Some(DEFAULT_MAX_IPFS_MAP_FILE_SIZE));
-        restrict_file_size(path, &stat, &max_file_size)?;
 
-        let mut stream = client.cat(&path).compat().fuse();
+        let chunks: ChunkStream = match IPFS_DISK_CACHE
+            .as_ref()
+            .and_then(|disk| disk.get(logger, path))
+        {
+            Some((size, data)) => {
+                trace!(logger, "IPFS disk cache hit"; "hash" => path);
+                restrict_file_size(path, size, &max_file_size)?;
+                Box::pin(futures03::stream::once(async move { Ok(data) }))
+            }
+            None => {
+                let (stat, endpoint) = select_fastest_client_with_stat(
+                    &self.endpoints,
+                    logger,
+                    path,
+                    self.timeout,
+                    self.retry,
+                )
+                .await?;
+                restrict_file_size(path, stat.cumulative_size, &max_file_size)?;
+
+                let chunks = endpoint
+                    .client
+                    .cat(path)
+                    .map_ok(|b| b.to_vec())
+                    .map_err(failure::Error::from);
+                let chunks = with_chunk_timeout(chunks.boxed(), self.timeout, path.to_owned());
+                match IPFS_DISK_CACHE.as_ref() {
+                    Some(disk) => cache_on_disk(
+                        disk,
+                        logger.clone(),
+                        path.to_owned(),
+                        stat.cumulative_size,
+                        chunks,
+                    ),
+                    None => chunks,
+                }
+            }
+        };
+        let mut stream = chunks.compat().fuse();
 
         let mut buf = BytesMut::with_capacity(1024);
 
@@ -314,6 +589,11 @@ mod tests {
             .await
             .unwrap_err();
         env::remove_var(MAX_IPFS_FILE_SIZE_VAR);
+        assert_eq!(
+            err.downcast_ref::<FileTooLargeError>()
+                .map(|e| (e.max_file_size, e.size)),
+            Some((200, 212))
+        );
         assert_eq!(
             err.to_string(),
             format!(
@@ -323,6 +603,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unhealthy_endpoints_are_asked_last() {
+        let logger = Logger::root(slog::Discard, o!());
+        let endpoint = |address: &str| IpfsEndpoint {
+            address: address.to_owned(),
+            client: IpfsClient::default(),
+            failures: AtomicUsize::new(0),
+        };
+        let endpoints = vec![endpoint("a"), endpoint("b"), endpoint("c")];
+        let order = |endpoints: &[IpfsEndpoint]| -> Vec<String> {
+            by_health(endpoints)
+                .iter()
+                .map(|endpoint| endpoint.address.clone())
+                .collect()
+        };
+
+        for _ in 0..UNHEALTHY_AFTER_FAILURES {
+            endpoints[0].record_failure(&logger);
+        }
+        endpoints[1].record_failure(&logger);
+        assert_eq!(vec!["b", "c", "a"], order(&endpoints));
+
+        endpoints[0].record_success(&logger);
+        assert_eq!(vec!["a", "b", "c"], order(&endpoints));
+    }
+
     async fn json_round_trip(text: &'static str) -> Result<Vec<Value>, failure::Error> {
         let client = IpfsClient::default();
         let resolver = super::LinkResolver::from(client.clone());
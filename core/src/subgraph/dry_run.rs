@@ -0,0 +1,311 @@
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+
+use graph::components::ethereum::{blocks_with_triggers, EthereumNetworks};
+use graph::prelude::ethabi::RawLog;
+use graph::prelude::web3::types::Address;
+use graph::prelude::{DataSourceLoader as _, TriggerDryRun as TriggerDryRunTrait, *};
+use graph::util::ethereum::{contract_event_with_signature, event_topic0};
+
+use crate::DataSourceLoader;
+
+lazy_static! {
+    /// The largest number of blocks that one dry run can cover
+    static ref MAX_BLOCKS: u64 = env::var("GRAPH_TRIGGER_DRY_RUN_MAX_BLOCKS")
+        .unwrap_or("10000".into())
+        .parse::<u64>()
+        .expect("invalid GRAPH_TRIGGER_DRY_RUN_MAX_BLOCKS");
+}
+
+/// Counts the triggers that the handlers of a deployment would receive by
+/// running the deployment's trigger filters against a range of blocks. The
+/// data sources are the ones from the manifest and the dynamic data sources
+/// the deployment has created so far; data sources that handlers would
+/// create during the range are not known without running the mappings.
+pub struct TriggerDryRun<L, Q, S> {
+    logger: Logger,
+    link_resolver: Arc<L>,
+    graphql_runner: Arc<Q>,
+    stores: HashMap<String, Arc<S>>,
+    eth_networks: EthereumNetworks,
+    ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
+}
+
+impl<L, Q, S> TriggerDryRun<L, Q, S>
+where
+    L: LinkResolver,
+    Q: GraphQlRunner,
+    S: Store + ChainStore + SubgraphDeploymentStore,
+{
+    pub fn new(
+        logger: &Logger,
+        link_resolver: Arc<L>,
+        graphql_runner: Arc<Q>,
+        stores: HashMap<String, Arc<S>>,
+        eth_networks: EthereumNetworks,
+        registry: Arc<impl MetricsRegistry>,
+    ) -> Self {
+        TriggerDryRun {
+            logger: logger.new(o!("component" => "TriggerDryRun")),
+            link_resolver,
+            graphql_runner,
+            stores,
+            eth_networks,
+            // Dry runs share their metrics so that they do not register
+            // metrics for every deployment they look at
+            ethrpc_metrics: Arc::new(SubgraphEthRpcMetrics::new(
+                registry,
+                "trigger_dry_run".to_owned(),
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl<L, Q, S> TriggerDryRunTrait for TriggerDryRun<L, Q, S>
+where
+    L: LinkResolver,
+    Q: GraphQlRunner,
+    S: Store + ChainStore + SubgraphDeploymentStore,
+{
+    async fn trigger_counts(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<HandlerTriggerCount>, Error> {
+        if from > to {
+            return Err(format_err!(
+                "the start block {} is after the end block {}",
+                from,
+                to
+            ));
+        }
+        if to - from >= *MAX_BLOCKS {
+            return Err(format_err!(
+                "a dry run can cover at most {} blocks, but {} to {} are {} blocks",
+                *MAX_BLOCKS,
+                from,
+                to,
+                to - from + 1
+            ));
+        }
+
+        let logger = self.logger.new(o!("subgraph_id" => deployment.to_string()));
+        let mut manifest =
+            SubgraphManifest::resolve(deployment.to_ipfs_link(), &*self.link_resolver, &logger)
+                .await
+                .map_err(|e| format_err!("failed to resolve subgraph `{}`: {}", deployment, e))?;
+
+        let network = manifest.network_name();
+        let store = self
+            .stores
+            .get(&network)
+            .ok_or_else(|| format_err!("no store for network `{}`", network))?
+            .clone();
+        let eth_adapter = self
+            .eth_networks
+            .adapter_with_capabilities(network, &manifest.required_ethereum_capabilities())?
+            .clone();
+
+        let loader = DataSourceLoader::new(
+            store.clone(),
+            self.link_resolver.clone(),
+            self.graphql_runner.clone(),
+        );
+        let data_sources = loader
+            .load_dynamic_data_sources(deployment.clone(), logger.clone())
+            .await?;
+        manifest.data_sources.extend(data_sources);
+
+        // Filter by the contracts of data sources for a list of contracts
+        // like indexing does, but count their triggers together
+        let split_data_sources = manifest
+            .data_sources
+            .iter()
+            .cloned()
+            .flat_map(DataSource::split_addresses)
+            .collect::<Vec<_>>();
+
+        let blocks = blocks_with_triggers(
+            eth_adapter,
+            logger,
+            store,
+            self.ethrpc_metrics.clone(),
+            from,
+            to,
+            EthereumLogFilter::from_data_sources(&split_data_sources),
+            EthereumCallFilter::from_data_sources(&split_data_sources),
+            EthereumBlockFilter::from_data_sources(&split_data_sources),
+        )
+        .compat()
+        .await?;
+
+        Ok(count_triggers(&manifest.data_sources, blocks))
+    }
+}
+
+/// Count the triggers in `blocks` for each handler of `data_sources`. There
+/// is one count for every handler, in the order of the manifest, so that
+/// handlers that would not be called show up with a count of 0
+fn count_triggers(
+    data_sources: &[DataSource],
+    blocks: Vec<EthereumBlockWithTriggers>,
+) -> Vec<HandlerTriggerCount> {
+    let mut counts = Vec::new();
+    let mut positions = HashMap::new();
+    for (i, data_source) in data_sources.iter().enumerate() {
+        let mut add = |kind: &'static str, j: usize, filter: Option<String>, handler: &str| {
+            positions.insert((i, kind, j), counts.len());
+            counts.push(HandlerTriggerCount {
+                data_source: data_source.name.clone(),
+                kind,
+                filter,
+                handler: handler.to_owned(),
+                triggers: 0,
+                blocks: 0,
+            });
+        };
+        let mapping = &data_source.mapping;
+        for (j, handler) in mapping.event_handlers.iter().enumerate() {
+            add("event", j, Some(handler.event.clone()), &handler.handler);
+        }
+        for (j, handler) in mapping.call_handlers.iter().enumerate() {
+            add("call", j, Some(handler.function.clone()), &handler.handler);
+        }
+        for (j, handler) in mapping.block_handlers.iter().enumerate() {
+            let filter = handler.filter.as_ref().map(|filter| match filter {
+                BlockHandlerFilter::Call => "call".to_owned(),
+            });
+            add("block", j, filter, &handler.handler);
+        }
+    }
+
+    let mut last_blocks = vec![None; counts.len()];
+    for block in blocks {
+        let number = block.ethereum_block.number();
+        for trigger in &block.triggers {
+            for (i, data_source) in data_sources.iter().enumerate() {
+                if number < data_source.source.start_block {
+                    continue;
+                }
+                if let Some((kind, j)) = handler_for_trigger(data_source, number, trigger) {
+                    let position = positions[&(i, kind, j)];
+                    counts[position].triggers += 1;
+                    if last_blocks[position] != Some(number) {
+                        counts[position].blocks += 1;
+                        last_blocks[position] = Some(number);
+                    }
+                }
+            }
+        }
+    }
+    counts
+}
+
+/// The handler of `data_source` that would be called for `trigger` in the
+/// block `number`, as the kind of handler and its position in the mapping.
+/// This follows how the runtime host matches triggers to handlers.
+fn handler_for_trigger(
+    data_source: &DataSource,
+    number: u64,
+    trigger: &EthereumTrigger,
+) -> Option<(&'static str, usize)> {
+    // Whether `contract` is a contract of the data source; `default` for
+    // data sources without contracts
+    let source = &data_source.source;
+    let is_source_contract = |contract: &Address, default: bool| {
+        if let Some(address) = source.address {
+            address == *contract
+        } else if !source.addresses.is_empty() {
+            source.addresses.contains(contract)
+        } else {
+            default
+        }
+    };
+    let mapping = &data_source.mapping;
+    match trigger {
+        EthereumTrigger::Log(log) => {
+            if !is_source_contract(&log.address, true) {
+                return None;
+            }
+            let contract = &mapping
+                .abis
+                .iter()
+                .find(|abi| abi.name == data_source.source.abi_at(number))?
+                .contract;
+            let anonymous = mapping.anonymous_event_handlers(&data_source.source);
+
+            let decodes = |event: &ethabi::Event| {
+                event
+                    .parse_log(RawLog {
+                        topics: log.topics.clone(),
+                        data: log.data.0.clone(),
+                    })
+                    .is_ok()
+            };
+
+            // Handlers that match the event signature take precedence over
+            // the wildcard handler, and that over handlers for anonymous
+            // events
+            let position = |for_anonymous: bool| {
+                mapping.event_handlers.iter().position(|handler| {
+                    let is_anonymous = anonymous.contains(&handler);
+                    is_anonymous == for_anonymous
+                        && (is_anonymous || log.topics.first() == Some(&handler.topic0()))
+                        && contract_event_with_signature(contract, &handler.event)
+                            .map_or(false, decodes)
+                })
+            };
+            let wildcard = || {
+                let topic0 = log.topics.first()?;
+                let decoded = contract.events().any(|event| {
+                    !event.anonymous && event_topic0(event) == *topic0 && decodes(event)
+                });
+                if decoded {
+                    mapping
+                        .event_handlers
+                        .iter()
+                        .position(|handler| handler.is_wildcard())
+                } else {
+                    None
+                }
+            };
+            position(false)
+                .or_else(wildcard)
+                .or_else(|| position(true))
+                .map(|j| ("event", j))
+        }
+        EthereumTrigger::Call(call) => {
+            if !is_source_contract(&call.to, true) {
+                return None;
+            }
+            mapping
+                .call_handlers
+                .iter()
+                .position(|handler| {
+                    let hash = tiny_keccak::keccak256(handler.function.as_bytes());
+                    call.input.0.starts_with(&hash[..4])
+                        && (handler.include_failed || !call.is_failed())
+                })
+                .map(|j| ("call", j))
+        }
+        EthereumTrigger::Block(_, EthereumBlockTriggerType::Every) => mapping
+            .block_handlers
+            .iter()
+            .position(|handler| handler.filter.is_none())
+            .map(|j| ("block", j)),
+        EthereumTrigger::Block(_, EthereumBlockTriggerType::WithCallTo(to)) => {
+            if !is_source_contract(to, false) {
+                return None;
+            }
+            mapping
+                .block_handlers
+                .iter()
+                .position(|handler| handler.filter == Some(BlockHandlerFilter::Call))
+                .map(|j| ("block", j))
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::env;
+use std::time::Duration;
+
+use graph::data::subgraph::schema::{
+    SubgraphDeploymentAssignmentEntity, SubgraphEntity, SubgraphVersionEntity, TypedEntity,
+};
+use graph::prelude::*;
+
+use super::registrar::{least_loaded_node, reassign_subgraph};
+
+lazy_static! {
+    /// How often index nodes record that they are alive, in seconds
+    static ref HEARTBEAT_INTERVAL: Duration = Duration::from_secs(
+        env::var("GRAPH_NODE_HEARTBEAT_INTERVAL")
+            .unwrap_or("30".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_NODE_HEARTBEAT_INTERVAL")
+    );
+
+    /// How long an index node can go without recording a heartbeat before
+    /// it is considered dead, in seconds
+    static ref HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(
+        env::var("GRAPH_NODE_HEARTBEAT_TIMEOUT")
+            .unwrap_or("300".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_NODE_HEARTBEAT_TIMEOUT")
+    );
+}
+
+/// Records heartbeats for this node and, if failover is enabled, moves the
+/// deployments assigned to nodes that have stopped recording heartbeats to
+/// nodes that are alive. The new node is chosen according to the placement
+/// rules, but only among live nodes; without a matching rule, any live node
+/// can take the deployment.
+///
+/// Nodes that have never recorded a heartbeat are left alone, since they
+/// might be running a version of graph-node that does not record them.
+pub struct NodeFailover<S> {
+    logger: Logger,
+    store: Arc<S>,
+    node_id: NodeId,
+    placer: Option<Arc<dyn DeploymentPlacer>>,
+    failover: bool,
+}
+
+impl<S> NodeFailover<S>
+where
+    S: Store + SubgraphDeploymentStore + NodeLivenessStore,
+{
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        store: Arc<S>,
+        node_id: NodeId,
+        placer: Option<Arc<dyn DeploymentPlacer>>,
+        failover: bool,
+    ) -> Self {
+        NodeFailover {
+            logger: logger_factory.component_logger("NodeFailover", None),
+            store,
+            node_id,
+            placer,
+            failover,
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            if let Err(e) = self.store.heartbeat(&self.node_id) {
+                warn!(self.logger, "Failed to record heartbeat"; "error" => e.to_string());
+            }
+
+            if self.failover {
+                if let Err(e) = self.reassign_from_dead_nodes() {
+                    warn!(self.logger, "Failed to move deployments from dead nodes";
+                          "error" => e.to_string());
+                }
+            }
+
+            tokio::time::delay_for(*HEARTBEAT_INTERVAL).await;
+        }
+    }
+
+    fn reassign_from_dead_nodes(&self) -> Result<(), Error> {
+        let (live, dead): (Vec<_>, Vec<_>) = self
+            .store
+            .heartbeat_ages()?
+            .into_iter()
+            .partition(|(_, age)| *age < *HEARTBEAT_TIMEOUT);
+        let live: HashSet<NodeId> = live.into_iter().map(|(node, _)| node).collect();
+        let dead: Vec<NodeId> = dead
+            .into_iter()
+            .map(|(node, _)| node)
+            .filter(|node| node != &self.node_id)
+            .collect();
+        if dead.is_empty() {
+            return Ok(());
+        }
+
+        let assignments = self
+            .store
+            .find(
+                SubgraphDeploymentAssignmentEntity::query().filter(EntityFilter::In(
+                    "nodeId".to_owned(),
+                    dead.iter().map(|node| node.to_string().into()).collect(),
+                )),
+            )?;
+
+        for assignment in assignments {
+            let id = SubgraphDeploymentId::new(assignment.id()?)
+                .map_err(|()| format_err!("invalid deployment id in assignment"))?;
+            let dead_node = assignment
+                .get("nodeId")
+                .map(|node| node.to_string())
+                .unwrap_or_default();
+
+            let candidates = self.candidates(&id, &live)?;
+            let node = match least_loaded_node(self.store.as_ref(), candidates)? {
+                Some(node) => node,
+                None => {
+                    warn!(self.logger, "No live node to move deployment to";
+                          "subgraph_id" => id.to_string(),
+                          "dead_node" => &dead_node);
+                    continue;
+                }
+            };
+
+            // Other nodes might be moving the same deployment; only one of
+            // them succeeds since the reassignment checks the current node
+            match reassign_subgraph(self.store.clone(), id.clone(), node.clone()) {
+                Ok(()) => info!(self.logger, "Moved deployment from dead node";
+                                "subgraph_id" => id.to_string(),
+                                "dead_node" => &dead_node,
+                                "node" => node.to_string()),
+                Err(e) => debug!(self.logger, "Could not move deployment from dead node";
+                                 "subgraph_id" => id.to_string(),
+                                 "dead_node" => &dead_node,
+                                 "error" => e.to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    /// The live nodes the deployment `id` may be moved to.
+    fn candidates(
+        &self,
+        id: &SubgraphDeploymentId,
+        live: &HashSet<NodeId>,
+    ) -> Result<Vec<NodeId>, Error> {
+        let placed = match (&self.placer, self.subgraph_name(id)?) {
+            (Some(placer), Some(name)) => {
+                let network = self.store.network_name(id)?.unwrap_or_default();
+                placer
+                    .place(&name, &network)
+                    .map_err(|e| format_err!("{}", e))?
+            }
+            _ => None,
+        };
+        Ok(match placed {
+            Some(nodes) => nodes
+                .into_iter()
+                .filter(|node| live.contains(node))
+                .collect(),
+            None => live.iter().cloned().collect(),
+        })
+    }
+
+    /// The name of a subgraph that has the deployment `id` as a version.
+    fn subgraph_name(&self, id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
+        let version = self.store.find_one(
+            SubgraphVersionEntity::query()
+                .filter(EntityFilter::new_equal("deployment", id.to_string())),
+        )?;
+        let subgraph = match version.and_then(|version| version.get("subgraph").cloned()) {
+            Some(Value::String(subgraph)) => subgraph,
+            _ => return Ok(None),
+        };
+        Ok(self
+            .store
+            .find_one(SubgraphEntity::query().filter(EntityFilter::new_equal("id", subgraph)))?
+            .and_then(|subgraph| subgraph.get("name").cloned())
+            .and_then(Value::as_string))
+    }
+}
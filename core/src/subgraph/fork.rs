@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Mutex;
+
+use graph::data::graphql::ext::{DocumentExt, FieldExt, TypeExt};
+use graph::data::schema::Schema;
+use graph::prelude::{SubgraphFork as SubgraphForkTrait, *};
+use graph::url::Url;
+use graph_graphql::graphql_parser::{query as q, schema as s};
+use graph_graphql::schema::api::VERSION_FIELD;
+use graph_graphql::schema::ast::get_root_query_type;
+
+/// Fetches the entities of a deployment that are not in the local store
+/// from the GraphQL endpoint of the same subgraph on another graph-node.
+/// This makes it possible to start a deployment at a late block and debug
+/// a handler there without indexing everything that comes before it.
+pub struct SubgraphFork {
+    logger: Logger,
+    http_client: reqwest::Client,
+    endpoint: Url,
+    subgraph_id: SubgraphDeploymentId,
+    /// The API schema of the deployment, used to build the queries for
+    /// entities and to interpret the responses
+    schema: Arc<Schema>,
+    /// The entities that have already been fetched; they are in the local
+    /// store now, or have been removed there
+    fetched: Mutex<HashSet<(String, String)>>,
+}
+
+impl SubgraphFork {
+    pub fn new(
+        logger: Logger,
+        endpoint: Url,
+        subgraph_id: SubgraphDeploymentId,
+        schema: Arc<Schema>,
+    ) -> Self {
+        SubgraphFork {
+            logger,
+            http_client: reqwest::Client::new(),
+            endpoint,
+            subgraph_id,
+            schema,
+            fetched: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Build the query for one entity of type `entity_type` as of `block`,
+    /// and return it together with the name of the field in the response
+    /// that holds it.
+    fn query(
+        &self,
+        entity_type: &str,
+        id: &str,
+        block: BlockNumber,
+    ) -> Result<(String, String), Error> {
+        let document = &self.schema.document;
+
+        // The query field for a single entity has the entity type as its type
+        let field = get_root_query_type(document)
+            .and_then(|query_type| {
+                query_type
+                    .fields
+                    .iter()
+                    .find(|field| match &field.field_type {
+                        s::Type::NamedType(name) => name == entity_type,
+                        _ => false,
+                    })
+            })
+            .ok_or_else(|| format_err!("no query field for entity type `{}`", entity_type))?;
+        let object_type = document
+            .get_object_type_definition(entity_type)
+            .ok_or_else(|| format_err!("unknown entity type `{}`", entity_type))?;
+
+        let selection = object_type
+            .fields
+            .iter()
+            .filter(|field| !is_derived(field) && !is_api_field(field))
+            .map(|field| {
+                let base_type = field.field_type.get_base_type();
+                if document.get_object_type_definition(base_type).is_some()
+                    || document.find_interface(base_type).is_some()
+                {
+                    format!("{} {{ id }}", field.name)
+                } else {
+                    field.name.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let query = format!(
+            "query {{ {}(id: {}, block: {{ number: {} }}) {{ {} }} }}",
+            field.name,
+            q::Value::String(id.to_owned()),
+            block,
+            selection
+        );
+        Ok((query, field.name.clone()))
+    }
+
+    fn entity(
+        &self,
+        entity_type: &str,
+        data: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Entity, Error> {
+        let object_type = self
+            .schema
+            .document
+            .get_object_type_definition(entity_type)
+            .ok_or_else(|| format_err!("unknown entity type `{}`", entity_type))?;
+
+        let mut entity = Entity::new();
+        for (name, value) in data {
+            let field = match object_type.fields.iter().find(|field| field.name == name) {
+                Some(field) => field,
+                None => continue,
+            };
+            let value = Value::from_query_value(&query_value(value)?, &field.field_type)?;
+            entity.insert(name, value);
+        }
+        Ok(entity)
+    }
+
+    fn fetch_entity(&self, key: &EntityKey, block: BlockNumber) -> Result<Option<Entity>, Error> {
+        let (query, field) = self.query(&key.entity_type, &key.entity_id, block)?;
+        debug!(self.logger, "Fetching entity from fork";
+               "entity_type" => &key.entity_type,
+               "id" => &key.entity_id,
+               "block" => block);
+
+        let mut body = BTreeMap::new();
+        body.insert("query", query);
+        let body = serde_json::to_string(&body)?;
+
+        let response = graph::block_on_allow_panic(
+            self.http_client
+                .post(self.endpoint.clone())
+                .header("Content-Type", "application/json")
+                .body(body)
+                .timeout(Duration::from_secs(60))
+                .send()
+                .and_then(|res| async { res.error_for_status() })
+                .and_then(|res| res.text()),
+        )
+        .map_err(|e| format_err!("failed to fetch entity from fork: {}", e))?;
+
+        let mut response: serde_json::Value = serde_json::from_str(&response)?;
+        if let Some(errors) = response.get("errors") {
+            return Err(format_err!("fork responded with errors: {}", errors));
+        }
+        match response
+            .get_mut("data")
+            .and_then(|data| data.get_mut(&field))
+            .map(serde_json::Value::take)
+        {
+            Some(serde_json::Value::Object(data)) => Ok(Some(self.entity(&key.entity_type, data)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl SubgraphForkTrait for SubgraphFork {
+    fn fetch(&self, key: &EntityKey, block: BlockNumber) -> Result<Option<Entity>, StoreError> {
+        if key.subgraph_id != self.subgraph_id {
+            return Ok(None);
+        }
+        if !self
+            .fetched
+            .lock()
+            .unwrap()
+            .insert((key.entity_type.clone(), key.entity_id.clone()))
+        {
+            return Ok(None);
+        }
+
+        self.fetch_entity(key, block).map_err(|e| {
+            // Allow the entity to be fetched again when the block is retried
+            self.fetched
+                .lock()
+                .unwrap()
+                .remove(&(key.entity_type.clone(), key.entity_id.clone()));
+            e.into()
+        })
+    }
+}
+
+fn is_derived(field: &s::Field) -> bool {
+    field
+        .directives
+        .iter()
+        .any(|directive| directive.name == "derivedFrom")
+}
+
+/// Fields that the API schema adds to entity types and that are not
+/// attributes of the entity
+fn is_api_field(field: &s::Field) -> bool {
+    field.name == VERSION_FIELD || field.derived_count_field().is_some()
+}
+
+/// Turn a JSON value from a query response into the `q::Value` it
+/// represents. References to other entities are queried as `{ id }` and
+/// become the id of the entity.
+fn query_value(value: serde_json::Value) -> Result<q::Value, Error> {
+    use serde_json::Value as J;
+
+    Ok(match value {
+        J::Null => q::Value::Null,
+        J::Bool(b) => q::Value::Boolean(b),
+        J::Number(n) => match n.as_i64() {
+            Some(i) => q::Value::Int(
+                i32::try_from(i)
+                    .map_err(|_| format_err!("fork returned {} for an Int field", n))?
+                    .into(),
+            ),
+            None => q::Value::String(n.to_string()),
+        },
+        J::String(s) => q::Value::String(s),
+        J::Array(values) => q::Value::List(
+            values
+                .into_iter()
+                .map(query_value)
+                .collect::<Result<_, _>>()?,
+        ),
+        J::Object(mut object) => match object.remove("id") {
+            Some(id) => query_value(id)?,
+            None => q::Value::Null,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_graphql::schema::api::api_schema;
+
+    const GQL: &str = "
+        type Band @entity {
+            id: ID!
+            name: String!
+            members: [Musician!]!
+            fans: [Fan!]! @derivedFrom(field: \"band\")
+        }
+        type Musician @entity { id: ID! }
+        type Fan @entity { id: ID! band: Band! }
+    ";
+
+    fn fork() -> SubgraphFork {
+        let id = SubgraphDeploymentId::new("fork").unwrap();
+        let mut schema = Schema::parse(GQL, id.clone()).unwrap();
+        schema.document = api_schema(&schema.document).unwrap();
+        SubgraphFork::new(
+            Logger::root(slog::Discard, o!()),
+            Url::parse("http://localhost:8000/subgraphs/id/fork").unwrap(),
+            id,
+            Arc::new(schema),
+        )
+    }
+
+    #[test]
+    fn query_at_block() {
+        let (query, field) = fork().query("Band", "b1", 7).unwrap();
+        assert_eq!("band", field);
+        assert_eq!(
+            "query { band(id: \"b1\", block: { number: 7 }) { id name members { id } } }",
+            query
+        );
+    }
+
+    #[test]
+    fn int_out_of_range() {
+        assert_eq!(
+            q::Value::Int(5.into()),
+            query_value(serde_json::json!(5)).unwrap()
+        );
+        assert!(query_value(serde_json::json!(1_i64 << 40)).is_err());
+    }
+}
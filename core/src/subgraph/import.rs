@@ -0,0 +1,341 @@
+use std::collections::HashSet;
+
+use graph::data::graphql::ext::{DocumentExt, TypeExt};
+use graph::data::schema::Schema;
+use graph::prelude::*;
+use graph_graphql::graphql_parser::{query as q, schema as s};
+use graph_graphql::prelude::validate_entity;
+
+/// Parse the entities of type `entity_type` that `data` contains and check
+/// them against `schema`. Errors name the line of `data` they refer to.
+pub(crate) fn parse_entities(
+    schema: &Schema,
+    entity_type: &str,
+    format: ImportFormat,
+    data: &str,
+) -> Result<Vec<(EntityKey, Entity)>, String> {
+    let object_type = schema
+        .document
+        .get_object_type_definition(entity_type)
+        .ok_or_else(|| format!("unknown entity type `{}`", entity_type))?;
+
+    let rows = match format {
+        ImportFormat::Jsonl => jsonl_rows(data)?,
+        ImportFormat::Csv => csv_rows(object_type, data)?,
+    };
+
+    let mut ids = HashSet::new();
+    let mut entities = Vec::with_capacity(rows.len());
+    for (line, row) in rows {
+        let entity = entity(object_type, row).map_err(|e| format!("line {}: {}", line, e))?;
+        let entity_id = match entity.get("id") {
+            Some(Value::String(id)) => id.clone(),
+            Some(Value::Bytes(id)) => id.to_string(),
+            _ => return Err(format!("line {}: the entity has no `id`", line)),
+        };
+        if !ids.insert(entity_id.clone()) {
+            return Err(format!("line {}: duplicate id `{}`", line, entity_id));
+        }
+        let key = EntityKey {
+            subgraph_id: schema.id.clone(),
+            entity_type: entity_type.to_owned(),
+            entity_id,
+        };
+        validate_entity(&schema.document, &key, &entity)
+            .map_err(|e| format!("line {}: {}", line, e))?;
+        entities.push((key, entity));
+    }
+    Ok(entities)
+}
+
+/// Turn the named values of one row into an entity of `object_type`
+fn entity(object_type: &s::ObjectType, row: Vec<(String, q::Value)>) -> Result<Entity, String> {
+    let mut entity = Entity::new();
+    for (name, value) in row {
+        let field = object_type
+            .fields
+            .iter()
+            .find(|field| field.name == name)
+            .ok_or_else(|| format!("unknown field `{}`", name))?;
+        let value = Value::from_query_value(&value, &field.field_type)
+            .map_err(|e| format!("invalid value for `{}`: {}", name, e))?;
+        entity.insert(name, value);
+    }
+    Ok(entity)
+}
+
+/// Each non-empty line of `data` holds one JSON object
+fn jsonl_rows(data: &str) -> Result<Vec<(usize, Vec<(String, q::Value)>)>, String> {
+    let mut rows = vec![];
+    for (index, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object = match serde_json::from_str(line) {
+            Ok(serde_json::Value::Object(object)) => object,
+            Ok(_) => return Err(format!("line {}: expected a JSON object", index + 1)),
+            Err(e) => return Err(format!("line {}: {}", index + 1, e)),
+        };
+        let row = object
+            .into_iter()
+            .map(|(name, value)| (name, query_value(value)))
+            .collect();
+        rows.push((index + 1, row));
+    }
+    Ok(rows)
+}
+
+/// The first record of `data` names the fields, and every further record
+/// holds the values of one entity. Empty cells are `null`, and cells for
+/// list fields hold a JSON array
+fn csv_rows(
+    object_type: &s::ObjectType,
+    data: &str,
+) -> Result<Vec<(usize, Vec<(String, q::Value)>)>, String> {
+    let mut records = csv_records(data)?.into_iter();
+    let header = match records.next() {
+        Some((_, header)) => header,
+        None => return Ok(vec![]),
+    };
+
+    let mut rows = vec![];
+    for (line, record) in records {
+        if record.len() != header.len() {
+            return Err(format!(
+                "line {}: expected {} values but found {}",
+                line,
+                header.len(),
+                record.len()
+            ));
+        }
+        let row = header
+            .iter()
+            .zip(record)
+            .map(|(name, cell)| {
+                let field_type = object_type
+                    .fields
+                    .iter()
+                    .find(|field| &field.name == name)
+                    .map(|field| &field.field_type);
+                let value = match field_type {
+                    Some(field_type) => cell_value(field_type, cell),
+                    // `entity` reports the unknown field
+                    None => Ok(q::Value::Null),
+                };
+                value
+                    .map(|value| (name.clone(), value))
+                    .map_err(|e| format!("line {}: invalid value for `{}`: {}", line, name, e))
+            })
+            .collect::<Result<_, _>>()?;
+        rows.push((line, row));
+    }
+    Ok(rows)
+}
+
+/// Split `data` into records of cells. Cells may be quoted with `"`, and
+/// quoted cells may contain commas, newlines, and `""` for a quote. Each
+/// record comes with the line it starts on
+fn csv_records(data: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if cell.is_empty() => quoted = true,
+            (',', false) => record.push(std::mem::take(&mut cell)),
+            ('\r', false) if chars.peek() == Some(&'\n') => (),
+            ('\n', false) => {
+                record.push(std::mem::take(&mut cell));
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push((start, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start = line;
+            }
+            (c, _) => {
+                if c == '\n' {
+                    line += 1;
+                }
+                cell.push(c)
+            }
+        }
+    }
+    if quoted {
+        return Err(format!("line {}: unterminated quoted value", start));
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push((start, record));
+    }
+    Ok(records)
+}
+
+/// The value that the CSV `cell` holds for a field of type `field_type`
+fn cell_value(field_type: &s::Type, cell: String) -> Result<q::Value, String> {
+    if cell.is_empty() {
+        return Ok(q::Value::Null);
+    }
+    if is_list(field_type) {
+        return serde_json::from_str(&cell)
+            .map(query_value)
+            .map_err(|e| e.to_string());
+    }
+    match field_type.get_base_type().as_str() {
+        "Int" => cell
+            .parse::<i32>()
+            .map(|i| q::Value::Int(i.into()))
+            .map_err(|e| e.to_string()),
+        "Boolean" => cell
+            .parse::<bool>()
+            .map(q::Value::Boolean)
+            .map_err(|e| e.to_string()),
+        _ => Ok(q::Value::String(cell)),
+    }
+}
+
+fn is_list(field_type: &s::Type) -> bool {
+    match field_type {
+        s::Type::NamedType(_) => false,
+        s::Type::NonNullType(inner) => is_list(inner),
+        s::Type::ListType(_) => true,
+    }
+}
+
+/// Turn a JSON value into the `q::Value` it represents. Numbers that do
+/// not fit into an `Int` become strings, which `BigInt` and `BigDecimal`
+/// fields accept; large numbers have to be given as strings in the first
+/// place to keep their precision
+fn query_value(value: serde_json::Value) -> q::Value {
+    use serde_json::Value as J;
+
+    match value {
+        J::Null => q::Value::Null,
+        J::Bool(b) => q::Value::Boolean(b),
+        J::Number(n) => match n.as_i64() {
+            Some(i) if i >= i32::MIN as i64 && i <= i32::MAX as i64 => {
+                q::Value::Int((i as i32).into())
+            }
+            _ => q::Value::String(n.to_string()),
+        },
+        J::String(s) => q::Value::String(s),
+        J::Array(values) => q::Value::List(values.into_iter().map(query_value).collect()),
+        J::Object(_) => q::Value::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const SCHEMA: &str = "
+        type Token @entity {
+            id: ID!
+            symbol: String!
+            decimals: Int!
+            supply: BigInt
+            listed: Boolean
+            tags: [String!]
+        }";
+
+    fn schema() -> Schema {
+        Schema::parse(SCHEMA, SubgraphDeploymentId::new("import").unwrap()).unwrap()
+    }
+
+    fn parse(format: ImportFormat, data: &str) -> Result<Vec<(EntityKey, Entity)>, String> {
+        parse_entities(&schema(), "Token", format, data)
+    }
+
+    #[test]
+    fn jsonl() {
+        let data = r#"{"id": "dai", "symbol": "DAI", "decimals": 18, "supply": "123456789012345678901", "tags": ["stable"]}
+
+{"id": "weth", "symbol": "WETH", "decimals": 18, "listed": true}
+"#;
+        let entities = parse(ImportFormat::Jsonl, data).unwrap();
+        assert_eq!(2, entities.len());
+
+        let (key, dai) = &entities[0];
+        assert_eq!("dai", key.entity_id);
+        assert_eq!(Some(&Value::Int(18)), dai.get("decimals"));
+        assert_eq!(
+            Some(&Value::BigInt(
+                BigInt::from_str("123456789012345678901").unwrap()
+            )),
+            dai.get("supply")
+        );
+        assert_eq!(
+            Some(&Value::List(vec![Value::String("stable".to_owned())])),
+            dai.get("tags")
+        );
+        assert_eq!(Some(&Value::Bool(true)), entities[1].1.get("listed"));
+    }
+
+    #[test]
+    fn csv() {
+        let data = "id,symbol,decimals,supply,listed,tags\n\
+                    dai,DAI,18,123456789012345678901,,\"[\"\"stable\"\"]\"\r\n\
+                    \"we,th\",WETH,18,,true,\n";
+        let entities = parse(ImportFormat::Csv, data).unwrap();
+        assert_eq!(2, entities.len());
+
+        let (key, dai) = &entities[0];
+        assert_eq!("dai", key.entity_id);
+        assert_eq!(Some(&Value::Int(18)), dai.get("decimals"));
+        assert_eq!(Some(&Value::Null), dai.get("listed"));
+        assert_eq!(
+            Some(&Value::List(vec![Value::String("stable".to_owned())])),
+            dai.get("tags")
+        );
+
+        let (key, weth) = &entities[1];
+        assert_eq!("we,th", key.entity_id);
+        assert_eq!(Some(&Value::Bool(true)), weth.get("listed"));
+    }
+
+    #[test]
+    fn invalid_entities() {
+        let err = |format, data| parse(format, data).unwrap_err();
+
+        assert!(
+            err(ImportFormat::Jsonl, r#"{"id": "dai", "decimals": 18}"#).starts_with("line 1: ")
+        );
+        assert_eq!(
+            "line 2: unknown field `name`",
+            err(
+                ImportFormat::Jsonl,
+                "{\"id\": \"dai\", \"symbol\": \"DAI\", \"decimals\": 18}\n\
+                 {\"id\": \"weth\", \"name\": \"WETH\"}"
+            )
+        );
+        assert_eq!(
+            "line 3: duplicate id `dai`",
+            err(
+                ImportFormat::Csv,
+                "id,symbol,decimals\ndai,DAI,18\ndai,DAI,18\n"
+            )
+        );
+        assert_eq!(
+            "line 2: expected 3 values but found 2",
+            err(ImportFormat::Csv, "id,symbol,decimals\ndai,DAI\n")
+        );
+        assert!(err(ImportFormat::Csv, "id,symbol,decimals\ndai,DAI,many\n")
+            .starts_with("line 2: invalid value for `decimals`"));
+        assert_eq!(
+            "unknown entity type `Pair`",
+            parse_entities(&schema(), "Pair", ImportFormat::Jsonl, "").unwrap_err()
+        );
+    }
+}
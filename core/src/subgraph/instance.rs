@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use futures01::sync::mpsc::Sender;
 use lazy_static::lazy_static;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::str::FromStr;
 
@@ -15,6 +15,22 @@ lazy_static! {
         .ok()
         .map(|s| usize::from_str(&s)
             .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_SUBGRAPH_MAX_DATA_SOURCES")));
+
+    /// Whether the triggers of data sources that declare disjoint sets of
+    /// entity types are processed in parallel
+    pub(crate) static ref PARALLEL_TRIGGERS: bool = env::var("GRAPH_PARALLEL_TRIGGER_PROCESSING")
+        .unwrap_or("false".into())
+        .parse::<bool>()
+        .expect("invalid GRAPH_PARALLEL_TRIGGER_PROCESSING");
+}
+
+/// Hosts whose data sources declare none of the entity types that the data
+/// sources of the hosts in other groups declare
+pub(crate) struct HostGroup<H> {
+    /// The hosts with their index in the subgraph instance, in the order of
+    /// the hosts in the instance
+    pub hosts: Vec<(usize, Arc<H>)>,
+    pub entity_types: BTreeSet<String>,
 }
 
 pub struct SubgraphInstance<T: RuntimeHostBuilder> {
@@ -30,7 +46,9 @@ pub struct SubgraphInstance<T: RuntimeHostBuilder> {
     hosts: Vec<Arc<T::Host>>,
 
     /// Maps the hash of a module to a channel to the thread in which the module is instantiated.
-    module_cache: HashMap<[u8; 32], Sender<T::Req>>,
+    /// When triggers are processed in parallel, data sources that declare different entity
+    /// types get their own thread, so that the hash is paired with the declared types.
+    module_cache: HashMap<([u8; 32], Vec<String>), Sender<T::Req>>,
 }
 
 impl<T> SubgraphInstance<T>
@@ -40,11 +58,11 @@ where
     pub(crate) fn from_manifest(
         logger: &Logger,
         manifest: SubgraphManifest,
+        network: String,
         host_builder: T,
         host_metrics: Arc<HostMetrics>,
     ) -> Result<Self, Error> {
         let subgraph_id = manifest.id.clone();
-        let network = manifest.network_name();
         let templates = Arc::new(manifest.templates);
 
         let mut this = SubgraphInstance {
@@ -96,7 +114,16 @@ where
         let mapping_request_sender = {
             let module_bytes = data_source.mapping.runtime.as_ref();
             let module_hash = tiny_keccak::keccak256(module_bytes);
-            if let Some(sender) = self.module_cache.get(&module_hash) {
+            let entities = match *PARALLEL_TRIGGERS {
+                true => {
+                    let mut entities = data_source.mapping.entities.clone();
+                    entities.sort();
+                    entities
+                }
+                false => vec![],
+            };
+            let key = (module_hash, entities);
+            if let Some(sender) = self.module_cache.get(&key) {
                 sender.clone()
             } else {
                 let sender = T::spawn_mapping(
@@ -105,7 +132,7 @@ where
                     self.subgraph_id.clone(),
                     host_metrics.clone(),
                 )?;
-                self.module_cache.insert(module_hash, sender.clone());
+                self.module_cache.insert(key, sender.clone());
                 sender
             }
         };
@@ -120,6 +147,71 @@ where
             )
             .compat_err()
     }
+
+    pub(crate) fn host_count(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Drop the hosts for data sources that were added after the first
+    /// `count` hosts, used to undo dynamic data sources of a failed block.
+    pub(crate) fn truncate_hosts(&mut self, count: usize) {
+        self.hosts.truncate(count)
+    }
+
+    /// Partition the hosts into groups such that the data sources of hosts
+    /// in different groups declare disjoint sets of entity types. Groups are
+    /// ordered by their first host.
+    pub(crate) fn host_groups(&self) -> Vec<HostGroup<T::Host>> {
+        group_hosts(
+            self.hosts
+                .iter()
+                .map(|host| (host.clone(), host.entities().iter().cloned().collect())),
+        )
+    }
+}
+
+/// Partition hosts, given with the entity types of their data sources, as
+/// described for `SubgraphInstance::host_groups`
+fn group_hosts<H>(hosts: impl Iterator<Item = (Arc<H>, BTreeSet<String>)>) -> Vec<HostGroup<H>> {
+    let mut groups: Vec<HostGroup<H>> = Vec::new();
+    for (index, (host, entity_types)) in hosts.enumerate() {
+        let mut group = HostGroup {
+            hosts: vec![(index, host)],
+            entity_types,
+        };
+
+        // Merge all groups that share an entity type with the host into
+        // the first of them
+        let mut position = None;
+        let mut i = 0;
+        while i < groups.len() {
+            if groups[i].entity_types.is_disjoint(&group.entity_types) {
+                i += 1;
+                continue;
+            }
+            match position {
+                None => {
+                    position = Some(i);
+                    i += 1;
+                }
+                Some(_) => {
+                    let other = groups.remove(i);
+                    group.hosts.extend(other.hosts);
+                    group.entity_types.extend(other.entity_types);
+                }
+            }
+        }
+        match position {
+            Some(position) => {
+                let first = &mut groups[position];
+                first.hosts.extend(group.hosts);
+                first.hosts.sort_by_key(|(index, _)| *index);
+                first.entity_types.extend(group.entity_types);
+            }
+            None => groups.push(group),
+        }
+    }
+    groups
 }
 
 #[async_trait]
@@ -139,7 +231,7 @@ where
         trigger: EthereumTrigger,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error> {
+    ) -> Result<BlockState, MappingError> {
         Self::process_trigger_in_runtime_hosts(
             logger,
             &self.hosts,
@@ -158,7 +250,7 @@ where
         trigger: EthereumTrigger,
         mut state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error> {
+    ) -> Result<BlockState, MappingError> {
         match trigger {
             EthereumTrigger::Log(log) => {
                 let log = Arc::new(log);
@@ -226,6 +318,36 @@ where
         Ok(state)
     }
 
+    async fn process_entity_change(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        source: &SubgraphDeploymentId,
+        entity_type: &str,
+        entity: &Entity,
+        mut state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        let block_number = block.number.unwrap().as_u64();
+        let matching_hosts = self
+            .hosts
+            .iter()
+            .filter(|host| host.matches_entity(source, entity_type, block_number));
+        for host in matching_hosts {
+            state = host
+                .process_entity(
+                    logger,
+                    block,
+                    entity_type,
+                    entity,
+                    state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await?;
+        }
+        Ok(state)
+    }
+
     fn add_dynamic_data_source(
         &mut self,
         logger: &Logger,
@@ -253,3 +375,71 @@ where
         Ok(host)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Group hosts named after their index that declare `entities`, and
+    /// return the host indexes and entity types of each group
+    fn groups(entities: &[&[&str]]) -> Vec<(Vec<usize>, Vec<String>)> {
+        let hosts = entities.iter().enumerate().map(|(index, entities)| {
+            (
+                Arc::new(index),
+                entities.iter().map(|entity| entity.to_string()).collect(),
+            )
+        });
+        group_hosts(hosts)
+            .into_iter()
+            .map(|group| {
+                for (index, host) in &group.hosts {
+                    assert_eq!(index, host.as_ref());
+                }
+                (
+                    group.hosts.into_iter().map(|(index, _)| index).collect(),
+                    group.entity_types.into_iter().collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn types(types: &[&str]) -> Vec<String> {
+        types.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn disjoint_hosts_get_groups_of_their_own() {
+        assert_eq!(
+            groups(&[&["Token"], &["Pair", "Swap"], &[]]),
+            vec![
+                (vec![0], types(&["Token"])),
+                (vec![1], types(&["Pair", "Swap"])),
+                (vec![2], types(&[])),
+            ]
+        );
+    }
+
+    #[test]
+    fn hosts_sharing_entity_types_are_grouped() {
+        assert_eq!(
+            groups(&[&["Token"], &["Pair"], &["Token", "Swap"], &["Pair"]]),
+            vec![
+                (vec![0, 2], types(&["Swap", "Token"])),
+                (vec![1, 3], types(&["Pair"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn host_joining_groups_merges_them() {
+        // The last host links the first two groups, which become one that
+        // keeps the position of the first
+        assert_eq!(
+            groups(&[&["Token"], &["Pair"], &["Swap"], &["Pair", "Token"]]),
+            vec![
+                (vec![0, 1, 3], types(&["Pair", "Token"])),
+                (vec![2], types(&["Swap"])),
+            ]
+        );
+    }
+}
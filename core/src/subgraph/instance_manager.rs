@@ -1,21 +1,38 @@
 use atomic_refcell::AtomicRefCell;
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
-use graph::components::ethereum::{triggers_in_block, EthereumNetworks};
+use graph::blockchain::{Blockchain, TriggerData, TriggerFilter};
+use graph::components::ethereum::{
+    rpc_trace, EthereumChain, EthereumNetworks, EthereumTriggerFilter,
+};
 use graph::components::store::ModificationsAndCache;
-use graph::components::subgraph::{ProofOfIndexing, SharedProofOfIndexing};
+use graph::components::subgraph::RETRIES;
+use graph::components::subgraph::{ProofOfIndexing, RecordedEvent, SharedProofOfIndexing};
 use graph::data::store::scalar::Bytes;
 use graph::data::subgraph::schema::{
-    queries::LazyMetadata, DynamicEthereumContractDataSourceEntity, SubgraphError, SubgraphHealth,
-    POI_OBJECT,
+    queries::LazyMetadata, DynamicEthereumContractDataSourceEntity,
+    SubgraphDeploymentAssignmentEntity, SubgraphError, SubgraphHealth, POI_OBJECT,
+};
+use graph::log::recent::RECENT_LOGS;
+use graph::prelude::{
+    SubgraphFork as SubgraphForkTrait, SubgraphInstance as SubgraphInstanceTrait, *,
 };
-use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
+use graph::url::Url;
+use graph::util::backoff::ExponentialBackoff;
+use graph::util::budget::PriorityBudget;
 use graph::util::lfu_cache::LfuCache;
+use web3::types::H256;
 
+use super::fork::SubgraphFork;
+use super::instance::{HostGroup, PARALLEL_TRIGGERS};
+use super::quotas::ResourceQuotas;
+use super::shutdown::IndexingShutdown;
+use super::sync_queue::{SyncPermit, SyncQueue};
 use super::SubgraphInstance;
 
 lazy_static! {
@@ -26,10 +43,160 @@ lazy_static! {
             .unwrap_or("10000".into())
             .parse::<usize>()
             .expect("invalid GRAPH_ENTITY_CACHE_SIZE");
+
+    /// Maximum number of final blocks whose changes are written to the store
+    /// in one transaction. With the default of 1, every block is written by
+    /// itself.
+    static ref WRITE_BATCH_SIZE: usize = std::env::var("GRAPH_SUBGRAPH_WRITE_BATCH_SIZE")
+        .unwrap_or("1".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_SUBGRAPH_WRITE_BATCH_SIZE");
+
+    /// Maximum time the changes of a processed block wait for more blocks
+    /// to be written with them, in seconds.
+    static ref WRITE_BATCH_MAX_LATENCY: Duration = Duration::from_secs(
+        std::env::var("GRAPH_SUBGRAPH_WRITE_BATCH_MAX_LATENCY")
+            .unwrap_or("10".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_SUBGRAPH_WRITE_BATCH_MAX_LATENCY")
+    );
+
+    /// Maximum number of deployments that write to the store at the same
+    /// time. Deployments that have to wait write in the order of their
+    /// priority. Defaults to the size of the connection pool.
+    static ref MAX_CONCURRENT_WRITES: usize =
+        std::env::var("GRAPH_SUBGRAPH_MAX_CONCURRENT_WRITES")
+            .or_else(|_| std::env::var("STORE_CONNECTION_POOL_SIZE"))
+            .unwrap_or("10".into())
+            .parse::<usize>()
+            .expect("invalid GRAPH_SUBGRAPH_MAX_CONCURRENT_WRITES");
 }
 
+/// A deployment that is at most this many blocks behind the chain head is
+/// done with its initial sync and gives up its place in the sync queue
+const CAUGHT_UP_DISTANCE: u64 = 100;
+
+/// How often to check whether a source deployment has processed the block
+/// that is being processed, and how often to log that we are still waiting
+const SOURCE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const SOURCE_WAIT_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often a deployment that waits in the sync queue checks whether it
+/// was stopped
+const SYNC_QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
 
+/// Initial and maximum delay before retrying a block that failed with a
+/// non-deterministic error, or a subgraph that failed to start.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30 * 60);
+
+/// How much retry delays vary randomly, so that subgraphs that failed
+/// because of the same outage do not all retry at once
+const RETRY_JITTER: f64 = 0.2;
+
+fn retry_backoff() -> ExponentialBackoff {
+    ExponentialBackoff::new(MIN_RETRY_DELAY, MAX_RETRY_DELAY).with_jitter(RETRY_JITTER)
+}
+
+/// Errors that can occur while processing a block.
+#[derive(Debug)]
+enum BlockProcessingError {
+    /// Processing the block will fail the same way every time. The
+    /// subgraph is marked as failed.
+    Deterministic(Error),
+
+    /// The error may be transient; the block is retried after a delay.
+    Unknown(Error),
+
+    /// A handler failed deterministically and the subgraph does not allow
+    /// non-fatal errors.
+    FailedHandler(SubgraphError),
+
+    /// The subgraph exceeded one of its resource quotas. It is marked as
+    /// failed, so that it stops using resources of the node.
+    QuotaExceeded(String),
+
+    /// The subgraph was stopped while the block was being processed.
+    Canceled,
+}
+
+impl BlockProcessingError {
+    fn from_mapping_error(
+        e: MappingError,
+        transaction_id: Option<H256>,
+        block_ptr: &EthereumBlockPointer,
+    ) -> Self {
+        let deterministic = e.is_deterministic();
+        let e = match transaction_id {
+            Some(tx_hash) => format_err!(
+                "Failed to process trigger in block {}, transaction {:x}: {}",
+                block_ptr,
+                tx_hash,
+                e
+            ),
+            None => format_err!("Failed to process trigger: {}", e),
+        };
+        match deterministic {
+            true => BlockProcessingError::Deterministic(e),
+            false => BlockProcessingError::Unknown(e),
+        }
+    }
+
+    /// The error to record on the deployment when this error fails the subgraph.
+    fn into_subgraph_error(
+        self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_ptr: EthereumBlockPointer,
+    ) -> SubgraphError {
+        match self {
+            BlockProcessingError::FailedHandler(error) => error,
+            BlockProcessingError::Deterministic(e) | BlockProcessingError::Unknown(e) => {
+                SubgraphError {
+                    subgraph_id: subgraph_id.clone(),
+                    message: e.to_string(),
+                    block_ptr: Some(block_ptr),
+                    handler: None,
+                }
+            }
+            BlockProcessingError::QuotaExceeded(message) => SubgraphError {
+                subgraph_id: subgraph_id.clone(),
+                message,
+                block_ptr: Some(block_ptr),
+                handler: None,
+            },
+            BlockProcessingError::Canceled => {
+                unreachable!("canceling a subgraph does not fail it")
+            }
+        }
+    }
+}
+
+impl From<Error> for BlockProcessingError {
+    fn from(e: Error) -> Self {
+        BlockProcessingError::Unknown(e)
+    }
+}
+
+impl From<CancelableError<Error>> for BlockProcessingError {
+    fn from(e: CancelableError<Error>) -> Self {
+        match e {
+            CancelableError::Cancel => BlockProcessingError::Canceled,
+            CancelableError::Error(e) => BlockProcessingError::Unknown(e),
+        }
+    }
+}
+
+impl From<MappingError> for BlockProcessingError {
+    fn from(e: MappingError) -> Self {
+        match e {
+            MappingError::Deterministic(e) => BlockProcessingError::Deterministic(e.compat_err()),
+            MappingError::Unknown(e) => BlockProcessingError::Unknown(e.compat_err()),
+        }
+    }
+}
+
 struct IndexingInputs<B, S> {
     deployment_id: SubgraphDeploymentId,
     network_name: String,
@@ -39,17 +206,68 @@ struct IndexingInputs<B, S> {
     stream_builder: B,
     include_calls_in_blocks: bool,
     top_level_templates: Arc<Vec<DataSourceTemplate>>,
+    features: BTreeSet<SubgraphFeature>,
+    /// The deployments whose entity changes `subgraph` data sources handle,
+    /// with the entity types they handle
+    source_subgraphs: BTreeMap<SubgraphDeploymentId, Vec<String>>,
+    /// Where to fetch entities that are not in the store from
+    fork: Option<Arc<dyn SubgraphForkTrait>>,
+    shutdown: Arc<IndexingShutdown>,
+    /// The number of data sources in the manifest; the subgraph instance
+    /// has a host for each of them, followed by the dynamic data sources
+    static_data_sources: usize,
+    quotas: ResourceQuotas,
+    priority: DeploymentPriority,
+    sync_queue: Arc<SyncQueue>,
+    /// Shared by all deployments so that they take turns writing
+    write_budget: Arc<PriorityBudget>,
 }
 
 struct IndexingState<T: RuntimeHostBuilder> {
     logger: Logger,
     instance: SubgraphInstance<T>,
     instances: SharedInstanceKeepAliveMap,
-    log_filter: EthereumLogFilter,
-    call_filter: EthereumCallFilter,
-    block_filter: EthereumBlockFilter,
+    filter: EthereumTriggerFilter,
     restarts: u64,
     entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    /// Whether the triggers of independent data sources are processed in
+    /// parallel. Turned off once handlers access entity types that their
+    /// data sources do not declare.
+    parallel_triggers: bool,
+    /// Processed blocks whose changes have not been written to the store
+    /// yet. Until they are, `entity_lfu_cache` holds the only copy of the
+    /// entities they changed and must not be evicted.
+    pending_writes: PendingWrites,
+    /// Held while the subgraph syncs historical blocks
+    sync_permit: Option<SyncPermit>,
+}
+
+/// The changes of processed blocks that wait to be written to the store
+/// together, see `GRAPH_SUBGRAPH_WRITE_BATCH_SIZE`
+#[derive(Default)]
+struct PendingWrites {
+    blocks: Vec<BlockModifications>,
+    /// When the first of `blocks` was processed
+    since: Option<Instant>,
+}
+
+impl PendingWrites {
+    fn push(&mut self, block: BlockModifications) {
+        self.since.get_or_insert_with(Instant::now);
+        self.blocks.push(block);
+    }
+
+    fn is_due(&self) -> bool {
+        self.blocks.len() >= *WRITE_BATCH_SIZE
+            || self
+                .since
+                .map_or(false, |since| since.elapsed() >= *WRITE_BATCH_MAX_LATENCY)
+    }
+
+    fn take(&mut self) -> Vec<BlockModifications> {
+        self.since = None;
+        std::mem::take(&mut self.blocks)
+    }
 }
 
 struct IndexingContext<B, T: RuntimeHostBuilder, S> {
@@ -74,6 +292,7 @@ struct IndexingContext<B, T: RuntimeHostBuilder, S> {
 pub struct SubgraphInstanceManager {
     logger: Logger,
     input: Sender<SubgraphAssignmentProviderEvent>,
+    shutdown: Arc<IndexingShutdown>,
 }
 
 struct SubgraphInstanceManagerMetrics {
@@ -95,22 +314,6 @@ impl SubgraphInstanceManagerMetrics {
     }
 }
 
-enum TriggerType {
-    Event,
-    Call,
-    Block,
-}
-
-impl TriggerType {
-    fn label_value(&self) -> &str {
-        match self {
-            TriggerType::Event => "event",
-            TriggerType::Call => "call",
-            TriggerType::Block => "block",
-        }
-    }
-}
-
 struct SubgraphInstanceMetrics {
     pub block_trigger_count: Box<Histogram>,
     pub block_processing_duration: Box<Histogram>,
@@ -165,9 +368,9 @@ impl SubgraphInstanceMetrics {
         }
     }
 
-    pub fn observe_trigger_processing_duration(&self, duration: f64, trigger: TriggerType) {
+    pub fn observe_trigger_processing_duration(&self, duration: f64, trigger_kind: &str) {
         self.trigger_processing_duration
-            .with_label_values(vec![trigger.label_value()].as_slice())
+            .with_label_values(vec![trigger_kind].as_slice())
             .observe(duration);
     }
 
@@ -189,6 +392,7 @@ impl SubgraphInstanceManager {
         block_stream_builder: B,
         metrics_registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
+        fork_base: Option<Url>,
     ) -> Self
     where
         S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
@@ -201,34 +405,48 @@ impl SubgraphInstanceManager {
         // Create channel for receiving subgraph provider events.
         let (subgraph_sender, subgraph_receiver) = channel(100);
 
+        let shutdown = Arc::new(IndexingShutdown::default());
+
         // Handle incoming events from the subgraph provider.
         Self::handle_subgraph_events(
             logger_factory,
             subgraph_receiver,
+            subgraph_sender.clone(),
+            shutdown.clone(),
             stores,
             eth_networks,
             host_builder,
             block_stream_builder,
             metrics_registry.clone(),
             graphql_runner,
+            fork_base,
         );
 
         SubgraphInstanceManager {
             logger,
             input: subgraph_sender,
+            shutdown,
         }
     }
 
+    /// The handle to stop indexing with when the node shuts down.
+    pub fn shutdown_handle(&self) -> Arc<IndexingShutdown> {
+        self.shutdown.clone()
+    }
+
     /// Handle incoming events from subgraph providers.
     fn handle_subgraph_events<B, S, M>(
         logger_factory: LoggerFactory,
         receiver: Receiver<SubgraphAssignmentProviderEvent>,
+        sender: Sender<SubgraphAssignmentProviderEvent>,
+        shutdown: Arc<IndexingShutdown>,
         stores: HashMap<String, Arc<S>>,
         eth_networks: EthereumNetworks,
         host_builder: impl RuntimeHostBuilder,
         block_stream_builder: B,
         metrics_registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
+        fork_base: Option<Url>,
     ) where
         S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
         B: BlockStreamBuilder,
@@ -241,6 +459,14 @@ impl SubgraphInstanceManager {
         // Subgraph instance shutdown senders
         let instances: SharedInstanceKeepAliveMap = Default::default();
 
+        let sync_queue = SyncQueue::new();
+        let write_budget = PriorityBudget::new(*MAX_CONCURRENT_WRITES);
+
+        // Subgraphs that failed to start are started again after a delay;
+        // dropping the guard cancels that
+        let mut start_retries: HashMap<SubgraphDeploymentId, (ExponentialBackoff, CancelGuard)> =
+            HashMap::new();
+
         // Blocking due to store interactions. Won't be blocking after #905.
         graph::spawn_blocking(async move {
             let mut assignment_stream = receiver.compat();
@@ -250,15 +476,20 @@ impl SubgraphInstanceManager {
                 use self::SubgraphAssignmentProviderEvent::*;
 
                 match event {
-                    SubgraphStart(manifest) => {
+                    SubgraphStart {
+                        manifest,
+                        dynamic_data_sources,
+                    } => {
                         let logger = logger_factory.subgraph_logger(&manifest.id);
                         info!(
                             logger,
                             "Start subgraph";
                             "data_sources" => manifest.data_sources.len()
                         );
-                        let network = manifest.network_name();
+                        let network = Self::indexing_network(&logger, &stores, &manifest);
                         let required_capabilities = manifest.required_ethereum_capabilities();
+                        let id = manifest.id.clone();
+                        let retry_manifest = manifest.clone();
 
                         match Self::start_subgraph(
                             logger.clone(),
@@ -282,24 +513,67 @@ impl SubgraphInstanceManager {
                                     &required_capabilities))
                                 .clone(),
                             manifest,
+                            network,
+                            dynamic_data_sources,
                             metrics_registry_for_subgraph.clone(),
                             graphql_runner.clone(),
+                            fork_base.clone(),
+                            shutdown.clone(),
+                            sync_queue.clone(),
+                            write_budget.clone(),
                         )
                         .await
                         {
-                            Ok(()) => manager_metrics.subgraph_count.inc(),
-                            Err(err) => error!(
-                                logger,
-                                "Failed to start subgraph";
-                                "error" => format!("{}", err),
-                                "code" => LogCode::SubgraphStartFailure
-                            ),
+                            Ok(()) => {
+                                if start_retries.remove(&id).is_some() {
+                                    RETRIES.remove(&id);
+                                }
+                                manager_metrics.subgraph_count.inc()
+                            }
+                            Err(err) => {
+                                let (backoff, guard) = start_retries
+                                    .entry(id.clone())
+                                    .or_insert_with(|| (retry_backoff(), CancelGuard::new()));
+                                *guard = CancelGuard::new();
+                                let handle = guard.handle();
+                                let delay = backoff.next_attempt();
+                                RETRIES.schedule(
+                                    &id,
+                                    backoff.attempt,
+                                    err.to_string(),
+                                    RpcErrorKind::of(&err),
+                                    delay,
+                                );
+                                error!(
+                                    logger,
+                                    "Failed to start subgraph, retrying";
+                                    "error" => format!("{}", err),
+                                    "attempt" => backoff.attempt,
+                                    "retry_delay_s" => delay.as_secs(),
+                                    "code" => LogCode::SubgraphStartFailure
+                                );
+
+                                let sender = sender.clone();
+                                graph::spawn(async move {
+                                    tokio::time::delay_for(delay).await;
+                                    if !handle.is_canceled() {
+                                        let event = SubgraphStart {
+                                            manifest: retry_manifest,
+                                            dynamic_data_sources,
+                                        };
+                                        sender.send(event).compat().await.ok();
+                                    }
+                                });
+                            }
                         }
                     }
                     SubgraphStop(id) => {
                         let logger = logger_factory.subgraph_logger(&id);
                         info!(logger, "Stop subgraph");
 
+                        RECENT_LOGS.remove(&id);
+                        RETRIES.remove(&id);
+                        start_retries.remove(&id);
                         Self::stop_subgraph(instances.clone(), id);
                         manager_metrics.subgraph_count.dec();
                     }
@@ -315,9 +589,15 @@ impl SubgraphInstanceManager {
         stream_builder: B,
         store: Arc<S>,
         eth_adapter: Arc<dyn EthereumAdapter>,
-        manifest: SubgraphManifest,
+        mut manifest: SubgraphManifest,
+        network_name: String,
+        dynamic_data_sources: usize,
         registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
+        fork_base: Option<Url>,
+        shutdown: Arc<IndexingShutdown>,
+        sync_queue: Arc<SyncQueue>,
+        write_budget: Arc<PriorityBudget>,
     ) -> Result<(), Error>
     where
         B: BlockStreamBuilder,
@@ -345,6 +625,14 @@ impl SubgraphInstanceManager {
         };
         store.start_subgraph_deployment(&logger, &manifest.id, status_ops)?;
 
+        // Data sources for a list of contracts are indexed as one data
+        // source for each contract; their log filters are combined below
+        manifest.data_sources = manifest
+            .data_sources
+            .into_iter()
+            .flat_map(DataSource::split_addresses)
+            .collect();
+
         let mut templates: Vec<DataSourceTemplate> = vec![];
         for data_source in manifest.data_sources.iter() {
             for template in data_source.templates.iter() {
@@ -354,12 +642,22 @@ impl SubgraphInstanceManager {
 
         // Clone the deployment ID for later
         let deployment_id = manifest.id.clone();
-        let network_name = manifest.network_name();
+
+        let priority = store
+            .get(SubgraphDeploymentAssignmentEntity::key(
+                deployment_id.clone(),
+            ))
+            .ok()
+            .flatten()
+            .as_ref()
+            .map(SubgraphDeploymentAssignmentEntity::priority_of)
+            .unwrap_or_default();
+        if priority != DeploymentPriority::Normal {
+            info!(logger, "Indexing with priority"; "priority" => priority.as_str());
+        }
 
         // Obtain filters from the manifest
-        let log_filter = EthereumLogFilter::from_data_sources(&manifest.data_sources);
-        let call_filter = EthereumCallFilter::from_data_sources(&manifest.data_sources);
-        let block_filter = EthereumBlockFilter::from_data_sources(&manifest.data_sources);
+        let filter = EthereumTriggerFilter::from_data_sources(manifest.data_sources.iter());
         let start_blocks = manifest.start_blocks();
 
         // Identify whether there are mappings with call handlers or
@@ -368,6 +666,22 @@ impl SubgraphInstanceManager {
         let include_calls_in_blocks = manifest.requires_traces();
 
         let top_level_templates = Arc::new(manifest.templates.clone());
+        let features = manifest.features.clone();
+        let source_subgraphs = manifest.source_subgraphs();
+        let static_data_sources = manifest.data_sources.len() - dynamic_data_sources;
+
+        // When debugging with a fork, entities that are not in the store yet
+        // are fetched from the same subgraph on another node
+        let fork = match fork_base {
+            Some(endpoint) => {
+                info!(logger, "Fetching missing entities from fork"; "fork" => endpoint.as_str());
+                let schema = store.api_schema(&deployment_id)?;
+                let fork =
+                    SubgraphFork::new(logger.clone(), endpoint, deployment_id.clone(), schema);
+                Some(Arc::new(fork) as Arc<dyn SubgraphForkTrait>)
+            }
+            None => None,
+        };
 
         // Create a subgraph instance from the manifest; this moves
         // ownership of the manifest and host builder into the new instance
@@ -393,8 +707,13 @@ impl SubgraphInstanceManager {
             deployment_id.clone(),
             stopwatch_metrics,
         ));
-        let instance =
-            SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
+        let instance = SubgraphInstance::from_manifest(
+            &logger,
+            manifest,
+            network_name.clone(),
+            host_builder,
+            host_metrics.clone(),
+        )?;
 
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
@@ -407,16 +726,26 @@ impl SubgraphInstanceManager {
                 stream_builder,
                 include_calls_in_blocks,
                 top_level_templates,
+                features,
+                source_subgraphs,
+                fork,
+                shutdown,
+                static_data_sources,
+                quotas: ResourceQuotas::from_env(),
+                priority,
+                sync_queue,
+                write_budget,
             },
             state: IndexingState {
                 logger,
                 instance,
                 instances,
-                log_filter,
-                call_filter,
-                block_filter,
+                filter,
                 restarts: 0,
                 entity_lfu_cache: LfuCache::new(),
+                parallel_triggers: *PARALLEL_TRIGGERS,
+                pending_writes: PendingWrites::default(),
+                sync_permit: None,
             },
             subgraph_metrics,
             host_metrics,
@@ -432,7 +761,9 @@ impl SubgraphInstanceManager {
         //
         // This task has many calls to the store, so mark it as `blocking`.
         graph::spawn_blocking(async move {
-            let res = run_subgraph(ctx).await;
+            // Lets the JSON-RPC transports trace the calls of the deployment
+            // and limit them according to its priority
+            let res = rpc_trace::for_deployment(deployment_id, priority, run_subgraph(ctx)).await;
             subgraph_metrics_unregister.unregister(registry);
             res
         });
@@ -440,6 +771,41 @@ impl SubgraphInstanceManager {
         Ok(())
     }
 
+    /// The network whose providers index the deployment of `manifest`. That
+    /// is the network alias of its assignment if it has one that this node
+    /// knows, and otherwise the network of the manifest.
+    fn indexing_network<S: Store>(
+        logger: &Logger,
+        stores: &HashMap<String, Arc<S>>,
+        manifest: &SubgraphManifest,
+    ) -> String {
+        let network = manifest.network_name();
+        let alias = stores
+            .get(&network)
+            .and_then(|store| {
+                store
+                    .get(SubgraphDeploymentAssignmentEntity::key(manifest.id.clone()))
+                    .ok()
+                    .flatten()
+            })
+            .as_ref()
+            .and_then(SubgraphDeploymentAssignmentEntity::alias_of);
+        match alias {
+            Some(alias) if stores.contains_key(&alias) => alias,
+            Some(alias) => {
+                warn!(
+                    logger,
+                    "Network alias of deployment is not configured on this node, \
+                     using the providers of its network";
+                    "network_alias" => &alias,
+                    "network" => &network,
+                );
+                network
+            }
+            None => network,
+        }
+    }
+
     fn stop_subgraph(instances: SharedInstanceKeepAliveMap, id: SubgraphDeploymentId) {
         // Drop the cancel guard to shut down the subgraph now
         let mut instances = instances.write().unwrap();
@@ -470,6 +836,12 @@ where
     let store_for_err = ctx.inputs.store.cheap_clone();
     let logger = ctx.state.logger.cheap_clone();
     let id_for_err = ctx.inputs.deployment_id.clone();
+    let mut backoff = retry_backoff();
+
+    if !acquire_sync_slot(&mut ctx).await {
+        debug!(logger, "Subgraph was stopped while waiting to sync");
+        return Err(());
+    }
 
     loop {
         debug!(logger, "Starting or restarting subgraph");
@@ -484,9 +856,9 @@ where
                 ctx.inputs.deployment_id.clone(),
                 ctx.inputs.network_name.clone(),
                 ctx.inputs.start_blocks.clone(),
-                ctx.state.log_filter.clone(),
-                ctx.state.call_filter.clone(),
-                ctx.state.block_filter.clone(),
+                ctx.state.filter.log.clone(),
+                ctx.state.filter.call.clone(),
+                ctx.state.filter.block.clone(),
                 ctx.inputs.include_calls_in_blocks,
                 ctx.block_stream_metrics.clone(),
             )
@@ -507,50 +879,109 @@ where
         // Process events from the stream as long as no restart is needed
         loop {
             let block = match block_stream.next().await {
-                Some(Ok(BlockStreamEvent::Block(block))) => block,
+                Some(Ok(BlockStreamEvent::Block(block))) => Some(block),
                 Some(Ok(BlockStreamEvent::Revert)) => {
                     // On revert, clear the entity cache.
                     ctx.state.entity_lfu_cache = LfuCache::new();
                     continue;
                 }
+                // The block stream continues from the subgraph block pointer
+                // in the store, which has to include all processed blocks
+                Some(Ok(BlockStreamEvent::Drained)) => None,
                 // Log and drop the errors from the block_stream
                 // The block stream will continue attempting to produce blocks
                 Some(Err(e)) => {
+                    let kind = match &e {
+                        CancelableError::Error(e) => RpcErrorKind::of(e),
+                        CancelableError::Cancel => None,
+                    };
                     debug!(
                         &logger,
                         "Block stream produced a non-fatal error";
                         "error" => format!("{}", e),
+                        "kind" => kind.map_or("other", |kind| kind.as_str()),
                     );
-                    continue;
+                    None
                 }
                 None => unreachable!("The block stream stopped producing blocks"),
             };
 
-            let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
+            // Once the node is shutting down, write the blocks that were
+            // processed already and leave the block to whoever picks up the
+            // subgraph next; it will start right after the last block we wrote
+            let in_progress = match ctx.inputs.shutdown.start_block() {
+                Ok(in_progress) => in_progress,
+                // Holding on to `_stopping` makes the shutdown wait for the write
+                Err(_stopping) => {
+                    info!(
+                        &logger,
+                        "Stopping subgraph because the node is shutting down"
+                    );
+                    if let Err(e) = write_pending_blocks(&logger, &mut ctx).await {
+                        error!(
+                            &logger,
+                            "Failed to write processed blocks while shutting down: {:?}", e;
+                            "id" => id_for_err.to_string(),
+                        );
+                    }
+                    return Err(());
+                }
+            };
 
-            if block.triggers.len() > 0 {
-                subgraph_metrics
-                    .block_trigger_count
-                    .observe(block.triggers.len() as f64);
-            }
+            let (block_ptr, res) = match block {
+                Some(block) => {
+                    let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
 
-            let start = Instant::now();
+                    if block.triggers.len() > 0 {
+                        subgraph_metrics
+                            .block_trigger_count
+                            .observe(block.triggers.len() as f64);
+                    }
 
-            let res = process_block(
-                &logger,
-                ctx.inputs.eth_adapter.cheap_clone(),
-                ctx,
-                block_stream_cancel_handle.clone(),
-                block,
-            )
-            .await;
+                    let start = Instant::now();
 
-            let elapsed = start.elapsed().as_secs_f64();
-            subgraph_metrics.block_processing_duration.observe(elapsed);
+                    let res = process_block(
+                        &logger,
+                        ctx.inputs.eth_adapter.cheap_clone(),
+                        &mut ctx,
+                        block_stream_cancel_handle.clone(),
+                        block,
+                    )
+                    .await;
+
+                    let elapsed = start.elapsed().as_secs_f64();
+                    subgraph_metrics.block_processing_duration.observe(elapsed);
+
+                    (Some(block_ptr), res)
+                }
+                None => match write_pending_blocks(&logger, &mut ctx).await {
+                    Ok(()) => {
+                        release_sync_slot_if_caught_up(&mut ctx);
+                        continue;
+                    }
+                    Err(e) => (None, Err(e)),
+                },
+            };
+
+            // The blocks before a failed one were processed successfully; write
+            // them so that the subgraph resumes, or fails, right at the failed block
+            let res = match res {
+                Err(e) if !matches!(e, BlockProcessingError::Canceled) => {
+                    write_pending_blocks(&logger, &mut ctx).await.and(Err(e))
+                }
+                res => res,
+            };
+
+            // The block has been written or discarded by now; a shutdown
+            // should not wait for the retry delay below
+            drop(in_progress);
 
             match res {
-                Ok((c, needs_restart)) => {
-                    ctx = c;
+                Ok(needs_restart) => {
+                    if backoff.attempt > 0 {
+                        RETRIES.remove(&id_for_err);
+                        backoff.reset();
+                    }
                     if needs_restart {
                         // Increase the restart counter
                         ctx.state.restarts += 1;
@@ -566,7 +997,7 @@ where
                         break;
                     }
                 }
-                Err(CancelableError::Cancel) => {
+                Err(BlockProcessingError::Canceled) => {
                     debug!(
                         &logger,
                         "Subgraph block stream shut down cleanly";
@@ -574,21 +1005,59 @@ where
                     );
                     return Err(());
                 }
-                // Handle unexpected stream errors by marking the subgraph as failed.
-                Err(CancelableError::Error(e)) => {
+                // The error may go away, e.g. once the Ethereum node is reachable
+                // again, so restart the block stream and retry the block later.
+                Err(BlockProcessingError::Unknown(e)) => {
+                    let delay = backoff.next_attempt();
+                    RETRIES.schedule(
+                        &id_for_err,
+                        backoff.attempt,
+                        e.to_string(),
+                        RpcErrorKind::of(&e),
+                        delay,
+                    );
                     error!(
                         &logger,
-                        "Subgraph instance failed to run: {}", e;
+                        "Subgraph instance failed with a non-deterministic error, retrying: {}", e;
                         "id" => id_for_err.to_string(),
+                        "attempt" => backoff.attempt,
+                        "retry_delay_s" => delay.as_secs(),
                         "code" => LogCode::SubgraphSyncingFailure
                     );
 
-                    let error = SubgraphError {
-                        subgraph_id: id_for_err.clone(),
-                        message: e.to_string(),
-                        block_ptr: Some(block_ptr),
-                        handler: None,
-                    };
+                    // Cancel the stream for real
+                    ctx.state
+                        .instances
+                        .write()
+                        .unwrap()
+                        .remove(&ctx.inputs.deployment_id);
+
+                    tokio::time::delay_for(delay).await;
+
+                    // Another node may have taken over the subgraph while we were waiting
+                    if block_stream_cancel_handle.is_canceled() {
+                        return Err(());
+                    }
+
+                    // And restart the subgraph
+                    break;
+                }
+                // Handle deterministic errors by marking the subgraph as failed.
+                Err(e @ BlockProcessingError::Deterministic(_))
+                | Err(e @ BlockProcessingError::FailedHandler(_))
+                | Err(e @ BlockProcessingError::QuotaExceeded(_)) => {
+                    let block_ptr =
+                        block_ptr.expect("only processing a block fails deterministically");
+                    let error = e.into_subgraph_error(&id_for_err, block_ptr);
+
+                    error!(
+                        &logger,
+                        "Subgraph instance failed to run: {}", error.message;
+                        "id" => id_for_err.to_string(),
+                        "code" => LogCode::SubgraphSyncingFailure
+                    );
+
+                    RETRIES.remove(&id_for_err);
 
                     // Set subgraph status to Failed
                     let status_ops = SubgraphDeploymentEntity::fail_operations(&id_for_err, error);
@@ -607,15 +1076,118 @@ where
     }
 }
 
-/// Processes a block and returns the updated context and a boolean flag indicating
-/// whether new dynamic data sources have been added to the subgraph.
+/// How many blocks the deployment is behind the chain head, or `None` if it
+/// finished syncing before
+fn sync_distance<S: Store + ChainStore>(
+    store: &S,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<u64>, Error> {
+    if store.is_deployment_synced(id.clone())? {
+        return Ok(None);
+    }
+    let head = store.chain_head_ptr()?.map_or(0, |ptr| ptr.number);
+    let ptr = store.block_ptr(id.clone())?.map_or(0, |ptr| ptr.number);
+    Ok(Some(head.saturating_sub(ptr)))
+}
+
+/// Wait until the deployment may sync historical blocks, see
+/// `GRAPH_SUBGRAPH_MAX_CONCURRENT_SYNCS`. Deployments that are close to the
+/// chain head start right away. Returns `false` if the deployment was
+/// stopped while it waited.
+async fn acquire_sync_slot<B, T: RuntimeHostBuilder, S>(ctx: &mut IndexingContext<B, T, S>) -> bool
+where
+    S: ChainStore + Store,
+{
+    let logger = ctx.state.logger.cheap_clone();
+    let distance = match sync_distance(&*ctx.inputs.store, &ctx.inputs.deployment_id) {
+        Ok(Some(distance)) if distance > CAUGHT_UP_DISTANCE => distance,
+        Ok(_) => return true,
+        Err(e) => {
+            warn!(
+                logger,
+                "Failed to determine how far the subgraph is behind, not waiting to sync";
+                "error" => e.to_string()
+            );
+            return true;
+        }
+    };
+
+    let network = ctx.inputs.network_name.clone();
+    let mut receiver = match ctx
+        .inputs
+        .sync_queue
+        .enqueue(&network, distance, ctx.inputs.priority)
+    {
+        Ok(permit) => {
+            ctx.state.sync_permit = Some(permit);
+            return true;
+        }
+        Err(receiver) => receiver,
+    };
+    let (syncing, waiting) = ctx.inputs.sync_queue.counts(&network);
+    info!(
+        logger,
+        "Waiting for other subgraphs to finish their initial sync";
+        "blocks_behind" => distance,
+        "syncing" => syncing,
+        "waiting" => waiting,
+    );
+
+    // The block stream has not been started yet; this guard is what stops
+    // the deployment until then
+    let guard = CancelGuard::new();
+    let handle = guard.handle();
+    ctx.state
+        .instances
+        .write()
+        .unwrap()
+        .insert(ctx.inputs.deployment_id.clone(), guard);
+
+    loop {
+        match tokio::time::timeout(SYNC_QUEUE_POLL_INTERVAL, &mut receiver).await {
+            Ok(permit) => {
+                ctx.state.sync_permit = permit.ok();
+                info!(logger, "Starting initial sync");
+                return true;
+            }
+            Err(_) if handle.is_canceled() => return false,
+            Err(_) => continue,
+        }
+    }
+}
+
+/// Let the next deployment in the sync queue start once this one is close
+/// to the chain head
+fn release_sync_slot_if_caught_up<B, T: RuntimeHostBuilder, S>(ctx: &mut IndexingContext<B, T, S>)
+where
+    S: ChainStore + Store,
+{
+    if ctx.state.sync_permit.is_none() {
+        return;
+    }
+    match sync_distance(&*ctx.inputs.store, &ctx.inputs.deployment_id) {
+        Ok(Some(distance)) if distance > CAUGHT_UP_DISTANCE => {}
+        Ok(_) => {
+            info!(ctx.state.logger, "Finished initial sync");
+            ctx.state.sync_permit = None;
+        }
+        Err(e) => debug!(
+            ctx.state.logger,
+            "Failed to determine how far the subgraph is behind";
+            "error" => e.to_string()
+        ),
+    }
+}
+
+/// Processes a block and returns a boolean flag indicating whether new dynamic data sources
+/// have been added to the subgraph.
 async fn process_block<B: BlockStreamBuilder, T: RuntimeHostBuilder, S>(
     logger: &Logger,
     eth_adapter: Arc<dyn EthereumAdapter>,
-    mut ctx: IndexingContext<B, T, S>,
+    ctx: &mut IndexingContext<B, T, S>,
     block_stream_cancel_handle: CancelHandle,
     block: EthereumBlockWithTriggers,
-) -> Result<(IndexingContext<B, T, S>, bool), CancelableError<Error>>
+) -> Result<bool, BlockProcessingError>
 where
     S: ChainStore + Store + EthereumCallCache + SubgraphDeploymentStore,
 {
@@ -638,12 +1210,16 @@ where
         );
     }
 
-    // Obtain current and new block pointer (after this block is processed)
+    let _block_trace = ctx
+        .host_metrics
+        .stopwatch
+        .start_block(&block_ptr, triggers.len());
+
     let light_block = Arc::new(block.light_block());
-    let block_ptr_after = EthereumBlockPointer::from(&block);
-    let block_ptr_for_new_data_sources = block_ptr_after.clone();
 
-    let metrics = ctx.subgraph_metrics.clone();
+    // Blocks that are far enough behind the chain head are final; the block
+    // stream only sends blocks close to the head with their full data
+    let block_is_final = matches!(block, BlockFinality::Final(_));
 
     let proof_of_indexing = if ctx
         .inputs
@@ -658,25 +1234,115 @@ where
         None
     };
 
+    // The time all handlers for this block take, which is limited by the
+    // deployment's quota
+    let mut handler_time = Duration::from_secs(0);
+
     // Process events one after the other, passing in entity operations
     // collected previously to every new event being processed
-    let (mut ctx, mut block_state) = process_triggers(
+    let block_state = process_triggers(
         &logger,
         BlockState::new(
             ctx.inputs.store.clone(),
             std::mem::take(&mut ctx.state.entity_lfu_cache),
-        ),
+        )
+        .with_fork(
+            ctx.inputs.fork.clone(),
+            block_ptr.number.saturating_sub(1) as BlockNumber,
+        )
+        .with_block_finality(block_is_final),
         proof_of_indexing.cheap_clone(),
         ctx,
         &light_block,
         triggers,
+        &mut handler_time,
+    )
+    .await?;
+    let mut block_state = process_entity_changes(
+        &logger,
+        block_state,
+        proof_of_indexing.cheap_clone(),
+        ctx,
+        &light_block,
+        block_is_final,
+        &block_stream_cancel_handle,
+        &mut handler_time,
     )
     .await?;
+    check_failed_handlers(ctx, &mut block_state)?;
 
     // If new data sources have been created, restart the subgraph after this block.
     // This is necessary to re-create the block stream.
     let needs_restart = !block_state.created_data_sources.is_empty();
+
+    // Creating data sources changes the subgraph instance and the filters in place. Remember
+    // what they looked like so that they can be restored if the block needs to be retried.
+    let snapshot = match needs_restart {
+        true => Some(DataSourcesSnapshot::take(&ctx.state)),
+        false => None,
+    };
+
+    let res = process_data_sources_and_transact(
+        &logger,
+        eth_adapter,
+        ctx,
+        block_stream_cancel_handle,
+        block,
+        light_block,
+        proof_of_indexing,
+        block_state,
+        handler_time,
+    )
+    .await;
+
+    if let (Err(_), Some(snapshot)) = (&res, snapshot) {
+        snapshot.restore(&mut ctx.state);
+    }
+
+    res.map(|()| needs_restart)
+}
+
+/// The dynamic data sources of a subgraph instance at some point in time.
+struct DataSourcesSnapshot {
+    host_count: usize,
+    filter: EthereumTriggerFilter,
+}
+
+impl DataSourcesSnapshot {
+    fn take<T: RuntimeHostBuilder>(state: &IndexingState<T>) -> Self {
+        DataSourcesSnapshot {
+            host_count: state.instance.host_count(),
+            filter: state.filter.clone(),
+        }
+    }
+
+    fn restore<T: RuntimeHostBuilder>(self, state: &mut IndexingState<T>) {
+        state.instance.truncate_hosts(self.host_count);
+        state.filter = self.filter;
+    }
+}
+
+/// Instantiates the data sources created while processing `block`, runs their handlers
+/// for the block and writes all changes to the store.
+async fn process_data_sources_and_transact<B: BlockStreamBuilder, T: RuntimeHostBuilder, S>(
+    logger: &Logger,
+    eth_adapter: Arc<dyn EthereumAdapter>,
+    ctx: &mut IndexingContext<B, T, S>,
+    block_stream_cancel_handle: CancelHandle,
+    block: BlockFinality,
+    light_block: Arc<LightEthereumBlock>,
+    proof_of_indexing: SharedProofOfIndexing,
+    mut block_state: BlockState,
+    mut handler_time: Duration,
+) -> Result<(), BlockProcessingError>
+where
+    S: ChainStore + Store + EthereumCallCache + SubgraphDeploymentStore,
+{
+    // Obtain the new block pointer (after this block is processed)
+    let block_ptr_after = EthereumBlockPointer::from(&block);
+    let block_ptr_for_new_data_sources = block_ptr_after.clone();
     let host_metrics = ctx.host_metrics.clone();
+    let created_data_sources = !block_state.created_data_sources.is_empty();
 
     // This loop will:
     // 1. Instantiate created data sources.
@@ -687,29 +1353,46 @@ where
     // first_ on the tree implied by the parent-child relationship between data sources. Only a
     // very contrived subgraph would be able to observe this.
     while !block_state.created_data_sources.is_empty() {
+        let dynamic_data_sources = ctx.state.instance.host_count() - ctx.inputs.static_data_sources
+            + block_state.created_data_sources.len();
+        ctx.inputs
+            .quotas
+            .check_dynamic_data_sources(dynamic_data_sources as u64)
+            .map_err(BlockProcessingError::QuotaExceeded)?;
+
         // Instantiate dynamic data sources, removing them from the block state.
         let (data_sources, runtime_hosts) = create_dynamic_data_sources(
             logger.clone(),
-            &mut ctx,
+            ctx,
             host_metrics.clone(),
             block_state.created_data_sources.drain(..),
         )
-        .compat_err()?;
+        .map_err(|e| BlockProcessingError::Deterministic(e.compat_err()))?;
 
         // Reprocess the triggers from this block that match the new data sources
-        let block_with_triggers = triggers_in_block(
+        let chain = EthereumChain::new(
             eth_adapter.clone(),
-            logger.cheap_clone(),
             ctx.inputs.store.clone(),
             ctx.ethrpc_metrics.clone(),
-            EthereumLogFilter::from_data_sources(data_sources.iter()),
-            EthereumCallFilter::from_data_sources(data_sources.iter()),
-            EthereumBlockFilter::from_data_sources(data_sources.iter()),
-            block.clone(),
-        )
-        .await?;
+        );
+        let block_with_triggers = chain
+            .triggers_in_block(
+                logger,
+                block.clone(),
+                &EthereumTriggerFilter::from_data_sources(data_sources.iter()),
+            )
+            .await?;
 
-        let triggers = block_with_triggers.triggers;
+        let triggers = block_with_triggers.trigger_data;
+
+        // `file/arweave` data sources have no triggers; they handle their
+        // file in the block that creates them
+        let file_hosts = data_sources
+            .iter()
+            .zip(runtime_hosts.iter())
+            .filter(|(data_source, _)| data_source.arweave_file().is_some())
+            .map(|(_, host)| host.clone())
+            .collect::<Vec<_>>();
 
         if triggers.len() == 1 {
             info!(
@@ -728,7 +1411,7 @@ where
         // and add runtimes for the data sources to the subgraph instance.
         persist_dynamic_data_sources(
             logger.clone(),
-            &mut ctx,
+            ctx,
             &mut block_state.entity_cache,
             data_sources,
             block_ptr_for_new_data_sources,
@@ -737,6 +1420,7 @@ where
         // Process the triggers in each host in the same order the
         // corresponding data sources have been created.
         for trigger in triggers.into_iter() {
+            let start = Instant::now();
             block_state = SubgraphInstance::<T>::process_trigger_in_runtime_hosts(
                 &logger,
                 &runtime_hosts,
@@ -745,16 +1429,31 @@ where
                 block_state,
                 proof_of_indexing.cheap_clone(),
             )
-            .await
-            .compat_err()?;
+            .await?;
+            add_handler_time(ctx, &mut handler_time, start.elapsed())?;
+        }
+
+        for host in file_hosts {
+            let start = Instant::now();
+            block_state = host
+                .process_file(
+                    &logger,
+                    &light_block,
+                    block_state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await
+                .map_err(|e| BlockProcessingError::from_mapping_error(e, None, &block_ptr_after))?;
+            add_handler_time(ctx, &mut handler_time, start.elapsed())?;
         }
     }
+    check_failed_handlers(ctx, &mut block_state)?;
 
     // Apply entity operations and advance the stream
 
     // Avoid writing to store if block stream has been canceled
     if block_stream_cancel_handle.is_canceled() {
-        return Err(CancelableError::Cancel);
+        return Err(BlockProcessingError::Canceled);
     }
 
     if let Some(proof_of_indexing) = proof_of_indexing {
@@ -769,37 +1468,89 @@ where
     }
 
     let section = ctx.host_metrics.stopwatch.start_section("as_modifications");
+    let BlockState {
+        entity_cache,
+        deterministic_errors,
+        ..
+    } = block_state;
     let ModificationsAndCache {
         modifications: mods,
-        entity_lfu_cache: mut cache,
-    } = block_state
-        .entity_cache
+        entity_lfu_cache: cache,
+    } = entity_cache
         .as_modifications(ctx.inputs.store.as_ref())
         .map_err(|e| {
-            CancelableError::from(format_err!(
+            BlockProcessingError::Unknown(format_err!(
                 "Error while processing block stream for a subgraph: {}",
                 e
             ))
         })?;
     section.end();
 
-    let section = ctx
-        .host_metrics
-        .stopwatch
-        .start_section("entity_cache_evict");
-    cache.evict(*ENTITY_CACHE_SIZE);
-    section.end();
-
     // Put the cache back in the ctx, asserting that the placeholder cache was not used.
     assert!(ctx.state.entity_lfu_cache.is_empty());
     ctx.state.entity_lfu_cache = cache;
 
+    check_entity_quota(ctx, &mods)?;
+
     if !mods.is_empty() {
         info!(&logger, "Applying {} entity operation(s)", mods.len());
     }
 
-    // Transact entity operations into the store and update the
-    // subgraph's block stream pointer
+    for error in deterministic_errors.iter() {
+        warn!(
+            &logger,
+            "Skipped handler that failed deterministically: {}", error.message;
+            "handler" => error.handler.as_deref().unwrap_or(""),
+            "code" => LogCode::SubgraphSyncingFailure
+        );
+    }
+
+    ctx.state.pending_writes.push(BlockModifications {
+        block_ptr: block_ptr_after,
+        mods,
+        deterministic_errors,
+    });
+
+    // Only final blocks are written together with later blocks. Blocks that
+    // created data sources are written right away since the subgraph restarts
+    // after them.
+    let batchable = match block {
+        BlockFinality::Final(_) => !created_data_sources,
+        BlockFinality::NonFinal(_) => false,
+    };
+    if !batchable || ctx.state.pending_writes.is_due() {
+        write_pending_blocks(&logger, ctx).await?;
+    }
+    Ok(())
+}
+
+/// Transact the changes of all processed blocks that have not been written
+/// yet into the store and update the subgraph's block stream pointer. When
+/// too many deployments write at once, see
+/// `GRAPH_SUBGRAPH_MAX_CONCURRENT_WRITES`, deployments with a higher priority
+/// write first
+async fn write_pending_blocks<B, T: RuntimeHostBuilder, S>(
+    logger: &Logger,
+    ctx: &mut IndexingContext<B, T, S>,
+) -> Result<(), BlockProcessingError>
+where
+    S: ChainStore + Store + EthereumCallCache + SubgraphDeploymentStore,
+{
+    let blocks = ctx.state.pending_writes.take();
+    let block_ptr_to = match blocks.last() {
+        Some(block) => block.block_ptr,
+        None => return Ok(()),
+    };
+    if blocks.len() > 1 {
+        debug!(
+            logger,
+            "Writing {} blocks in one transaction", blocks.len();
+            "block_number" => block_ptr_to.number,
+        );
+    }
+
+    let _permit = ctx.inputs.write_budget.acquire(ctx.inputs.priority).await;
+
     let _section = ctx.host_metrics.stopwatch.start_section("transact_block");
     let subgraph_id = ctx.inputs.deployment_id.clone();
     let stopwatch = ctx.host_metrics.stopwatch.clone();
@@ -808,21 +1559,34 @@ where
     match ctx
         .inputs
         .store
-        .transact_block_operations(subgraph_id, block_ptr_after, mods, stopwatch)
+        .transact_blocks_operations(subgraph_id, blocks, stopwatch)
     {
         Ok(should_migrate) => {
             let elapsed = start.elapsed().as_secs_f64();
-            metrics.block_ops_transaction_duration.observe(elapsed);
+            ctx.subgraph_metrics
+                .block_ops_transaction_duration
+                .observe(elapsed);
             if should_migrate {
                 ctx.inputs.store.migrate_subgraph_deployment(
-                    &logger,
+                    logger,
                     &ctx.inputs.deployment_id,
-                    &block_ptr_after,
+                    &block_ptr_to,
                 );
             }
-            Ok((ctx, needs_restart))
+
+            // Now that the store has all changes, the cache can be trimmed
+            let section = ctx
+                .host_metrics
+                .stopwatch
+                .start_section("entity_cache_evict");
+            ctx.state.entity_lfu_cache.evict(*ENTITY_CACHE_SIZE);
+            section.end();
+
+            Ok(())
         }
         Err(e) => {
+            // The cache has changes that were never written
+            ctx.state.entity_lfu_cache = LfuCache::new();
             Err(format_err!("Error while processing block stream for a subgraph: {}", e).into())
         }
     }
@@ -830,7 +1594,7 @@ where
 
 /// Transform the proof of indexing changes into entity updates that will be
 /// inserted when as_modifications is called.
-async fn update_proof_of_indexing(
+pub(crate) async fn update_proof_of_indexing(
     proof_of_indexing: ProofOfIndexing,
     stopwatch: &StopwatchMetrics,
     deployment_id: &SubgraphDeploymentId,
@@ -879,23 +1643,42 @@ async fn process_triggers<B: BlockStreamBuilder, T: RuntimeHostBuilder, S: Send
     logger: &Logger,
     mut block_state: BlockState,
     proof_of_indexing: SharedProofOfIndexing,
-    ctx: IndexingContext<B, T, S>,
+    ctx: &mut IndexingContext<B, T, S>,
     block: &Arc<LightEthereumBlock>,
     triggers: Vec<EthereumTrigger>,
-) -> Result<(IndexingContext<B, T, S>, BlockState), CancelableError<Error>> {
+    handler_time: &mut Duration,
+) -> Result<BlockState, BlockProcessingError> {
+    if ctx.state.parallel_triggers && !triggers.is_empty() {
+        let groups = ctx.state.instance.host_groups();
+        if groups.len() > 1 {
+            let processed = process_triggers_in_parallel(
+                logger,
+                &mut block_state,
+                &proof_of_indexing,
+                ctx,
+                block,
+                &triggers,
+                groups,
+                handler_time,
+            )
+            .await?;
+            if processed {
+                return Ok(block_state);
+            }
+            warn!(
+                logger,
+                "Processing triggers one after the other from now on, since \
+                 handlers access entity types that their data sources do not declare"
+            );
+            ctx.state.parallel_triggers = false;
+        }
+    }
+
     for trigger in triggers.into_iter() {
         let block_ptr = EthereumBlockPointer::from(block.as_ref());
         let subgraph_metrics = ctx.subgraph_metrics.clone();
-        let trigger_type = match trigger {
-            EthereumTrigger::Log(_) => TriggerType::Event,
-            EthereumTrigger::Call(_) => TriggerType::Call,
-            EthereumTrigger::Block(..) => TriggerType::Block,
-        };
-        let transaction_id = match &trigger {
-            EthereumTrigger::Log(log) => log.transaction_hash,
-            EthereumTrigger::Call(call) => call.transaction_hash,
-            EthereumTrigger::Block(..) => None,
-        };
+        let trigger_kind = trigger.kind();
+        let transaction_id = trigger.transaction_hash();
         let start = Instant::now();
         block_state = ctx
             .state
@@ -908,19 +1691,420 @@ async fn process_triggers<B: BlockStreamBuilder, T: RuntimeHostBuilder, S: Send
                 proof_of_indexing.cheap_clone(),
             )
             .await
-            .map_err(move |e| match transaction_id {
-                Some(tx_hash) => format_err!(
-                    "Failed to process trigger in block {}, transaction {:x}: {:#}",
-                    block_ptr,
-                    tx_hash,
-                    e
-                ),
-                None => format_err!("Failed to process trigger: {:#}", e),
-            })?;
-        let elapsed = start.elapsed().as_secs_f64();
-        subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_type);
+            .map_err(|e| BlockProcessingError::from_mapping_error(e, transaction_id, &block_ptr))?;
+        let elapsed = start.elapsed();
+        subgraph_metrics.observe_trigger_processing_duration(elapsed.as_secs_f64(), trigger_kind);
+        add_handler_time(ctx, handler_time, elapsed)?;
     }
-    Ok((ctx, block_state))
+    Ok(block_state)
+}
+
+/// The changes that the handlers of one group of hosts made in a block,
+/// with the positions of the handlers that created each data source and
+/// failed with each error.
+struct GroupOutcome {
+    state: BlockState,
+    poi_events: Vec<RecordedEvent>,
+    created_data_sources: Vec<(usize, usize)>,
+    deterministic_errors: Vec<(usize, usize)>,
+}
+
+/// A handler of a group that failed at `position`, which ends the
+/// processing of the group.
+struct GroupFailure {
+    position: (usize, usize),
+    error: MappingError,
+}
+
+/// Processes the triggers for each group of hosts in parallel, every group
+/// with a block state of its own that starts out with the cached entities
+/// of the group's entity types. The changes of the groups are merged into
+/// `block_state` in the order in which the handlers run when the triggers
+/// are processed one after the other, which gives the same entities, data
+/// sources and proof of indexing.
+///
+/// If a handler fails, the block fails with the error of the handler that
+/// runs first when the triggers are processed one after the other. Returns
+/// `false` if the triggers have to be processed again one after the other,
+/// in which case `block_state` only got its cached entities back.
+async fn process_triggers_in_parallel<B, T: RuntimeHostBuilder, S>(
+    logger: &Logger,
+    block_state: &mut BlockState,
+    proof_of_indexing: &SharedProofOfIndexing,
+    ctx: &IndexingContext<B, T, S>,
+    block: &Arc<LightEthereumBlock>,
+    triggers: &[EthereumTrigger],
+    groups: Vec<HostGroup<T::Host>>,
+    handler_time: &mut Duration,
+) -> Result<bool, BlockProcessingError> {
+    let _unordered_sections = ctx.host_metrics.stopwatch.unordered_sections();
+    let start = Instant::now();
+    let block_ptr = EthereumBlockPointer::from(block.as_ref());
+    let states = groups
+        .iter()
+        .map(|group| {
+            let current = block_state
+                .entity_cache
+                .split_off_current(&group.entity_types);
+            BlockState::new(block_state.entity_cache.store.clone(), current)
+                .with_fork(
+                    block_state.entity_cache.fork.clone(),
+                    block_state.entity_cache.fork_block,
+                )
+                .with_block_finality(block_state.block_is_final)
+                .with_declared_types(Arc::new(group.entity_types.clone()))
+        })
+        .collect::<Vec<_>>();
+    let outcomes = futures03::future::join_all(groups.iter().zip(states).map(|(group, state)| {
+        let proof_of_indexing = proof_of_indexing.as_ref().map(|_| {
+            Arc::new(AtomicRefCell::new(ProofOfIndexing::recording(
+                block_ptr.number,
+            )))
+        });
+        process_triggers_in_group::<T>(
+            logger,
+            state,
+            proof_of_indexing,
+            &ctx.subgraph_metrics,
+            block,
+            triggers,
+            &group.hosts,
+        )
+    }))
+    .await;
+
+    // The cached entities of the groups are lost along with their states,
+    // but the block fails, and the pending changes are written, before the
+    // cache is used again
+    let (outcomes, failures): (Vec<_>, Vec<_>) = outcomes.into_iter().partition(Result::is_ok);
+    if let Some(GroupFailure { position, error }) = failures
+        .into_iter()
+        .filter_map(Result::err)
+        .min_by_key(|failure| failure.position)
+    {
+        let transaction_id = triggers[position.0].transaction_hash();
+        return Err(BlockProcessingError::from_mapping_error(
+            error,
+            transaction_id,
+            &block_ptr,
+        ));
+    }
+
+    let outcomes = outcomes.into_iter().filter_map(Result::ok).collect();
+    let merged = merge_group_outcomes(logger, block_state, proof_of_indexing, outcomes)?;
+    if merged {
+        add_handler_time(ctx, handler_time, start.elapsed())?;
+    }
+    Ok(merged)
+}
+
+/// Merges the changes of the groups into `block_state`. Handlers of
+/// different groups must not have depended on each other's changes; if
+/// some accessed entity types that their data sources do not declare, only
+/// the cached entities of the groups are merged and `false` is returned.
+fn merge_group_outcomes(
+    logger: &Logger,
+    block_state: &mut BlockState,
+    proof_of_indexing: &SharedProofOfIndexing,
+    outcomes: Vec<GroupOutcome>,
+) -> Result<bool, Error> {
+    if outcomes
+        .iter()
+        .any(|outcome| outcome.state.entity_cache.accessed_undeclared_types())
+    {
+        for outcome in outcomes {
+            block_state
+                .entity_cache
+                .extend_current(outcome.state.entity_cache);
+        }
+        return Ok(false);
+    }
+
+    let mut created_data_sources = Vec::new();
+    let mut deterministic_errors = Vec::new();
+    let mut poi_events = Vec::new();
+    for outcome in outcomes {
+        let GroupOutcome {
+            state,
+            poi_events: events,
+            created_data_sources: created_at,
+            deterministic_errors: failed_at,
+        } = outcome;
+        block_state.entity_cache.extend(state.entity_cache)?;
+        created_data_sources.extend(created_at.into_iter().zip(state.created_data_sources));
+        deterministic_errors.extend(failed_at.into_iter().zip(state.deterministic_errors));
+        poi_events.extend(events);
+    }
+
+    // Sorting is stable, and the entries of each group are in the order of
+    // their positions already
+    created_data_sources.sort_by_key(|(position, _)| *position);
+    deterministic_errors.sort_by_key(|(position, _)| *position);
+    block_state.created_data_sources.extend(
+        created_data_sources
+            .into_iter()
+            .map(|(_, data_source)| data_source),
+    );
+    block_state
+        .deterministic_errors
+        .extend(deterministic_errors.into_iter().map(|(_, error)| error));
+    if let Some(proof_of_indexing) = proof_of_indexing {
+        proof_of_indexing.borrow_mut().replay(logger, poi_events);
+    }
+    Ok(true)
+}
+
+/// Processes `triggers` with the hosts of one group. The position of a
+/// handler is the index of its trigger in the block and the index of its
+/// host in the subgraph instance.
+async fn process_triggers_in_group<T: RuntimeHostBuilder>(
+    logger: &Logger,
+    mut state: BlockState,
+    proof_of_indexing: SharedProofOfIndexing,
+    subgraph_metrics: &SubgraphInstanceMetrics,
+    block: &Arc<LightEthereumBlock>,
+    triggers: &[EthereumTrigger],
+    hosts: &[(usize, Arc<T::Host>)],
+) -> Result<GroupOutcome, GroupFailure> {
+    let mut created_data_sources = Vec::new();
+    let mut deterministic_errors = Vec::new();
+    for (trigger_index, trigger) in triggers.iter().enumerate() {
+        let start = Instant::now();
+        let mut matched = false;
+        for (host_index, host) in hosts
+            .iter()
+            .filter(|(_, host)| matches_trigger(host.as_ref(), trigger))
+        {
+            let position = (trigger_index, *host_index);
+            if let Some(proof_of_indexing) = &proof_of_indexing {
+                proof_of_indexing.borrow_mut().set_position(position);
+            }
+            state = SubgraphInstance::<T>::process_trigger_in_runtime_hosts(
+                logger,
+                std::slice::from_ref(host),
+                block,
+                trigger.clone(),
+                state,
+                proof_of_indexing.cheap_clone(),
+            )
+            .await
+            .map_err(|error| GroupFailure { position, error })?;
+            created_data_sources.resize(state.created_data_sources.len(), position);
+            deterministic_errors.resize(state.deterministic_errors.len(), position);
+            matched = true;
+        }
+        if matched {
+            subgraph_metrics
+                .observe_trigger_processing_duration(start.elapsed().as_secs_f64(), trigger.kind());
+        }
+    }
+
+    let poi_events = proof_of_indexing
+        .map(|proof_of_indexing| proof_of_indexing.borrow_mut().take_recorded())
+        .unwrap_or_default();
+    Ok(GroupOutcome {
+        state,
+        poi_events,
+        created_data_sources,
+        deterministic_errors,
+    })
+}
+
+/// Whether `host` has a handler for `trigger`.
+fn matches_trigger<H: RuntimeHost>(host: &H, trigger: &EthereumTrigger) -> bool {
+    match trigger {
+        EthereumTrigger::Log(log) => host.matches_log(log),
+        EthereumTrigger::Call(call) => host.matches_call(call),
+        EthereumTrigger::Block(ptr, trigger_type) => host.matches_block(trigger_type, ptr.number),
+    }
+}
+
+/// Runs the handlers of `subgraph` data sources for the entities that their
+/// source deployments wrote in `block`. If a source deployment has not
+/// processed `block` yet, the block is retried later.
+async fn process_entity_changes<B, T: RuntimeHostBuilder, S>(
+    logger: &Logger,
+    mut block_state: BlockState,
+    proof_of_indexing: SharedProofOfIndexing,
+    ctx: &IndexingContext<B, T, S>,
+    block: &Arc<LightEthereumBlock>,
+    final_block: bool,
+    cancel_handle: &CancelHandle,
+    handler_time: &mut Duration,
+) -> Result<BlockState, BlockProcessingError>
+where
+    S: ChainStore + Store,
+{
+    let block_ptr = EthereumBlockPointer::from(block.as_ref());
+    for (source, entity_types) in &ctx.inputs.source_subgraphs {
+        wait_for_source(logger, ctx, source, &block_ptr, final_block, cancel_handle).await?;
+
+        let changes = ctx
+            .inputs
+            .store
+            .entity_changes_in_block(source, entity_types, block_ptr.number as BlockNumber)
+            .map_err(|e| BlockProcessingError::Unknown(e.into()))?;
+        for (entity_type, entity) in changes {
+            let start = Instant::now();
+            block_state = ctx
+                .state
+                .instance
+                .process_entity_change(
+                    logger,
+                    block,
+                    source,
+                    &entity_type,
+                    &entity,
+                    block_state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await
+                .map_err(|e| BlockProcessingError::from_mapping_error(e, None, &block_ptr))?;
+            add_handler_time(ctx, handler_time, start.elapsed())?;
+        }
+    }
+    Ok(block_state)
+}
+
+/// Wait until the deployment `source` has processed `block_ptr`. Source
+/// deployments usually index the same chain head as the deployments that
+/// read from them and are only a little ahead or behind them.
+async fn wait_for_source<B, T: RuntimeHostBuilder, S>(
+    logger: &Logger,
+    ctx: &IndexingContext<B, T, S>,
+    source: &SubgraphDeploymentId,
+    block_ptr: &EthereumBlockPointer,
+    final_block: bool,
+    cancel_handle: &CancelHandle,
+) -> Result<(), BlockProcessingError>
+where
+    S: ChainStore + Store,
+{
+    let mut last_log = Instant::now();
+    while !source_processed(&*ctx.inputs.store, source, block_ptr, final_block)? {
+        if cancel_handle.is_canceled() || ctx.inputs.shutdown.is_stopping() {
+            return Err(BlockProcessingError::Canceled);
+        }
+        if last_log.elapsed() >= SOURCE_WAIT_LOG_INTERVAL {
+            info!(
+                logger,
+                "Waiting for source subgraph to process the block";
+                "source" => source.to_string(),
+            );
+            last_log = Instant::now();
+        }
+        tokio::time::delay_for(SOURCE_POLL_INTERVAL).await;
+    }
+    Ok(())
+}
+
+/// Whether `source` has processed `block_ptr`. For blocks that may still be
+/// reorged, the block that `source` processed at that height must be
+/// `block_ptr`, otherwise we would read the changes `source` made on another
+/// branch of the chain.
+fn source_processed<S: ChainStore + Store>(
+    store: &S,
+    source: &SubgraphDeploymentId,
+    block_ptr: &EthereumBlockPointer,
+    final_block: bool,
+) -> Result<bool, BlockProcessingError> {
+    let source_ptr = match store
+        .block_ptr(source.clone())
+        .map_err(BlockProcessingError::Unknown)?
+    {
+        Some(source_ptr) if source_ptr.number >= block_ptr.number => source_ptr,
+        _ => return Ok(false),
+    };
+    if final_block {
+        return Ok(true);
+    }
+    if source_ptr.number == block_ptr.number {
+        return Ok(source_ptr.hash == block_ptr.hash);
+    }
+    let ancestor = store
+        .ancestor_block(source_ptr, source_ptr.number - block_ptr.number)
+        .map_err(BlockProcessingError::Unknown)?;
+    Ok(ancestor.map_or(false, |ancestor| {
+        ancestor.block.hash == Some(block_ptr.hash)
+    }))
+}
+
+/// Fails the block if a handler failed deterministically, unless the subgraph
+/// opted into skipping such handlers with the `nonFatalErrors` feature.
+fn check_failed_handlers<B, T: RuntimeHostBuilder, S>(
+    ctx: &IndexingContext<B, T, S>,
+    block_state: &mut BlockState,
+) -> Result<(), BlockProcessingError> {
+    if block_state.has_errors()
+        && !ctx
+            .inputs
+            .features
+            .contains(&SubgraphFeature::NonFatalErrors)
+    {
+        let error = block_state.deterministic_errors.remove(0);
+        return Err(BlockProcessingError::FailedHandler(error));
+    }
+    Ok(())
+}
+
+/// Adds the time a handler took to the handler time of the block, and fails
+/// the block if that exceeds the deployment's quota.
+fn add_handler_time<B, T: RuntimeHostBuilder, S>(
+    ctx: &IndexingContext<B, T, S>,
+    handler_time: &mut Duration,
+    elapsed: Duration,
+) -> Result<(), BlockProcessingError> {
+    *handler_time += elapsed;
+    ctx.inputs
+        .quotas
+        .check_handler_time(*handler_time)
+        .map_err(BlockProcessingError::QuotaExceeded)
+}
+
+/// Fails the block if writing `mods`, after the blocks that wait to be
+/// written, would take the deployment over its entity quota.
+fn check_entity_quota<B, T: RuntimeHostBuilder, S: Store>(
+    ctx: &IndexingContext<B, T, S>,
+    mods: &[EntityModification],
+) -> Result<(), BlockProcessingError> {
+    if ctx.inputs.quotas.max_entity_count.is_none() {
+        return Ok(());
+    }
+
+    let key = SubgraphDeploymentEntity::key(ctx.inputs.deployment_id.clone());
+    let count = ctx
+        .inputs
+        .store
+        .get(key)
+        .map_err(|e| BlockProcessingError::Unknown(e.into()))?
+        .and_then(|deployment| deployment.get("entityCount").cloned())
+        .and_then(|count| count.as_bigint())
+        .and_then(|count| u64::try_from(&count).ok());
+    // The count is not known while the store recomputes it
+    let count = match count {
+        Some(count) => count,
+        None => return Ok(()),
+    };
+
+    // The store does not count the blocks that wait to be written yet
+    let pending = ctx
+        .state
+        .pending_writes
+        .blocks
+        .iter()
+        .flat_map(|block| block.mods.iter());
+    let (inserts, removes) = pending
+        .chain(mods.iter())
+        .filter(|m| !m.entity_key().subgraph_id.is_meta())
+        .fold((0, 0), |(inserts, removes), m| match m {
+            EntityModification::Insert { .. } => (inserts + 1, removes),
+            EntityModification::Remove { .. } => (inserts, removes + 1),
+            EntityModification::Overwrite { .. } => (inserts, removes),
+        });
+    ctx.inputs
+        .quotas
+        .check_entity_count((count + inserts).saturating_sub(removes))
+        .map_err(BlockProcessingError::QuotaExceeded)
 }
 
 fn create_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
@@ -987,20 +2171,157 @@ where
         entity_cache.append(operations)?;
     }
 
-    // Merge log filters from data sources into the block stream builder
+    // Merge the filters of the data sources into the ones the block stream
+    // is built with
     ctx.state
-        .log_filter
-        .extend(EthereumLogFilter::from_data_sources(&data_sources));
+        .filter
+        .extend(EthereumTriggerFilter::from_data_sources(
+            data_sources.iter(),
+        ));
 
-    // Merge call filters from data sources into the block stream builder
-    ctx.state
-        .call_filter
-        .extend(EthereumCallFilter::from_data_sources(&data_sources));
+    Ok(())
+}
 
-    // Merge block filters from data sources into the block stream builder
-    ctx.state
-        .block_filter
-        .extend(EthereumBlockFilter::from_data_sources(&data_sources));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::mock::MockStore;
 
-    Ok(())
+    fn key(entity_type: &str, id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: SubgraphDeploymentId::new("parallelTriggers").unwrap(),
+            entity_type: entity_type.to_owned(),
+            entity_id: id.to_owned(),
+        }
+    }
+
+    fn entity(id: &str) -> Entity {
+        Entity::from(vec![("id", Value::from(id))])
+    }
+
+    fn error(message: &str) -> SubgraphError {
+        SubgraphError {
+            subgraph_id: SubgraphDeploymentId::new("parallelTriggers").unwrap(),
+            message: message.to_owned(),
+            block_ptr: None,
+            handler: None,
+        }
+    }
+
+    fn store() -> Arc<dyn Store> {
+        let mut store = MockStore::new();
+        store
+            .expect_get_many_mock()
+            .returning(|_, _| Ok(BTreeMap::new()));
+        Arc::new(store)
+    }
+
+    /// A block state that has one cached entity of each of `entity_types`,
+    /// and the outcome of a group of hosts for those types whose handlers
+    /// set `changed` and failed at `failed_at`
+    fn group(
+        block_state: &mut BlockState,
+        entity_types: &[&str],
+        changed: EntityKey,
+        failed_at: Vec<(usize, usize)>,
+    ) -> GroupOutcome {
+        let entity_types: BTreeSet<_> = entity_types.iter().map(|t| t.to_string()).collect();
+        let current = block_state.entity_cache.split_off_current(&entity_types);
+        let mut state = BlockState::new(block_state.entity_cache.store.clone(), current)
+            .with_declared_types(Arc::new(entity_types));
+        state
+            .entity_cache
+            .set(changed.clone(), entity(&changed.entity_id))
+            .unwrap();
+        for position in &failed_at {
+            state
+                .deterministic_errors
+                .push(error(&format!("{:?}", position)));
+        }
+        GroupOutcome {
+            state,
+            poi_events: vec![],
+            created_data_sources: vec![],
+            deterministic_errors: failed_at,
+        }
+    }
+
+    fn block_state(store: &Arc<dyn Store>) -> BlockState {
+        let mut current = LfuCache::new();
+        current.insert(key("Token", "cached"), Some(entity("cached")));
+        current.insert(key("Pair", "cached"), Some(entity("cached")));
+        BlockState::new(store.clone(), current)
+    }
+
+    #[test]
+    fn group_outcomes_are_merged_in_handler_order() {
+        let logger = Logger::root(slog::Discard, o!());
+        let store = store();
+        let mut block_state = block_state(&store);
+        let tokens = group(
+            &mut block_state,
+            &["Token"],
+            key("Token", "new"),
+            vec![(0, 0), (2, 0)],
+        );
+        let pairs = group(
+            &mut block_state,
+            &["Pair"],
+            key("Pair", "new"),
+            vec![(1, 1)],
+        );
+
+        let merged =
+            merge_group_outcomes(&logger, &mut block_state, &None, vec![tokens, pairs]).unwrap();
+        assert!(merged);
+
+        let messages: Vec<_> = block_state
+            .deterministic_errors
+            .iter()
+            .map(|error| error.message.as_str())
+            .collect();
+        assert_eq!(vec!["(0, 0)", "(1, 1)", "(2, 0)"], messages);
+
+        let ModificationsAndCache {
+            modifications,
+            entity_lfu_cache,
+        } = block_state.entity_cache.as_modifications(&*store).unwrap();
+        let mut changed: Vec<_> = modifications
+            .iter()
+            .map(|modification| modification.entity_key().clone())
+            .collect();
+        changed.sort();
+        assert_eq!(vec![key("Pair", "new"), key("Token", "new")], changed);
+        assert!(entity_lfu_cache.contains_key(&key("Token", "cached")));
+        assert!(entity_lfu_cache.contains_key(&key("Pair", "cached")));
+    }
+
+    #[test]
+    fn undeclared_access_only_merges_cached_entities() {
+        let logger = Logger::root(slog::Discard, o!());
+        let store = store();
+        let mut block_state = block_state(&store);
+        let tokens = group(&mut block_state, &["Token"], key("Pair", "new"), vec![]);
+        let pairs = group(
+            &mut block_state,
+            &["Pair"],
+            key("Pair", "other"),
+            vec![(0, 1)],
+        );
+
+        let merged =
+            merge_group_outcomes(&logger, &mut block_state, &None, vec![tokens, pairs]).unwrap();
+        assert!(!merged);
+        assert!(block_state.deterministic_errors.is_empty());
+
+        // The triggers are processed again one after the other, with all
+        // cached entities but none of the changes
+        let ModificationsAndCache {
+            modifications,
+            entity_lfu_cache,
+        } = block_state.entity_cache.as_modifications(&*store).unwrap();
+        assert_eq!(Vec::<EntityModification>::new(), modifications);
+        assert!(entity_lfu_cache.contains_key(&key("Token", "cached")));
+        assert!(entity_lfu_cache.contains_key(&key("Pair", "cached")));
+    }
 }
@@ -51,7 +51,7 @@ where
                   network
                   name
                   context
-                  source { address abi }
+                  source { address abi file }
                   mapping {
                     kind
                     apiVersion
@@ -60,8 +60,9 @@ where
                     entities
                     abis { name file }
                     blockHandlers { handler filter }
-                    callHandlers {  function handler }
+                    callHandlers {  function handler includeFailed }
                     eventHandlers { event handler topic0 }
+                    handler
                   }
                   templates {
                     kind
@@ -76,8 +77,9 @@ where
                       entities
                       abis { name file }
                       blockHandlers { handler filter }
-                      callHandlers { function handler }
+                      callHandlers { function handler includeFailed }
                       eventHandlers { event handler topic0 }
+                      handler
                     }
                   }
                 }
@@ -0,0 +1,140 @@
+//! Deploying subgraphs from the files that `graph build` writes, so that
+//! local development and air-gapped nodes do not need an IPFS node.
+
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::Path;
+
+use graph::prelude::*;
+
+/// The name of the manifest in the directory that `graph build` writes
+const MANIFEST: &str = "subgraph.yaml";
+
+/// Store the manifest in `dir`, and the files it references, in `store` and
+/// return the id of the deployment. Files are referenced by paths relative
+/// to the manifest; like `graph deploy` does with IPFS links, they are
+/// replaced with links to the stored files, so that the deployment id
+/// changes whenever any of the files does.
+pub(crate) fn store_subgraph_files(
+    store: &dyn SubgraphFileStore,
+    dir: &Path,
+) -> Result<SubgraphDeploymentId, failure::Error> {
+    let path = dir.join(MANIFEST);
+    let manifest = fs::read_to_string(&path)
+        .map_err(|e| format_err!("failed to read {}: {}", path.display(), e))?;
+    let mut manifest: Value = serde_yaml::from_str(&manifest)
+        .map_err(|e| format_err!("failed to parse {}: {}", path.display(), e))?;
+    store_referenced_files(store, dir, &mut manifest)?;
+
+    let manifest = serde_yaml::to_string(&manifest)?;
+    let hash = subgraph_file_hash(manifest.as_bytes());
+    store.put_subgraph_file(&hash, manifest.as_bytes())?;
+    SubgraphDeploymentId::new(hash.clone())
+        .map_err(|()| format_err!("invalid deployment id: {}", hash))
+}
+
+fn store_referenced_files(
+    store: &dyn SubgraphFileStore,
+    dir: &Path,
+    value: &mut Value,
+) -> Result<(), failure::Error> {
+    match value {
+        Value::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                if let (Some("file"), Value::String(path)) = (key.as_str(), &*value) {
+                    let path = dir.join(path);
+                    let content = fs::read(&path)
+                        .map_err(|e| format_err!("failed to read {}: {}", path.display(), e))?;
+                    let hash = subgraph_file_hash(&content);
+                    store.put_subgraph_file(&hash, &content)?;
+                    *value = link(&hash);
+                } else {
+                    store_referenced_files(store, dir, value)?;
+                }
+            }
+        }
+        Value::Sequence(values) => {
+            for value in values {
+                store_referenced_files(store, dir, value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The same kind of link to a stored file that `graph deploy` writes for a
+/// file in IPFS.
+fn link(hash: &str) -> Value {
+    let mut link = Mapping::new();
+    link.insert(
+        Value::String("/".to_owned()),
+        Value::String(format!("/ipfs/{}", hash)),
+    );
+    Value::Mapping(link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Files(Mutex<HashMap<String, Vec<u8>>>);
+
+    impl SubgraphFileStore for Files {
+        fn put_subgraph_file(&self, hash: &str, content: &[u8]) -> Result<(), StoreError> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(hash.to_owned(), content.to_vec());
+            Ok(())
+        }
+
+        fn subgraph_file(&self, hash: &str) -> Result<Option<Vec<u8>>, StoreError> {
+            Ok(self.0.lock().unwrap().get(hash).cloned())
+        }
+    }
+
+    #[test]
+    fn replaces_paths_with_links() {
+        let dir = std::env::temp_dir().join(format!("graph-local-files-{}", std::process::id()));
+        fs::create_dir_all(dir.join("Token")).unwrap();
+        fs::write(dir.join("schema.graphql"), "type Token @entity { id: ID! }").unwrap();
+        fs::write(dir.join("Token/Token.wasm"), b"\0asm").unwrap();
+        fs::write(
+            dir.join(MANIFEST),
+            "specVersion: 0.0.1
+schema:
+  file: schema.graphql
+dataSources:
+  - kind: ethereum/contract
+    mapping:
+      file: Token/Token.wasm
+",
+        )
+        .unwrap();
+
+        let files = Files::default();
+        let id = store_subgraph_files(&files, &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let manifest = files.subgraph_file(&id.to_string()).unwrap().unwrap();
+        let manifest: Value = serde_yaml::from_slice(&manifest).unwrap();
+        let schema = subgraph_file_hash(b"type Token @entity { id: ID! }");
+        let mapping = subgraph_file_hash(b"\0asm");
+        assert_eq!(
+            manifest["schema"]["file"]["/"],
+            Value::String(format!("/ipfs/{}", schema))
+        );
+        assert_eq!(
+            manifest["dataSources"][0]["mapping"]["file"]["/"],
+            Value::String(format!("/ipfs/{}", mapping))
+        );
+        assert_eq!(
+            files.subgraph_file(&mapping).unwrap(),
+            Some(b"\0asm".to_vec())
+        );
+    }
+}
@@ -1,11 +1,24 @@
+mod dry_run;
+mod failover;
+mod fork;
+mod import;
 mod instance;
 mod instance_manager;
 mod loader;
+mod local_files;
 mod provider;
+mod quotas;
 mod registrar;
+mod replay;
+mod shutdown;
+mod sync_queue;
 
+pub use self::dry_run::TriggerDryRun;
+pub use self::failover::NodeFailover;
 pub use self::instance::SubgraphInstance;
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::loader::DataSourceLoader;
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::SubgraphRegistrar;
+pub use self::replay::{replay_blocks, ReplayCallCache, ReplayedBlock, ReplayedCall};
+pub use self::shutdown::IndexingShutdown;
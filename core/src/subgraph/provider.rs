@@ -11,7 +11,9 @@ use graph::prelude::{
     SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait, *,
 };
 
-use crate::subgraph::registrar::IPFS_SUBGRAPH_LOADING_TIMEOUT;
+use crate::subgraph::registrar::{
+    IPFS_SUBGRAPH_LOADING_MAX_FILE_SIZE, IPFS_SUBGRAPH_LOADING_TIMEOUT,
+};
 use crate::DataSourceLoader;
 
 pub struct SubgraphAssignmentProvider<L, Q, S> {
@@ -51,6 +53,7 @@ where
                     .as_ref()
                     .clone()
                     .with_timeout(*IPFS_SUBGRAPH_LOADING_TIMEOUT)
+                    .with_max_file_size(*IPFS_SUBGRAPH_LOADING_MAX_FILE_SIZE)
                     .with_retries(),
             ),
             subgraphs_running: Arc::new(Mutex::new(HashSet::new())),
@@ -119,6 +122,7 @@ where
             info!(logger, "Successfully resolved subgraph files using IPFS");
 
             // Add dynamic data sources to the subgraph
+            let dynamic_data_sources = data_sources.len();
             subgraph.data_sources.extend(data_sources);
 
             // If subgraph ID already in set
@@ -153,7 +157,10 @@ where
             if let Err(e) = self_clone
                 .event_sink
                 .clone()
-                .send(SubgraphAssignmentProviderEvent::SubgraphStart(subgraph))
+                .send(SubgraphAssignmentProviderEvent::SubgraphStart {
+                    manifest: subgraph,
+                    dynamic_data_sources,
+                })
                 .compat()
                 .await
             {
@@ -0,0 +1,110 @@
+use lazy_static::lazy_static;
+use std::env;
+use std::time::Duration;
+
+lazy_static! {
+    static ref MAX_ENTITY_COUNT: Option<u64> = quota("GRAPH_SUBGRAPH_MAX_ENTITY_COUNT");
+    static ref MAX_DYNAMIC_DATA_SOURCES: Option<u64> =
+        quota("GRAPH_SUBGRAPH_MAX_DYNAMIC_DATA_SOURCES");
+    /// In seconds
+    static ref MAX_HANDLER_TIME_PER_BLOCK: Option<u64> =
+        quota("GRAPH_SUBGRAPH_MAX_HANDLER_TIME_PER_BLOCK");
+}
+
+fn quota(var: &str) -> Option<u64> {
+    env::var(var)
+        .ok()
+        .map(|s| s.parse().unwrap_or_else(|_| panic!("invalid {}", var)))
+}
+
+/// Limits on what a single deployment may use, so that one subgraph can not
+/// take over the node. A deployment that exceeds one of them fails with an
+/// error that names the limit; it resumes when it is restarted after the
+/// limit has been raised.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ResourceQuotas {
+    pub max_entity_count: Option<u64>,
+    pub max_dynamic_data_sources: Option<u64>,
+    pub max_handler_time_per_block: Option<Duration>,
+}
+
+impl ResourceQuotas {
+    pub fn from_env() -> Self {
+        ResourceQuotas {
+            max_entity_count: *MAX_ENTITY_COUNT,
+            max_dynamic_data_sources: *MAX_DYNAMIC_DATA_SOURCES,
+            max_handler_time_per_block: MAX_HANDLER_TIME_PER_BLOCK.map(Duration::from_secs),
+        }
+    }
+
+    /// Check the number of entities the deployment would have after
+    /// writing a block.
+    pub fn check_entity_count(&self, count: u64) -> Result<(), String> {
+        match self.max_entity_count {
+            Some(max) if count > max => Err(format!(
+                "Subgraph exceeded its quota of {} entities with {} entities \
+                 (GRAPH_SUBGRAPH_MAX_ENTITY_COUNT)",
+                max, count
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the number of data sources the deployment created from
+    /// templates, including those that a block is about to create.
+    pub fn check_dynamic_data_sources(&self, count: u64) -> Result<(), String> {
+        match self.max_dynamic_data_sources {
+            Some(max) if count > max => Err(format!(
+                "Subgraph exceeded its quota of {} dynamic data sources with {} \
+                 (GRAPH_SUBGRAPH_MAX_DYNAMIC_DATA_SOURCES)",
+                max, count
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the time all handlers for one block have taken so far.
+    pub fn check_handler_time(&self, time: Duration) -> Result<(), String> {
+        match self.max_handler_time_per_block {
+            Some(max) if time > max => Err(format!(
+                "Subgraph exceeded its quota of {}s of handler time per block with {:.1}s \
+                 (GRAPH_SUBGRAPH_MAX_HANDLER_TIME_PER_BLOCK)",
+                max.as_secs(),
+                time.as_secs_f64()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unset_quotas_allow_everything() {
+        let quotas = ResourceQuotas::default();
+        assert!(quotas.check_entity_count(u64::MAX).is_ok());
+        assert!(quotas.check_dynamic_data_sources(u64::MAX).is_ok());
+        assert!(quotas
+            .check_handler_time(Duration::from_secs(u64::MAX))
+            .is_ok());
+    }
+
+    #[test]
+    fn quotas_are_inclusive() {
+        let quotas = ResourceQuotas {
+            max_entity_count: Some(10),
+            max_dynamic_data_sources: Some(2),
+            max_handler_time_per_block: Some(Duration::from_secs(5)),
+        };
+        assert!(quotas.check_entity_count(10).is_ok());
+        assert!(quotas.check_entity_count(11).is_err());
+        assert!(quotas.check_dynamic_data_sources(2).is_ok());
+        assert!(quotas.check_dynamic_data_sources(3).is_err());
+        assert!(quotas.check_handler_time(Duration::from_secs(5)).is_ok());
+        assert!(quotas
+            .check_handler_time(Duration::from_millis(5001))
+            .is_err());
+    }
+}
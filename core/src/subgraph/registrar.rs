@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{env, iter};
 
@@ -10,11 +11,15 @@ use graph::data::subgraph::schema::{
     generate_entity_id, SubgraphDeploymentAssignmentEntity, SubgraphDeploymentEntity,
     SubgraphEntity, SubgraphVersionEntity, TypedEntity,
 };
+use graph::data::subgraph::SubgraphManifestValidationWarning;
 use graph::prelude::{
     CreateSubgraphResult, SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait,
     SubgraphRegistrar as SubgraphRegistrarTrait, *,
 };
 
+use crate::subgraph::import::parse_entities;
+use crate::subgraph::local_files::store_subgraph_files;
+
 lazy_static! {
     // The timeout for IPFS requests in seconds
     pub static ref IPFS_SUBGRAPH_LOADING_TIMEOUT: Duration = Duration::from_secs(
@@ -23,6 +28,13 @@ lazy_static! {
             .parse::<u64>()
             .expect("invalid IPFS subgraph loading timeout")
     );
+
+    // The size limit for files that subgraphs are loaded from, in bytes
+    pub static ref IPFS_SUBGRAPH_LOADING_MAX_FILE_SIZE: Option<u64> =
+        env::var("GRAPH_MAX_IPFS_SUBGRAPH_FILE_BYTES")
+            .or_else(|_| env::var("GRAPH_MAX_IPFS_FILE_BYTES"))
+            .ok()
+            .map(|size| size.parse().expect("invalid IPFS subgraph file size limit"));
 }
 
 pub struct SubgraphRegistrar<L, P, S, CS> {
@@ -35,6 +47,7 @@ pub struct SubgraphRegistrar<L, P, S, CS> {
     ethereum_networks: EthereumNetworks,
     node_id: NodeId,
     version_switching_mode: SubgraphVersionSwitchingMode,
+    placer: Option<Arc<dyn DeploymentPlacer>>,
     assignment_event_stream_cancel_guard: CancelGuard, // cancels on drop
 }
 
@@ -54,6 +67,7 @@ where
         ethereum_networks: EthereumNetworks,
         node_id: NodeId,
         version_switching_mode: SubgraphVersionSwitchingMode,
+        placer: Option<Arc<dyn DeploymentPlacer>>,
     ) -> Self {
         let logger = logger_factory.component_logger("SubgraphRegistrar", None);
         let logger_factory = logger_factory.with_parent(logger.clone());
@@ -66,6 +80,7 @@ where
                     .as_ref()
                     .clone()
                     .with_timeout(*IPFS_SUBGRAPH_LOADING_TIMEOUT)
+                    .with_max_file_size(*IPFS_SUBGRAPH_LOADING_MAX_FILE_SIZE)
                     .with_retries(),
             ),
             provider,
@@ -74,6 +89,7 @@ where
             ethereum_networks,
             node_id,
             version_switching_mode,
+            placer,
             assignment_event_stream_cancel_guard: CancelGuard::new(),
         }
     }
@@ -194,6 +210,9 @@ where
                                         if let Some(entity) = entity_opt {
                                             if entity.get("nodeId")
                                                 == Some(&node_id.to_string().into())
+                                                && !SubgraphDeploymentAssignmentEntity::is_paused(
+                                                    &entity,
+                                                )
                                             {
                                                 // Start subgraph on this node
                                                 Box::new(stream::once(Ok(AssignmentEvent::Add {
@@ -201,7 +220,8 @@ where
                                                     node_id: node_id.clone(),
                                                 })))
                                             } else {
-                                                // Ensure it is removed from this node
+                                                // Ensure it is removed from this node, or
+                                                // stopped if it is paused
                                                 Box::new(stream::once(Ok(
                                                     AssignmentEvent::Remove {
                                                         subgraph_id: subgraph_hash,
@@ -232,6 +252,30 @@ where
             .flatten()
     }
 
+    /// Pick the node for a new deployment of `name` on `network`. Among the
+    /// nodes the placement rules allow, the one with the fewest assigned
+    /// deployments is chosen. Without a matching rule, deployments go to
+    /// this node.
+    fn place(&self, name: &SubgraphName, network: &str) -> Result<NodeId, SubgraphRegistrarError> {
+        let nodes = match &self.placer {
+            Some(placer) => placer
+                .place(&name.to_string(), network)
+                .map_err(SubgraphRegistrarError::PlacementError)?,
+            None => None,
+        };
+        let nodes = match nodes {
+            Some(nodes) => nodes,
+            None => return Ok(self.node_id.clone()),
+        };
+
+        least_loaded_node(self.store.as_ref(), nodes)?.ok_or_else(|| {
+            SubgraphRegistrarError::PlacementError(format!(
+                "no index nodes to assign {} on {} to",
+                name, network
+            ))
+        })
+    }
+
     fn start_assigned_subgraphs(&self) -> impl Future<Item = (), Error = Error> {
         let provider = self.provider.clone();
         let logger = self.logger.clone();
@@ -245,6 +289,7 @@ where
             .and_then(move |assignment_entities| {
                 assignment_entities
                     .into_iter()
+                    .filter(|entity| !SubgraphDeploymentAssignmentEntity::is_paused(entity))
                     .map(|assignment_entity| {
                         // Parse as subgraph hash
                         assignment_entity.id().and_then(|id| {
@@ -279,33 +324,44 @@ where
     }
 }
 
-#[async_trait]
-impl<L, P, S, CS> SubgraphRegistrarTrait for SubgraphRegistrar<L, P, S, CS>
+/// The metadata operations that deploy a subgraph version, worked out
+/// without applying them
+struct VersionOperations {
+    ops: Vec<MetadataOperation>,
+    /// The schema and indexer hints of the deployment if it does not exist
+    /// yet
+    new_deployment: Option<(Schema, IndexerHints)>,
+    validation_warnings: Vec<SubgraphManifestValidationWarning>,
+}
+
+impl<L, P, S, CS> SubgraphRegistrar<L, P, S, CS>
 where
     L: LinkResolver,
     P: SubgraphAssignmentProviderTrait,
-    S: Store + SubgraphDeploymentStore,
+    S: Store + SubgraphDeploymentStore + SubgraphFileStore,
     CS: ChainStore,
 {
-    async fn create_subgraph(
-        &self,
-        name: SubgraphName,
-    ) -> Result<CreateSubgraphResult, SubgraphRegistrarError> {
-        create_subgraph(&self.logger, self.store.clone(), name)
+    fn check_deployed(&self, hash: &SubgraphDeploymentId) -> Result<(), SubgraphRegistrarError> {
+        if self.store.is_deployed(hash)? {
+            Ok(())
+        } else {
+            Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()))
+        }
     }
 
-    async fn create_subgraph_version(
+    /// Resolve and validate the manifest of `hash`, and work out the
+    /// operations that deploy it as the new version of `name`
+    async fn prepare_subgraph_version(
         &self,
+        logger: &Logger,
         name: SubgraphName,
         hash: SubgraphDeploymentId,
-        node_id: NodeId,
-    ) -> Result<(), SubgraphRegistrarError> {
-        let logger = self.logger_factory.subgraph_logger(&hash);
-
+        node_id: Option<NodeId>,
+    ) -> Result<VersionOperations, SubgraphRegistrarError> {
         let unvalidated = UnvalidatedSubgraphManifest::resolve(
             hash.to_ipfs_link(),
             self.resolver.clone(),
-            &logger,
+            logger,
         )
         .map_err(SubgraphRegistrarError::ResolveError)
         .await?;
@@ -314,51 +370,206 @@ where
             .validate(self.store.clone())
             .map_err(SubgraphRegistrarError::ManifestValidationError)?;
 
+        // A deployment that takes over the storage of the current version
+        // is as far along as that version and replaces it right away
+        let (version_switching_mode, migration_ops) = match &manifest.migrate {
+            Some(migration) => (
+                SubgraphVersionSwitchingMode::Instant,
+                check_migration_base(self.store.clone(), &name, &migration.base)?,
+            ),
+            None => (self.version_switching_mode, vec![]),
+        };
+
         let network_name = manifest.network_name();
 
         let chain_store = self.chain_stores.get(&network_name).ok_or(
             SubgraphRegistrarError::NetworkNotSupported(network_name.clone()),
         )?;
 
+        let node_id = match node_id {
+            Some(node_id) => node_id,
+            None => self.place(&name, &network_name)?,
+        };
+
+        // The placement rules can have the deployment indexed with the
+        // providers of an alias of its network
+        let network_alias = self
+            .placer
+            .as_ref()
+            .and_then(|placer| placer.network_alias(&name.to_string(), &network_name));
+        let provider_network = network_alias.clone().unwrap_or(network_name);
+
         let subgraph_eth_requirements = manifest.required_ethereum_capabilities();
 
         let ethereum_adapter = self
             .ethereum_networks
-            .adapter_with_capabilities(network_name.clone(), &subgraph_eth_requirements)
+            .adapter_with_capabilities(provider_network.clone(), &subgraph_eth_requirements)
             .map_err(|_| {
                 SubgraphRegistrarError::SubgraphNetworkRequirementsNotSupported(
-                    network_name,
+                    provider_network,
                     subgraph_eth_requirements,
                 )
             })?;
 
-        let manifest_id = manifest.id.clone();
-        create_subgraph_version(
-            &logger,
+        let (ops, new_deployment) = subgraph_version_operations(
+            logger,
             self.store.clone(),
             chain_store.clone(),
             ethereum_adapter.clone(),
-            name.clone(),
+            name,
             manifest,
             node_id,
-            self.version_switching_mode,
+            network_alias,
+            version_switching_mode,
         )
         .compat()
         .await?;
+        // Check the migration base before anything changes its assignment
+        let ops = migration_ops.into_iter().chain(ops).collect();
+
+        Ok(VersionOperations {
+            ops,
+            new_deployment,
+            validation_warnings,
+        })
+    }
+
+    /// Work out the metadata operations for one operation of a batch, the
+    /// schema and indexer hints of the deployment it creates, if any, and
+    /// what it will have done once the operations are applied
+    async fn prepare_operation(
+        &self,
+        operation: SubgraphOperation,
+    ) -> Result<
+        (
+            Vec<MetadataOperation>,
+            Option<(Schema, IndexerHints)>,
+            SubgraphOperationOutcome,
+        ),
+        SubgraphRegistrarError,
+    > {
+        let store = self.store.clone();
+        let done = SubgraphOperationOutcome::Done;
+        Ok(match operation {
+            SubgraphOperation::Create { name } => {
+                let (ops, result) = create_subgraph_operations(&self.logger, store, name)?;
+                (ops, None, SubgraphOperationOutcome::Created(result))
+            }
+            SubgraphOperation::Deploy {
+                name,
+                hash,
+                node_id,
+            } => {
+                let logger = self.logger_factory.subgraph_logger(&hash);
+                let version = self
+                    .prepare_subgraph_version(&logger, name, hash, node_id)
+                    .await?;
+                (version.ops, version.new_deployment, done)
+            }
+            SubgraphOperation::Remove { name } => (
+                remove_subgraph_operations(&self.logger, store, name)?,
+                None,
+                done,
+            ),
+            SubgraphOperation::Reassign { hash, node_id } => (
+                reassign_subgraph_operations(store, hash, node_id)?,
+                None,
+                done,
+            ),
+            SubgraphOperation::Pause { hash } => {
+                (subgraph_paused_operations(store, hash, true)?, None, done)
+            }
+            SubgraphOperation::Resume { hash } => {
+                (subgraph_paused_operations(store, hash, false)?, None, done)
+            }
+            SubgraphOperation::Promote { name, force } => (
+                promote_subgraph_operations(&self.logger, store, name, force)?,
+                None,
+                done,
+            ),
+        })
+    }
+}
+
+#[async_trait]
+impl<L, P, S, CS> SubgraphRegistrarTrait for SubgraphRegistrar<L, P, S, CS>
+where
+    L: LinkResolver,
+    P: SubgraphAssignmentProviderTrait,
+    S: Store + SubgraphDeploymentStore + SubgraphFileStore,
+    CS: ChainStore,
+{
+    async fn create_subgraph(
+        &self,
+        name: SubgraphName,
+    ) -> Result<CreateSubgraphResult, SubgraphRegistrarError> {
+        let (ops, result) =
+            create_subgraph_operations(&self.logger, self.store.clone(), name.clone())?;
+        self.store.apply_metadata_operations(ops)?;
+        debug!(self.logger, "Created subgraph"; "subgraph_name" => name.to_string());
+        Ok(result)
+    }
+
+    async fn create_subgraph_version(
+        &self,
+        name: SubgraphName,
+        hash: SubgraphDeploymentId,
+        node_id: Option<NodeId>,
+    ) -> Result<(), SubgraphRegistrarError> {
+        let logger = self.logger_factory.subgraph_logger(&hash);
+
+        let VersionOperations {
+            ops,
+            new_deployment,
+            validation_warnings,
+        } = self
+            .prepare_subgraph_version(&logger, name.clone(), hash.clone(), node_id)
+            .await?;
+
+        // Apply the subgraph versioning and deployment operations,
+        // creating a new subgraph deployment if one doesn't exist.
+        match &new_deployment {
+            Some((schema, hints)) => self.store.create_subgraph_deployment(schema, hints, ops),
+            None => self.store.apply_metadata_operations(ops),
+        }
+        .map_err(SubgraphRegistrarError::SubgraphDeploymentError)?;
 
         debug!(
             &logger,
             "Wrote new subgraph version to store";
             "subgraph_name" => name.to_string(),
-            "subgraph_hash" => manifest_id.to_string(),
+            "subgraph_hash" => hash.to_string(),
             "validation_warnings" => format!("{:?}", validation_warnings),
         );
 
         Ok(())
     }
 
+    async fn create_subgraph_version_from_files(
+        &self,
+        name: SubgraphName,
+        directory: PathBuf,
+        node_id: Option<NodeId>,
+    ) -> Result<SubgraphDeploymentId, SubgraphRegistrarError> {
+        let hash = store_subgraph_files(self.store.as_ref(), &directory)
+            .map_err(|e| SubgraphRegistrarError::LocalFilesError(e.to_string()))?;
+        info!(
+            self.logger,
+            "Stored subgraph files in the database";
+            "directory" => directory.display().to_string(),
+            "subgraph_hash" => hash.to_string(),
+        );
+
+        self.create_subgraph_version(name, hash.clone(), node_id)
+            .await?;
+        Ok(hash)
+    }
+
     async fn remove_subgraph(&self, name: SubgraphName) -> Result<(), SubgraphRegistrarError> {
-        remove_subgraph(&self.logger, self.store.clone(), name)
+        let ops = remove_subgraph_operations(&self.logger, self.store.clone(), name.clone())?;
+        self.store.apply_metadata_operations(ops)?;
+        debug!(self.logger, "Removed subgraph"; "subgraph_name" => name.to_string());
+        Ok(())
     }
 
     async fn reassign_subgraph(
@@ -368,6 +579,215 @@ where
     ) -> Result<(), SubgraphRegistrarError> {
         reassign_subgraph(self.store.clone(), hash, node_id)
     }
+
+    async fn pause_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError> {
+        let ops = subgraph_paused_operations(self.store.clone(), hash, true)?;
+        if !ops.is_empty() {
+            self.store.apply_metadata_operations(ops)?;
+        }
+        Ok(())
+    }
+
+    async fn resume_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError> {
+        let ops = subgraph_paused_operations(self.store.clone(), hash, false)?;
+        if !ops.is_empty() {
+            self.store.apply_metadata_operations(ops)?;
+        }
+        Ok(())
+    }
+
+    async fn set_subgraph_priority(
+        &self,
+        hash: SubgraphDeploymentId,
+        priority: DeploymentPriority,
+    ) -> Result<(), SubgraphRegistrarError> {
+        set_subgraph_priority(self.store.clone(), hash.clone(), priority)?;
+        info!(self.logger, "Set priority of deployment";
+              "subgraph_id" => hash.to_string(),
+              "priority" => priority.as_str());
+        Ok(())
+    }
+
+    async fn promote_subgraph(
+        &self,
+        name: SubgraphName,
+        force: bool,
+    ) -> Result<(), SubgraphRegistrarError> {
+        let ops =
+            promote_subgraph_operations(&self.logger, self.store.clone(), name.clone(), force)?;
+        self.store.apply_metadata_operations(ops)?;
+        info!(self.logger, "Promoted pending version of subgraph";
+              "subgraph_name" => name.to_string());
+        Ok(())
+    }
+
+    async fn apply_batch(
+        &self,
+        operations: Vec<SubgraphOperation>,
+    ) -> Vec<Result<SubgraphOperationOutcome, SubgraphRegistrarError>> {
+        let conflicts = batch_conflicts(&operations);
+        let mut prepared = Vec::with_capacity(operations.len());
+        for (operation, conflict) in operations.into_iter().zip(conflicts) {
+            prepared.push(match conflict {
+                Some(conflict) => Err(SubgraphRegistrarError::BatchNotApplied(conflict)),
+                None => self.prepare_operation(operation).await,
+            });
+        }
+
+        if let Some(failed) = prepared.iter().position(|result| result.is_err()) {
+            return prepared
+                .into_iter()
+                .map(|result| {
+                    result.and_then(|_| {
+                        Err(SubgraphRegistrarError::BatchNotApplied(format!(
+                            "operation {} of the batch failed",
+                            failed + 1
+                        )))
+                    })
+                })
+                .collect();
+        }
+
+        let mut ops = vec![];
+        let mut deployments = vec![];
+        let mut outcomes = vec![];
+        for (operation_ops, deployment, outcome) in prepared.into_iter().map(Result::unwrap) {
+            ops.extend(operation_ops);
+            deployments.extend(deployment);
+            outcomes.push(outcome);
+        }
+        match self.store.create_subgraph_deployments(&deployments, ops) {
+            Ok(()) => {
+                info!(self.logger, "Applied batch of subgraph operations";
+                      "operations" => outcomes.len());
+                outcomes.into_iter().map(Ok).collect()
+            }
+            Err(e) => outcomes
+                .iter()
+                .map(|_| {
+                    Err(SubgraphRegistrarError::BatchNotApplied(format!(
+                        "failed to write the batch: {}",
+                        e
+                    )))
+                })
+                .collect(),
+        }
+    }
+
+    async fn set_query_allowlist(
+        &self,
+        hash: SubgraphDeploymentId,
+        enabled: bool,
+    ) -> Result<(), SubgraphRegistrarError> {
+        self.check_deployed(&hash)?;
+        self.store.set_query_allowlist_enabled(&hash, enabled)?;
+        info!(self.logger, "Changed query allowlist mode";
+              "subgraph_id" => hash.to_string(), "enabled" => enabled);
+        Ok(())
+    }
+
+    async fn allow_query(
+        &self,
+        hash: SubgraphDeploymentId,
+        query: String,
+    ) -> Result<String, SubgraphRegistrarError> {
+        self.check_deployed(&hash)?;
+        let document = graphql_parser::parse_query(&query)
+            .map_err(|e| SubgraphRegistrarError::InvalidQuery(e.to_string()))?;
+        let query_hash = query_hash(&document);
+        self.store
+            .allow_query(&hash, &query_hash, &document.to_string())?;
+        Ok(query_hash)
+    }
+
+    async fn disallow_query(
+        &self,
+        hash: SubgraphDeploymentId,
+        query_hash: String,
+    ) -> Result<bool, SubgraphRegistrarError> {
+        self.check_deployed(&hash)?;
+        Ok(self.store.disallow_query(&hash, &query_hash)?)
+    }
+
+    async fn allowed_queries(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<Vec<AllowedQuery>, SubgraphRegistrarError> {
+        self.check_deployed(&hash)?;
+        Ok(self.store.allowed_queries(&hash)?)
+    }
+
+    async fn import_entities(
+        &self,
+        hash: SubgraphDeploymentId,
+        entity_type: String,
+        format: ImportFormat,
+        data: String,
+        block: BlockNumber,
+    ) -> Result<usize, SubgraphRegistrarError> {
+        self.check_deployed(&hash)?;
+        let schema = self.store.input_schema(&hash)?;
+        let entities = parse_entities(&schema, &entity_type, format, &data)
+            .map_err(SubgraphRegistrarError::InvalidImport)?;
+        let count = entities.len();
+        self.store.import_entities(&hash, block, entities)?;
+        info!(self.logger, "Imported entities";
+              "subgraph_id" => hash.to_string(),
+              "entity_type" => &entity_type,
+              "block" => block,
+              "count" => count);
+        Ok(count)
+    }
+}
+
+/// For each of `operations`, why it conflicts with an earlier operation of
+/// the batch, if it does. Operations are prepared against the state before
+/// the batch, and would not see what earlier operations on the same
+/// subgraph name or deployment change.
+fn batch_conflicts(operations: &[SubgraphOperation]) -> Vec<Option<String>> {
+    let mut names: HashMap<String, usize> = HashMap::new();
+    let mut hashes: HashMap<SubgraphDeploymentId, usize> = HashMap::new();
+    operations
+        .iter()
+        .enumerate()
+        .map(|(i, operation)| {
+            let (name, hash) = match operation {
+                SubgraphOperation::Create { name }
+                | SubgraphOperation::Remove { name }
+                | SubgraphOperation::Promote { name, .. } => (Some(name), None),
+                SubgraphOperation::Deploy { name, hash, .. } => (Some(name), Some(hash)),
+                SubgraphOperation::Reassign { hash, .. }
+                | SubgraphOperation::Pause { hash }
+                | SubgraphOperation::Resume { hash } => (None, Some(hash)),
+            };
+            let mut conflict = None;
+            if let Some(name) = name {
+                if let Some(j) = names.insert(name.to_string(), i) {
+                    conflict = Some(format!(
+                        "subgraph {} is also used by operation {} of the batch",
+                        name,
+                        j + 1
+                    ));
+                }
+            }
+            if let Some(hash) = hash {
+                if let Some(j) = hashes.insert(hash.clone(), i) {
+                    conflict = Some(format!(
+                        "deployment {} is also used by operation {} of the batch",
+                        hash,
+                        j + 1
+                    ));
+                }
+            }
+            conflict
+        })
+        .collect()
 }
 
 async fn handle_assignment_event(
@@ -431,11 +851,11 @@ async fn start_subgraph(
     }
 }
 
-fn create_subgraph(
+fn create_subgraph_operations(
     logger: &Logger,
     store: Arc<impl Store>,
     name: SubgraphName,
-) -> Result<CreateSubgraphResult, SubgraphRegistrarError> {
+) -> Result<(Vec<MetadataOperation>, CreateSubgraphResult), SubgraphRegistrarError> {
     let mut ops = vec![];
 
     // Check if this subgraph already exists
@@ -470,11 +890,7 @@ fn create_subgraph(
             .map(|op| op.into()),
     );
 
-    store.apply_metadata_operations(ops)?;
-
-    debug!(logger, "Created subgraph"; "subgraph_name" => name.to_string());
-
-    Ok(CreateSubgraphResult { id: entity_id })
+    Ok((ops, CreateSubgraphResult { id: entity_id }))
 }
 
 /// Resolves the subgraph's earliest block and the manifest's graft base block
@@ -551,7 +967,9 @@ fn resolve_subgraph_chain_blocks(
 struct SubraphVersionUpdatingMetadata {
     subgraph_entity_id: String,
     version_entity_id: String,
-    current_is_synced: bool,
+    /// Whether the new version becomes the pending version rather than the
+    /// current one
+    new_version_is_pending: bool,
     current_version_id_opt: Option<String>,
     pending_version_id_opt: Option<String>,
     version_summaries_before: Vec<SubgraphVersionSummary>,
@@ -627,6 +1045,13 @@ fn get_version_ids_and_summaries(
 
     let version_entity_id = generate_entity_id();
 
+    let keep_current = match version_switching_mode {
+        SubgraphVersionSwitchingMode::Instant => false,
+        SubgraphVersionSwitchingMode::Synced => current_is_synced,
+        SubgraphVersionSwitchingMode::Manual => true,
+    };
+    let new_version_is_pending = keep_current && current_version_id_opt.is_some();
+
     // Simulate the creation of the new version and updating of Subgraph.pending/current
     let version_summaries_after = match version_switching_mode {
         SubgraphVersionSwitchingMode::Instant => {
@@ -658,9 +1083,10 @@ fn get_version_ids_and_summaries(
 
             version_summaries_after
         }
-        SubgraphVersionSwitchingMode::Synced => {
+        SubgraphVersionSwitchingMode::Synced | SubgraphVersionSwitchingMode::Manual => {
             // There is a current version. Depending on whether it is synced
-            // or not, make the new version the pending or the current version
+            // or not, make the new version the pending or the current version.
+            // With manual promotion, the current version stays current
             if current_version_id_opt.is_some() {
                 // Previous pending version (if there was one) is no longer pending
                 // Previous current version if it's not fully synced is no
@@ -672,7 +1098,7 @@ fn get_version_ids_and_summaries(
                         if Some(&version_summary.id) == pending_version_id_opt.as_ref() {
                             version_summary.pending = false;
                         }
-                        if !current_is_synced
+                        if !keep_current
                             && Some(&version_summary.id) == current_version_id_opt.as_ref()
                         {
                             // We will make the new version the current version
@@ -688,7 +1114,7 @@ fn get_version_ids_and_summaries(
                 // already has a current version, the new version becomes the
                 // pending version if the current version is synced,
                 // and replaces the current version if the current version is still syncing
-                let (pending, current) = if current_is_synced {
+                let (pending, current) = if keep_current {
                     (true, false)
                 } else {
                     (false, true)
@@ -724,7 +1150,7 @@ fn get_version_ids_and_summaries(
     Ok(SubraphVersionUpdatingMetadata {
         subgraph_entity_id,
         version_entity_id,
-        current_is_synced,
+        new_version_is_pending,
         current_version_id_opt,
         pending_version_id_opt,
         version_summaries_before,
@@ -733,7 +1159,9 @@ fn get_version_ids_and_summaries(
     })
 }
 
-fn create_subgraph_version(
+/// The operations that deploy `manifest` as the new version of `name`, and
+/// the schema and indexer hints of the deployment if it has to be created
+fn subgraph_version_operations(
     logger: &Logger,
     store: Arc<impl Store>,
     chain_store: Arc<impl ChainStore>,
@@ -741,13 +1169,18 @@ fn create_subgraph_version(
     name: SubgraphName,
     manifest: SubgraphManifest,
     node_id: NodeId,
+    network_alias: Option<String>,
     version_switching_mode: SubgraphVersionSwitchingMode,
-) -> Box<dyn Future<Item = (), Error = SubgraphRegistrarError> + Send> {
+) -> Box<
+    dyn Future<
+            Item = (Vec<MetadataOperation>, Option<(Schema, IndexerHints)>),
+            Error = SubgraphRegistrarError,
+        > + Send,
+> {
     let logger = logger.clone();
     let manifest = manifest.clone();
     let manifest_id = manifest.id.clone();
     let store = store.clone();
-    let deployment_store = store.clone();
 
     Box::new(
         future::result(get_version_ids_and_summaries(
@@ -803,42 +1236,27 @@ fn create_subgraph_version(
                         subgraph_version_data.version_summaries_before,
                         subgraph_version_data.version_summaries_after,
                         Some(node_id),
+                        network_alias,
                     )
                     .into_iter()
                     .map(|op| op.into()),
             );
 
             // Update current/pending versions in Subgraph entity
-            match version_switching_mode {
-                SubgraphVersionSwitchingMode::Instant => {
-                    ops.extend(SubgraphEntity::update_pending_version_operations(
-                        &subgraph_version_data.subgraph_entity_id,
-                        None,
-                    ));
-                    ops.extend(SubgraphEntity::update_current_version_operations(
-                        &subgraph_version_data.subgraph_entity_id,
-                        Some(subgraph_version_data.version_entity_id),
-                    ));
-                }
-                SubgraphVersionSwitchingMode::Synced => {
-                    // Set the new version as pending, unless there is no fully synced
-                    // current versions
-                    if subgraph_version_data.current_is_synced {
-                        ops.extend(SubgraphEntity::update_pending_version_operations(
-                            &subgraph_version_data.subgraph_entity_id,
-                            Some(subgraph_version_data.version_entity_id),
-                        ));
-                    } else {
-                        ops.extend(SubgraphEntity::update_pending_version_operations(
-                            &subgraph_version_data.subgraph_entity_id,
-                            None,
-                        ));
-                        ops.extend(SubgraphEntity::update_current_version_operations(
-                            &subgraph_version_data.subgraph_entity_id,
-                            Some(subgraph_version_data.version_entity_id),
-                        ));
-                    }
-                }
+            if subgraph_version_data.new_version_is_pending {
+                ops.extend(SubgraphEntity::update_pending_version_operations(
+                    &subgraph_version_data.subgraph_entity_id,
+                    Some(subgraph_version_data.version_entity_id),
+                ));
+            } else {
+                ops.extend(SubgraphEntity::update_pending_version_operations(
+                    &subgraph_version_data.subgraph_entity_id,
+                    None,
+                ));
+                ops.extend(SubgraphEntity::update_current_version_operations(
+                    &subgraph_version_data.subgraph_entity_id,
+                    Some(subgraph_version_data.version_entity_id),
+                ));
             }
 
             // Check if deployment exists
@@ -881,31 +1299,91 @@ fn create_subgraph_version(
                     "block" => format!("{:?}", base_block.as_ref().map(|(_,ptr)| ptr.number))
                 );
 
-                // Apply the subgraph versioning and deployment operations,
-                // creating a new subgraph deployment if one doesn't exist.
+                // A new subgraph deployment is created if one doesn't exist
                 if deployment_exists {
-                    deployment_store
-                        .apply_metadata_operations(ops)
-                        .map_err(|e| SubgraphRegistrarError::SubgraphDeploymentError(e))
+                    Ok((ops, None))
                 } else {
+                    let migration_base = manifest.migrate.as_ref().map(|migration| migration.base.clone());
                     let deployment = SubgraphDeploymentEntity::new(
                         &manifest,
                         false,
                         start_block,
-                    ).graft(base_block);
+                    ).graft(base_block).migrate(migration_base);
                     ops.extend(
                         deployment
                         .create_operations(&manifest.id),
                     );
-                    deployment_store
-                        .create_subgraph_deployment(&manifest.schema, ops)
-                        .map_err(|e| SubgraphRegistrarError::SubgraphDeploymentError(e))
+                    Ok((ops, Some((manifest.schema, manifest.indexer_hints))))
                 }
             })
         }),
     )
 }
 
+/// Check that `base`, whose storage the new version of `name` takes over,
+/// is the current version of `name` and of no other subgraph, and that it
+/// is paused, so that nothing else uses that storage any more. Returns the
+/// operations that make sure `base` stays paused until the new version is
+/// deployed
+fn check_migration_base(
+    store: Arc<impl Store>,
+    name: &SubgraphName,
+    base: &SubgraphDeploymentId,
+) -> Result<Vec<MetadataOperation>, SubgraphRegistrarError> {
+    let invalid = |message: String| {
+        SubgraphRegistrarError::ManifestValidationError(vec![
+            SubgraphManifestValidationError::MigrationBaseInvalid(message),
+        ])
+    };
+
+    let (summaries, _) = store.read_subgraph_version_summaries(vec![base.clone()])?;
+    let subgraph = store
+        .find_one(
+            SubgraphEntity::query().filter(EntityFilter::new_equal("name", name.to_string())),
+        )?
+        .ok_or_else(|| SubgraphRegistrarError::NameNotFound(name.to_string()))?
+        .id()?;
+    if !summaries
+        .iter()
+        .any(|summary| summary.current && summary.subgraph_id == subgraph)
+    {
+        return Err(invalid(format!(
+            "`{}` is not the current version of {}",
+            base, name
+        )));
+    }
+    if summaries
+        .iter()
+        .any(|summary| (summary.current || summary.pending) && summary.subgraph_id != subgraph)
+    {
+        return Err(invalid(format!(
+            "`{}` is also used by another subgraph",
+            base
+        )));
+    }
+
+    let assignment = store.get(SubgraphDeploymentAssignmentEntity::key(base.clone()))?;
+    if assignment.as_ref().map_or(false, |assignment| {
+        !SubgraphDeploymentAssignmentEntity::is_paused(assignment)
+    }) {
+        return Err(invalid(format!(
+            "`{}` has to be paused before its storage can be taken over",
+            base
+        )));
+    }
+    Ok(vec![MetadataOperation::AbortUnless {
+        description: "Migration base must stay paused".to_owned(),
+        query: SubgraphDeploymentAssignmentEntity::query().filter(EntityFilter::And(vec![
+            EntityFilter::new_equal("id", base.to_string()),
+            EntityFilter::new_equal("paused", true),
+        ])),
+        entity_ids: match assignment {
+            Some(_) => vec![base.to_string()],
+            None => vec![],
+        },
+    }])
+}
+
 fn get_subgraph_version_deployment_id(
     store: Arc<impl Store>,
     version_id: String,
@@ -926,11 +1404,11 @@ fn get_subgraph_version_deployment_id(
     .unwrap())
 }
 
-fn remove_subgraph(
+fn remove_subgraph_operations(
     logger: &Logger,
     store: Arc<impl Store>,
     name: SubgraphName,
-) -> Result<(), SubgraphRegistrarError> {
+) -> Result<Vec<MetadataOperation>, SubgraphRegistrarError> {
     let mut ops = vec![];
 
     // Find the subgraph entity
@@ -976,11 +1454,7 @@ fn remove_subgraph(
         id: subgraph_entity.id()?,
     });
 
-    store.apply_metadata_operations(ops)?;
-
-    debug!(logger, "Removed subgraph"; "subgraph_name" => name.to_string());
-
-    Ok(())
+    Ok(ops)
 }
 
 /// Remove a set of subgraph versions atomically.
@@ -1041,6 +1515,7 @@ fn remove_subgraph_versions(
                 version_summaries,
                 version_summaries_after_delete,
                 None,
+                None,
             )
             .into_iter()
             .map(|op| op.into()),
@@ -1061,15 +1536,105 @@ fn remove_subgraph_versions(
     Ok(ops)
 }
 
+/// The operations that make the pending version of `name` its current
+/// version. The assignment of the previous current version is removed
+/// unless another version still needs it.
+fn promote_subgraph_operations(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    name: SubgraphName,
+    force: bool,
+) -> Result<Vec<MetadataOperation>, SubgraphRegistrarError> {
+    let subgraph = store
+        .find_one(
+            SubgraphEntity::query().filter(EntityFilter::new_equal("name", name.to_string())),
+        )?
+        .ok_or_else(|| SubgraphRegistrarError::NameNotFound(name.to_string()))?;
+    let subgraph_id = subgraph.id()?;
+    let version_id = |field: &str| match subgraph.get(field) {
+        Some(Value::String(id)) => Some(id.to_owned()),
+        _ => None,
+    };
+    let current_version_id = version_id("currentVersion");
+    let pending_version_id = version_id("pendingVersion")
+        .ok_or_else(|| SubgraphRegistrarError::NoPendingVersion(name.to_string()))?;
+
+    let pending_hash =
+        get_subgraph_version_deployment_id(store.clone(), pending_version_id.clone())?;
+    if !force && !store.is_deployment_synced(pending_hash.clone())? {
+        return Err(SubgraphRegistrarError::PendingVersionNotSynced(
+            name.to_string(),
+        ));
+    }
+    let current_hash = match &current_version_id {
+        Some(id) => Some(get_subgraph_version_deployment_id(
+            store.clone(),
+            id.clone(),
+        )?),
+        None => None,
+    };
+
+    let mut ops = vec![MetadataOperation::AbortUnless {
+        description: "Subgraph entity must still exist, have same currentVersion/pendingVersion"
+            .to_owned(),
+        query: SubgraphEntity::query().filter(EntityFilter::And(vec![
+            EntityFilter::new_equal("name", name.to_string()),
+            EntityFilter::new_equal("currentVersion", current_version_id.clone()),
+            EntityFilter::new_equal("pendingVersion", pending_version_id.clone()),
+        ])),
+        entity_ids: vec![subgraph_id.clone()],
+    }];
+
+    // Simulate swapping the versions to find the assignments that are no
+    // longer needed
+    let (versions_before, read_summaries_ops) = store
+        .read_subgraph_version_summaries(iter::once(pending_hash).chain(current_hash).collect())?;
+    ops.extend(read_summaries_ops);
+    let versions_after = versions_before
+        .clone()
+        .into_iter()
+        .map(|mut version| {
+            if version.id == pending_version_id {
+                version.pending = false;
+                version.current = true;
+            } else if Some(&version.id) == current_version_id.as_ref() {
+                version.current = false;
+            }
+            version
+        })
+        .collect();
+    ops.extend(store.reconcile_assignments(logger, versions_before, versions_after, None, None));
+
+    ops.extend(SubgraphEntity::update_pending_version_operations(
+        &subgraph_id,
+        None,
+    ));
+    ops.extend(SubgraphEntity::update_current_version_operations(
+        &subgraph_id,
+        Some(pending_version_id),
+    ));
+    Ok(ops)
+}
+
 /// Reassign a subgraph deployment to a different node.
 ///
 /// Reassigning to a nodeId that does not match any reachable graph-nodes will effectively pause the
 /// subgraph syncing process.
-fn reassign_subgraph(
+pub(crate) fn reassign_subgraph(
     store: Arc<impl Store>,
     hash: SubgraphDeploymentId,
     node_id: NodeId,
 ) -> Result<(), SubgraphRegistrarError> {
+    let ops = reassign_subgraph_operations(store.clone(), hash, node_id)?;
+    store.apply_metadata_operations(ops)?;
+    Ok(())
+}
+
+fn reassign_subgraph_operations(
+    store: Arc<impl Store>,
+    hash: SubgraphDeploymentId,
+    node_id: NodeId,
+) -> Result<Vec<MetadataOperation>, SubgraphRegistrarError> {
     let mut ops = vec![];
 
     let current_deployment = store.find(
@@ -1097,16 +1662,187 @@ fn reassign_subgraph(
         entity_ids: vec![hash.clone().to_string()],
     });
 
+    // A paused deployment stays paused on its new node, and keeps using
+    // the providers of its network alias and its priority
+    let paused = current_deployment
+        .first()
+        .map(SubgraphDeploymentAssignmentEntity::is_paused)
+        .unwrap_or(false);
+    let network_alias = current_deployment
+        .first()
+        .and_then(SubgraphDeploymentAssignmentEntity::alias_of);
+    let priority = current_deployment
+        .first()
+        .map(SubgraphDeploymentAssignmentEntity::priority_of)
+        .unwrap_or_default();
+
     // Create the assignment update operations.
     // Note: This will also generate a remove operation for the existing subgraph assignment.
     ops.extend(
         SubgraphDeploymentAssignmentEntity::new(node_id)
+            .paused(paused)
+            .network_alias(network_alias)
+            .priority(priority)
             .write_operations(&hash.clone())
             .into_iter()
             .map(|op| op.into()),
     );
 
-    store.apply_metadata_operations(ops)?;
+    Ok(ops)
+}
+
+/// The node among `nodes` that has the fewest deployments assigned to it.
+pub(crate) fn least_loaded_node(
+    store: &impl Store,
+    nodes: Vec<NodeId>,
+) -> Result<Option<NodeId>, QueryExecutionError> {
+    let assignments = store.find(SubgraphDeploymentAssignmentEntity::query().filter(
+        EntityFilter::In(
+            "nodeId".to_owned(),
+            nodes.iter().map(|node| node.to_string().into()).collect(),
+        ),
+    ))?;
+    let load = |node: &NodeId| {
+        assignments
+            .iter()
+            .filter(|assignment| assignment.get("nodeId") == Some(&node.to_string().into()))
+            .count()
+    };
+    Ok(nodes.into_iter().min_by_key(load))
+}
 
+/// The operations that mark the deployment `hash` as paused or not, none if
+/// it already is. The assignment event this causes makes the node it is
+/// assigned to stop or start indexing it.
+fn subgraph_paused_operations(
+    store: Arc<impl Store>,
+    hash: SubgraphDeploymentId,
+    paused: bool,
+) -> Result<Vec<MetadataOperation>, SubgraphRegistrarError> {
+    let assignment = store
+        .get(SubgraphDeploymentAssignmentEntity::key(hash.clone()))?
+        .ok_or_else(|| SubgraphRegistrarError::DeploymentNotFound(hash.to_string()))?;
+
+    if SubgraphDeploymentAssignmentEntity::is_paused(&assignment) == paused {
+        return Ok(vec![]);
+    }
+
+    let priority = SubgraphDeploymentAssignmentEntity::priority_of(&assignment);
+    assignment_update_operations(&hash, &assignment, paused, priority)
+}
+
+/// Give the deployment `hash` the priority `priority`. Since a deployment
+/// only reads its priority when it starts, one that is being indexed is
+/// restarted by pausing and resuming it.
+fn set_subgraph_priority(
+    store: Arc<impl Store>,
+    hash: SubgraphDeploymentId,
+    priority: DeploymentPriority,
+) -> Result<(), SubgraphRegistrarError> {
+    let assignment = store
+        .get(SubgraphDeploymentAssignmentEntity::key(hash.clone()))?
+        .ok_or_else(|| SubgraphRegistrarError::DeploymentNotFound(hash.to_string()))?;
+
+    if SubgraphDeploymentAssignmentEntity::priority_of(&assignment) == priority {
+        return Ok(());
+    }
+
+    let paused = SubgraphDeploymentAssignmentEntity::is_paused(&assignment);
+    store.apply_metadata_operations(assignment_update_operations(
+        &hash,
+        &assignment,
+        true,
+        priority,
+    )?)?;
+    if !paused {
+        store.apply_metadata_operations(assignment_update_operations(
+            &hash,
+            &assignment,
+            false,
+            priority,
+        )?)?;
+    }
     Ok(())
 }
+
+/// The operations that replace the current `assignment` of the deployment
+/// `hash` with one for the same node and network alias, but with the given
+/// `paused` flag and `priority`
+fn assignment_update_operations(
+    hash: &SubgraphDeploymentId,
+    assignment: &Entity,
+    paused: bool,
+    priority: DeploymentPriority,
+) -> Result<Vec<MetadataOperation>, SubgraphRegistrarError> {
+    let node_id = assignment
+        .get("nodeId")
+        .cloned()
+        .and_then(Value::as_string)
+        .and_then(|node_id| NodeId::new(node_id).ok())
+        .ok_or_else(|| {
+            SubgraphRegistrarError::Unknown(format_err!(
+                "invalid node id in assignment for {}",
+                hash
+            ))
+        })?;
+
+    let mut ops = vec![MetadataOperation::AbortUnless {
+        description: "Deployment assignment is unchanged".to_owned(),
+        query: SubgraphDeploymentAssignmentEntity::query().filter(EntityFilter::And(vec![
+            EntityFilter::new_equal("nodeId", node_id.to_string()),
+            EntityFilter::new_equal("id", hash.to_string()),
+        ])),
+        entity_ids: vec![hash.to_string()],
+    }];
+    ops.extend(
+        SubgraphDeploymentAssignmentEntity::new(node_id)
+            .paused(paused)
+            .network_alias(SubgraphDeploymentAssignmentEntity::alias_of(assignment))
+            .priority(priority)
+            .write_operations(hash),
+    );
+
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicting_batch_operations() {
+        let name = |name: &str| SubgraphName::new(name).unwrap();
+        let hash = |hash: &str| SubgraphDeploymentId::new(hash).unwrap();
+        let operations = vec![
+            SubgraphOperation::Deploy {
+                name: name("team/one"),
+                hash: hash("QmOne"),
+                node_id: None,
+            },
+            SubgraphOperation::Deploy {
+                name: name("team/two"),
+                hash: hash("QmTwo"),
+                node_id: None,
+            },
+            SubgraphOperation::Remove {
+                name: name("team/one"),
+            },
+            SubgraphOperation::Pause {
+                hash: hash("QmTwo"),
+            },
+            SubgraphOperation::Create {
+                name: name("team/three"),
+            },
+        ];
+        assert_eq!(
+            vec![
+                None,
+                None,
+                Some("subgraph team/one is also used by operation 1 of the batch".to_owned()),
+                Some("deployment QmTwo is also used by operation 2 of the batch".to_owned()),
+                None,
+            ],
+            batch_conflicts(&operations)
+        );
+    }
+}
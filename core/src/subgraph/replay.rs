@@ -0,0 +1,514 @@
+use atomic_refcell::AtomicRefCell;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use graph::components::ethereum::{blocks_with_triggers, EthereumNetworks};
+use graph::components::store::ModificationsAndCache;
+use graph::components::subgraph::{ProofOfIndexing, ProofOfIndexingFinisher};
+use graph::data::subgraph::schema::{SubgraphError, POI_OBJECT};
+use graph::prelude::{DataSourceLoader as _, SubgraphInstance as SubgraphInstanceTrait, *};
+use graph::util::lfu_cache::LfuCache;
+
+use super::instance_manager::update_proof_of_indexing;
+use super::SubgraphInstance;
+use crate::DataSourceLoader;
+
+/// What processing a block during a replay would have done.
+#[derive(Debug)]
+pub struct ReplayedBlock {
+    pub block_ptr: EthereumBlockPointer,
+
+    /// The entity changes the block makes, including the changes to the
+    /// proof of indexing
+    pub modifications: Vec<EntityModification>,
+
+    /// Handlers that failed deterministically
+    pub deterministic_errors: Vec<SubgraphError>,
+
+    /// Data sources created by handlers. Their handlers are not run
+    /// during a replay.
+    pub created_data_sources: Vec<DataSourceTemplateInfo>,
+
+    /// The contract calls that handlers made, in the order they made them
+    pub calls: Vec<ReplayedCall>,
+
+    /// The proof of indexing of the deployment after the block, for an
+    /// indexer with no address; `None` if the deployment does not support
+    /// proofs of indexing
+    pub proof_of_indexing: Option<[u8; 32]>,
+}
+
+/// A contract call that a handler made during a replay
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayedCall {
+    pub address: ethabi::Address,
+    pub call_data: Vec<u8>,
+    /// The block the call was made against; `None` for calls whose result
+    /// is the same for every block that were answered from the cache
+    pub block_ptr: Option<EthereumBlockPointer>,
+    pub return_value: Vec<u8>,
+    /// Whether the result came from the call cache
+    pub cached: bool,
+}
+
+/// Answers contract calls from the call cache of the store like during
+/// indexing, but does not add the results of new calls to it. All calls
+/// are recorded so that a replay can report them.
+pub struct ReplayCallCache {
+    cache: Arc<dyn EthereumCallCache>,
+    calls: Mutex<Vec<ReplayedCall>>,
+}
+
+impl ReplayCallCache {
+    pub fn new(cache: Arc<dyn EthereumCallCache>) -> Self {
+        ReplayCallCache {
+            cache,
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The calls recorded since the last time this was called
+    pub fn take_calls(&self) -> Vec<ReplayedCall> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+
+    fn record(
+        &self,
+        address: ethabi::Address,
+        call_data: &[u8],
+        block_ptr: Option<EthereumBlockPointer>,
+        return_value: &[u8],
+        cached: bool,
+    ) {
+        self.calls.lock().unwrap().push(ReplayedCall {
+            address,
+            call_data: call_data.to_vec(),
+            block_ptr,
+            return_value: return_value.to_vec(),
+            cached,
+        });
+    }
+}
+
+impl EthereumCallCache for ReplayCallCache {
+    fn get_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let result = self.cache.get_call(contract_address, encoded_call, block)?;
+        if let Some(return_value) = &result {
+            self.record(
+                contract_address,
+                encoded_call,
+                Some(block),
+                return_value,
+                true,
+            );
+        }
+        Ok(result)
+    }
+
+    fn set_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        return_value: &[u8],
+    ) -> Result<(), Error> {
+        self.record(
+            contract_address,
+            encoded_call,
+            Some(block),
+            return_value,
+            false,
+        );
+        Ok(())
+    }
+
+    fn get_immutable_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let result = self
+            .cache
+            .get_immutable_call(contract_address, encoded_call)?;
+        if let Some(return_value) = &result {
+            self.record(contract_address, encoded_call, None, return_value, true);
+        }
+        Ok(result)
+    }
+
+    fn set_immutable_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        return_value: &[u8],
+    ) -> Result<(), Error> {
+        self.record(
+            contract_address,
+            encoded_call,
+            Some(block),
+            return_value,
+            false,
+        );
+        Ok(())
+    }
+}
+
+/// Process the blocks `from` to `to` of a deployment against the current
+/// state of its store and return what the handlers would change, without
+/// writing anything. Later blocks see the changes of earlier ones. This is
+/// meant for debugging deployments whose data diverges from other nodes.
+///
+/// The runtime hosts are built by `host_builder`, which must use the call
+/// cache it is passed for contract calls so that they are reported and not
+/// written to the store.
+pub async fn replay_blocks<L, Q, S, M, H>(
+    logger: Logger,
+    link_resolver: Arc<L>,
+    graphql_runner: Arc<Q>,
+    stores: HashMap<String, Arc<S>>,
+    eth_networks: EthereumNetworks,
+    host_builder: impl FnOnce(Arc<dyn EthereumCallCache>) -> H,
+    registry: Arc<M>,
+    id: SubgraphDeploymentId,
+    from: u64,
+    to: u64,
+) -> Result<Vec<ReplayedBlock>, Error>
+where
+    L: LinkResolver,
+    Q: GraphQlRunner,
+    S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
+    M: MetricsRegistry,
+    H: RuntimeHostBuilder,
+{
+    let mut manifest = SubgraphManifest::resolve(id.to_ipfs_link(), &*link_resolver, &logger)
+        .await
+        .map_err(|e| format_err!("failed to resolve subgraph `{}`: {}", id, e))?;
+
+    let network = manifest.network_name();
+    let store = stores
+        .get(&network)
+        .ok_or_else(|| format_err!("no store for network `{}`", network))?
+        .clone();
+    let eth_adapter = eth_networks
+        .adapter_with_capabilities(network.clone(), &manifest.required_ethereum_capabilities())?
+        .clone();
+
+    let loader = DataSourceLoader::new(store.clone(), link_resolver, graphql_runner);
+    let data_sources = loader
+        .load_dynamic_data_sources(id.clone(), logger.clone())
+        .await?;
+    manifest.data_sources.extend(data_sources);
+
+    let log_filter = EthereumLogFilter::from_data_sources(&manifest.data_sources);
+    let call_filter = EthereumCallFilter::from_data_sources(&manifest.data_sources);
+    let block_filter = EthereumBlockFilter::from_data_sources(&manifest.data_sources);
+
+    let stopwatch = StopwatchMetrics::new(logger.clone(), id.clone(), registry.clone());
+    let host_metrics = Arc::new(HostMetrics::new(
+        registry.clone(),
+        id.to_string(),
+        stopwatch.clone(),
+    ));
+    let ethrpc_metrics = Arc::new(SubgraphEthRpcMetrics::new(registry, id.to_string()));
+    let call_cache = Arc::new(ReplayCallCache::new(store.clone()));
+    let host_builder = host_builder(call_cache.clone());
+    let instance =
+        SubgraphInstance::from_manifest(&logger, manifest, network, host_builder, host_metrics)?;
+
+    let blocks = blocks_with_triggers(
+        eth_adapter,
+        logger.clone(),
+        store.clone(),
+        ethrpc_metrics,
+        from,
+        to,
+        log_filter,
+        call_filter,
+        block_filter,
+    )
+    .compat()
+    .await?;
+
+    let supports_proof_of_indexing = store.supports_proof_of_indexing(&id).await?;
+    let mut digests = match supports_proof_of_indexing {
+        true => poi_digests(store.as_ref(), &id)?,
+        false => HashMap::new(),
+    };
+    let mut cache: LfuCache<EntityKey, Option<Entity>> = LfuCache::new();
+    let mut replayed = Vec::new();
+    for block in blocks {
+        let block_is_final = matches!(block.ethereum_block, BlockFinality::Final(_));
+        let light_block = Arc::new(block.ethereum_block.light_block());
+        let block_ptr = EthereumBlockPointer::from(light_block.as_ref());
+        let logger = logger.new(o!("block_number" => block_ptr.number));
+
+        let proof_of_indexing = match supports_proof_of_indexing {
+            true => Some(Arc::new(AtomicRefCell::new(ProofOfIndexing::new(
+                block_ptr.number,
+            )))),
+            false => None,
+        };
+
+        let mut state = BlockState::new(store.clone(), std::mem::take(&mut cache))
+            .with_block_finality(block_is_final);
+        for trigger in block.triggers {
+            state = instance
+                .process_trigger(
+                    &logger,
+                    &light_block,
+                    trigger,
+                    state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await
+                .map_err(|e| format_err!("failed to process trigger: {}", e))?;
+        }
+
+        if let Some(proof_of_indexing) = proof_of_indexing {
+            let proof_of_indexing = Arc::try_unwrap(proof_of_indexing).unwrap().into_inner();
+            update_proof_of_indexing(proof_of_indexing, &stopwatch, &id, &mut state.entity_cache)
+                .await?;
+        }
+
+        let BlockState {
+            entity_cache,
+            created_data_sources,
+            deterministic_errors,
+            ..
+        } = state;
+        let ModificationsAndCache {
+            modifications,
+            entity_lfu_cache,
+        } = entity_cache.as_modifications(store.as_ref())?;
+
+        // The cache holds the state after this block, which is what the
+        // next block needs to see since nothing was written to the store
+        cache = entity_lfu_cache;
+
+        let proof_of_indexing = match supports_proof_of_indexing {
+            true => {
+                update_poi_digests(&mut digests, &modifications);
+                Some(finish_proof_of_indexing(&digests, &block_ptr, &id))
+            }
+            false => None,
+        };
+
+        replayed.push(ReplayedBlock {
+            block_ptr,
+            modifications,
+            deterministic_errors,
+            created_data_sources,
+            calls: call_cache.take_calls(),
+            proof_of_indexing,
+        });
+    }
+    Ok(replayed)
+}
+
+/// The digests of the proof of indexing of each causality region of the
+/// deployment in the store
+fn poi_digests<S: Store>(
+    store: &S,
+    id: &SubgraphDeploymentId,
+) -> Result<HashMap<String, Vec<u8>>, Error> {
+    let query = EntityQuery::new(
+        id.clone(),
+        BLOCK_NUMBER_MAX,
+        EntityCollection::All(vec![POI_OBJECT.to_owned()]),
+    )
+    .range(EntityRange {
+        first: None,
+        skip: 0,
+    });
+    let mut digests = HashMap::new();
+    for entity in store.find(query)? {
+        if let (Some(Value::String(id)), Some(Value::Bytes(digest))) =
+            (entity.get("id"), entity.get("digest"))
+        {
+            digests.insert(id.clone(), digest.to_vec());
+        }
+    }
+    Ok(digests)
+}
+
+/// Apply the changes that a block makes to the proof of indexing to
+/// `digests`
+fn update_poi_digests(digests: &mut HashMap<String, Vec<u8>>, mods: &[EntityModification]) {
+    use EntityModification::*;
+
+    for modification in mods {
+        match modification {
+            Insert { key, data } | Overwrite { key, data } if key.entity_type == POI_OBJECT => {
+                if let Some(Value::Bytes(digest)) = data.get("digest") {
+                    digests.insert(key.entity_id.clone(), digest.to_vec());
+                }
+            }
+            Remove { key } if key.entity_type == POI_OBJECT => {
+                digests.remove(&key.entity_id);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn finish_proof_of_indexing(
+    digests: &HashMap<String, Vec<u8>>,
+    block_ptr: &EthereumBlockPointer,
+    id: &SubgraphDeploymentId,
+) -> [u8; 32] {
+    let mut finisher = ProofOfIndexingFinisher::new(block_ptr, id, &None);
+    for (name, digest) in digests {
+        finisher.add_causality_region(name, digest);
+    }
+    finisher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::data::store::scalar::Bytes;
+    use web3::types::H256;
+
+    /// A call cache that holds one call for every block and counts writes
+    #[derive(Default)]
+    struct TestCallCache {
+        writes: Mutex<usize>,
+    }
+
+    impl EthereumCallCache for TestCallCache {
+        fn get_call(
+            &self,
+            _: ethabi::Address,
+            encoded_call: &[u8],
+            _: EthereumBlockPointer,
+        ) -> Result<Option<Vec<u8>>, Error> {
+            Ok(match encoded_call {
+                b"cached" => Some(b"result".to_vec()),
+                _ => None,
+            })
+        }
+
+        fn set_call(
+            &self,
+            _: ethabi::Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+            _: &[u8],
+        ) -> Result<(), Error> {
+            *self.writes.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        fn get_immutable_call(
+            &self,
+            _: ethabi::Address,
+            _: &[u8],
+        ) -> Result<Option<Vec<u8>>, Error> {
+            Ok(None)
+        }
+
+        fn set_immutable_call(
+            &self,
+            _: ethabi::Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+            _: &[u8],
+        ) -> Result<(), Error> {
+            *self.writes.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn replay_call_cache_records_calls_without_writing() {
+        let store = Arc::new(TestCallCache::default());
+        let cache = ReplayCallCache::new(store.clone());
+        let address = ethabi::Address::from_low_u64_be(1);
+        let block = EthereumBlockPointer {
+            number: 7,
+            hash: H256::from_low_u64_be(7),
+        };
+
+        assert_eq!(
+            Some(b"result".to_vec()),
+            cache.get_call(address, b"cached", block).unwrap()
+        );
+        assert_eq!(None, cache.get_call(address, b"new", block).unwrap());
+        cache.set_call(address, b"new", block, b"fresh").unwrap();
+        cache
+            .set_immutable_call(address, b"constant", block, b"same")
+            .unwrap();
+
+        assert_eq!(0, *store.writes.lock().unwrap());
+        let calls = cache.take_calls();
+        assert_eq!(
+            vec![
+                (&b"cached"[..], &b"result"[..], true),
+                (&b"new"[..], &b"fresh"[..], false),
+                (&b"constant"[..], &b"same"[..], false),
+            ],
+            calls
+                .iter()
+                .map(|call| (
+                    call.call_data.as_slice(),
+                    call.return_value.as_slice(),
+                    call.cached
+                ))
+                .collect::<Vec<_>>()
+        );
+        assert!(cache.take_calls().is_empty());
+    }
+
+    #[test]
+    fn proof_of_indexing_follows_block_changes() {
+        let id = SubgraphDeploymentId::new("replay").unwrap();
+        let block_ptr = EthereumBlockPointer {
+            number: 7,
+            hash: H256::from_low_u64_be(7),
+        };
+        let poi_key = |region: &str| EntityKey {
+            subgraph_id: id.clone(),
+            entity_type: POI_OBJECT.to_owned(),
+            entity_id: region.to_owned(),
+        };
+        let poi_entity = |region: &str, digest: &[u8]| {
+            let mut entity = Entity::new();
+            entity.set("id", region);
+            entity.set("digest", Bytes::from(digest));
+            entity
+        };
+
+        let mut digests = HashMap::new();
+        digests.insert("mainnet".to_owned(), vec![1]);
+        let before = finish_proof_of_indexing(&digests, &block_ptr, &id);
+
+        update_poi_digests(
+            &mut digests,
+            &[
+                EntityModification::Overwrite {
+                    key: poi_key("mainnet"),
+                    data: poi_entity("mainnet", &[2]),
+                },
+                EntityModification::Insert {
+                    key: EntityKey {
+                        subgraph_id: id.clone(),
+                        entity_type: "Thing".to_owned(),
+                        entity_id: "ipfs".to_owned(),
+                    },
+                    data: poi_entity("ipfs", &[3]),
+                },
+            ],
+        );
+
+        let mut expected = HashMap::new();
+        expected.insert("mainnet".to_owned(), vec![2]);
+        assert_eq!(expected, digests);
+        assert_ne!(before, finish_proof_of_indexing(&digests, &block_ptr, &id));
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::prelude::tokio;
+
+/// How often `shutdown` checks whether all blocks have been written.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Stops indexing between blocks when the node shuts down. Subgraphs do not
+/// start processing new blocks once a shutdown has begun, and the shutdown
+/// waits for the blocks that are being processed to be written, so that
+/// their entity changes and the subgraph pointer are in the store.
+#[derive(Debug, Default)]
+pub struct IndexingShutdown {
+    stopping: AtomicBool,
+    blocks_in_progress: AtomicUsize,
+}
+
+impl IndexingShutdown {
+    /// Register a block that is about to be processed. Returns `Err` if
+    /// the node is shutting down and the block must not be processed; the
+    /// shutdown still waits for it, so that the blocks that were processed
+    /// before it can be written.
+    pub(crate) fn start_block(self: &Arc<Self>) -> Result<BlockInProgress, BlockInProgress> {
+        // Count the block before checking the flag; `shutdown` sets the flag
+        // before reading the count, so it never misses a block
+        self.blocks_in_progress.fetch_add(1, Ordering::SeqCst);
+        let block = BlockInProgress(self.clone());
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(block);
+        }
+        Ok(block)
+    }
+
+    /// Stop processing new blocks and wait until the blocks that are being
+    /// processed have been written. Returns `false` if that did not happen
+    /// within `timeout`.
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        self.stopping.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        while self.blocks_in_progress.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::delay_for(POLL_INTERVAL).await;
+        }
+        true
+    }
+
+    /// Whether a shutdown has begun. Blocks that wait for something should
+    /// give up once it has, since the shutdown waits for them.
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    pub fn blocks_in_progress(&self) -> usize {
+        self.blocks_in_progress.load(Ordering::SeqCst)
+    }
+}
+
+/// Marks a block as being processed until it is dropped.
+pub(crate) struct BlockInProgress(Arc<IndexingShutdown>);
+
+impl Drop for BlockInProgress {
+    fn drop(&mut self) {
+        self.0.blocks_in_progress.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_blocks_in_progress() {
+        let shutdown = Arc::new(IndexingShutdown::default());
+        let block = shutdown
+            .start_block()
+            .unwrap_or_else(|_| panic!("not shutting down yet"));
+
+        let waiting = {
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move { shutdown.shutdown(Duration::from_secs(10)).await })
+        };
+        tokio::time::delay_for(Duration::from_millis(250)).await;
+        let stopping = shutdown.start_block();
+        assert!(stopping.is_err());
+        assert_eq!(2, shutdown.blocks_in_progress());
+        drop(stopping);
+        assert_eq!(1, shutdown.blocks_in_progress());
+
+        drop(block);
+        assert!(waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_timeout() {
+        let shutdown = Arc::new(IndexingShutdown::default());
+        let _block = shutdown
+            .start_block()
+            .unwrap_or_else(|_| panic!("not shutting down yet"));
+        assert!(!shutdown.shutdown(Duration::from_millis(200)).await);
+    }
+}
@@ -0,0 +1,219 @@
+use lazy_static::lazy_static;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use graph::prelude::{tokio::sync::oneshot, DeploymentPriority};
+
+lazy_static! {
+    /// Maximum number of deployments per network that sync historical blocks
+    /// at the same time. 0 means no limit.
+    static ref MAX_CONCURRENT_SYNCS: usize =
+        std::env::var("GRAPH_SUBGRAPH_MAX_CONCURRENT_SYNCS")
+            .unwrap_or("10".into())
+            .parse::<usize>()
+            .expect("invalid GRAPH_SUBGRAPH_MAX_CONCURRENT_SYNCS");
+}
+
+/// A deployment waiting for its turn to sync
+struct Waiter {
+    priority: DeploymentPriority,
+    /// How many blocks the deployment is behind the chain head
+    distance: u64,
+    /// The order in which deployments started waiting, to break ties
+    seq: u64,
+    ready: oneshot::Sender<SyncPermit>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    /// `BinaryHeap` is a max-heap; the deployment with the highest
+    /// priority, among those the one closest to the chain head, and among
+    /// those the one that has waited longest, comes first
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.distance.cmp(&self.distance))
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct NetworkQueue {
+    syncing: usize,
+    waiting: BinaryHeap<Waiter>,
+    next_seq: u64,
+}
+
+/// Limits how many deployments of each network sync historical blocks at
+/// the same time, so that deploying many subgraphs at once does not
+/// overload the Ethereum nodes and the database. Deployments that have to
+/// wait are started in the order of their priority, and then of how far
+/// they are behind the chain head, closest first. Critical deployments do
+/// not wait at all.
+pub(crate) struct SyncQueue {
+    max_concurrent: usize,
+    networks: Mutex<HashMap<String, NetworkQueue>>,
+}
+
+impl SyncQueue {
+    pub fn new() -> Arc<Self> {
+        Self::with_limit(*MAX_CONCURRENT_SYNCS)
+    }
+
+    fn with_limit(max_concurrent: usize) -> Arc<Self> {
+        Arc::new(SyncQueue {
+            max_concurrent,
+            networks: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Ask to sync a deployment with `priority` on `network` that is
+    /// `distance` blocks behind the chain head. Returns the permit to sync
+    /// right away if a slot is free, and otherwise a receiver that gets the
+    /// permit once it is the deployment's turn. Dropping the receiver
+    /// leaves the queue.
+    pub fn enqueue(
+        self: &Arc<Self>,
+        network: &str,
+        distance: u64,
+        priority: DeploymentPriority,
+    ) -> Result<SyncPermit, oneshot::Receiver<SyncPermit>> {
+        if priority == DeploymentPriority::Critical {
+            // Critical deployments do not take up a slot
+            return Ok(SyncPermit {
+                queue: self.clone(),
+                network: None,
+            });
+        }
+        let mut networks = self.networks.lock().unwrap();
+        let queue = networks.entry(network.to_owned()).or_default();
+        if self.max_concurrent == 0 || queue.syncing < self.max_concurrent {
+            queue.syncing += 1;
+            return Ok(SyncPermit {
+                queue: self.clone(),
+                network: Some(network.to_owned()),
+            });
+        }
+        let (ready, receiver) = oneshot::channel();
+        queue.waiting.push(Waiter {
+            priority,
+            distance,
+            seq: queue.next_seq,
+            ready,
+        });
+        queue.next_seq += 1;
+        Err(receiver)
+    }
+
+    /// The number of deployments syncing and waiting to sync on `network`
+    pub fn counts(&self, network: &str) -> (usize, usize) {
+        self.networks
+            .lock()
+            .unwrap()
+            .get(network)
+            .map_or((0, 0), |queue| (queue.syncing, queue.waiting.len()))
+    }
+
+    /// Hand the slot of a finished sync to the next waiting deployment that
+    /// is still interested, or free it
+    fn release(self: &Arc<Self>, network: String) {
+        let mut networks = self.networks.lock().unwrap();
+        let queue = networks
+            .get_mut(&network)
+            .expect("a permit belongs to a known network");
+        while let Some(waiter) = queue.waiting.pop() {
+            let permit = SyncPermit {
+                queue: self.clone(),
+                network: Some(network.clone()),
+            };
+            match waiter.ready.send(permit) {
+                Ok(()) => return,
+                // The deployment stopped waiting. Dropping the permit we got
+                // back must not release the slot again
+                Err(mut permit) => permit.network = None,
+            }
+        }
+        queue.syncing -= 1;
+    }
+}
+
+/// Allows a deployment to sync historical blocks until it is dropped
+pub(crate) struct SyncPermit {
+    queue: Arc<SyncQueue>,
+    /// `None` once the slot has been given to someone else
+    network: Option<String>,
+}
+
+impl Drop for SyncPermit {
+    fn drop(&mut self) {
+        if let Some(network) = self.network.take() {
+            self.queue.release(network);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::tokio;
+
+    use DeploymentPriority::*;
+
+    #[tokio::test]
+    async fn closest_to_head_goes_first() {
+        let queue = SyncQueue::with_limit(1);
+        let first = queue.enqueue("mainnet", 1000, Normal).ok().unwrap();
+        let far = queue.enqueue("mainnet", 5000, Normal).err().unwrap();
+        let gone = queue.enqueue("mainnet", 10, Normal).err().unwrap();
+        let close = queue.enqueue("mainnet", 100, Normal).err().unwrap();
+        // Other networks have their own slots
+        let _other = queue.enqueue("rinkeby", 5000, Normal).ok().unwrap();
+        assert_eq!((1, 3), queue.counts("mainnet"));
+
+        drop(gone);
+        drop(first);
+        let close = close.await.unwrap();
+        assert_eq!((1, 1), queue.counts("mainnet"));
+
+        drop(close);
+        let far = far.await.unwrap();
+        assert_eq!((1, 0), queue.counts("mainnet"));
+
+        drop(far);
+        assert_eq!((0, 0), queue.counts("mainnet"));
+    }
+
+    #[tokio::test]
+    async fn higher_priority_goes_first() {
+        let queue = SyncQueue::with_limit(1);
+        let first = queue.enqueue("mainnet", 1000, Normal).ok().unwrap();
+        let batch = queue.enqueue("mainnet", 10, Batch).err().unwrap();
+        let normal = queue.enqueue("mainnet", 5000, Normal).err().unwrap();
+        // Critical deployments start right away and do not take a slot
+        let critical = queue.enqueue("mainnet", 5000, Critical).ok().unwrap();
+        assert_eq!((1, 2), queue.counts("mainnet"));
+        drop(critical);
+        assert_eq!((1, 2), queue.counts("mainnet"));
+
+        drop(first);
+        let normal = normal.await.unwrap();
+        drop(normal);
+        let batch = batch.await.unwrap();
+        drop(batch);
+        assert_eq!((0, 0), queue.counts("mainnet"));
+    }
+}
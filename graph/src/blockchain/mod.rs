@@ -0,0 +1,92 @@
+//! The traits that the indexing loop uses to work with the chain a subgraph
+//! indexes. A chain is integrated by implementing `Blockchain` and the
+//! traits of its associated types; so far, Ethereum is the only chain that
+//! does.
+
+use async_trait::async_trait;
+use failure::Error;
+use slog::Logger;
+use std::fmt::Debug;
+use std::sync::Arc;
+use web3::types::H256;
+
+use crate::components::ethereum::EthereumBlockPointer;
+use crate::data::subgraph::DataSource;
+
+/// Points to a block by its hash and number. All chains that are integrated
+/// so far identify blocks by a 32 byte hash.
+pub type BlockPtr = EthereumBlockPointer;
+
+#[async_trait]
+pub trait Blockchain: Sized + Send + Sync + 'static {
+    /// The data source kind of the chain, e.g. the `ethereum` in
+    /// `ethereum/contract`
+    const KIND: &'static str;
+
+    type Block: Block;
+    type TriggerData: TriggerData;
+    type TriggerFilter: TriggerFilter<Self>;
+    type RuntimeAdapter: RuntimeAdapter<Self>;
+
+    /// The triggers in `block` that match `filter`, in the order in which
+    /// their handlers have to run.
+    async fn triggers_in_block(
+        &self,
+        logger: &Logger,
+        block: Self::Block,
+        filter: &Self::TriggerFilter,
+    ) -> Result<BlockWithTriggers<Self>, Error>;
+
+    fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter>;
+}
+
+pub trait Block: Clone + Debug + Send + Sync + 'static {
+    fn ptr(&self) -> BlockPtr;
+
+    /// The pointer to the parent block, or `None` for the genesis block.
+    fn parent_ptr(&self) -> Option<BlockPtr>;
+
+    fn number(&self) -> u64 {
+        self.ptr().number
+    }
+}
+
+/// Something in a block that a data source has a handler for.
+pub trait TriggerData: Debug + Send + Sync + 'static {
+    /// The kind of the trigger, used as the value of metric labels.
+    fn kind(&self) -> &'static str;
+
+    /// The transaction the trigger is part of, if any.
+    fn transaction_hash(&self) -> Option<H256>;
+}
+
+/// Decides which triggers in a block the data sources of a subgraph have
+/// handlers for.
+pub trait TriggerFilter<C: Blockchain>: Clone + Send + Sync + 'static {
+    fn from_data_sources<'a>(data_sources: impl Iterator<Item = &'a DataSource> + Clone) -> Self;
+
+    /// Also match the triggers that `other` matches, e.g. those of data
+    /// sources created from templates.
+    fn extend(&mut self, other: Self);
+}
+
+/// What the WASM runtime needs from a chain beyond what all chains have in
+/// common.
+pub trait RuntimeAdapter<C: Blockchain>: Send + Sync + 'static {
+    /// The names of the host functions the chain provides to mappings,
+    /// e.g. `ethereum.call`.
+    fn host_fns(&self) -> &'static [&'static str];
+}
+
+/// A block together with the triggers in it that a subgraph handles.
+#[derive(Debug)]
+pub struct BlockWithTriggers<C: Blockchain> {
+    pub block: C::Block,
+    pub trigger_data: Vec<C::TriggerData>,
+}
+
+impl<C: Blockchain> BlockWithTriggers<C> {
+    pub fn ptr(&self) -> BlockPtr {
+        self.block.ptr()
+    }
+}
@@ -6,3 +6,12 @@ use failure::Error;
 pub trait ArweaveAdapter: Send + Sync {
     async fn tx_data(&self, tx_id: &str) -> Result<Bytes, Error>;
 }
+
+/// Whether `tx_id` looks like the id of an Arweave transaction, i.e. 43
+/// characters of base64url. Such ids are safe to interpolate into URLs.
+pub fn is_valid_tx_id(tx_id: &str) -> bool {
+    tx_id.len() == 43
+        && tx_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
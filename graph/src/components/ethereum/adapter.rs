@@ -15,6 +15,7 @@ use web3::types::*;
 use super::types::*;
 use crate::components::metrics::{CounterVec, GaugeVec, HistogramVec};
 use crate::prelude::*;
+use crate::util::ethereum::event_topic0;
 
 pub type EventSignature = H256;
 
@@ -45,9 +46,19 @@ pub struct EthereumContractState {
 #[derive(Clone, Debug)]
 pub struct EthereumContractCall {
     pub address: Address,
-    pub block_ptr: EthereumBlockPointer,
+    /// The block whose state the call runs against, pinned by its hash so
+    /// that a reorg can not change it. `None` runs the call against the
+    /// latest block and never caches its result.
+    pub block_ptr: Option<EthereumBlockPointer>,
+    /// Whether `block_ptr` is too far behind the chain head to be reorged
+    /// away. Results of calls against other blocks are not cached, since
+    /// the state they ran against may disappear.
+    pub block_is_final: bool,
     pub function: Function,
     pub args: Vec<Token>,
+    /// The call returns the same for every block, so that its result is
+    /// cached once for all blocks rather than by block
+    pub immutable: bool,
 }
 
 #[derive(Fail, Debug)]
@@ -59,8 +70,12 @@ pub enum EthereumContractCallError {
     TypeError(Token, ParamType),
     #[fail(display = "call error: {}", _0)]
     Web3Error(web3::Error),
+    /// The reason the node gave for the revert, and the revert data of the
+    /// call if the node returned it. With the ABI of the contract, the
+    /// data can be decoded into a better reason with
+    /// `util::ethereum::decode_revert_reason`
     #[fail(display = "call reverted: {}", _0)]
-    Revert(String),
+    Revert(String, Vec<u8>),
     #[fail(display = "ethereum node took too long to perform call")]
     Timeout,
 }
@@ -92,13 +107,111 @@ impl From<Error> for EthereumAdapterError {
     }
 }
 
+/// A request to an Ethereum node that failed for good, i.e., after it was
+/// retried as often as the request allows
+#[derive(Fail, Debug)]
+pub enum EthereumRpcError {
+    /// The node did not respond in time
+    #[fail(display = "{}", _0)]
+    Timeout(String),
+
+    /// The node responded with an error, or could not be reached
+    #[fail(display = "{}", _0)]
+    Provider(Error),
+
+    /// The node responded, but with data that does not fit what it returned
+    /// before, like a block that it no longer knows about or traces for the
+    /// wrong block. Usually the node lags behind or the chain reorganized
+    #[fail(display = "{}", _0)]
+    Inconsistent(String),
+}
+
+impl EthereumRpcError {
+    /// Turn the error of a request that was retried with a timeout into
+    /// an `EthereumRpcError`; `timeout` describes the request that timed out
+    pub fn from_timeout(e: TimeoutError<Error>, timeout: impl FnOnce() -> String) -> Error {
+        match e.into_inner() {
+            Some(e) => match e.downcast::<EthereumRpcError>() {
+                Ok(e) => e.into(),
+                Err(e) => EthereumRpcError::Provider(e).into(),
+            },
+            None => EthereumRpcError::Timeout(timeout()).into(),
+        }
+    }
+
+    pub fn kind(&self) -> RpcErrorKind {
+        match self {
+            EthereumRpcError::Timeout(_) => RpcErrorKind::ProviderTimeout,
+            EthereumRpcError::Provider(_) => RpcErrorKind::ProviderError,
+            EthereumRpcError::Inconsistent(_) => RpcErrorKind::DataInconsistency,
+        }
+    }
+}
+
+/// The kinds of `EthereumRpcError`s. Each kind is retried differently by
+/// the block stream, and is reported separately in metrics and in the
+/// retries of a deployment
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RpcErrorKind {
+    ProviderTimeout,
+    ProviderError,
+    DataInconsistency,
+}
+
+impl RpcErrorKind {
+    /// The kind of the `EthereumRpcError` that caused `e`, if any
+    pub fn of(e: &Error) -> Option<Self> {
+        e.iter_chain().find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<EthereumRpcError>() {
+                Some(e.kind())
+            } else {
+                match cause.downcast_ref::<EthereumAdapterError>() {
+                    Some(EthereumAdapterError::BlockUnavailable(_)) => {
+                        Some(RpcErrorKind::DataInconsistency)
+                    }
+                    Some(EthereumAdapterError::Unknown(e)) => Self::of(e),
+                    None => None,
+                }
+            }
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RpcErrorKind::ProviderTimeout => "provider_timeout",
+            RpcErrorKind::ProviderError => "provider_error",
+            RpcErrorKind::DataInconsistency => "data_inconsistency",
+        }
+    }
+
+    /// How long to wait before retrying after the `attempt`-th error in a
+    /// row of this kind. A node that times out is likely overloaded and
+    /// gets a little while, one that fails gets more time to recover, and
+    /// one that lags behind usually catches up within a few blocks
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let (step, max) = match self {
+            RpcErrorKind::ProviderTimeout => (5, 60),
+            RpcErrorKind::ProviderError => (5, 120),
+            RpcErrorKind::DataInconsistency => (2, 30),
+        };
+        Duration::from_secs((step * attempt as u64).min(max))
+    }
+}
+
+impl fmt::Display for RpcErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 enum LogFilterNode {
     Contract(Address),
     Event(EventSignature),
 }
 
-/// Corresponds to an `eth_getLogs` call.
+/// Corresponds to an `eth_getLogs` call. An empty list of event signatures
+/// matches all logs of the contracts.
 #[derive(Clone)]
 pub struct EthGetLogsFilter {
     pub contracts: Vec<Address>,
@@ -114,6 +227,8 @@ impl fmt::Display for EthGetLogsFilter {
                 self.contracts[0],
                 self.event_signatures.len()
             )
+        } else if self.event_signatures.is_empty() {
+            write!(f, "all events, {} contracts", self.contracts.len())
         } else if self.event_signatures.len() == 1 {
             write!(
                 f,
@@ -136,6 +251,10 @@ pub struct EthereumLogFilter {
 
     // Event sigs with no associated address, matching on all addresses.
     wildcard_events: HashSet<EventSignature>,
+
+    // Contracts with handlers for anonymous events. Since the logs of such events do not
+    // start with the event signature, all logs of these contracts match.
+    anonymous_event_contracts: HashSet<Address>,
 }
 
 impl EthereumLogFilter {
@@ -149,6 +268,10 @@ impl EthereumLogFilter {
 
     /// Check if this filter matches the specified `Log`.
     pub fn matches(&self, log: &Log) -> bool {
+        if self.anonymous_event_contracts.contains(&log.address) {
+            return true;
+        }
+
         // First topic should be event sig
         match log.topics.first() {
             None => false,
@@ -172,7 +295,24 @@ impl EthereumLogFilter {
     pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
         let mut this = EthereumLogFilter::default();
         for ds in iter {
-            for event_sig in ds.mapping.event_handlers.iter().map(|e| e.topic0()) {
+            if let Some(contract) = ds.source.address {
+                if !ds.mapping.anonymous_event_handlers(&ds.source).is_empty() {
+                    this.anonymous_event_contracts.insert(contract);
+                }
+            }
+            let event_sigs = ds
+                .mapping
+                .event_handlers
+                .iter()
+                .filter(|handler| !handler.is_wildcard())
+                .map(|handler| handler.topic0())
+                .chain(
+                    ds.mapping
+                        .wildcard_events(&ds.source)
+                        .into_iter()
+                        .map(event_topic0),
+                );
+            for event_sig in event_sigs {
                 match ds.source.address {
                     Some(contract) => {
                         this.contracts_and_events_graph.add_edge(
@@ -196,11 +336,14 @@ impl EthereumLogFilter {
         let EthereumLogFilter {
             contracts_and_events_graph,
             wildcard_events,
+            anonymous_event_contracts,
         } = other;
         for (s, t, ()) in contracts_and_events_graph.all_edges() {
             self.contracts_and_events_graph.add_edge(s, t, ());
         }
         self.wildcard_events.extend(wildcard_events);
+        self.anonymous_event_contracts
+            .extend(anonymous_event_contracts);
     }
 
     /// An empty filter is one that never matches.
@@ -209,8 +352,11 @@ impl EthereumLogFilter {
         let EthereumLogFilter {
             contracts_and_events_graph,
             wildcard_events,
+            anonymous_event_contracts,
         } = self;
-        contracts_and_events_graph.edge_count() == 0 && wildcard_events.is_empty()
+        contracts_and_events_graph.edge_count() == 0
+            && wildcard_events.is_empty()
+            && anonymous_event_contracts.is_empty()
     }
 
     /// Filters for `eth_getLogs` calls. The filters will not return false positives. This attempts
@@ -227,6 +373,19 @@ impl EthereumLogFilter {
             })
         }
 
+        // Contracts with anonymous events need all of their logs, which also covers any other
+        // events of these contracts.
+        let mut g = self.contracts_and_events_graph;
+        if !self.anonymous_event_contracts.is_empty() {
+            for contract in &self.anonymous_event_contracts {
+                g.remove_node(LogFilterNode::Contract(*contract));
+            }
+            filters.push(EthGetLogsFilter {
+                contracts: self.anonymous_event_contracts.into_iter().collect(),
+                event_signatures: vec![],
+            });
+        }
+
         // The current algorithm is to repeatedly find the maximum cardinality vertex and turn all
         // of its edges into a filter. This is nice because it is neutral between filtering by
         // contract or by events, if there are many events that appear on only one data source
@@ -240,7 +399,6 @@ impl EthereumLogFilter {
         // single node. For example if a subgraph has two data sources, each with the same two
         // events, we could cover that with a single filter and no false positives. However that
         // might cause the filter to become too broad, so at the moment it seems excessive.
-        let mut g = self.contracts_and_events_graph;
         while g.edge_count() > 0 {
             // If there are edges, there are vertexes.
             let max_vertex = g.nodes().max_by_key(|&n| g.neighbors(n).count()).unwrap();
@@ -278,10 +436,20 @@ pub struct EthereumCallFilter {
     // Each call filter has a map of filters keyed by address, each containing a tuple with
     // start_block and the set of function signatures
     pub contract_addresses_function_signatures: HashMap<Address, (u64, HashSet<[u8; 4]>)>,
+
+    // The contracts and function signatures for which failed calls match, too. Only successful
+    // calls match otherwise.
+    pub failed_calls: HashSet<(Address, [u8; 4])>,
 }
 
 impl EthereumCallFilter {
     pub fn matches(&self, call: &EthereumCall) -> bool {
+        if call.is_failed() {
+            let mut sig = [0u8; 4];
+            sig.copy_from_slice(&call.input.0[..4]);
+            return self.failed_calls.contains(&(call.to, sig));
+        }
+
         // Ensure the call is to a contract the filter expressed an interest in
         if !self
             .contract_addresses_function_signatures
@@ -311,7 +479,8 @@ impl EthereumCallFilter {
     }
 
     pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
-        iter.into_iter()
+        let handlers = iter
+            .into_iter()
             .filter_map(|data_source| data_source.source.address.map(|addr| (addr, data_source)))
             .map(|(contract_addr, data_source)| {
                 let start_block = data_source.source.start_block;
@@ -321,15 +490,29 @@ impl EthereumCallFilter {
                     .iter()
                     .map(move |call_handler| {
                         let sig = keccak256(call_handler.function.as_bytes());
-                        (start_block, contract_addr, [sig[0], sig[1], sig[2], sig[3]])
+                        let sig = [sig[0], sig[1], sig[2], sig[3]];
+                        (start_block, contract_addr, sig, call_handler.include_failed)
                     })
             })
             .flatten()
-            .collect()
+            .collect::<Vec<_>>();
+
+        let mut filter: Self = handlers
+            .iter()
+            .map(|(start_block, contract_addr, sig, _)| (*start_block, *contract_addr, *sig))
+            .collect();
+        filter.failed_calls = handlers
+            .into_iter()
+            .filter(|(_, _, _, include_failed)| *include_failed)
+            .map(|(_, contract_addr, sig, _)| (contract_addr, sig))
+            .collect();
+        filter
     }
 
     /// Extends this call filter with another one.
     pub fn extend(&mut self, other: EthereumCallFilter) {
+        self.failed_calls.extend(other.failed_calls);
+
         // Extend existing address / function signature key pairs
         // Add new address / function signature key pairs from the provided EthereumCallFilter
         for (address, (proposed_start_block, new_sigs)) in
@@ -357,7 +540,9 @@ impl EthereumCallFilter {
         // Destructure to make sure we're checking all fields.
         let EthereumCallFilter {
             contract_addresses_function_signatures,
+            failed_calls: _,
         } = self;
+        // Failed calls only match for functions that are also in the filter
         contract_addresses_function_signatures.is_empty()
     }
 
@@ -391,6 +576,7 @@ impl FromIterator<(u64, Address, [u8; 4])> for EthereumCallFilter {
             });
         EthereumCallFilter {
             contract_addresses_function_signatures: lookup,
+            failed_calls: HashSet::new(),
         }
     }
 }
@@ -403,6 +589,7 @@ impl From<EthereumBlockFilter> for EthereumCallFilter {
                 .into_iter()
                 .map(|(start_block_opt, address)| (address, (start_block_opt, HashSet::default())))
                 .collect::<HashMap<Address, (u64, HashSet<[u8; 4]>)>>(),
+            failed_calls: HashSet::new(),
         }
     }
 }
@@ -416,8 +603,17 @@ pub struct EthereumBlockFilter {
 impl EthereumBlockFilter {
     pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
         iter.into_iter()
-            .filter(|data_source| data_source.source.address.is_some())
+            .filter(|data_source| {
+                data_source.source.address.is_some() || data_source.source_subgraph().is_some()
+            })
             .fold(Self::default(), |mut filter_opt, data_source| {
+                // Entity changes of the source deployment of a `subgraph` data
+                // source are checked for in every block
+                if data_source.source_subgraph().is_some() {
+                    filter_opt.trigger_every_block = true;
+                    return filter_opt;
+                }
+
                 let has_block_handler_with_call_filter = data_source
                     .mapping
                     .block_handlers
@@ -489,6 +685,8 @@ impl EthereumBlockFilter {
 pub struct ProviderEthRpcMetrics {
     request_duration: Box<HistogramVec>,
     errors: Box<CounterVec>,
+    retries: Box<CounterVec>,
+    retries_exhausted: Box<CounterVec>,
 }
 
 impl ProviderEthRpcMetrics {
@@ -510,9 +708,39 @@ impl ProviderEthRpcMetrics {
                 vec![String::from("method")],
             )
             .unwrap();
+        let retries = registry
+            .new_counter_vec(
+                String::from("eth_rpc_retries"),
+                String::from(
+                    "Counts the eth rpc requests that failed or timed out and were retried",
+                ),
+                HashMap::new(),
+                vec![
+                    String::from("provider"),
+                    String::from("network"),
+                    String::from("operation"),
+                ],
+            )
+            .unwrap();
+        let retries_exhausted = registry
+            .new_counter_vec(
+                String::from("eth_rpc_retries_exhausted"),
+                String::from(
+                    "Counts the eth rpc requests that failed after running out of retries",
+                ),
+                HashMap::new(),
+                vec![
+                    String::from("provider"),
+                    String::from("network"),
+                    String::from("operation"),
+                ],
+            )
+            .unwrap();
         Self {
             request_duration,
             errors,
+            retries,
+            retries_exhausted,
         }
     }
 
@@ -525,6 +753,18 @@ impl ProviderEthRpcMetrics {
     pub fn add_error(&self, method: &str) {
         self.errors.with_label_values(vec![method].as_slice()).inc();
     }
+
+    pub fn add_retry(&self, provider: &str, network: &str, operation: &str) {
+        self.retries
+            .with_label_values(vec![provider, network, operation].as_slice())
+            .inc();
+    }
+
+    pub fn add_retries_exhausted(&self, provider: &str, network: &str, operation: &str) {
+        self.retries_exhausted
+            .with_label_values(vec![provider, network, operation].as_slice())
+            .inc();
+    }
 }
 
 #[derive(Clone)]
@@ -574,6 +814,7 @@ pub struct BlockStreamMetrics {
     pub blocks_behind: Box<Gauge>,
     pub reverted_blocks: Box<Gauge>,
     pub stopwatch: StopwatchMetrics,
+    errors: Box<CounterVec>,
 }
 
 impl BlockStreamMetrics {
@@ -599,13 +840,29 @@ impl BlockStreamMetrics {
                 HashMap::new(),
             )
             .expect("Failed to create `subgraph_reverted_blocks` gauge");
+        let errors = registry
+            .new_counter_vec(
+                format!("subgraph_block_stream_errors_{}", deployment_id.to_string()),
+                String::from("Counts the errors of the block stream of a subgraph deployment"),
+                HashMap::new(),
+                vec![String::from("kind")],
+            )
+            .expect("Failed to create `subgraph_block_stream_errors` counter");
         Self {
             ethrpc_metrics,
             blocks_behind,
             reverted_blocks,
             stopwatch,
+            errors,
         }
     }
+
+    /// Count an error of the block stream; errors that did not come from
+    /// the Ethereum node are counted as `other`
+    pub fn add_error(&self, kind: Option<RpcErrorKind>) {
+        let kind = kind.map_or("other", |kind| kind.as_str());
+        self.errors.with_label_values(vec![kind].as_slice()).inc();
+    }
 }
 
 /// Common trait for components that watch and manage access to Ethereum.
@@ -616,6 +873,20 @@ impl BlockStreamMetrics {
 pub trait EthereumAdapter: Send + Sync + 'static {
     fn url_hostname(&self) -> &str;
 
+    /// Whether the provider-specific JSON-RPC `method`, like
+    /// `erigon_getBlockByTimestamp`, was registered for this adapter.
+    fn supports_custom_rpc(&self, method: &str) -> bool;
+
+    /// Call the provider-specific JSON-RPC `method` with `params` and
+    /// return its raw result. Fails if `method` was not registered for
+    /// this adapter.
+    fn custom_rpc(
+        &self,
+        logger: &Logger,
+        method: &str,
+        params: Vec<serde_json::Value>,
+    ) -> Box<dyn Future<Item = serde_json::Value, Error = Error> + Send>;
+
     /// Ask the Ethereum node for some identifying information about the Ethereum network it is
     /// connected to.
     fn net_identifiers(
@@ -686,6 +957,20 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         block_number: u64,
     ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send>;
 
+    /// Find the number of the last block whose timestamp, in seconds, is at
+    /// or before `timestamp`, looking no further than block `up_to`, or the
+    /// latest block if that is `None`. Returns `None` if `timestamp` is
+    /// before the genesis block or after block `up_to`. The blocks cached
+    /// in `chain_store`, if given, narrow down the binary search over the
+    /// blocks of the Ethereum node.
+    fn block_number_by_timestamp(
+        &self,
+        logger: &Logger,
+        chain_store: Option<Arc<dyn ChainStore>>,
+        timestamp: u64,
+        up_to: Option<u64>,
+    ) -> Box<dyn Future<Item = Option<u64>, Error = Error> + Send>;
+
     /// Find a block by its number. The `block_is_final` flag indicates whether
     /// it is ok to remove blocks in the block cache with that number but with
     /// a different hash which were left over from reorgs we saw before we
@@ -868,30 +1153,20 @@ pub async fn triggers_in_block(
     }
 }
 
-/// Returns blocks with triggers, corresponding to the specified range and filters.
-/// If a block contains no triggers, there may be no corresponding item in the stream.
-/// However the `to` block will always be present, even if triggers are empty.
-///
-/// Careful: don't use this function without considering race conditions.
-/// Chain reorgs could happen at any time, and could affect the answer received.
-/// Generally, it is only safe to use this function with blocks that have received enough
-/// confirmations to guarantee no further reorgs, **and** where the Ethereum node is aware of
-/// those confirmations.
-/// If the Ethereum node is far behind in processing blocks, even old blocks can be subject to
-/// reorgs.
-/// It is recommended that `to` be far behind the block number of latest block the Ethereum
-/// node is aware of.
-pub fn blocks_with_triggers(
+/// Returns the triggers in the blocks `from` to `to`, where `to_hash` is the
+/// hash of block `to`.
+fn triggers_in_block_range(
     adapter: Arc<dyn EthereumAdapter>,
     logger: Logger,
     chain_store: Arc<dyn ChainStore>,
     subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
     from: u64,
     to: u64,
+    to_hash: H256,
     log_filter: EthereumLogFilter,
     call_filter: EthereumCallFilter,
     block_filter: EthereumBlockFilter,
-) -> Box<dyn Future<Item = Vec<EthereumBlockWithTriggers>, Error = Error> + Send> {
+) -> impl Future<Item = Vec<EthereumTrigger>, Error = Error> + Send {
     // Each trigger filter needs to be queried for the same block range
     // and the blocks yielded need to be deduped. If any error occurs
     // while searching for a trigger type, the entire operation fails.
@@ -903,9 +1178,17 @@ pub fn blocks_with_triggers(
     // Scan the block range from triggers to find relevant blocks
     if !log_filter.is_empty() {
         trigger_futs.push(Box::new(
-            eth.logs_in_block_range(&logger, subgraph_metrics.clone(), from, to, log_filter)
-                .map_ok(|logs: Vec<Log>| logs.into_iter().map(EthereumTrigger::Log).collect())
-                .compat(),
+            Box::pin(log_triggers_in_block_range(
+                eth.clone(),
+                logger.clone(),
+                chain_store,
+                subgraph_metrics.clone(),
+                from,
+                to,
+                to_hash,
+                log_filter,
+            ))
+            .compat(),
         ))
     }
 
@@ -944,28 +1227,143 @@ pub fn blocks_with_triggers(
         ));
     }
 
+    trigger_futs.concat2()
+}
+
+/// Returns the log triggers in the blocks `from` to `to`. The chain store
+/// often has many of these blocks with their receipts already, for example
+/// when a deployment catches up after a restart. A bitmap marks the blocks
+/// that the store has with all receipts and that are ancestors of `to_hash`;
+/// their logs are taken from the store, and only the logs of the remaining
+/// ranges of blocks are requested from the Ethereum node.
+async fn log_triggers_in_block_range(
+    adapter: Arc<dyn EthereumAdapter>,
+    logger: Logger,
+    chain_store: Arc<dyn ChainStore>,
+    subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+    from: u64,
+    to: u64,
+    to_hash: H256,
+    log_filter: EthereumLogFilter,
+) -> Result<Vec<EthereumTrigger>, Error> {
+    let stored = match chain_store.ancestor_blocks(to_hash, from) {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            warn!(logger, "Failed to load blocks from the chain store"; "error" => e.to_string());
+            vec![]
+        }
+    };
+
+    let mut complete = vec![false; (to - from + 1) as usize];
+    let mut triggers = Vec::new();
+    for block in stored {
+        if block.transaction_receipts.len() != block.block.transactions.len() {
+            continue;
+        }
+        complete[(block.block.number() - from) as usize] = true;
+        triggers.extend(parse_log_triggers(log_filter.clone(), &block));
+    }
+
+    let mut missing = Vec::new();
+    let mut start = None;
+    for (offset, is_complete) in complete.iter().enumerate() {
+        let number = from + offset as u64;
+        match (is_complete, start) {
+            (false, None) => start = Some(number),
+            (true, Some(first)) => {
+                missing.push((first, number - 1));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = start {
+        missing.push((first, to));
+    }
+
+    if missing.len() != 1 || missing[0] != (from, to) {
+        debug!(
+            logger,
+            "Found {} of {} block(s) in the chain store",
+            complete.iter().filter(|complete| **complete).count(),
+            complete.len()
+        );
+    }
+
+    for (start, end) in missing {
+        let logs = adapter
+            .logs_in_block_range(
+                &logger,
+                subgraph_metrics.cheap_clone(),
+                start,
+                end,
+                log_filter.clone(),
+            )
+            .await?;
+        triggers.extend(logs.into_iter().map(EthereumTrigger::Log));
+    }
+    Ok(triggers)
+}
+
+/// Returns blocks with triggers, corresponding to the specified range and filters.
+/// If a block contains no triggers, there may be no corresponding item in the stream.
+/// However the `to` block will always be present, even if triggers are empty.
+///
+/// Careful: don't use this function without considering race conditions.
+/// Chain reorgs could happen at any time, and could affect the answer received.
+/// Generally, it is only safe to use this function with blocks that have received enough
+/// confirmations to guarantee no further reorgs, **and** where the Ethereum node is aware of
+/// those confirmations.
+/// If the Ethereum node is far behind in processing blocks, even old blocks can be subject to
+/// reorgs.
+/// It is recommended that `to` be far behind the block number of latest block the Ethereum
+/// node is aware of.
+pub fn blocks_with_triggers(
+    adapter: Arc<dyn EthereumAdapter>,
+    logger: Logger,
+    chain_store: Arc<dyn ChainStore>,
+    subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+    from: u64,
+    to: u64,
+    log_filter: EthereumLogFilter,
+    call_filter: EthereumCallFilter,
+    block_filter: EthereumBlockFilter,
+) -> Box<dyn Future<Item = Vec<EthereumBlockWithTriggers>, Error = Error> + Send> {
     let logger1 = logger.cheap_clone();
     let logger2 = logger.cheap_clone();
-    let eth_clone = eth.cheap_clone();
+    let eth = adapter.clone();
+    let chain_store1 = chain_store.cheap_clone();
     Box::new(
-        trigger_futs
-            .concat2()
-            .join(
-                adapter
-                    .clone()
-                    .block_hash_by_block_number(&logger, chain_store.clone(), to, true)
-                    .then(move |to_hash| match to_hash {
-                        Ok(n) => n.ok_or_else(|| {
-                            warn!(logger2,
-                                    "Ethereum endpoint is behind";
-                                    "url" => eth_clone.url_hostname()
-                            );
-                            format_err!("Block {} not found in the chain", to)
-                        }),
-                        Err(e) => Err(e),
-                    }),
-            )
-            .map(move |(triggers, to_hash)| {
+        adapter
+            .clone()
+            .block_hash_by_block_number(&logger, chain_store.clone(), to, true)
+            .then(move |to_hash| match to_hash {
+                Ok(n) => n.ok_or_else(|| {
+                    warn!(logger2,
+                            "Ethereum endpoint is behind";
+                            "url" => eth.url_hostname()
+                    );
+                    EthereumRpcError::Inconsistent(format!("Block {} not found in the chain", to))
+                        .into()
+                }),
+                Err(e) => Err(e),
+            })
+            .and_then(move |to_hash| {
+                triggers_in_block_range(
+                    adapter.clone(),
+                    logger.clone(),
+                    chain_store1,
+                    subgraph_metrics,
+                    from,
+                    to,
+                    to_hash,
+                    log_filter,
+                    call_filter,
+                    block_filter,
+                )
+                .map(move |triggers| (triggers, to_hash, adapter, logger))
+            })
+            .map(move |(triggers, to_hash, adapter, logger)| {
                 let mut block_hashes: HashSet<H256> =
                     triggers.iter().map(EthereumTrigger::block_hash).collect();
                 let mut triggers_by_block: HashMap<u64, Vec<EthereumTrigger>> =
@@ -980,9 +1378,9 @@ pub fn blocks_with_triggers(
                 block_hashes.insert(to_hash);
                 triggers_by_block.entry(to).or_insert(Vec::new());
 
-                (block_hashes, triggers_by_block)
+                (block_hashes, triggers_by_block, adapter)
             })
-            .and_then(move |(block_hashes, mut triggers_by_block)| {
+            .and_then(move |(block_hashes, mut triggers_by_block, adapter)| {
                 adapter
                     .load_blocks(logger1, chain_store, block_hashes)
                     .map(move |block| {
@@ -1004,9 +1402,13 @@ pub fn blocks_with_triggers(
 
 #[cfg(test)]
 mod tests {
-    use super::EthereumCallFilter;
+    use super::{
+        EthereumAdapterError, EthereumCall, EthereumCallFilter, EthereumLogFilter,
+        EthereumRpcError, LogFilterNode, RpcErrorKind,
+    };
+    use crate::prelude::{err_msg, Error, TimeoutError};
 
-    use web3::types::Address;
+    use web3::types::{Address, Bytes, H256};
 
     use std::collections::{HashMap, HashSet};
     use std::iter::FromIterator;
@@ -1024,6 +1426,7 @@ mod tests {
                     (1, HashSet::from_iter(vec![[1u8; 4]])),
                 ),
             ]),
+            failed_calls: HashSet::new(),
         };
         let extension = EthereumCallFilter {
             contract_addresses_function_signatures: HashMap::from_iter(vec![
@@ -1036,6 +1439,7 @@ mod tests {
                     (3, HashSet::from_iter(vec![[3u8; 4]])),
                 ),
             ]),
+            failed_calls: HashSet::new(),
         };
         base.extend(extension);
 
@@ -1055,4 +1459,93 @@ mod tests {
             Some(&(1, HashSet::from_iter(vec![[1u8; 4]])))
         );
     }
+
+    #[test]
+    fn call_filter_matches_failed_calls() {
+        let contract = Address::from_low_u64_be(1);
+        let filter = EthereumCallFilter {
+            contract_addresses_function_signatures: HashMap::from_iter(vec![(
+                contract,
+                (0, HashSet::from_iter(vec![[1u8; 4], [2u8; 4]])),
+            )]),
+            failed_calls: HashSet::from_iter(vec![(contract, [2u8; 4])]),
+        };
+
+        let call = |sig: [u8; 4], error: Option<&str>| {
+            let mut call = EthereumCall::default();
+            call.to = contract;
+            call.input = Bytes(sig.to_vec());
+            call.error = error.map(str::to_owned);
+            call
+        };
+
+        assert!(filter.matches(&call([1u8; 4], None)));
+        assert!(!filter.matches(&call([1u8; 4], Some("Reverted"))));
+        assert!(filter.matches(&call([2u8; 4], Some("Reverted"))));
+        assert!(!filter.matches(&call([3u8; 4], Some("Reverted"))));
+    }
+
+    #[test]
+    fn log_filters_for_anonymous_events() {
+        let anonymous = Address::from_low_u64_be(1);
+        let other = Address::from_low_u64_be(2);
+        let event = H256::from_low_u64_be(3);
+
+        let mut filter = EthereumLogFilter::default();
+        for contract in &[anonymous, other] {
+            filter.contracts_and_events_graph.add_edge(
+                LogFilterNode::Contract(*contract),
+                LogFilterNode::Event(event),
+                (),
+            );
+        }
+        filter.anonymous_event_contracts.insert(anonymous);
+
+        // All logs of the contract with anonymous events are requested, which
+        // also covers its other events
+        let filters = filter.eth_get_logs_filters().collect::<Vec<_>>();
+        assert_eq!(2, filters.len());
+        assert_eq!(vec![anonymous], filters[0].contracts);
+        assert!(filters[0].event_signatures.is_empty());
+        assert_eq!(vec![other], filters[1].contracts);
+        assert_eq!(vec![event], filters[1].event_signatures);
+    }
+
+    #[test]
+    fn rpc_error_kinds() {
+        let timeout = EthereumRpcError::from_timeout(TimeoutError::Elapsed, || "slow".to_owned());
+        assert_eq!(
+            Some(RpcErrorKind::ProviderTimeout),
+            RpcErrorKind::of(&timeout)
+        );
+        assert_eq!("slow", timeout.to_string());
+
+        let failed = EthereumRpcError::from_timeout(TimeoutError::Inner(err_msg("boom")), || {
+            "slow".to_owned()
+        });
+        assert_eq!(Some(RpcErrorKind::ProviderError), RpcErrorKind::of(&failed));
+
+        // Errors that already have a kind keep it, also when wrapped
+        let inconsistent = Error::from(EthereumRpcError::Inconsistent("missing".to_owned()));
+        let inconsistent =
+            EthereumRpcError::from_timeout(TimeoutError::Inner(inconsistent), || "slow".to_owned());
+        let wrapped = Error::from(EthereumAdapterError::Unknown(inconsistent));
+        assert_eq!(
+            Some(RpcErrorKind::DataInconsistency),
+            RpcErrorKind::of(&wrapped)
+        );
+
+        assert_eq!(None, RpcErrorKind::of(&err_msg("store error")));
+    }
+
+    #[test]
+    fn rpc_error_retry_delays() {
+        assert_eq!(10, RpcErrorKind::ProviderError.retry_delay(2).as_secs());
+        assert_eq!(120, RpcErrorKind::ProviderError.retry_delay(100).as_secs());
+        assert_eq!(60, RpcErrorKind::ProviderTimeout.retry_delay(100).as_secs());
+        assert_eq!(
+            30,
+            RpcErrorKind::DataInconsistency.retry_delay(100).as_secs()
+        );
+    }
 }
@@ -0,0 +1,134 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+use web3::types::H256;
+
+use super::adapter::{
+    triggers_in_block, EthereumAdapter, EthereumBlockFilter, EthereumCallFilter, EthereumLogFilter,
+    SubgraphEthRpcMetrics,
+};
+use super::types::{BlockFinality, EthereumBlockPointer, EthereumTrigger, LightEthereumBlockExt};
+use crate::blockchain::{
+    self, BlockPtr, BlockWithTriggers, Blockchain, RuntimeAdapter, TriggerData, TriggerFilter,
+};
+use crate::prelude::*;
+
+/// Ethereum as a `Blockchain`, reading blocks through `adapter`.
+pub struct EthereumChain {
+    adapter: Arc<dyn EthereumAdapter>,
+    chain_store: Arc<dyn ChainStore>,
+    metrics: Arc<SubgraphEthRpcMetrics>,
+}
+
+impl EthereumChain {
+    pub fn new(
+        adapter: Arc<dyn EthereumAdapter>,
+        chain_store: Arc<dyn ChainStore>,
+        metrics: Arc<SubgraphEthRpcMetrics>,
+    ) -> Self {
+        EthereumChain {
+            adapter,
+            chain_store,
+            metrics,
+        }
+    }
+}
+
+#[async_trait]
+impl Blockchain for EthereumChain {
+    const KIND: &'static str = "ethereum";
+
+    type Block = BlockFinality;
+    type TriggerData = EthereumTrigger;
+    type TriggerFilter = EthereumTriggerFilter;
+    type RuntimeAdapter = EthereumRuntimeAdapter;
+
+    async fn triggers_in_block(
+        &self,
+        logger: &Logger,
+        block: BlockFinality,
+        filter: &EthereumTriggerFilter,
+    ) -> Result<BlockWithTriggers<Self>, Error> {
+        let block = triggers_in_block(
+            self.adapter.clone(),
+            logger.clone(),
+            self.chain_store.clone(),
+            self.metrics.clone(),
+            filter.log.clone(),
+            filter.call.clone(),
+            filter.block.clone(),
+            block,
+        )
+        .await?;
+        Ok(BlockWithTriggers {
+            block: block.ethereum_block,
+            trigger_data: block.triggers,
+        })
+    }
+
+    fn runtime_adapter(&self) -> Arc<EthereumRuntimeAdapter> {
+        Arc::new(EthereumRuntimeAdapter)
+    }
+}
+
+impl blockchain::Block for BlockFinality {
+    fn ptr(&self) -> BlockPtr {
+        EthereumBlockPointer::from(self)
+    }
+
+    fn parent_ptr(&self) -> Option<BlockPtr> {
+        match self {
+            BlockFinality::Final(block) => block.parent_ptr(),
+            BlockFinality::NonFinal(block) => block.ethereum_block.block.parent_ptr(),
+        }
+    }
+}
+
+impl TriggerData for EthereumTrigger {
+    fn kind(&self) -> &'static str {
+        match self {
+            EthereumTrigger::Log(_) => "event",
+            EthereumTrigger::Call(_) => "call",
+            EthereumTrigger::Block(..) => "block",
+        }
+    }
+
+    fn transaction_hash(&self) -> Option<H256> {
+        match self {
+            EthereumTrigger::Log(log) => log.transaction_hash,
+            EthereumTrigger::Call(call) => call.transaction_hash,
+            EthereumTrigger::Block(..) => None,
+        }
+    }
+}
+
+/// The log, call and block filters of a subgraph.
+#[derive(Clone, Debug)]
+pub struct EthereumTriggerFilter {
+    pub log: EthereumLogFilter,
+    pub call: EthereumCallFilter,
+    pub block: EthereumBlockFilter,
+}
+
+impl TriggerFilter<EthereumChain> for EthereumTriggerFilter {
+    fn from_data_sources<'a>(data_sources: impl Iterator<Item = &'a DataSource> + Clone) -> Self {
+        EthereumTriggerFilter {
+            log: EthereumLogFilter::from_data_sources(data_sources.clone()),
+            call: EthereumCallFilter::from_data_sources(data_sources.clone()),
+            block: EthereumBlockFilter::from_data_sources(data_sources),
+        }
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.log.extend(other.log);
+        self.call.extend(other.call);
+        self.block.extend(other.block);
+    }
+}
+
+pub struct EthereumRuntimeAdapter;
+
+impl RuntimeAdapter<EthereumChain> for EthereumRuntimeAdapter {
+    fn host_fns(&self) -> &'static [&'static str] {
+        &["ethereum.call"]
+    }
+}
@@ -1,6 +1,8 @@
 mod adapter;
+mod chain;
 mod listener;
 mod network;
+pub mod rpc_trace;
 mod stream;
 mod types;
 
@@ -8,9 +10,10 @@ pub use self::adapter::{
     blocks_with_triggers, triggers_in_block, BlockStreamMetrics, EthGetLogsFilter, EthereumAdapter,
     EthereumAdapterError, EthereumBlockFilter, EthereumCallFilter, EthereumContractCall,
     EthereumContractCallError, EthereumContractState, EthereumContractStateError,
-    EthereumContractStateRequest, EthereumLogFilter, EthereumNetworkIdentifier,
-    MockEthereumAdapter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+    EthereumContractStateRequest, EthereumLogFilter, EthereumNetworkIdentifier, EthereumRpcError,
+    MockEthereumAdapter, ProviderEthRpcMetrics, RpcErrorKind, SubgraphEthRpcMetrics,
 };
+pub use self::chain::{EthereumChain, EthereumRuntimeAdapter, EthereumTriggerFilter};
 pub use self::listener::{ChainHeadUpdate, ChainHeadUpdateListener, ChainHeadUpdateStream};
 pub use self::network::{EthereumNetworkAdapters, EthereumNetworks, NodeCapabilities};
 pub use self::stream::{BlockStream, BlockStreamBuilder, BlockStreamEvent};
@@ -18,5 +21,5 @@ pub use self::types::{
     BlockFinality, EthereumBlock, EthereumBlockData, EthereumBlockPointer,
     EthereumBlockTriggerType, EthereumBlockWithCalls, EthereumBlockWithTriggers, EthereumCall,
     EthereumCallData, EthereumEventData, EthereumTransactionData, EthereumTrigger,
-    LightEthereumBlock, LightEthereumBlockExt,
+    EthereumWildcardEventData, LightEthereumBlock, LightEthereumBlockExt,
 };
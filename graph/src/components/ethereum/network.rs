@@ -113,6 +113,17 @@ impl EthereumNetworkAdapters {
         Ok(&sufficient_adapters.iter().choose(&mut rng).unwrap().adapter)
     }
 
+    /// An adapter that supports the provider-specific JSON-RPC `method`,
+    /// chosen at random if there are several
+    pub fn with_custom_rpc(&self, method: &str) -> Option<&Arc<dyn EthereumAdapter>> {
+        let mut rng = rand::thread_rng();
+        self.adapters
+            .iter()
+            .filter(|adapter| adapter.adapter.supports_custom_rpc(method))
+            .choose(&mut rng)
+            .map(|adapter| &adapter.adapter)
+    }
+
     pub fn cheapest(&self) -> Option<&Arc<dyn EthereumAdapter>> {
         // EthereumAdapters are sorted by their NodeCapabilities when the EthereumNetworks
         // struct is instantiated so they do not need to be sorted here
@@ -191,6 +202,18 @@ impl EthereumNetworks {
             .ok_or(format_err!("network not supported: {}", &network_name))
             .and_then(|adapters| adapters.cheapest_with(requirements))
     }
+
+    /// An adapter for `network_name` whose provider supports the
+    /// provider-specific JSON-RPC `method`, if there is one
+    pub fn adapter_with_custom_rpc(
+        &self,
+        network_name: &str,
+        method: &str,
+    ) -> Option<&Arc<dyn EthereumAdapter>> {
+        self.networks
+            .get(network_name)
+            .and_then(|adapters| adapters.with_custom_rpc(method))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,33 @@
+//! Keeps track of the deployment that a task works for, so that the
+//! JSON-RPC calls made on behalf of some deployments can be traced, and
+//! so that calls are limited according to the deployment's priority.
+use std::future::Future;
+
+use crate::components::subgraph::DeploymentPriority;
+use crate::data::subgraph::SubgraphDeploymentId;
+
+tokio::task_local! {
+    static DEPLOYMENT: (SubgraphDeploymentId, DeploymentPriority);
+}
+
+/// Run `f` as the work of the deployment `id`, which has `priority`
+pub async fn for_deployment<F: Future>(
+    id: SubgraphDeploymentId,
+    priority: DeploymentPriority,
+    f: F,
+) -> F::Output {
+    DEPLOYMENT.scope((id, priority), f).await
+}
+
+/// The deployment that the current task works for, if any
+pub fn current_deployment() -> Option<SubgraphDeploymentId> {
+    DEPLOYMENT.try_with(|(id, _)| id.clone()).ok()
+}
+
+/// The priority of the deployment that the current task works for. Work
+/// that is not done for a deployment, like ingesting blocks, is critical
+pub fn current_priority() -> DeploymentPriority {
+    DEPLOYMENT
+        .try_with(|(_, priority)| *priority)
+        .unwrap_or(DeploymentPriority::Critical)
+}
@@ -8,6 +8,12 @@ pub enum BlockStreamEvent {
 
     /// Signals that a revert happened and was processed.
     Revert,
+
+    /// Signals that all blocks found so far have been yielded. When it is
+    /// polled next, the stream looks for more blocks after the subgraph block
+    /// pointer in the store, which therefore has to include all blocks
+    /// processed up to this point.
+    Drained,
 }
 
 pub trait BlockStream: Stream<Item = BlockStreamEvent, Error = Error> {}
@@ -114,7 +114,7 @@ pub struct EthereumBlock {
     pub transaction_receipts: Vec<TransactionReceipt>,
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EthereumCall {
     pub from: Address,
     pub to: Address,
@@ -126,15 +126,15 @@ pub struct EthereumCall {
     pub block_hash: H256,
     pub transaction_hash: Option<H256>,
     transaction_index: u64,
+    /// Why the call failed, `None` if it succeeded. This is the reason that
+    /// the contract passed to `revert` if the Ethereum node reports it, and
+    /// the error from the trace, e.g. `Reverted`, otherwise.
+    #[serde(default)]
+    pub error: Option<String>,
 }
 
 impl EthereumCall {
     pub fn try_from_trace(trace: &Trace) -> Option<Self> {
-        // The parity-ethereum tracing api returns traces for operations which had execution errors.
-        // Filter errorful traces out, since call handlers should only run on successful CALLs.
-        if trace.error.is_some() {
-            return None;
-        }
         // We are only interested in traces from CALLs
         let call = match &trace.action {
             // Contract to contract value transfers compile to the CALL opcode
@@ -142,10 +142,17 @@ impl EthereumCall {
             Action::Call(call) if call.input.0.len() >= 4 => call,
             _ => return None,
         };
-        let (output, gas_used) = match &trace.result {
-            Some(Res::Call(result)) => (result.output.clone(), result.gas_used),
+        // The parity-ethereum tracing api returns traces for operations which had execution
+        // errors. These are kept, and it is up to call filters whether they want failed calls.
+        let (output, gas_used) = match (&trace.result, &trace.error) {
+            (Some(Res::Call(result)), _) => (result.output.clone(), result.gas_used),
+            (None, Some(_)) => (Bytes(vec![]), U256::zero()),
             _ => return None,
         };
+        let error = trace
+            .error
+            .as_ref()
+            .map(|error| solidity_revert_reason(&output.0).unwrap_or_else(|| error.clone()));
 
         // The only traces without transactions are those from Parity block reward contracts, we
         // don't support triggering on that.
@@ -162,11 +169,29 @@ impl EthereumCall {
             block_hash: trace.block_hash,
             transaction_hash: trace.transaction_hash,
             transaction_index,
+            error,
         })
     }
+
+    pub fn is_failed(&self) -> bool {
+        self.error.is_some()
+    }
 }
 
-#[derive(Clone, Debug)]
+/// The reason string in `output` if it is the encoding of `Error(string)`,
+/// which is what Solidity's `revert` and `require` return
+fn solidity_revert_reason(output: &[u8]) -> Option<String> {
+    let selector = &tiny_keccak::keccak256(b"Error(string)")[..4];
+    if output.len() < 4 || &output[..4] != selector {
+        return None;
+    }
+    ethabi::decode(&[ethabi::ParamType::String], &output[4..])
+        .ok()
+        .and_then(|mut tokens| tokens.pop())
+        .and_then(|token| token.to_string())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EthereumTrigger {
     Block(EthereumBlockPointer, EthereumBlockTriggerType),
     Call(EthereumCall),
@@ -193,7 +218,7 @@ impl PartialEq for EthereumTrigger {
 
 impl Eq for EthereumTrigger {}
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EthereumBlockTriggerType {
     Every,
     WithCallTo(Address),
@@ -280,6 +305,10 @@ pub struct EthereumBlockData {
     pub difficulty: U256,
     pub total_difficulty: U256,
     pub size: Option<U256>,
+    pub nonce: Option<H64>,
+    pub mix_hash: Option<H256>,
+    pub logs_bloom: H2048,
+    pub extra_data: Bytes,
 }
 
 impl<'a, T> From<&'a Block<T>> for EthereumBlockData {
@@ -299,6 +328,10 @@ impl<'a, T> From<&'a Block<T>> for EthereumBlockData {
             difficulty: block.difficulty,
             total_difficulty: block.total_difficulty.unwrap_or_default(),
             size: block.size,
+            nonce: block.nonce,
+            mix_hash: block.mix_hash,
+            logs_bloom: block.logs_bloom,
+            extra_data: block.extra_data.clone(),
         }
     }
 }
@@ -364,6 +397,14 @@ impl Clone for EthereumEventData {
     }
 }
 
+/// The event that a wildcard event handler gets; `name` is the name of the
+/// event of the ABI that decoded the log
+#[derive(Clone, Debug)]
+pub struct EthereumWildcardEventData {
+    pub event: EthereumEventData,
+    pub name: String,
+}
+
 /// An Ethereum call executed within a transaction within a block to a contract address.
 #[derive(Debug)]
 pub struct EthereumCallData {
@@ -373,6 +414,8 @@ pub struct EthereumCallData {
     pub transaction: EthereumTransactionData,
     pub inputs: Vec<LogParam>,
     pub outputs: Vec<LogParam>,
+    /// Why the call failed, `None` if it succeeded
+    pub error: Option<String>,
 }
 
 impl Clone for EthereumCallData {
@@ -398,6 +441,7 @@ impl Clone for EthereumCallData {
                     value: log_param.value.clone(),
                 })
                 .collect(),
+            error: self.error.clone(),
         }
     }
 }
@@ -405,7 +449,7 @@ impl Clone for EthereumCallData {
 /// A block hash and block number from a specific Ethereum block.
 ///
 /// Maximum block number supported: 2^63 - 1
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct EthereumBlockPointer {
     pub hash: H256,
     pub number: u64,
@@ -2,7 +2,7 @@ use std::pin::Pin;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use failure::Error;
+use failure::{Error, Fail};
 use futures03::prelude::Stream;
 use serde_json::Value;
 use slog::Logger;
@@ -20,6 +20,20 @@ pub struct JsonStreamValue {
 pub type JsonValueStream =
     Pin<Box<dyn Stream<Item = Result<JsonStreamValue, Error>> + Send + 'static>>;
 
+/// The file behind a link is larger than the resolver accepts. Whether a
+/// file is too large only depends on the file and the limit, and not on
+/// whether IPFS happened to be reachable.
+#[derive(Fail, Debug, Clone, PartialEq)]
+#[fail(
+    display = "IPFS file {} is too large. It can be at most {} bytes but is {} bytes",
+    path, max_file_size, size
+)]
+pub struct FileTooLargeError {
+    pub path: String,
+    pub max_file_size: u64,
+    pub size: u64,
+}
+
 /// Resolves links to subgraph manifests and resources referenced by them.
 #[async_trait]
 pub trait LinkResolver: Send + Sync + 'static {
@@ -28,6 +42,12 @@ pub trait LinkResolver: Send + Sync + 'static {
     where
         Self: Sized;
 
+    /// Updates the size limit for files that `cat` fetches; `None` means
+    /// that files of any size are fetched.
+    fn with_max_file_size(self, max_file_size: Option<u64>) -> Self
+    where
+        Self: Sized;
+
     /// Enables infinite retries.
     fn with_retries(self) -> Self
     where
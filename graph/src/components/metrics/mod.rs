@@ -11,6 +11,9 @@ pub mod stopwatch;
 /// Aggregates over individual values.
 pub mod aggregate;
 
+/// OpenTelemetry traces of block processing.
+pub mod trace;
+
 pub trait MetricsRegistry: Send + Sync + 'static {
     fn new_gauge(
         &self,
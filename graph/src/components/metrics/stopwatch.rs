@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use std::sync::{atomic::AtomicBool, atomic::Ordering, Mutex};
 use std::time::Instant;
 
+use super::trace::DeploymentSpans;
+
 /// This is a "section guard", that closes the section on drop.
 pub struct Section {
     id: String,
@@ -60,6 +62,8 @@ impl StopwatchMetrics {
             counters: HashMap::new(),
             section_stack: Vec::new(),
             timer: Instant::now(),
+            spans: DeploymentSpans::new(subgraph_id.clone()),
+            unordered_sections: 0,
         };
 
         // Start a base section so that all time is accounted for.
@@ -84,6 +88,32 @@ impl StopwatchMetrics {
         }
     }
 
+    /// Trace the processing of the block `block_ptr` until the returned
+    /// guard is dropped. Sections that run in the meantime are traced as
+    /// part of the block.
+    pub fn start_block(&self, block_ptr: &EthereumBlockPointer, trigger_count: usize) -> Block {
+        if !self.disabled.load(Ordering::SeqCst) {
+            self.inner
+                .lock()
+                .unwrap()
+                .spans
+                .start_block(block_ptr, trigger_count);
+        }
+        Block {
+            stopwatch: self.clone(),
+        }
+    }
+
+    /// Allow sections to end in a different order than they were started in
+    /// until the returned guard is dropped. That happens while handlers of
+    /// independent data sources run in parallel.
+    pub fn unordered_sections(&self) -> UnorderedSections {
+        self.inner.lock().unwrap().unordered_sections += 1;
+        UnorderedSections {
+            stopwatch: self.clone(),
+        }
+    }
+
     /// Turns `start_section` and `end_section` into no-ops, no more metrics will be updated.
     pub fn disable(&self) {
         self.disabled.store(true, Ordering::SeqCst)
@@ -96,6 +126,30 @@ impl StopwatchMetrics {
     }
 }
 
+/// A "block guard", that ends the trace of a block on drop.
+pub struct Block {
+    stopwatch: StopwatchMetrics,
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        if !self.stopwatch.disabled.load(Ordering::SeqCst) {
+            self.stopwatch.inner.lock().unwrap().spans.end_block();
+        }
+    }
+}
+
+/// A guard that lets sections end out of order until it is dropped.
+pub struct UnorderedSections {
+    stopwatch: StopwatchMetrics,
+}
+
+impl Drop for UnorderedSections {
+    fn drop(&mut self) {
+        self.stopwatch.inner.lock().unwrap().unordered_sections -= 1;
+    }
+}
+
 /// We want to account for all subgraph indexing time, based on "wall clock" time. To do this we
 /// break down indexing into _sequential_ sections, and register the total time spent in each. So
 /// that there is no double counting, time spent in child sections doesn't count for the parent.
@@ -115,6 +169,12 @@ struct StopwatchInner {
 
     // The timer is reset whenever a section starts or ends.
     timer: Instant,
+
+    // Trace spans for the current block and the sections running in it.
+    spans: DeploymentSpans,
+
+    // The number of live `UnorderedSections` guards.
+    unordered_sections: usize,
 }
 
 impl StopwatchInner {
@@ -152,21 +212,42 @@ impl StopwatchInner {
 
     fn start_section(&mut self, id: String) {
         self.record_and_reset();
+        // The base section spans the whole life of the subgraph, which is
+        // too long to trace
+        if !self.section_stack.is_empty() {
+            self.spans.start_section(&id);
+        }
         self.section_stack.push(id);
     }
 
     fn end_section(&mut self, id: String) {
-        // Validate that the expected section is running.
-        match self.section_stack.last() {
-            Some(current_section) if current_section == &id => {
+        // Validate that the expected section is running. Other sections
+        // may only end first while `unordered_sections` allows it.
+        let index = match self.section_stack.last() {
+            Some(current_section) if current_section == &id => Some(self.section_stack.len() - 1),
+            _ if self.unordered_sections > 0 => self
+                .section_stack
+                .iter()
+                .rposition(|section| section == &id),
+            _ => None,
+        };
+        match index {
+            Some(index) => {
                 self.record_and_reset();
-                self.section_stack.pop();
+                if index > 0 {
+                    self.spans.end_section(index - 1);
+                }
+                self.section_stack.remove(index);
             }
-            Some(current_section) => error!(self.logger, "`end_section` with mismatched section";
-                                                        "current" => current_section,
-                                                        "received" => id),
-            None => error!(self.logger, "`end_section` with no current section";
-                                        "received" => id),
+            None => match self.section_stack.last() {
+                Some(current_section) => {
+                    error!(self.logger, "`end_section` with mismatched section";
+                                        "current" => current_section,
+                                        "received" => id)
+                }
+                None => error!(self.logger, "`end_section` with no current section";
+                                            "received" => id),
+            },
         }
     }
 }
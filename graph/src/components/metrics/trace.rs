@@ -0,0 +1,84 @@
+use opentelemetry::api::{KeyValue, Span, SpanBuilder, SpanContext, Tracer};
+use opentelemetry::global::{self, BoxedSpan, BoxedTracer};
+use std::env;
+
+use crate::prelude::*;
+
+/// Export the spans of block processing to the OpenTelemetry collector at
+/// `GRAPH_OTLP_ENDPOINT`, if that is set. Spans are only exported while
+/// the returned guard is alive; without it, spans are not recorded at all.
+pub fn init(logger: &Logger) -> Option<opentelemetry_otlp::Uninstall> {
+    let endpoint = env::var("GRAPH_OTLP_ENDPOINT").ok()?;
+    info!(logger, "Exporting traces over OTLP"; "endpoint" => &endpoint);
+    let (_, uninstall) = opentelemetry_otlp::new_pipeline()
+        .with_endpoint(endpoint)
+        .install();
+    Some(uninstall)
+}
+
+/// The spans of one deployment: one for each block that is processed and,
+/// below it, one for each stopwatch section that runs while processing it.
+pub(crate) struct DeploymentSpans {
+    tracer: BoxedTracer,
+    subgraph_id: SubgraphDeploymentId,
+    block: Option<BoxedSpan>,
+    sections: Vec<BoxedSpan>,
+}
+
+impl DeploymentSpans {
+    pub fn new(subgraph_id: SubgraphDeploymentId) -> Self {
+        DeploymentSpans {
+            tracer: global::tracer("graph-node"),
+            subgraph_id,
+            block: None,
+            sections: Vec::new(),
+        }
+    }
+
+    fn parent(&self) -> Option<SpanContext> {
+        self.sections
+            .last()
+            .or(self.block.as_ref())
+            .map(|span| span.span_context())
+    }
+
+    fn start(&self, name: &str, mut attributes: Vec<KeyValue>) -> BoxedSpan {
+        attributes.push(KeyValue::new("subgraph_id", self.subgraph_id.to_string()));
+        let mut builder = SpanBuilder::from_name(name.to_owned()).with_attributes(attributes);
+        if let Some(parent) = self.parent() {
+            builder = builder.with_parent(parent);
+        }
+        self.tracer.build(builder)
+    }
+
+    pub fn start_block(&mut self, block_ptr: &EthereumBlockPointer, trigger_count: usize) {
+        self.end_block();
+        self.block = Some(self.start(
+            "process_block",
+            vec![
+                KeyValue::new("block_number", block_ptr.number as i64),
+                KeyValue::new("block_hash", block_ptr.hash_hex()),
+                KeyValue::new("trigger_count", trigger_count as i64),
+            ],
+        ));
+    }
+
+    pub fn end_block(&mut self) {
+        if let Some(mut span) = self.block.take() {
+            span.end();
+        }
+    }
+
+    pub fn start_section(&mut self, id: &str) {
+        let span = self.start(id, vec![]);
+        self.sections.push(span);
+    }
+
+    /// End the span of the running section at `index`, where the oldest
+    /// running section is at index 0
+    pub fn end_section(&mut self, index: usize) {
+        if index < self.sections.len() {
+            self.sections.remove(index).end();
+        }
+    }
+}
@@ -1,8 +1,66 @@
+use serde::Deserialize;
 use std::io;
 use std::sync::Arc;
 
 use crate::prelude::Logger;
-use crate::prelude::NodeId;
+
+/// What a token for the JSON-RPC admin server allows its bearer to do. Each
+/// scope includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminScope {
+    /// Call the methods that only look at the node's state, like listing
+    /// the queries a deployment allows
+    Read,
+    /// Create subgraphs and deploy new versions of them
+    Deploy,
+    /// Everything, including removing, reassigning, pausing and resuming
+    Admin,
+}
+
+/// The tokens that give access to the JSON-RPC admin server. Without any
+/// tokens, the server does not check requests at all.
+#[derive(Clone, Debug, Default)]
+pub struct AdminTokens(Vec<(String, AdminScope)>);
+
+impl AdminTokens {
+    pub fn new(tokens: impl IntoIterator<Item = (String, AdminScope)>) -> Self {
+        AdminTokens(tokens.into_iter().collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether a request that presented `token` may do what `scope` allows.
+    /// The token is compared with all configured tokens in constant time so
+    /// that the time a request takes does not give away how much of a
+    /// valid token it guessed.
+    pub fn allows(&self, token: Option<&str>, scope: AdminScope) -> bool {
+        if self.0.is_empty() {
+            return true;
+        }
+        let token = match token {
+            Some(token) => token,
+            None => return false,
+        };
+        let mut granted = None;
+        for (valid, valid_scope) in &self.0 {
+            if constant_time_eq(valid.as_bytes(), token.as_bytes()) {
+                granted = Some(*valid_scope);
+            }
+        }
+        granted.map_or(false, |granted| granted >= scope)
+    }
+}
+
+/// Compare `a` and `b` in a time that only depends on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
 
 /// Common trait for JSON-RPC admin server implementations.
 pub trait JsonRpcServer<P> {
@@ -13,7 +71,43 @@ pub trait JsonRpcServer<P> {
         http_port: u16,
         ws_port: u16,
         provider: Arc<P>,
-        node_id: NodeId,
+        tokens: AdminTokens,
         logger: Logger,
     ) -> Result<Self::Server, io::Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes() {
+        let tokens = AdminTokens::new(vec![
+            ("monitor".to_owned(), AdminScope::Read),
+            ("pipeline".to_owned(), AdminScope::Deploy),
+            ("operator".to_owned(), AdminScope::Admin),
+        ]);
+
+        assert!(tokens.allows(Some("monitor"), AdminScope::Read));
+        assert!(!tokens.allows(Some("monitor"), AdminScope::Deploy));
+        assert!(tokens.allows(Some("pipeline"), AdminScope::Read));
+        assert!(tokens.allows(Some("pipeline"), AdminScope::Deploy));
+        assert!(!tokens.allows(Some("pipeline"), AdminScope::Admin));
+        assert!(tokens.allows(Some("operator"), AdminScope::Deploy));
+        assert!(tokens.allows(Some("operator"), AdminScope::Admin));
+        assert!(!tokens.allows(Some("other"), AdminScope::Read));
+        assert!(!tokens.allows(Some("pipelin"), AdminScope::Read));
+        assert!(!tokens.allows(Some("pipeline2"), AdminScope::Read));
+        assert!(!tokens.allows(None, AdminScope::Read));
+
+        assert!(AdminTokens::default().allows(None, AdminScope::Admin));
+    }
+
+    #[test]
+    fn compare_in_constant_time() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"toke"));
+    }
+}
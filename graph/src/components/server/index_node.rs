@@ -1,4 +1,11 @@
+use async_trait::async_trait;
+use failure::Error;
 use futures::prelude::*;
+use std::sync::Arc;
+
+use crate::components::ethereum::EthereumAdapter;
+use crate::components::store::ChainStore;
+use crate::data::subgraph::SubgraphDeploymentId;
 
 /// Common trait for index node server implementations.
 pub trait IndexNodeServer {
@@ -10,3 +17,66 @@ pub trait IndexNodeServer {
         port: u16,
     ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError>;
 }
+
+/// A check of one part of the node, like the store or the Ethereum providers
+/// of a network. The node is only reported as ready to Kubernetes and other
+/// orchestrators when all of its checks pass.
+#[async_trait]
+pub trait HealthCheck: Send + Sync + 'static {
+    /// The name of the check in readiness reports, e.g. `store`.
+    fn name(&self) -> &str;
+
+    /// Check the part of the node, returning why it is not healthy if it
+    /// is not.
+    async fn check(&self) -> Result<(), String>;
+}
+
+/// A network that the node is configured for, as listed by the `networks`
+/// query of the index node server.
+#[derive(Clone)]
+pub struct NetworkInfo {
+    pub name: String,
+    /// The kind of chain, e.g. `ethereum`
+    pub kind: String,
+    /// How many providers the node has for the network
+    pub providers: usize,
+    pub chain_store: Arc<dyn ChainStore>,
+    /// One of the adapters for the providers of the network
+    pub ethereum_adapter: Arc<dyn EthereumAdapter>,
+    /// Checks the block ingestor of the network; `None` if this node does
+    /// not ingest blocks
+    pub ingestion_check: Option<Arc<dyn HealthCheck>>,
+}
+
+/// How often one handler of a deployment would be called for a range of
+/// blocks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandlerTriggerCount {
+    pub data_source: String,
+    /// The kind of handler, `event`, `call` or `block`
+    pub kind: &'static str,
+    /// The event or function signature of the handler, or the filter of a
+    /// block handler
+    pub filter: Option<String>,
+    pub handler: String,
+    /// How many triggers the handler would receive
+    pub triggers: u64,
+    /// How many blocks have triggers for the handler
+    pub blocks: u64,
+}
+
+/// Works out which handlers of a deployment would be called for a range of
+/// blocks from the trigger filters of its data sources, without running any
+/// mappings. This lets subgraph developers check their filters before a
+/// long sync.
+#[async_trait]
+pub trait TriggerDryRun: Send + Sync + 'static {
+    /// The trigger counts for all handlers of `deployment` for the blocks
+    /// `from` to `to`, including handlers that would not be called at all
+    async fn trigger_counts(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<HandlerTriggerCount>, Error>;
+}
@@ -6,7 +6,7 @@ use mockall::predicate::*;
 use mockall::*;
 use serde::{Deserialize, Serialize};
 use stable_hash::prelude::*;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::env;
 use std::fmt;
 use std::str::FromStr;
@@ -76,6 +76,11 @@ pub enum EntityFilter {
     NotStartsWith(Attribute, Value),
     EndsWith(Attribute, Value),
     NotEndsWith(Attribute, Value),
+    ContainsNoCase(Attribute, Value),
+    StartsWithNoCase(Attribute, Value),
+    EndsWithNoCase(Attribute, Value),
+    /// The attribute matches a POSIX regular expression
+    Matches(Attribute, Value),
 }
 
 // Define some convenience methods
@@ -718,6 +723,68 @@ pub struct AttributeIndexDefinition {
     pub entity_name: String,
 }
 
+/// The space that one table of a deployment takes up in the database. The
+/// counts are estimates that Postgres keeps for query planning
+#[derive(Clone, Debug)]
+pub struct TableStorage {
+    /// The entity type stored in the table, `None` if the table does not
+    /// store exactly one entity type
+    pub entity_type: Option<String>,
+    pub table: String,
+    pub entity_count: i64,
+    /// The number of entity versions, including those that are only kept
+    /// as history
+    pub version_count: i64,
+    pub table_bytes: i64,
+    pub index_bytes: i64,
+}
+
+/// The space that a deployment takes up in the database
+#[derive(Clone, Debug)]
+pub struct DeploymentStorage {
+    pub subgraph: SubgraphDeploymentId,
+    pub tables: Vec<TableStorage>,
+    /// The earliest and the latest block of the entity history the
+    /// deployment keeps
+    pub earliest_block: Option<u64>,
+    pub latest_block: Option<u64>,
+}
+
+impl DeploymentStorage {
+    pub fn total_bytes(&self) -> i64 {
+        self.tables
+            .iter()
+            .map(|table| table.table_bytes + table.index_bytes)
+            .sum()
+    }
+}
+
+/// How far a deployment is behind the head of its chain
+#[derive(Clone, Debug)]
+pub struct IndexingFreshness {
+    /// The latest block the deployment has indexed
+    pub latest_block: EthereumBlockPointer,
+    /// The Unix timestamp of `latest_block`, if the block is still in the
+    /// block cache
+    pub latest_block_timestamp: Option<u64>,
+    pub chain_head_block_number: u64,
+}
+
+impl IndexingFreshness {
+    pub fn blocks_behind(&self) -> u64 {
+        self.chain_head_block_number
+            .saturating_sub(self.latest_block.number)
+    }
+}
+
+/// A query on the allowlist of a deployment
+#[derive(Clone, Debug, Serialize)]
+pub struct AllowedQuery {
+    pub hash: String,
+    pub query: String,
+    pub added_at: String,
+}
+
 #[derive(Fail, Debug)]
 pub enum StoreError {
     #[fail(display = "store transaction failed, need to retry: {}", _0)]
@@ -823,6 +890,16 @@ pub trait Store: Send + Sync + 'static {
         ids_for_type: BTreeMap<&str, Vec<&str>>,
     ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError>;
 
+    /// Return the entities of the given types that were created or updated
+    /// in `block`, in the version written in that block, ordered by type and
+    /// then by id. Returns pairs of `(type, entity)`.
+    fn entity_changes_in_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<Vec<(String, Entity)>, StoreError>;
+
     /// Queries the store for entities that match the store query.
     fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError>;
 
@@ -838,6 +915,9 @@ pub trait Store: Send + Sync + 'static {
     ///
     /// `block_ptr_to` must point to a child block of the current subgraph block pointer.
     ///
+    /// `deterministic_errors` are errors of handlers that were skipped while processing the
+    /// block; they are recorded as non-fatal errors of the subgraph.
+    ///
     /// Return `true` if the subgraph mentioned in `history_event` should have
     /// its schema migrated at `block_ptr_to`
     fn transact_block_operations(
@@ -846,6 +926,23 @@ pub trait Store: Send + Sync + 'static {
         block_ptr_to: EthereumBlockPointer,
         mods: Vec<EntityModification>,
         stopwatch: StopwatchMetrics,
+        deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<bool, StoreError>;
+
+    /// Transact the entity changes of several consecutive blocks atomically into the store,
+    /// and update the subgraph block pointer to the last of them. The changes of each block
+    /// are recorded at that block, exactly as if `transact_block_operations` had been called
+    /// for every block, so that they can be reverted block by block.
+    ///
+    /// The first block must be a child of the current subgraph block pointer, and `blocks`
+    /// must be sorted by block number.
+    ///
+    /// Return `true` if the subgraph should have its schema migrated at the last block
+    fn transact_blocks_operations(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        blocks: Vec<BlockModifications>,
+        stopwatch: StopwatchMetrics,
     ) -> Result<bool, StoreError>;
 
     /// Apply the specified metadata operations.
@@ -1057,14 +1154,16 @@ pub trait Store: Send + Sync + 'static {
     /// Produce the MetadataOperations needed to create/remove
     /// SubgraphDeploymentAssignments to reflect the addition/removal of
     /// SubgraphVersions between `versions_before` and `versions_after`.
-    /// Any new assignments are created with the specified `node_id`.
-    /// `node_id` can be `None` if it is known that no versions were added.
+    /// Any new assignments are created with the specified `node_id` and
+    /// `network_alias`. `node_id` can be `None` if it is known that no
+    /// versions were added.
     fn reconcile_assignments(
         &self,
         logger: &Logger,
         versions_before: Vec<SubgraphVersionSummary>,
         versions_after: Vec<SubgraphVersionSummary>,
         node_id: Option<NodeId>,
+        network_alias: Option<String>,
     ) -> Vec<MetadataOperation> {
         fn should_have_assignment(version: &SubgraphVersionSummary) -> bool {
             version.pending || version.current
@@ -1122,6 +1221,7 @@ pub trait Store: Send + Sync + 'static {
                     .clone()
                     .expect("Cannot create new subgraph deployment assignment without node ID"),
             )
+            .network_alias(network_alias.clone())
             .write_operations(deployment_id)
         }));
 
@@ -1158,10 +1258,21 @@ pub trait Store: Send + Sync + 'static {
     /// Create a new subgraph deployment. The deployment must not exist yet. `ops`
     /// needs to contain all the operations on subgraphs and subgraph deployments to
     /// create the deployment, including any assignments as a current or pending
-    /// version
+    /// version. The storage for the deployment follows the `hints` from its
+    /// manifest
     fn create_subgraph_deployment(
         &self,
         schema: &Schema,
+        hints: &IndexerHints,
+        ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError>;
+
+    /// Apply `ops` and create the deployments with the given schemas and
+    /// hints in one transaction, like `create_subgraph_deployment` does for
+    /// a single deployment. `deployments` can be empty.
+    fn create_subgraph_deployments(
+        &self,
+        deployments: &[(Schema, IndexerHints)],
         ops: Vec<MetadataOperation>,
     ) -> Result<(), StoreError>;
 
@@ -1207,6 +1318,8 @@ pub trait Store: Send + Sync + 'static {
 
 mock! {
     pub Store {
+        fn get_mock(&self, _key: EntityKey) -> Result<Option<Entity>, QueryExecutionError>;
+
         fn get_many_mock<'a>(
             &self,
             _subgraph_id: &SubgraphDeploymentId,
@@ -1244,8 +1357,8 @@ impl Store for MockStore {
         unimplemented!();
     }
 
-    fn get(&self, _key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
-        unimplemented!()
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        self.get_mock(key)
     }
 
     fn get_many(
@@ -1256,6 +1369,15 @@ impl Store for MockStore {
         self.get_many_mock(subgraph_id, ids_for_type)
     }
 
+    fn entity_changes_in_block(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _entity_types: &[String],
+        _block: BlockNumber,
+    ) -> Result<Vec<(String, Entity)>, StoreError> {
+        unimplemented!()
+    }
+
     fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
         unimplemented!()
     }
@@ -1274,6 +1396,16 @@ impl Store for MockStore {
         _block_ptr_to: EthereumBlockPointer,
         _mods: Vec<EntityModification>,
         _stopwatch: StopwatchMetrics,
+        _deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn transact_blocks_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _blocks: Vec<BlockModifications>,
+        _stopwatch: StopwatchMetrics,
     ) -> Result<bool, StoreError> {
         unimplemented!()
     }
@@ -1309,6 +1441,15 @@ impl Store for MockStore {
     fn create_subgraph_deployment(
         &self,
         _schema: &Schema,
+        _hints: &IndexerHints,
+        _ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn create_subgraph_deployments(
+        &self,
+        _deployments: &[(Schema, IndexerHints)],
         _ops: Vec<MetadataOperation>,
     ) -> Result<(), StoreError> {
         unimplemented!()
@@ -1363,6 +1504,81 @@ pub trait SubgraphDeploymentStore: Send + Sync + 'static {
     /// Return the name of the network that the subgraph is indexing from. The
     /// names returned are things like `mainnet` or `ropsten`
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error>;
+
+    /// Return true if the subgraph has recorded non-fatal errors for blocks
+    /// up to and including `block`, or for any block if `block` is `None`
+    fn has_non_fatal_errors(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+    ) -> Result<bool, Error>;
+
+    /// Return the earliest block for which the deployment still has its
+    /// complete history. That is the start of the chain unless its manifest
+    /// asked for old history to be pruned
+    fn earliest_block(&self, subgraph_id: &SubgraphDeploymentId) -> Result<BlockNumber, Error>;
+
+    /// Return how much space the deployments with the given ids take up,
+    /// or all deployments if `subgraph_ids` is `None`. Unknown ids are
+    /// ignored
+    fn deployment_storage(
+        &self,
+        subgraph_ids: Option<Vec<SubgraphDeploymentId>>,
+    ) -> Result<Vec<DeploymentStorage>, Error>;
+
+    /// Return how far the deployment is behind the head of its chain, or
+    /// `None` if it has not indexed any blocks yet or the head of its
+    /// chain is not known
+    fn indexing_freshness(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<IndexingFreshness>, Error>;
+
+    /// Return the hashes of the queries that the deployment runs if it is
+    /// in allowlist mode, or `None` if it runs any query. The allowlist is
+    /// cached, and changes made by other nodes take a little while to be
+    /// seen
+    fn query_allowlist(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<Arc<HashSet<String>>>, Error>;
+
+    /// Turn allowlist mode for the deployment on or off
+    fn set_query_allowlist_enabled(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        enabled: bool,
+    ) -> Result<(), Error>;
+
+    /// Put `query`, whose hash is `hash`, on the allowlist of the deployment
+    fn allow_query(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        hash: &str,
+        query: &str,
+    ) -> Result<(), Error>;
+
+    /// Take the query with `hash` off the allowlist of the deployment.
+    /// Returns `false` if it was not on it
+    fn disallow_query(&self, subgraph_id: &SubgraphDeploymentId, hash: &str)
+        -> Result<bool, Error>;
+
+    /// Return the queries on the allowlist of the deployment
+    fn allowed_queries(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<AllowedQuery>, Error>;
+
+    /// Add `entities` to the deployment as if a handler had created them
+    /// in `block`. The deployment must use relational storage and must
+    /// still have the history for `block`, and none of the entities may
+    /// exist already
+    fn import_entities(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: BlockNumber,
+        entities: Vec<(EntityKey, Entity)>,
+    ) -> Result<(), StoreError>;
 }
 
 /// Common trait for blockchain store implementations.
@@ -1428,6 +1644,15 @@ pub trait ChainStore: Send + Sync + 'static {
         offset: u64,
     ) -> Result<Option<EthereumBlock>, Error>;
 
+    /// Return the block with hash `block_hash` and its ancestors down to
+    /// the one with number `min_number`, from the newest to the oldest.
+    /// Stops at the first ancestor that is not in the store.
+    fn ancestor_blocks(
+        &self,
+        block_hash: H256,
+        min_number: u64,
+    ) -> Result<Vec<EthereumBlock>, Error>;
+
     /// Remove old blocks from the cache we maintain in the database and
     /// return a pair containing the number of the oldest block retained
     /// and the number of blocks deleted.
@@ -1441,6 +1666,20 @@ pub trait ChainStore: Send + Sync + 'static {
     /// Confirm that block number `number` has hash `hash` and that the store
     /// may purge any other blocks with that number
     fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error>;
+
+    /// Return the numbers in `from..=to` at which the cached blocks do not
+    /// form a single chain: numbers with several cached blocks, and numbers
+    /// whose cached block is not the parent of the cached block with the
+    /// next number
+    fn inconsistent_block_numbers(&self, from: u64, to: u64) -> Result<Vec<u64>, Error>;
+
+    /// Return the number and timestamp of the last cached block whose
+    /// timestamp is at or before `timestamp`, and of the first cached block
+    /// after it. Either is `None` if the cache has no such block
+    fn cached_blocks_around_timestamp(
+        &self,
+        timestamp: u64,
+    ) -> Result<(Option<(u64, u64)>, Option<(u64, u64)>), Error>;
 }
 
 pub trait EthereumCallCache: Send + Sync + 'static {
@@ -1460,6 +1699,92 @@ pub trait EthereumCallCache: Send + Sync + 'static {
         block: EthereumBlockPointer,
         return_value: &[u8],
     ) -> Result<(), Error>;
+
+    /// Cached return value of a call that returns the same for every block.
+    fn get_immutable_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Add an entry for a call that returns the same for every block to
+    /// the cache. `block` is the block the call was made against and must
+    /// be final.
+    fn set_immutable_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        return_value: &[u8],
+    ) -> Result<(), Error>;
+}
+
+/// Keeps track of which index nodes are alive. Every node records a
+/// heartbeat periodically; nodes that stop doing that are considered dead.
+pub trait NodeLivenessStore: Send + Sync + 'static {
+    /// Record that `node` is alive.
+    fn heartbeat(&self, node: &NodeId) -> Result<(), StoreError>;
+
+    /// All nodes that have ever recorded a heartbeat, together with the
+    /// time that has passed since their last one.
+    fn heartbeat_ages(&self) -> Result<Vec<(NodeId, Duration)>, StoreError>;
+
+    /// Try to make `node` the block ingestor of `network` for the next
+    /// `duration`. Succeeds if `node` already holds the lease, or if nobody
+    /// holds it or the lease of its holder has expired. Returns whether
+    /// `node` holds the lease now.
+    fn acquire_ingestor_lease(
+        &self,
+        network: &str,
+        node: &NodeId,
+        duration: Duration,
+    ) -> Result<bool, StoreError>;
+}
+
+/// The prefix of the hashes of subgraph files that are stored in the
+/// database rather than in IPFS.
+pub const SUBGRAPH_FILE_PREFIX: &str = "local";
+
+/// The hash under which `content` is stored in a `SubgraphFileStore`. It is
+/// short enough to be used as a deployment id, and can not be confused with
+/// an IPFS hash.
+pub fn subgraph_file_hash(content: &[u8]) -> String {
+    let hash = tiny_keccak::keccak256(content);
+    format!("{}{}", SUBGRAPH_FILE_PREFIX, hex::encode(&hash[..20]))
+}
+
+/// Keeps the files of subgraphs that were deployed from local files, so
+/// that they can be deployed without an IPFS node.
+pub trait SubgraphFileStore: Send + Sync + 'static {
+    /// Store `content` under `hash`; storing the same file again has no
+    /// effect.
+    fn put_subgraph_file(&self, hash: &str, content: &[u8]) -> Result<(), StoreError>;
+
+    /// The file stored under `hash`, if there is one.
+    fn subgraph_file(&self, hash: &str) -> Result<Option<Vec<u8>>, StoreError>;
+}
+
+/// Encrypts designated `String` and `Bytes` attributes of entities before
+/// the store writes them and decrypts them when it reads them, so that the
+/// database only ever sees their ciphertext. The store does not come with
+/// a cipher; operators that need one pass theirs to the store when they
+/// build the node.
+///
+/// Encrypted attributes can not be filtered or sorted by in a meaningful
+/// way, and the `id` and attributes that reference other entities are never
+/// encrypted. Changing which attributes are encrypted only affects values
+/// written afterwards, which is why that should only be done for new
+/// deployments.
+pub trait AttributeCipher: Send + Sync + 'static {
+    /// Whether the values of `attribute` of `entity_type` in `subgraph` are
+    /// encrypted
+    fn encrypts(&self, subgraph: &SubgraphDeploymentId, entity_type: &str, attribute: &str)
+        -> bool;
+
+    fn encrypt(&self, subgraph: &SubgraphDeploymentId, plaintext: &[u8]) -> Result<Vec<u8>, Error>;
+
+    fn decrypt(&self, subgraph: &SubgraphDeploymentId, ciphertext: &[u8])
+        -> Result<Vec<u8>, Error>;
 }
 
 /// Store operations used when serving queries
@@ -1469,6 +1794,18 @@ pub trait QueryStore: Send + Sync {
         query: EntityQuery,
     ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError>;
 
+    /// Count the children in each of `windows` at `block` without loading
+    /// them, and return how many children each parent id has. Parents that
+    /// have no children are not in the result. Only windows with an
+    /// `EntityLink::Direct` link, i.e., for `@derivedFrom` fields, can be
+    /// counted
+    fn count_children(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        windows: Vec<EntityWindow>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, i64>, QueryExecutionError>;
+
     fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox;
 
     fn is_deployment_synced(&self, id: SubgraphDeploymentId) -> Result<bool, Error>;
@@ -1508,6 +1845,25 @@ impl EntityModification {
     }
 }
 
+/// The changes that processing one block makes to a subgraph deployment
+#[derive(Clone, Debug)]
+pub struct BlockModifications {
+    pub block_ptr: EthereumBlockPointer,
+    pub mods: Vec<EntityModification>,
+    /// Errors of handlers that were skipped while processing the block
+    pub deterministic_errors: Vec<SubgraphError>,
+}
+
+/// A source for the entities of a deployment that are not in the local
+/// store, used to debug a deployment at a late block without indexing it
+/// from its start block. Entities returned by the fork are written to the
+/// local store, which is authoritative for them from then on.
+pub trait SubgraphFork: Send + Sync {
+    /// Fetch the entity for `key` as it was after `block`. Each entity is
+    /// returned at most once.
+    fn fetch(&self, key: &EntityKey, block: BlockNumber) -> Result<Option<Entity>, StoreError>;
+}
+
 /// A cache for entities from the store that provides the basic functionality
 /// needed for the store interactions in the host exports. This struct tracks
 /// how entities are modified, and caches all entities looked up from the
@@ -1525,7 +1881,29 @@ pub struct EntityCache {
     /// means that the entity should be deleted
     updates: BTreeMap<EntityKey, Option<Entity>>,
 
+    /// The changes made by the handler that is currently running. They are
+    /// kept apart from `updates` so that they can be discarded if the
+    /// handler fails
+    handler_updates: BTreeMap<EntityKey, Option<Entity>>,
+
+    /// Whether changes go to `handler_updates` rather than `updates`
+    in_handler: bool,
+
     pub store: Arc<dyn Store>,
+
+    /// Where to look for entities that are not in the store
+    pub fork: Option<Arc<dyn SubgraphFork>>,
+
+    /// The block of the local deployment, i.e., the block before the one
+    /// that is being processed. Entities are fetched from the fork as they
+    /// were after this block
+    pub fork_block: BlockNumber,
+
+    /// The entity types that handlers using this cache declared. Whether
+    /// other entity types were read or written is tracked in
+    /// `undeclared_access`
+    declared_types: Option<Arc<BTreeSet<String>>>,
+    undeclared_access: bool,
 }
 
 impl Debug for EntityCache {
@@ -1533,6 +1911,7 @@ impl Debug for EntityCache {
         f.debug_struct("EntityCache")
             .field("current", &self.current)
             .field("updates", &self.updates)
+            .field("handler_updates", &self.handler_updates)
             .finish()
     }
 }
@@ -1547,7 +1926,13 @@ impl EntityCache {
         Self {
             current: LfuCache::new(),
             updates: BTreeMap::new(),
+            handler_updates: BTreeMap::new(),
+            in_handler: false,
             store,
+            fork: None,
+            fork_block: 0,
+            declared_types: None,
+            undeclared_access: false,
         }
     }
 
@@ -1558,37 +1943,108 @@ impl EntityCache {
         EntityCache {
             current,
             updates: BTreeMap::new(),
+            handler_updates: BTreeMap::new(),
+            in_handler: false,
             store,
+            fork: None,
+            fork_block: 0,
+            declared_types: None,
+            undeclared_access: false,
         }
     }
 
+    pub fn with_fork(mut self, fork: Option<Arc<dyn SubgraphFork>>, block: BlockNumber) -> Self {
+        self.fork = fork;
+        self.fork_block = block;
+        self
+    }
+
+    /// Track whether entities of types other than `declared_types` are
+    /// accessed through this cache
+    pub fn with_declared_types(mut self, declared_types: Arc<BTreeSet<String>>) -> Self {
+        self.declared_types = Some(declared_types);
+        self
+    }
+
+    /// Whether entities of a type that was not declared with
+    /// `with_declared_types` were read or written
+    pub fn accessed_undeclared_types(&self) -> bool {
+        self.undeclared_access
+    }
+
+    fn track_access(&mut self, key: &EntityKey) {
+        if let Some(declared_types) = &self.declared_types {
+            if !declared_types.contains(&key.entity_type) {
+                self.undeclared_access = true;
+            }
+        }
+    }
+
+    /// Start collecting the changes of a handler separately, so that they
+    /// can be discarded with `exit_handler_and_discard_changes`.
+    pub fn enter_handler(&mut self) {
+        assert!(!self.in_handler);
+        self.in_handler = true;
+    }
+
+    /// Keep the changes made by the current handler.
+    pub fn exit_handler(&mut self) -> Result<(), QueryExecutionError> {
+        assert!(self.in_handler);
+        self.in_handler = false;
+
+        let handler_updates = std::mem::take(&mut self.handler_updates);
+        for (key, update) in handler_updates {
+            match update {
+                Some(update) => self.set(key, update)?,
+                None => self.remove(key),
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget all changes made by the current handler.
+    pub fn exit_handler_and_discard_changes(&mut self) {
+        assert!(self.in_handler);
+        self.in_handler = false;
+        self.handler_updates.clear();
+    }
+
     pub fn get(&mut self, key: &EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        self.track_access(key);
         let current = self.current.get_entity(&*self.store, &key)?;
-        let updates = self.updates.get(&key).cloned();
-        match (current, updates) {
-            // Entity is unchanged
-            (current, None) => Ok(current),
-            // Entity was deleted
-            (_, Some(None)) => Ok(None),
-            // Entity created
-            (None, Some(updates)) => Ok(updates),
-            // Entity updated
-            (Some(current), Some(Some(updates))) => {
-                let mut current = current;
-                current.merge_remove_null_fields(updates);
-                Ok(Some(current))
-            }
+        if current.is_none() {
+            self.fetch_from_fork(key)?;
         }
+        let entity = apply_update(current, self.updates.get(&key).cloned());
+        Ok(apply_update(
+            entity,
+            self.handler_updates.get(&key).cloned(),
+        ))
     }
 
     pub fn remove(&mut self, key: EntityKey) {
-        self.updates.insert(key, None);
+        self.track_access(&key);
+        match self.in_handler {
+            true => self.handler_updates.insert(key, None),
+            false => self.updates.insert(key, None),
+        };
     }
 
     pub fn set(&mut self, key: EntityKey, mut entity: Entity) -> Result<(), QueryExecutionError> {
         use std::collections::btree_map::Entry;
 
-        let update = self.updates.entry(key.clone());
+        self.track_access(&key);
+
+        // The state of the entity before any of the changes we are adding to
+        let previous = match self.in_handler {
+            true => self.updates.get(&key).cloned(),
+            false => None,
+        };
+
+        let update = match self.in_handler {
+            true => self.handler_updates.entry(key.clone()),
+            false => self.updates.entry(key.clone()),
+        };
 
         match update {
             // First change.
@@ -1602,7 +2058,8 @@ impl EntityCache {
 
                 // Previous change was a removal, clear fields in `current`.
                 None => {
-                    if let Some(current) = self.current.get_entity(&*self.store, &key)? {
+                    let current = self.current.get_entity(&*self.store, &key)?;
+                    if let Some(current) = apply_update(current, previous) {
                         // Entity was removed so the fields not updated need to be unset.
                         for field in current.keys().cloned() {
                             entity.entry(field).or_insert(Value::Null);
@@ -1630,9 +2087,35 @@ impl EntityCache {
         Ok(())
     }
 
-    pub fn extend(&mut self, other: EntityCache) -> Result<(), QueryExecutionError> {
-        self.current.extend(other.current);
-        for (key, update) in other.updates {
+    /// Move the cached state of entities of `entity_types` into a cache of
+    /// their own, for a cache whose handlers only access entities of those
+    /// types. `extend` and `extend_current` move it back.
+    pub fn split_off_current(
+        &mut self,
+        entity_types: &BTreeSet<String>,
+    ) -> LfuCache<EntityKey, Option<Entity>> {
+        self.current
+            .split_off(|key| entity_types.contains(&key.entity_type))
+    }
+
+    /// Take over the entities that `other` looked up, but not its changes.
+    /// If `other` declared its entity types, only entities of those types
+    /// are taken, since this cache may know the others better.
+    pub fn extend_current(&mut self, other: EntityCache) {
+        let declared_types = other.declared_types;
+        self.current.merge(other.current, |key| {
+            declared_types
+                .as_ref()
+                .map_or(true, |types| types.contains(&key.entity_type))
+        });
+    }
+
+    pub fn extend(&mut self, mut other: EntityCache) -> Result<(), QueryExecutionError> {
+        assert!(!other.in_handler);
+
+        let updates = std::mem::take(&mut other.updates);
+        self.extend_current(other);
+        for (key, update) in updates {
             match update {
                 Some(update) => self.set(key, update)?,
                 None => self.remove(key),
@@ -1650,6 +2133,8 @@ impl EntityCache {
         mut self,
         store: &(impl Store + ?Sized),
     ) -> Result<ModificationsAndCache, QueryExecutionError> {
+        assert!(!self.in_handler);
+
         // The first step is to make sure all entities being set are in `self.current`.
         // For each subgraph, we need a map of entity type to missing entity ids.
         let missing = self
@@ -1680,6 +2165,19 @@ impl EntityCache {
             }
         }
 
+        // Entities that are not in the store might still be in the fork
+        if self.fork.is_some() {
+            let missing: Vec<_> = self
+                .updates
+                .keys()
+                .filter(|key| !self.current.contains_key(key))
+                .cloned()
+                .collect();
+            for key in missing {
+                self.fetch_from_fork(&key)?;
+            }
+        }
+
         let mut mods = Vec::new();
         for (key, update) in self.updates {
             use EntityModification::*;
@@ -1723,6 +2221,56 @@ impl EntityCache {
     }
 }
 
+impl EntityCache {
+    /// Look up an entity that is not in the store in the fork and record
+    /// it as created, so that it gets written to the store with the other
+    /// changes of the block.
+    fn fetch_from_fork(&mut self, key: &EntityKey) -> Result<(), QueryExecutionError> {
+        use std::collections::btree_map::Entry;
+
+        let fork = match &self.fork {
+            Some(fork) if !key.subgraph_id.is_meta() => fork.clone(),
+            _ => return Ok(()),
+        };
+        if let Some(None) = self.updates.get(key) {
+            // The entity was removed, no need to know what it was
+            return Ok(());
+        }
+
+        if let Some(mut entity) = fork.fetch(key, self.fork_block)? {
+            match self.updates.entry(key.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(Some(entity));
+                }
+                Entry::Occupied(mut entry) => {
+                    if let Some(update) = entry.get_mut() {
+                        entity.merge(update.clone());
+                        *update = entity;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply a change recorded in an `EntityCache` to the entity it was made to.
+fn apply_update(entity: Option<Entity>, update: Option<Option<Entity>>) -> Option<Entity> {
+    match (entity, update) {
+        // Entity is unchanged
+        (entity, None) => entity,
+        // Entity was deleted
+        (_, Some(None)) => None,
+        // Entity created
+        (None, Some(update)) => update,
+        // Entity updated
+        (Some(mut entity), Some(Some(update))) => {
+            entity.merge_remove_null_fields(update);
+            Some(entity)
+        }
+    }
+}
+
 impl LfuCache<EntityKey, Option<Entity>> {
     // Helper for cached lookup of an entity.
     fn get_entity(
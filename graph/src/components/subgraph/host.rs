@@ -6,11 +6,64 @@ use async_trait::async_trait;
 use failure::Error;
 use futures::sync::mpsc;
 
-use crate::components::metrics::HistogramVec;
+use crate::components::metrics::{CounterVec, HistogramVec};
 use crate::components::subgraph::SharedProofOfIndexing;
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
 
+/// An error that occurred while running a mapping handler.
+#[derive(Debug)]
+pub enum MappingError {
+    /// Processing the same trigger again will fail in the same way. This
+    /// covers reverts, ABI decoding failures, traps in the WASM module and
+    /// misuse of host functions. Errors like this fail the subgraph.
+    Deterministic(anyhow::Error),
+
+    /// The error may be transient, for example because the Ethereum node,
+    /// IPFS or the database could not be reached. Processing the trigger
+    /// should be retried.
+    Unknown(anyhow::Error),
+}
+
+impl MappingError {
+    pub fn is_deterministic(&self) -> bool {
+        match self {
+            MappingError::Deterministic(_) => true,
+            MappingError::Unknown(_) => false,
+        }
+    }
+
+    /// Add `context` to the error without changing its classification.
+    pub fn context(self, context: String) -> Self {
+        match self {
+            MappingError::Deterministic(e) => MappingError::Deterministic(e.context(context)),
+            MappingError::Unknown(e) => MappingError::Unknown(e.context(context)),
+        }
+    }
+
+    pub fn into_inner(self) -> anyhow::Error {
+        match self {
+            MappingError::Deterministic(e) | MappingError::Unknown(e) => e,
+        }
+    }
+}
+
+/// Errors are considered transient unless they are explicitly classified
+/// as deterministic.
+impl From<anyhow::Error> for MappingError {
+    fn from(e: anyhow::Error) -> Self {
+        MappingError::Unknown(e)
+    }
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MappingError::Deterministic(e) | MappingError::Unknown(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
 /// Common trait for runtime host implementations.
 #[async_trait]
 pub trait RuntimeHost: Send + Sync + Debug + 'static {
@@ -23,6 +76,18 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
     /// Returns true if the RuntimeHost has a handler for an Ethereum block.
     fn matches_block(&self, call: &EthereumBlockTriggerType, block_number: u64) -> bool;
 
+    /// Returns true if the RuntimeHost has a handler for changes to entities
+    /// of type `entity_type` in the deployment `source`.
+    fn matches_entity(
+        &self,
+        source: &SubgraphDeploymentId,
+        entity_type: &str,
+        block_number: u64,
+    ) -> bool;
+
+    /// The entity types that the mapping of the data source declares.
+    fn entities(&self) -> &[String];
+
     /// Process an Ethereum event and return a vector of entity operations.
     async fn process_log(
         &self,
@@ -32,7 +97,7 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
         log: &Arc<Log>,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error>;
+    ) -> Result<BlockState, MappingError>;
 
     /// Process an Ethereum call and return a vector of entity operations
     async fn process_call(
@@ -43,7 +108,7 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
         call: &Arc<EthereumCall>,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error>;
+    ) -> Result<BlockState, MappingError>;
 
     /// Process an Ethereum block and return a vector of entity operations
     async fn process_block(
@@ -53,12 +118,36 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
         trigger_type: &EthereumBlockTriggerType,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error>;
+    ) -> Result<BlockState, MappingError>;
+
+    /// Process an entity that the source deployment of a `subgraph` data
+    /// source wrote in `block`
+    async fn process_entity(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        entity_type: &str,
+        entity: &Entity,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError>;
+
+    /// Fetch the file of a `file/arweave` data source and pass it to the
+    /// handler of the data source as part of processing `block`
+    async fn process_file(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError>;
 }
 
 pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
     host_fn_execution_time: Box<HistogramVec>,
+    handler_host_fn_calls: Box<CounterVec>,
+    handler_entity_operations: Box<CounterVec>,
     pub stopwatch: StopwatchMetrics,
 }
 
@@ -93,9 +182,27 @@ impl HostMetrics {
                 vec![0.025, 0.05, 0.2, 2.0, 8.0, 20.0],
             )
             .expect("failed to create `subgraph_host_fn_execution_time` histogram");
+        let handler_host_fn_calls = registry
+            .new_counter_vec(
+                format!("subgraph_handler_host_fn_calls_{}", subgraph_hash),
+                String::from("Counts the host functions called by handlers"),
+                HashMap::new(),
+                vec![String::from("handler")],
+            )
+            .expect("failed to create `subgraph_handler_host_fn_calls` counter");
+        let handler_entity_operations = registry
+            .new_counter_vec(
+                format!("subgraph_handler_entity_operations_{}", subgraph_hash),
+                String::from("Counts the entity operations performed by handlers"),
+                HashMap::new(),
+                vec![String::from("handler"), String::from("operation")],
+            )
+            .expect("failed to create `subgraph_handler_entity_operations` counter");
         Self {
             handler_execution_time,
             host_fn_execution_time,
+            handler_host_fn_calls,
+            handler_entity_operations,
             stopwatch,
         }
     }
@@ -111,6 +218,20 @@ impl HostMetrics {
             .with_label_values(vec![fn_name].as_slice())
             .observe(duration);
     }
+
+    pub fn observe_handler_host_fn_calls(&self, count: u64, handler: &str) {
+        self.handler_host_fn_calls
+            .with_label_values(vec![handler].as_slice())
+            .inc_by(count as f64);
+    }
+
+    /// Count `count` entity operations of kind `operation` (`get`, `set`
+    /// or `remove`) for `handler`
+    pub fn observe_handler_entity_operations(&self, count: u64, handler: &str, operation: &str) {
+        self.handler_entity_operations
+            .with_label_values(vec![handler, operation].as_slice())
+            .inc_by(count as f64);
+    }
 }
 
 pub trait RuntimeHostBuilder: Clone + Send + Sync + 'static {
@@ -1,7 +1,9 @@
 use async_trait::async_trait;
+use std::collections::BTreeSet;
 use web3::types::Log;
 
-use crate::components::subgraph::SharedProofOfIndexing;
+use crate::components::subgraph::{PendingEvents, SharedProofOfIndexing};
+use crate::data::subgraph::schema::SubgraphError;
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
 
@@ -17,6 +19,21 @@ pub struct DataSourceTemplateInfo {
 pub struct BlockState {
     pub entity_cache: EntityCache,
     pub created_data_sources: Vec<DataSourceTemplateInfo>,
+
+    /// Errors of handlers that failed deterministically. The changes made
+    /// by these handlers have been discarded.
+    pub deterministic_errors: Vec<SubgraphError>,
+
+    /// Proof of indexing events of the current handler. They are written to
+    /// the proof of indexing when the handler succeeds.
+    pub poi_events: PendingEvents,
+
+    /// Whether the block is too far behind the chain head to be reorged
+    /// away, so that the results of `eth_call`s against it can be cached
+    pub block_is_final: bool,
+
+    /// The number of `created_data_sources` when the current handler started.
+    handler_start: usize,
 }
 
 impl BlockState {
@@ -24,8 +41,67 @@ impl BlockState {
         BlockState {
             entity_cache: EntityCache::with_current(store, lfu_cache),
             created_data_sources: Vec::new(),
+            deterministic_errors: Vec::new(),
+            poi_events: PendingEvents::default(),
+            block_is_final: false,
+            handler_start: 0,
         }
     }
+
+    /// Fetch entities that are not in the store from `fork`, as they were
+    /// after `block`, see `EntityCache::fork_block`
+    pub fn with_fork(mut self, fork: Option<Arc<dyn SubgraphFork>>, block: BlockNumber) -> Self {
+        self.entity_cache = self.entity_cache.with_fork(fork, block);
+        self
+    }
+
+    /// Mark the block as final, see `block_is_final`
+    pub fn with_block_finality(mut self, block_is_final: bool) -> Self {
+        self.block_is_final = block_is_final;
+        self
+    }
+
+    /// Track whether handlers access entities of other types than
+    /// `entity_types`, see `EntityCache::accessed_undeclared_types`
+    pub fn with_declared_types(mut self, entity_types: Arc<BTreeSet<String>>) -> Self {
+        self.entity_cache = self.entity_cache.with_declared_types(entity_types);
+        self
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.deterministic_errors.is_empty()
+    }
+
+    /// Track the changes of a handler separately until it either succeeds or
+    /// fails.
+    pub fn enter_handler(&mut self) {
+        self.entity_cache.enter_handler();
+        self.handler_start = self.created_data_sources.len();
+    }
+
+    /// Keep the changes of the current handler and write its proof of
+    /// indexing events to `proof_of_indexing`
+    pub fn exit_handler(
+        &mut self,
+        logger: &Logger,
+        proof_of_indexing: &SharedProofOfIndexing,
+    ) -> Result<(), QueryExecutionError> {
+        self.entity_cache.exit_handler()?;
+        let events = std::mem::take(&mut self.poi_events);
+        if let Some(proof_of_indexing) = proof_of_indexing {
+            proof_of_indexing.borrow_mut().write_pending(logger, events);
+        }
+        Ok(())
+    }
+
+    /// Drop the entity changes, data sources and proof of indexing events of
+    /// the current handler and remember why it failed.
+    pub fn exit_handler_and_discard_changes_due_to_error(&mut self, e: SubgraphError) {
+        self.entity_cache.exit_handler_and_discard_changes();
+        self.poi_events.clear();
+        self.created_data_sources.truncate(self.handler_start);
+        self.deterministic_errors.push(e);
+    }
 }
 
 /// Represents a loaded instance of a subgraph.
@@ -42,7 +118,7 @@ pub trait SubgraphInstance<H: RuntimeHost> {
         trigger: EthereumTrigger,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error>;
+    ) -> Result<BlockState, MappingError>;
 
     /// Like `process_trigger` but processes an Ethereum event in a given list of hosts.
     async fn process_trigger_in_runtime_hosts(
@@ -52,7 +128,20 @@ pub trait SubgraphInstance<H: RuntimeHost> {
         trigger: EthereumTrigger,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error>;
+    ) -> Result<BlockState, MappingError>;
+
+    /// Process an entity of type `entity_type` that the deployment `source`
+    /// wrote in `block` with all hosts that have a handler for it.
+    async fn process_entity_change(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        source: &SubgraphDeploymentId,
+        entity_type: &str,
+        entity: &Entity,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError>;
 
     /// Adds dynamic data sources to the subgraph.
     fn add_dynamic_data_source(
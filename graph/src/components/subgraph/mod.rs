@@ -5,16 +5,21 @@ mod loader;
 mod proof_of_indexing;
 mod provider;
 mod registrar;
+mod retries;
 
 pub use crate::prelude::Entity;
 
-pub use self::host::{HostMetrics, RuntimeHost, RuntimeHostBuilder};
+pub use self::host::{HostMetrics, MappingError, RuntimeHost, RuntimeHostBuilder};
 pub use self::instance::{BlockState, DataSourceTemplateInfo, SubgraphInstance};
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::loader::DataSourceLoader;
 pub use self::proof_of_indexing::{
-    BlockEventStream, ProofOfIndexing, ProofOfIndexingEvent, ProofOfIndexingFinisher,
-    SharedProofOfIndexing,
+    BlockEventStream, PendingEvents, ProofOfIndexing, ProofOfIndexingEvent,
+    ProofOfIndexingFinisher, RecordedEvent, SharedProofOfIndexing,
 };
 pub use self::provider::SubgraphAssignmentProvider;
-pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
+pub use self::registrar::{
+    DeploymentPlacer, DeploymentPriority, ImportFormat, SubgraphOperation,
+    SubgraphOperationOutcome, SubgraphRegistrar, SubgraphVersionSwitchingMode,
+};
+pub use self::retries::{Retries, Retry, RETRIES};
@@ -3,7 +3,9 @@ mod online;
 mod reference;
 
 pub use event::ProofOfIndexingEvent;
-pub use online::{BlockEventStream, ProofOfIndexing, ProofOfIndexingFinisher};
+pub use online::{
+    BlockEventStream, PendingEvents, ProofOfIndexing, ProofOfIndexingFinisher, RecordedEvent,
+};
 
 use atomic_refcell::AtomicRefCell;
 use std::sync::Arc;
@@ -264,4 +266,57 @@ mod tests {
             }
         }
     }
+
+    /// Events of handlers that ran in parallel are recorded out of order.
+    /// Replaying them has to give the same digest as writing the events of
+    /// the handlers one after the other
+    #[test]
+    fn replay_recorded_events() {
+        let logger = Logger::root(Discard, o!());
+        let data = hashmap! {
+            "val".to_owned() => Value::Int(1)
+        };
+        let set = |entity_type: &'static str| ProofOfIndexingEvent::SetEntity {
+            entity_type,
+            id: "id",
+            data: &data,
+        };
+        let remove = |entity_type: &'static str| ProofOfIndexingEvent::RemoveEntity {
+            entity_type,
+            id: "id",
+        };
+
+        let mut sequential = ProofOfIndexing::new(3);
+        sequential.write(&logger, "eth", &set("a"));
+        sequential.write(&logger, "eth", &set("b"));
+        sequential.write(&logger, "eth", &remove("a"));
+        sequential.write(&logger, "eth", &remove("b"));
+
+        // The data source at index 1 wrote entities of type `b`, the one at
+        // index 0 those of type `a`, for two triggers
+        let mut first = ProofOfIndexing::recording(3);
+        first.set_position((0, 1));
+        first.write(&logger, "eth", &set("b"));
+        first.set_position((1, 1));
+        first.write(&logger, "eth", &remove("b"));
+        let mut second = ProofOfIndexing::recording(3);
+        second.set_position((0, 0));
+        second.write(&logger, "eth", &set("a"));
+        second.set_position((1, 0));
+        second.write(&logger, "eth", &remove("a"));
+
+        let mut recorded = first.take_recorded();
+        recorded.extend(second.take_recorded());
+        assert!(first.take().is_empty());
+        let mut replayed = ProofOfIndexing::new(3);
+        replayed.replay(&logger, recorded);
+
+        let digest = |poi: ProofOfIndexing| {
+            poi.take()
+                .into_iter()
+                .map(|(name, stream)| (name, stream.pause(None)))
+                .collect::<HashMap<_, _>>()
+        };
+        assert_eq!(digest(sequential), digest(replayed));
+    }
 }
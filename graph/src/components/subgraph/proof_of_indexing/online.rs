@@ -3,7 +3,7 @@
 //! to the reference implementation, but this is updated incrementally
 
 use super::ProofOfIndexingEvent;
-use crate::prelude::{debug, EthereumBlockPointer, Logger, SubgraphDeploymentId};
+use crate::prelude::{debug, EthereumBlockPointer, Logger, SubgraphDeploymentId, Value};
 use lazy_static::lazy_static;
 use stable_hash::crypto::{Blake3SeqNo, SetHasher};
 use stable_hash::prelude::*;
@@ -107,6 +107,99 @@ pub struct ProofOfIndexing {
     /// state with other data sources. This may also give us some freedom to change
     /// the order of triggers in the future.
     per_causality_region: HashMap<String, BlockEventStream>,
+    /// Set for a proof of indexing that records events instead of hashing
+    /// them, see `ProofOfIndexing::recording`
+    recorded: Option<Vec<RecordedEvent>>,
+    /// The position that recorded events are tagged with
+    position: (usize, usize),
+}
+
+/// An event written to a recording `ProofOfIndexing`, together with the
+/// position of the handler that wrote it
+pub struct RecordedEvent {
+    position: (usize, usize),
+    causality_region: String,
+    event: OwnedEvent,
+}
+
+enum OwnedEvent {
+    RemoveEntity {
+        entity_type: String,
+        id: String,
+    },
+    SetEntity {
+        entity_type: String,
+        id: String,
+        data: HashMap<String, Value>,
+    },
+}
+
+impl OwnedEvent {
+    fn new(event: &ProofOfIndexingEvent<'_>) -> Self {
+        match event {
+            ProofOfIndexingEvent::RemoveEntity { entity_type, id } => OwnedEvent::RemoveEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+            },
+            ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => OwnedEvent::SetEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+                data: (*data).clone(),
+            },
+        }
+    }
+
+    fn as_event(&self) -> ProofOfIndexingEvent<'_> {
+        match self {
+            OwnedEvent::RemoveEntity { entity_type, id } => {
+                ProofOfIndexingEvent::RemoveEntity { entity_type, id }
+            }
+            OwnedEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            },
+        }
+    }
+}
+
+/// Events that a handler wrote to the proof of indexing. They are held back
+/// until the handler finishes so that the events of a handler whose changes
+/// are discarded never make it into the proof of indexing
+#[derive(Default)]
+pub struct PendingEvents(Vec<(String, OwnedEvent)>);
+
+impl PendingEvents {
+    pub fn push(&mut self, causality_region: &str, event: &ProofOfIndexingEvent<'_>) {
+        self.0
+            .push((causality_region.to_owned(), OwnedEvent::new(event)));
+    }
+
+    pub fn extend(&mut self, other: PendingEvents) {
+        self.0.extend(other.0);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for PendingEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PendingEvents").field(&self.0.len()).finish()
+    }
 }
 
 impl fmt::Debug for ProofOfIndexing {
@@ -120,8 +213,50 @@ impl ProofOfIndexing {
         Self {
             block_number,
             per_causality_region: HashMap::new(),
+            recorded: None,
+            position: (0, 0),
+        }
+    }
+
+    /// A proof of indexing that only records the events written to it. Each
+    /// event is tagged with the position set by `set_position` so that the
+    /// events of handlers that ran in parallel can be written to the proof
+    /// of indexing of the block in the order the handlers would have run in
+    /// one after the other, with `replay`
+    pub fn recording(block_number: u64) -> Self {
+        Self {
+            recorded: Some(Vec::new()),
+            ..Self::new(block_number)
         }
     }
+
+    /// Set the position of the handler that writes the next events, as the
+    /// index of its trigger in the block and of its data source
+    pub fn set_position(&mut self, position: (usize, usize)) {
+        self.position = position;
+    }
+
+    /// Take the events written to a recording proof of indexing
+    pub fn take_recorded(&mut self) -> Vec<RecordedEvent> {
+        self.recorded
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Write recorded events in the order of their positions. Events with
+    /// the same position are written in the order they were recorded in
+    pub fn replay(&mut self, logger: &Logger, mut events: Vec<RecordedEvent>) {
+        events.sort_by_key(|recorded| recorded.position);
+        for recorded in events {
+            self.write(
+                logger,
+                &recorded.causality_region,
+                &recorded.event.as_event(),
+            );
+        }
+    }
+
     /// Adds an event to the digest of the ProofOfIndexingStream local to the causality region
     pub fn write(
         &mut self,
@@ -129,6 +264,15 @@ impl ProofOfIndexing {
         causality_region: &str,
         event: &ProofOfIndexingEvent<'_>,
     ) {
+        if let Some(recorded) = &mut self.recorded {
+            recorded.push(RecordedEvent {
+                position: self.position,
+                causality_region: causality_region.to_owned(),
+                event: OwnedEvent::new(event),
+            });
+            return;
+        }
+
         if *LOG_EVENTS {
             debug!(
                 logger,
@@ -148,6 +292,15 @@ impl ProofOfIndexing {
                 .insert(causality_region.to_owned(), entry);
         }
     }
+
+    /// Write the events of a handler that finished in the order they were
+    /// written by the handler
+    pub fn write_pending(&mut self, logger: &Logger, pending: PendingEvents) {
+        for (causality_region, event) in pending.0 {
+            self.write(logger, &causality_region, &event.as_event());
+        }
+    }
+
     pub fn take(self) -> HashMap<String, BlockEventStream> {
         self.per_causality_region
     }
@@ -1,11 +1,21 @@
 use async_trait::async_trait;
+use std::fmt;
+use std::path::PathBuf;
 
 use crate::prelude::*;
 
-#[derive(Clone, Copy, Debug)]
+/// When a new version of a subgraph replaces its current version
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SubgraphVersionSwitchingMode {
+    /// Right away
     Instant,
+    /// Once the new version has synced, if the current version has synced;
+    /// otherwise right away
     Synced,
+    /// When it is promoted with `SubgraphRegistrar::promote_subgraph`; until
+    /// then, it syncs as the pending version. A subgraph without a current
+    /// version gets the new version as its current version right away
+    Manual,
 }
 
 impl SubgraphVersionSwitchingMode {
@@ -13,11 +23,128 @@ impl SubgraphVersionSwitchingMode {
         match mode.to_ascii_lowercase().as_str() {
             "instant" => SubgraphVersionSwitchingMode::Instant,
             "synced" => SubgraphVersionSwitchingMode::Synced,
+            "manual" => SubgraphVersionSwitchingMode::Manual,
             _ => panic!("invalid version switching mode: {:?}", mode),
         }
     }
 }
 
+/// How the data given to `SubgraphRegistrar::import_entities` is encoded
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// Comma-separated values with a header row that names the fields
+    Csv,
+}
+
+/// How a deployment fares against the other deployments of its node when
+/// they compete for sync slots, writes to the store, and requests to their
+/// Ethereum providers. Ordered from lowest to highest priority
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum DeploymentPriority {
+    /// Backfills and other deployments that can wait
+    Batch,
+    Normal,
+    /// Deployments that have to keep up with the chain head no matter what
+    Critical,
+}
+
+impl Default for DeploymentPriority {
+    fn default() -> Self {
+        DeploymentPriority::Normal
+    }
+}
+
+impl DeploymentPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeploymentPriority::Batch => "batch",
+            DeploymentPriority::Normal => "normal",
+            DeploymentPriority::Critical => "critical",
+        }
+    }
+
+    pub fn parse(priority: &str) -> Option<Self> {
+        match priority {
+            "batch" => Some(DeploymentPriority::Batch),
+            "normal" => Some(DeploymentPriority::Normal),
+            "critical" => Some(DeploymentPriority::Critical),
+            _ => None,
+        }
+    }
+
+    /// The fraction of a contended budget that deployments of this
+    /// priority may hold together while deployments of a higher priority
+    /// use it, too
+    pub fn share(&self) -> f64 {
+        match self {
+            DeploymentPriority::Batch => 0.25,
+            DeploymentPriority::Normal => 0.75,
+            DeploymentPriority::Critical => 1.0,
+        }
+    }
+}
+
+impl fmt::Display for DeploymentPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Decides which index nodes new deployments are assigned to.
+pub trait DeploymentPlacer: Send + Sync + 'static {
+    /// The index nodes a deployment of the subgraph `name` on `network` may
+    /// be assigned to, or `None` if the placer has no opinion about it.
+    fn place(&self, name: &str, network: &str) -> Result<Option<Vec<NodeId>>, String>;
+
+    /// The network alias whose providers should index a deployment of the
+    /// subgraph `name` on `network`, or `None` if it should use the
+    /// providers of `network` itself.
+    fn network_alias(&self, _name: &str, _network: &str) -> Option<String> {
+        None
+    }
+}
+
+/// One of the operations in a batch given to `SubgraphRegistrar::apply_batch`
+#[derive(Clone, Debug)]
+pub enum SubgraphOperation {
+    Create {
+        name: SubgraphName,
+    },
+    Deploy {
+        name: SubgraphName,
+        hash: SubgraphDeploymentId,
+        node_id: Option<NodeId>,
+    },
+    Remove {
+        name: SubgraphName,
+    },
+    Reassign {
+        hash: SubgraphDeploymentId,
+        node_id: NodeId,
+    },
+    Pause {
+        hash: SubgraphDeploymentId,
+    },
+    Resume {
+        hash: SubgraphDeploymentId,
+    },
+    Promote {
+        name: SubgraphName,
+        force: bool,
+    },
+}
+
+/// What a `SubgraphOperation` did
+#[derive(Debug)]
+pub enum SubgraphOperationOutcome {
+    Created(CreateSubgraphResult),
+    Done,
+}
+
 /// Common trait for subgraph registrars.
 #[async_trait]
 pub trait SubgraphRegistrar: Send + Sync + 'static {
@@ -26,13 +153,26 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         name: SubgraphName,
     ) -> Result<CreateSubgraphResult, SubgraphRegistrarError>;
 
+    /// Deploy `hash` as the new version of `name`. Without an
+    /// `assignment_node_id`, the deployment is assigned according to the
+    /// placement rules of the registrar.
     async fn create_subgraph_version(
         &self,
         name: SubgraphName,
         hash: SubgraphDeploymentId,
-        assignment_node_id: NodeId,
+        assignment_node_id: Option<NodeId>,
     ) -> Result<(), SubgraphRegistrarError>;
 
+    /// Store the subgraph that `graph build` wrote to `directory` in the
+    /// database and deploy it as the new version of `name`, without IPFS.
+    /// Returns the id of the deployment.
+    async fn create_subgraph_version_from_files(
+        &self,
+        name: SubgraphName,
+        directory: PathBuf,
+        assignment_node_id: Option<NodeId>,
+    ) -> Result<SubgraphDeploymentId, SubgraphRegistrarError>;
+
     async fn remove_subgraph(&self, name: SubgraphName) -> Result<(), SubgraphRegistrarError>;
 
     async fn reassign_subgraph(
@@ -40,4 +180,85 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         hash: SubgraphDeploymentId,
         node_id: NodeId,
     ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Stop indexing the deployment `hash`, but keep it assigned to its node.
+    async fn pause_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Resume indexing the deployment `hash` after it has been paused.
+    async fn resume_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Give the deployment `hash` the priority `priority`. A deployment
+    /// that is being indexed is restarted to pick it up.
+    async fn set_subgraph_priority(
+        &self,
+        hash: SubgraphDeploymentId,
+        priority: DeploymentPriority,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Make the pending version of `name` its current version. Unless
+    /// `force` is set, the pending version has to have synced.
+    async fn promote_subgraph(
+        &self,
+        name: SubgraphName,
+        force: bool,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Apply all `operations` in one transaction, or none of them if any
+    /// fails. Every operation sees the subgraphs as they were before the
+    /// batch, which is why a subgraph name or deployment may only appear
+    /// in one operation. Returns the result of each operation; if one
+    /// fails, the others report that they were not applied.
+    async fn apply_batch(
+        &self,
+        operations: Vec<SubgraphOperation>,
+    ) -> Vec<Result<SubgraphOperationOutcome, SubgraphRegistrarError>>;
+
+    /// Turn allowlist mode for the deployment `hash` on or off. In
+    /// allowlist mode, the deployment only runs the queries on its
+    /// allowlist and rejects all others.
+    async fn set_query_allowlist(
+        &self,
+        hash: SubgraphDeploymentId,
+        enabled: bool,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Put `query` on the allowlist of the deployment `hash`. Returns the
+    /// hash that identifies the query.
+    async fn allow_query(
+        &self,
+        hash: SubgraphDeploymentId,
+        query: String,
+    ) -> Result<String, SubgraphRegistrarError>;
+
+    /// Take the query with `query_hash` off the allowlist of the deployment
+    /// `hash`. Returns `false` if it was not on it.
+    async fn disallow_query(
+        &self,
+        hash: SubgraphDeploymentId,
+        query_hash: String,
+    ) -> Result<bool, SubgraphRegistrarError>;
+
+    /// The queries on the allowlist of the deployment `hash`.
+    async fn allowed_queries(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<Vec<AllowedQuery>, SubgraphRegistrarError>;
+
+    /// Parse entities of type `entity_type` from `data`, validate them
+    /// against the schema of the deployment `hash`, and add them to it as
+    /// of `block`. Returns how many entities were imported.
+    async fn import_entities(
+        &self,
+        hash: SubgraphDeploymentId,
+        entity_type: String,
+        format: ImportFormat,
+        data: String,
+        block: BlockNumber,
+    ) -> Result<usize, SubgraphRegistrarError>;
 }
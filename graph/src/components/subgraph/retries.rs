@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::components::ethereum::RpcErrorKind;
+use crate::data::subgraph::SubgraphDeploymentId;
+
+lazy_static! {
+    /// The retries of the deployments this node indexes
+    pub static ref RETRIES: Retries = Retries::default();
+}
+
+/// A deployment that ran into an error that may go away, e.g. because an
+/// Ethereum node or the database could not be reached, and that will be
+/// retried after a delay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retry {
+    /// How many times the deployment failed in a row
+    pub attempt: u64,
+    pub error: String,
+    /// The kind of the error if the Ethereum node caused it
+    pub kind: Option<RpcErrorKind>,
+    pub delay: Duration,
+    pub next_attempt_at: DateTime<Utc>,
+}
+
+/// Keeps track of the deployments that wait to be retried, so that the
+/// schedule can be looked at through the indexing status API.
+#[derive(Default)]
+pub struct Retries {
+    retries: Mutex<HashMap<SubgraphDeploymentId, Retry>>,
+}
+
+impl Retries {
+    /// Record that the deployment failed for the `attempt`-th time in a row
+    /// and will be retried after `delay`.
+    pub fn schedule(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        attempt: u64,
+        error: String,
+        kind: Option<RpcErrorKind>,
+        delay: Duration,
+    ) {
+        let next_attempt_at = Utc::now()
+            + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+        self.retries.lock().unwrap().insert(
+            subgraph_id.clone(),
+            Retry {
+                attempt,
+                error,
+                kind,
+                delay,
+                next_attempt_at,
+            },
+        );
+    }
+
+    pub fn get(&self, subgraph_id: &SubgraphDeploymentId) -> Option<Retry> {
+        self.retries.lock().unwrap().get(subgraph_id).cloned()
+    }
+
+    /// Forget the retries of a deployment, e.g. because it made progress
+    /// again or the node stopped indexing it.
+    pub fn remove(&self, subgraph_id: &SubgraphDeploymentId) {
+        self.retries.lock().unwrap().remove(subgraph_id);
+    }
+}
@@ -11,8 +11,10 @@ use std::time::{Duration, Instant};
 
 use crate::components::metrics::{Gauge, MetricsRegistry};
 use crate::components::store::PoolWaitStats;
+use crate::data::graphql::query_stats::QueryStats;
 use crate::data::graphql::shape_hash::shape_hash;
 use crate::data::query::QueryExecutionError;
+use crate::data::subgraph::SubgraphDeploymentId;
 use crate::prelude::{debug, info, o, warn, Logger};
 use crate::util::stats::{MovingStats, BIN_SIZE, WINDOW_SIZE};
 
@@ -237,6 +239,7 @@ pub struct LoadManager {
     jailed_queries: RwLock<HashSet<u64>>,
     kill_state: RwLock<KillState>,
     effort_gauge: Box<Gauge>,
+    stats: QueryStats,
 }
 
 impl LoadManager {
@@ -268,6 +271,7 @@ impl LoadManager {
                 HashMap::new(),
             )
             .expect("failed to create `query_effort_ms` counter");
+        let stats = QueryStats::new(&logger, &*registry);
         Self {
             logger,
             effort: QueryEffort::default(),
@@ -276,10 +280,24 @@ impl LoadManager {
             jailed_queries: RwLock::new(HashSet::new()),
             kill_state: RwLock::new(KillState::new()),
             effort_gauge,
+            stats,
         }
     }
 
-    pub fn add_query(&self, shape_hash: u64, duration: Duration) {
+    /// Record that `query` ran against `deployment` in `duration`; `ok`
+    /// and `cached` indicate whether it succeeded and whether its result
+    /// came from the query cache
+    pub fn add_query(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        shape_hash: u64,
+        query: &str,
+        duration: Duration,
+        ok: bool,
+        cached: bool,
+    ) {
+        self.stats
+            .add(deployment, shape_hash, query, duration, ok, cached);
         if !*LOAD_MANAGEMENT_DISABLED {
             self.effort.add(shape_hash, duration, &self.effort_gauge);
         }
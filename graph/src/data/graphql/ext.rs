@@ -1,4 +1,4 @@
-use crate::data::schema::SCHEMA_TYPE_NAME;
+use crate::data::schema::{MUTATION_TYPE_NAME, SCHEMA_TYPE_NAME};
 use graphql_parser::schema::{
     Definition, Directive, Document, EnumType, Field, InterfaceType, Name, ObjectType, Type,
     TypeDefinition, Value,
@@ -8,18 +8,30 @@ use std::collections::{BTreeMap, HashMap};
 
 pub trait ObjectTypeExt {
     fn field(&self, name: &Name) -> Option<&Field>;
+
+    /// Whether this is the `Mutation` type whose mutations are forwarded
+    /// to an external service rather than an entity type
+    fn is_mutation_type(&self) -> bool;
 }
 
 impl ObjectTypeExt for ObjectType {
     fn field(&self, name: &Name) -> Option<&Field> {
         self.fields.iter().find(|field| &field.name == name)
     }
+
+    fn is_mutation_type(&self) -> bool {
+        self.name == MUTATION_TYPE_NAME && self.find_directive("entity".to_string()).is_none()
+    }
 }
 
 impl ObjectTypeExt for InterfaceType {
     fn field(&self, name: &Name) -> Option<&Field> {
         self.fields.iter().find(|field| &field.name == name)
     }
+
+    fn is_mutation_type(&self) -> bool {
+        false
+    }
 }
 
 pub trait DocumentExt {
@@ -34,6 +46,8 @@ pub trait DocumentExt {
     fn find_interface(&self, name: &str) -> Option<&InterfaceType>;
 
     fn get_fulltext_directives<'a>(&'a self) -> Vec<&'a Directive>;
+
+    fn get_mutation_type(&self) -> Option<&ObjectType>;
 }
 
 impl DocumentExt for Document {
@@ -93,6 +107,11 @@ impl DocumentExt for Document {
                     .collect()
             })
     }
+    fn get_mutation_type(&self) -> Option<&ObjectType> {
+        self.get_object_type_definitions()
+            .into_iter()
+            .find(|object_type| object_type.is_mutation_type())
+    }
 }
 
 pub trait TypeExt {
@@ -179,6 +198,30 @@ impl DirectiveFinder for Field {
     }
 }
 
+pub trait FieldExt {
+    /// Whether the field is marked `@unindexed`, which means that it can
+    /// not be used in filters or for sorting and that the store does not
+    /// index it
+    fn is_unindexed(&self) -> bool;
+
+    /// If this is one of the `<field>Count` fields that the API schema adds
+    /// for `@derivedFrom` list fields, the name of the field it counts
+    fn derived_count_field(&self) -> Option<&str>;
+}
+
+impl FieldExt for Field {
+    fn is_unindexed(&self) -> bool {
+        self.find_directive(Name::from("unindexed")).is_some()
+    }
+
+    fn derived_count_field(&self) -> Option<&str> {
+        self.find_directive(Name::from("derivedCount"))
+            .and_then(|directive| directive.argument("field"))
+            .and_then(|value| value.as_string())
+            .map(|field| field.as_str())
+    }
+}
+
 impl DirectiveFinder for Vec<Directive> {
     fn find_directive(&self, name: Name) -> Option<&Directive> {
         self.iter().find(|directive| directive.name.eq(&name))
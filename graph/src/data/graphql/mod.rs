@@ -23,3 +23,5 @@ pub use self::values::{
 pub mod shape_hash;
 
 pub mod effort;
+
+pub mod query_stats;
@@ -0,0 +1,288 @@
+//! Statistics about the queries a node runs, aggregated by deployment and
+//! query shape, to find the queries that are worth optimizing
+
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::components::metrics::{CounterVec, HistogramVec, MetricsRegistry};
+use crate::data::subgraph::SubgraphDeploymentId;
+use crate::prelude::{info, Logger};
+
+lazy_static! {
+    /// Whether to report metrics for each query shape to Prometheus. That
+    /// is off by default since the number of query shapes is unbounded
+    static ref SHAPE_METRICS: bool = env::var("GRAPH_QUERY_SHAPE_METRICS")
+        .ok()
+        .map(|s| {
+            bool::from_str(&s).unwrap_or_else(|_| {
+                panic!("GRAPH_QUERY_SHAPE_METRICS must be `true` or `false`, but is `{}`", s)
+            })
+        })
+        .unwrap_or(false);
+
+    /// How often to log a summary of the most expensive query shapes. A
+    /// value of 0 turns the summary off
+    static ref SUMMARY_INTERVAL: Duration = {
+        let interval = env::var("GRAPH_QUERY_SHAPE_SUMMARY_INTERVAL")
+            .ok()
+            .map(|s| {
+                u64::from_str(&s).unwrap_or_else(|_| {
+                    panic!("GRAPH_QUERY_SHAPE_SUMMARY_INTERVAL must be a number, but is `{}`", s)
+                })
+            })
+            .unwrap_or(300);
+        Duration::from_secs(interval)
+    };
+
+    /// How many query shapes to include in the summary
+    static ref SUMMARY_SIZE: usize = env::var("GRAPH_QUERY_SHAPE_SUMMARY_SIZE")
+        .ok()
+        .map(|s| {
+            usize::from_str(&s).unwrap_or_else(|_| {
+                panic!("GRAPH_QUERY_SHAPE_SUMMARY_SIZE must be a number, but is `{}`", s)
+            })
+        })
+        .unwrap_or(10);
+}
+
+/// Upper bounds in milliseconds of the buckets that we sort query times
+/// into to estimate percentiles; one more bucket holds everything slower
+const BUCKETS: [u64; 15] = [
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000, 20000, 50000,
+];
+
+/// What we know about one query shape for one deployment since the last
+/// summary
+#[derive(Default)]
+struct ShapeStats {
+    count: u64,
+    errors: u64,
+    cache_hits: u64,
+    total: Duration,
+    buckets: [u64; BUCKETS.len() + 1],
+    /// One query with this shape, to make the summary readable
+    query: String,
+}
+
+impl ShapeStats {
+    fn add(&mut self, query: &str, duration: Duration, ok: bool, cached: bool) {
+        if self.query.is_empty() {
+            self.query = query.to_owned();
+        }
+        self.count += 1;
+        self.errors += if ok { 0 } else { 1 };
+        self.cache_hits += if cached { 1 } else { 0 };
+        self.total += duration;
+        let ms = duration.as_millis() as u64;
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    /// An upper bound in milliseconds for the time that a fraction `p` of
+    /// queries took, or `None` if that is longer than the largest bucket
+    fn percentile(&self, p: f64) -> Option<u64> {
+        let rank = (p * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BUCKETS.get(bucket).cloned();
+            }
+        }
+        None
+    }
+}
+
+fn format_percentile(ms: Option<u64>) -> String {
+    match ms {
+        Some(ms) => format!("<={}", ms),
+        None => format!(">{}", BUCKETS[BUCKETS.len() - 1]),
+    }
+}
+
+struct Summary {
+    since: Instant,
+    shapes: HashMap<(SubgraphDeploymentId, u64), ShapeStats>,
+}
+
+/// Aggregates query count, latency, errors and cache hits by deployment and
+/// query shape. The numbers go to Prometheus, and every
+/// `GRAPH_QUERY_SHAPE_SUMMARY_INTERVAL` seconds a summary of the query
+/// shapes that took the most time is logged
+pub struct QueryStats {
+    logger: Logger,
+    summary: Mutex<Summary>,
+    query_count: Box<CounterVec>,
+    cache_hits: Box<CounterVec>,
+    shape_execution_time: Option<Box<HistogramVec>>,
+    shape_failed: Option<Box<CounterVec>>,
+}
+
+impl QueryStats {
+    pub fn new(logger: &Logger, registry: &dyn MetricsRegistry) -> Self {
+        let query_count = registry
+            .new_counter_vec(
+                String::from("deployment_query_count"),
+                String::from("Number of queries run against a deployment"),
+                HashMap::new(),
+                vec![String::from("deployment"), String::from("status")],
+            )
+            .expect("failed to create `deployment_query_count` counter");
+        let cache_hits = registry
+            .new_counter_vec(
+                String::from("deployment_query_cache_hits"),
+                String::from("Number of queries against a deployment answered from the cache"),
+                HashMap::new(),
+                vec![String::from("deployment")],
+            )
+            .expect("failed to create `deployment_query_cache_hits` counter");
+        let (shape_execution_time, shape_failed) = if *SHAPE_METRICS {
+            let execution_time = registry
+                .new_histogram_vec(
+                    String::from("query_shape_execution_time"),
+                    String::from("Execution time in seconds for queries with a given shape"),
+                    HashMap::new(),
+                    vec![String::from("deployment"), String::from("shape")],
+                    vec![0.01, 0.1, 0.5, 1.0, 10.0, 100.0],
+                )
+                .expect("failed to create `query_shape_execution_time` histogram");
+            let failed = registry
+                .new_counter_vec(
+                    String::from("query_shape_failed"),
+                    String::from("Number of failed queries with a given shape"),
+                    HashMap::new(),
+                    vec![String::from("deployment"), String::from("shape")],
+                )
+                .expect("failed to create `query_shape_failed` counter");
+            (Some(execution_time), Some(failed))
+        } else {
+            (None, None)
+        };
+
+        QueryStats {
+            logger: logger.clone(),
+            summary: Mutex::new(Summary {
+                since: Instant::now(),
+                shapes: HashMap::new(),
+            }),
+            query_count,
+            cache_hits,
+            shape_execution_time,
+            shape_failed,
+        }
+    }
+
+    /// Record that `query` with shape `shape_hash` ran against `deployment`
+    /// in `duration`, whether it succeeded, and whether its result came
+    /// from the query cache
+    pub fn add(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        shape_hash: u64,
+        query: &str,
+        duration: Duration,
+        ok: bool,
+        cached: bool,
+    ) {
+        let status = if ok { "success" } else { "failed" };
+        self.query_count
+            .with_label_values(&[deployment.as_str(), status])
+            .inc();
+        if cached {
+            self.cache_hits
+                .with_label_values(&[deployment.as_str()])
+                .inc();
+        }
+        if let (Some(execution_time), Some(failed)) =
+            (&self.shape_execution_time, &self.shape_failed)
+        {
+            let shape = format!("{:x}", shape_hash);
+            execution_time
+                .with_label_values(&[deployment.as_str(), &shape])
+                .observe(duration.as_secs_f64());
+            if !ok {
+                failed
+                    .with_label_values(&[deployment.as_str(), &shape])
+                    .inc();
+            }
+        }
+
+        if *SUMMARY_INTERVAL == Duration::from_secs(0) {
+            return;
+        }
+        let expired = {
+            let mut summary = self.summary.lock().unwrap();
+            summary
+                .shapes
+                .entry((deployment.clone(), shape_hash))
+                .or_default()
+                .add(query, duration, ok, cached);
+            if summary.since.elapsed() >= *SUMMARY_INTERVAL {
+                Some(std::mem::replace(
+                    &mut *summary,
+                    Summary {
+                        since: Instant::now(),
+                        shapes: HashMap::new(),
+                    },
+                ))
+            } else {
+                None
+            }
+        };
+        // Log after releasing the lock so that queries do not wait for that
+        if let Some(summary) = expired {
+            self.log_summary(summary);
+        }
+    }
+
+    fn log_summary(&self, summary: Summary) {
+        let elapsed = summary.since.elapsed().as_secs_f64();
+        let mut shapes: Vec<_> = summary.shapes.into_iter().collect();
+        shapes.sort_by(|(_, a), (_, b)| b.total.cmp(&a.total));
+        for ((deployment, shape_hash), stats) in shapes.into_iter().take(*SUMMARY_SIZE) {
+            info!(self.logger, "Query shape summary";
+                "deployment" => deployment.as_str(),
+                "shape" => format!("{:x}", shape_hash),
+                "count" => stats.count,
+                "qps" => format!("{:.2}", stats.count as f64 / elapsed),
+                "total_ms" => stats.total.as_millis(),
+                "avg_ms" => (stats.total / stats.count as u32).as_millis(),
+                "p50_ms" => format_percentile(stats.percentile(0.5)),
+                "p95_ms" => format_percentile(stats.percentile(0.95)),
+                "p99_ms" => format_percentile(stats.percentile(0.99)),
+                "error_rate" => format!("{:.4}", stats.errors as f64 / stats.count as f64),
+                "cache_hit_rate" => format!("{:.4}", stats.cache_hits as f64 / stats.count as f64),
+                "query" => stats.query,
+                "interval_s" => format!("{:.0}", elapsed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let mut stats = ShapeStats::default();
+        for ms in 1..=100 {
+            stats.add("{ things { id } }", Duration::from_millis(ms), true, false);
+        }
+        assert_eq!(Some(50), stats.percentile(0.5));
+        assert_eq!(Some(100), stats.percentile(0.95));
+        assert_eq!(Some(1), stats.percentile(0.0));
+
+        stats.add("{ things { id } }", Duration::from_secs(60), false, true);
+        assert_eq!(None, stats.percentile(1.0));
+        assert_eq!(1, stats.errors);
+        assert_eq!(1, stats.cache_hits);
+        assert_eq!(">50000", format_percentile(stats.percentile(1.0)));
+    }
+}
@@ -9,7 +9,7 @@ use std::fmt;
 use std::string::FromUtf8Error;
 use std::sync::Arc;
 
-use crate::components::store::StoreError;
+use crate::components::store::{BlockNumber, StoreError};
 use crate::data::graphql::SerializableValue;
 use crate::data::subgraph::*;
 
@@ -78,6 +78,14 @@ pub enum QueryExecutionError {
     Panic(String),
     EventStreamError,
     FulltextQueryRequiresFilter,
+    DeploymentHasErrors(String),
+    UnindexedFieldError(String, String),
+    QueryNotAllowed(String, String),
+    MutationFailed(String),
+    RegexTooLong(String, usize),
+    TriggerDryRunFailed(String),
+    BlockByTimestampFailed(String),
+    BlockNotReached(String, u64, BlockNumber), // (subgraph, latest block, requested block)
 }
 
 impl Error for QueryExecutionError {
@@ -226,7 +234,21 @@ impl fmt::Display for QueryExecutionError {
             EventStreamError => write!(f, "error in the subscription event stream"),
             FulltextQueryRequiresFilter => write!(f, "fulltext search queries can only use EntityFilter::Equal"),
             TooExpensive => write!(f, "query is too expensive"),
-            Throttled=> write!(f, "service is overloaded and can not run the query right now. Please try again in a few minutes")
+            Throttled=> write!(f, "service is overloaded and can not run the query right now. Please try again in a few minutes"),
+            DeploymentHasErrors(id) => write!(f, "indexing errors in subgraph `{}`, \
+                           query with `subgraphError: allow` to get the data anyways", id),
+            UnindexedFieldError(entity, field) => write!(f, "the field `{}` of type `{}` is \
+                           not indexed and can not be used to filter or sort by", field, entity),
+            QueryNotAllowed(id, hash) => write!(f, "subgraph `{}` only runs the queries on its \
+                           allowlist, and the query with hash `{}` is not on it", id, hash),
+            MutationFailed(msg) => write!(f, "{}", msg),
+            RegexTooLong(field, max) => write!(f, "the regular expression for `{}_matches` \
+                           is longer than the limit of {} characters", field, max),
+            TriggerDryRunFailed(msg) => write!(f, "trigger dry run failed: {}", msg),
+            BlockByTimestampFailed(msg) => write!(f, "failed to find block by timestamp: {}", msg),
+            BlockNotReached(id, latest, number) => write!(f, "subgraph `{}` has only indexed up \
+                           to block number {} and did not reach block number {} in time; \
+                           try again later", id, latest, number),
         }
     }
 }
@@ -3,5 +3,5 @@ mod query;
 mod result;
 
 pub use self::error::{QueryError, QueryExecutionError};
-pub use self::query::{Query, QueryVariables};
+pub use self::query::{query_hash, Query, QueryVariables};
 pub use self::result::QueryResult;
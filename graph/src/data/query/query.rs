@@ -4,6 +4,7 @@ use serde::Deserialize;
 use std::collections::{BTreeMap, HashMap};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use tiny_keccak::keccak256;
 
 use crate::data::graphql::shape_hash::shape_hash;
 use crate::data::schema::Schema;
@@ -117,6 +118,16 @@ pub struct Query {
     _force_use_of_new: (),
 }
 
+/// The hash that identifies a query on the allowlist of a deployment. It
+/// is taken over the normalized query text, so that formatting and comments
+/// do not change it, but it does depend on the literal values in the query
+pub fn query_hash(document: &q::Document) -> String {
+    format!(
+        "0x{}",
+        hex::encode(keccak256(document.to_string().as_bytes()))
+    )
+}
+
 impl Query {
     /// The `network` is currently used only for caching purposes, so it is not mandatory.
     pub fn new(
@@ -150,3 +161,21 @@ impl Query {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_hash_ignores_formatting() {
+        let parse = |text| graphql_parser::parse_query(text).expect("query is valid");
+        let hash = query_hash(&parse("{ things(first: 5) { id name } }"));
+        assert_eq!(
+            hash,
+            query_hash(&parse(
+                "# the first things\nquery {\n  things(first: 5) {\n    id\n    name\n  }\n}"
+            ))
+        );
+        assert_ne!(hash, query_hash(&parse("{ things(first: 6) { id name } }")));
+    }
+}
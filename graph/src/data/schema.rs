@@ -1,5 +1,7 @@
 use crate::components::store::{Store, SubgraphDeploymentStore};
-use crate::data::graphql::ext::{DirectiveExt, DirectiveFinder, DocumentExt, TypeExt, ValueExt};
+use crate::data::graphql::ext::{
+    DirectiveExt, DirectiveFinder, DocumentExt, FieldExt, ObjectTypeExt, TypeExt, ValueExt,
+};
 use crate::data::store::ValueType;
 use crate::data::subgraph::{SubgraphDeploymentId, SubgraphName};
 use crate::prelude::Fail;
@@ -24,6 +26,10 @@ use std::sync::Arc;
 
 pub const SCHEMA_TYPE_NAME: &str = "_Schema_";
 
+/// The type that lists the mutations of a subgraph. Unlike the other
+/// object types, it is not an entity type
+pub const MUTATION_TYPE_NAME: &str = "Mutation";
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Strings(Vec<String>);
 
@@ -108,6 +114,12 @@ pub enum SchemaValidationError {
     FulltextIncludedFieldMissingRequiredProperty,
     #[fail(display = "Fulltext entity field, {}, not found or not a string", _0)]
     FulltextIncludedFieldInvalid(String),
+    #[fail(
+        display = "Field `{}` in type `{}` can not be @unindexed; only String and Bytes \
+                   attributes other than `id` can be",
+        _1, _0
+    )]
+    UnindexedFieldInvalid(String, String), // (type, field)
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -525,6 +537,17 @@ impl Schema {
             })
     }
 
+    /// Whether `name` is an object or interface type that is defined in or
+    /// imported into this schema.
+    pub fn defines_type(&self, name: &str) -> bool {
+        self.document.get_object_type_definition(name).is_some()
+            || self.document.find_interface(name).is_some()
+            || self
+                .imported_types()
+                .keys()
+                .any(|imported| imported.alias == name)
+    }
+
     pub fn imported_schemas(&self) -> Vec<SchemaReference> {
         self.subgraph_schema_object_type().map_or(vec![], |object| {
             object
@@ -614,6 +637,7 @@ impl Schema {
         self.validate_directives_on_schema_type()
             .unwrap_or_else(|err| errors.push(err));
         errors.append(&mut self.validate_fields());
+        errors.append(&mut self.validate_unindexed_fields());
         errors.append(&mut self.validate_import_directives());
         errors.append(&mut self.validate_fulltext_directives());
         errors.append(&mut self.validate_imported_types(schemas));
@@ -1029,13 +1053,33 @@ impl Schema {
             })
     }
 
+    /// Only large attributes make sense as `@unindexed`, and entities
+    /// always need to be looked up by their `id`
+    fn validate_unindexed_fields(&self) -> Vec<SchemaValidationError> {
+        let mut errors = vec![];
+        for (type_name, fields) in self.document.get_object_and_interface_type_fields() {
+            for field in fields.iter().filter(|field| field.is_unindexed()) {
+                let base = field.field_type.get_base_type();
+                if field.name == "id" || (base != "String" && base != "Bytes") {
+                    errors.push(SchemaValidationError::UnindexedFieldInvalid(
+                        type_name.to_string(),
+                        field.name.to_string(),
+                    ));
+                }
+            }
+        }
+        errors
+    }
+
     fn validate_schema_types(&self) -> Result<(), SchemaValidationError> {
         let types_without_entity_directive = self
             .document
             .get_object_type_definitions()
             .iter()
             .filter(|t| {
-                t.find_directive(String::from("entity")).is_none() && !t.name.eq(SCHEMA_TYPE_NAME)
+                t.find_directive(String::from("entity")).is_none()
+                    && !t.name.eq(SCHEMA_TYPE_NAME)
+                    && !t.is_mutation_type()
             })
             .map(|t| t.name.to_owned())
             .collect::<Vec<_>>();
@@ -1352,6 +1396,28 @@ type Account implements Address @entity { id: ID!, txn: Transaction! @derivedFro
     validate("j: B @derivedFrom(field: \"id\")", "ok");
 }
 
+#[test]
+fn test_unindexed_field_validation() {
+    const SCHEMA: &str = "
+type Transaction @entity {
+  id: ID!
+  input: Bytes! @unindexed
+  metadata: String @unindexed
+  blob: [Bytes!] @unindexed
+  value: BigInt! @unindexed
+}";
+
+    let document = graphql_parser::parse_schema(SCHEMA).expect("Failed to parse schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+    assert_eq!(
+        vec![SchemaValidationError::UnindexedFieldInvalid(
+            "Transaction".to_owned(),
+            "value".to_owned()
+        )],
+        schema.validate_unindexed_fields()
+    );
+}
+
 #[test]
 fn test_reserved_type_with_fields() {
     const ROOT_SCHEMA: &str = "
@@ -225,6 +225,25 @@ impl Value {
                     _ => Value::String(s.clone()),
                 }
             }
+            // Numbers for `BigInt` and `BigDecimal` fields keep that type so
+            // that the store compares them as numbers, and not as an `Int`
+            // that a large stored value may not fit into
+            (query::Value::Int(i), NamedType(n))
+                if n == BIG_INT_SCALAR || n == BIG_DECIMAL_SCALAR =>
+            {
+                let i = i
+                    .to_owned()
+                    .as_i64()
+                    .ok_or_else(|| QueryExecutionError::NamedTypeError(n.to_string()))?;
+                if n == BIG_INT_SCALAR {
+                    Value::BigInt(scalar::BigInt::from(i))
+                } else {
+                    Value::BigDecimal(scalar::BigDecimal::from(i))
+                }
+            }
+            (query::Value::Float(f), NamedType(n)) if n == BIG_DECIMAL_SCALAR => {
+                Value::BigDecimal(scalar::BigDecimal::from(*f))
+            }
             (query::Value::Int(i), _) => Value::Int(
                 i.to_owned()
                     .as_i64()
@@ -627,3 +646,16 @@ fn value_bigint() {
     );
     assert_eq!(query::Value::from(from_query), graphql_value);
 }
+
+#[test]
+fn value_big_numbers_from_literals() {
+    let ty = query::Type::NamedType(BIG_INT_SCALAR.to_owned());
+    let from_query = Value::from_query_value(&query::Value::Int(17.into()), &ty).unwrap();
+    assert_eq!(from_query, Value::BigInt(scalar::BigInt::from(17)));
+
+    let ty = query::Type::NamedType(BIG_DECIMAL_SCALAR.to_owned());
+    let from_query = Value::from_query_value(&query::Value::Int(17.into()), &ty).unwrap();
+    assert_eq!(from_query, Value::BigDecimal(scalar::BigDecimal::from(17)));
+    let from_query = Value::from_query_value(&query::Value::Float(1.5), &ty).unwrap();
+    assert_eq!(from_query, Value::BigDecimal(scalar::BigDecimal::from(1.5)));
+}
@@ -14,7 +14,7 @@ use stable_hash::{
 use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Display, Formatter};
 use std::io::Write;
-use std::ops::{Add, Deref, Div, Mul, Rem, Sub};
+use std::ops::{Add, BitAnd, BitOr, Deref, Div, Mul, Rem, Shl, Shr, Sub};
 use std::str::FromStr;
 
 pub use num_bigint::Sign as BigIntSign;
@@ -62,6 +62,22 @@ impl BigDecimal {
         self.0.digits()
     }
 
+    /// Check that the exponent of this value, when written as
+    /// `digits * 10^exp`, lies between `MIN_EXP` and `MAX_EXP`
+    pub fn check_exponent(&self) -> Result<(), anyhow::Error> {
+        let exp = -self.as_bigint_and_exponent().1;
+        let min_exp: i64 = Self::MIN_EXP.into();
+        let max_exp: i64 = Self::MAX_EXP.into();
+        anyhow::ensure!(
+            min_exp <= exp && exp <= max_exp,
+            "big decimal exponent `{}` is outside the `{}` to `{}` range",
+            exp,
+            min_exp,
+            max_exp
+        );
+        Ok(())
+    }
+
     // Copy-pasted from `bigdecimal::BigDecimal::normalize`. We can use the upstream version once it
     // is included in a released version supported by Diesel.
     #[must_use]
@@ -323,6 +339,15 @@ impl BigInt {
     pub fn bits(&self) -> usize {
         self.0.bits()
     }
+
+    /// The integer square root, rounded down. Panics for negative numbers.
+    pub fn sqrt(&self) -> Self {
+        assert!(
+            self.0.sign() != BigIntSign::Minus,
+            "square root of a negative BigInt"
+        );
+        BigInt(self.0.sqrt())
+    }
 }
 
 impl Display for BigInt {
@@ -442,10 +467,49 @@ impl Rem for BigInt {
     type Output = BigInt;
 
     fn rem(self, other: BigInt) -> BigInt {
+        if other == BigInt::from(0) {
+            panic!("Cannot take the remainder of a division by zero-valued `BigInt`!")
+        }
+
         BigInt(self.0.rem(other.0))
     }
 }
 
+/// Bit operations treat negative numbers as if they were in two's
+/// complement representation with infinite sign extension
+impl BitOr for BigInt {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self::from(self.0.bitor(other.0))
+    }
+}
+
+impl BitAnd for BigInt {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self::from(self.0.bitand(other.0))
+    }
+}
+
+impl Shl<u8> for BigInt {
+    type Output = Self;
+
+    fn shl(self, bits: u8) -> Self {
+        Self::from(self.0.shl(usize::from(bits)))
+    }
+}
+
+/// Shifting negative numbers rounds towards negative infinity
+impl Shr<u8> for BigInt {
+    type Output = Self;
+
+    fn shr(self, bits: u8) -> Self {
+        Self::from(self.0.shr(usize::from(bits)))
+    }
+}
+
 /// A byte array that's serialized as a hex string prefixed by `0x`.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Bytes(Box<[u8]>);
@@ -538,6 +602,34 @@ mod test {
         assert_eq!(left, right);
     }
 
+    #[test]
+    fn big_int_bit_operations() {
+        let n = |i: i32| BigInt::from(i);
+
+        assert_eq!(n(-5), n(-6) | n(3));
+        assert_eq!(n(2), n(-6) & n(3));
+        assert_eq!(n(7), n(6) | n(3));
+        assert_eq!(n(1024), n(1) << 10);
+        assert_eq!(n(-3), n(-5) >> 1);
+        assert_eq!(n(2), n(5) >> 1);
+        assert_eq!(n(4), n(17).sqrt());
+        assert_eq!(n(0), n(0).sqrt());
+    }
+
+    #[test]
+    fn big_decimal_exponent_limits() {
+        let dec = |exp: i64| BigDecimal::new(BigInt::from(1), exp);
+
+        assert!(dec(BigDecimal::MAX_EXP.into()).check_exponent().is_ok());
+        assert!(dec(BigDecimal::MIN_EXP.into()).check_exponent().is_ok());
+        assert!(dec(i64::from(BigDecimal::MAX_EXP) + 1)
+            .check_exponent()
+            .is_err());
+        assert!(dec(i64::from(BigDecimal::MIN_EXP) - 1)
+            .check_exponent()
+            .is_err());
+    }
+
     #[test]
     fn big_int_stable_hash_same_as_int() {
         same_stable_hash(0, BigInt::from(0u64));
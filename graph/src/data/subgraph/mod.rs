@@ -15,9 +15,11 @@ use stable_hash::prelude::*;
 use wasmparser;
 use web3::types::{Address, H256};
 
+use crate::components::arweave::is_valid_tx_id;
 use crate::components::link_resolver::LinkResolver;
 use crate::components::store::{Store, StoreError, SubgraphDeploymentStore};
 use crate::components::subgraph::DataSourceTemplateInfo;
+use crate::data::graphql::ext::{DirectiveFinder, DocumentExt, ObjectTypeExt, TypeExt};
 use crate::data::graphql::{TryFromValue, ValueMap};
 use crate::data::query::QueryExecutionError;
 use crate::data::schema::{Schema, SchemaImportError, SchemaValidationError};
@@ -32,10 +34,15 @@ use crate::prelude::{
     anyhow::{self, Context},
     format_err, impl_slog_value, BlockNumber, Deserialize, Fail, Serialize,
 };
-use crate::util::ethereum::string_to_h256;
+use crate::util::ethereum::{
+    contract_event_with_signature, contract_function_with_signature, function_signature,
+    string_to_h256,
+};
 use graphql_parser::query as q;
+use graphql_parser::schema as s;
 
 use crate::components::ethereum::NodeCapabilities;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
@@ -47,6 +54,23 @@ lazy_static! {
         .ok()
         .map(|s| s.eq_ignore_ascii_case("true"))
         .unwrap_or(false);
+
+    /// How many blocks of history deployments with `prune: auto` keep
+    static ref PRUNE_AUTO_HISTORY_BLOCKS: BlockNumber =
+        std::env::var("GRAPH_PRUNE_AUTO_HISTORY_BLOCKS")
+            .ok()
+            .map(|s| BlockNumber::from_str(&s).unwrap_or_else(|_| {
+                panic!("failed to parse env var GRAPH_PRUNE_AUTO_HISTORY_BLOCKS")
+            }))
+            .unwrap_or(10_000);
+
+    /// Deployments have to keep enough history to revert as many blocks as
+    /// the block ingestor considers reorgs for
+    static ref MIN_HISTORY_BLOCKS: BlockNumber = std::env::var("ETHEREUM_REORG_THRESHOLD")
+        .ok()
+        .map(|s| BlockNumber::from_str(&s)
+            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
+        .unwrap_or(50);
 }
 
 /// Rust representation of the GraphQL schema for a `SubgraphManifest`.
@@ -66,6 +90,19 @@ where
         .map(Some)
 }
 
+fn deserialize_addresses<'de, D>(deserializer: D) -> Result<Vec<Address>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let addresses: Vec<String> = de::Deserialize::deserialize(deserializer)?;
+    addresses
+        .iter()
+        .map(|s| Address::from_str(s.trim_start_matches("0x")).map_err(D::Error::custom))
+        .collect()
+}
+
 // Note: This has a StableHash impl. Do not modify fields without a backward
 // compatible change to the StableHash impl (below)
 #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -282,6 +319,8 @@ pub enum SubgraphRegistrarError {
     DeploymentNotFound(String),
     #[fail(display = "deployment assignment unchanged: {}", _0)]
     DeploymentAssignmentUnchanged(String),
+    #[fail(display = "deployment placement error: {}", _0)]
+    PlacementError(String),
     #[fail(display = "subgraph registrar internal query error: {}", _0)]
     QueryExecutionError(QueryExecutionError),
     #[fail(display = "subgraph registrar error with store: {}", _0)]
@@ -290,6 +329,18 @@ pub enum SubgraphRegistrarError {
     ManifestValidationError(Vec<SubgraphManifestValidationError>),
     #[fail(display = "subgraph deployment error: {}", _0)]
     SubgraphDeploymentError(StoreError),
+    #[fail(display = "failed to store subgraph files: {}", _0)]
+    LocalFilesError(String),
+    #[fail(display = "not applied: {}", _0)]
+    BatchNotApplied(String),
+    #[fail(display = "subgraph has no pending version: {}", _0)]
+    NoPendingVersion(String),
+    #[fail(display = "pending version has not synced yet: {}", _0)]
+    PendingVersionNotSynced(String),
+    #[fail(display = "invalid query: {}", _0)]
+    InvalidQuery(String),
+    #[fail(display = "invalid import: {}", _0)]
+    InvalidImport(String),
     #[fail(display = "subgraph registrar error: {}", _0)]
     Unknown(failure::Error),
 }
@@ -356,8 +407,13 @@ impl From<::diesel::result::Error> for SubgraphAssignmentProviderError {
 /// Events emitted by [SubgraphAssignmentProvider](trait.SubgraphAssignmentProvider.html) implementations.
 #[derive(Debug, PartialEq)]
 pub enum SubgraphAssignmentProviderEvent {
-    /// A subgraph with the given manifest should start processing.
-    SubgraphStart(SubgraphManifest),
+    /// A subgraph with the given manifest should start processing. The data
+    /// sources of the manifest end with the `dynamic_data_sources` data
+    /// sources that the subgraph created from templates so far.
+    SubgraphStart {
+        manifest: SubgraphManifest,
+        dynamic_data_sources: usize,
+    },
     /// The subgraph with the given ID should stop processing.
     SubgraphStop(SubgraphDeploymentId),
 }
@@ -388,6 +444,32 @@ pub enum SubgraphManifestValidationError {
     SchemaValidationError(Vec<SchemaValidationError>),
     #[fail(display = "the graft base is invalid: {}", _0)]
     GraftBaseInvalid(String),
+    #[fail(display = "the migration base is invalid: {}", _0)]
+    MigrationBaseInvalid(String),
+    #[fail(
+        display = "the subgraph uses features that are not declared in the manifest: {}",
+        _0
+    )]
+    FeatureValidationError(String),
+    #[fail(display = "{}: {}: {}", file, path, message)]
+    InvalidDefinition {
+        /// The file that contains the problem
+        file: String,
+        /// Where in the file the problem is, e.g.
+        /// `dataSources[0].mapping.eventHandlers[1].handler`
+        path: String,
+        message: String,
+    },
+}
+
+impl SubgraphManifestValidationError {
+    fn invalid_definition(file: &str, path: String, message: String) -> Self {
+        SubgraphManifestValidationError::InvalidDefinition {
+            file: file.to_owned(),
+            path,
+            message,
+        }
+    }
 }
 
 #[derive(Fail, Debug)]
@@ -447,21 +529,84 @@ impl UnresolvedSchema {
 pub struct Source {
     #[serde(default, deserialize_with = "deserialize_address")]
     pub address: Option<Address>,
+    /// The contracts of a data source that handles the same events for a
+    /// list of contracts instead of the contract at `address`. The data
+    /// source is indexed as one data source for each of them
+    #[serde(default, deserialize_with = "deserialize_addresses")]
+    pub addresses: Vec<Address>,
+    /// A file with more `addresses`, one per line; they are added to
+    /// `addresses` when the data source is resolved
+    #[serde(rename = "addressFile", default)]
+    pub address_file: Option<Link>,
+    /// The ABI of the contract. It is required for `ethereum/contract` data
+    /// sources, which validation checks, and not used by `subgraph` data
+    /// sources
+    #[serde(default)]
     pub abi: String,
+    /// The deployment whose entity changes trigger the handlers of a
+    /// `subgraph` data source
+    #[serde(default)]
+    pub subgraph: Option<SubgraphDeploymentId>,
     #[serde(rename = "startBlock", default)]
     pub start_block: u64,
+    /// If the contract is a proxy, the ABIs of the implementations it was
+    /// upgraded to, ordered by the block of the upgrade. Before the first
+    /// upgrade, `abi` describes the contract
+    #[serde(default)]
+    pub implementations: Vec<SourceImplementation>,
+    /// The id of the Arweave transaction whose data a `file/arweave` data
+    /// source handles. It is set when the data source is created from its
+    /// template
+    #[serde(skip)]
+    pub file: Option<String>,
+}
+
+impl Source {
+    /// The ABI that describes the contract at `block`
+    pub fn abi_at(&self, block: u64) -> &str {
+        self.implementations
+            .iter()
+            .rev()
+            .find(|implementation| implementation.start_block <= block)
+            .map_or(self.abi.as_str(), |implementation| {
+                implementation.abi.as_str()
+            })
+    }
+
+    /// All ABIs that describe the contract at some block
+    pub fn abis(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.abi.as_str()).chain(
+            self.implementations
+                .iter()
+                .map(|implementation| implementation.abi.as_str()),
+        )
+    }
 }
 
 impl From<EthereumContractSourceEntity> for Source {
     fn from(entity: EthereumContractSourceEntity) -> Self {
         Self {
             address: entity.address,
+            addresses: vec![],
+            address_file: None,
             abi: entity.abi,
+            subgraph: None,
             start_block: entity.start_block,
+            implementations: vec![],
+            file: entity.file,
         }
     }
 }
 
+/// An implementation that a proxy contract delegates to from `start_block`
+/// on, described by the ABI `abi`
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct SourceImplementation {
+    pub abi: String,
+    #[serde(rename = "startBlock")]
+    pub start_block: u64,
+}
+
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Deserialize)]
 pub struct TemplateSource {
     pub abi: String,
@@ -477,6 +622,9 @@ impl From<EthereumContractDataSourceTemplateSourceEntity> for TemplateSource {
 pub struct UnresolvedMappingABI {
     pub name: String,
     pub file: Link,
+    /// Functions like `decimals()` that return the same for every block
+    #[serde(default, rename = "immutableCalls")]
+    pub immutable_calls: Vec<String>,
 }
 
 impl From<EthereumContractAbiEntity> for UnresolvedMappingABI {
@@ -484,6 +632,7 @@ impl From<EthereumContractAbiEntity> for UnresolvedMappingABI {
         Self {
             name: entity.name,
             file: entity.file.into(),
+            immutable_calls: vec![],
         }
     }
 }
@@ -492,9 +641,32 @@ impl From<EthereumContractAbiEntity> for UnresolvedMappingABI {
 pub struct MappingABI {
     pub name: String,
     pub contract: Contract,
+    /// The custom errors that the functions of the contract can revert with
+    pub errors: Vec<ContractError>,
+    /// The `name(uint256,address)` signatures of the functions whose calls
+    /// return the same for every block, so that their results are cached
+    /// once for all blocks
+    pub immutable_calls: Vec<String>,
     pub link: Link,
 }
 
+impl MappingABI {
+    /// Whether calls of `function` return the same for every block
+    pub fn is_immutable_call(&self, function: &ethabi::Function) -> bool {
+        let signature = function_signature(function);
+        self.immutable_calls.iter().any(|call| *call == signature)
+    }
+}
+
+/// A custom error from an ABI entry of type `error`, like
+/// `error Unauthorized(address caller)` in Solidity
+#[derive(Clone, Debug, Deserialize)]
+pub struct ContractError {
+    pub name: String,
+    #[serde(default)]
+    pub inputs: Vec<ethabi::Param>,
+}
+
 impl UnresolvedMappingABI {
     pub async fn resolve(
         self,
@@ -509,10 +681,26 @@ impl UnresolvedMappingABI {
         );
 
         let contract_bytes = resolver.cat(&logger, &self.file).await?;
-        let contract = Contract::load(&*contract_bytes).map_err(SyncFailure::new)?;
+
+        // `Contract` does not know about custom errors, so they are taken
+        // out of the ABI and parsed on their own
+        let (errors, entries): (Vec<_>, Vec<_>) =
+            serde_json::from_slice::<Vec<serde_json::Value>>(&contract_bytes)?
+                .into_iter()
+                .partition(|entry| {
+                    entry.get("type").and_then(|kind| kind.as_str()) == Some("error")
+                });
+        let errors = errors
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<Vec<ContractError>, _>>()?;
+        let contract = Contract::load(&*serde_json::to_vec(&entries)?).map_err(SyncFailure::new)?;
+
         Ok(MappingABI {
             name: self.name,
             contract,
+            errors,
+            immutable_calls: self.immutable_calls,
             link: self.file,
         })
     }
@@ -545,6 +733,9 @@ impl From<EthereumBlockHandlerEntity> for MappingBlockHandler {
 pub struct MappingCallHandler {
     pub function: String,
     pub handler: String,
+    /// Whether the handler is also called for calls that failed
+    #[serde(default, rename = "includeFailed")]
+    pub include_failed: bool,
 }
 
 impl From<EthereumCallHandlerEntity> for MappingCallHandler {
@@ -552,6 +743,7 @@ impl From<EthereumCallHandlerEntity> for MappingCallHandler {
         Self {
             function: entity.function,
             handler: entity.handler,
+            include_failed: entity.include_failed,
         }
     }
 }
@@ -563,7 +755,15 @@ pub struct MappingEventHandler {
     pub handler: String,
 }
 
+/// The `event` of the event handler that handles all events of the ABIs of
+/// its data source that no other handler handles
+pub const WILDCARD_EVENT: &str = "*";
+
 impl MappingEventHandler {
+    pub fn is_wildcard(&self) -> bool {
+        self.event == WILDCARD_EVENT
+    }
+
     pub fn topic0(&self) -> H256 {
         self.topic0
             .unwrap_or_else(|| string_to_h256(&self.event.replace("indexed ", "")))
@@ -580,6 +780,15 @@ impl From<EthereumContractEventHandlerEntity> for MappingEventHandler {
     }
 }
 
+/// A handler that is called with the new version of every entity of type
+/// `entity` that the source deployment of a `subgraph` data source creates
+/// or updates.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct MappingEntityHandler {
+    pub entity: String,
+    pub handler: String,
+}
+
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UnresolvedMapping {
@@ -587,6 +796,7 @@ pub struct UnresolvedMapping {
     pub api_version: String,
     pub language: String,
     pub entities: Vec<String>,
+    #[serde(default)]
     pub abis: Vec<UnresolvedMappingABI>,
     #[serde(default)]
     pub block_handlers: Vec<MappingBlockHandler>,
@@ -594,6 +804,11 @@ pub struct UnresolvedMapping {
     pub call_handlers: Vec<MappingCallHandler>,
     #[serde(default)]
     pub event_handlers: Vec<MappingEventHandler>,
+    #[serde(default)]
+    pub entity_handlers: Vec<MappingEntityHandler>,
+    /// The handler of a `file/arweave` data source
+    #[serde(default)]
+    pub handler: Option<String>,
     pub file: Link,
 }
 
@@ -607,6 +822,10 @@ pub struct Mapping {
     pub block_handlers: Vec<MappingBlockHandler>,
     pub call_handlers: Vec<MappingCallHandler>,
     pub event_handlers: Vec<MappingEventHandler>,
+    pub entity_handlers: Vec<MappingEntityHandler>,
+    /// The handler that a `file/arweave` data source passes the content of
+    /// its file to
+    pub handler: Option<String>,
     pub runtime: Arc<Vec<u8>>,
     pub link: Link,
 }
@@ -634,10 +853,262 @@ impl Mapping {
         return false;
     }
 
+    /// The names of all functions that the mapping module exports.
+    pub fn exported_functions(&self) -> Result<BTreeSet<String>, wasmparser::BinaryReaderError> {
+        use wasmparser::{ExternalKind, Payload};
+
+        let runtime = self.runtime.as_ref().as_ref();
+
+        let mut exports = BTreeSet::new();
+        for payload in wasmparser::Parser::new(0).parse_all(runtime) {
+            if let Payload::ExportSection(s) = payload? {
+                for export in s {
+                    let export = export?;
+                    if let ExternalKind::Function = export.kind {
+                        exports.insert(export.field.to_owned());
+                    }
+                }
+            }
+        }
+        Ok(exports)
+    }
+
+    /// Check that the entities, ABIs and handlers that the mapping refers to
+    /// exist. `path` is the location of the mapping in the manifest `file`.
+    /// Handlers have to be defined by at least one of the `source_abis`.
+    fn validate(
+        &self,
+        file: &str,
+        path: &str,
+        source_abis: &[&str],
+        schema: &Schema,
+    ) -> Vec<SubgraphManifestValidationError> {
+        let invalid = |path: String, message: String| {
+            SubgraphManifestValidationError::invalid_definition(file, path, message)
+        };
+        let mut errors = vec![];
+
+        for (i, entity) in self.entities.iter().enumerate() {
+            if !schema.defines_type(entity) {
+                errors.push(invalid(
+                    format!("{}.entities[{}]", path, i),
+                    format!("entity `{}` is not defined in the schema", entity),
+                ));
+            }
+        }
+
+        let mut abis = vec![];
+        for source_abi in source_abis {
+            match self.abis.iter().find(|abi| abi.name == *source_abi) {
+                Some(abi) => abis.push(abi),
+                None => errors.push(invalid(
+                    format!("{}.abis", path),
+                    format!(
+                        "the source ABI `{}` is not listed in the mapping ABIs",
+                        source_abi
+                    ),
+                )),
+            }
+        }
+        for (i, abi) in self.abis.iter().enumerate() {
+            for (j, call) in abi.immutable_calls.iter().enumerate() {
+                if !abi
+                    .contract
+                    .functions()
+                    .any(|function| function_signature(function) == *call)
+                {
+                    errors.push(invalid(
+                        format!("{}.abis[{}].immutableCalls[{}]", path, i, j),
+                        format!("function `{}` is not defined in ABI `{}`", call, abi.name),
+                    ));
+                }
+            }
+        }
+
+        let abi_names = abis
+            .iter()
+            .map(|abi| format!("`{}`", abi.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let in_abis = if abis.len() > 1 {
+            "in any of the ABIs"
+        } else {
+            "in ABI"
+        };
+
+        let exports = match self.exported_functions() {
+            Ok(exports) => Some(exports),
+            Err(e) => {
+                errors.push(invalid(
+                    format!("{}.file", path),
+                    format!(
+                        "mapping `{}` is not a valid WASM module: {}",
+                        self.link.link, e
+                    ),
+                ));
+                None
+            }
+        };
+        let unexported = |handler_path: String, handler: &str| match &exports {
+            Some(exports) if !exports.contains(handler) => Some(invalid(
+                handler_path,
+                format!(
+                    "handler `{}` is not exported by mapping `{}`",
+                    handler, self.link.link
+                ),
+            )),
+            _ => None,
+        };
+
+        // Wildcard event handlers get the name of the event, which only the
+        // event class of `apiVersion` 0.0.5 and later has
+        let supports_wildcard = semver::Version::parse(&self.api_version)
+            .map_or(true, |version| version >= semver::Version::new(0, 0, 5));
+        let mut has_wildcard = false;
+        for (i, handler) in self.event_handlers.iter().enumerate() {
+            let handler_path = format!("{}.eventHandlers[{}]", path, i);
+            if handler.is_wildcard() {
+                if !supports_wildcard {
+                    errors.push(invalid(
+                        format!("{}.event", handler_path),
+                        format!(
+                            "wildcard event handlers require apiVersion 0.0.5 or later, \
+                             but the mapping uses {}",
+                            self.api_version
+                        ),
+                    ));
+                }
+                if has_wildcard {
+                    errors.push(invalid(
+                        format!("{}.event", handler_path),
+                        "a mapping can only have one wildcard event handler".to_owned(),
+                    ));
+                }
+                has_wildcard = true;
+            } else if !abis.is_empty()
+                && abis.iter().all(|abi| {
+                    contract_event_with_signature(&abi.contract, &handler.event).is_none()
+                })
+            {
+                errors.push(invalid(
+                    format!("{}.event", handler_path),
+                    format!(
+                        "event `{}` is not defined {} {}",
+                        handler.event, in_abis, abi_names
+                    ),
+                ));
+            }
+            errors.extend(unexported(
+                format!("{}.handler", handler_path),
+                &handler.handler,
+            ));
+        }
+
+        for (i, handler) in self.call_handlers.iter().enumerate() {
+            let handler_path = format!("{}.callHandlers[{}]", path, i);
+            if !abis.is_empty()
+                && abis.iter().all(|abi| {
+                    contract_function_with_signature(&abi.contract, &handler.function).is_none()
+                })
+            {
+                errors.push(invalid(
+                    format!("{}.function", handler_path),
+                    format!(
+                        "function `{}` is not defined {} {}",
+                        handler.function, in_abis, abi_names
+                    ),
+                ));
+            }
+            // Failed calls are passed to handlers with their revert reason,
+            // which only the newer `ethereum.Call` class has
+            let supports_failed = semver::Version::parse(&self.api_version)
+                .map_or(true, |version| version >= semver::Version::new(0, 0, 5));
+            if handler.include_failed && !supports_failed {
+                errors.push(invalid(
+                    format!("{}.includeFailed", handler_path),
+                    format!(
+                        "failed calls can only be handled with apiVersion 0.0.5 or later, \
+                         but the mapping uses {}",
+                        self.api_version
+                    ),
+                ));
+            }
+            errors.extend(unexported(
+                format!("{}.handler", handler_path),
+                &handler.handler,
+            ));
+        }
+
+        for (i, handler) in self.block_handlers.iter().enumerate() {
+            errors.extend(unexported(
+                format!("{}.blockHandlers[{}].handler", path, i),
+                &handler.handler,
+            ));
+        }
+
+        for (i, handler) in self.entity_handlers.iter().enumerate() {
+            errors.extend(unexported(
+                format!("{}.entityHandlers[{}].handler", path, i),
+                &handler.handler,
+            ));
+        }
+
+        if let Some(handler) = &self.handler {
+            errors.extend(unexported(format!("{}.handler", path), handler));
+        }
+
+        errors
+    }
+
     pub fn has_call_handler(&self) -> bool {
         !self.call_handlers.is_empty()
     }
 
+    /// The event handlers for events that one of the ABIs of `source`
+    /// declares as `anonymous` and that do not set a `topic0`. The logs of
+    /// these events do not start with the event signature, so they can only
+    /// be matched by the address of the contract and decoded by the
+    /// position of their parameters
+    pub fn anonymous_event_handlers(&self, source: &Source) -> Vec<&MappingEventHandler> {
+        let abis = source
+            .abis()
+            .filter_map(|source_abi| self.abis.iter().find(|abi| abi.name == source_abi))
+            .collect::<Vec<_>>();
+        self.event_handlers
+            .iter()
+            .filter(|handler| {
+                handler.topic0.is_none()
+                    && abis.iter().any(|abi| {
+                        contract_event_with_signature(&abi.contract, &handler.event)
+                            .map_or(false, |event| event.anonymous)
+                    })
+            })
+            .collect()
+    }
+
+    /// The handler for all events that no other handler handles, if the
+    /// mapping has one
+    pub fn wildcard_event_handler(&self) -> Option<&MappingEventHandler> {
+        self.event_handlers
+            .iter()
+            .find(|handler| handler.is_wildcard())
+    }
+
+    /// The events of the ABIs of `source` that a wildcard event handler
+    /// handles. Anonymous events are left out since their logs do not say
+    /// which event they are
+    pub fn wildcard_events(&self, source: &Source) -> Vec<&ethabi::Event> {
+        if self.wildcard_event_handler().is_none() {
+            return vec![];
+        }
+        source
+            .abis()
+            .filter_map(|source_abi| self.abis.iter().find(|abi| abi.name == source_abi))
+            .flat_map(|abi| abi.contract.events())
+            .filter(|event| !event.anonymous)
+            .collect()
+    }
+
     pub fn has_block_handler_with_call_filter(&self) -> bool {
         self.block_handlers
             .iter()
@@ -663,6 +1134,8 @@ impl UnresolvedMapping {
             block_handlers,
             call_handlers,
             event_handlers,
+            entity_handlers,
+            handler,
             file: link,
         } = self;
 
@@ -690,6 +1163,8 @@ impl UnresolvedMapping {
             block_handlers: block_handlers.clone(),
             call_handlers: call_handlers.clone(),
             event_handlers: event_handlers.clone(),
+            entity_handlers,
+            handler,
             runtime,
             link,
         })
@@ -707,6 +1182,8 @@ impl From<EthereumContractMappingEntity> for UnresolvedMapping {
             event_handlers: entity.event_handlers.into_iter().map(Into::into).collect(),
             call_handlers: entity.call_handlers.into_iter().map(Into::into).collect(),
             block_handlers: entity.block_handlers.into_iter().map(Into::into).collect(),
+            entity_handlers: vec![],
+            handler: entity.handler,
             file: entity.file.into(),
         }
     }
@@ -728,6 +1205,65 @@ pub struct BaseDataSource<M, T> {
 pub type UnresolvedDataSource = BaseDataSource<UnresolvedMapping, UnresolvedDataSourceTemplate>;
 pub type DataSource = BaseDataSource<Mapping, DataSourceTemplate>;
 
+impl DataSource {
+    /// The deployment whose entity changes this data source handles, if
+    /// this is a `subgraph` data source.
+    pub fn source_subgraph(&self) -> Option<&SubgraphDeploymentId> {
+        match self.kind.as_str() {
+            "subgraph" => self.source.subgraph.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The id of the Arweave transaction whose data this data source
+    /// handles, if this is a `file/arweave` data source.
+    pub fn arweave_file(&self) -> Option<&str> {
+        match self.kind.as_str() {
+            "file/arweave" => self.source.file.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Split a data source with a list of `addresses` into one data source
+    /// for each of them. Other data sources are left alone
+    pub fn split_addresses(self) -> Vec<DataSource> {
+        if self.source.addresses.is_empty() {
+            return vec![self];
+        }
+        self.source
+            .addresses
+            .iter()
+            .map(|address| {
+                let mut data_source = self.clone();
+                data_source.source.address = Some(*address);
+                data_source.source.addresses = vec![];
+                data_source.source.address_file = None;
+                data_source
+            })
+            .collect()
+    }
+}
+
+/// Parse the addresses in an address file, one per line. Blank lines are
+/// skipped
+fn parse_address_file(link: &Link, bytes: Vec<u8>) -> Result<Vec<Address>, failure::Error> {
+    let text = String::from_utf8(bytes)?;
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            Address::from_str(line.trim_start_matches("0x")).map_err(|e| {
+                format_err!(
+                    "invalid address `{}` in address file `{}`: {}",
+                    line,
+                    link.link,
+                    e
+                )
+            })
+        })
+        .collect()
+}
+
 impl UnresolvedDataSource {
     pub async fn resolve(
         self,
@@ -738,7 +1274,7 @@ impl UnresolvedDataSource {
             kind,
             network,
             name,
-            source,
+            mut source,
             mapping,
             templates,
             context,
@@ -746,6 +1282,12 @@ impl UnresolvedDataSource {
 
         info!(logger, "Resolve data source"; "name" => &name, "source" => &source.start_block);
 
+        if let Some(link) = &source.address_file {
+            let bytes = resolver.cat(logger, link).await?;
+            let addresses = parse_address_file(link, bytes)?;
+            source.addresses.extend(addresses);
+        }
+
         let (mapping, templates) = try_join(
             mapping.resolve(&*resolver, logger),
             templates
@@ -779,6 +1321,46 @@ impl TryFrom<DataSourceTemplateInfo> for DataSource {
             context,
         } = info;
 
+        // The data sources of `file/arweave` templates handle the data of
+        // the Arweave transaction in the parameters
+        if template.kind == "file/arweave" {
+            let tx_id = params.get(0).with_context(|| {
+                format!(
+                    "Failed to create data source from template `{}`: \
+                     transaction id parameter is missing",
+                    template.name
+                )
+            })?;
+            if !is_valid_tx_id(tx_id) {
+                anyhow::bail!(
+                    "Failed to create data source from template `{}`, \
+                     invalid Arweave transaction id `{}` provided",
+                    template.name,
+                    tx_id
+                );
+            }
+
+            return Ok(DataSource {
+                kind: template.kind,
+                network: template.network,
+                name: template.name,
+                source: Source {
+                    address: None,
+                    addresses: vec![],
+                    address_file: None,
+                    abi: String::new(),
+                    subgraph: None,
+                    start_block: 0,
+                    implementations: vec![],
+                    file: Some(tx_id.clone()),
+                },
+                mapping: template.mapping,
+                context,
+
+                templates: Vec::new(),
+            });
+        }
+
         // Obtain the address from the parameters
         let string = params
             .get(0)
@@ -803,8 +1385,13 @@ impl TryFrom<DataSourceTemplateInfo> for DataSource {
             name: template.name,
             source: Source {
                 address: Some(address),
+                addresses: vec![],
+                address_file: None,
                 abi: template.source.abi,
+                subgraph: None,
                 start_block: 0,
+                implementations: vec![],
+                file: None,
             },
             mapping: template.mapping,
             context,
@@ -846,6 +1433,8 @@ pub struct BaseDataSourceTemplate<M> {
     pub kind: String,
     pub network: Option<String>,
     pub name: String,
+    /// `file/arweave` templates have no source
+    #[serde(default)]
     pub source: TemplateSource,
     pub mapping: M,
 }
@@ -915,18 +1504,300 @@ impl Graft {
             )),
             Ok(Some(ptr)) => {
                 if ptr.number < self.block as u64 {
-                    gbi(format!(
+                    return gbi(format!(
                         "failed to graft onto `{}` at block {} since it has only processed block {}",
                         self.base, self.block, ptr.number
-                    ))
-                } else {
-                    vec![]
+                    ));
+                }
+                match store.earliest_block(&self.base) {
+                    Err(e) => gbi(e.to_string()),
+                    Ok(earliest) if earliest > self.block => gbi(format!(
+                        "failed to graft onto `{}` at block {} since it has pruned its \
+                         history before block {}",
+                        self.base, self.block, earliest
+                    )),
+                    Ok(_) => vec![],
                 }
             }
         }
     }
 }
 
+/// A deployment whose storage a new version of the same subgraph takes
+/// over, changing its tables in place instead of indexing from scratch.
+/// That is only possible if the schema of the new version just adds to the
+/// schema of `base`, which the store checks when it creates the deployment
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Migration {
+    pub base: SubgraphDeploymentId,
+}
+
+impl Migration {
+    fn validate<S: Store + SubgraphDeploymentStore>(
+        &self,
+        store: Arc<S>,
+    ) -> Vec<SubgraphManifestValidationError> {
+        match store.block_ptr(self.base.clone()) {
+            Err(e) => vec![SubgraphManifestValidationError::MigrationBaseInvalid(
+                e.to_string(),
+            )],
+            Ok(None) => vec![SubgraphManifestValidationError::MigrationBaseInvalid(
+                format!(
+                    "failed to migrate `{}` since it has not processed any blocks",
+                    self.base
+                ),
+            )],
+            Ok(Some(_)) => vec![],
+        }
+    }
+}
+
+/// Hints from the subgraph developer about how to store the subgraph's
+/// data, declared under `indexerHints` in the manifest and applied when
+/// the deployment is created
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexerHints {
+    /// How much history to keep; all of it if this is not set
+    pub prune: Option<Prune>,
+    /// Indexes to create in addition to the ones on each attribute
+    #[serde(default)]
+    pub indexes: Vec<IndexHint>,
+    /// Entity types with few entities that change very often, like account
+    /// balances, whose tables are stored and queried so that looking up an
+    /// entity does not have to go through all its old versions
+    #[serde(default)]
+    pub account_like: Vec<String>,
+}
+
+impl IndexerHints {
+    /// How many blocks of history the deployment keeps, or `None` if it
+    /// keeps all of it
+    pub fn history_blocks(&self) -> Option<BlockNumber> {
+        self.prune.map(|prune| match prune {
+            Prune::Auto => *PRUNE_AUTO_HISTORY_BLOCKS,
+            Prune::Blocks(blocks) => blocks,
+        })
+    }
+
+    fn validate(&self, file: &str, schema: &Schema) -> Vec<SubgraphManifestValidationError> {
+        let invalid = |path: String, message: String| {
+            SubgraphManifestValidationError::invalid_definition(file, path, message)
+        };
+        let mut errors = vec![];
+
+        if let Some(blocks) = self.history_blocks() {
+            if blocks < *MIN_HISTORY_BLOCKS {
+                errors.push(invalid(
+                    "indexerHints.prune".to_owned(),
+                    format!(
+                        "deployments have to keep at least {} blocks of history, \
+                         but only {} are kept",
+                        *MIN_HISTORY_BLOCKS, blocks
+                    ),
+                ));
+            }
+        }
+
+        for (i, index) in self.indexes.iter().enumerate() {
+            let path = format!("indexerHints.indexes[{}]", i);
+            let object_type = match schema.document.get_object_type_definition(&index.entity) {
+                Some(object_type) => object_type,
+                None => {
+                    errors.push(invalid(
+                        format!("{}.entity", path),
+                        format!("entity `{}` is not defined in the schema", index.entity),
+                    ));
+                    continue;
+                }
+            };
+            if index.fields.is_empty() {
+                errors.push(invalid(
+                    format!("{}.fields", path),
+                    "an index needs at least one field".to_owned(),
+                ));
+            }
+            let fields = index
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(j, field)| (format!("{}.fields[{}]", path, j), field))
+                .chain(
+                    index
+                        .filter
+                        .keys()
+                        .map(|field| (format!("{}.where.{}", path, field), field)),
+                );
+            for (path, field) in fields {
+                match object_type.field(field) {
+                    None => errors.push(invalid(
+                        path,
+                        format!("entity `{}` has no field `{}`", index.entity, field),
+                    )),
+                    Some(field) if field.find_directive("derivedFrom".to_owned()).is_some() => {
+                        errors.push(invalid(
+                            path,
+                            format!(
+                                "the derived field `{}` is not stored and can not be indexed",
+                                field.name
+                            ),
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+            if index.method == IndexMethod::Trigram {
+                for (j, field) in index.fields.iter().enumerate() {
+                    let is_string = object_type.field(field).map_or(true, |field| {
+                        field.field_type.get_base_type() == "String"
+                            && !is_list_type(&field.field_type)
+                    });
+                    if !is_string {
+                        errors.push(invalid(
+                            format!("{}.fields[{}]", path, j),
+                            format!(
+                                "trigram indexes only work for String fields, \
+                                 but `{}` is not one",
+                                field
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (i, entity) in self.account_like.iter().enumerate() {
+            if schema.document.get_object_type_definition(entity).is_none() {
+                errors.push(invalid(
+                    format!("indexerHints.accountLike[{}]", i),
+                    format!("entity `{}` is not defined in the schema", entity),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+fn is_list_type(field_type: &s::Type) -> bool {
+    match field_type {
+        s::Type::ListType(_) => true,
+        s::Type::NonNullType(inner) => is_list_type(inner),
+        s::Type::NamedType(_) => false,
+    }
+}
+
+/// How much history a deployment keeps: either the amount that the node
+/// considers sensible, or a number of blocks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prune {
+    Auto,
+    Blocks(BlockNumber),
+}
+
+impl<'de> de::Deserialize<'de> for Prune {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Blocks(BlockNumber),
+            Keyword(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Blocks(blocks) => Ok(Prune::Blocks(blocks)),
+            Repr::Keyword(keyword) if keyword == "auto" => Ok(Prune::Auto),
+            Repr::Keyword(keyword) => Err(D::Error::custom(format!(
+                "invalid prune policy `{}`, expected `auto` or a number of blocks",
+                keyword
+            ))),
+        }
+    }
+}
+
+/// An additional index on one or more fields of an entity type. With
+/// `current`, only the current versions of entities are indexed, and with
+/// `where`, only entities whose fields have the given values
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexHint {
+    pub entity: String,
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub current: bool,
+    #[serde(default, rename = "where")]
+    pub filter: BTreeMap<String, HintValue>,
+    #[serde(default)]
+    pub method: IndexMethod,
+}
+
+/// How an `IndexHint` indexes its fields: `btree` indexes speed up
+/// comparisons and sorting, and `trigram` indexes speed up the
+/// `_contains`, `_nocase` and `_matches` filters on string fields
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexMethod {
+    Btree,
+    Trigram,
+}
+
+impl Default for IndexMethod {
+    fn default() -> Self {
+        IndexMethod::Btree
+    }
+}
+
+/// The value of a field in the `where` clause of an `IndexHint`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum HintValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+impl fmt::Display for HintValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HintValue::Bool(b) => write!(f, "{}", b),
+            HintValue::Int(i) => write!(f, "{}", i),
+            HintValue::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Optional behavior that a subgraph has to opt into by listing it under
+/// `features` in its manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SubgraphFeature {
+    /// A handler that fails deterministically is skipped and its error is
+    /// recorded on the deployment instead of halting the subgraph.
+    NonFatalErrors,
+    /// The schema defines `@fulltext` search fields.
+    FullTextSearch,
+    /// The subgraph starts from the data of another deployment.
+    Grafting,
+    /// The subgraph takes over the storage of another deployment.
+    SchemaMigration,
+}
+
+impl fmt::Display for SubgraphFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubgraphFeature::NonFatalErrors => write!(f, "nonFatalErrors"),
+            SubgraphFeature::FullTextSearch => write!(f, "fullTextSearch"),
+            SubgraphFeature::Grafting => write!(f, "grafting"),
+            SubgraphFeature::SchemaMigration => write!(f, "schemaMigration"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BaseSubgraphManifest<S, D, T> {
@@ -938,8 +1809,13 @@ pub struct BaseSubgraphManifest<S, D, T> {
     pub schema: S,
     pub data_sources: Vec<D>,
     pub graft: Option<Graft>,
+    pub migrate: Option<Migration>,
     #[serde(default)]
     pub templates: Vec<T>,
+    #[serde(default)]
+    pub features: BTreeSet<SubgraphFeature>,
+    #[serde(default)]
+    pub indexer_hints: IndexerHints,
 }
 
 /// Consider two subgraphs to be equal if they come from the same IPLD link.
@@ -994,13 +1870,24 @@ impl UnvalidatedSubgraphManifest {
         }
 
         // Validate that the manifest has a `source` address in each data source
-        // which has call or block handlers
+        // which has call or block handlers, handlers for anonymous events, or
+        // the implementations of a proxy
         if self.0.data_sources.iter().any(|data_source| {
-            let no_source_address = data_source.source.address.is_none();
+            let no_source_address =
+                data_source.source.address.is_none() && data_source.source.addresses.is_empty();
             let has_call_handlers = !data_source.mapping.call_handlers.is_empty();
             let has_block_handlers = !data_source.mapping.block_handlers.is_empty();
-
-            no_source_address && (has_call_handlers || has_block_handlers)
+            let has_anonymous_event_handlers = !data_source
+                .mapping
+                .anonymous_event_handlers(&data_source.source)
+                .is_empty();
+            let is_proxy = !data_source.source.implementations.is_empty();
+
+            no_source_address
+                && (has_call_handlers
+                    || has_block_handlers
+                    || has_anonymous_event_handlers
+                    || is_proxy)
         }) {
             errors.push(SubgraphManifestValidationError::SourceAddressRequired)
         };
@@ -1031,12 +1918,94 @@ impl UnvalidatedSubgraphManifest {
             errors.push(SubgraphManifestValidationError::DataSourceBlockHandlerLimitExceeded)
         }
 
+        // Validate that the entities, ABIs and handlers that data sources and
+        // templates refer to actually exist
+        for (i, data_source) in self.0.data_sources.iter().enumerate() {
+            let path = format!("dataSources[{}]", i);
+            let source_abis = match data_source.kind.as_str() {
+                "subgraph" => {
+                    errors.extend(validate_source_subgraph(
+                        &self.0.location,
+                        &path,
+                        data_source,
+                        store.as_ref(),
+                    ));
+                    vec![]
+                }
+                "ethereum/contract" => {
+                    if data_source.source.abi.is_empty() {
+                        errors.push(SubgraphManifestValidationError::invalid_definition(
+                            &self.0.location,
+                            format!("{}.source.abi", path),
+                            "an `ethereum/contract` data source must name the ABI of its contract"
+                                .to_owned(),
+                        ));
+                        continue;
+                    }
+                    if data_source.source.address.is_some()
+                        && !data_source.source.addresses.is_empty()
+                    {
+                        errors.push(SubgraphManifestValidationError::invalid_definition(
+                            &self.0.location,
+                            format!("{}.source.addresses", path),
+                            "a data source can have an `address` or `addresses`, but not both"
+                                .to_owned(),
+                        ));
+                    }
+                    errors.extend(validate_source_implementations(
+                        &self.0.location,
+                        &path,
+                        &data_source.source,
+                    ));
+                    data_source.source.abis().collect()
+                }
+                "file/arweave" => {
+                    errors.push(SubgraphManifestValidationError::invalid_definition(
+                        &self.0.location,
+                        format!("{}.kind", path),
+                        "`file/arweave` data sources can only be created from templates".to_owned(),
+                    ));
+                    continue;
+                }
+                kind => {
+                    errors.push(unsupported_kind(&self.0.location, &path, kind));
+                    continue;
+                }
+            };
+            errors.extend(data_source.mapping.validate(
+                &self.0.location,
+                &format!("{}.mapping", path),
+                &source_abis,
+                &self.0.schema,
+            ));
+        }
+        for (i, template) in self.0.templates.iter().enumerate() {
+            let path = format!("templates[{}]", i);
+            let source_abis = match template.kind.as_str() {
+                "ethereum/contract" => vec![template.source.abi.as_str()],
+                "file/arweave" => {
+                    errors.extend(validate_file_template(&self.0.location, &path, template));
+                    vec![]
+                }
+                kind => {
+                    errors.push(unsupported_kind(&self.0.location, &path, kind));
+                    continue;
+                }
+            };
+            errors.extend(template.mapping.validate(
+                &self.0.location,
+                &format!("{}.mapping", path),
+                &source_abis,
+                &self.0.schema,
+            ));
+        }
+
         let mut networks = self
             .0
             .data_sources
             .iter()
             .cloned()
-            .filter(|d| d.kind.eq("ethereum/contract"))
+            .filter(|d| d.kind.eq("ethereum/contract") || d.kind.eq("subgraph"))
             .filter_map(|d| d.network)
             .collect::<Vec<String>>();
         networks.sort();
@@ -1064,7 +2033,38 @@ impl UnvalidatedSubgraphManifest {
                     "Grafting of subgraphs is currently disabled".to_owned(),
                 ));
             }
-            errors.extend(graft.validate(store));
+            errors.extend(graft.validate(store.clone()));
+        }
+
+        if let Some(migration) = &self.0.migrate {
+            if self.0.graft.is_some() {
+                errors.push(SubgraphManifestValidationError::MigrationBaseInvalid(
+                    "a subgraph can not both be grafted and take over the storage of \
+                     another subgraph"
+                        .to_owned(),
+                ));
+            }
+            errors.extend(migration.validate(store));
+        }
+
+        errors.extend(
+            self.0
+                .indexer_hints
+                .validate(&self.0.location, &self.0.schema),
+        );
+
+        if self.0.requires_declared_features() {
+            let undeclared_features = self
+                .0
+                .used_features()
+                .difference(&self.0.features)
+                .map(|feature| format!("`{}`", feature))
+                .collect::<Vec<_>>();
+            if !undeclared_features.is_empty() {
+                errors.push(SubgraphManifestValidationError::FeatureValidationError(
+                    undeclared_features.join(", "),
+                ));
+            }
         }
 
         match errors.is_empty() {
@@ -1074,6 +2074,130 @@ impl UnvalidatedSubgraphManifest {
     }
 }
 
+/// The error for a data source or template of a kind that this node does
+/// not know how to index, so that it is not indexed as an Ethereum contract
+fn unsupported_kind(file: &str, path: &str, kind: &str) -> SubgraphManifestValidationError {
+    SubgraphManifestValidationError::invalid_definition(
+        file,
+        format!("{}.kind", path),
+        format!("data source kind `{}` is not supported by this node", kind),
+    )
+}
+
+/// Check that a `file/arweave` template names the handler for its file
+/// and has no handlers for Ethereum triggers, which it would never get
+fn validate_file_template(
+    file: &str,
+    path: &str,
+    template: &DataSourceTemplate,
+) -> Vec<SubgraphManifestValidationError> {
+    let invalid = |path: String, message: &str| {
+        SubgraphManifestValidationError::invalid_definition(file, path, message.to_owned())
+    };
+    let mapping = &template.mapping;
+    let mut errors = vec![];
+
+    if mapping.handler.is_none() {
+        errors.push(invalid(
+            format!("{}.mapping.handler", path),
+            "a `file/arweave` template must name the handler for its file",
+        ));
+    }
+    if !mapping.event_handlers.is_empty()
+        || !mapping.call_handlers.is_empty()
+        || !mapping.block_handlers.is_empty()
+        || !mapping.entity_handlers.is_empty()
+    {
+        errors.push(invalid(
+            format!("{}.mapping", path),
+            "a `file/arweave` template can only have a file handler",
+        ));
+    }
+    errors
+}
+
+/// Check that the source deployment of a `subgraph` data source exists and
+/// has the entity types that the data source has handlers for.
+fn validate_source_subgraph(
+    file: &str,
+    path: &str,
+    data_source: &DataSource,
+    store: &impl SubgraphDeploymentStore,
+) -> Vec<SubgraphManifestValidationError> {
+    let invalid = |path: String, message: String| {
+        SubgraphManifestValidationError::invalid_definition(file, path, message)
+    };
+
+    let source = match &data_source.source.subgraph {
+        Some(source) => source,
+        None => {
+            return vec![invalid(
+                format!("{}.source.subgraph", path),
+                "a `subgraph` data source must name its source deployment".to_owned(),
+            )]
+        }
+    };
+    let schema = match store.input_schema(source) {
+        Ok(schema) => schema,
+        Err(_) => {
+            return vec![invalid(
+                format!("{}.source.subgraph", path),
+                format!("the source deployment `{}` does not exist", source),
+            )]
+        }
+    };
+
+    data_source
+        .mapping
+        .entity_handlers
+        .iter()
+        .enumerate()
+        .filter(|(_, handler)| !schema.defines_type(&handler.entity))
+        .map(|(i, handler)| {
+            invalid(
+                format!("{}.mapping.entityHandlers[{}].entity", path, i),
+                format!(
+                    "entity `{}` is not defined in the schema of the source deployment `{}`",
+                    handler.entity, source
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Check that the implementations of a proxy contract do not start before
+/// the data source does, and that each starts after the one before it.
+fn validate_source_implementations(
+    file: &str,
+    path: &str,
+    source: &Source,
+) -> Vec<SubgraphManifestValidationError> {
+    let mut errors = vec![];
+    let mut previous: Option<&SourceImplementation> = None;
+    for (i, implementation) in source.implementations.iter().enumerate() {
+        let message = match previous {
+            Some(previous) if implementation.start_block <= previous.start_block => Some(format!(
+                "implementation `{}` has to start after implementation `{}`, which starts at block {}",
+                implementation.abi, previous.abi, previous.start_block
+            )),
+            None if implementation.start_block < source.start_block => Some(format!(
+                "implementation `{}` can not start before the data source, which starts at block {}",
+                implementation.abi, source.start_block
+            )),
+            _ => None,
+        };
+        if let Some(message) = message {
+            errors.push(SubgraphManifestValidationError::invalid_definition(
+                file,
+                format!("{}.source.implementations[{}].startBlock", path, i),
+                message,
+            ));
+        }
+        previous = Some(implementation);
+    }
+    errors
+}
+
 impl SubgraphManifest {
     /// Entry point for resolving a subgraph definition.
     /// Right now the only supported links are of the form:
@@ -1126,7 +2250,7 @@ impl SubgraphManifest {
         self.data_sources
             .iter()
             .cloned()
-            .filter(|d| &d.kind == "ethereum/contract")
+            .filter(|d| &d.kind == "ethereum/contract" || &d.kind == "subgraph")
             .filter_map(|d| d.network)
             .next()
             .expect("Validated manifest does not have a network defined on any datasource")
@@ -1158,22 +2282,66 @@ impl SubgraphManifest {
     }
 
     pub fn requires_archive(&self) -> bool {
-        self.mappings()
-            .iter()
-            .any(|mapping| mapping.calls_host_fn("ethereum.call"))
+        self.mappings().iter().any(|mapping| {
+            mapping.calls_host_fn("ethereum.call") || mapping.calls_host_fn("ethereum.callAtBlock")
+        })
     }
 
     pub fn required_ethereum_capabilities(&self) -> NodeCapabilities {
         let mappings = self.mappings();
         NodeCapabilities {
-            archive: mappings
-                .iter()
-                .any(|mapping| mapping.calls_host_fn("ethereum.call")),
+            archive: mappings.iter().any(|mapping| {
+                mapping.calls_host_fn("ethereum.call")
+                    || mapping.calls_host_fn("ethereum.callAtBlock")
+            }),
             traces: mappings.iter().any(|mapping| {
                 mapping.has_call_handler() || mapping.has_block_handler_with_call_filter()
             }),
         }
     }
+
+    /// The deployments whose entity changes `subgraph` data sources handle,
+    /// with the types of the entities that they have handlers for.
+    pub fn source_subgraphs(&self) -> BTreeMap<SubgraphDeploymentId, Vec<String>> {
+        let mut sources: BTreeMap<SubgraphDeploymentId, Vec<String>> = BTreeMap::new();
+        for data_source in &self.data_sources {
+            if let Some(source) = data_source.source_subgraph() {
+                let entity_types = sources.entry(source.clone()).or_default();
+                for handler in &data_source.mapping.entity_handlers {
+                    entity_types.push(handler.entity.clone());
+                }
+                entity_types.sort();
+                entity_types.dedup();
+            }
+        }
+        sources
+    }
+
+    /// Manifests with spec version 0.0.4 or later have to declare the
+    /// features they use. Subgraphs that were deployed before features
+    /// existed keep deploying without declaring them.
+    pub fn requires_declared_features(&self) -> bool {
+        semver::Version::parse(&self.spec_version)
+            .map(|version| version >= semver::Version::new(0, 0, 4))
+            .unwrap_or(false)
+    }
+
+    /// The features that the schema and manifest make use of and that
+    /// therefore have to be declared under `features`. `nonFatalErrors`
+    /// changes how failures are handled and is never implied by usage.
+    pub fn used_features(&self) -> BTreeSet<SubgraphFeature> {
+        let mut features = BTreeSet::new();
+        if !self.schema.document.get_fulltext_directives().is_empty() {
+            features.insert(SubgraphFeature::FullTextSearch);
+        }
+        if self.graft.is_some() {
+            features.insert(SubgraphFeature::Grafting);
+        }
+        if self.migrate.is_some() {
+            features.insert(SubgraphFeature::SchemaMigration);
+        }
+        features
+    }
 }
 
 impl UnresolvedSubgraphManifest {
@@ -1191,7 +2359,10 @@ impl UnresolvedSubgraphManifest {
             schema,
             data_sources,
             graft,
+            migrate,
             templates,
+            features,
+            indexer_hints,
         } = self;
 
         match semver::Version::parse(&spec_version) {
@@ -1200,10 +2371,10 @@ impl UnresolvedSubgraphManifest {
             // version. To avoid breaking those, we accept 0.0.3 though it
             // doesn't exist. In the future we should not use 0.0.3 as version
             // and skip to 0.0.4 to avoid ambiguity.
-            Ok(ref ver) if *ver <= semver::Version::new(0, 0, 3) => {}
+            Ok(ref ver) if *ver <= semver::Version::new(0, 0, 4) => {}
             _ => {
                 return Err(format_err!(
-                    "This Graph Node only supports manifest spec versions <= 0.0.2,
+                    "This Graph Node only supports manifest spec versions <= 0.0.4,
                     but subgraph `{}` uses `{}`",
                     id,
                     spec_version
@@ -1235,7 +2406,10 @@ impl UnresolvedSubgraphManifest {
             schema,
             data_sources,
             graft,
+            migrate,
             templates,
+            features,
+            indexer_hints,
         })
     }
 }
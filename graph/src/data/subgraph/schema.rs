@@ -295,6 +295,7 @@ pub struct SubgraphDeploymentEntity {
     graft_base: Option<SubgraphDeploymentId>,
     graft_block_hash: Option<H256>,
     graft_block_number: Option<u64>,
+    migration_base: Option<SubgraphDeploymentId>,
 }
 
 impl TypedEntity for SubgraphDeploymentEntity {
@@ -322,6 +323,7 @@ impl SubgraphDeploymentEntity {
             graft_base: None,
             graft_block_hash: None,
             graft_block_number: None,
+            migration_base: None,
         }
     }
 
@@ -336,6 +338,12 @@ impl SubgraphDeploymentEntity {
         self
     }
 
+    /// Take over the storage of `base` instead of creating new storage
+    pub fn migrate(mut self, base: Option<SubgraphDeploymentId>) -> Self {
+        self.migration_base = base;
+        self
+    }
+
     // Overwrite entity if it exists. Only in debug builds so it's not used outside tests.
     #[cfg(debug_assertions)]
     pub fn create_operations_replace(self, id: &SubgraphDeploymentId) -> Vec<MetadataOperation> {
@@ -373,6 +381,7 @@ impl SubgraphDeploymentEntity {
             graft_base,
             graft_block_hash,
             graft_block_number,
+            migration_base,
         } = self;
 
         // A fresh subgraph will not have any errors.
@@ -398,6 +407,7 @@ impl SubgraphDeploymentEntity {
             graftBase: graft_base.map(|sid| sid.to_string()),
             graftBlockHash: graft_block_hash,
             graftBlockNumber: graft_block_number,
+            migrationBase: migration_base.map(|sid| sid.to_string()),
         };
 
         ops.push(set_metadata_operation(
@@ -460,6 +470,34 @@ impl SubgraphDeploymentEntity {
         ]
     }
 
+    /// Record the errors of handlers that were skipped because they failed
+    /// deterministically. The errors are appended to the ids of the
+    /// deployment's existing `non_fatal_errors`, and the deployment is marked
+    /// as unhealthy.
+    pub fn non_fatal_errors_operations(
+        id: &SubgraphDeploymentId,
+        mut non_fatal_errors: Vec<String>,
+        errors: Vec<SubgraphError>,
+    ) -> Vec<MetadataOperation> {
+        let mut ops = vec![];
+        for error in errors {
+            let error_id = uuid::Uuid::new_v4().to_string();
+            ops.push(error.create_operation(error_id.clone()));
+            non_fatal_errors.push(error_id);
+        }
+
+        let entity = entity! {
+            health: SubgraphHealth::Unhealthy,
+            nonFatalErrors: non_fatal_errors,
+        };
+        ops.push(update_metadata_operation(
+            Self::TYPENAME,
+            id.as_str(),
+            entity,
+        ));
+        ops
+    }
+
     pub fn update_synced_operations(
         id: &SubgraphDeploymentId,
         synced: bool,
@@ -480,6 +518,9 @@ impl SubgraphDeploymentEntity {
 pub struct SubgraphDeploymentAssignmentEntity {
     node_id: NodeId,
     cost: u64,
+    paused: bool,
+    network_alias: Option<String>,
+    priority: DeploymentPriority,
 }
 
 impl TypedEntity for SubgraphDeploymentAssignmentEntity {
@@ -489,7 +530,56 @@ impl TypedEntity for SubgraphDeploymentAssignmentEntity {
 
 impl SubgraphDeploymentAssignmentEntity {
     pub fn new(node_id: NodeId) -> Self {
-        Self { node_id, cost: 1 }
+        Self {
+            node_id,
+            cost: 1,
+            paused: false,
+            network_alias: None,
+            priority: DeploymentPriority::default(),
+        }
+    }
+
+    /// Keep the deployment assigned to its node, but do not index it
+    pub fn paused(self, paused: bool) -> Self {
+        Self { paused, ..self }
+    }
+
+    /// Whether the assignment `entity` is for a paused deployment
+    pub fn is_paused(entity: &Entity) -> bool {
+        entity.get("paused") == Some(&Value::Bool(true))
+    }
+
+    /// Index the deployment with the providers of the network alias
+    /// `network_alias` rather than those of the network in its manifest
+    pub fn network_alias(self, network_alias: Option<String>) -> Self {
+        Self {
+            network_alias,
+            ..self
+        }
+    }
+
+    /// The network alias of the assignment `entity`, if it has one
+    pub fn alias_of(entity: &Entity) -> Option<String> {
+        entity
+            .get("networkAlias")
+            .cloned()
+            .and_then(Value::as_string)
+    }
+
+    /// How the deployment competes with the other deployments of its node
+    pub fn priority(self, priority: DeploymentPriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// The priority of the assignment `entity`; assignments from before
+    /// there were priorities have normal priority
+    pub fn priority_of(entity: &Entity) -> DeploymentPriority {
+        entity
+            .get("priority")
+            .cloned()
+            .and_then(Value::as_string)
+            .and_then(|priority| DeploymentPriority::parse(&priority))
+            .unwrap_or_default()
     }
 
     pub fn write_operations(self, id: &SubgraphDeploymentId) -> Vec<MetadataOperation> {
@@ -497,6 +587,9 @@ impl SubgraphDeploymentAssignmentEntity {
             id: id.to_string(),
             nodeId: self.node_id.to_string(),
             cost: self.cost,
+            paused: self.paused,
+            networkAlias: self.network_alias,
+            priority: self.priority.as_str(),
         };
         vec![set_metadata_operation(Self::TYPENAME, id.as_str(), entity)]
     }
@@ -773,6 +866,7 @@ pub struct EthereumContractSourceEntity {
     pub address: Option<super::Address>,
     pub abi: String,
     pub start_block: u64,
+    pub file: Option<String>,
 }
 
 impl TypedEntity for EthereumContractSourceEntity {
@@ -787,6 +881,7 @@ impl WriteOperations for EthereumContractSourceEntity {
         entity.set("address", self.address);
         entity.set("abi", self.abi);
         entity.set("startBlock", self.start_block);
+        entity.set("file", self.file);
         ops.add(Self::TYPENAME, id.to_owned(), entity);
     }
 }
@@ -797,6 +892,7 @@ impl From<super::Source> for EthereumContractSourceEntity {
             address: source.address,
             abi: source.abi,
             start_block: source.start_block,
+            file: source.file,
         }
     }
 }
@@ -815,6 +911,7 @@ impl TryFromValue for EthereumContractSourceEntity {
             address: map.get_optional("address")?,
             abi: map.get_required("abi")?,
             start_block: map.get_optional("startBlock")?.unwrap_or_default(),
+            file: map.get_optional("file")?,
         })
     }
 }
@@ -830,6 +927,7 @@ pub struct EthereumContractMappingEntity {
     pub block_handlers: Vec<EthereumBlockHandlerEntity>,
     pub call_handlers: Vec<EthereumCallHandlerEntity>,
     pub event_handlers: Vec<EthereumContractEventHandlerEntity>,
+    pub handler: Option<String>,
 }
 
 impl TypedEntity for EthereumContractMappingEntity {
@@ -898,6 +996,7 @@ impl WriteOperations for EthereumContractMappingEntity {
         entity.set("eventHandlers", event_handler_ids);
         entity.set("callHandlers", call_handler_ids);
         entity.set("blockHandlers", block_handler_ids);
+        entity.set("handler", self.handler);
 
         ops.add(Self::TYPENAME, id.to_owned(), entity);
     }
@@ -930,6 +1029,7 @@ impl<'a> From<&'a super::Mapping> for EthereumContractMappingEntity {
                 .into_iter()
                 .map(Into::into)
                 .collect(),
+            handler: mapping.handler.clone(),
         }
     }
 }
@@ -954,6 +1054,7 @@ impl TryFromValue for EthereumContractMappingEntity {
             event_handlers: map.get_optional("eventHandlers")?.unwrap_or_default(),
             call_handlers: map.get_optional("callHandlers")?.unwrap_or_default(),
             block_handlers: map.get_optional("blockHandlers")?.unwrap_or_default(),
+            handler: map.get_optional("handler")?,
         })
     }
 }
@@ -1110,6 +1211,7 @@ impl TryFromValue for EthereumBlockHandlerFilterEntity {
 pub struct EthereumCallHandlerEntity {
     pub function: String,
     pub handler: String,
+    pub include_failed: bool,
 }
 
 impl TypedEntity for EthereumCallHandlerEntity {
@@ -1123,6 +1225,7 @@ impl WriteOperations for EthereumCallHandlerEntity {
         entity.set("id", id);
         entity.set("function", self.function);
         entity.set("handler", self.handler);
+        entity.set("includeFailed", self.include_failed);
         ops.add(Self::TYPENAME, id.to_owned(), entity);
     }
 }
@@ -1132,6 +1235,7 @@ impl From<super::MappingCallHandler> for EthereumCallHandlerEntity {
         Self {
             function: call_handler.function,
             handler: call_handler.handler,
+            include_failed: call_handler.include_failed,
         }
     }
 }
@@ -1149,6 +1253,7 @@ impl TryFromValue for EthereumCallHandlerEntity {
         Ok(Self {
             function: map.get_required("function")?,
             handler: map.get_required("handler")?,
+            include_failed: map.get_optional("includeFailed")?.unwrap_or(false),
         })
     }
 }
@@ -1334,7 +1439,7 @@ fn update_metadata_operation(
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SubgraphError {
     pub subgraph_id: SubgraphDeploymentId,
     pub message: String,
@@ -1,6 +1,9 @@
 /// Traits and types for all system components.
 pub mod components;
 
+/// The abstraction over the chains that subgraphs index.
+pub mod blockchain;
+
 /// Common data types used throughout The Graph.
 pub mod data;
 
@@ -80,33 +83,42 @@ pub mod prelude {
         EthereumBlockPointer, EthereumBlockTriggerType, EthereumBlockWithCalls,
         EthereumBlockWithTriggers, EthereumCall, EthereumCallData, EthereumCallFilter,
         EthereumContractCall, EthereumContractCallError, EthereumEventData, EthereumLogFilter,
-        EthereumNetworkIdentifier, EthereumTransactionData, EthereumTrigger, LightEthereumBlock,
-        LightEthereumBlockExt, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+        EthereumNetworkIdentifier, EthereumRpcError, EthereumTransactionData, EthereumTrigger,
+        LightEthereumBlock, LightEthereumBlockExt, ProviderEthRpcMetrics, RpcErrorKind,
+        SubgraphEthRpcMetrics,
     };
     pub use crate::components::graphql::{GraphQlRunner, SubscriptionResultFuture};
-    pub use crate::components::link_resolver::{JsonStreamValue, JsonValueStream, LinkResolver};
+    pub use crate::components::link_resolver::{
+        FileTooLargeError, JsonStreamValue, JsonValueStream, LinkResolver,
+    };
     pub use crate::components::metrics::{
         aggregate::Aggregate, stopwatch::StopwatchMetrics, Collector, Counter, CounterVec, Gauge,
         GaugeVec, Histogram, HistogramOpts, HistogramVec, MetricsRegistry, Opts, PrometheusError,
         Registry,
     };
-    pub use crate::components::server::admin::JsonRpcServer;
-    pub use crate::components::server::index_node::IndexNodeServer;
+    pub use crate::components::server::admin::{AdminScope, AdminTokens, JsonRpcServer};
+    pub use crate::components::server::index_node::{
+        HandlerTriggerCount, HealthCheck, IndexNodeServer, NetworkInfo, TriggerDryRun,
+    };
     pub use crate::components::server::metrics::MetricsServer;
     pub use crate::components::server::query::GraphQLServer;
     pub use crate::components::server::subscription::SubscriptionServer;
     pub use crate::components::store::{
-        AttributeIndexDefinition, BlockNumber, ChainStore, ChildMultiplicity, EntityCache,
-        EntityChange, EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityLink,
-        EntityModification, EntityOperation, EntityOrder, EntityQuery, EntityRange, EntityWindow,
-        EthereumCallCache, MetadataOperation, ParentLink, PoolWaitStats, QueryStore, Store,
-        StoreError, StoreEvent, StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore,
-        TransactionAbortError, WindowAttribute, BLOCK_NUMBER_MAX, SUBSCRIPTION_THROTTLE_INTERVAL,
+        subgraph_file_hash, AllowedQuery, AttributeCipher, AttributeIndexDefinition,
+        BlockModifications, BlockNumber, ChainStore, ChildMultiplicity, DeploymentStorage,
+        EntityCache, EntityChange, EntityChangeOperation, EntityCollection, EntityFilter,
+        EntityKey, EntityLink, EntityModification, EntityOperation, EntityOrder, EntityQuery,
+        EntityRange, EntityWindow, EthereumCallCache, IndexingFreshness, MetadataOperation,
+        NodeLivenessStore, ParentLink, PoolWaitStats, QueryStore, Store, StoreError, StoreEvent,
+        StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore, SubgraphFileStore,
+        SubgraphFork, TableStorage, TransactionAbortError, WindowAttribute, BLOCK_NUMBER_MAX,
+        SUBGRAPH_FILE_PREFIX, SUBSCRIPTION_THROTTLE_INTERVAL,
     };
     pub use crate::components::subgraph::{
-        BlockState, DataSourceLoader, DataSourceTemplateInfo, HostMetrics, RuntimeHost,
-        RuntimeHostBuilder, SubgraphAssignmentProvider, SubgraphInstance, SubgraphInstanceManager,
-        SubgraphRegistrar, SubgraphVersionSwitchingMode,
+        BlockState, DataSourceLoader, DataSourceTemplateInfo, DeploymentPlacer, DeploymentPriority,
+        HostMetrics, ImportFormat, MappingError, RuntimeHost, RuntimeHostBuilder,
+        SubgraphAssignmentProvider, SubgraphInstance, SubgraphInstanceManager, SubgraphOperation,
+        SubgraphOperationOutcome, SubgraphRegistrar, SubgraphVersionSwitchingMode,
     };
     pub use crate::components::{EventConsumer, EventProducer};
 
@@ -115,7 +127,7 @@ pub mod prelude {
         shape_hash::shape_hash, SerializableValue, TryFromValue, ValueMap,
     };
     pub use crate::data::query::{
-        Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
+        query_hash, Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
     };
     pub use crate::data::schema::Schema;
     pub use crate::data::store::ethereum::*;
@@ -127,9 +139,10 @@ pub mod prelude {
     pub use crate::data::subgraph::schema::{SubgraphDeploymentEntity, TypedEntity};
     pub use crate::data::subgraph::{
         BlockHandlerFilter, CreateSubgraphResult, DataSource, DataSourceContext,
-        DataSourceTemplate, Link, MappingABI, MappingBlockHandler, MappingCallHandler,
-        MappingEventHandler, SubgraphAssignmentProviderError, SubgraphAssignmentProviderEvent,
-        SubgraphDeploymentId, SubgraphManifest, SubgraphManifestResolveError,
+        DataSourceTemplate, IndexHint, IndexerHints, Link, MappingABI, MappingBlockHandler,
+        MappingCallHandler, MappingEntityHandler, MappingEventHandler,
+        SubgraphAssignmentProviderError, SubgraphAssignmentProviderEvent, SubgraphDeploymentId,
+        SubgraphFeature, SubgraphManifest, SubgraphManifestResolveError,
         SubgraphManifestValidationError, SubgraphName, SubgraphRegistrarError,
         UnvalidatedSubgraphManifest,
     };
@@ -144,11 +157,13 @@ pub mod prelude {
     pub use crate::log::codes::LogCode;
     pub use crate::log::elastic::{elastic_logger, ElasticDrainConfig, ElasticLoggingConfig};
     pub use crate::log::factory::{
-        ComponentLoggerConfig, ElasticComponentLoggerConfig, LoggerFactory,
+        ComponentLoggerConfig, DeploymentLogDrain, ElasticComponentLoggerConfig, LogDrainTarget,
+        LoggerFactory,
     };
+    pub use crate::log::loki::{loki_logger, LokiDrainConfig, LokiLoggingConfig};
     pub use crate::log::split::split_logger;
     pub use crate::util::cache_weight::CacheWeight;
     pub use crate::util::error::CompatErr;
-    pub use crate::util::futures::{retry, TimeoutError};
+    pub use crate::util::futures::{retry, RetryObserver, TimeoutError};
     pub use crate::util::stats::MovingStats;
 }
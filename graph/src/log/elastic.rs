@@ -139,6 +139,9 @@ pub struct ElasticDrainConfig {
     pub custom_id_value: String,
     /// The batching interval.
     pub flush_interval: Duration,
+    /// Keys of the logger's own values, e.g. `block_number`, that are added
+    /// to the arguments of each log besides those of the log statement.
+    pub logger_args: Vec<String>,
 }
 
 /// An slog `Drain` for logging to Elasticsearch.
@@ -323,7 +326,21 @@ impl Drain for ElasticDrain {
             .kv()
             .serialize(record, &mut serializer)
             .expect("failed to serialize log message arguments into hash map");
-        let arguments = serializer.finish();
+        let mut arguments = serializer.finish();
+
+        // Add the requested logger values that the log message does not
+        // override
+        if !self.config.logger_args.is_empty() {
+            let mut serializer = HashMapKVSerializer::new();
+            values
+                .serialize(record, &mut serializer)
+                .expect("failed to serialize logger arguments into hash map");
+            for (key, value) in serializer.finish() {
+                if self.config.logger_args.contains(&key) {
+                    arguments.entry(key).or_insert(value);
+                }
+            }
+        }
 
         let mut text = format!("{}", record.msg());
         if n_logger_kvs > 0 {
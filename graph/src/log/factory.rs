@@ -1,7 +1,11 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::data::subgraph::SubgraphDeploymentId;
 use crate::log::elastic::*;
+use crate::log::loki::*;
+use crate::log::recent::{self, RecentLogsDrain};
 use crate::log::split::*;
 use slog::*;
 
@@ -15,11 +19,31 @@ pub struct ComponentLoggerConfig {
     pub elastic: Option<ElasticComponentLoggerConfig>,
 }
 
+/// Where a deployment log drain sends logs to.
+#[derive(Clone, Debug)]
+pub enum LogDrainTarget {
+    Elasticsearch {
+        general: ElasticLoggingConfig,
+        index: String,
+    },
+    Loki(LokiLoggingConfig),
+}
+
+/// Sends the mapping logs and the errors of some deployments somewhere
+/// besides the node's own logs, so that the developers of these subgraphs
+/// can see them.
+#[derive(Clone, Debug)]
+pub struct DeploymentLogDrain {
+    pub deployments: Vec<SubgraphDeploymentId>,
+    pub target: LogDrainTarget,
+}
+
 /// Factory for creating component and subgraph loggers.
 #[derive(Clone)]
 pub struct LoggerFactory {
     parent: Logger,
     elastic_config: Option<ElasticLoggingConfig>,
+    deployment_drains: Arc<Vec<DeploymentLogDrain>>,
 }
 
 impl LoggerFactory {
@@ -28,6 +52,16 @@ impl LoggerFactory {
         Self {
             parent: logger,
             elastic_config,
+            deployment_drains: Arc::new(vec![]),
+        }
+    }
+
+    /// Creates a new factory whose subgraph loggers also log to the
+    /// `drains` that apply to their deployment.
+    pub fn with_deployment_drains(self, drains: Vec<DeploymentLogDrain>) -> Self {
+        Self {
+            deployment_drains: Arc::new(drains),
+            ..self
         }
     }
 
@@ -36,6 +70,7 @@ impl LoggerFactory {
         Self {
             parent,
             elastic_config: self.elastic_config.clone(),
+            deployment_drains: self.deployment_drains.clone(),
         }
     }
 
@@ -65,6 +100,7 @@ impl LoggerFactory {
                                     custom_id_key: String::from("componentId"),
                                     custom_id_value: component.to_string(),
                                     flush_interval: Duration::from_secs(5),
+                                    logger_args: vec![],
                                 },
                                 term_logger.clone(),
                             ),
@@ -75,12 +111,32 @@ impl LoggerFactory {
         }
     }
 
-    /// Creates a subgraph logger with Elasticsearch support.
+    /// Creates a subgraph logger with Elasticsearch support that also logs
+    /// to the deployment log drains for the subgraph.
     pub fn subgraph_logger(&self, subgraph_id: &SubgraphDeploymentId) -> Logger {
         let term_logger = self
             .parent
             .new(o!("subgraph_id" => subgraph_id.to_string()));
 
+        let logger = self.with_elastic(subgraph_id, term_logger.clone());
+        let logger = if recent::enabled() {
+            split_logger(logger, RecentLogsDrain::new(subgraph_id.clone()))
+        } else {
+            logger
+        };
+
+        // Problems with a drain are logged without going to the drains, so
+        // that a drain that is down does not log about itself forever
+        self.deployment_drains
+            .iter()
+            .filter(|drain| drain.deployments.contains(subgraph_id))
+            .fold(logger, |logger, drain| {
+                let drain_logger = self.deployment_drain_logger(subgraph_id, drain, &term_logger);
+                split_logger(logger, SubgraphOutput(drain_logger))
+            })
+    }
+
+    fn with_elastic(&self, subgraph_id: &SubgraphDeploymentId, term_logger: Logger) -> Logger {
         self.elastic_config
             .clone()
             .map(|elastic_config| {
@@ -94,6 +150,7 @@ impl LoggerFactory {
                             custom_id_key: String::from("subgraphId"),
                             custom_id_value: subgraph_id.to_string(),
                             flush_interval: Duration::from_secs(5),
+                            logger_args: vec![],
                         },
                         term_logger.clone(),
                     ),
@@ -101,4 +158,56 @@ impl LoggerFactory {
             })
             .unwrap_or(term_logger)
     }
+
+    fn deployment_drain_logger(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        drain: &DeploymentLogDrain,
+        error_logger: &Logger,
+    ) -> Logger {
+        match &drain.target {
+            LogDrainTarget::Elasticsearch { general, index } => elastic_logger(
+                ElasticDrainConfig {
+                    general: general.clone(),
+                    index: index.clone(),
+                    document_type: String::from("log"),
+                    custom_id_key: String::from("subgraphId"),
+                    custom_id_value: subgraph_id.to_string(),
+                    flush_interval: Duration::from_secs(5),
+                    logger_args: vec![String::from("block_number"), String::from("block_hash")],
+                },
+                error_logger.clone(),
+            ),
+            LogDrainTarget::Loki(general) => {
+                let mut labels = BTreeMap::new();
+                labels.insert(String::from("subgraph_id"), subgraph_id.to_string());
+                loki_logger(
+                    LokiDrainConfig {
+                        general: general.clone(),
+                        labels,
+                        flush_interval: Duration::from_secs(5),
+                    },
+                    error_logger.clone(),
+                )
+            }
+        }
+    }
+}
+
+/// Passes on what a subgraph developer needs to see: the logs of the
+/// subgraph's mappings, which are the only logs with a tag, and errors.
+#[derive(Debug)]
+struct SubgraphOutput(Logger);
+
+impl Drain for SubgraphOutput {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        if !record.tag().is_empty() || record.level().is_at_least(Level::Error) {
+            Drain::log(&self.0, record, values)
+        } else {
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use chrono::prelude::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use slog::*;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use super::{HeaderSerializer, KeyValueSerializer};
+
+/// A drain that writes every record as a single line of JSON, for log
+/// collectors like Loki or Elasticsearch. Besides `timestamp`, `level` and
+/// `msg`, each line has the `subgraph_id` and `component` of the logger (if
+/// set) and all other key values as fields of their own.
+pub struct JsonFormat<W: Write> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> JsonFormat<W> {
+    pub fn new(writer: W) -> Self {
+        JsonFormat {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write> Drain for JsonFormat<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let line = to_json(record, values)?;
+        let mut writer = self.writer.lock().unwrap();
+        writeln!(writer, "{}", line)?;
+        writer.flush()
+    }
+}
+
+pub(crate) fn to_json(record: &Record, values: &OwnedKVList) -> io::Result<Value> {
+    let mut serializer = KeyValueSerializer::new();
+    record.kv().serialize(record, &mut serializer)?;
+    let body_kvs = serializer.finish();
+
+    let mut serializer = HeaderSerializer::new();
+    values.serialize(record, &mut serializer)?;
+    let (subgraph_id, components, header_kvs) = serializer.finish();
+
+    // Key values of the log statement take precedence over those of the
+    // logger, the fixed fields over both
+    let mut object = Map::new();
+    for (k, v) in header_kvs.into_iter().chain(body_kvs) {
+        object.insert(k, Value::String(v));
+    }
+    object.insert(
+        "timestamp".to_owned(),
+        Value::String(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    object.insert(
+        "level".to_owned(),
+        Value::String(record.level().as_str().to_owned()),
+    );
+    object.insert("msg".to_owned(), Value::String(record.msg().to_string()));
+    if let Some(subgraph_id) = subgraph_id {
+        object.insert("subgraph_id".to_owned(), Value::String(subgraph_id));
+    }
+    if !components.is_empty() {
+        object.insert(
+            "component".to_owned(),
+            Value::String(components.join(" > ")),
+        );
+    }
+    Ok(Value::Object(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn one_object_per_line() {
+        let buffer = Buffer::default();
+        let logger = Logger::root(JsonFormat::new(buffer.clone()).fuse(), o!())
+            .new(o!("component" => "SubgraphInstanceManager", "subgraph_id" => "QmTest"))
+            .new(o!("component" => "BlockStream", "network" => "mainnet"));
+
+        info!(logger, "Scanning blocks"; "provider" => "infura", "from" => 10);
+        warn!(logger, "Trying again");
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+
+        let first = &lines[0];
+        assert_eq!("INFO", first["level"]);
+        assert_eq!("Scanning blocks", first["msg"]);
+        assert_eq!("QmTest", first["subgraph_id"]);
+        assert_eq!("SubgraphInstanceManager > BlockStream", first["component"]);
+        assert_eq!("mainnet", first["network"]);
+        assert_eq!("infura", first["provider"]);
+        assert_eq!("10", first["from"]);
+        assert!(first["timestamp"].is_string());
+
+        assert_eq!("WARN", lines[1]["level"]);
+        assert!(lines[1].get("provider").is_none());
+    }
+}
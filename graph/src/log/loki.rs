@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::prelude::Utc;
+use futures03::TryFutureExt;
+use reqwest;
+use reqwest::Client;
+use serde_json::{json, Value};
+use slog::*;
+use slog_async;
+
+use super::json::to_json;
+
+/// General configuration parameters for logging to Loki.
+#[derive(Clone, Debug)]
+pub struct LokiLoggingConfig {
+    /// The Loki service to log to.
+    pub endpoint: String,
+    /// The username for basic authentication (optional).
+    pub username: Option<String>,
+    /// The password for basic authentication (optional).
+    pub password: Option<String>,
+}
+
+/// Configuration for `LokiDrain`.
+#[derive(Clone, Debug)]
+pub struct LokiDrainConfig {
+    /// General Loki logging configuration.
+    pub general: LokiLoggingConfig,
+    /// The labels of the streams that logs are pushed to. Each log level
+    /// gets a stream of its own, with the additional label `level`.
+    pub labels: BTreeMap<String, String>,
+    /// The batching interval.
+    pub flush_interval: Duration,
+}
+
+#[derive(Clone, Debug)]
+struct LokiEntry {
+    level: &'static str,
+    /// Nanoseconds since the epoch, as Loki wants them
+    timestamp: String,
+    line: String,
+}
+
+/// An slog `Drain` for logging to Loki. Each log is a line of JSON in the
+/// same format that `GRAPH_LOG_FORMAT=json` produces, so that the key
+/// values of the log, e.g. the block a subgraph is processing, can be
+/// extracted with Loki's `json` parser.
+pub struct LokiDrain {
+    config: LokiDrainConfig,
+    error_logger: Logger,
+    entries: Arc<Mutex<Vec<LokiEntry>>>,
+}
+
+impl LokiDrain {
+    /// Creates a new `LokiDrain`.
+    pub fn new(config: LokiDrainConfig, error_logger: Logger) -> Self {
+        let drain = LokiDrain {
+            config,
+            error_logger,
+            entries: Arc::new(Mutex::new(vec![])),
+        };
+        drain.periodically_flush_logs();
+        drain
+    }
+
+    fn periodically_flush_logs(&self) {
+        use futures03::stream::StreamExt;
+
+        let flush_logger = self.error_logger.clone();
+        let entries = self.entries.clone();
+        let config = self.config.clone();
+
+        crate::task_spawn::spawn(tokio::time::interval(self.config.flush_interval).for_each(
+            move |_| {
+                let entries = entries.clone();
+                let config = config.clone();
+                let flush_logger = flush_logger.clone();
+                async move {
+                    let entries_to_send =
+                        std::mem::replace(&mut *entries.lock().unwrap(), Vec::new());
+
+                    // Do nothing if there are no logs to flush
+                    if entries_to_send.is_empty() {
+                        return;
+                    }
+
+                    trace!(
+                        flush_logger,
+                        "Flushing {} logs to Loki",
+                        entries_to_send.len()
+                    );
+
+                    let mut push_url = reqwest::Url::parse(config.general.endpoint.as_str())
+                        .expect("invalid Loki URL");
+                    push_url.set_path("loki/api/v1/push");
+
+                    let request = Client::new()
+                        .post(push_url)
+                        .header("Content-Type", "application/json")
+                        .body(push_body(&config.labels, entries_to_send).to_string());
+                    let request = match config.general.username {
+                        Some(username) => request.basic_auth(username, config.general.password),
+                        None => request,
+                    };
+                    request
+                        .send()
+                        .and_then(|response| async { response.error_for_status() })
+                        .map_ok(|_| ())
+                        .unwrap_or_else(move |e| {
+                            // Log if there was a problem sending the logs
+                            error!(flush_logger, "Failed to send logs to Loki: {}", e);
+                        })
+                        .await;
+                }
+            },
+        ));
+    }
+}
+
+/// The body of a request to Loki's push API, with one stream per log level.
+fn push_body(labels: &BTreeMap<String, String>, entries: Vec<LokiEntry>) -> Value {
+    let mut streams: BTreeMap<&str, Vec<[String; 2]>> = BTreeMap::new();
+    for entry in entries {
+        streams
+            .entry(entry.level)
+            .or_default()
+            .push([entry.timestamp, entry.line]);
+    }
+    let streams: Vec<_> = streams
+        .into_iter()
+        .map(|(level, values)| {
+            let mut labels = labels.clone();
+            labels.insert("level".to_owned(), level.to_owned());
+            json!({ "stream": labels, "values": values })
+        })
+        .collect();
+    json!({ "streams": streams })
+}
+
+impl Drain for LokiDrain {
+    type Ok = ();
+    type Err = ();
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        // Don't sent `trace` logs to Loki.
+        if record.level() == Level::Trace {
+            return Ok(());
+        }
+
+        let line = to_json(record, values).map_err(|_| ())?.to_string();
+        let entry = LokiEntry {
+            level: match record.level() {
+                Level::Critical => "critical",
+                Level::Error => "error",
+                Level::Warning => "warning",
+                Level::Info => "info",
+                Level::Debug => "debug",
+                Level::Trace => "trace",
+            },
+            timestamp: Utc::now().timestamp_nanos().to_string(),
+            line,
+        };
+        self.entries.lock().unwrap().push(entry);
+
+        Ok(())
+    }
+}
+
+/// Creates a new asynchronous Loki logger.
+///
+/// Uses `error_logger` to print any Loki logging errors,
+/// so they don't go unnoticed.
+pub fn loki_logger(config: LokiDrainConfig, error_logger: Logger) -> Logger {
+    let loki_drain = LokiDrain::new(config, error_logger).fuse();
+    let async_drain = slog_async::Async::new(loki_drain)
+        .chan_size(10000)
+        .build()
+        .fuse();
+    Logger::root(async_drain, o!())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_stream_per_level() {
+        let entry = |level, timestamp: &str, line: &str| LokiEntry {
+            level,
+            timestamp: timestamp.to_owned(),
+            line: line.to_owned(),
+        };
+        let labels: BTreeMap<_, _> = vec![("subgraph_id".to_owned(), "QmTest".to_owned())]
+            .into_iter()
+            .collect();
+
+        let body = push_body(
+            &labels,
+            vec![
+                entry("info", "1", "first"),
+                entry("error", "2", "second"),
+                entry("info", "3", "third"),
+            ],
+        );
+
+        assert_eq!(
+            json!({
+                "streams": [
+                    {
+                        "stream": { "subgraph_id": "QmTest", "level": "error" },
+                        "values": [["2", "second"]]
+                    },
+                    {
+                        "stream": { "subgraph_id": "QmTest", "level": "info" },
+                        "values": [["1", "first"], ["3", "third"]]
+                    }
+                ]
+            }),
+            body
+        );
+    }
+}
@@ -28,12 +28,35 @@ use std::{env, fmt, io, result};
 pub mod codes;
 pub mod elastic;
 pub mod factory;
+pub mod json;
+pub mod loki;
+pub mod recent;
 pub mod split;
 
+use self::json::JsonFormat;
+
 pub fn logger(show_debug: bool) -> Logger {
-    let use_color = isatty::stdout_isatty();
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = CustomFormat::new(decorator, use_color).fuse();
+    match env::var("GRAPH_LOG_FORMAT")
+        .unwrap_or_else(|_| "terminal".into())
+        .as_str()
+    {
+        "terminal" => {
+            let use_color = isatty::stdout_isatty();
+            let decorator = slog_term::TermDecorator::new().build();
+            logger_with_drain(CustomFormat::new(decorator, use_color).fuse(), show_debug)
+        }
+        "json" => logger_with_drain(JsonFormat::new(io::stdout()).fuse(), show_debug),
+        format => panic!(
+            "invalid GRAPH_LOG_FORMAT `{}`, expected `terminal` or `json`",
+            format
+        ),
+    }
+}
+
+fn logger_with_drain<D>(drain: D, show_debug: bool) -> Logger
+where
+    D: Drain<Ok = (), Err = Never> + Send + 'static,
+{
     let drain = slog_envlogger::LogBuilder::new(drain)
         .filter(
             None,
@@ -0,0 +1,159 @@
+use chrono::prelude::{SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use slog::*;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::Mutex;
+
+use super::KeyValueSerializer;
+use crate::data::subgraph::SubgraphDeploymentId;
+
+lazy_static! {
+    /// How many mapping log lines to keep for each deployment
+    static ref RECENT_LOG_LINES: usize = env::var("GRAPH_RECENT_LOG_LINES")
+        .unwrap_or("100".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_RECENT_LOG_LINES");
+
+    /// The recent mapping logs of the deployments this node indexes
+    pub static ref RECENT_LOGS: RecentLogs = RecentLogs::new(*RECENT_LOG_LINES);
+}
+
+/// Whether subgraph loggers need to keep recent mapping logs at all.
+pub(crate) fn enabled() -> bool {
+    *RECENT_LOG_LINES > 0
+}
+
+/// A line that a mapping logged with `log.*`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLine {
+    pub timestamp: String,
+    pub level: Level,
+    pub data_source: String,
+    pub message: String,
+    /// The block the subgraph was processing
+    pub block_number: Option<u64>,
+}
+
+/// Keeps the last lines that the mappings of each deployment logged, so
+/// that they can be looked at without access to the node's logs.
+pub struct RecentLogs {
+    capacity: usize,
+    lines: Mutex<HashMap<SubgraphDeploymentId, VecDeque<LogLine>>>,
+}
+
+impl RecentLogs {
+    pub fn new(capacity: usize) -> Self {
+        RecentLogs {
+            capacity,
+            lines: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn push(&self, subgraph_id: &SubgraphDeploymentId, line: LogLine) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap();
+        let lines = lines.entry(subgraph_id.clone()).or_default();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// The recent lines of a deployment, oldest first.
+    pub fn get(&self, subgraph_id: &SubgraphDeploymentId) -> Vec<LogLine> {
+        self.lines
+            .lock()
+            .unwrap()
+            .get(subgraph_id)
+            .map(|lines| lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forget the lines of a deployment, e.g. when the node stops indexing
+    /// it.
+    pub fn remove(&self, subgraph_id: &SubgraphDeploymentId) {
+        self.lines.lock().unwrap().remove(subgraph_id);
+    }
+}
+
+/// An slog `Drain` that keeps the mapping logs of a deployment in
+/// `RecentLogs`. Mapping logs are the only logs with a tag, which is the
+/// name of the data source.
+#[derive(Debug)]
+pub(crate) struct RecentLogsDrain {
+    subgraph_id: SubgraphDeploymentId,
+}
+
+impl RecentLogsDrain {
+    pub fn new(subgraph_id: SubgraphDeploymentId) -> Self {
+        RecentLogsDrain { subgraph_id }
+    }
+}
+
+impl Drain for RecentLogsDrain {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        if record.tag().is_empty() {
+            return Ok(());
+        }
+
+        let mut serializer = KeyValueSerializer::new();
+        // Serializing into strings can not fail
+        values.serialize(record, &mut serializer).ok();
+        let block_number = serializer
+            .finish()
+            .into_iter()
+            .find(|(key, _)| key == "block_number")
+            .and_then(|(_, value)| value.parse().ok());
+
+        RECENT_LOGS.push(
+            &self.subgraph_id,
+            LogLine {
+                timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                level: record.level(),
+                data_source: record.tag().to_owned(),
+                message: record.msg().to_string(),
+                block_number,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(message: &str) -> LogLine {
+        LogLine {
+            timestamp: "2020-08-01T00:00:00.000Z".to_owned(),
+            level: Level::Info,
+            data_source: "Token".to_owned(),
+            message: message.to_owned(),
+            block_number: Some(1),
+        }
+    }
+
+    #[test]
+    fn keeps_the_most_recent_lines() {
+        let logs = RecentLogs::new(2);
+        let id = SubgraphDeploymentId::new("QmTest").unwrap();
+        let other = SubgraphDeploymentId::new("QmOther").unwrap();
+
+        logs.push(&id, line("one"));
+        logs.push(&id, line("two"));
+        logs.push(&id, line("three"));
+        logs.push(&other, line("other"));
+
+        assert_eq!(vec![line("two"), line("three")], logs.get(&id));
+        assert_eq!(vec![line("other")], logs.get(&other));
+
+        logs.remove(&id);
+        assert!(logs.get(&id).is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Facilitate sleeping with an exponential backoff. Sleep durations will
+/// increase by a factor of 2 from `base` until they reach `ceiling`, at
+/// which point any call to `sleep` or `sleep_async` will sleep for
+/// `ceiling`
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    pub attempt: u64,
+    base: Duration,
+    ceiling: Duration,
+    /// The fraction by which sleep durations are randomly shortened or
+    /// lengthened
+    jitter: f64,
+}
+
+impl ExponentialBackoff {
+    pub fn new(base: Duration, ceiling: Duration) -> Self {
+        ExponentialBackoff {
+            attempt: 0,
+            base,
+            ceiling,
+            jitter: 0.0,
+        }
+    }
+
+    /// Randomly vary each sleep by up to `jitter` times its duration, so
+    /// that many things that failed at the same time do not all retry at
+    /// the same time, too
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
+    /// Record that we made an attempt and sleep for the appropriate amount
+    /// of time. Do not use this from async contexts since it uses
+    /// `thread::sleep`
+    pub fn sleep(&mut self) {
+        std::thread::sleep(self.next_attempt());
+    }
+
+    /// Record that we made an attempt and sleep for the appropriate amount
+    /// of time
+    pub async fn sleep_async(&mut self) {
+        tokio::time::delay_for(self.next_attempt()).await
+    }
+
+    /// The amount of time the next call to `sleep` or `sleep_async` will
+    /// wait for, before jitter is applied
+    pub fn delay(&self) -> Duration {
+        let mut delay = self.base.saturating_mul(1 << self.attempt.min(31) as u32);
+        if delay > self.ceiling {
+            delay = self.ceiling;
+        }
+        delay
+    }
+
+    /// Record that we made an attempt and return how long to wait before
+    /// the next one, for callers that need to know the delay before they
+    /// sleep
+    pub fn next_attempt(&mut self) -> Duration {
+        let mut delay = self.delay();
+        if self.jitter > 0.0 {
+            let factor = rand::thread_rng().gen_range(1.0 - self.jitter, 1.0 + self.jitter);
+            delay = delay.mul_f64(factor);
+        }
+        self.attempt += 1;
+        delay
+    }
+
+    /// Forget about any previous attempts, the next sleep will wait for
+    /// `base`
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[test]
+fn delay_doubles_until_ceiling() {
+    let mut backoff = ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(5));
+    let delays: Vec<_> = (0..5).map(|_| backoff.next_attempt().as_secs()).collect();
+    assert_eq!(vec![1, 2, 4, 5, 5], delays);
+
+    backoff.reset();
+    assert_eq!(Duration::from_secs(1), backoff.delay());
+}
+
+#[test]
+fn jitter_stays_within_bounds() {
+    let mut backoff =
+        ExponentialBackoff::new(Duration::from_secs(10), Duration::from_secs(100)).with_jitter(0.5);
+    for expected in &[10, 20, 40, 80, 100] {
+        let delay = backoff.next_attempt().as_secs_f64();
+        assert!(delay >= *expected as f64 * 0.5 && delay <= *expected as f64 * 1.5);
+    }
+}
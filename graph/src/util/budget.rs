@@ -0,0 +1,229 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+use crate::components::subgraph::DeploymentPriority;
+
+fn index(priority: DeploymentPriority) -> usize {
+    priority as usize
+}
+
+/// Someone waiting for a unit of the budget
+struct Waiter {
+    priority: DeploymentPriority,
+    /// The order in which waiters arrived, to break ties
+    seq: u64,
+    ready: oneshot::Sender<BudgetPermit>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    /// `BinaryHeap` is a max-heap; the highest priority, and among those
+    /// the waiter that arrived first, comes first
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// The units in use, by priority
+    held: [usize; 3],
+    waiting: BinaryHeap<Waiter>,
+    next_seq: u64,
+}
+
+/// A fixed number of units of something that deployments share, like the
+/// requests a provider serves at once or the writes the store does at
+/// once. Units are handed out in the order of the priority of the
+/// deployments that wait for them. While deployments of a higher priority
+/// hold units, those of a lower priority only get up to their
+/// `DeploymentPriority::share` of the budget, so that the rest stays
+/// available for the former.
+pub struct PriorityBudget {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+impl PriorityBudget {
+    pub fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(PriorityBudget {
+            capacity: capacity.max(1),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Wait for a unit of the budget for a deployment with `priority`. The
+    /// unit is returned when the permit is dropped
+    pub async fn acquire(self: &Arc<Self>, priority: DeploymentPriority) -> BudgetPermit {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.waiting.is_empty() && self.may_take(&state, priority) {
+                state.held[index(priority)] += 1;
+                return BudgetPermit {
+                    budget: self.clone(),
+                    priority: Some(priority),
+                };
+            }
+            let (ready, receiver) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.waiting.push(Waiter {
+                priority,
+                seq,
+                ready,
+            });
+            // A free unit that the waiters so far could not have might be
+            // one this waiter can have
+            self.dispatch(&mut state);
+            receiver
+        };
+        receiver
+            .await
+            .expect("waiters get a permit before they are dropped")
+    }
+
+    /// The number of units in use
+    pub fn in_use(&self) -> usize {
+        self.state.lock().unwrap().held.iter().sum()
+    }
+
+    /// The number of units a deployment with `priority` may have together
+    /// with the other deployments of its priority while deployments of a
+    /// higher priority hold units
+    fn share(&self, priority: DeploymentPriority) -> usize {
+        ((self.capacity as f64 * priority.share()).floor() as usize).max(1)
+    }
+
+    fn may_take(&self, state: &State, priority: DeploymentPriority) -> bool {
+        if state.held.iter().sum::<usize>() >= self.capacity {
+            return false;
+        }
+        let contended = state.held[index(priority) + 1..].iter().any(|n| *n > 0);
+        !contended || state.held[index(priority)] < self.share(priority)
+    }
+
+    /// Hand free units to the waiters in order for as long as the first
+    /// of them may have one
+    fn dispatch(self: &Arc<Self>, state: &mut State) {
+        loop {
+            let priority = match state.waiting.peek() {
+                Some(waiter) => waiter.priority,
+                None => return,
+            };
+            if !self.may_take(state, priority) {
+                return;
+            }
+            let waiter = state.waiting.pop().expect("the waiter is there");
+            state.held[index(priority)] += 1;
+            let permit = BudgetPermit {
+                budget: self.clone(),
+                priority: Some(priority),
+            };
+            if let Err(mut permit) = waiter.ready.send(permit) {
+                // The waiter gave up; dropping the permit we got back must
+                // not release the unit again
+                permit.priority = None;
+                state.held[index(priority)] -= 1;
+            }
+        }
+    }
+
+    fn release(self: &Arc<Self>, priority: DeploymentPriority) {
+        let mut state = self.state.lock().unwrap();
+        state.held[index(priority)] -= 1;
+        self.dispatch(&mut state);
+    }
+}
+
+/// A unit of a `PriorityBudget`, which goes back to the budget when the
+/// permit is dropped
+pub struct BudgetPermit {
+    budget: Arc<PriorityBudget>,
+    /// `None` once the unit has been released
+    priority: Option<DeploymentPriority>,
+}
+
+impl Drop for BudgetPermit {
+    fn drop(&mut self) {
+        if let Some(priority) = self.priority.take() {
+            self.budget.release(priority);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures03::FutureExt;
+
+    use DeploymentPriority::*;
+
+    #[tokio::test]
+    async fn higher_priorities_go_first() {
+        let budget = PriorityBudget::new(2);
+        let first = budget.acquire(Batch).await;
+        let second = budget.acquire(Batch).await;
+        assert_eq!(2, budget.in_use());
+
+        // Polling the waiters once puts them in line
+        let mut batch = budget.acquire(Batch).boxed();
+        let mut normal = budget.acquire(Normal).boxed();
+        let mut critical = budget.acquire(Critical).boxed();
+        assert!((&mut batch).now_or_never().is_none());
+        assert!((&mut normal).now_or_never().is_none());
+        assert!((&mut critical).now_or_never().is_none());
+
+        drop(first);
+        let critical = critical.await;
+        assert!((&mut normal).now_or_never().is_none());
+
+        drop(second);
+        let _normal = normal.await;
+        assert!((&mut batch).now_or_never().is_none());
+
+        drop(critical);
+        batch.await;
+    }
+
+    #[tokio::test]
+    async fn lower_priorities_keep_to_their_share() {
+        let budget = PriorityBudget::new(4);
+        let critical = budget.acquire(Critical).await;
+        let _batch = budget.acquire(Batch).await;
+
+        // While a critical deployment uses the budget, batch deployments
+        // may only hold one of its four units
+        let mut waiting = budget.acquire(Batch).boxed();
+        assert!((&mut waiting).now_or_never().is_none());
+        assert_eq!(2, budget.in_use());
+
+        // Normal deployments get ahead of the waiting batch deployment
+        let normal = budget.acquire(Normal).await;
+        assert_eq!(3, budget.in_use());
+
+        drop(critical);
+        assert!((&mut waiting).now_or_never().is_none());
+
+        drop(normal);
+        let _waiting = waiting.await;
+        assert_eq!(2, budget.in_use());
+    }
+}
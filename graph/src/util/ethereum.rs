@@ -1,7 +1,10 @@
-use ethabi::{Contract, Event, Function, ParamType};
+use ethabi::{Contract, Event, Function, ParamType, Token};
 use tiny_keccak::Keccak;
 use web3::types::H256;
 
+use crate::data::store::scalar::BigInt;
+use crate::data::subgraph::ContractError;
+
 /// Hashes a string to a H256 hash.
 pub fn string_to_h256(s: &str) -> H256 {
     let mut result = [0u8; 32];
@@ -60,6 +63,12 @@ fn ambiguous_event_signature(event: &Event) -> String {
     )
 }
 
+/// Returns the topic0 of the logs of a non-anonymous event, the hash of its
+/// `Event(uint256,address)` signature.
+pub fn event_topic0(event: &Event) -> H256 {
+    string_to_h256(&ambiguous_event_signature(event))
+}
+
 /// Returns an `Event(indexed uint256,address)` type signature for an event.
 fn event_signature(event: &Event) -> String {
     format!(
@@ -118,6 +127,94 @@ pub fn contract_event_with_signature<'a>(
         })
 }
 
+/// Returns the `name(uint256,address)` signature of a function.
+pub fn function_signature(function: &Function) -> String {
+    format!(
+        "{}({})",
+        function.name,
+        function
+            .inputs
+            .iter()
+            .map(|input| event_param_type_signature(&input.kind))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+/// Returns the selector of a function or custom error with the signature
+/// `Name(uint256,address)`, the first four bytes of its hash
+fn selector(signature: &str) -> [u8; 4] {
+    let mut selector = [0u8; 4];
+    selector.copy_from_slice(&string_to_h256(signature).0[..4]);
+    selector
+}
+
+/// Returns the `Error(uint256,address)` signature of a custom error.
+fn error_signature(error: &ContractError) -> String {
+    format!(
+        "{}({})",
+        error.name,
+        error
+            .inputs
+            .iter()
+            .map(|input| event_param_type_signature(&input.kind))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+/// Decodes the reason for a revert from the revert data of a call: the
+/// message of a Solidity `Error(string)`, the code of a `Panic(uint256)`,
+/// or one of the custom `errors` of the contract with its arguments.
+/// Returns `None` if the data is none of these.
+pub fn decode_revert_reason(data: &[u8], errors: &[ContractError]) -> Option<String> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (data_selector, payload) = data.split_at(4);
+
+    if data_selector == selector("Error(string)") {
+        return ethabi::decode(&[ParamType::String], payload)
+            .ok()?
+            .pop()?
+            .to_string();
+    }
+    if data_selector == selector("Panic(uint256)") {
+        let code = ethabi::decode(&[ParamType::Uint(256)], payload)
+            .ok()?
+            .pop()?
+            .to_uint()?;
+        return Some(format!("panic code 0x{:x}", code));
+    }
+
+    let error = errors
+        .iter()
+        .find(|error| data_selector == selector(&error_signature(error)))?;
+    let kinds = error
+        .inputs
+        .iter()
+        .map(|input| input.kind.clone())
+        .collect::<Vec<_>>();
+    let args = ethabi::decode(&kinds, payload).ok()?;
+    Some(format!(
+        "{}({})",
+        error.name,
+        args.iter().map(revert_arg).collect::<Vec<_>>().join(", ")
+    ))
+}
+
+/// Formats an argument of a custom error like Solidity literals
+fn revert_arg(arg: &Token) -> String {
+    match arg {
+        Token::Address(address) => format!("0x{:x}", address),
+        Token::Int(n) => BigInt::from_signed_u256(n).to_string(),
+        Token::Uint(n) => BigInt::from_unsigned_u256(n).to_string(),
+        Token::String(s) => format!("{:?}", s),
+        Token::FixedBytes(bytes) | Token::Bytes(bytes) => format!("0x{}", hex::encode(bytes)),
+        _ => format!("{}", arg),
+    }
+}
+
 pub fn contract_function_with_signature<'a>(
     contract: &'a Contract,
     target_signature: &str,
@@ -140,3 +237,56 @@ pub fn contract_function_with_signature<'a>(
                 && target_signature == actual_signature
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethabi::Param;
+    use web3::types::U256;
+
+    #[test]
+    fn decode_revert_reasons() {
+        let errors = vec![ContractError {
+            name: "TooLow".to_owned(),
+            inputs: vec![Param {
+                name: "min".to_owned(),
+                kind: ParamType::Uint(256),
+            }],
+        }];
+        let revert = |signature: &str, args: &[Token]| {
+            let mut data = selector(signature).to_vec();
+            data.extend(ethabi::encode(args));
+            data
+        };
+
+        assert_eq!(
+            Some("not the owner".to_owned()),
+            decode_revert_reason(
+                &revert("Error(string)", &[Token::String("not the owner".into())]),
+                &errors
+            )
+        );
+        assert_eq!(
+            Some("panic code 0x11".to_owned()),
+            decode_revert_reason(
+                &revert("Panic(uint256)", &[Token::Uint(U256::from(0x11))]),
+                &errors
+            )
+        );
+        assert_eq!(
+            Some("TooLow(10)".to_owned()),
+            decode_revert_reason(
+                &revert("TooLow(uint256)", &[Token::Uint(U256::from(10))]),
+                &errors
+            )
+        );
+        assert_eq!(
+            None,
+            decode_revert_reason(
+                &revert("Unknown(uint256)", &[Token::Uint(U256::from(10))]),
+                &errors
+            )
+        );
+        assert_eq!(None, decode_revert_reason(&[], &errors));
+    }
+}
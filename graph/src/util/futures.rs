@@ -1,11 +1,13 @@
 use crate::ext::futures::FutureExtension;
 use failure::Fail;
 use futures::prelude::*;
+use lazy_static::lazy_static;
 use slog::{debug, trace, warn, Logger};
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::marker::PhantomData;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio_retry::strategy::{jitter, ExponentialBackoff};
 use tokio_retry::Retry;
 
@@ -21,7 +23,13 @@ use tokio_retry::Retry;
 ///    `.no_timeout()`.
 /// 6. Call `.run(...)`.
 ///
-/// All steps are required, except Step 2 and Step 3.
+/// All steps are required, except Step 2 and Step 3. Retries can also be
+/// counted by passing a `RetryObserver` to `.observe(...)`.
+///
+/// Once an operation has failed `warn_after` times in a row, its failures
+/// are logged as warnings, but only once every `GRAPH_RETRY_LOG_INTERVAL`
+/// seconds for all operations with the same name; the warning says how
+/// many failures were not logged since the last one.
 ///
 /// Example usage:
 /// ```
@@ -53,6 +61,7 @@ pub fn retry<I, E>(operation_name: impl ToString, logger: &Logger) -> RetryConfi
         log_after: 1,
         warn_after: 10,
         limit: RetryConfigProperty::Unknown,
+        observer: None,
         phantom_item: PhantomData,
         phantom_error: PhantomData,
     }
@@ -65,6 +74,7 @@ pub struct RetryConfig<I, E> {
     log_after: u64,
     warn_after: u64,
     limit: RetryConfigProperty<usize>,
+    observer: Option<Arc<dyn RetryObserver>>,
     phantom_item: PhantomData<I>,
     phantom_error: PhantomData<E>,
 }
@@ -105,6 +115,13 @@ where
         self
     }
 
+    /// Report the attempts that are retried, and whether the operation runs
+    /// out of attempts, to `observer`.
+    pub fn observe(mut self, observer: Arc<dyn RetryObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
     /// Set a limit on how many retry attempts to make.
     pub fn limit(mut self, limit: usize) -> Self {
         self.limit.set(limit);
@@ -167,6 +184,7 @@ where
         let log_after = self.inner.log_after;
         let warn_after = self.inner.warn_after;
         let limit_opt = self.inner.limit.unwrap(&operation_name, "limit");
+        let observer = self.inner.observer;
         let timeout = self.timeout;
 
         trace!(logger, "Run with retry: {}", operation_name);
@@ -178,6 +196,7 @@ where
             log_after,
             warn_after,
             limit_opt,
+            observer,
             move || {
                 try_it()
                     .timeout(timeout)
@@ -208,6 +227,7 @@ impl<I, E> RetryConfigNoTimeout<I, E> {
         let log_after = self.inner.log_after;
         let warn_after = self.inner.warn_after;
         let limit_opt = self.inner.limit.unwrap(&operation_name, "limit");
+        let observer = self.inner.observer;
 
         trace!(logger, "Run with retry: {}", operation_name);
 
@@ -218,6 +238,7 @@ impl<I, E> RetryConfigNoTimeout<I, E> {
             log_after,
             warn_after,
             limit_opt,
+            observer,
             // No timeout, so all errors are inner errors
             move || try_it().map_err(TimeoutError::Inner),
         )
@@ -228,7 +249,7 @@ impl<I, E> RetryConfigNoTimeout<I, E> {
     }
 }
 
-#[derive(Fail, Debug)]
+#[derive(Fail, Debug, Clone)]
 pub enum TimeoutError<T: Debug + Send + Sync + 'static> {
     #[fail(display = "{:?}", _0)]
     Inner(T),
@@ -252,6 +273,73 @@ impl<T: Debug + Send + Sync + 'static> TimeoutError<T> {
     }
 }
 
+/// Counts what happens to an operation that is run with `retry`, e.g. as
+/// metrics
+pub trait RetryObserver: Send + Sync + 'static {
+    /// An attempt of the operation failed or timed out in a way that calls
+    /// for another attempt
+    fn retried(&self);
+
+    /// The operation gave up because it ran out of attempts; follows the
+    /// `retried` of the last attempt
+    fn exhausted(&self);
+}
+
+lazy_static! {
+    /// How often failures of operations with the same name are logged
+    static ref RETRY_LOG_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_RETRY_LOG_INTERVAL")
+            .unwrap_or("30".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_RETRY_LOG_INTERVAL env var")
+    );
+
+    /// When failures of operations were last logged, by operation name. Names
+    /// that were not logged for a while are forgotten once there are more
+    /// than `MAX_RETRY_WARNINGS` of them
+    static ref RETRY_WARNINGS: Mutex<HashMap<String, RetryWarnings>> = Mutex::new(HashMap::new());
+}
+
+const MAX_RETRY_WARNINGS: usize = 1000;
+
+struct RetryWarnings {
+    last: Instant,
+    /// The failures that were not logged since `last`
+    suppressed: u64,
+}
+
+/// Whether a failure of `operation_name` should be logged now, see
+/// `RETRY_LOG_INTERVAL`. If it should, returns how many failures of the
+/// operation were not logged since the last one that was
+fn warn_now(operation_name: &str, interval: Duration) -> Option<u64> {
+    let mut warnings = RETRY_WARNINGS.lock().unwrap();
+    match warnings.get_mut(operation_name) {
+        Some(operation) if operation.last.elapsed() < interval => {
+            operation.suppressed += 1;
+            None
+        }
+        Some(operation) => {
+            let suppressed = operation.suppressed;
+            operation.last = Instant::now();
+            operation.suppressed = 0;
+            Some(suppressed)
+        }
+        None => {
+            if warnings.len() >= MAX_RETRY_WARNINGS {
+                warnings.retain(|_, operation| operation.last.elapsed() < interval);
+            }
+            warnings.insert(
+                operation_name.to_owned(),
+                RetryWarnings {
+                    last: Instant::now(),
+                    suppressed: 0,
+                },
+            );
+            Some(0)
+        }
+    }
+}
+
 fn run_retry<I, E, F, R>(
     operation_name: String,
     logger: Logger,
@@ -259,6 +347,7 @@ fn run_retry<I, E, F, R>(
     log_after: u64,
     warn_after: u64,
     limit_opt: Option<usize>,
+    observer: Option<Arc<dyn RetryObserver>>,
     mut try_it_with_timeout: F,
 ) -> impl Future<Item = I, Error = TimeoutError<E>> + Send
 where
@@ -268,12 +357,14 @@ where
     R: Future<Item = I, Error = TimeoutError<E>> + Send,
 {
     let condition = Arc::new(condition);
+    let observer_for_result = observer.clone();
 
     let mut attempt_count = 0;
     Retry::spawn(retry_strategy(limit_opt), move || {
         let operation_name = operation_name.clone();
         let logger = logger.clone();
         let condition = condition.clone();
+        let observer = observer.clone();
 
         attempt_count += 1;
 
@@ -285,6 +376,9 @@ where
                 .unwrap_or(false);
 
             if is_elapsed {
+                if let Some(observer) = &observer {
+                    observer.retried();
+                }
                 if attempt_count >= log_after {
                     debug!(
                         logger,
@@ -304,17 +398,23 @@ where
 
                 // If needs retry
                 if condition.check(&result) {
+                    if let Some(observer) = &observer {
+                        observer.retried();
+                    }
                     if attempt_count >= warn_after {
                         // This looks like it would be nice to de-duplicate, but if we try
                         // to use log! slog complains about requiring a const for the log level
                         // See also b05e1594-e408-4047-aefb-71fc60d70e8f
-                        warn!(
-                            logger,
-                            "Trying again after {} failed (attempt #{}) with result {:?}",
-                            &operation_name,
-                            attempt_count,
-                            result
-                        );
+                        if let Some(suppressed) = warn_now(&operation_name, *RETRY_LOG_INTERVAL) {
+                            warn!(
+                                logger,
+                                "Trying again after {} failed (attempt #{}) with result {:?}",
+                                &operation_name,
+                                attempt_count,
+                                result;
+                                "failures_not_logged" => suppressed,
+                            );
+                        }
                     } else if attempt_count >= log_after {
                         // See also b05e1594-e408-4047-aefb-71fc60d70e8f
                         debug!(
@@ -335,12 +435,18 @@ where
             }
         })
     })
-    .then(|retry_result| {
+    .then(move |retry_result| {
         // Unwrap the inner result.
         // The outer Ok/Err is only used for retry control flow.
         match retry_result {
             Ok(r) => r,
-            Err(e) => e,
+            // The last attempt asked to be retried, too
+            Err(e) => {
+                if let Some(observer) = observer_for_result {
+                    observer.exhausted();
+                }
+                e
+            }
         }
     })
 }
@@ -428,6 +534,7 @@ mod tests {
     use futures::future;
     use futures03::compat::Future01CompatExt;
     use slog::o;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Mutex;
 
     #[test]
@@ -539,4 +646,79 @@ mod tests {
 
         assert_eq!(result, 10);
     }
+
+    #[derive(Default)]
+    struct CountingObserver {
+        retried: AtomicUsize,
+        exhausted: AtomicUsize,
+    }
+
+    impl RetryObserver for CountingObserver {
+        fn retried(&self) {
+            self.retried.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn exhausted(&self) {
+            self.exhausted.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn observe_retries() {
+        let logger = Logger::root(::slog::Discard, o!());
+
+        let observer = Arc::new(CountingObserver::default());
+        let c = Mutex::new(0);
+        let result = retry("test", &logger)
+            .no_logging()
+            .limit(5)
+            .observe(observer.clone())
+            .no_timeout()
+            .run(move || {
+                let mut c_guard = c.lock().unwrap();
+                *c_guard += 1;
+                if *c_guard >= 3 {
+                    future::ok(*c_guard)
+                } else {
+                    future::err(*c_guard)
+                }
+            })
+            .compat()
+            .await;
+        assert_eq!(Ok(3), result);
+        assert_eq!(2, observer.retried.load(Ordering::SeqCst));
+        assert_eq!(0, observer.exhausted.load(Ordering::SeqCst));
+
+        let observer = Arc::new(CountingObserver::default());
+        let result = retry("test", &logger)
+            .no_logging()
+            .limit(5)
+            .observe(observer.clone())
+            .no_timeout()
+            .run(|| future::err::<(), _>(()))
+            .compat()
+            .await;
+        assert_eq!(Err(()), result);
+        assert_eq!(5, observer.retried.load(Ordering::SeqCst));
+        assert_eq!(1, observer.exhausted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn warnings_are_rate_limited() {
+        let interval = Duration::from_secs(3600);
+        assert_eq!(Some(0), warn_now("rate limited test", interval));
+        assert_eq!(None, warn_now("rate limited test", interval));
+        assert_eq!(None, warn_now("rate limited test", interval));
+        assert_eq!(Some(0), warn_now("other rate limited test", interval));
+
+        // Once the interval has passed, the failures in between are reported
+        assert_eq!(
+            Some(2),
+            warn_now("rate limited test", Duration::from_secs(0))
+        );
+        assert_eq!(
+            Some(0),
+            warn_now("rate limited test", Duration::from_secs(0))
+        );
+    }
 }
@@ -145,6 +145,34 @@ impl<K: Clone + Ord + Eq + Hash + Debug, V: CacheWeight + Default> LfuCache<K, V
         self.queue.len()
     }
 
+    /// Move the entries whose key matches `pred` into a cache of their own.
+    pub fn split_off(&mut self, pred: impl Fn(&K) -> bool) -> Self {
+        let mut other = LfuCache::new();
+        let queue = std::mem::replace(&mut self.queue, PriorityQueue::new());
+        for (entry, priority) in queue {
+            if pred(&entry.key) {
+                self.total_weight -= entry.weight;
+                other.total_weight += entry.weight;
+                other.queue.push(entry, priority);
+            } else {
+                self.queue.push(entry, priority);
+            }
+        }
+        other
+    }
+
+    /// Move the entries of `other` whose key matches `pred` into this cache,
+    /// replacing entries with the same key. The other entries are dropped.
+    pub fn merge(&mut self, other: Self, pred: impl Fn(&K) -> bool) {
+        for (entry, priority) in other.queue {
+            if pred(&entry.key) {
+                self.remove(&entry.key);
+                self.total_weight += entry.weight;
+                self.queue.push(entry, priority);
+            }
+        }
+    }
+
     pub fn evict(&mut self, max_weight: usize) {
         if self.total_weight <= max_weight {
             return;
@@ -190,21 +218,23 @@ impl<K: Ord + Eq + Hash, V> Extend<(CacheEntry<K, V>, Priority)> for LfuCache<K,
     }
 }
 
-#[test]
-fn entity_lru_cache() {
-    #[derive(Default, Debug, PartialEq, Eq)]
-    struct Weight(usize);
+#[cfg(test)]
+#[derive(Default, Debug, PartialEq, Eq)]
+struct Weight(usize);
 
-    impl CacheWeight for Weight {
-        fn weight(&self) -> usize {
-            self.0
-        }
+#[cfg(test)]
+impl CacheWeight for Weight {
+    fn weight(&self) -> usize {
+        self.0
+    }
 
-        fn indirect_weight(&self) -> usize {
-            0
-        }
+    fn indirect_weight(&self) -> usize {
+        0
     }
+}
 
+#[test]
+fn entity_lru_cache() {
     let mut cache: LfuCache<&'static str, Weight> = LfuCache::new();
     cache.insert("panda", Weight(2));
     cache.insert("cow", Weight(1));
@@ -246,3 +276,33 @@ fn entity_lru_cache() {
     assert!(cache.get(&"alligator").is_none());
     assert_eq!(cache.get(&"lion"), Some(&Weight(3)));
 }
+
+#[test]
+fn split_off_and_merge() {
+    let mut cache: LfuCache<&'static str, Weight> = LfuCache::new();
+    cache.insert("panda", Weight(2));
+    cache.insert("cow", Weight(1));
+    cache.insert("crow", Weight(3));
+
+    let mut birds = cache.split_off(|key| *key == "crow");
+    assert_eq!(cache.len(), 2);
+    assert!(!cache.contains_key(&"crow"));
+    assert_eq!(birds.get(&"crow"), Some(&Weight(3)));
+
+    // The weights moved along with the entries.
+    cache.evict(3);
+    assert_eq!(cache.len(), 2);
+    birds.evict(3);
+    assert_eq!(birds.len(), 1);
+
+    // Only birds come back, and they replace what the cache has.
+    birds.insert("owl", Weight(1));
+    birds.insert("wolf", Weight(5));
+    cache.insert("owl", Weight(4));
+    cache.merge(birds, |key| *key != "wolf");
+    assert_eq!(cache.len(), 4);
+    assert_eq!(cache.get(&"owl"), Some(&Weight(1)));
+    assert!(!cache.contains_key(&"wolf"));
+    cache.evict(7);
+    assert_eq!(cache.len(), 4);
+}
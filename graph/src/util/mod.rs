@@ -14,3 +14,7 @@ pub mod error;
 pub mod stats;
 
 pub mod cache_weight;
+
+pub mod backoff;
+
+pub mod budget;
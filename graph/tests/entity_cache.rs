@@ -1,10 +1,16 @@
-use std::collections::BTreeMap;
-use std::sync::Arc;
+use atomic_refcell::AtomicRefCell;
+use slog::{o, Discard, Logger};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
+use graph::components::subgraph::{ProofOfIndexing, ProofOfIndexingEvent};
+use graph::data::subgraph::schema::SubgraphError;
 use graph::mock::MockStore;
 use graph::prelude::{
-    Entity, EntityCache, EntityKey, EntityModification, SubgraphDeploymentId, Value,
+    BlockNumber, BlockState, Entity, EntityCache, EntityKey, EntityModification, StoreError,
+    SubgraphDeploymentId, SubgraphFork, Value,
 };
+use graph::util::lfu_cache::LfuCache;
 
 fn make_band(id: &'static str, data: Vec<(&str, Value)>) -> (EntityKey, Entity) {
     let subgraph_id = SubgraphDeploymentId::new("entity_cache").unwrap();
@@ -19,6 +25,26 @@ fn make_band(id: &'static str, data: Vec<(&str, Value)>) -> (EntityKey, Entity)
     )
 }
 
+/// A fork that has every band, with a genre
+#[derive(Default)]
+struct BandFork {
+    fetched: Mutex<HashSet<String>>,
+}
+
+impl SubgraphFork for BandFork {
+    fn fetch(&self, key: &EntityKey, block: BlockNumber) -> Result<Option<Entity>, StoreError> {
+        // Entities are fetched as of the block before the one being processed
+        assert_eq!(41, block);
+        if !self.fetched.lock().unwrap().insert(key.entity_id.clone()) {
+            return Ok(None);
+        }
+        Ok(Some(Entity::from(vec![
+            ("id", key.entity_id.as_str().into()),
+            ("genre", "Rock".into()),
+        ])))
+    }
+}
+
 fn sort_by_entity_key(mut mods: Vec<EntityModification>) -> Vec<EntityModification> {
     mods.sort_by_key(|m| m.entity_key().clone());
     mods
@@ -207,3 +233,213 @@ fn consecutive_modifications() {
         },])
     );
 }
+
+#[test]
+fn discarded_handler_modifications() {
+    let mut store = MockStore::new();
+
+    store
+        .expect_get_many_mock()
+        .returning(|_, _| Ok(BTreeMap::new()));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    // The first handler succeeds and its changes are kept.
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    cache.enter_handler();
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
+    cache.exit_handler().unwrap();
+
+    // The second handler fails, none of its changes should be applied.
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    cache.enter_handler();
+    cache.set(sigurros_key, sigurros_data).unwrap();
+    cache.remove(mogwai_key.clone());
+    cache.exit_handler_and_discard_changes();
+
+    let result = cache.as_modifications(&*store);
+    assert_eq!(
+        sort_by_entity_key(result.unwrap().modifications),
+        sort_by_entity_key(vec![EntityModification::Insert {
+            key: mogwai_key,
+            data: mogwai_data,
+        }])
+    );
+}
+
+fn poi_digests(proof_of_indexing: ProofOfIndexing) -> HashMap<String, Vec<u8>> {
+    proof_of_indexing
+        .take()
+        .into_iter()
+        .map(|(name, region)| (name, region.pause(None)))
+        .collect()
+}
+
+#[test]
+fn discarded_handler_poi_events() {
+    let logger = Logger::root(Discard, o!());
+    let proof_of_indexing = Arc::new(AtomicRefCell::new(ProofOfIndexing::new(1)));
+    let shared = Some(proof_of_indexing.clone());
+    let mut state = BlockState::new(Arc::new(MockStore::new()), LfuCache::new());
+
+    let mogwai = ProofOfIndexingEvent::RemoveEntity {
+        entity_type: "Band",
+        id: "mogwai",
+    };
+    let sigurros = ProofOfIndexingEvent::RemoveEntity {
+        entity_type: "Band",
+        id: "sigurros",
+    };
+
+    // The first handler succeeds and its events are written
+    state.enter_handler();
+    state.poi_events.push("mainnet", &mogwai);
+    state.exit_handler(&logger, &shared).unwrap();
+
+    // The second handler fails and its events are dropped
+    state.enter_handler();
+    state.poi_events.push("mainnet", &sigurros);
+    state.exit_handler_and_discard_changes_due_to_error(SubgraphError {
+        subgraph_id: SubgraphDeploymentId::new("entity_cache").unwrap(),
+        message: "failed".to_owned(),
+        block_ptr: None,
+        handler: None,
+    });
+    assert!(state.poi_events.is_empty());
+
+    drop(shared);
+    let actual = Arc::try_unwrap(proof_of_indexing)
+        .ok()
+        .unwrap()
+        .into_inner();
+
+    let mut expected = ProofOfIndexing::new(1);
+    expected.write(&logger, "mainnet", &mogwai);
+
+    assert_eq!(poi_digests(expected), poi_digests(actual));
+}
+
+#[test]
+fn forked_modifications() {
+    let mut store = MockStore::new();
+
+    store.expect_get_mock().returning(|_| Ok(None));
+    store
+        .expect_get_many_mock()
+        .returning(|_, _| Ok(BTreeMap::new()));
+
+    let store = Arc::new(store);
+    let mut cache =
+        EntityCache::new(store.clone()).with_fork(Some(Arc::new(BandFork::default())), 41);
+
+    // An entity that is loaded comes from the fork and gets stored even if
+    // it is not changed
+    let (mogwai_key, _) = make_band("mogwai", vec![]);
+    let mogwai_data = Entity::from(vec![("id", "mogwai".into()), ("genre", "Rock".into())]);
+    assert_eq!(cache.get(&mogwai_key).unwrap(), Some(mogwai_data.clone()));
+    assert_eq!(cache.get(&mogwai_key).unwrap(), Some(mogwai_data.clone()));
+
+    // An entity that is only set is merged with the entity from the fork
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    cache.set(sigurros_key.clone(), sigurros_data).unwrap();
+
+    let result = cache.as_modifications(&*store);
+    assert_eq!(
+        sort_by_entity_key(result.unwrap().modifications),
+        sort_by_entity_key(vec![
+            EntityModification::Insert {
+                key: mogwai_key,
+                data: mogwai_data,
+            },
+            EntityModification::Insert {
+                key: sigurros_key,
+                data: Entity::from(vec![
+                    ("id", "sigurros".into()),
+                    ("name", "Sigur Ros".into()),
+                    ("genre", "Rock".into()),
+                ]),
+            },
+        ])
+    );
+}
+
+#[test]
+fn undeclared_entity_types() {
+    let store = Arc::new(MockStore::new());
+    let declared: BTreeSet<_> = vec!["Band".to_owned()].into_iter().collect();
+    let mut cache = EntityCache::new(store).with_declared_types(Arc::new(declared));
+
+    let (mogwai_key, mogwai_data) = make_band("mogwai", vec![("id", "mogwai".into())]);
+    cache.set(mogwai_key.clone(), mogwai_data).unwrap();
+    cache.remove(mogwai_key);
+    assert!(!cache.accessed_undeclared_types());
+
+    let (mut album_key, _) = make_band("come-on-die-young", vec![]);
+    album_key.entity_type = "Album".into();
+    cache.remove(album_key);
+    assert!(cache.accessed_undeclared_types());
+}
+
+#[test]
+fn split_off_and_extend_current() {
+    let mut store = MockStore::new();
+    store
+        .expect_get_many_mock()
+        .returning(|_, _| Ok(BTreeMap::new()));
+    let store = Arc::new(store);
+
+    let (mogwai_key, mogwai_data) = make_band("mogwai", vec![("id", "mogwai".into())]);
+    let (mut album_key, _) = make_band("come-on-die-young", vec![]);
+    album_key.entity_type = "Album".into();
+    let mut current = LfuCache::new();
+    current.insert(mogwai_key.clone(), Some(mogwai_data.clone()));
+    current.insert(album_key.clone(), None);
+    let mut cache = EntityCache::with_current(store.clone(), current);
+
+    // The bands move to a cache that only declares bands
+    let declared: BTreeSet<_> = vec!["Band".to_owned()].into_iter().collect();
+    let bands = cache.split_off_current(&declared);
+    assert_eq!(1, bands.len());
+    let mut bands = EntityCache::with_current(store.clone(), bands)
+        .with_declared_types(Arc::new(declared.clone()));
+    assert_eq!(bands.get(&mogwai_key).unwrap(), Some(mogwai_data.clone()));
+
+    // Only the cached bands come back, without any changes
+    let (sigurros_key, sigurros_data) = make_band("sigurros", vec![("id", "sigurros".into())]);
+    bands
+        .set(sigurros_key.clone(), sigurros_data.clone())
+        .unwrap();
+    cache.extend_current(bands);
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(result.modifications, vec![]);
+    assert!(result.entity_lfu_cache.contains_key(&mogwai_key));
+    assert!(result.entity_lfu_cache.contains_key(&album_key));
+
+    // With `extend`, the changes come back as well
+    let mut cache = EntityCache::with_current(store.clone(), result.entity_lfu_cache);
+    let mut bands = EntityCache::with_current(store.clone(), cache.split_off_current(&declared))
+        .with_declared_types(Arc::new(declared));
+    bands
+        .set(sigurros_key.clone(), sigurros_data.clone())
+        .unwrap();
+    cache.extend(bands).unwrap();
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(
+        result.modifications,
+        vec![EntityModification::Insert {
+            key: sigurros_key,
+            data: sigurros_data,
+        }]
+    );
+    assert!(result.entity_lfu_cache.contains_key(&mogwai_key));
+}
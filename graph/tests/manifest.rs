@@ -1,13 +1,16 @@
 use async_trait::async_trait;
 use slog::Logger;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::sync::Arc;
 use std::time::Duration;
 
 use graph::components::link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait};
+use graph::data::subgraph::HintValue;
+use graph::prelude::web3::types::Address;
 use graph::prelude::{
-    Entity, Link, SubgraphDeploymentId, SubgraphManifest, SubgraphManifestValidationError,
-    UnvalidatedSubgraphManifest,
+    DataSource, DataSourceTemplateInfo, Entity, Link, SubgraphDeploymentId, SubgraphManifest,
+    SubgraphManifestValidationError, UnvalidatedSubgraphManifest,
 };
 
 use test_store::LOGGER;
@@ -29,6 +32,10 @@ impl LinkResolverTrait for TextResolver {
         self
     }
 
+    fn with_max_file_size(self, _max_file_size: Option<u64>) -> Self {
+        self
+    }
+
     fn with_retries(self) -> Self {
         self
     }
@@ -53,8 +60,21 @@ const GQL_SCHEMA: &str = "type Thing @entity { id: ID! }";
 
 const ABI: &str = "[{\"type\":\"function\", \"inputs\": [{\"name\": \"i\",\"type\": \"uint256\"}],\"name\":\"get\",\"outputs\": [{\"type\": \"address\",\"name\": \"o\"}]}]";
 
+const ADDRESSES: &str = "
+0x22843e74c59580b3eaf6c233fa67d8b7c561a835
+
+0x9c1b3f1a3ab6e4c43d1b2f6a2e2c1c6de8b4ed3a
+";
+
 const MAPPING: &str = "export function handleGet(call: getCall): void {}";
 
+/// A WASM module that exports a single empty function `handleGet`
+const WASM_MAPPING: &str = "\0asm\x01\0\0\0\
+    \x01\x04\x01\x60\0\0\
+    \x03\x02\x01\0\
+    \x07\x0d\x01\x09handleGet\0\0\
+    \x0a\x04\x01\x02\0\x0b";
+
 async fn resolve_manifest(text: &str) -> SubgraphManifest {
     let mut resolver = TextResolver::default();
     let link = Link::from("/ipfs/Qmmanifest".to_owned());
@@ -62,6 +82,7 @@ async fn resolve_manifest(text: &str) -> SubgraphManifest {
     resolver.add(link.link.as_str(), text);
     resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
     resolver.add("/ipfs/Qmabi", ABI);
+    resolver.add("/ipfs/Qmaddresses", ADDRESSES);
     resolver.add("/ipfs/Qmmapping", MAPPING);
 
     SubgraphManifest::resolve(link, &resolver, &LOGGER)
@@ -75,6 +96,9 @@ async fn resolve_unvalidated(text: &str) -> UnvalidatedSubgraphManifest {
 
     resolver.add(link.link.as_str(), text);
     resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
+    resolver.add("/ipfs/Qmabi", ABI);
+    resolver.add("/ipfs/Qmaddresses", ADDRESSES);
+    resolver.add("/ipfs/Qmmapping", WASM_MAPPING);
 
     UnvalidatedSubgraphManifest::resolve(link, Arc::new(resolver), &LOGGER)
         .await
@@ -118,6 +142,83 @@ specVersion: 0.0.1
     assert_eq!(12345, graft.block);
 }
 
+#[tokio::test]
+async fn indexer_hints_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+indexerHints:
+  prune: 1000
+  indexes:
+    - entity: Thing
+      fields: [id]
+      current: true
+      where:
+        id: thing1
+  accountLike: [Thing]
+specVersion: 0.0.1
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    let hints = manifest.indexer_hints;
+    assert_eq!(Some(1000), hints.history_blocks());
+    assert_eq!(1, hints.indexes.len());
+    let index = &hints.indexes[0];
+    assert_eq!("Thing", index.entity);
+    assert_eq!(vec!["id".to_owned()], index.fields);
+    assert!(index.current);
+    assert_eq!(
+        Some(&HintValue::String("thing1".to_owned())),
+        index.filter.get("id")
+    );
+    assert_eq!(vec!["Thing".to_owned()], hints.account_like);
+}
+
+#[test]
+fn invalid_indexer_hints_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+indexerHints:
+  prune: 10
+  indexes:
+    - entity: Gadget
+      fields: [id]
+    - entity: Thing
+      fields: [name]
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: indexerHints.prune: \
+                deployments have to keep at least 50 blocks of history, but only 10 are kept",
+                "/ipfs/Qmmanifest: indexerHints.indexes[0].entity: \
+                entity `Gadget` is not defined in the schema",
+                "/ipfs/Qmmanifest: indexerHints.indexes[1].fields[0]: \
+                entity `Thing` has no field `name`",
+            ],
+            msgs
+        );
+    })
+}
+
 #[test]
 fn graft_invalid_manifest() {
     const YAML: &str = "
@@ -179,6 +280,213 @@ specVersion: 0.0.1
     })
 }
 
+#[test]
+fn undeclared_feature_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+graft:
+  base: Qmbase
+  block: 1
+features:
+  - nonFatalErrors
+specVersion: 0.0.4
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        // Manifests with an earlier spec version do not have to declare
+        // the features they use
+        let unvalidated = resolve_unvalidated(&YAML.replace("0.0.4", "0.0.1")).await;
+        let errors = unvalidated
+            .validate(store.clone())
+            .expect_err("Validation must fail");
+        assert!(!errors.iter().any(|e| matches!(
+            e,
+            SubgraphManifestValidationError::FeatureValidationError(_)
+        )));
+
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msg = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .find(|e| {
+                matches!(
+                    e,
+                    SubgraphManifestValidationError::FeatureValidationError(_)
+                )
+            })
+            .expect("There must be a FeatureValidationError")
+            .to_string();
+        assert_eq!(
+            "the subgraph uses features that are not declared in the manifest: `grafting`",
+            msg
+        );
+    })
+}
+
+#[test]
+fn invalid_definition_manifest() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Factory
+    network: mainnet
+    source:
+      abi: Factory
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+        - Gadget
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Factory
+          file:
+            /: /ipfs/Qmabi
+      eventHandlers:
+        - event: Transfer(address,uint256)
+          handler: handleTransfer
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: dataSources[0].mapping.entities[1]: \
+                entity `Gadget` is not defined in the schema",
+                "/ipfs/Qmmanifest: dataSources[0].mapping.eventHandlers[0].event: \
+                event `Transfer(address,uint256)` is not defined in ABI `Factory`",
+                "/ipfs/Qmmanifest: dataSources[0].mapping.eventHandlers[0].handler: \
+                handler `handleTransfer` is not exported by mapping `/ipfs/Qmmapping`",
+            ],
+            msgs
+        );
+    })
+}
+
+#[test]
+fn missing_abi_manifest() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Factory
+    network: mainnet
+    source:
+      startBlock: 1
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Factory
+          file:
+            /: /ipfs/Qmabi
+      callHandlers:
+        - function: get(uint256)
+          handler: handleGet
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: dataSources[0].source.abi: \
+                an `ethereum/contract` data source must name the ABI of its contract",
+            ],
+            msgs
+        );
+    })
+}
+
+#[test]
+fn unsupported_kind_manifest() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contrakt
+    name: Factory
+    network: mainnet
+    source:
+      address: \"0x0000000000000000000000000000000000000000\"
+      abi: Factory
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Factory
+          file:
+            /: /ipfs/Qmabi
+      blockHandlers:
+        - handler: handleBlock
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: dataSources[0].kind: \
+                data source kind `ethereum/contrakt` is not supported by this node",
+            ],
+            msgs
+        );
+    })
+}
+
 #[tokio::test]
 async fn parse_call_handlers() {
     const YAML: &str = "
@@ -216,3 +524,431 @@ specVersion: 0.0.1
     assert_eq!("Qmmanifest", manifest.id.as_str());
     assert_eq!(true, requires_traces);
 }
+
+#[tokio::test]
+async fn parse_subgraph_data_source() {
+    const YAML: &str = "
+dataSources:
+  - kind: subgraph
+    name: Things
+    network: mainnet
+    source:
+      subgraph: Qmsource
+      startBlock: 100
+    mapping:
+      kind: subgraph/entities
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      abis: []
+      entityHandlers:
+        - entity: Thing
+          handler: handleThing
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    let source = SubgraphDeploymentId::new("Qmsource").unwrap();
+
+    assert_eq!(Some(&source), manifest.data_sources[0].source_subgraph());
+    assert_eq!("mainnet", manifest.network_name());
+    assert_eq!(
+        vec![(source, vec!["Thing".to_owned()])],
+        manifest.source_subgraphs().into_iter().collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn parse_file_template() {
+    const YAML: &str = "
+dataSources: []
+templates:
+  - kind: file/arweave
+    name: Metadata
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      handler: handleMetadata
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+    const TX_ID: &str = "W2czhcswOAe4TgL4Q8kHHqoZ1jbFBntUCrtamYX_rOU";
+
+    let manifest = resolve_manifest(YAML).await;
+    let template = manifest.templates[0].clone();
+    assert_eq!(Some("handleMetadata"), template.mapping.handler.as_deref());
+
+    let info = |params: Vec<&str>| DataSourceTemplateInfo {
+        data_source: "Token".to_owned(),
+        template: template.clone(),
+        params: params.into_iter().map(str::to_owned).collect(),
+        context: None,
+    };
+
+    let data_source = DataSource::try_from(info(vec![TX_ID])).unwrap();
+    assert_eq!(Some(TX_ID), data_source.arweave_file());
+    assert_eq!(None, data_source.source.address);
+
+    let e =
+        DataSource::try_from(info(vec!["0x22843e74c59580b3eaf6c233fa67d8b7c561a835"])).unwrap_err();
+    assert!(
+        e.to_string().contains("invalid Arweave transaction id"),
+        "{}",
+        e
+    );
+    let e = DataSource::try_from(info(vec![])).unwrap_err();
+    assert!(
+        e.to_string()
+            .contains("transaction id parameter is missing"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn invalid_file_manifest() {
+    const YAML: &str = "
+dataSources:
+  - kind: file/arweave
+    name: Metadata
+    network: mainnet
+    source:
+      abi: Factory
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      handler: handleGet
+templates:
+  - kind: file/arweave
+    name: NoHandler
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      blockHandlers:
+        - handler: handleGet
+  - kind: file/arweave
+    name: Unexported
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      handler: handleMetadata
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: dataSources[0].kind: \
+                `file/arweave` data sources can only be created from templates",
+                "/ipfs/Qmmanifest: templates[0].mapping.handler: \
+                a `file/arweave` template must name the handler for its file",
+                "/ipfs/Qmmanifest: templates[0].mapping: \
+                a `file/arweave` template can only have a file handler",
+                "/ipfs/Qmmanifest: templates[1].mapping.handler: \
+                handler `handleMetadata` is not exported by mapping `/ipfs/Qmmapping`",
+            ],
+            msgs
+        );
+    })
+}
+
+const PROXY_YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Token
+    network: mainnet
+    source:
+      address: \"0x22843e74c59580b3eaf6c233fa67d8b7c561a835\"
+      abi: TokenV1
+      startBlock: 10
+      implementations:
+        - abi: TokenV2
+          startBlock: 50
+        - abi: TokenV3
+          startBlock: 100
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: TokenV1
+          file:
+            /: /ipfs/Qmabi
+        - name: TokenV2
+          file:
+            /: /ipfs/Qmabi
+      callHandlers:
+        - function: get(uint256)
+          handler: handleGet
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+#[tokio::test]
+async fn parse_proxy_implementations() {
+    let manifest = resolve_manifest(PROXY_YAML).await;
+    let source = &manifest.data_sources[0].source;
+
+    assert_eq!("TokenV1", source.abi_at(10));
+    assert_eq!("TokenV1", source.abi_at(49));
+    assert_eq!("TokenV2", source.abi_at(50));
+    assert_eq!("TokenV3", source.abi_at(1000));
+    assert_eq!(
+        vec!["TokenV1", "TokenV2", "TokenV3"],
+        source.abis().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn invalid_proxy_manifest() {
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        // Upgrade to TokenV2 before the data source starts
+        let yaml = PROXY_YAML.replace("startBlock: 50", "startBlock: 5");
+        let unvalidated = resolve_unvalidated(&yaml).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: dataSources[0].source.implementations[0].startBlock: \
+                implementation `TokenV2` can not start before the data source, \
+                which starts at block 10",
+                "/ipfs/Qmmanifest: dataSources[0].mapping.abis: \
+                the source ABI `TokenV3` is not listed in the mapping ABIs",
+            ],
+            msgs
+        );
+    })
+}
+
+#[test]
+fn invalid_wildcard_manifest() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Factory
+    network: mainnet
+    source:
+      abi: Factory
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Factory
+          file:
+            /: /ipfs/Qmabi
+      eventHandlers:
+        - event: \"*\"
+          handler: handleGet
+        - event: \"*\"
+          handler: handleGet
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: dataSources[0].mapping.eventHandlers[0].event: \
+                wildcard event handlers require apiVersion 0.0.5 or later, \
+                but the mapping uses 0.0.4",
+                "/ipfs/Qmmanifest: dataSources[0].mapping.eventHandlers[1].event: \
+                wildcard event handlers require apiVersion 0.0.5 or later, \
+                but the mapping uses 0.0.4",
+                "/ipfs/Qmmanifest: dataSources[0].mapping.eventHandlers[1].event: \
+                a mapping can only have one wildcard event handler",
+            ],
+            msgs
+        );
+    })
+}
+
+const ADDRESSES_YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Pool
+    network: mainnet
+    source:
+      addresses:
+        - \"0x6b175474e89094c44da98b954eedeac495271d0f\"
+      addressFile:
+        /: /ipfs/Qmaddresses
+      abi: Pool
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Pool
+          file:
+            /: /ipfs/Qmabi
+      callHandlers:
+        - function: get(uint256)
+          handler: handleGet
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+#[tokio::test]
+async fn parse_address_list() {
+    let manifest = resolve_manifest(ADDRESSES_YAML).await;
+    let data_source = manifest.data_sources[0].clone();
+
+    let addresses: Vec<Address> = vec![
+        "6b175474e89094c44da98b954eedeac495271d0f".parse().unwrap(),
+        "22843e74c59580b3eaf6c233fa67d8b7c561a835".parse().unwrap(),
+        "9c1b3f1a3ab6e4c43d1b2f6a2e2c1c6de8b4ed3a".parse().unwrap(),
+    ];
+    assert_eq!(addresses, data_source.source.addresses);
+
+    let split = data_source.split_addresses();
+    assert_eq!(
+        addresses.into_iter().map(Some).collect::<Vec<_>>(),
+        split
+            .iter()
+            .map(|data_source| data_source.source.address)
+            .collect::<Vec<_>>()
+    );
+    assert!(split
+        .iter()
+        .all(|data_source| data_source.source.addresses.is_empty()));
+}
+
+#[test]
+fn invalid_address_list_manifest() {
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let yaml = ADDRESSES_YAML.replace(
+            "      addresses:",
+            "      address: \"0x22843e74c59580b3eaf6c233fa67d8b7c561a835\"\n      addresses:",
+        );
+        let unvalidated = resolve_unvalidated(&yaml).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: dataSources[0].source.addresses: \
+                a data source can have an `address` or `addresses`, but not both",
+            ],
+            msgs
+        );
+    })
+}
+
+#[test]
+fn invalid_immutable_calls_manifest() {
+    let yaml = ADDRESSES_YAML.replace(
+        "        - name: Pool
+          file:
+            /: /ipfs/Qmabi
+",
+        "        - name: Pool
+          file:
+            /: /ipfs/Qmabi
+          immutableCalls:
+            - get(uint256)
+            - decimals()
+",
+    );
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let unvalidated = resolve_unvalidated(&yaml).await;
+        let msgs = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| matches!(e, SubgraphManifestValidationError::InvalidDefinition { .. }))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "/ipfs/Qmmanifest: dataSources[0].mapping.abis[0].immutableCalls[1]: \
+                function `decimals()` is not defined in ABI `Pool`",
+            ],
+            msgs
+        );
+    })
+}
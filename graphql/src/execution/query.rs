@@ -9,7 +9,10 @@ use graph::prelude::{CheapClone, QueryExecutionError};
 
 use crate::execution::{get_field, get_named_type};
 use crate::introspection::introspection_schema;
-use crate::query::{ast as qast, ext::BlockConstraint, ext::FieldExt};
+use crate::query::{
+    ast as qast,
+    ext::{BlockConstraint, ErrorPolicy, FieldExt},
+};
 use crate::schema::ast as sast;
 
 #[derive(Copy, Clone, Debug)]
@@ -111,11 +114,13 @@ impl Query {
         Ok(Arc::new(query))
     }
 
-    /// Return the block constraint for the toplevel query field(s), merging the
-    /// selection sets of fields that have the same block constraint.
+    /// Return the block constraint and error policy for the toplevel query
+    /// field(s), merging the selection sets of fields that have the same
+    /// block constraint and error policy.
     pub fn block_constraint(
         &self,
-    ) -> Result<HashMap<BlockConstraint, q::SelectionSet>, Vec<QueryExecutionError>> {
+    ) -> Result<HashMap<(BlockConstraint, ErrorPolicy), q::SelectionSet>, Vec<QueryExecutionError>>
+    {
         let mut bcs = HashMap::new();
         let mut errors = Vec::new();
 
@@ -123,7 +128,10 @@ impl Query {
             q::Selection::Field(f) => Some(f),
             _ => None,
         }) {
-            match field.block_constraint(&self.variables) {
+            let bc = field
+                .block_constraint(&self.variables)
+                .and_then(|bc| Ok((bc, field.subgraph_error_policy(&self.variables)?)));
+            match bc {
                 Ok(bc) => {
                     let selection_set = bcs.entry(bc).or_insert(q::SelectionSet {
                         span: self.selection_set.span.clone(),
@@ -1,6 +1,7 @@
 use graphql_parser::{query as q, schema as s, Pos};
 use std::collections::{BTreeMap, HashMap};
 
+use graph::data::graphql::ext::DocumentExt;
 use graph::prelude::*;
 
 use crate::prelude::*;
@@ -330,7 +331,12 @@ impl<'a> IntrospectionResolver<'a> {
                 self.type_objects
                     .get(&String::from("Subscription"))
                     .cloned(),
-            mutationType: q::Value::Null,
+            mutationType:
+                self.schema
+                    .document
+                    .get_mutation_type()
+                    .and_then(|mutation_type| self.type_objects.get(&mutation_type.name))
+                    .cloned(),
             types: self.type_objects.values().cloned().collect::<Vec<_>>(),
             directives: self.directives.clone(),
         }
@@ -26,17 +26,23 @@ mod store;
 /// The external interface for actually running queries
 mod runner;
 
+/// Forwarding mutations to external services
+mod mutations;
+
 /// Prelude that exports the most important traits and types.
 pub mod prelude {
     pub use super::execution::{ExecutionContext, ObjectOrInterface, Query, Resolver};
     pub use super::introspection::{introspection_schema, IntrospectionResolver};
-    pub use super::query::{execute_query, ext::BlockConstraint, QueryExecutionOptions};
+    pub use super::query::{
+        execute_query, ext::BlockConstraint, ext::ErrorPolicy, QueryExecutionOptions,
+    };
     pub use super::schema::{api_schema, ast::validate_entity, APISchemaError};
     pub use super::store::{build_query, StoreResolver};
     pub use super::subscription::{execute_subscription, SubscriptionExecutionOptions};
     pub use super::values::{object_value, IntoValue, MaybeCoercible};
 
     pub use super::graphql_parser::{query::Name, schema::ObjectType};
+    pub use super::mutations::MutationEndpoint;
     pub use super::runner::GraphQlRunner;
 
     pub use crate::object;
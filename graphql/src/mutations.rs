@@ -0,0 +1,243 @@
+use graphql_parser::query as q;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use graph::data::graphql::ext::{DocumentExt, ObjectTypeExt};
+use graph::prelude::*;
+use graph::url::Url;
+
+use crate::query::ast as qast;
+
+/// Where the mutations of some deployments are sent to
+#[derive(Clone, Debug)]
+pub struct MutationEndpoint {
+    pub deployments: Vec<SubgraphDeploymentId>,
+    pub url: Url,
+    /// Headers that are sent with every request, e.g., for authorization
+    pub headers: HashMap<String, String>,
+}
+
+/// Forwards mutations to the endpoint configured for their deployment. The
+/// endpoint gets the mutation as a normal GraphQL request, with the id of
+/// the deployment and the block it has indexed up to in the `subgraph`
+/// extension, and its response is passed on to the client unchanged.
+pub struct MutationProxy {
+    logger: Logger,
+    http_client: reqwest::Client,
+    endpoints: Vec<MutationEndpoint>,
+}
+
+/// Whether `document` is a mutation. Documents with several operations
+/// are never run as mutations since queries do not say which operation to
+/// run
+pub fn is_mutation(document: &q::Document) -> bool {
+    match qast::get_operation(document, None) {
+        Ok(q::OperationDefinition::Mutation(_)) => true,
+        _ => false,
+    }
+}
+
+impl MutationProxy {
+    pub fn new(logger: &Logger, endpoints: Vec<MutationEndpoint>) -> Self {
+        MutationProxy {
+            logger: logger.new(o!("component" => "MutationProxy")),
+            http_client: reqwest::Client::new(),
+            endpoints,
+        }
+    }
+
+    fn endpoint(&self, subgraph_id: &SubgraphDeploymentId) -> Option<&MutationEndpoint> {
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.deployments.contains(subgraph_id))
+    }
+
+    /// Check that the mutation only uses mutations from the schema of its
+    /// deployment, and that the deployment has an endpoint
+    fn check(&self, query: &Query) -> Result<&MutationEndpoint, QueryExecutionError> {
+        let mutation_type = query.schema.document.get_mutation_type().ok_or_else(|| {
+            QueryExecutionError::NotSupported("Mutations are not supported".to_owned())
+        })?;
+        let endpoint = self.endpoint(&query.schema.id).ok_or_else(|| {
+            QueryExecutionError::NotSupported(format!(
+                "no mutation endpoint is configured for subgraph `{}`",
+                query.schema.id
+            ))
+        })?;
+        if let Ok(q::OperationDefinition::Mutation(mutation)) =
+            qast::get_operation(&query.document, None)
+        {
+            for selection in &mutation.selection_set.items {
+                if let q::Selection::Field(field) = selection {
+                    if mutation_type.field(&field.name).is_none() {
+                        return Err(QueryExecutionError::UnknownField(
+                            field.position,
+                            mutation_type.name.clone(),
+                            field.name.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(endpoint)
+    }
+
+    /// Send the mutation `query` to the endpoint of its deployment, which
+    /// has indexed up to `block`
+    pub async fn forward(
+        &self,
+        query: &Query,
+        block: Option<EthereumBlockPointer>,
+        timeout: Duration,
+    ) -> QueryResult {
+        let endpoint = match self.check(query) {
+            Ok(endpoint) => endpoint,
+            Err(e) => return e.into(),
+        };
+
+        let block = block
+            .map(|block| serde_json::json!({ "number": block.number, "hash": block.hash_hex() }))
+            .unwrap_or(serde_json::Value::Null);
+        let body = serde_json::json!({
+            "query": query.document.to_string(),
+            "variables": query.variables,
+            "extensions": {
+                "subgraph": { "id": query.schema.id.to_string(), "block": block }
+            },
+        });
+        debug!(self.logger, "Forwarding mutation";
+               "subgraph_id" => query.schema.id.to_string(),
+               "url" => endpoint.url.as_str());
+
+        let mut request = self
+            .http_client
+            .post(endpoint.url.clone())
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .timeout(timeout);
+        for (name, value) in &endpoint.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let response = request
+            .send()
+            .and_then(|res| async { res.error_for_status() })
+            .and_then(|res| res.text())
+            .await;
+
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(self.logger, "Failed to forward mutation";
+                      "subgraph_id" => query.schema.id.to_string(),
+                      "error" => e.to_string());
+                return QueryExecutionError::MutationFailed(format!(
+                    "mutation endpoint failed: {}",
+                    e
+                ))
+                .into();
+            }
+        };
+        match serde_json::from_str(&response) {
+            Ok(response) => query_result(response),
+            Err(e) => QueryExecutionError::MutationFailed(format!(
+                "mutation endpoint sent an invalid response: {}",
+                e
+            ))
+            .into(),
+        }
+    }
+}
+
+/// Turn the JSON response of the endpoint into a `QueryResult`. Errors only
+/// keep their message
+fn query_result(mut response: serde_json::Value) -> QueryResult {
+    let data = response.get_mut("data").map(serde_json::Value::take);
+    let errors = response
+        .get("errors")
+        .and_then(serde_json::Value::as_array)
+        .map(|errors| {
+            errors
+                .iter()
+                .map(|error| {
+                    let message = error
+                        .get("message")
+                        .and_then(serde_json::Value::as_str)
+                        .map(str::to_owned)
+                        .unwrap_or_else(|| error.to_string());
+                    QueryError::from(QueryExecutionError::MutationFailed(message))
+                })
+                .collect::<Vec<_>>()
+        });
+    QueryResult {
+        data: data.map(query_value),
+        errors,
+        extensions: None,
+    }
+}
+
+fn query_value(value: serde_json::Value) -> q::Value {
+    use serde_json::Value as J;
+
+    match value {
+        J::Null => q::Value::Null,
+        J::Bool(b) => q::Value::Boolean(b),
+        J::Number(n) => match n.as_i64() {
+            Some(i) if i >= std::i32::MIN as i64 && i <= std::i32::MAX as i64 => {
+                q::Value::Int((i as i32).into())
+            }
+            _ => n
+                .as_f64()
+                .map(q::Value::Float)
+                .unwrap_or_else(|| q::Value::String(n.to_string())),
+        },
+        J::String(s) => q::Value::String(s),
+        J::Array(values) => q::Value::List(values.into_iter().map(query_value).collect()),
+        J::Object(object) => q::Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (key, query_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn detects_mutations() {
+        let parse = |text| graphql_parser::parse_query(text).expect("query is valid");
+        assert!(is_mutation(&parse(
+            "mutation { mint(to: \"0x01\") { id } }"
+        )));
+        assert!(!is_mutation(&parse("{ tokens { id } }")));
+        assert!(!is_mutation(&parse("subscription { tokens { id } }")));
+        assert!(!is_mutation(&parse(
+            "mutation A { mint(to: \"0x01\") { id } } query B { tokens { id } }"
+        )));
+    }
+
+    #[test]
+    fn converts_responses() {
+        let result = query_result(serde_json::json!({
+            "data": { "mint": { "id": "1", "amount": 5, "big": 9007199254740993u64 } },
+            "errors": [ { "message": "not enough gas", "path": ["mint"] } ]
+        }));
+        let mut mint = BTreeMap::new();
+        mint.insert("id".to_owned(), q::Value::String("1".to_owned()));
+        mint.insert("amount".to_owned(), q::Value::Int(5.into()));
+        mint.insert(
+            "big".to_owned(),
+            q::Value::Float(9007199254740993u64 as f64),
+        );
+        let mut data = BTreeMap::new();
+        data.insert("mint".to_owned(), q::Value::Object(mint));
+        assert_eq!(Some(q::Value::Object(data)), result.data);
+        assert_eq!(
+            "not enough gas",
+            result.errors.expect("errors are kept")[0].to_string()
+        );
+    }
+}
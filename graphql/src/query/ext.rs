@@ -59,6 +59,9 @@ impl ValueExt for q::Value {
 pub enum BlockConstraint {
     Hash(H256),
     Number(BlockNumber),
+    /// The latest block, which has to be at least this block number;
+    /// queries with this constraint wait for the deployment to get there
+    Min(BlockNumber),
     Latest,
 }
 
@@ -68,11 +71,30 @@ impl Default for BlockConstraint {
     }
 }
 
+/// Whether a query should return data for a subgraph that has skipped
+/// over handlers that failed with non-fatal errors
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ErrorPolicy {
+    Allow,
+    Deny,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::Deny
+    }
+}
+
 pub trait FieldExt {
     fn block_constraint<'a>(
         &self,
         vars: &HashMap<q::Name, q::Value>,
     ) -> Result<BlockConstraint, QueryExecutionError>;
+
+    fn subgraph_error_policy(
+        &self,
+        vars: &HashMap<q::Name, q::Value>,
+    ) -> Result<ErrorPolicy, QueryExecutionError>;
 }
 
 impl FieldExt for q::Field {
@@ -118,6 +140,16 @@ impl FieldExt for q::Field {
                                 .map_err(|_| invalid_argument("block.number", self, number_value))
                         })
                         .map(|number| BlockConstraint::Number(number))
+                } else if let Some(number_value) = map.get("number_gte") {
+                    let number_value = number_value.lookup(vars, self.position)?;
+                    TryFromValue::try_from_value(number_value)
+                        .map_err(|_| invalid_argument("block.number_gte", self, number_value))
+                        .and_then(|number: u64| {
+                            TryFrom::try_from(number).map_err(|_| {
+                                invalid_argument("block.number_gte", self, number_value)
+                            })
+                        })
+                        .map(|number| BlockConstraint::Min(number))
                 } else {
                     Err(invalid_argument("block", self, value))
                 }
@@ -128,4 +160,27 @@ impl FieldExt for q::Field {
             Ok(BlockConstraint::Latest)
         }
     }
+
+    fn subgraph_error_policy(
+        &self,
+        vars: &HashMap<q::Name, q::Value>,
+    ) -> Result<ErrorPolicy, QueryExecutionError> {
+        let value = self
+            .arguments
+            .iter()
+            .find(|(name, _)| name == "subgraphError")
+            .map(|(_, value)| value);
+        match value {
+            Some(value) => match value.lookup(vars, self.position)? {
+                q::Value::Enum(policy) if policy == "allow" => Ok(ErrorPolicy::Allow),
+                q::Value::Enum(policy) if policy == "deny" => Ok(ErrorPolicy::Deny),
+                value => Err(QueryExecutionError::InvalidArgumentError(
+                    self.position.clone(),
+                    "subgraphError".to_owned(),
+                    value.clone(),
+                )),
+            },
+            None => Ok(ErrorPolicy::Deny),
+        }
+    }
 }
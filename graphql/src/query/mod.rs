@@ -82,7 +82,18 @@ where
     let start = Instant::now();
     let result = execute_root_selection_set(&ctx, selection_set, query_type, block_ptr);
     let elapsed = start.elapsed();
-    options.load_manager.add_query(query.shape_hash, elapsed);
+    let cached = match ctx.cache_status.load() {
+        CacheStatus::Hit | CacheStatus::Shared => true,
+        CacheStatus::Insert | CacheStatus::Miss => false,
+    };
+    options.load_manager.add_query(
+        &query.schema.id,
+        query.shape_hash,
+        &query.query_text,
+        elapsed,
+        !result.has_errors(),
+        cached,
+    );
     if *graph::log::LOG_GQL_TIMING {
         info!(
             query_logger,
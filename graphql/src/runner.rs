@@ -6,16 +6,19 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use crate::mutations::{is_mutation, MutationEndpoint, MutationProxy};
 use crate::prelude::{
     object, object_value, QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions,
 };
 use crate::query::execute_query;
+use crate::query::ext::BlockConstraint;
 use crate::subscription::execute_prepared_subscription;
 use graph::data::graphql::effort::LoadManager;
 use graph::prelude::{
-    async_trait, o, EthereumBlockPointer, GraphQlRunner as GraphQlRunnerTrait, Logger, Query,
-    QueryExecutionError, QueryResult, Store, StoreError, SubgraphDeploymentId,
-    SubgraphDeploymentStore, Subscription, SubscriptionError, SubscriptionResultFuture,
+    async_trait, o, query_hash, tokio, warn, BlockNumber, EthereumBlockPointer,
+    GraphQlRunner as GraphQlRunnerTrait, IndexingFreshness, Logger, Query, QueryExecutionError,
+    QueryResult, Store, StoreError, SubgraphDeploymentId, SubgraphDeploymentStore, Subscription,
+    SubscriptionError, SubscriptionResultFuture,
 };
 
 use lazy_static::lazy_static;
@@ -25,6 +28,7 @@ pub struct GraphQlRunner<S> {
     logger: Logger,
     store: Arc<S>,
     load_manager: Arc<LoadManager>,
+    mutation_proxy: MutationProxy,
 }
 
 lazy_static! {
@@ -48,8 +52,29 @@ lazy_static! {
         .map(|s| u32::from_str(&s)
             .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_FIRST")))
         .unwrap_or(1000);
+    static ref GRAPHQL_FRESHNESS_WARNING_BLOCKS: Option<u64> =
+        env::var("GRAPH_GRAPHQL_FRESHNESS_WARNING_BLOCKS")
+            .ok()
+            .map(|s| u64::from_str(&s).unwrap_or_else(|_| {
+                panic!("failed to parse env var GRAPH_GRAPHQL_FRESHNESS_WARNING_BLOCKS")
+            }));
+    static ref GRAPHQL_BLOCK_WAIT_TIMEOUT: Duration = env::var("GRAPH_GRAPHQL_BLOCK_WAIT_TIMEOUT")
+        .ok()
+        .map(
+            |s| Duration::from_secs(u64::from_str(&s).unwrap_or_else(|_| {
+                panic!("failed to parse env var GRAPH_GRAPHQL_BLOCK_WAIT_TIMEOUT")
+            }))
+        )
+        .unwrap_or(Duration::from_secs(10));
 }
 
+/// How long mutations may take if `GRAPH_GRAPHQL_QUERY_TIMEOUT` is not set
+const MUTATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often queries with a `number_gte` block constraint check whether the
+/// deployment has reached that block
+const BLOCK_WAIT_INTERVAL: Duration = Duration::from_millis(250);
+
 impl<S> GraphQlRunner<S>
 where
     S: Store + SubgraphDeploymentStore,
@@ -57,13 +82,21 @@ where
     /// Creates a new query runner.
     pub fn new(logger: &Logger, store: Arc<S>, load_manager: Arc<LoadManager>) -> Self {
         let logger = logger.new(o!("component" => "GraphQlRunner"));
+        let mutation_proxy = MutationProxy::new(&logger, vec![]);
         GraphQlRunner {
             logger,
             store,
             load_manager,
+            mutation_proxy,
         }
     }
 
+    /// Forward mutations against the deployments of `endpoints` to them
+    pub fn with_mutation_endpoints(mut self, endpoints: Vec<MutationEndpoint>) -> Self {
+        self.mutation_proxy = MutationProxy::new(&self.logger, endpoints);
+        self
+    }
+
     /// Create a JSON value that contains the block information for our
     /// response
     #[allow(dead_code)]
@@ -107,7 +140,104 @@ where
         Ok(exts)
     }
 
-    fn execute(
+    /// Reject `query` if its deployment is in allowlist mode and the
+    /// query is not on the allowlist
+    fn check_allowlist(&self, query: &Query) -> Result<(), QueryExecutionError> {
+        let allowlist = self
+            .store
+            .query_allowlist(&query.schema.id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        match allowlist {
+            Some(allowlist) => {
+                let hash = query_hash(&query.document);
+                if allowlist.contains(&hash) {
+                    Ok(())
+                } else {
+                    Err(QueryExecutionError::QueryNotAllowed(
+                        query.schema.id.to_string(),
+                        hash,
+                    ))
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Add a warning to the extensions of `result` if `subgraph_id` is
+    /// more than `GRAPH_GRAPHQL_FRESHNESS_WARNING_BLOCKS` blocks behind the
+    /// head of its chain, so that clients can tell that the data is stale
+    fn add_freshness_warning(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        result: Arc<QueryResult>,
+    ) -> Arc<QueryResult> {
+        let threshold = match *GRAPHQL_FRESHNESS_WARNING_BLOCKS {
+            Some(threshold) if !subgraph_id.is_meta() => threshold,
+            _ => return result,
+        };
+        let freshness = match self.store.indexing_freshness(subgraph_id) {
+            Ok(Some(freshness)) if freshness.blocks_behind() > threshold => freshness,
+            Ok(_) => return result,
+            Err(e) => {
+                warn!(self.logger, "Failed to check how far behind the subgraph is";
+                      "subgraph_id" => subgraph_id.to_string(),
+                      "error" => e.to_string());
+                return result;
+            }
+        };
+
+        let mut result = result.as_ref().clone();
+        let mut extensions = match result.extensions.take() {
+            Some(q::Value::Object(extensions)) => extensions,
+            _ => BTreeMap::new(),
+        };
+        extensions.insert(
+            "warnings".to_owned(),
+            q::Value::List(vec![freshness_warning(subgraph_id, &freshness)]),
+        );
+        Arc::new(result.with_extensions(extensions))
+    }
+
+    /// Wait until `subgraph_id` has indexed the block `number`, but no
+    /// longer than `GRAPH_GRAPHQL_BLOCK_WAIT_TIMEOUT`. Whether it got there
+    /// is checked again when the query is resolved
+    async fn wait_for_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        number: BlockNumber,
+    ) -> Result<(), QueryExecutionError> {
+        let deadline = Instant::now() + *GRAPHQL_BLOCK_WAIT_TIMEOUT;
+        loop {
+            let reached = self
+                .store
+                .block_ptr(subgraph_id.clone())
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?
+                .map_or(false, |ptr| ptr.number >= number as u64);
+            if reached || Instant::now() >= deadline {
+                return Ok(());
+            }
+            tokio::time::delay_for(BLOCK_WAIT_INTERVAL).await;
+        }
+    }
+
+    async fn run_mutation(&self, query: Query) -> QueryResult {
+        if let Err(e) = self.check_allowlist(&query) {
+            return e.into();
+        }
+        let block = match self.store.block_ptr(query.schema.id.clone()) {
+            Ok(block) => block,
+            Err(e) => return QueryExecutionError::StoreError(e.into()).into(),
+        };
+        self.mutation_proxy
+            .forward(
+                &query,
+                block,
+                GRAPHQL_QUERY_TIMEOUT.unwrap_or(MUTATION_TIMEOUT),
+            )
+            .await
+    }
+
+    async fn execute(
         &self,
         query: Query,
         max_complexity: Option<u64>,
@@ -115,6 +245,7 @@ where
         max_first: Option<u32>,
     ) -> Result<Arc<QueryResult>, QueryResult> {
         let max_depth = max_depth.unwrap_or(*GRAPHQL_MAX_DEPTH);
+        self.check_allowlist(&query)?;
         let query = crate::execution::Query::new(query, max_complexity, max_depth)?;
         self.load_manager
             .decide(query.shape_hash, query.query_text.as_ref())
@@ -135,23 +266,44 @@ where
             )
         };
 
+        let by_block_constraint = query.block_constraint()?;
+
+        // Give the deployment a chance to catch up with the highest block
+        // that the query needs it to have indexed
+        let min_block = by_block_constraint
+            .keys()
+            .filter_map(|(bc, _)| match bc {
+                BlockConstraint::Min(number) => Some(*number),
+                _ => None,
+            })
+            .max();
+        if let Some(number) = min_block {
+            self.wait_for_block(&query.schema.id, number).await?;
+        }
+
         // Unwrap: There is always at least one block constraint, even if it
         // is an implicit 'BlockContraint::Latest'.
-        let mut by_block_constraint = query.block_constraint()?.into_iter();
-        let (bc, selection_set) = by_block_constraint.next().unwrap();
-        let (resolver, block_ptr) =
-            StoreResolver::at_block(&self.logger, self.store.clone(), bc, &query.schema.id)?;
+        let mut by_block_constraint = by_block_constraint.into_iter();
+        let ((bc, error_policy), selection_set) = by_block_constraint.next().unwrap();
+        let (resolver, block_ptr) = StoreResolver::at_block(
+            &self.logger,
+            self.store.clone(),
+            bc,
+            error_policy,
+            &query.schema.id,
+        )?;
         let mut result = execute(selection_set, block_ptr, resolver);
 
         // We want to optimize for the common case of a single block constraint, where we can avoid
         // cloning the result. If there are multiple constraints we have to clone.
         if by_block_constraint.len() > 0 {
             let mut partial_res = result.as_ref().clone();
-            for (bc, selection_set) in by_block_constraint {
+            for ((bc, error_policy), selection_set) in by_block_constraint {
                 let (resolver, block_ptr) = StoreResolver::at_block(
                     &self.logger,
                     self.store.clone(),
                     bc,
+                    error_policy,
                     &query.schema.id,
                 )?;
                 partial_res.append(execute(selection_set, block_ptr, resolver).as_ref().clone());
@@ -185,11 +337,22 @@ where
         max_depth: Option<u8>,
         max_first: Option<u32>,
     ) -> Arc<QueryResult> {
-        self.execute(query, max_complexity, max_depth, max_first)
-            .unwrap_or_else(|e| Arc::new(e))
+        if is_mutation(&query.document) {
+            return Arc::new(self.run_mutation(query).await);
+        }
+        let subgraph_id = query.schema.id.clone();
+        let result = self
+            .execute(query, max_complexity, max_depth, max_first)
+            .await
+            .unwrap_or_else(|e| Arc::new(e));
+        self.add_freshness_warning(&subgraph_id, result)
     }
 
     fn run_subscription(&self, subscription: Subscription) -> SubscriptionResultFuture {
+        if let Err(e) = self.check_allowlist(&subscription.query) {
+            return Box::new(future::err(e.into()));
+        }
+
         let query = match crate::execution::Query::new(
             subscription.query,
             *GRAPHQL_MAX_COMPLEXITY,
@@ -227,3 +390,55 @@ where
         self.load_manager.clone()
     }
 }
+
+/// The warning that tells clients how far behind the head of its chain the
+/// deployment that answered their query is
+fn freshness_warning(
+    subgraph_id: &SubgraphDeploymentId,
+    freshness: &IndexingFreshness,
+) -> q::Value {
+    object! {
+        kind: "SUBGRAPH_BEHIND",
+        message: format!(
+            "subgraph `{}` is {} blocks behind the head of its chain",
+            subgraph_id,
+            freshness.blocks_behind()
+        ),
+        subgraph: subgraph_id.to_string(),
+        blocksBehind: freshness.blocks_behind() as i32,
+        latestBlock: object! {
+            number: freshness.latest_block.number as i32,
+            hash: freshness.latest_block.hash_hex(),
+            timestamp: freshness.latest_block_timestamp.map(|timestamp| timestamp as i32),
+        },
+        chainHeadBlockNumber: freshness.chain_head_block_number as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::web3::types::H256;
+
+    #[test]
+    fn freshness_warnings() {
+        let subgraph_id = SubgraphDeploymentId::new("QmXYZ").unwrap();
+        let freshness = IndexingFreshness {
+            latest_block: EthereumBlockPointer::from((H256::zero(), 90u64)),
+            latest_block_timestamp: None,
+            chain_head_block_number: 100,
+        };
+        let warning = match freshness_warning(&subgraph_id, &freshness) {
+            q::Value::Object(warning) => warning,
+            warning => panic!("expected an object, got {:?}", warning),
+        };
+        assert_eq!(Some(&q::Value::Int(10.into())), warning.get("blocksBehind"));
+        match warning.get("latestBlock") {
+            Some(q::Value::Object(block)) => {
+                assert_eq!(Some(&q::Value::Int(90.into())), block.get("number"));
+                assert_eq!(Some(&q::Value::Null), block.get("timestamp"));
+            }
+            block => panic!("expected an object, got {:?}", block),
+        }
+    }
+}
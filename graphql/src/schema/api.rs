@@ -1,10 +1,11 @@
 use graphql_parser::schema::{Name, Value, *};
 use graphql_parser::Pos;
 use inflector::Inflector;
+use std::collections::HashSet;
 
 use crate::schema::ast;
 
-use graph::data::graphql::ext::{DirectiveExt, DocumentExt, ValueExt};
+use graph::data::graphql::ext::{DirectiveExt, DocumentExt, FieldExt, ObjectTypeExt, ValueExt};
 use graph::prelude::*;
 
 #[derive(Fail, Debug)]
@@ -17,6 +18,14 @@ pub enum APISchemaError {
 
 const BLOCK_HEIGHT: &str = "Block_height";
 
+const ERROR_POLICY_TYPE: &str = "_SubgraphErrorPolicy_";
+
+/// The type of the `_version` field, which describes the version of an
+/// entity that a query returned
+pub(crate) const VERSION_TYPE: &str = "_Version_";
+
+pub const VERSION_FIELD: &str = "_version";
+
 /// Derives a full-fledged GraphQL API schema from an input schema.
 ///
 /// The input schema should only have type/enum/interface/union definitions
@@ -25,7 +34,12 @@ const BLOCK_HEIGHT: &str = "Block_height";
 /// types.
 pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
     // Refactor: Take `input_schema` by value.
-    let object_types = ast::get_object_type_definitions(input_schema);
+    // The mutations are forwarded to an external service, and the
+    // `Mutation` type is passed through unchanged
+    let object_types: Vec<_> = ast::get_object_type_definitions(input_schema)
+        .into_iter()
+        .filter(|object_type| !object_type.is_mutation_type())
+        .collect();
     let interface_types = ast::get_interface_type_definitions(input_schema);
 
     // Refactor: Don't clone the schema.
@@ -34,9 +48,13 @@ pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
     add_builtin_scalar_types(&mut schema)?;
     add_order_direction_enum(&mut schema);
     add_block_height_type(&mut schema);
+    add_subgraph_error_policy_enum(&mut schema);
     add_types_for_object_types(&mut schema, &object_types)?;
     add_types_for_interface_types(&mut schema, &interface_types)?;
     add_field_arguments(&mut schema, &input_schema)?;
+    add_count_fields(&mut schema);
+    add_version_type(&mut schema)?;
+    add_version_fields(&mut schema, &object_types, &interface_types);
     add_query_type(&mut schema, &object_types, &interface_types)?;
     add_subscription_type(&mut schema, &object_types, &interface_types)?;
     Ok(schema)
@@ -112,9 +130,34 @@ fn add_directives(schema: &mut Document) {
         locations: vec![DirectiveLocation::Object],
     });
 
+    let unindexed = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: "unindexed".to_owned(),
+        arguments: vec![],
+        locations: vec![DirectiveLocation::FieldDefinition],
+    });
+
+    let derived_count = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: "derivedCount".to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "field".to_owned(),
+            value_type: Type::NamedType("String".to_owned()),
+            default_value: None,
+            directives: vec![],
+        }],
+        locations: vec![DirectiveLocation::FieldDefinition],
+    });
+
     schema.definitions.push(entity);
     schema.definitions.push(derived_from);
     schema.definitions.push(subgraph_id);
+    schema.definitions.push(unindexed);
+    schema.definitions.push(derived_count);
 }
 
 /// Adds a global `OrderDirection` type to the schema.
@@ -163,6 +206,46 @@ fn add_block_height_type(schema: &mut Document) {
                 default_value: None,
                 directives: vec![],
             },
+            InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "number_gte".to_owned(),
+                value_type: Type::NamedType("Int".to_owned()),
+                default_value: None,
+                directives: vec![],
+            },
+        ],
+    });
+    let def = Definition::TypeDefinition(typedef);
+    schema.definitions.push(def);
+}
+
+/// Adds a global `_SubgraphErrorPolicy_` enum to the schema. The
+/// `subgraphError` argument accepts values of this type
+fn add_subgraph_error_policy_enum(schema: &mut Document) {
+    let typedef = TypeDefinition::Enum(EnumType {
+        position: Pos::default(),
+        description: None,
+        name: ERROR_POLICY_TYPE.to_string(),
+        directives: vec![],
+        values: vec![
+            EnumValue {
+                position: Pos::default(),
+                description: Some(
+                    "Data will be returned even if the subgraph has indexing errors".to_owned(),
+                ),
+                name: "allow".to_owned(),
+                directives: vec![],
+            },
+            EnumValue {
+                position: Pos::default(),
+                description: Some(
+                    "If the subgraph has indexing errors, data will be omitted. The default."
+                        .to_owned(),
+                ),
+                name: "deny".to_owned(),
+                directives: vec![],
+            },
         ],
     });
     let def = Definition::TypeDefinition(typedef);
@@ -192,7 +275,8 @@ fn add_types_for_interface_types(
     Ok(())
 }
 
-/// Adds a `<type_name>_orderBy` enum type for the given fields to the schema.
+/// Adds a `<type_name>_orderBy` enum type for the given fields to the
+/// schema. Fields marked `@unindexed` can not be sorted by
 fn add_order_by_type(
     schema: &mut Document,
     type_name: &Name,
@@ -209,6 +293,7 @@ fn add_order_by_type(
                 directives: vec![],
                 values: fields
                     .iter()
+                    .filter(|field| !field.is_unindexed())
                     .map(|field| &field.name)
                     .map(|name| EnumValue {
                         position: Pos::default(),
@@ -260,13 +345,14 @@ fn add_filter_type(
     Ok(())
 }
 
-/// Generates `*_filter` input values for the given set of fields.
+/// Generates `*_filter` input values for the given set of fields. Fields
+/// marked `@unindexed` can not be filtered by
 fn field_input_values(
     schema: &Document,
     fields: &[Field],
 ) -> Result<Vec<InputValue>, APISchemaError> {
     let mut input_values = vec![];
-    for field in fields {
+    for field in fields.iter().filter(|field| !field.is_unindexed()) {
         input_values.extend(field_filter_input_values(
             schema,
             &field,
@@ -345,6 +431,10 @@ fn field_scalar_filter_input_values(
             "not_starts_with",
             "ends_with",
             "not_ends_with",
+            "contains_nocase",
+            "starts_with_nocase",
+            "ends_with_nocase",
+            "matches",
         ],
         _ => vec!["", "not"],
     }
@@ -529,6 +619,7 @@ fn query_field_for_fulltext(fulltext: &Directive) -> Option<Field> {
             default_value: None,
             directives: vec![],
         },
+        subgraph_error_argument(),
     ];
     Some(Field {
         position: Pos::default(),
@@ -589,11 +680,27 @@ fn block_argument() -> InputValue {
     }
 }
 
+fn subgraph_error_argument() -> InputValue {
+    InputValue {
+        position: Pos::default(),
+        description: Some(
+            "Set to `allow` to receive data even if the subgraph has skipped over errors \
+             while syncing."
+                .to_owned(),
+        ),
+        name: "subgraphError".to_string(),
+        value_type: Type::NonNullType(Box::new(Type::NamedType(ERROR_POLICY_TYPE.to_string()))),
+        default_value: Some(Value::Enum("deny".to_string())),
+        directives: vec![],
+    }
+}
+
 /// Generates `Query` fields for the given type name (e.g. `users` and `user`).
 fn query_fields_for_type(schema: &Document, type_name: &Name) -> Vec<Field> {
     let input_objects = ast::get_input_object_definitions(schema);
     let mut collection_arguments = collection_arguments_for_named_type(&input_objects, type_name);
     collection_arguments.push(block_argument());
+    collection_arguments.push(subgraph_error_argument());
 
     vec![
         Field {
@@ -610,6 +717,7 @@ fn query_fields_for_type(schema: &Document, type_name: &Name) -> Vec<Field> {
                     directives: vec![],
                 },
                 block_argument(),
+                subgraph_error_argument(),
             ],
             field_type: Type::NamedType(type_name.to_owned()),
             directives: vec![],
@@ -678,7 +786,10 @@ fn add_field_arguments(
     // Refactor: Remove the `input_schema` argument and do a mutable iteration
     // over the definitions in `schema`. Also the duplication between this and
     // the loop for interfaces below.
-    for input_object_type in ast::get_object_type_definitions(input_schema) {
+    for input_object_type in ast::get_object_type_definitions(input_schema)
+        .into_iter()
+        .filter(|object_type| !object_type.is_mutation_type())
+    {
         for input_field in &input_object_type.fields {
             if let Some(input_reference_type) =
                 ast::get_referenced_entity_type(input_schema, &input_field)
@@ -748,10 +859,119 @@ fn add_field_arguments(
     Ok(())
 }
 
+/// Adds a field `<field>Count: Int!` to object and interface types for each
+/// of their `@derivedFrom` list fields, unless the type already has a field
+/// with that name. Queries resolve these fields by counting the children in
+/// the store rather than loading them
+fn add_count_fields(schema: &mut Document) {
+    for definition in schema.definitions.iter_mut() {
+        let fields = match definition {
+            Definition::TypeDefinition(TypeDefinition::Object(t)) => &mut t.fields,
+            Definition::TypeDefinition(TypeDefinition::Interface(t)) => &mut t.fields,
+            _ => continue,
+        };
+        let count_fields: Vec<_> = fields
+            .iter()
+            .filter(|field| {
+                ast::is_list_or_non_null_list_field(field)
+                    && ast::get_derived_from_directive(field).is_some()
+            })
+            .map(|field| count_field(&field.name))
+            .filter(|count| fields.iter().all(|field| field.name != count.name))
+            .collect();
+        fields.extend(count_fields);
+    }
+}
+
+fn count_field(field_name: &Name) -> Field {
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}Count", field_name),
+        arguments: vec![],
+        field_type: Type::NonNullType(Box::new(Type::NamedType("Int".to_owned()))),
+        directives: vec![Directive {
+            position: Pos::default(),
+            name: "derivedCount".to_owned(),
+            arguments: vec![("field".to_owned(), Value::String(field_name.to_owned()))],
+        }],
+    }
+}
+
+/// Adds the `_Version_` type, which holds the number of the block in
+/// which a version of an entity was written
+fn add_version_type(schema: &mut Document) -> Result<(), APISchemaError> {
+    if ast::get_named_type(schema, &VERSION_TYPE.to_owned()).is_some() {
+        return Err(APISchemaError::TypeExists(VERSION_TYPE.to_owned()));
+    }
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: VERSION_TYPE.to_owned(),
+        implements_interfaces: vec![],
+        directives: vec![],
+        fields: vec![Field {
+            position: Pos::default(),
+            description: Some(
+                "The number of the block in which this version was written".to_owned(),
+            ),
+            name: "block".to_owned(),
+            arguments: vec![],
+            field_type: Type::NonNullType(Box::new(Type::NamedType("Int".to_owned()))),
+            directives: vec![],
+        }],
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
+/// Adds a field `_version: _Version_` to entity and interface types, unless
+/// they already have a field with that name. The field is `null` when the
+/// store does not keep track of versions
+fn add_version_fields(
+    schema: &mut Document,
+    object_types: &[&ObjectType],
+    interface_types: &[&InterfaceType],
+) {
+    let type_names: HashSet<_> = object_types
+        .iter()
+        .map(|object_type| &object_type.name)
+        .chain(interface_types.iter().map(|interface| &interface.name))
+        .cloned()
+        .collect();
+    for definition in schema.definitions.iter_mut() {
+        let fields = match definition {
+            Definition::TypeDefinition(TypeDefinition::Object(t))
+                if type_names.contains(&t.name) =>
+            {
+                &mut t.fields
+            }
+            Definition::TypeDefinition(TypeDefinition::Interface(t))
+                if type_names.contains(&t.name) =>
+            {
+                &mut t.fields
+            }
+            _ => continue,
+        };
+        if fields.iter().all(|field| field.name != VERSION_FIELD) {
+            fields.push(Field {
+                position: Pos::default(),
+                description: None,
+                name: VERSION_FIELD.to_owned(),
+                arguments: vec![],
+                field_type: Type::NamedType(VERSION_TYPE.to_owned()),
+                directives: vec![],
+            });
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use graphql_parser::schema::*;
 
+    use graph::data::graphql::ext::FieldExt;
+
     use super::api_schema;
     use crate::schema::ast;
 
@@ -818,6 +1038,133 @@ mod tests {
         assert_eq!(values, [&"id".to_string(), &"name".to_string()]);
     }
 
+    #[test]
+    fn api_schema_skips_unindexed_fields() {
+        let input_schema =
+            parse_schema("type User { id: ID!, name: String!, bio: String @unindexed }")
+                .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derived API schema");
+
+        let order_by = match ast::get_named_type(&schema, &"User_orderBy".to_string()) {
+            Some(TypeDefinition::Enum(t)) => t,
+            _ => panic!("User_orderBy type is missing or not an enum"),
+        };
+        assert!(order_by.values.iter().all(|value| value.name != "bio"));
+
+        let filter = match ast::get_named_type(&schema, &"User_filter".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("User_filter type is missing or not an input object"),
+        };
+        assert!(filter.fields.iter().any(|field| field.name == "name"));
+        assert!(filter
+            .fields
+            .iter()
+            .all(|field| !field.name.starts_with("bio")));
+    }
+
+    #[test]
+    fn api_schema_adds_count_fields_for_derived_lists() {
+        let input_schema = parse_schema(
+            r#"
+              type Pet {
+                  id: ID!
+                  owner: User!
+                  sitters: [User!]!
+              }
+
+              type User {
+                  id: ID!
+                  pets: [Pet!]! @derivedFrom(field: "owner")
+                  sittedPets: [Pet!]! @derivedFrom(field: "sitters")
+                  sittedPetsCount: Int!
+                  favoritePet: Pet @derivedFrom(field: "owner")
+              }
+            "#,
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let user = match ast::get_named_type(&schema, &"User".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("User type is missing or not an object"),
+        };
+        let pets_count = user
+            .fields
+            .iter()
+            .find(|field| field.name == "petsCount")
+            .expect("petsCount field is missing");
+        assert_eq!(
+            pets_count.field_type,
+            Type::NonNullType(Box::new(Type::NamedType("Int".to_owned())))
+        );
+        assert_eq!(Some("pets"), pets_count.derived_count_field());
+
+        // Existing fields are not replaced, and single objects are not counted
+        let sitted_pets_count = user
+            .fields
+            .iter()
+            .find(|field| field.name == "sittedPetsCount")
+            .expect("sittedPetsCount field is missing");
+        assert_eq!(None, sitted_pets_count.derived_count_field());
+        assert!(user
+            .fields
+            .iter()
+            .all(|field| field.name != "favoritePetCount"));
+
+        // Count fields can not be used for filtering
+        let filter = match ast::get_named_type(&schema, &"User_filter".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("User_filter type is missing or not an input object"),
+        };
+        assert!(filter
+            .fields
+            .iter()
+            .all(|field| !field.name.starts_with("petsCount")));
+    }
+
+    #[test]
+    fn api_schema_adds_version_fields() {
+        let input_schema = parse_schema(
+            r#"
+              interface Named {
+                  name: String!
+              }
+
+              type User implements Named @entity {
+                  id: ID!
+                  name: String!
+              }
+            "#,
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let version_type = Type::NamedType("_Version_".to_owned());
+        match ast::get_named_type(&schema, &"User".to_string()) {
+            Some(TypeDefinition::Object(t)) => assert!(t
+                .fields
+                .iter()
+                .any(|field| field.name == "_version" && field.field_type == version_type)),
+            _ => panic!("User type is missing or not an object"),
+        };
+        match ast::get_named_type(&schema, &"Named".to_string()) {
+            Some(TypeDefinition::Interface(t)) => assert!(t
+                .fields
+                .iter()
+                .any(|field| field.name == "_version" && field.field_type == version_type)),
+            _ => panic!("Named type is missing or not an interface"),
+        };
+        match ast::get_named_type(&schema, &"_Version_".to_string()) {
+            Some(TypeDefinition::Object(t)) => {
+                assert_eq!(
+                    vec!["block"],
+                    t.fields.iter().map(|f| &f.name).collect::<Vec<_>>()
+                )
+            }
+            _ => panic!("_Version_ type is missing or not an object"),
+        };
+    }
+
     #[test]
     fn api_schema_contains_object_type_filter_enum() {
         let input_schema = parse_schema(
@@ -881,6 +1228,10 @@ mod tests {
                 "name_not_starts_with",
                 "name_ends_with",
                 "name_not_ends_with",
+                "name_contains_nocase",
+                "name_starts_with_nocase",
+                "name_ends_with_nocase",
+                "name_matches",
                 "favoritePetNames",
                 "favoritePetNames_not",
                 "favoritePetNames_contains",
@@ -903,6 +1254,10 @@ mod tests {
                 "favoritePet_not_starts_with",
                 "favoritePet_ends_with",
                 "favoritePet_not_ends_with",
+                "favoritePet_contains_nocase",
+                "favoritePet_starts_with_nocase",
+                "favoritePet_ends_with_nocase",
+                "favoritePet_matches",
             ]
             .iter()
             .map(|name| name.to_string())
@@ -938,7 +1293,11 @@ mod tests {
                 .iter()
                 .map(|input_value| input_value.name.to_owned())
                 .collect::<Vec<String>>(),
-            vec!["id".to_string(), "block".to_string()],
+            vec![
+                "id".to_string(),
+                "block".to_string(),
+                "subgraphError".to_string()
+            ],
         );
 
         let user_plural_field = match query_type {
@@ -966,7 +1325,8 @@ mod tests {
                 "orderBy",
                 "orderDirection",
                 "where",
-                "block"
+                "block",
+                "subgraphError",
             ]
             .iter()
             .map(|name| name.to_string())
@@ -1029,7 +1389,11 @@ mod tests {
                 .iter()
                 .map(|input_value| input_value.name.to_owned())
                 .collect::<Vec<String>>(),
-            vec!["id".to_string(), "block".to_string()],
+            vec![
+                "id".to_string(),
+                "block".to_string(),
+                "subgraphError".to_string()
+            ],
         );
 
         let plural_field = match query_type {
@@ -1057,7 +1421,8 @@ mod tests {
                 "orderBy",
                 "orderDirection",
                 "where",
-                "block"
+                "block",
+                "subgraphError",
             ]
             .iter()
             .map(|name| name.to_string())
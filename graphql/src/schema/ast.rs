@@ -23,6 +23,10 @@ pub(crate) enum FilterOp {
     NotStartsWith,
     EndsWith,
     NotEndsWith,
+    ContainsNoCase,
+    StartsWithNoCase,
+    EndsWithNoCase,
+    Matches,
     Equal,
 }
 
@@ -36,6 +40,12 @@ pub(crate) fn parse_field_as_filter(key: &Name) -> (Name, FilterOp) {
         k if k.ends_with("_lte") => ("_lte", FilterOp::LessOrEqual),
         k if k.ends_with("_not_in") => ("_not_in", FilterOp::NotIn),
         k if k.ends_with("_in") => ("_in", FilterOp::In),
+        k if k.ends_with("_contains_nocase") => ("_contains_nocase", FilterOp::ContainsNoCase),
+        k if k.ends_with("_starts_with_nocase") => {
+            ("_starts_with_nocase", FilterOp::StartsWithNoCase)
+        }
+        k if k.ends_with("_ends_with_nocase") => ("_ends_with_nocase", FilterOp::EndsWithNoCase),
+        k if k.ends_with("_matches") => ("_matches", FilterOp::Matches),
         k if k.ends_with("_not_contains") => ("_not_contains", FilterOp::NotContains),
         k if k.ends_with("_contains") => ("_contains", FilterOp::Contains),
         k if k.ends_with("_not_starts_with") => ("_not_starts_with", FilterOp::NotStartsWith),
@@ -8,7 +8,7 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
 use std::time::Instant;
 
-use graph::data::graphql::ext::ObjectTypeExt;
+use graph::data::graphql::ext::{FieldExt, ObjectTypeExt};
 use graph::prelude::{
     BlockNumber, ChildMultiplicity, EntityCollection, EntityFilter, EntityLink, EntityOrder,
     EntityWindow, Logger, ParentLink, QueryExecutionError, QueryStore, Schema, Value as StoreValue,
@@ -17,8 +17,9 @@ use graph::prelude::{
 
 use crate::execution::{ExecutionContext, ObjectOrInterface, Resolver};
 use crate::query::ast as qast;
+use crate::schema::api::{VERSION_FIELD, VERSION_TYPE};
 use crate::schema::ast as sast;
-use crate::store::{build_query, StoreResolver};
+use crate::store::{build_query, parse_subgraph_id, StoreResolver};
 
 lazy_static! {
     static ref ARG_FIRST: String = String::from("first");
@@ -595,6 +596,25 @@ fn execute_selection_set<'a>(
                 .expect("collect_fields does not create type conditions for nonexistent types");
 
             if let Some(ref field) = concrete_type.field(&fields[0].name) {
+                if is_version_field(field) {
+                    execute_version(&concrete_type, &mut parents, response_key);
+                    continue;
+                }
+
+                if let Some(counted_field) = field.derived_count_field() {
+                    if let Err(mut e) = execute_count(
+                        resolver,
+                        ctx,
+                        &concrete_type,
+                        &mut parents,
+                        counted_field,
+                        &field.name,
+                    ) {
+                        errors.append(&mut e);
+                    }
+                    continue;
+                }
+
                 let child_type =
                     object_or_interface_from_type(&ctx.query.schema.document, &field.field_type)
                         .expect("we only collect fields that are objects or interfaces");
@@ -694,12 +714,21 @@ fn collect_fields<'a>(
                 .unwrap_or(false)
         }
 
+        fn is_count_field(object_type: &ObjectOrInterface, field: &q::Field) -> bool {
+            object_type
+                .field(&field.name)
+                .map(|field_def| field_def.derived_count_field().is_some())
+                .unwrap_or(false)
+        }
+
         for selection in selections {
             match selection {
                 q::Selection::Field(ref field) => {
-                    // Only consider fields that point to objects or interfaces, and
-                    // ignore nonexistent fields
-                    if is_reference_field(&ctx.query.schema.document, object_type, field) {
+                    // Only consider fields that point to objects or interfaces,
+                    // or that count children, and ignore nonexistent fields
+                    if is_reference_field(&ctx.query.schema.document, object_type, field)
+                        || is_count_field(object_type, field)
+                    {
                         let response_key = qast::get_response_key(field);
 
                         // Create a field group for this response key and add the field
@@ -844,6 +873,88 @@ fn execute_field(
     .map_err(|e| vec![e])
 }
 
+/// Resolve the `<field>Count` field `count_field` by counting the children
+/// that the `@derivedFrom` field `counted_field` would return for each of
+/// `parents`, without loading them. The count is stored in each parent under
+/// the name of `count_field`, just like a scalar attribute
+fn execute_count(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    object_type: &ObjectOrInterface<'_>,
+    parents: &mut Vec<Node>,
+    counted_field: &str,
+    count_field: &s::Name,
+) -> Result<(), Vec<QueryExecutionError>> {
+    let field = object_type
+        .field(&counted_field.to_owned())
+        .expect("the API schema only adds count fields for existing fields");
+    let child_type = object_or_interface_from_type(&ctx.query.schema.document, &field.field_type)
+        .expect("derived fields are objects or interfaces");
+    let join = Join::new(
+        ctx.query.schema.as_ref(),
+        object_type,
+        &child_type,
+        &field.name,
+    );
+
+    let windows = join.windows(parents, ChildMultiplicity::Many);
+    let counts = if windows.is_empty() {
+        BTreeMap::default()
+    } else {
+        let subgraph_id = parse_subgraph_id(*object_type).map_err(|e| vec![e])?;
+        resolver
+            .store
+            .count_children(&subgraph_id, windows, resolver.block)
+            .map_err(|e| vec![e])?
+    };
+
+    for parent in parents.iter_mut().filter(|parent| {
+        join.conds
+            .iter()
+            .any(|cond| cond.parent_type == parent.typename())
+    }) {
+        let count = parent
+            .id()
+            .ok()
+            .and_then(|id| counts.get(&id).cloned())
+            .unwrap_or(0);
+        // GraphQL's `Int` only has 32 bits
+        let count = count.min(std::i32::MAX as i64) as i32;
+        parent.entity.insert(
+            count_field.to_owned(),
+            q::Value::Int(q::Number::from(count)),
+        );
+    }
+    Ok(())
+}
+
+fn is_version_field(field: &s::Field) -> bool {
+    field.name == VERSION_FIELD && field.field_type == s::Type::NamedType(VERSION_TYPE.to_owned())
+}
+
+/// Resolve the `_version` field from the block at which the store wrote the
+/// version of each of `parents`, which it puts into the attribute
+/// `g$version_block`. Parents without that attribute get a `null` version
+fn execute_version(
+    object_type: &ObjectOrInterface<'_>,
+    parents: &mut Vec<Node>,
+    response_key: &str,
+) {
+    for parent in parents.iter_mut().filter(|parent| match object_type {
+        ObjectOrInterface::Object(object_type) => parent.typename() == object_type.name,
+        ObjectOrInterface::Interface(_) => true,
+    }) {
+        let version = parent.get("g$version_block").cloned().map(|block| {
+            let mut version = BTreeMap::new();
+            version.insert("block".to_owned(), block);
+            Rc::new(Node::from(version))
+        });
+        parent
+            .children
+            .insert(response_key.to_owned(), version.into_iter().collect());
+    }
+}
+
 /// Query child entities for `parents` from the store. The `join` indicates
 /// in which child field to look for the parent's id/join field. When
 /// `is_single` is `true`, there is at most one child per parent.
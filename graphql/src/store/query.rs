@@ -1,12 +1,27 @@
 use graphql_parser::{query as q, query::Name, schema as s, schema::ObjectType};
+use lazy_static::lazy_static;
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::env;
 use std::mem::discriminant;
+use std::str::FromStr;
 
+use graph::data::graphql::ext::FieldExt;
 use graph::prelude::*;
 
 use crate::execution::ObjectOrInterface;
 use crate::schema::ast as sast;
 
+lazy_static! {
+    /// The longest regular expression that `_matches` filters accept, in
+    /// characters, so that clients can not make the database spend a long
+    /// time on a single filter
+    static ref GRAPHQL_MAX_REGEX_LENGTH: usize = env::var("GRAPH_GRAPHQL_MAX_REGEX_LENGTH")
+        .ok()
+        .map(|s| usize::from_str(&s)
+            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_REGEX_LENGTH")))
+        .unwrap_or(256);
+}
+
 #[derive(Debug)]
 enum OrderDirection {
     Ascending,
@@ -154,6 +169,13 @@ fn build_filter_from_object(
                     )
                 })?;
 
+                if field.is_unindexed() {
+                    return Err(QueryExecutionError::UnindexedFieldError(
+                        entity.name().to_owned(),
+                        field_name,
+                    ));
+                }
+
                 let ty = &field.field_type;
                 let store_value = Value::from_query_value(value, &ty)?;
 
@@ -171,6 +193,20 @@ fn build_filter_from_object(
                     NotStartsWith => EntityFilter::NotStartsWith(field_name, store_value),
                     EndsWith => EntityFilter::EndsWith(field_name, store_value),
                     NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
+                    ContainsNoCase => EntityFilter::ContainsNoCase(field_name, store_value),
+                    StartsWithNoCase => EntityFilter::StartsWithNoCase(field_name, store_value),
+                    EndsWithNoCase => EntityFilter::EndsWithNoCase(field_name, store_value),
+                    Matches => {
+                        if let Value::String(regex) = &store_value {
+                            if regex.chars().count() > *GRAPHQL_MAX_REGEX_LENGTH {
+                                return Err(QueryExecutionError::RegexTooLong(
+                                    field_name,
+                                    *GRAPHQL_MAX_REGEX_LENGTH,
+                                ));
+                            }
+                        }
+                        EntityFilter::Matches(field_name, store_value)
+                    }
                     Equal => EntityFilter::Equal(field_name, store_value),
                 })
             })
@@ -216,6 +252,12 @@ fn build_order_by(
             let field = sast::get_field(entity, &name).ok_or_else(|| {
                 QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.clone())
             })?;
+            if field.is_unindexed() {
+                return Err(QueryExecutionError::UnindexedFieldError(
+                    entity.name().to_owned(),
+                    name.clone(),
+                ));
+            }
             sast::get_field_value_type(&field.field_type)
                 .map(|value_type| Some((name.to_owned(), value_type)))
                 .map_err(|_| {
@@ -712,4 +754,47 @@ mod tests {
             )]))
         )
     }
+
+    #[test]
+    fn build_query_yields_nocase_and_regex_filters() {
+        let whre = "where".to_string();
+        let object = ObjectType {
+            fields: vec![field("name", Type::NamedType("string".to_owned()))],
+            ..default_object()
+        };
+        let filter = |key: &str, value: &str| {
+            let mut args = default_arguments();
+            args.insert(
+                &whre,
+                q::Value::Object(BTreeMap::from_iter(vec![(
+                    key.to_string(),
+                    q::Value::String(value.to_string()),
+                )])),
+            );
+            build_query(
+                &object,
+                BLOCK_NUMBER_MAX,
+                &args,
+                &BTreeMap::new(),
+                std::u32::MAX,
+            )
+            .map(|query| query.filter)
+        };
+
+        assert_eq!(
+            filter("name_starts_with_nocase", "hel").unwrap(),
+            Some(EntityFilter::And(vec![EntityFilter::StartsWithNoCase(
+                "name".to_string(),
+                Value::String("hel".to_string()),
+            )]))
+        );
+        assert_eq!(
+            filter("name_matches", "^h.*o$").unwrap(),
+            Some(EntityFilter::And(vec![EntityFilter::Matches(
+                "name".to_string(),
+                Value::String("^h.*o$".to_string()),
+            )]))
+        );
+        assert!(filter("name_matches", &"a".repeat(*GRAPHQL_MAX_REGEX_LENGTH + 1)).is_err());
+    }
 }
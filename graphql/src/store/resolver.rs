@@ -7,7 +7,7 @@ use graph::components::store::*;
 use graph::prelude::*;
 
 use crate::prelude::*;
-use crate::query::ext::BlockConstraint;
+use crate::query::ext::{BlockConstraint, ErrorPolicy};
 use crate::schema::ast as sast;
 
 use crate::store::query::{collect_entities_from_query_field, parse_subgraph_id};
@@ -39,14 +39,49 @@ impl StoreResolver {
     /// by `bc`. Any calls to find objects will always return entities as
     /// of that block. Note that if `bc` is `BlockConstraint::Latest` we use
     /// whatever the latest block for the subgraph was when the resolver was
-    /// created
+    /// created. Unless `error_policy` is `ErrorPolicy::Allow`, querying a
+    /// subgraph that has non-fatal errors at that block fails
     pub fn at_block(
         logger: &Logger,
         store: Arc<impl Store + SubgraphDeploymentStore>,
         bc: BlockConstraint,
+        error_policy: ErrorPolicy,
         subgraph: &SubgraphDeploymentId,
     ) -> Result<(Self, EthereumBlockPointer), QueryExecutionError> {
+        let field = match bc {
+            BlockConstraint::Number(_) => Some("block.number"),
+            BlockConstraint::Hash(_) => Some("block.hash"),
+            BlockConstraint::Min(_) | BlockConstraint::Latest => None,
+        };
         let block_ptr = Self::locate_block(store.as_ref(), bc, subgraph)?;
+
+        // Deployments that prune their history can not answer queries for
+        // blocks before the earliest block they still have
+        if let Some(field) = field {
+            let earliest_block = store.earliest_block(subgraph).map_err(StoreError::from)?;
+            if (block_ptr.number as BlockNumber) < earliest_block {
+                return Err(QueryExecutionError::ValueParseError(
+                    field.to_owned(),
+                    format!(
+                        "subgraph {} only has data starting at block number {} \
+                         and data for block number {} is therefore not available",
+                        subgraph, earliest_block, block_ptr.number
+                    ),
+                ));
+            }
+        }
+
+        if error_policy == ErrorPolicy::Deny && !subgraph.is_meta() {
+            let has_errors = store
+                .has_non_fatal_errors(subgraph, Some(block_ptr.number as BlockNumber))
+                .map_err(StoreError::from)?;
+            if has_errors {
+                return Err(QueryExecutionError::DeploymentHasErrors(
+                    subgraph.to_string(),
+                ));
+            }
+        }
+
         let resolver = StoreResolver {
             logger: logger.new(o!("component" => "StoreResolver")),
             store: store.query_store(false),
@@ -108,6 +143,22 @@ impl StoreResolver {
                             })
                             .map(|number| EthereumBlockPointer::from((hash, number as u64)))
                     }),
+                BlockConstraint::Min(number) => store
+                    .block_ptr(subgraph.clone())
+                    .map_err(|e| StoreError::from(e).into())
+                    .and_then(|ptr| {
+                        let ptr =
+                            ptr.expect("we should have already checked that the subgraph exists");
+                        if ptr.number < number as u64 {
+                            Err(QueryExecutionError::BlockNotReached(
+                                subgraph.to_string(),
+                                ptr.number,
+                                number,
+                            ))
+                        } else {
+                            Ok(ptr)
+                        }
+                    }),
                 BlockConstraint::Latest => store
                     .block_ptr(subgraph.clone())
                     .map_err(|e| StoreError::from(e).into())
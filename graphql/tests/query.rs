@@ -3,7 +3,7 @@ extern crate pretty_assertions;
 
 use graphql_parser::{query as q, Pos};
 use lazy_static::lazy_static;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::FromIterator;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -11,9 +11,9 @@ use std::time::{Duration, Instant};
 use graph::prelude::{
     futures03::stream::StreamExt, futures03::FutureExt, futures03::TryFutureExt, o, slog, tokio,
     Entity, EntityKey, EntityOperation, EthereumBlockPointer, FutureExtension, GraphQlRunner as _,
-    Logger, Query, QueryError, QueryExecutionError, QueryResult, QueryVariables, Schema, Store,
-    SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphManifest,
-    Subscription, SubscriptionError, Value,
+    IndexerHints, Logger, Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
+    Schema, Store, SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphDeploymentStore,
+    SubgraphManifest, Subscription, SubscriptionError, Value,
 };
 use graph_graphql::prelude::*;
 use test_store::{
@@ -93,7 +93,10 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
         schema: schema.clone(),
         data_sources: vec![],
         graft: None,
+        migrate: None,
         templates: vec![],
+        features: BTreeSet::new(),
+        indexer_hints: IndexerHints::default(),
     };
 
     let ops = SubgraphDeploymentEntity::new(&manifest, false, None)
@@ -101,7 +104,9 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
         .into_iter()
         .map(|op| op.into())
         .collect();
-    store.create_subgraph_deployment(&schema, ops).unwrap();
+    store
+        .create_subgraph_deployment(&schema, &IndexerHints::default(), ops)
+        .unwrap();
 
     let entities0 = vec![
         Entity::from(vec![
@@ -520,6 +525,109 @@ fn can_query_many_to_many_relationship() {
     );
 }
 
+#[test]
+fn can_query_derived_field_counts() {
+    let result = execute_query_document(
+        graphql_parser::parse_query(
+            "
+            query {
+                musicians(first: 100, orderBy: id) {
+                    name
+                    writtenSongsCount
+                }
+                bands(first: 100, orderBy: id) {
+                    name
+                    membersCount
+                }
+            }
+            ",
+        )
+        .expect("Invalid test query"),
+    );
+
+    assert!(
+        result.errors.is_none(),
+        format!("Unexpected errors return for query: {:#?}", result.errors)
+    );
+
+    let musician = |name: &str, count: i32| {
+        object_value(vec![
+            ("name", q::Value::String(String::from(name))),
+            ("writtenSongsCount", q::Value::Int(q::Number::from(count))),
+        ])
+    };
+    let band = |name: &str, count: i32| {
+        object_value(vec![
+            ("name", q::Value::String(String::from(name))),
+            ("membersCount", q::Value::Int(q::Number::from(count))),
+        ])
+    };
+
+    assert_eq!(
+        result.data,
+        Some(object_value(vec![
+            (
+                "musicians",
+                q::Value::List(vec![
+                    musician("John", 2),
+                    musician("Lisa", 1),
+                    musician("Tom", 1),
+                    musician("Valerie", 0),
+                ]),
+            ),
+            (
+                "bands",
+                q::Value::List(vec![band("The Musicians", 3), band("The Amateurs", 2)]),
+            ),
+        ]))
+    );
+}
+
+#[test]
+fn can_query_version_blocks() {
+    let result = execute_query_document(
+        graphql_parser::parse_query(
+            "
+            query {
+                musicians(first: 100, orderBy: id) {
+                    name
+                    _version { block }
+                }
+            }
+            ",
+        )
+        .expect("Invalid test query"),
+    );
+
+    assert!(
+        result.errors.is_none(),
+        format!("Unexpected errors return for query: {:#?}", result.errors)
+    );
+
+    let musician = |name: &str, block: i32| {
+        object_value(vec![
+            ("name", q::Value::String(String::from(name))),
+            (
+                "_version",
+                object_value(vec![("block", q::Value::Int(q::Number::from(block)))]),
+            ),
+        ])
+    };
+
+    assert_eq!(
+        result.data,
+        Some(object_value(vec![(
+            "musicians",
+            q::Value::List(vec![
+                musician("John", 0),
+                musician("Lisa", 0),
+                musician("Tom", 1),
+                musician("Valerie", 1),
+            ]),
+        )]))
+    );
+}
+
 #[test]
 fn query_variables_are_used() {
     let query = graphql_parser::parse_query(
@@ -1323,11 +1431,17 @@ fn query_at_block() {
     const BLOCK_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
          up to block number 1 and data for block number 7000 is therefore not yet available";
     const BLOCK_HASH_NOT_FOUND: &str = "no block with that hash found";
+    const BLOCK_NOT_REACHED: &str = "subgraph `graphqlTestsQuery` has only indexed \
+         up to block number 1 and did not reach block number 7000 in time; try again later";
 
     musicians_at("number: 7000", Err(BLOCK_NOT_INDEXED), "n7000");
     musicians_at("number: 0", Ok(vec!["m1", "m2"]), "n0");
     musicians_at("number: 1", Ok(vec!["m1", "m2", "m3", "m4"]), "n1");
 
+    musicians_at("number_gte: 7000", Err(BLOCK_NOT_REACHED), "gte7000");
+    musicians_at("number_gte: 0", Ok(vec!["m1", "m2", "m3", "m4"]), "gte0");
+    musicians_at("number_gte: 1", Ok(vec!["m1", "m2", "m3", "m4"]), "gte1");
+
     musicians_at(&hash(&*GENESIS_BLOCK), Ok(vec!["m1", "m2"]), "h0");
     musicians_at(&hash(&*BLOCK_ONE), Ok(vec!["m1", "m2", "m3", "m4"]), "h1");
     musicians_at(&hash(&*BLOCK_TWO), Ok(vec!["m1", "m2", "m3", "m4"]), "h2");
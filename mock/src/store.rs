@@ -1,6 +1,6 @@
 use mockall::predicate::*;
 use mockall::*;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 
 use graph::components::store::*;
 use graph::data::subgraph::schema::*;
@@ -21,6 +21,45 @@ mock! {
         fn uses_relational_schema(&self, subgraph_id: &SubgraphDeploymentId) -> Result<bool, Error>;
 
         fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error>;
+
+        fn has_non_fatal_errors(
+            &self,
+            subgraph_id: &SubgraphDeploymentId,
+            block: Option<BlockNumber>,
+        ) -> Result<bool, Error>;
+
+        fn earliest_block(&self, subgraph_id: &SubgraphDeploymentId) -> Result<BlockNumber, Error>;
+
+        fn deployment_storage(
+            &self,
+            subgraph_ids: Option<Vec<SubgraphDeploymentId>>,
+        ) -> Result<Vec<DeploymentStorage>, Error>;
+
+        fn query_allowlist(
+            &self,
+            subgraph_id: &SubgraphDeploymentId,
+        ) -> Result<Option<Arc<HashSet<String>>>, Error>;
+
+        fn set_query_allowlist_enabled(
+            &self,
+            subgraph_id: &SubgraphDeploymentId,
+            enabled: bool,
+        ) -> Result<(), Error>;
+
+        fn allow_query(
+            &self,
+            subgraph_id: &SubgraphDeploymentId,
+            hash: &str,
+            query: &str,
+        ) -> Result<(), Error>;
+
+        fn disallow_query(&self, subgraph_id: &SubgraphDeploymentId, hash: &str) -> Result<bool, Error>;
+
+        fn allowed_queries(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Vec<AllowedQuery>, Error>;
+
+        fn import_entities(&self, subgraph_id: &SubgraphDeploymentId, block: BlockNumber, entities: Vec<(EntityKey, Entity)>) -> Result<(), StoreError>;
+
+        fn indexing_freshness(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<IndexingFreshness>, Error>;
     }
 
     trait ChainStore: Send + Sync + 'static {
@@ -48,11 +87,21 @@ mock! {
             offset: u64,
         ) -> Result<Option<EthereumBlock>, Error>;
 
+        fn ancestor_blocks(
+            &self,
+            block_hash: H256,
+            min_number: u64,
+        ) -> Result<Vec<EthereumBlock>, Error>;
+
         fn cleanup_cached_blocks(&self, ancestor_count: u64) -> Result<(BlockNumber, usize), Error>;
 
         fn block_hashes_by_block_number(&self, number: u64) -> Result<Vec<H256>, Error>;
 
         fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error>;
+
+        fn inconsistent_block_numbers(&self, from: u64, to: u64) -> Result<Vec<u64>, Error>;
+
+        fn cached_blocks_around_timestamp(&self, timestamp: u64) -> Result<(Option<(u64, u64)>, Option<(u64, u64)>), Error>;
     }
 }
 
@@ -76,6 +125,15 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn entity_changes_in_block(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _entity_types: &[String],
+        _block: BlockNumber,
+    ) -> Result<Vec<(String, Entity)>, StoreError> {
+        unimplemented!()
+    }
+
     fn supports_proof_of_indexing<'a>(
         &'a self,
         _subgraph_id: &'a SubgraphDeploymentId,
@@ -110,6 +168,16 @@ impl Store for MockStore {
         _block_ptr_to: EthereumBlockPointer,
         _mods: Vec<EntityModification>,
         _stopwatch: StopwatchMetrics,
+        _deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn transact_blocks_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _blocks: Vec<BlockModifications>,
+        _stopwatch: StopwatchMetrics,
     ) -> Result<bool, StoreError> {
         unimplemented!()
     }
@@ -145,6 +213,15 @@ impl Store for MockStore {
     fn create_subgraph_deployment(
         &self,
         _schema: &Schema,
+        _hints: &IndexerHints,
+        _ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn create_subgraph_deployments(
+        &self,
+        _deployments: &[(Schema, IndexerHints)],
         _ops: Vec<MetadataOperation>,
     ) -> Result<(), StoreError> {
         unimplemented!()
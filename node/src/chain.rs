@@ -0,0 +1,349 @@
+//! `graph-node chain ...`: inspect the blocks that the chain store caches
+//! for a network, repair them and fill the cache, without starting to index
+//! anything.
+
+use clap::ArgMatches;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use graph::prelude::{web3::types::H256, *};
+use graph::util::security::SafeDisplay;
+use graph_core::MetricsRegistry;
+use graph_store_postgres::block_cache::{BlockCache, BlockRange};
+use prometheus::Registry;
+
+use crate::config::Config;
+
+/// Run the `chain` subcommand given in `matches`. Returns `false` if it
+/// failed or found problems with the cached blocks
+pub async fn run(
+    logger: &Logger,
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    postgres_url: &str,
+) -> bool {
+    let (command, args) = match matches.subcommand() {
+        (command, Some(args)) => (command, args),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+    let network = args.value_of("network").expect("--network is required");
+    let cache = match BlockCache::connect(postgres_url, network) {
+        Ok(cache) => cache,
+        Err(e) => {
+            println!("Failed to connect to {}: {}", SafeDisplay(postgres_url), e);
+            return false;
+        }
+    };
+
+    let result = match command {
+        "check" => check(&cache, network, block_range(args)),
+        "remove" => remove(&cache, hashes(args)),
+        "refetch" => refetch(logger, &cache, config, network, hashes(args)).await,
+        "backfill" => {
+            let parallelism = args.value_of("parallelism").map(|n| {
+                n.parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .expect("--parallelism must be a positive number")
+            });
+            backfill(
+                logger,
+                &cache,
+                config,
+                network,
+                block_range(args),
+                parallelism,
+            )
+            .await
+        }
+        _ => unreachable!("unknown chain subcommand {}", command),
+    };
+    result.unwrap_or_else(|e| {
+        println!("{}", e);
+        false
+    })
+}
+
+fn block_range(args: &ArgMatches) -> BlockRange {
+    let number = |name| {
+        args.value_of(name).map(|n| {
+            n.parse::<i64>()
+                .unwrap_or_else(|_| panic!("--{} must be a block number", name))
+        })
+    };
+    let default = BlockRange::default();
+    BlockRange {
+        from: number("from").unwrap_or(default.from),
+        to: number("to").unwrap_or(default.to),
+    }
+}
+
+fn hashes(args: &ArgMatches) -> Vec<H256> {
+    args.values_of("hash")
+        .expect("a block hash is required")
+        .map(|hash| {
+            H256::from_str(hash.trim_start_matches("0x"))
+                .unwrap_or_else(|_| panic!("`{}` is not a block hash", hash))
+        })
+        .collect()
+}
+
+fn check(cache: &BlockCache, network: &str, range: BlockRange) -> Result<bool, Error> {
+    let summary = cache.summary(range)?;
+    let (min, max) = match (summary.min_number, summary.max_number) {
+        (Some(min), Some(max)) => (min, max),
+        _ => {
+            println!("No blocks cached for network {}", network);
+            return Ok(true);
+        }
+    };
+    println!(
+        "{} blocks with {} different numbers from {} to {}",
+        summary.blocks, summary.numbers, min, max
+    );
+    if let Some(head) = cache.chain_head()? {
+        println!("Chain head: {}", head);
+    }
+
+    let gaps = cache.gaps(range)?;
+    if !gaps.is_empty() {
+        println!("\nMissing blocks");
+        for gap in &gaps {
+            if gap.start == gap.end {
+                println!("  {}", gap.start);
+            } else {
+                println!("  {} to {}", gap.start, gap.end);
+            }
+        }
+    }
+
+    let duplicates = cache.duplicates(range)?;
+    if !duplicates.is_empty() {
+        println!("\nSeveral blocks with the same number");
+        for duplicate in &duplicates {
+            println!("  {}: {}", duplicate.number, duplicate.hashes.join(", "));
+        }
+    }
+
+    let broken = cache.broken_links(range)?;
+    if !broken.is_empty() {
+        println!("\nBlocks whose parent is not cached");
+        for link in &broken {
+            println!(
+                "  {} {} (parent {})",
+                link.number,
+                link.hash,
+                link.parent_hash.as_deref().unwrap_or("unknown")
+            );
+        }
+    }
+
+    // Gaps are normal since only the blocks that subgraphs need are cached;
+    // blocks that do not connect to the chain are not
+    Ok(broken.is_empty())
+}
+
+fn remove(cache: &BlockCache, hashes: Vec<H256>) -> Result<bool, Error> {
+    let head = cache.chain_head()?;
+    let mut ok = true;
+    for hash in hashes {
+        let deleted = cache.delete_block(&hash)?;
+        if deleted == 0 {
+            ok = false;
+            println!("{:x}: not cached", hash);
+            continue;
+        }
+        println!("{:x}: removed", hash);
+        if head.as_deref() == Some(format!("{:x}", hash).as_str()) {
+            println!(
+                "  this was the chain head; the block ingestor sets a new one \
+                 when it sees the next block"
+            );
+        }
+    }
+    Ok(ok)
+}
+
+async fn refetch(
+    logger: &Logger,
+    cache: &BlockCache,
+    config: &Config,
+    network: &str,
+    hashes: Vec<H256>,
+) -> Result<bool, Error> {
+    let provider = config
+        .chains
+        .get(network)
+        .and_then(|chain| chain.providers.first())
+        .ok_or_else(|| {
+            format_err!(
+                "refetching blocks needs a provider for network {} in the config file",
+                network
+            )
+        })?;
+    let (_event_loop, transport) = provider.transport().map_err(|e| format_err!("{}", e))?;
+    let metrics = Arc::new(ProviderEthRpcMetrics::new(Arc::new(MetricsRegistry::new(
+        logger.clone(),
+        Arc::new(Registry::new()),
+    ))));
+    let adapter =
+        graph_chain_ethereum::EthereumAdapter::new(&provider.url, transport, metrics.clone());
+
+    let mut ok = true;
+    for hash in hashes {
+        let block = match adapter.block_by_hash(logger, hash).compat().await? {
+            Some(block) => block,
+            None => {
+                // The block is not part of the chain any more
+                let deleted = cache.delete_block(&hash)?;
+                println!(
+                    "{:x}: unknown to {}, {}",
+                    hash,
+                    SafeDisplay(&provider.url),
+                    if deleted > 0 { "removed" } else { "not cached" }
+                );
+                continue;
+            }
+        };
+        match adapter.load_full_block(logger, block).compat().await {
+            Ok(block) => {
+                cache.replace_block(&block)?;
+                println!("{:x}: refetched", hash);
+            }
+            Err(e) => {
+                ok = false;
+                println!("{:x}: failed to load receipts: {}", hash, e);
+            }
+        }
+    }
+    Ok(ok)
+}
+
+/// Load the blocks in `range` that are not cached yet, with their receipts,
+/// from all providers of the network in the config file. Each provider loads
+/// as many blocks at a time as it accepts requests at a time, unless
+/// `parallelism` says otherwise; providers that respond faster load more of
+/// the blocks
+async fn backfill(
+    logger: &Logger,
+    cache: &BlockCache,
+    config: &Config,
+    network: &str,
+    range: BlockRange,
+    parallelism: Option<usize>,
+) -> Result<bool, Error> {
+    let providers = config
+        .chains
+        .get(network)
+        .map(|chain| &chain.providers)
+        .filter(|providers| !providers.is_empty())
+        .ok_or_else(|| {
+            format_err!(
+                "backfilling blocks needs providers for network {} in the config file",
+                network
+            )
+        })?;
+
+    let cached: HashSet<i64> = cache.cached_numbers(range)?.into_iter().collect();
+    // Pop blocks off the end of the queue in ascending order
+    let queue: Vec<u64> = (range.from..=range.to)
+        .rev()
+        .filter(|number| !cached.contains(number))
+        .map(|number| number as u64)
+        .collect();
+    let total = queue.len();
+    println!(
+        "{} blocks from {} to {} are cached already, loading {}",
+        cached.len(),
+        range.from,
+        range.to,
+        total
+    );
+    if total == 0 {
+        return Ok(true);
+    }
+
+    let metrics = Arc::new(ProviderEthRpcMetrics::new(Arc::new(MetricsRegistry::new(
+        logger.clone(),
+        Arc::new(Registry::new()),
+    ))));
+    let mut event_loops = vec![];
+    let mut adapters = vec![];
+    for provider in providers {
+        let (event_loop, transport) = provider.transport().map_err(|e| format_err!("{}", e))?;
+        event_loops.push(event_loop);
+        let adapter = Arc::new(graph_chain_ethereum::EthereumAdapter::new(
+            &provider.url,
+            transport,
+            metrics.clone(),
+        )) as Arc<dyn EthereumAdapter>;
+        let workers = parallelism.unwrap_or_else(|| provider.max_parallel_requests());
+        println!(
+            "Loading {} blocks at a time from {}",
+            workers,
+            SafeDisplay(&provider.url)
+        );
+        adapters.push((adapter, workers));
+    }
+
+    let queue = Mutex::new(queue);
+    let loaded = AtomicUsize::new(0);
+    let workers = adapters.iter().flat_map(|(adapter, workers)| {
+        (0..*workers).map(|_| load_blocks(logger, adapter.as_ref(), cache, &queue, &loaded, total))
+    });
+    let results = futures03::future::join_all(workers).await;
+
+    let failed = results.into_iter().filter(|ok| !ok).count();
+    println!("Loaded {} of {} blocks", loaded.into_inner(), total);
+    Ok(failed == 0)
+}
+
+/// Load the blocks in `queue` with `adapter` and cache them, one at a time,
+/// until the queue is empty. Returns `false` if any block failed to load
+async fn load_blocks(
+    logger: &Logger,
+    adapter: &dyn EthereumAdapter,
+    cache: &BlockCache,
+    queue: &Mutex<Vec<u64>>,
+    loaded: &AtomicUsize,
+    total: usize,
+) -> bool {
+    let mut ok = true;
+    loop {
+        let number = match queue.lock().unwrap().pop() {
+            Some(number) => number,
+            None => return ok,
+        };
+        let block = match adapter.block_by_number(logger, number).compat().await {
+            Ok(Some(block)) => block,
+            Ok(None) => {
+                ok = false;
+                println!("{}: unknown to {}", number, adapter.url_hostname());
+                continue;
+            }
+            Err(e) => {
+                ok = false;
+                println!("{}: failed to load block: {}", number, e);
+                continue;
+            }
+        };
+        let result = match adapter.load_full_block(logger, block).compat().await {
+            Ok(block) => cache.replace_block(&block),
+            Err(e) => Err(format_err!("failed to load receipts: {}", e)),
+        };
+        match result {
+            Ok(()) => {
+                let loaded = loaded.fetch_add(1, Ordering::SeqCst) + 1;
+                if loaded % 1000 == 0 {
+                    println!("Loaded {} of {} blocks", loaded, total);
+                }
+            }
+            Err(e) => {
+                ok = false;
+                println!("{}: {}", number, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! `graph-node config check`: validates the configuration file and prints
+//! what the node would do with it, without starting to index anything.
+
+use std::env;
+use std::time::Duration;
+
+use graph::prelude::*;
+use graph::util::security::SafeDisplay;
+use graph_core::MetricsRegistry;
+use graph_store_postgres::connection_pool::check_connection;
+use prometheus::Registry;
+
+use crate::config::Config;
+
+/// How long to wait for the store or a provider to respond
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Environment variables that take precedence over the config file, and
+/// the settings they replace
+const OVERRIDES: &[(&str, &str)] = &[
+    ("GRAPH_NODE_ID", "general.node_id"),
+    ("POSTGRES_URL", "store.connection"),
+    ("STORE_CONNECTION_POOL_SIZE", "store.pool_size"),
+    ("STORE_QUERY_CONNECTION_POOL_SIZE", "store.query_pool_size"),
+    (
+        "STORE_METADATA_CONNECTION_POOL_SIZE",
+        "store.metadata_pool_size",
+    ),
+    ("GRAPH_POSTGRES_SECONDARY_HOSTS", "store.replicas"),
+    ("ETHEREUM_RPC", "chains"),
+    ("ETHEREUM_WS", "chains"),
+    ("ETHEREUM_IPC", "chains"),
+];
+
+/// Check the config file at `path` and print the effective settings.
+/// Connects to the store and, with `probe`, to every Ethereum provider.
+/// Returns `false` if the file is invalid or anything can not be reached.
+pub async fn run(logger: &Logger, path: &str, probe: bool) -> bool {
+    let config = match Config::from_file(path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return false;
+        }
+    };
+    println!("Config file {} is valid", path);
+    let mut ok = true;
+
+    println!("\nNode");
+    println!(
+        "  id: {}",
+        config.general.node_id.as_deref().unwrap_or("default")
+    );
+    println!("  role: {}", config.general.role);
+
+    println!("\nStore");
+    match &config.store {
+        Some(store) => {
+            println!(
+                "  pool size: {}",
+                store
+                    .pool_size
+                    .map_or("10 (default)".to_owned(), |size| size.to_string())
+            );
+            for (name, size) in &[
+                ("query", store.query_pool_size),
+                ("metadata", store.metadata_pool_size),
+            ] {
+                println!(
+                    "  {} pool size: {}",
+                    name,
+                    size.map_or("shares the main pool".to_owned(), |size| size.to_string())
+                );
+            }
+            for url in std::iter::once(store.connection_url()).chain(store.replica_urls()) {
+                let result = check_store(url.clone()).await;
+                ok &= result.is_ok();
+                println!("  {}: {}", SafeDisplay(&url), outcome(result));
+            }
+        }
+        None => println!("  none; --postgres-url/POSTGRES_URL has to be set"),
+    }
+
+    println!("\nChains");
+    if config.chains.is_empty() {
+        println!("  none; Ethereum providers have to be given on the command line");
+    }
+    let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(Arc::new(MetricsRegistry::new(
+        logger.clone(),
+        Arc::new(Registry::new()),
+    ))));
+    for (name, chain) in &config.chains {
+        match &chain.network {
+            Some(network) => println!("  {} (alias of {})", name, network),
+            None => println!("  {} (profile {})", name, chain.profile),
+        }
+        for provider in &chain.providers {
+            let description = format!(
+                "{} ({}, {})",
+                SafeDisplay(&provider.url),
+                provider.transport,
+                provider.capabilities()
+            );
+            if !probe {
+                println!("    {}", description);
+                continue;
+            }
+            let result = match provider.transport() {
+                Ok((_event_loop, transport)) => {
+                    let adapter = graph_chain_ethereum::EthereumAdapter::new(
+                        &provider.url,
+                        transport,
+                        eth_rpc_metrics.clone(),
+                    );
+                    tokio::time::timeout(CHECK_TIMEOUT, adapter.net_identifiers(logger).compat())
+                        .await
+                        .map_err(|_| "timed out".to_owned())
+                        .and_then(|result| result.map_err(|e| e.to_string()))
+                        .map(|ident| {
+                            format!(
+                                "net_version {}, genesis block {:x}",
+                                ident.net_version, ident.genesis_block_hash
+                            )
+                        })
+                }
+                Err(e) => Err(e),
+            };
+            ok &= result.is_ok();
+            println!("    {}: {}", description, outcome(result));
+        }
+    }
+
+    let timeouts: Vec<_> = config
+        .timeouts
+        .vars()
+        .into_iter()
+        .filter_map(|(var, value)| value.map(|value| (var, value)))
+        .collect();
+    if !timeouts.is_empty() {
+        println!("\nTimeouts");
+        for (var, value) in timeouts {
+            println!("  {}: {}s", var, value);
+        }
+    }
+
+    println!("\nPlacement rules");
+    for (i, rule) in config.deployment.describe_rules().iter().enumerate() {
+        println!("  {}. {}", i + 1, rule);
+    }
+    println!("  Deployments that no rule matches are assigned to the node that receives them");
+
+    let scopes = config.admin.token_scopes();
+    println!("\nAdmin server");
+    if scopes.is_empty() {
+        println!("  no tokens; requests are not checked");
+    } else {
+        let count = |scope| scopes.iter().filter(|s| **s == scope).count();
+        println!(
+            "  {} tokens, {} with scope admin, {} with scope deploy, {} with scope read",
+            scopes.len(),
+            count(AdminScope::Admin),
+            count(AdminScope::Deploy),
+            count(AdminScope::Read)
+        );
+    }
+
+    let follow = config.deployment.follow_settings();
+    if !follow.is_empty() {
+        println!("\nDeployments that follow the chain head less closely");
+        for (deployment, settings) in follow {
+            let mut how = vec![];
+            if let Some(interval) = settings.polling_interval {
+                how.push(format!("every {}s", interval.as_secs()));
+            }
+            if settings.max_lag > 0 {
+                how.push(format!("once more than {} blocks behind", settings.max_lag));
+            }
+            println!("  {}: {}", deployment, how.join(", "));
+        }
+    }
+
+    let drains = config.log.drains();
+    if !drains.is_empty() {
+        println!("\nLog drains");
+        for drain in drains {
+            let target = match &drain.target {
+                LogDrainTarget::Elasticsearch { general, index } => format!(
+                    "Elasticsearch {}, index {}",
+                    SafeDisplay(&general.endpoint),
+                    index
+                ),
+                LogDrainTarget::Loki(general) => format!("Loki {}", SafeDisplay(&general.endpoint)),
+            };
+            println!("  {} deployments to {}", drain.deployments.len(), target);
+        }
+    }
+
+    let endpoints = config.mutations.endpoints();
+    if !endpoints.is_empty() {
+        println!("\nMutation endpoints");
+        for endpoint in endpoints {
+            println!(
+                "  {} deployments to {}",
+                endpoint.deployments.len(),
+                SafeDisplay(endpoint.url.as_str())
+            );
+        }
+    }
+
+    let overrides: Vec<_> = OVERRIDES
+        .iter()
+        .map(|(var, setting)| (*var, *setting))
+        .chain(
+            config
+                .timeouts
+                .vars()
+                .into_iter()
+                .filter_map(|(var, value)| value.map(|_| (var, "timeouts"))),
+        )
+        .filter(|(var, _)| env::var_os(var).is_some())
+        .collect();
+    if !overrides.is_empty() {
+        println!("\nSet in the environment, and used instead of the config file");
+        for (var, setting) in overrides {
+            println!("  {} replaces {}", var, setting);
+        }
+    }
+
+    ok
+}
+
+async fn check_store(url: String) -> Result<String, String> {
+    let check = graph::spawn_blocking_async_allow_panic(move || check_connection(&url));
+    tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .map_err(|_| "timed out".to_owned())?
+        .map(|()| "connected".to_owned())
+}
+
+fn outcome(result: Result<String, String>) -> String {
+    match result {
+        Ok(message) => format!("ok, {}", message),
+        Err(e) => format!("FAILED, {}", e),
+    }
+}
@@ -0,0 +1,161 @@
+//! The health checks that decide whether the node reports itself as ready
+//! on `/health/ready` of the index node server.
+
+use lazy_static::lazy_static;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::prelude::*;
+
+lazy_static! {
+    /// How long the chain head of a network can stay the same before the
+    /// block ingestor for it is considered stuck, in seconds
+    static ref MAX_CHAIN_HEAD_AGE: Duration = Duration::from_secs(
+        env::var("GRAPH_HEALTH_MAX_CHAIN_HEAD_AGE")
+            .unwrap_or("300".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_HEALTH_MAX_CHAIN_HEAD_AGE")
+    );
+}
+
+/// Checks that the database can be queried.
+pub struct StoreCheck<S> {
+    store: Arc<S>,
+}
+
+impl<S> StoreCheck<S> {
+    pub fn new(store: Arc<S>) -> Self {
+        StoreCheck { store }
+    }
+}
+
+#[async_trait]
+impl<S: ChainStore> HealthCheck for StoreCheck<S> {
+    fn name(&self) -> &str {
+        "store"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let store = self.store.clone();
+        graph::spawn_blocking_async_allow_panic(move || store.chain_head_ptr())
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Checks that at least one of the Ethereum providers of a network
+/// responds.
+pub struct ProviderCheck {
+    name: String,
+    logger: Logger,
+    adapters: Vec<Arc<dyn EthereumAdapter>>,
+}
+
+impl ProviderCheck {
+    pub fn new(logger: &Logger, network: &str, adapters: Vec<Arc<dyn EthereumAdapter>>) -> Self {
+        ProviderCheck {
+            name: format!("ethereum_{}", network),
+            logger: logger.new(o!("network" => network.to_owned())),
+            adapters,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for ProviderCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let mut errors = vec![];
+        for adapter in &self.adapters {
+            match adapter.latest_block_header(&self.logger).compat().await {
+                Ok(_) => return Ok(()),
+                Err(e) => errors.push(format!("{}: {}", adapter.url_hostname(), e)),
+            }
+        }
+        Err(format!("no provider responded: {}", errors.join("; ")))
+    }
+}
+
+/// Checks that the block ingestor of a network keeps moving the chain head
+/// forward.
+pub struct ChainHeadCheck<S> {
+    name: String,
+    store: Arc<S>,
+    /// The last chain head number we saw and when we first saw it
+    last_head: Mutex<Option<(u64, Instant)>>,
+}
+
+impl<S> ChainHeadCheck<S> {
+    pub fn new(network: &str, store: Arc<S>) -> Self {
+        ChainHeadCheck {
+            name: format!("block_ingestor_{}", network),
+            store,
+            last_head: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: ChainStore> HealthCheck for ChainHeadCheck<S> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let store = self.store.clone();
+        let head = graph::spawn_blocking_async_allow_panic(move || store.chain_head_ptr())
+            .await
+            .map_err(|e| e.to_string())?
+            .map(|ptr| ptr.number);
+
+        let mut last_head = self.last_head.lock().unwrap();
+        let since = match (head, *last_head) {
+            (None, _) => return Err("no chain head yet".to_owned()),
+            (Some(head), Some((last, since))) if head == last => since,
+            (Some(head), _) => {
+                *last_head = Some((head, Instant::now()));
+                return Ok(());
+            }
+        };
+        if since.elapsed() > *MAX_CHAIN_HEAD_AGE {
+            return Err(format!(
+                "chain head has not moved for {}s",
+                since.elapsed().as_secs()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the node has started the deployments assigned to it.
+pub struct AssignmentsCheck {
+    started: Arc<AtomicBool>,
+}
+
+impl AssignmentsCheck {
+    /// The check passes once `started` is set.
+    pub fn new(started: Arc<AtomicBool>) -> Self {
+        AssignmentsCheck { started }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for AssignmentsCheck {
+    fn name(&self) -> &str {
+        "assignments"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        if self.started.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err("assigned deployments have not been started yet".to_owned())
+        }
+    }
+}
@@ -1,15 +1,17 @@
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 use git_testament::{git_testament, render_testament};
 use ipfs_api::IpfsClient;
 use lazy_static::lazy_static;
 use prometheus::Registry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::RwLock;
 use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
 
 use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
@@ -21,9 +23,9 @@ use graph::util::security::SafeDisplay;
 use graph_chain_arweave::adapter::ArweaveAdapter;
 use graph_chain_ethereum::{network_indexer, BlockIngestor, BlockStreamBuilder, Transport};
 use graph_core::{
-    three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry,
-    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
-    SubgraphRegistrar as IpfsSubgraphRegistrar,
+    replay_blocks, three_box::ThreeBoxAdapter, IndexingShutdown, LinkResolver, MetricsRegistry,
+    NodeFailover, ReplayedBlock, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
+    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar, TriggerDryRun,
 };
 use graph_graphql::prelude::GraphQlRunner;
 use graph_runtime_wasm::RuntimeHostBuilder as WASMRuntimeHostBuilder;
@@ -32,13 +34,23 @@ use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
 use graph_server_metrics::PrometheusMetricsServer;
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::connection_pool::create_connection_pool;
+use graph_store_postgres::connection_pool::{create_connection_pool, ConnectionPools, MAIN_POOL};
 use graph_store_postgres::{
-    ChainHeadUpdateListener as PostgresChainHeadUpdateListener, Store as DieselStore, StoreConfig,
-    SubscriptionManager,
+    ChainHeadUpdateListener as PostgresChainHeadUpdateListener, Pruner, Store as DieselStore,
+    StoreConfig, StoreMaintenance, SubscriptionManager, UnusedDeploymentRemover,
 };
 use graphql_parser::query as q;
 
+mod chain;
+mod check;
+mod config;
+mod health;
+mod mapping_test;
+mod unused;
+
+use config::{ChainSettings, Config};
+use health::{AssignmentsCheck, ChainHeadCheck, ProviderCheck, StoreCheck};
+
 lazy_static! {
     // Default to an Ethereum reorg threshold to 50 blocks
     static ref REORG_THRESHOLD: u64 = env::var("ETHEREUM_REORG_THRESHOLD")
@@ -53,6 +65,14 @@ lazy_static! {
         .map(|s| u64::from_str(&s)
              .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_ANCESTOR_COUNT")))
         .unwrap_or(50);
+
+    // How long to wait for blocks that are being processed when shutting down
+    static ref SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(
+        env::var("GRAPH_SHUTDOWN_TIMEOUT")
+            .unwrap_or("30".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_SHUTDOWN_TIMEOUT")
+    );
 }
 
 git_testament!(TESTAMENT);
@@ -103,6 +123,7 @@ async fn main() {
         .version(render_testament!(TESTAMENT).as_str())
         .author("Graph Protocol, Inc.")
         .about("Scalable queries for a decentralized future")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("subgraph")
                 .takes_value(true)
@@ -114,7 +135,7 @@ async fn main() {
         .arg(
             Arg::with_name("postgres-url")
                 .takes_value(true)
-                .required(true)
+                .required_unless("config")
                 .long("postgres-url")
                 .value_name("URL")
                 .env("POSTGRES_URL")
@@ -137,7 +158,7 @@ async fn main() {
                 .takes_value(true)
                 .multiple(true)
                 .min_values(0)
-                .required_unless_one(&["ethereum-ws", "ethereum-ipc"])
+                .required_unless_one(&["ethereum-ws", "ethereum-ipc", "config"])
                 .conflicts_with_all(&["ethereum-ws", "ethereum-ipc"])
                 .long("ethereum-rpc")
                 .value_name("NETWORK_NAME:URL")
@@ -152,7 +173,7 @@ async fn main() {
                 .takes_value(true)
                 .multiple(true)
                 .min_values(0)
-                .required_unless_one(&["ethereum-rpc", "ethereum-ipc"])
+                .required_unless_one(&["ethereum-rpc", "ethereum-ipc", "config"])
                 .conflicts_with_all(&["ethereum-rpc", "ethereum-ipc"])
                 .long("ethereum-ws")
                 .value_name("NETWORK_NAME:URL")
@@ -167,7 +188,7 @@ async fn main() {
                 .takes_value(true)
                 .multiple(true)
                 .min_values(0)
-                .required_unless_one(&["ethereum-rpc", "ethereum-ws"])
+                .required_unless_one(&["ethereum-rpc", "ethereum-ws", "config"])
                 .conflicts_with_all(&["ethereum-rpc", "ethereum-ws"])
                 .long("ethereum-ipc")
                 .value_name("NETWORK_NAME:FILE")
@@ -185,7 +206,7 @@ async fn main() {
                 .multiple(true)
                 .value_name("HOST:PORT")
                 .env("IPFS")
-                .help("HTTP addresses of IPFS nodes"),
+                .help("HTTP addresses of IPFS nodes; requests fail over between them"),
         )
         .arg(
             Arg::with_name("http-port")
@@ -224,11 +245,10 @@ async fn main() {
         )
         .arg(
             Arg::with_name("node-id")
-                .default_value("default")
                 .long("node-id")
                 .value_name("NODE_ID")
                 .env("GRAPH_NODE_ID")
-                .help("a unique identifier for this node"),
+                .help("a unique identifier for this node [default: default]"),
         )
         .arg(
             Arg::with_name("debug")
@@ -278,9 +298,31 @@ async fn main() {
             Arg::with_name("store-connection-pool-size")
                 .long("store-connection-pool-size")
                 .value_name("STORE_CONNECTION_POOL_SIZE")
-                .default_value("10")
                 .env("STORE_CONNECTION_POOL_SIZE")
-                .help("Limits the number of connections in the store's connection pool"),
+                .help(
+                    "Limits the number of connections in the store's connection pool \
+                     [default: 10]",
+                ),
+        )
+        .arg(
+            Arg::with_name("store-query-connection-pool-size")
+                .long("store-query-connection-pool-size")
+                .value_name("STORE_QUERY_CONNECTION_POOL_SIZE")
+                .env("STORE_QUERY_CONNECTION_POOL_SIZE")
+                .help(
+                    "Gives GraphQL queries a pool with this many connections of their own. \
+                     Without it, queries share the store's connection pool",
+                ),
+        )
+        .arg(
+            Arg::with_name("store-metadata-connection-pool-size")
+                .long("store-metadata-connection-pool-size")
+                .value_name("STORE_METADATA_CONNECTION_POOL_SIZE")
+                .env("STORE_METADATA_CONNECTION_POOL_SIZE")
+                .help(
+                    "Gives operations on subgraph metadata a pool with this many connections \
+                     of their own. Without it, they share the store's connection pool",
+                ),
         )
         .arg(
             Arg::with_name("network-subgraphs")
@@ -298,8 +340,9 @@ async fn main() {
             Arg::with_name("arweave-api")
                 .default_value("https://arweave.net/")
                 .long("arweave-api")
+                .multiple(true)
                 .value_name("URL")
-                .help("HTTP endpoint of an Arweave gateway"),
+                .help("HTTP endpoints of Arweave gateways; requests fail over between them"),
         )
         .arg(
             Arg::with_name("3box-api")
@@ -308,10 +351,193 @@ async fn main() {
                 .value_name("URL")
                 .help("HTTP endpoint for 3box profiles"),
         )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help(
+                    "Configuration file for the node, its store, chains and timeouts, \
+                     deployment placement rules, admin tokens, log drains and mutation \
+                     endpoints",
+                ),
+        )
+        .arg(
+            Arg::with_name("failover")
+                .long("failover")
+                .help("Move deployments away from index nodes that stop sending heartbeats"),
+        )
+        .arg(
+            Arg::with_name("fork-base")
+                .long("fork-base")
+                .value_name("URL")
+                .help(
+                    "GraphQL endpoint of a subgraph on another graph-node to fetch \
+                     entities from that are not in the local store; for debugging",
+                ),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("IPFS_HASH:BLOCK[:BLOCK]")
+                .help(
+                    "Process a block or range of blocks of a deployment against its \
+                     current data, print the changes that would be made and exit \
+                     without writing anything",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Work with the configuration file")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about(
+                            "Validate the configuration file, check that the store can be \
+                             reached and print the effective settings without starting \
+                             the node",
+                        )
+                        .arg(
+                            Arg::with_name("config")
+                                .takes_value(true)
+                                .long("config")
+                                .value_name("FILE")
+                                .help("The configuration file to check"),
+                        )
+                        .arg(Arg::with_name("probe").long("probe").help(
+                            "Also connect to every Ethereum provider and ask it for its network",
+                        )),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("chain")
+                .about("Inspect and repair the blocks cached in the chain store")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about(
+                            "Look for missing blocks, blocks that share a number and \
+                             blocks whose parent is not cached",
+                        )
+                        .arg(network_arg())
+                        .arg(
+                            Arg::with_name("from")
+                                .takes_value(true)
+                                .long("from")
+                                .value_name("BLOCK")
+                                .help("The first block number to check"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .takes_value(true)
+                                .long("to")
+                                .value_name("BLOCK")
+                                .help("The last block number to check"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove blocks from the cache")
+                        .arg(network_arg())
+                        .arg(hash_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("backfill")
+                        .about(
+                            "Load the blocks in a range that are not cached yet, with their \
+                             receipts, from all providers of the network in the config file",
+                        )
+                        .arg(network_arg())
+                        .arg(
+                            Arg::with_name("from")
+                                .takes_value(true)
+                                .required(true)
+                                .long("from")
+                                .value_name("BLOCK")
+                                .help("The first block number to load"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .takes_value(true)
+                                .required(true)
+                                .long("to")
+                                .value_name("BLOCK")
+                                .help("The last block number to load"),
+                        )
+                        .arg(
+                            Arg::with_name("parallelism")
+                                .takes_value(true)
+                                .long("parallelism")
+                                .value_name("N")
+                                .help(
+                                    "How many blocks to load at a time from each provider; \
+                                     defaults to its max_parallel_requests",
+                                ),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("refetch")
+                        .about(
+                            "Load blocks from the first provider of the network in the \
+                             config file and replace the cached ones; blocks the provider \
+                             does not know are removed",
+                        )
+                        .arg(network_arg())
+                        .arg(hash_arg()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mapping-test")
+                .about(
+                    "Run unit tests of a compiled mapping against an in-memory store, \
+                     without a database or an Ethereum node",
+                )
+                .arg(
+                    Arg::with_name("tests")
+                        .required(true)
+                        .value_name("FILE")
+                        .help("JSON file that describes the mapping and the tests"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unused")
+                .about(
+                    "Manage deployments that no subgraph name points to and that are not queried",
+                )
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the deployments recorded as unused")
+                        .arg(
+                            Arg::with_name("all")
+                                .long("all")
+                                .help("Also list the deployments that were removed"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("record").about(
+                    "Record the deployments that became unused and forget those \
+                             that are used again",
+                ))
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about(
+                            "Remove the given unused deployments right away, or all unused \
+                             deployments whose grace period has passed",
+                        )
+                        .arg(deployment_arg().required(false)),
+                )
+                .subcommand(
+                    SubCommand::with_name("restore")
+                        .about(
+                            "Forget that deployments are unused; they start a new grace \
+                             period if they are still unused the next time",
+                        )
+                        .arg(deployment_arg()),
+                ),
+        )
         .get_matches();
 
-    // Set up logger
-    let logger = logger(matches.is_present("debug"));
+    // Set up logger; replays log at debug level to show the contract calls
+    let logger = logger(matches.is_present("debug") || matches.is_present("replay"));
 
     // Log version information
     info!(
@@ -320,15 +546,84 @@ async fn main() {
         render_testament!(TESTAMENT)
     );
 
-    // Safe to unwrap because a value is required by CLI
-    let postgres_url = matches.value_of("postgres-url").unwrap().to_string();
+    if let Some(check) = matches
+        .subcommand_matches("config")
+        .and_then(|config| config.subcommand_matches("check"))
+    {
+        let path = check
+            .value_of("config")
+            .or_else(|| matches.value_of("config"))
+            .expect("`config check` requires --config");
+        let ok = check::run(&logger, path, check.is_present("probe")).await;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    if let Some(tests) = matches.subcommand_matches("mapping-test") {
+        let ok = mapping_test::run(&logger, tests).await;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    // Export traces of block processing if requested; the exporter stops
+    // when this is dropped
+    let _trace_exporter = graph::components::metrics::trace::init(&logger);
+
+    // Read the configuration file; command line arguments and environment
+    // variables take precedence over it
+    let config = matches
+        .value_of("config")
+        .map(|path| Config::from_file(path).unwrap_or_else(|e| panic!("{}", e)))
+        .unwrap_or_default();
+    config.timeouts.apply();
+
+    let postgres_url = matches
+        .value_of("postgres-url")
+        .map(|url| url.to_string())
+        .or_else(|| config.store.as_ref().map(|store| store.connection_url()))
+        .expect("--postgres-url/POSTGRES_URL or a [store] section in the config file is required");
+
+    if let Some(chain) = matches.subcommand_matches("chain") {
+        let ok = chain::run(&logger, chain, &config, &postgres_url).await;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
 
-    let node_id = NodeId::new(matches.value_of("node-id").unwrap())
-        .expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
+    if let Some(unused) = matches.subcommand_matches("unused") {
+        let ok = unused::run(unused, &postgres_url);
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    let node_id = NodeId::new(
+        matches
+            .value_of("node-id")
+            .or_else(|| config.general.node_id.as_deref())
+            .unwrap_or("default"),
+    )
+    .expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
 
     // Obtain subgraph related command-line arguments
     let subgraph = matches.value_of("subgraph").map(|s| s.to_owned());
 
+    let admin_tokens = config.admin.tokens();
+    let mutation_endpoints = config.mutations.endpoints();
+    let network_aliases = config.network_aliases();
+    let placer: Arc<dyn DeploymentPlacer> = Arc::new(config.deployment);
+
+    // Obtain the deployment and the blocks to replay
+    let replay = matches.value_of("replay").map(|replay| {
+        let mut parts = replay.split(':');
+        let id = SubgraphDeploymentId::new(parts.next().unwrap())
+            .expect("Subgraph hash must be a valid IPFS hash");
+        let from: u64 = parts
+            .next()
+            .expect("--replay requires a block number")
+            .parse()
+            .expect("invalid block number for --replay");
+        let to = parts
+            .next()
+            .map(|to| to.parse().expect("invalid block number for --replay"))
+            .unwrap_or(from);
+        (id, from, to)
+    });
+
     // Obtain the Ethereum parameters
     let ethereum_rpc = matches.values_of("ethereum-rpc");
     let ethereum_ipc = matches.values_of("ethereum-ipc");
@@ -342,6 +637,27 @@ async fn main() {
             .expect("Ethereum polling interval must be a nonnegative integer"),
     );
 
+    // The polling interval and reorg handling of each chain in the config
+    // file, based on those from the command line and environment
+    let default_chain_settings = ChainSettings {
+        polling_interval: block_polling_interval,
+        reorg_threshold: *REORG_THRESHOLD,
+        ancestor_count: *ANCESTOR_COUNT,
+        ..ChainSettings::default()
+    };
+    // Network aliases use the settings of their network
+    let chain_settings: HashMap<String, ChainSettings> = config
+        .chains
+        .iter()
+        .map(|(name, chain)| {
+            let chain = chain
+                .network
+                .as_ref()
+                .map_or(chain, |network| &config.chains[network]);
+            (name.clone(), chain.settings(default_chain_settings))
+        })
+        .collect();
+
     // Obtain ports to use for the GraphQL server(s)
     let http_port = matches
         .value_of("http-port")
@@ -375,34 +691,88 @@ async fn main() {
         .parse()
         .expect("invalid metrics port");
 
-    // Obtain DISABLE_BLOCK_INGESTOR setting
+    // Obtain DISABLE_BLOCK_INGESTOR setting; query nodes never ingest blocks
     let disable_block_ingestor: bool = matches
         .value_of("disable-block-ingestor")
         .unwrap()
-        .parse()
-        .expect("invalid --disable-block-ingestor/DISABLE_BLOCK_INGESTOR value");
+        .parse::<bool>()
+        .expect("invalid --disable-block-ingestor/DISABLE_BLOCK_INGESTOR value")
+        || !config.general.role.ingests_blocks();
+
+    // Index nodes do not serve GraphQL queries
+    let serve_queries = config.general.role.serves_queries();
 
     // Obtain STORE_CONNECTION_POOL_SIZE setting
     let store_conn_pool_size: u32 = matches
         .value_of("store-connection-pool-size")
-        .unwrap()
-        .parse()
-        .expect("invalid --store-connection-pool-size/STORE_CONNECTION_POOL_SIZE value");
+        .map(|size| {
+            size.parse()
+                .expect("invalid --store-connection-pool-size/STORE_CONNECTION_POOL_SIZE value")
+        })
+        .or_else(|| config.store.as_ref().and_then(|store| store.pool_size))
+        .unwrap_or(10);
 
     // Minimum of two connections needed for the pool in order for the Store to bootstrap
     if store_conn_pool_size <= 1 {
         panic!("--store-connection-pool-size/STORE_CONNECTION_POOL_SIZE must be > 1")
     }
 
-    let arweave_adapter = Arc::new(ArweaveAdapter::new(
-        matches.value_of("arweave-api").unwrap().to_string(),
+    // Obtain the sizes of the separate pools for queries and metadata
+    let store_query_pool_size: Option<u32> = matches
+        .value_of("store-query-connection-pool-size")
+        .map(|size| {
+            size.parse().expect(
+                "invalid --store-query-connection-pool-size/STORE_QUERY_CONNECTION_POOL_SIZE value",
+            )
+        })
+        .or_else(|| {
+            config
+                .store
+                .as_ref()
+                .and_then(|store| store.query_pool_size)
+        });
+    let store_metadata_pool_size: Option<u32> = matches
+        .value_of("store-metadata-connection-pool-size")
+        .map(|size| {
+            size.parse().expect(
+                "invalid --store-metadata-connection-pool-size/\
+                 STORE_METADATA_CONNECTION_POOL_SIZE value",
+            )
+        })
+        .or_else(|| {
+            config
+                .store
+                .as_ref()
+                .and_then(|store| store.metadata_pool_size)
+        });
+    if store_query_pool_size == Some(0) || store_metadata_pool_size == Some(0) {
+        panic!("the query and metadata connection pools must have at least one connection")
+    }
+
+    let arweave_adapter = Arc::new(ArweaveAdapter::with_gateways(
+        matches
+            .values_of("arweave-api")
+            .unwrap()
+            .map(|gateway| gateway.to_string())
+            .collect(),
     ));
 
     let three_box_adapter = Arc::new(ThreeBoxAdapter::new(
         matches.value_of("3box-api").unwrap().to_string(),
     ));
 
-    let pg_read_replicas = matches.values_of("postgres-secondary-hosts");
+    let fork_base = matches
+        .value_of("fork-base")
+        .map(|url| url::Url::parse(url).expect("Invalid fork base URL"));
+
+    let pg_read_replicas: Vec<String> = match matches.values_of("postgres-secondary-hosts") {
+        Some(hosts) => hosts.map(|host| host.to_string()).collect(),
+        None => config
+            .store
+            .as_ref()
+            .map(|store| store.replica_urls())
+            .unwrap_or_default(),
+    };
 
     info!(logger, "Starting up");
 
@@ -430,9 +800,13 @@ async fn main() {
             });
 
     // Create a component and subgraph logger factory
-    let logger_factory = LoggerFactory::new(logger.clone(), elastic_config);
+    let logger_factory = LoggerFactory::new(logger.clone(), elastic_config)
+        .with_deployment_drains(config.log.drains());
 
-    // Try to create IPFS clients for each URL
+    // Try to create IPFS clients for each URL. When there are several, a
+    // node that can not be reached is only a warning, since requests fail
+    // over to the other nodes
+    let single_ipfs_node = ipfs_addresses.len() == 1;
     let ipfs_clients: Vec<_> = ipfs_addresses
         .into_iter()
         .map(|ipfs_address| {
@@ -465,12 +839,20 @@ async fn main() {
                 ipfs_test
                     .version()
                     .map_err(move |e| {
-                        error!(
+                        if single_ipfs_node {
+                            error!(
+                                ipfs_err_logger,
+                                "Is there an IPFS node running at \"{}\"?",
+                                SafeDisplay(ipfs_address_for_err),
+                            );
+                            panic!("Failed to connect to IPFS: {}", e);
+                        }
+                        warn!(
                             ipfs_err_logger,
-                            "Is there an IPFS node running at \"{}\"?",
+                            "Failed to connect to IPFS node at \"{}\", using the other nodes: {}",
                             SafeDisplay(ipfs_address_for_err),
+                            e
                         );
-                        panic!("Failed to connect to IPFS: {}", e);
                     })
                     .map_ok(move |_| {
                         info!(
@@ -482,12 +864,12 @@ async fn main() {
                     .await
             });
 
-            ipfs_client
+            (ipfs_address, ipfs_client)
         })
         .collect();
 
-    // Convert the client into a link resolver
-    let link_resolver = Arc::new(LinkResolver::from(ipfs_clients));
+    // Convert the clients into a link resolver
+    let link_resolver = Arc::new(LinkResolver::new(ipfs_clients));
 
     // Set up Prometheus registry
     let prometheus_registry = Arc::new(Registry::new());
@@ -498,8 +880,9 @@ async fn main() {
     let mut metrics_server =
         PrometheusMetricsServer::new(&logger_factory, prometheus_registry.clone());
 
-    // Ethereum clients
-    let mut eth_networks = [
+    // Ethereum clients; the ones from the command line replace those from
+    // the config file
+    let cli_networks: Vec<_> = [
         (ConnectionType::RPC, ethereum_rpc),
         (ConnectionType::IPC, ethereum_ipc),
         (ConnectionType::WS, ethereum_ws),
@@ -507,8 +890,32 @@ async fn main() {
     .iter()
     .cloned()
     .filter(|(_, values)| values.is_some())
-    .fold(
-        EthereumNetworks::new(),
+    .collect();
+    let initial_networks = if cli_networks.is_empty() {
+        if config.chains.is_empty() {
+            panic!(
+                "Ethereum providers are required; use --ethereum-rpc, --ethereum-ws, \
+                 --ethereum-ipc or a [chains] section in the config file"
+            );
+        }
+        config_ethereum_networks(
+            &logger,
+            &config.chains,
+            &chain_settings,
+            metrics_registry.clone(),
+        )
+    } else {
+        EthereumNetworks::new()
+    };
+    // Network aliases only come from the config file
+    let network_aliases = if cli_networks.is_empty() {
+        network_aliases
+    } else {
+        HashMap::new()
+    };
+    let store_network_aliases = network_aliases.clone();
+    let mut eth_networks = cli_networks.into_iter().fold(
+        initial_networks,
         |mut eth_networks, (connection_type, values)| match parse_ethereum_networks(
             logger.clone(),
             values.unwrap(),
@@ -533,6 +940,8 @@ async fn main() {
         "Connecting to Postgres";
         "url" => SafeDisplay(postgres_url.as_str()),
         "conn_pool_size" => store_conn_pool_size,
+        "query_conn_pool_size" => store_query_pool_size,
+        "metadata_conn_pool_size" => store_metadata_pool_size,
     );
 
     let connection_pool_registry = metrics_registry.clone();
@@ -546,6 +955,7 @@ async fn main() {
     let wait_stats = Arc::new(RwLock::new(MovingStats::default()));
 
     let postgres_conn_pool = create_connection_pool(
+        MAIN_POOL,
         postgres_url.clone(),
         store_conn_pool_size,
         &logger,
@@ -553,16 +963,46 @@ async fn main() {
         wait_stats.cheap_clone(),
     );
 
+    // Queries and metadata operations use the main pool unless they were
+    // given a pool of their own. The load manager throttles queries based on
+    // the wait times of the pools that serve them
+    let query_wait_stats = match store_query_pool_size {
+        Some(_) => Arc::new(RwLock::new(MovingStats::default())),
+        None => wait_stats.cheap_clone(),
+    };
+    let mut postgres_conn_pools = ConnectionPools::shared(postgres_conn_pool);
+    if let Some(size) = store_query_pool_size {
+        postgres_conn_pools.query = create_connection_pool(
+            "query",
+            postgres_url.clone(),
+            size,
+            &logger,
+            connection_pool_registry.cheap_clone(),
+            query_wait_stats.cheap_clone(),
+        );
+    }
+    if let Some(size) = store_metadata_pool_size {
+        postgres_conn_pools.metadata = create_connection_pool(
+            "metadata",
+            postgres_url.clone(),
+            size,
+            &logger,
+            connection_pool_registry.cheap_clone(),
+            Arc::new(RwLock::new(MovingStats::default())),
+        );
+    }
+
+    // Read replicas only serve queries
     let read_only_conn_pools: Vec<_> = pg_read_replicas
         .into_iter()
-        .flatten()
         .map(|url| {
             create_connection_pool(
-                url.to_string(),
+                MAIN_POOL,
+                url,
                 store_conn_pool_size,
                 &logger,
                 connection_pool_registry.cheap_clone(),
-                wait_stats.cheap_clone(),
+                query_wait_stats.cheap_clone(),
             )
         })
         .collect();
@@ -581,19 +1021,26 @@ async fn main() {
     let expensive_queries = read_expensive_queries().unwrap();
 
     graph::spawn(
-        futures::stream::FuturesOrdered::from_iter(stores_eth_networks.flatten().into_iter().map(
-            |(network_name, capabilities, eth_adapter)| {
-                info!(
-                    logger, "Connecting to Ethereum...";
-                    "network" => &network_name,
-                    "capabilities" => &capabilities
-                );
-                eth_adapter
-                    .net_identifiers(&logger)
-                    .map(move |network_identifier| (network_name, capabilities, network_identifier))
-                    .compat()
-            },
-        ))
+        futures::stream::FuturesOrdered::from_iter(
+            stores_eth_networks
+                .flatten()
+                .into_iter()
+                // Network aliases share the store of their network
+                .filter(|(network_name, _, _)| !network_aliases.contains_key(network_name))
+                .map(|(network_name, capabilities, eth_adapter)| {
+                    info!(
+                        logger, "Connecting to Ethereum...";
+                        "network" => &network_name,
+                        "capabilities" => &capabilities
+                    );
+                    eth_adapter
+                        .net_identifiers(&logger)
+                        .map(move |network_identifier| {
+                            (network_name, capabilities, network_identifier)
+                        })
+                        .compat()
+                }),
+        )
         .compat()
         .map_err(move |e| {
             error!(stores_error_logger, "Was a valid Ethereum node provided?");
@@ -613,33 +1060,48 @@ async fn main() {
                     StoreConfig {
                         postgres_url: postgres_url.clone(),
                         network_name: network_name.to_string(),
+                        attribute_cipher: None,
                     },
                     &stores_logger,
                     network_identifier,
                     chain_head_update_listener.clone(),
                     subscriptions.clone(),
-                    postgres_conn_pool.clone(),
+                    postgres_conn_pools.clone(),
                     read_only_conn_pools.clone(),
                     stores_metrics_registry.clone(),
                 )),
             )
         })
         .collect()
-        .map(|stores| HashMap::from_iter(stores.into_iter()))
+        .map(move |stores| {
+            let mut stores = HashMap::from_iter(stores.into_iter());
+            for (alias, network) in store_network_aliases {
+                let store = stores[&network].clone();
+                stores.insert(alias, store);
+            }
+            stores
+        })
         .and_then(move |stores| {
             let generic_store = stores.values().next().expect("error creating stores");
 
+            // Subgraphs deployed from local files are kept in the store
+            let link_resolver = Arc::new(
+                link_resolver
+                    .as_ref()
+                    .clone()
+                    .with_file_store(generic_store.clone()),
+            );
+
             let load_manager = Arc::new(LoadManager::new(
                 &logger,
-                wait_stats.clone(),
+                query_wait_stats.clone(),
                 expensive_queries,
                 metrics_registry.clone(),
             ));
-            let graphql_runner = Arc::new(GraphQlRunner::new(
-                &logger,
-                generic_store.clone(),
-                load_manager,
-            ));
+            let graphql_runner = Arc::new(
+                GraphQlRunner::new(&logger, generic_store.clone(), load_manager)
+                    .with_mutation_endpoints(mutation_endpoints),
+            );
             let mut graphql_server = GraphQLQueryServer::new(
                 &logger_factory,
                 graphql_metrics_registry,
@@ -653,11 +1115,66 @@ async fn main() {
                 generic_store.clone(),
             );
 
+            // Checks that decide whether the node reports itself as ready
+            let assignments_started = Arc::new(AtomicBool::new(false));
+            let mut health_checks: Vec<Arc<dyn HealthCheck>> = vec![
+                Arc::new(StoreCheck::new(generic_store.clone())),
+                Arc::new(AssignmentsCheck::new(assignments_started.clone())),
+            ];
+            let mut network_adapters: HashMap<String, Vec<Arc<dyn EthereumAdapter>>> =
+                HashMap::new();
+            for (network_name, _, eth_adapter) in eth_networks.flatten() {
+                network_adapters
+                    .entry(network_name)
+                    .or_default()
+                    .push(eth_adapter);
+            }
+            let mut networks = vec![];
+            for (network_name, eth_adapters) in network_adapters {
+                let store = stores.get(&network_name).expect("network with name");
+                let providers = eth_adapters.len();
+                let ethereum_adapter = eth_adapters[0].clone();
+                health_checks.push(Arc::new(ProviderCheck::new(
+                    &logger,
+                    &network_name,
+                    eth_adapters,
+                )));
+                let ingestion_check = if !disable_block_ingestor
+                    && !network_aliases.contains_key(&network_name)
+                {
+                    let check: Arc<dyn HealthCheck> =
+                        Arc::new(ChainHeadCheck::new(&network_name, store.clone()));
+                    health_checks.push(check.clone());
+                    Some(check)
+                } else {
+                    None
+                };
+                networks.push(NetworkInfo {
+                    name: network_name,
+                    kind: "ethereum".to_owned(),
+                    providers,
+                    chain_store: store.clone(),
+                    ethereum_adapter,
+                    ingestion_check,
+                });
+            }
+
+            let trigger_dry_run = Arc::new(TriggerDryRun::new(
+                &logger,
+                link_resolver.clone(),
+                graphql_runner.clone(),
+                stores.clone(),
+                eth_networks.clone(),
+                metrics_registry.clone(),
+            ));
             let mut index_node_server = IndexNodeServer::new(
                 &logger_factory,
                 graphql_runner.clone(),
                 generic_store.clone(),
                 node_id.clone(),
+                health_checks,
+                networks,
+                trigger_dry_run,
             );
 
             // Spawn Ethereum network indexers for all networks that are to be indexed
@@ -701,41 +1218,102 @@ async fn main() {
                     })
             };
 
-            if !disable_block_ingestor {
-                // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
-                // otherwise BlockStream will not work properly.
-                // BlockStream expects the blocks after the reorg threshold to be present in the
-                // database.
-                assert!(*ANCESTOR_COUNT >= *REORG_THRESHOLD);
+            // Replay blocks of a deployment without writing anything, then exit
+            if let Some((id, from, to)) = replay {
+                let runtime_host_builder = WASMRuntimeHostBuilder::new(
+                    eth_networks.clone(),
+                    link_resolver.clone(),
+                    stores.clone(),
+                    arweave_adapter,
+                    three_box_adapter,
+                );
+                let logger = logger.clone();
+                graph::spawn(async move {
+                    match replay_blocks(
+                        logger.clone(),
+                        link_resolver,
+                        graphql_runner,
+                        stores,
+                        eth_networks,
+                        |call_cache| runtime_host_builder.with_call_cache(call_cache),
+                        metrics_registry,
+                        id,
+                        from,
+                        to,
+                    )
+                    .await
+                    {
+                        Ok(blocks) => {
+                            blocks.iter().for_each(print_replayed_block);
+                            std::process::exit(0)
+                        }
+                        Err(e) => {
+                            error!(logger, "Failed to replay blocks: {}", e);
+                            std::process::exit(1)
+                        }
+                    }
+                });
+                return future::ok(());
+            }
 
+            if !disable_block_ingestor {
                 info!(logger, "Starting block ingestors");
 
-                // Create Ethereum block ingestors and spawn a thread to run each
+                // Create Ethereum block ingestors and spawn a thread to run each;
+                // network aliases rely on the block ingestor of their network
                 eth_networks
                     .networks
                     .iter()
+                    .filter(|(network_name, _)| !network_aliases.contains_key(*network_name))
                     .for_each(|(network_name, eth_adapters)| {
                         info!(
                             logger,
                             "Starting block ingestor for network";
                             "network_name" => &network_name
                         );
+                        let settings = chain_settings
+                            .get(network_name)
+                            .cloned()
+                            .unwrap_or(default_chain_settings);
+
+                        // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
+                        // otherwise BlockStream will not work properly.
+                        // BlockStream expects the blocks after the reorg threshold to be present in the
+                        // database.
+                        assert!(
+                            settings.ancestor_count >= settings.reorg_threshold,
+                            "the ancestor count of network `{}` must be at least its reorg threshold",
+                            network_name
+                        );
+
                         let eth_adapter = eth_adapters.cheapest().unwrap(); //Safe to unwrap since it cannot be empty
                         let block_ingestor = BlockIngestor::new(
                             stores.get(network_name).expect("network with name").clone(),
                             eth_adapter.clone(),
-                            *ANCESTOR_COUNT,
+                            settings.ancestor_count,
                             network_name.to_string(),
                             &logger_factory,
-                            block_polling_interval,
+                            settings.polling_interval,
                         )
-                        .expect("failed to create Ethereum block ingestor");
+                        .expect("failed to create Ethereum block ingestor")
+                        .with_batch_size(settings.ingestion_batch_size)
+                        .with_lease(generic_store.clone(), node_id.clone())
+                        .with_cache_check(settings.reorg_threshold, metrics_registry.clone())
+                        .expect("failed to register block cache metrics");
 
                         // Run the Ethereum block ingestor in the background
                         graph::spawn(block_ingestor.into_polling_stream());
                     });
             }
 
+            // Check version switching mode environment variable
+            let version_switching_mode = SubgraphVersionSwitchingMode::parse(
+                env::var_os("EXPERIMENTAL_SUBGRAPH_VERSION_SWITCHING_MODE")
+                    .unwrap_or_else(|| "instant".into())
+                    .to_str()
+                    .expect("invalid version switching mode"),
+            );
+
             let block_stream_builder = BlockStreamBuilder::new(
                 generic_store.clone(),
                 stores.clone(),
@@ -744,6 +1322,18 @@ async fn main() {
                 *REORG_THRESHOLD,
                 metrics_registry.clone(),
             );
+            let block_stream_builder = chain_settings.iter().fold(
+                block_stream_builder,
+                |builder, (network_name, settings)| {
+                    builder.with_reorg_threshold(network_name.clone(), settings.reorg_threshold)
+                },
+            );
+            let block_stream_builder = config.deployment.follow_settings().into_iter().fold(
+                block_stream_builder,
+                |builder, (deployment, follow)| builder.with_follow_settings(deployment, follow),
+            );
+            let block_stream_builder =
+                block_stream_builder.with_version_switching_mode(version_switching_mode);
             let runtime_host_builder = WASMRuntimeHostBuilder::new(
                 eth_networks.clone(),
                 link_resolver.clone(),
@@ -760,7 +1350,12 @@ async fn main() {
                 block_stream_builder,
                 metrics_registry.clone(),
                 graphql_runner.cheap_clone(),
+                fork_base,
             );
+            graph::spawn(shutdown_on_signal(
+                logger.clone(),
+                subgraph_instance_manager.shutdown_handle(),
+            ));
 
             // Create IPFS-based subgraph provider
             let mut subgraph_provider = IpfsSubgraphAssignmentProvider::new(
@@ -777,14 +1372,6 @@ async fn main() {
                     .compat(),
             );
 
-            // Check version switching mode environment variable
-            let version_switching_mode = SubgraphVersionSwitchingMode::parse(
-                env::var_os("EXPERIMENTAL_SUBGRAPH_VERSION_SWITCHING_MODE")
-                    .unwrap_or_else(|| "instant".into())
-                    .to_str()
-                    .expect("invalid version switching mode"),
-            );
-
             // Create named subgraph provider for resolving subgraph name->ID mappings
             let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
                 &logger_factory,
@@ -795,21 +1382,47 @@ async fn main() {
                 eth_networks.clone(),
                 node_id.clone(),
                 version_switching_mode,
+                Some(placer.clone()),
             ));
             graph::spawn(
                 subgraph_registrar
                     .start()
                     .map_err(|e| panic!("failed to initialize subgraph provider {}", e))
+                    .map(move |()| assignments_started.store(true, Ordering::SeqCst))
                     .compat(),
             );
 
+            // Record heartbeats for this node and move deployments away from
+            // dead nodes if requested
+            let failover = NodeFailover::new(
+                &logger_factory,
+                generic_store.clone(),
+                node_id.clone(),
+                Some(placer),
+                matches.is_present("failover"),
+            );
+            graph::spawn(failover.run());
+
+            // Tune autovacuum and refresh statistics for deployment tables
+            graph::spawn(StoreMaintenance::new(&logger, generic_store.as_ref().clone()).run());
+
+            // Remove old history from deployments that ask for that
+            graph::spawn(Pruner::new(&logger, generic_store.as_ref().clone()).run());
+
+            // Remove deployments that nothing uses any more if requested
+            if UnusedDeploymentRemover::enabled() {
+                graph::spawn(
+                    UnusedDeploymentRemover::new(&logger, generic_store.as_ref().clone()).run(),
+                );
+            }
+
             // Start admin JSON-RPC server.
             let json_rpc_server = JsonRpcServer::serve(
                 json_rpc_port,
                 http_port,
                 ws_port,
                 subgraph_registrar.clone(),
-                node_id.clone(),
+                admin_tokens,
                 logger.clone(),
             )
             .expect("failed to start JSON-RPC admin server");
@@ -835,23 +1448,25 @@ async fn main() {
                     async move {
                         subgraph_registrar.create_subgraph(name.clone()).await?;
                         subgraph_registrar
-                            .create_subgraph_version(name, subgraph_id, node_id)
+                            .create_subgraph_version(name, subgraph_id, Some(node_id))
                             .await
                     }
                     .map_err(|e| panic!("Failed to deploy subgraph from `--subgraph` flag: {}", e)),
                 );
             }
 
-            // Serve GraphQL queries over HTTP
-            graph::spawn(
-                graphql_server
-                    .serve(http_port, ws_port)
-                    .expect("Failed to start GraphQL query server")
-                    .compat(),
-            );
+            if serve_queries {
+                // Serve GraphQL queries over HTTP
+                graph::spawn(
+                    graphql_server
+                        .serve(http_port, ws_port)
+                        .expect("Failed to start GraphQL query server")
+                        .compat(),
+                );
 
-            // Serve GraphQL subscriptions over WebSockets
-            graph::spawn(subscription_server.serve(ws_port));
+                // Serve GraphQL subscriptions over WebSockets
+                graph::spawn(subscription_server.serve(ws_port));
+            }
 
             // Run the index node server
             graph::spawn(
@@ -907,6 +1522,39 @@ async fn main() {
     futures::future::pending::<()>().await;
 }
 
+/// Shut down on SIGTERM or SIGINT. Subgraphs stop before their next block,
+/// and the node exits once the blocks that are being processed have been
+/// written, so that every subgraph resumes right after its last block when
+/// the node is started again. A second signal, or `GRAPH_SHUTDOWN_TIMEOUT`
+/// passing, makes the node exit right away.
+async fn shutdown_on_signal(logger: Logger, indexing: Arc<IndexingShutdown>) {
+    let terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    let interrupt = signal(SignalKind::interrupt()).expect("failed to listen for SIGINT");
+    let mut signals = futures::stream::select(terminate, interrupt).boxed();
+    signals.next().await;
+
+    info!(
+        logger,
+        "Shutting down, waiting for blocks that are being processed";
+        "blocks" => indexing.blocks_in_progress(),
+        "timeout_s" => SHUTDOWN_TIMEOUT.as_secs(),
+    );
+    let finished = futures::select! {
+        finished = indexing.shutdown(*SHUTDOWN_TIMEOUT).boxed().fuse() => finished,
+        _ = signals.next().fuse() => false,
+    };
+    if finished {
+        info!(logger, "Shutdown complete");
+    } else {
+        warn!(
+            logger,
+            "Exiting before all blocks that were being processed were written";
+            "blocks" => indexing.blocks_in_progress(),
+        );
+    }
+    std::process::exit(0);
+}
+
 /// Parses an Ethereum connection string and returns the network name and Ethereum adapter.
 fn parse_ethereum_networks(
     logger: Logger,
@@ -988,22 +1636,131 @@ fn parse_ethereum_networks(
             networks.insert(
                 name.to_string(),
                 capabilities,
-                Arc::new(graph_chain_ethereum::EthereumAdapter::new(
-                    url,
-                    transport,
-                    eth_rpc_metrics.clone(),
-                )) as Arc<dyn EthereumAdapter>,
+                Arc::new(
+                    graph_chain_ethereum::EthereumAdapter::new(
+                        url,
+                        transport.with_tracing(&logger, url),
+                        eth_rpc_metrics.clone(),
+                    )
+                    .with_network(name),
+                ) as Arc<dyn EthereumAdapter>,
             );
             Ok(networks)
         }
     })
 }
 
+fn network_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("network")
+        .takes_value(true)
+        .required(true)
+        .long("network")
+        .value_name("NETWORK_NAME")
+        .help("The network whose blocks to look at")
+}
+
+fn hash_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("hash")
+        .required(true)
+        .multiple(true)
+        .value_name("BLOCK_HASH")
+        .help("The hashes of the blocks")
+}
+
+fn deployment_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("deployment")
+        .required(true)
+        .multiple(true)
+        .value_name("IPFS_HASH")
+        .help("The ids of the deployments")
+}
+
+/// Creates the Ethereum adapters for the providers in the config file.
+fn config_ethereum_networks(
+    logger: &Logger,
+    chains: &BTreeMap<String, config::Chain>,
+    settings: &HashMap<String, ChainSettings>,
+    registry: Arc<MetricsRegistry>,
+) -> EthereumNetworks {
+    let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(registry));
+    let mut networks = EthereumNetworks::new();
+    for (name, chain) in chains {
+        for provider in &chain.providers {
+            let capabilities = provider.capabilities();
+            info!(
+                logger,
+                "Creating transport";
+                "network" => name,
+                "url" => SafeDisplay(&provider.url),
+                "transport" => provider.transport.to_string(),
+                "capabilities" => capabilities
+            );
+
+            let (transport_event_loop, transport) =
+                provider.transport().unwrap_or_else(|e| panic!("{}", e));
+
+            // If we drop the event loop the transport will stop working.
+            // For now it's fine to just leak it.
+            std::mem::forget(transport_event_loop);
+
+            networks.insert(
+                name.to_string(),
+                capabilities,
+                Arc::new(
+                    graph_chain_ethereum::EthereumAdapter::new(
+                        &provider.url,
+                        transport.with_tracing(logger, &provider.url),
+                        eth_rpc_metrics.clone(),
+                    )
+                    .with_network(name)
+                    .with_receipt_batch_size(settings[name].receipt_batch_size)
+                    .with_multicall(settings[name].multicall)
+                    .with_custom_rpc_methods(provider.custom_rpc_methods.clone()),
+                ) as Arc<dyn EthereumAdapter>,
+            );
+        }
+    }
+    networks
+}
+
+fn print_replayed_block(block: &ReplayedBlock) {
+    println!("Block {}", block.block_ptr);
+    for modification in &block.modifications {
+        println!("  {:?}", modification);
+    }
+    for error in &block.deterministic_errors {
+        println!(
+            "  Handler `{}` failed: {}",
+            error.handler.as_deref().unwrap_or(""),
+            error.message
+        );
+    }
+    for data_source in &block.created_data_sources {
+        println!(
+            "  Created data source from template `{}`, its handlers were not run",
+            data_source.template.name
+        );
+    }
+    for call in &block.calls {
+        println!(
+            "  Call to {:x} with 0x{} returned 0x{}{}",
+            call.address,
+            hex::encode(&call.call_data),
+            hex::encode(&call.return_value),
+            if call.cached { " (cached)" } else { "" }
+        );
+    }
+    match &block.proof_of_indexing {
+        Some(poi) => println!("  Proof of indexing 0x{}", hex::encode(poi)),
+        None => println!("  The deployment does not support proofs of indexing"),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::parse_ethereum_networks;
     use crate::ConnectionType;
-    use clap::{App, Arg};
+    use clap::{App, AppSettings, Arg, SubCommand};
     use graph::components::ethereum::NodeCapabilities;
     use graph::log::logger;
     use graph_core::MetricsRegistry;
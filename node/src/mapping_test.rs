@@ -0,0 +1,316 @@
+//! `graph-node mapping-test <tests>`: run unit tests of a mapping against
+//! an in-memory store, without a database or an Ethereum node. The tests
+//! are described by a JSON file like
+//!
+//! ```json
+//! {
+//!   "schema": "schema.graphql",
+//!   "mapping": "build/Token/Token.wasm",
+//!   "apiVersion": "0.0.4",
+//!   "abis": { "Token": "abis/Token.json" },
+//!   "address": "0x6b175474e89094c44da98b954eedeac495271d0f",
+//!   "steps": [
+//!     { "mockCall": { "address": "0x6b17...", "function": "symbol",
+//!                     "args": [], "result": [["string", "DAI"]] } },
+//!     { "event": { "handler": "handleTransfer", "address": "0x6b17...", "block": 1,
+//!                  "params": [["from", "address", "0x..."], ["value", "uint256", "10"]] } },
+//!     { "block": { "handler": "handleBlock", "block": 2 } },
+//!     { "file": { "handler": "handleMetadata", "block": 3, "data": "{\"name\": \"DAI\"}" } },
+//!     { "assert": { "entity": "Token", "id": "0x6b17...", "fields": { "symbol": "DAI" } } },
+//!     { "assert": { "entity": "Account", "id": "0x0000...", "exists": false } }
+//!   ]
+//! }
+//! ```
+//!
+//! Paths are relative to the directory of the JSON file. The steps run in
+//! order against one in-memory store; calls that are not mocked revert.
+
+use clap::ArgMatches;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use graph::prelude::ethabi::param_type::Reader;
+use graph::prelude::ethabi::token::{LenientTokenizer, Tokenizer};
+use graph::prelude::ethabi::{Contract, LogParam, Token};
+use graph::prelude::serde_json::Value as JsonValue;
+use graph::prelude::web3::types::{Address, Log, Transaction, H256, U256, U64};
+use graph::prelude::*;
+use graph_mock::MockMetricsRegistry;
+use graph_runtime_wasm::testing::MappingTest;
+
+/// Run the tests in the file given in `matches`. Returns `false` if a
+/// handler failed, an assertion did not hold or the tests could not be run
+pub async fn run(logger: &Logger, matches: &ArgMatches<'_>) -> bool {
+    let path = PathBuf::from(matches.value_of("tests").expect("clap requires the tests"));
+    let logger = logger.clone();
+
+    // Mappings block while they run, so keep them off the async threads
+    let result = graph::spawn_blocking_async_allow_panic(move || run_tests(logger, &path)).await;
+    match result {
+        Ok(0) => {
+            println!("All assertions passed");
+            true
+        }
+        Ok(failed) => {
+            println!("{} assertion(s) failed", failed);
+            false
+        }
+        Err(e) => {
+            println!("{}", e);
+            false
+        }
+    }
+}
+
+/// Set up the data source described by the file at `path` and run its
+/// steps in order. Return the number of failed assertions.
+fn run_tests(logger: Logger, path: &Path) -> Result<usize, Error> {
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    let file = File::open(path)
+        .map_err(|e| format_err!("failed to open tests `{}`: {}", path.display(), e))?;
+    let spec: JsonValue = serde_json::from_reader(file)
+        .map_err(|e| format_err!("failed to parse tests `{}`: {}", path.display(), e))?;
+
+    let schema = std::fs::read_to_string(base.join(string(&spec, "schema")?))
+        .map_err(|e| format_err!("failed to read schema: {}", e))?;
+    let runtime = std::fs::read(base.join(string(&spec, "mapping")?))
+        .map_err(|e| format_err!("failed to read mapping: {}", e))?;
+
+    let mut abis = vec![];
+    if let Some(JsonValue::Object(entries)) = spec.get("abis") {
+        for (name, abi_path) in entries {
+            let abi_path = abi_path.as_str().unwrap_or_default();
+            let file = File::open(base.join(abi_path))
+                .map_err(|e| format_err!("failed to open ABI `{}`: {}", abi_path, e))?;
+            abis.push(MappingABI {
+                name: name.clone(),
+                contract: Contract::load(file)
+                    .map_err(|e| format_err!("failed to parse ABI `{}`: {}", abi_path, e))?,
+                errors: vec![],
+                immutable_calls: vec![],
+                link: Link {
+                    link: abi_path.to_owned(),
+                },
+            });
+        }
+    }
+
+    let data_source = DataSource {
+        kind: String::from("ethereum/contract"),
+        name: String::from("test"),
+        network: Some(String::from("mainnet")),
+        source: Source {
+            address: match spec.get("address") {
+                Some(_) => Some(address(&spec, "address")?),
+                None => None,
+            },
+            addresses: vec![],
+            address_file: None,
+            abi: String::new(),
+            subgraph: None,
+            start_block: 0,
+            implementations: vec![],
+            file: None,
+        },
+        mapping: Mapping {
+            kind: String::from("ethereum/events"),
+            api_version: spec
+                .get("apiVersion")
+                .and_then(JsonValue::as_str)
+                .unwrap_or("0.0.4")
+                .to_owned(),
+            language: String::from("wasm/assemblyscript"),
+            entities: vec![],
+            abis,
+            event_handlers: vec![],
+            call_handlers: vec![],
+            block_handlers: vec![],
+            entity_handlers: vec![],
+            handler: None,
+            link: Link {
+                link: string(&spec, "mapping")?.to_owned(),
+            },
+            runtime: Arc::new(runtime),
+        },
+        templates: vec![],
+        context: None,
+    };
+
+    let test = MappingTest::new(
+        logger,
+        SubgraphDeploymentId::new("mappingTest").unwrap(),
+        &schema,
+        data_source,
+        Arc::new(MockMetricsRegistry::new()),
+    )
+    .map_err(|e| format_err!("failed to load mapping: {}", e))?;
+
+    let steps = spec
+        .get("steps")
+        .and_then(JsonValue::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let mut failed = 0;
+    for step in steps {
+        if let Some(call) = step.get("mockCall") {
+            test.mock_call(
+                address(call, "address")?,
+                string(call, "function")?,
+                tokens(call, "args")?,
+                tokens(call, "result")?,
+            );
+        } else if let Some(event) = step.get("event") {
+            let handler = string(event, "handler")?;
+            let log = Log {
+                address: address(event, "address")?,
+                ..Default::default()
+            };
+            let transaction = Transaction {
+                transaction_index: Some(0.into()),
+                ..Default::default()
+            };
+            let result = test.handle_log(handler, block(event), transaction, log, params(event)?);
+            report(handler, result)?;
+        } else if let Some(trigger) = step.get("block") {
+            let handler = string(trigger, "handler")?;
+            report(handler, test.handle_block(handler, block(trigger)))?;
+        } else if let Some(file) = step.get("file") {
+            let handler = string(file, "handler")?;
+            let data = string(file, "data")?.as_bytes().to_vec();
+            report(handler, test.handle_file(handler, block(file), data))?;
+        } else if let Some(assertion) = step.get("assert") {
+            if !check(&test, assertion)? {
+                failed += 1;
+            }
+        } else {
+            return Err(format_err!("unknown step: {}", step));
+        }
+    }
+    Ok(failed)
+}
+
+fn report(
+    handler: &str,
+    result: Result<Vec<EntityModification>, MappingError>,
+) -> Result<(), Error> {
+    let modifications = result.map_err(|e| format_err!("{} failed: {}", handler, e))?;
+    println!("{}: {} entity change(s)", handler, modifications.len());
+    Ok(())
+}
+
+/// Check that an entity has the expected fields, or that it does not exist
+/// if `exists` is `false`.
+fn check(test: &MappingTest, assertion: &JsonValue) -> Result<bool, Error> {
+    let entity_type = string(assertion, "entity")?;
+    let id = string(assertion, "id")?;
+    let entity = test.entity(entity_type, id);
+
+    if assertion.get("exists").and_then(JsonValue::as_bool) == Some(false) {
+        if entity.is_some() {
+            println!("{}({}) exists but should not", entity_type, id);
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+
+    let entity = match entity {
+        Some(entity) => entity,
+        None => {
+            println!("{}({}) does not exist", entity_type, id);
+            return Ok(false);
+        }
+    };
+
+    let mut passed = true;
+    if let Some(JsonValue::Object(fields)) = assertion.get("fields") {
+        for (field, expected) in fields {
+            let actual = entity
+                .get(field)
+                .map(|value| value.to_string())
+                .unwrap_or_else(|| "null".to_owned());
+            let expected = match expected {
+                JsonValue::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            if actual != expected {
+                println!(
+                    "{}({}).{}: expected `{}` but found `{}`",
+                    entity_type, id, field, expected, actual
+                );
+                passed = false;
+            }
+        }
+    }
+    Ok(passed)
+}
+
+fn string<'a>(value: &'a JsonValue, field: &str) -> Result<&'a str, Error> {
+    value
+        .get(field)
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| format_err!("missing string `{}` in {}", field, value))
+}
+
+fn address(value: &JsonValue, field: &str) -> Result<Address, Error> {
+    let s = string(value, field)?;
+    Address::from_str(s.trim_start_matches("0x"))
+        .map_err(|e| format_err!("invalid address `{}`: {}", s, e))
+}
+
+/// A block with the number given in `value`; handlers need a hash, so it
+/// gets a made up one
+fn block(value: &JsonValue) -> LightEthereumBlock {
+    let number = value.get("block").and_then(JsonValue::as_u64).unwrap_or(0);
+    LightEthereumBlock {
+        hash: Some(H256::from_low_u64_be(number)),
+        number: Some(U64::from(number)),
+        timestamp: U256::from(number),
+        ..Default::default()
+    }
+}
+
+/// Parse a token written as `[type, value]`, e.g. `["uint256", "10"]`.
+fn token(value: &JsonValue) -> Result<Token, Error> {
+    let (kind, text) = match value.as_array().map(Vec::as_slice) {
+        Some([JsonValue::String(kind), JsonValue::String(text)]) => (kind, text),
+        _ => {
+            return Err(format_err!(
+                "tokens must be written as [type, value]: {}",
+                value
+            ))
+        }
+    };
+    let kind = Reader::read(kind).map_err(|e| format_err!("invalid ABI type `{}`: {}", kind, e))?;
+    LenientTokenizer::tokenize(&kind, text)
+        .map_err(|e| format_err!("invalid value `{}`: {}", text, e))
+}
+
+fn tokens(value: &JsonValue, field: &str) -> Result<Vec<Token>, Error> {
+    value
+        .get(field)
+        .and_then(JsonValue::as_array)
+        .map(|tokens| tokens.iter().map(token).collect())
+        .unwrap_or_else(|| Ok(vec![]))
+}
+
+/// Parse event parameters written as `[name, type, value]`.
+fn params(value: &JsonValue) -> Result<Vec<LogParam>, Error> {
+    let params = match value.get("params").and_then(JsonValue::as_array) {
+        Some(params) => params,
+        None => return Ok(vec![]),
+    };
+    params
+        .iter()
+        .map(|param| match param.as_array().map(Vec::as_slice) {
+            Some([JsonValue::String(name), kind, value]) => Ok(LogParam {
+                name: name.clone(),
+                value: token(&JsonValue::Array(vec![kind.clone(), value.clone()]))?,
+            }),
+            _ => Err(format_err!(
+                "parameters must be written as [name, type, value]: {}",
+                param
+            )),
+        })
+        .collect()
+}
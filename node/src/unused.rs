@@ -0,0 +1,104 @@
+//! `graph-node unused ...`: look at the deployments that nothing uses any
+//! more, and remove or keep them, without starting to index anything.
+
+use clap::ArgMatches;
+
+use graph::prelude::Error;
+use graph::util::security::SafeDisplay;
+use graph_store_postgres::unused::UnusedDeployments;
+
+/// Run the `unused` subcommand given in `matches`. Returns `false` if it
+/// failed or could not do what was asked for some deployments
+pub fn run(matches: &ArgMatches<'_>, postgres_url: &str) -> bool {
+    let (command, args) = match matches.subcommand() {
+        (command, Some(args)) => (command, args),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+    let unused = match UnusedDeployments::connect(postgres_url) {
+        Ok(unused) => unused,
+        Err(e) => {
+            println!("Failed to connect to {}: {}", SafeDisplay(postgres_url), e);
+            return false;
+        }
+    };
+
+    let deployments: Vec<&str> = args
+        .values_of("deployment")
+        .map(|values| values.collect())
+        .unwrap_or_default();
+    let result = match command {
+        "list" => list(&unused, args.is_present("all")),
+        "record" => record(&unused),
+        "remove" => remove(&unused, deployments),
+        "restore" => restore(&unused, deployments),
+        _ => unreachable!("unknown unused subcommand {}", command),
+    };
+    result.unwrap_or_else(|e| {
+        println!("{}", e);
+        false
+    })
+}
+
+fn list(unused: &UnusedDeployments, all: bool) -> Result<bool, Error> {
+    let deployments = unused.list(all)?;
+    if deployments.is_empty() {
+        println!("No unused deployments");
+    }
+    for deployment in deployments {
+        match deployment.removed_at {
+            Some(removed_at) => println!(
+                "{}: unused since {}, removed at {}",
+                deployment.deployment, deployment.unused_at, removed_at
+            ),
+            None => println!(
+                "{}: unused since {}",
+                deployment.deployment, deployment.unused_at
+            ),
+        }
+    }
+    Ok(true)
+}
+
+fn record(unused: &UnusedDeployments) -> Result<bool, Error> {
+    let (forgotten, recorded) = unused.record()?;
+    for deployment in forgotten {
+        println!("{}: used again, will be kept", deployment);
+    }
+    for deployment in recorded {
+        println!("{}: unused", deployment);
+    }
+    Ok(true)
+}
+
+/// Remove `deployments`, or all unused deployments whose grace period has
+/// passed if none are given
+fn remove(unused: &UnusedDeployments, deployments: Vec<&str>) -> Result<bool, Error> {
+    let deployments = if deployments.is_empty() {
+        unused.expired()?
+    } else {
+        deployments.into_iter().map(str::to_owned).collect()
+    };
+    let mut ok = true;
+    for deployment in deployments {
+        if unused.remove(&deployment)? {
+            println!("{}: removed", deployment);
+        } else {
+            ok = false;
+            println!("{}: not unused, kept", deployment);
+        }
+    }
+    Ok(ok)
+}
+
+fn restore(unused: &UnusedDeployments, deployments: Vec<&str>) -> Result<bool, Error> {
+    let mut ok = true;
+    for deployment in deployments {
+        if unused.restore(deployment)? {
+            println!("{}: restored", deployment);
+        } else {
+            ok = false;
+            println!("{}: not unused or already removed", deployment);
+        }
+    }
+    Ok(ok)
+}
@@ -418,6 +418,29 @@ pub(crate) struct AscEthereumBlock {
     pub size: AscPtr<AscBigInt>,
 }
 
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumBlock_0_0_5 {
+    pub hash: AscPtr<AscH256>,
+    pub parent_hash: AscPtr<AscH256>,
+    pub uncles_hash: AscPtr<AscH256>,
+    pub author: AscPtr<AscH160>,
+    pub state_root: AscPtr<AscH256>,
+    pub transactions_root: AscPtr<AscH256>,
+    pub receipts_root: AscPtr<AscH256>,
+    pub number: AscPtr<AscBigInt>,
+    pub gas_used: AscPtr<AscBigInt>,
+    pub gas_limit: AscPtr<AscBigInt>,
+    pub timestamp: AscPtr<AscBigInt>,
+    pub difficulty: AscPtr<AscBigInt>,
+    pub total_difficulty: AscPtr<AscBigInt>,
+    pub size: AscPtr<AscBigInt>,
+    pub nonce: AscPtr<Bytes>,
+    pub mix_hash: AscPtr<AscH256>,
+    pub logs_bloom: AscPtr<Bytes>,
+    pub extra_data: AscPtr<Bytes>,
+}
+
 #[repr(C)]
 #[derive(AscType)]
 pub(crate) struct AscEthereumTransaction {
@@ -445,19 +468,35 @@ pub(crate) struct AscEthereumTransaction_0_0_2 {
 
 #[repr(C)]
 #[derive(AscType)]
-pub(crate) struct AscEthereumEvent<T>
+pub(crate) struct AscEthereumEvent<T, B>
 where
     T: AscType,
+    B: AscType,
 {
     pub address: AscPtr<AscAddress>,
     pub log_index: AscPtr<AscBigInt>,
     pub transaction_log_index: AscPtr<AscBigInt>,
     pub log_type: AscPtr<AscString>,
-    pub block: AscPtr<AscEthereumBlock>,
+    pub block: AscPtr<B>,
     pub transaction: AscPtr<T>,
     pub params: AscPtr<AscLogParamArray>,
 }
 
+/// The event of wildcard event handlers, which extends the event of
+/// `apiVersion` 0.0.5 with the name of the event
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumWildcardEvent {
+    pub address: AscPtr<AscAddress>,
+    pub log_index: AscPtr<AscBigInt>,
+    pub transaction_log_index: AscPtr<AscBigInt>,
+    pub log_type: AscPtr<AscString>,
+    pub block: AscPtr<AscEthereumBlock_0_0_5>,
+    pub transaction: AscPtr<AscEthereumTransaction_0_0_2>,
+    pub params: AscPtr<AscLogParamArray>,
+    pub name: AscPtr<AscString>,
+}
+
 #[repr(C)]
 #[derive(AscType)]
 pub(crate) struct AscEthereumCall {
@@ -470,13 +509,28 @@ pub(crate) struct AscEthereumCall {
 
 #[repr(C)]
 #[derive(AscType)]
-pub(crate) struct AscEthereumCall_0_0_3 {
+pub(crate) struct AscEthereumCall_0_0_3<B>
+where
+    B: AscType,
+{
+    pub to: AscPtr<AscAddress>,
+    pub from: AscPtr<AscAddress>,
+    pub block: AscPtr<B>,
+    pub transaction: AscPtr<AscEthereumTransaction>,
+    pub inputs: AscPtr<AscLogParamArray>,
+    pub outputs: AscPtr<AscLogParamArray>,
+}
+
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumCall_0_0_5 {
     pub to: AscPtr<AscAddress>,
     pub from: AscPtr<AscAddress>,
-    pub block: AscPtr<AscEthereumBlock>,
+    pub block: AscPtr<AscEthereumBlock_0_0_5>,
     pub transaction: AscPtr<AscEthereumTransaction>,
     pub inputs: AscPtr<AscLogParamArray>,
     pub outputs: AscPtr<AscLogParamArray>,
+    pub error: AscPtr<AscString>,
 }
 
 #[repr(C)]
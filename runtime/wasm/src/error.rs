@@ -0,0 +1,60 @@
+use graph::prelude::*;
+
+/// Error returned by host exports. The variant decides whether a trap caused by the error fails
+/// the subgraph or whether the handler should be retried.
+#[derive(Debug)]
+pub(crate) enum HostExportError {
+    /// The host export was misused or got input that it will never be able to handle, such as
+    /// an unknown template name or a value that does not parse.
+    Deterministic(anyhow::Error),
+
+    /// Some resource outside of the mapping, like the store, IPFS or an Ethereum node, failed.
+    Unknown(anyhow::Error),
+}
+
+impl HostExportError {
+    pub fn deterministic(e: impl Into<anyhow::Error>) -> Self {
+        HostExportError::Deterministic(e.into())
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        match self {
+            HostExportError::Deterministic(_) => true,
+            HostExportError::Unknown(_) => false,
+        }
+    }
+}
+
+impl From<anyhow::Error> for HostExportError {
+    fn from(e: anyhow::Error) -> Self {
+        HostExportError::Unknown(e)
+    }
+}
+
+impl From<MappingError> for HostExportError {
+    fn from(e: MappingError) -> Self {
+        match e {
+            MappingError::Deterministic(e) => HostExportError::Deterministic(e),
+            MappingError::Unknown(e) => HostExportError::Unknown(e),
+        }
+    }
+}
+
+impl From<HostExportError> for wasmtime::Trap {
+    fn from(e: HostExportError) -> Self {
+        match e {
+            HostExportError::Deterministic(e) | HostExportError::Unknown(e) => e.into(),
+        }
+    }
+}
+
+/// Marks the error as deterministic, for use with `map_err`.
+pub(crate) trait DeterministicResultExt<T> {
+    fn deterministic(self) -> Result<T, HostExportError>;
+}
+
+impl<T, E: Into<anyhow::Error>> DeterministicResultExt<T> for Result<T, E> {
+    fn deterministic(self) -> Result<T, HostExportError> {
+        self.map_err(HostExportError::deterministic)
+    }
+}
@@ -1,12 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 
-use anyhow::ensure;
+use anyhow::{anyhow, ensure};
 use async_trait::async_trait;
-use ethabi::{LogParam, RawLog};
+use ethabi::{Contract, LogParam, RawLog};
 use futures::sync::mpsc::Sender;
 use futures03::channel::oneshot::channel;
+use lazy_static::lazy_static;
 use semver::{Version, VersionReq};
 use slog::{o, OwnedKV};
 use strum::AsStaticRef as _;
@@ -22,13 +23,25 @@ use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
 use graph::util;
-use web3::types::{Log, Transaction};
+use web3::types::{Log, Transaction, H256};
 
 use crate::host_exports::HostExports;
 use crate::mapping::{MappingContext, MappingRequest, MappingTrigger};
 
 pub(crate) const TIMEOUT_ENV_VAR: &str = "GRAPH_MAPPING_HANDLER_TIMEOUT";
 
+lazy_static! {
+    /// Handlers that take longer than this are logged as slow.
+    static ref SLOW_HANDLER_THRESHOLD: Duration =
+        std::env::var("GRAPH_MAPPING_SLOW_HANDLER_THRESHOLD")
+            .ok()
+            .map(|s| u64::from_str(&s).unwrap_or_else(|_| {
+                panic!("failed to parse env var GRAPH_MAPPING_SLOW_HANDLER_THRESHOLD")
+            }))
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_secs(5));
+}
+
 struct RuntimeHostConfig {
     subgraph_id: SubgraphDeploymentId,
     mapping: Mapping,
@@ -36,6 +49,8 @@ struct RuntimeHostConfig {
     data_source_name: String,
     data_source_context: Option<DataSourceContext>,
     contract: Source,
+    source_subgraph: Option<SubgraphDeploymentId>,
+    arweave_file: Option<String>,
     templates: Arc<Vec<DataSourceTemplate>>,
 }
 
@@ -45,6 +60,7 @@ pub struct RuntimeHostBuilder<S> {
     stores: HashMap<String, Arc<S>>,
     arweave_adapter: Arc<dyn ArweaveAdapter>,
     three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+    call_cache: Option<Arc<dyn EthereumCallCache>>,
 }
 
 impl<S> Clone for RuntimeHostBuilder<S>
@@ -58,13 +74,14 @@ where
             stores: self.stores.clone(),
             arweave_adapter: self.arweave_adapter.cheap_clone(),
             three_box_adapter: self.three_box_adapter.cheap_clone(),
+            call_cache: self.call_cache.clone(),
         }
     }
 }
 
 impl<S> RuntimeHostBuilder<S>
 where
-    S: Store + SubgraphDeploymentStore + EthereumCallCache,
+    S: Store + SubgraphDeploymentStore + EthereumCallCache + ChainStore,
 {
     pub fn new(
         ethereum_networks: EthereumNetworks,
@@ -79,13 +96,23 @@ where
             stores,
             arweave_adapter,
             three_box_adapter,
+            call_cache: None,
+        }
+    }
+
+    /// Use `call_cache` for the results of contract calls instead of the
+    /// store of the network
+    pub fn with_call_cache(self, call_cache: Arc<dyn EthereumCallCache>) -> Self {
+        RuntimeHostBuilder {
+            call_cache: Some(call_cache),
+            ..self
         }
     }
 }
 
 impl<S> RuntimeHostBuilderTrait for RuntimeHostBuilder<S>
 where
-    S: Send + Sync + 'static + Store + SubgraphDeploymentStore + EthereumCallCache,
+    S: Send + Sync + 'static + Store + SubgraphDeploymentStore + EthereumCallCache + ChainStore,
 {
     type Host = RuntimeHost;
     type Req = MappingRequest;
@@ -130,13 +157,17 @@ where
         let required_capabilities = NodeCapabilities {
             traces: data_source.mapping.calls_host_fn("ethereum.call"),
             archive: data_source.mapping.has_block_handler_with_call_filter()
-                || data_source.mapping.has_call_handler(),
+                || data_source.mapping.has_call_handler()
+                || data_source.mapping.calls_host_fn("ethereum.callAtBlock"),
         };
 
         let ethereum_adapter = self
             .ethereum_networks
             .adapter_with_capabilities(network_name.clone(), &required_capabilities)?;
 
+        let source_subgraph = data_source.source_subgraph().cloned();
+        let arweave_file = data_source.arweave_file().map(str::to_owned);
+
         // Detect whether the subgraph uses templates in data sources, which are
         // deprecated, or the top-level templates field.
         let templates = match top_level_templates.is_empty() {
@@ -144,18 +175,28 @@ where
             true => Arc::new(data_source.templates),
         };
 
+        let call_cache = match &self.call_cache {
+            Some(call_cache) => call_cache.clone(),
+            None => store.clone(),
+        };
+
         RuntimeHost::new(
             ethereum_adapter.clone(),
-            self.link_resolver.clone(),
             store.clone(),
+            self.link_resolver.clone(),
             store.clone(),
+            call_cache,
             RuntimeHostConfig {
                 subgraph_id,
                 mapping: data_source.mapping,
-                data_source_network: network_name,
+                // `network_name` may be a network alias; mappings only ever
+                // see the network of the data source
+                data_source_network: data_source.network.clone().unwrap_or(network_name),
                 data_source_name: data_source.name,
                 data_source_context: data_source.context,
                 contract: data_source.source,
+                source_subgraph,
+                arweave_file,
                 templates,
             },
             mapping_request_sender,
@@ -170,18 +211,32 @@ where
 pub struct RuntimeHost {
     data_source_name: String,
     data_source_contract: Source,
-    data_source_contract_abi: MappingABI,
+    /// The ABIs of the contract, with the block from which on each
+    /// describes it; more than one for proxy contracts
+    data_source_contract_abis: Vec<(u64, MappingABI)>,
+    data_source_source_subgraph: Option<SubgraphDeploymentId>,
     data_source_event_handlers: Vec<MappingEventHandler>,
+    data_source_anonymous_event_handlers: Vec<MappingEventHandler>,
+    data_source_wildcard_event_handler: Option<MappingEventHandler>,
+    /// The topic0 of the events that the wildcard event handler handles
+    data_source_wildcard_topics: HashSet<H256>,
     data_source_call_handlers: Vec<MappingCallHandler>,
     data_source_block_handlers: Vec<MappingBlockHandler>,
+    data_source_entity_handlers: Vec<MappingEntityHandler>,
+    /// The Arweave transaction of a `file/arweave` data source
+    data_source_arweave_file: Option<String>,
+    data_source_file_handler: Option<String>,
+    data_source_entities: Vec<String>,
     mapping_request_sender: Sender<MappingRequest>,
     host_exports: Arc<HostExports>,
+    arweave_adapter: Arc<dyn ArweaveAdapter>,
     metrics: Arc<HostMetrics>,
 }
 
 impl RuntimeHost {
     fn new(
         ethereum_adapter: Arc<dyn EthereumAdapter>,
+        chain_store: Arc<dyn ChainStore>,
         link_resolver: Arc<dyn LinkResolver>,
         store: Arc<dyn crate::RuntimeStore>,
         call_cache: Arc<dyn EthereumCallCache>,
@@ -192,29 +247,62 @@ impl RuntimeHost {
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
     ) -> Result<Self, Error> {
         let api_version = Version::parse(&config.mapping.api_version)?;
-        if !VersionReq::parse("<= 0.0.4").unwrap().matches(&api_version) {
+        if !VersionReq::parse("<= 0.0.5").unwrap().matches(&api_version) {
             return Err(format_err!(
-                "This Graph Node only supports mapping API versions <= 0.0.4, but subgraph `{}` uses `{}`",
+                "This Graph Node only supports mapping API versions <= 0.0.5, but subgraph `{}` uses `{}`",
                 config.subgraph_id,
                 api_version
             ));
         }
 
-        let data_source_contract_abi = config
-            .mapping
-            .abis
-            .iter()
-            .find(|abi| abi.name == config.contract.abi)
-            .ok_or_else(|| {
-                format_err!(
-                    "No ABI entry found for the main contract of data source \"{}\": {}",
-                    &config.data_source_name,
-                    config.contract.abi,
-                )
-            })?
-            .clone();
+        // Data sources that handle entity changes of another subgraph or
+        // the data of an Arweave transaction do not have a contract
+        let has_contract = config.source_subgraph.is_none() && config.arweave_file.is_none();
+        let data_source_contract_abis = match has_contract {
+            false => vec![],
+            true => {
+                let start_blocks = std::iter::once(0).chain(
+                    config
+                        .contract
+                        .implementations
+                        .iter()
+                        .map(|implementation| implementation.start_block),
+                );
+                start_blocks
+                    .zip(config.contract.abis())
+                    .map(|(start_block, source_abi)| {
+                        config
+                            .mapping
+                            .abis
+                            .iter()
+                            .find(|abi| abi.name == source_abi)
+                            .map(|abi| (start_block, abi.clone()))
+                            .ok_or_else(|| {
+                                format_err!(
+                                    "No ABI entry found for the main contract of data source \"{}\": {}",
+                                    &config.data_source_name,
+                                    source_abi,
+                                )
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
 
         let data_source_name = config.data_source_name;
+        let data_source_anonymous_event_handlers = config
+            .mapping
+            .anonymous_event_handlers(&config.contract)
+            .into_iter()
+            .cloned()
+            .collect();
+        let data_source_wildcard_event_handler = config.mapping.wildcard_event_handler().cloned();
+        let data_source_wildcard_topics = config
+            .mapping
+            .wildcard_events(&config.contract)
+            .into_iter()
+            .map(util::ethereum::event_topic0)
+            .collect();
 
         // Create new instance of externally hosted functions invoker. The `Arc` is simply to avoid
         // implementing `Clone` for `HostExports`.
@@ -228,22 +316,32 @@ impl RuntimeHost {
             config.templates,
             config.mapping.abis,
             ethereum_adapter,
+            Some(chain_store),
             link_resolver,
             store,
             call_cache,
-            arweave_adapter,
+            arweave_adapter.cheap_clone(),
             three_box_adapter,
         ));
 
         Ok(RuntimeHost {
             data_source_name,
             data_source_contract: config.contract,
-            data_source_contract_abi,
+            data_source_contract_abis,
+            data_source_source_subgraph: config.source_subgraph,
             data_source_event_handlers: config.mapping.event_handlers,
+            data_source_anonymous_event_handlers,
+            data_source_wildcard_event_handler,
+            data_source_wildcard_topics,
             data_source_call_handlers: config.mapping.call_handlers,
             data_source_block_handlers: config.mapping.block_handlers,
+            data_source_entity_handlers: config.mapping.entity_handlers,
+            data_source_arweave_file: config.arweave_file,
+            data_source_file_handler: config.mapping.handler,
+            data_source_entities: config.mapping.entities,
             mapping_request_sender,
             host_exports,
+            arweave_adapter,
             metrics,
         })
     }
@@ -262,7 +360,7 @@ impl RuntimeHost {
         self.data_source_call_handlers.iter().any(|handler| {
             let fhash = keccak256(handler.function.as_bytes());
             let actual_method_id = [fhash[0], fhash[1], fhash[2], fhash[3]];
-            target_method_id == actual_method_id
+            target_method_id == actual_method_id && (handler.include_failed || !call.is_failed())
         })
     }
 
@@ -276,6 +374,11 @@ impl RuntimeHost {
     }
 
     fn matches_log_signature(&self, log: &Log) -> bool {
+        // Logs of anonymous events can have any topic0, or none at all
+        if !self.data_source_anonymous_event_handlers.is_empty() {
+            return true;
+        }
+
         let topic0 = match log.topics.iter().next() {
             Some(topic0) => topic0,
             None => return false,
@@ -283,7 +386,8 @@ impl RuntimeHost {
 
         self.data_source_event_handlers
             .iter()
-            .any(|handler| *topic0 == handler.topic0())
+            .any(|handler| !handler.is_wildcard() && *topic0 == handler.topic0())
+            || self.data_source_wildcard_topics.contains(topic0)
     }
 
     fn matches_block_trigger(&self, block_trigger_type: &EthereumBlockTriggerType) -> bool {
@@ -300,18 +404,24 @@ impl RuntimeHost {
     }
 
     fn handlers_for_log(&self, log: &Arc<Log>) -> Result<Vec<MappingEventHandler>, anyhow::Error> {
-        // Get signature from the log
-        let topic0 = log.topics.get(0).context("Ethereum event has no topics")?;
+        // Get signature from the log; handlers for anonymous events are candidates for any log
+        let topic0 = log.topics.get(0);
 
         let handlers = self
             .data_source_event_handlers
             .iter()
-            .filter(|handler| *topic0 == handler.topic0())
+            .filter(|handler| {
+                (!handler.is_wildcard() && topic0 == Some(&handler.topic0()))
+                    || self.data_source_anonymous_event_handlers.contains(handler)
+            })
             .cloned()
             .collect::<Vec<_>>();
+        let has_wildcard = topic0.map_or(false, |topic0| {
+            self.data_source_wildcard_topics.contains(topic0)
+        });
 
         ensure!(
-            !handlers.is_empty(),
+            !handlers.is_empty() || has_wildcard,
             "No event handler found for event in data source \"{}\"",
             self.data_source_name,
         );
@@ -335,6 +445,7 @@ impl RuntimeHost {
                 let fhash = keccak256(handler.function.as_bytes());
                 let actual_method_id = [fhash[0], fhash[1], fhash[2], fhash[3]];
                 target_method_id == actual_method_id
+                    && (handler.include_failed || !call.is_failed())
             })
             .cloned()
             .with_context(|| {
@@ -380,6 +491,60 @@ impl RuntimeHost {
         }
     }
 
+    fn handler_for_entity(&self, entity_type: &str) -> Result<MappingEntityHandler, anyhow::Error> {
+        self.data_source_entity_handlers
+            .iter()
+            .find(|handler| handler.entity == entity_type)
+            .cloned()
+            .with_context(|| {
+                format_err!(
+                    "No entity handler found for entity type `{}` in data source \"{}\"",
+                    entity_type,
+                    self.data_source_name,
+                )
+            })
+    }
+
+    /// The wildcard event handler together with the name and the decoded
+    /// parameters of the event of `contract` that decodes `log`, if there
+    /// is such a handler and event
+    fn wildcard_handler_for_log(
+        &self,
+        contract: &Contract,
+        log: &Log,
+    ) -> Option<(&MappingEventHandler, String, Vec<LogParam>)> {
+        let handler = self.data_source_wildcard_event_handler.as_ref()?;
+        let topic0 = log.topics.first()?;
+        contract
+            .events()
+            .filter(|event| !event.anonymous && util::ethereum::event_topic0(event) == *topic0)
+            .find_map(|event| {
+                event
+                    .parse_log(RawLog {
+                        topics: log.topics.clone(),
+                        data: log.data.0.clone(),
+                    })
+                    .ok()
+                    .map(|decoded| (handler, event.name.clone(), decoded.params))
+            })
+    }
+
+    /// The ABI that describes the contract at `block`
+    fn contract_abi(&self, block: &LightEthereumBlock) -> Result<&MappingABI, MappingError> {
+        let number = block.number();
+        self.data_source_contract_abis
+            .iter()
+            .rev()
+            .find(|(start_block, _)| *start_block <= number)
+            .map(|(_, abi)| abi)
+            .ok_or_else(|| {
+                MappingError::Deterministic(anyhow!(
+                    "Data source \"{}\" has no contract",
+                    self.data_source_name
+                ))
+            })
+    }
+
     /// Sends a MappingRequest to the thread which owns the host,
     /// and awaits the result.
     async fn send_mapping_request<T: slog::SendSyncRefUnwindSafeKV>(
@@ -391,7 +556,7 @@ impl RuntimeHost {
         trigger: MappingTrigger,
         block: &Arc<LightEthereumBlock>,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error> {
+    ) -> Result<BlockState, MappingError> {
         let trigger_type = trigger.as_static();
         debug!(
             logger, "Start processing Ethereum trigger";
@@ -429,6 +594,18 @@ impl RuntimeHost {
         let elapsed = start_time.elapsed();
         metrics.observe_handler_execution_time(elapsed.as_secs_f64(), handler);
 
+        if elapsed >= *SLOW_HANDLER_THRESHOLD {
+            warn!(
+                logger, "Slow handler";
+                &extra,
+                "trigger_type" => trigger_type,
+                "total_ms" => elapsed.as_millis(),
+                "threshold_ms" => SLOW_HANDLER_THRESHOLD.as_millis(),
+                "handler" => handler,
+                "data_source" => &self.data_source_name,
+            );
+        }
+
         info!(
             logger, "Done processing Ethereum trigger";
             extra,
@@ -474,6 +651,24 @@ impl RuntimeHostTrait for RuntimeHost {
             && self.data_source_contract.start_block <= block_number
     }
 
+    fn matches_entity(
+        &self,
+        source: &SubgraphDeploymentId,
+        entity_type: &str,
+        block_number: u64,
+    ) -> bool {
+        self.data_source_source_subgraph.as_ref() == Some(source)
+            && self
+                .data_source_entity_handlers
+                .iter()
+                .any(|handler| handler.entity == entity_type)
+            && self.data_source_contract.start_block <= block_number
+    }
+
+    fn entities(&self) -> &[String] {
+        &self.data_source_entities
+    }
+
     async fn process_call(
         &self,
         logger: &Logger,
@@ -482,13 +677,16 @@ impl RuntimeHostTrait for RuntimeHost {
         call: &Arc<EthereumCall>,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error> {
+    ) -> Result<BlockState, MappingError> {
         // Identify the call handler for this call
-        let call_handler = self.handler_for_call(&call)?;
+        let call_handler = self
+            .handler_for_call(&call)
+            .map_err(MappingError::Deterministic)?;
 
         // Identify the function ABI in the contract
+        let contract_abi = self.contract_abi(block)?;
         let function_abi = util::ethereum::contract_function_with_signature(
-            &self.data_source_contract_abi.contract,
+            &contract_abi.contract,
             call_handler.function.as_str(),
         )
         .with_context(|| {
@@ -496,10 +694,11 @@ impl RuntimeHostTrait for RuntimeHost {
                 "Function with the signature \"{}\" not found in \
                     contract \"{}\" of data source \"{}\"",
                 call_handler.function,
-                self.data_source_contract_abi.name,
+                contract_abi.name,
                 self.data_source_name
             )
-        })?;
+        })
+        .map_err(MappingError::Deterministic)?;
 
         // Parse the inputs
         //
@@ -508,13 +707,15 @@ impl RuntimeHostTrait for RuntimeHost {
         // with the `Param`s in `function.inputs` to create a `Vec<LogParam>`.
         let tokens = function_abi
             .decode_input(&call.input.0[4..])
-            .context("Generating function inputs for an Ethereum call failed")?;
+            .context("Generating function inputs for an Ethereum call failed")
+            .map_err(MappingError::Deterministic)?;
 
-        ensure!(
-            tokens.len() == function_abi.inputs.len(),
-            "Number of arguments in call does not match \
+        if tokens.len() != function_abi.inputs.len() {
+            return Err(MappingError::Deterministic(anyhow!(
+                "Number of arguments in call does not match \
                     number of inputs in function signature."
-        );
+            )));
+        }
 
         let inputs = tokens
             .into_iter()
@@ -529,25 +730,32 @@ impl RuntimeHostTrait for RuntimeHost {
         //
         // Take the output for the call, then call `function.decode_output` to
         // get a vector of `Token`s. Match the `Token`s with the `Param`s in
-        // `function.outputs` to create a `Vec<LogParam>`.
-        let tokens = function_abi
-            .decode_output(&call.output.0)
-            .context("Generating function outputs for an Ethereum call failed")?;
-
-        ensure!(
-            tokens.len() == function_abi.outputs.len(),
-            "Number of parameters in the call output does not match \
+        // `function.outputs` to create a `Vec<LogParam>`. Failed calls have
+        // no outputs; the handler gets the revert reason instead
+        let outputs = if call.is_failed() {
+            vec![]
+        } else {
+            let tokens = function_abi
+                .decode_output(&call.output.0)
+                .context("Generating function outputs for an Ethereum call failed")
+                .map_err(MappingError::Deterministic)?;
+
+            if tokens.len() != function_abi.outputs.len() {
+                return Err(MappingError::Deterministic(anyhow!(
+                    "Number of parameters in the call output does not match \
                         number of outputs in the function signature."
-        );
+                )));
+            }
 
-        let outputs = tokens
-            .into_iter()
-            .enumerate()
-            .map(|(i, token)| LogParam {
-                name: function_abi.outputs[i].name.clone(),
-                value: token,
-            })
-            .collect::<Vec<_>>();
+            tokens
+                .into_iter()
+                .enumerate()
+                .map(|(i, token)| LogParam {
+                    name: function_abi.outputs[i].name.clone(),
+                    value: token,
+                })
+                .collect::<Vec<_>>()
+        };
 
         self.send_mapping_request(
             logger,
@@ -577,8 +785,10 @@ impl RuntimeHostTrait for RuntimeHost {
         trigger_type: &EthereumBlockTriggerType,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error> {
-        let block_handler = self.handler_for_block(trigger_type)?;
+    ) -> Result<BlockState, MappingError> {
+        let block_handler = self
+            .handler_for_block(trigger_type)
+            .map_err(MappingError::Deterministic)?;
         self.send_mapping_request(
             logger,
             o! {
@@ -596,6 +806,84 @@ impl RuntimeHostTrait for RuntimeHost {
         .await
     }
 
+    async fn process_entity(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        entity_type: &str,
+        entity: &Entity,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        let entity_handler = self
+            .handler_for_entity(entity_type)
+            .map_err(MappingError::Deterministic)?;
+        self.send_mapping_request(
+            logger,
+            o! {
+                "entity_type" => entity_type,
+                "id" => entity.id().unwrap_or_default(),
+            },
+            state,
+            &entity_handler.handler,
+            MappingTrigger::Entity {
+                entity: entity.clone(),
+                handler: entity_handler.clone(),
+            },
+            block,
+            proof_of_indexing,
+        )
+        .await
+    }
+
+    async fn process_file(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        let (tx_id, handler) = match (
+            &self.data_source_arweave_file,
+            &self.data_source_file_handler,
+        ) {
+            (Some(tx_id), Some(handler)) => (tx_id, handler),
+            _ => {
+                return Err(MappingError::Deterministic(anyhow!(
+                    "Data source \"{}\" does not handle a file",
+                    self.data_source_name
+                )))
+            }
+        };
+
+        // The gateways may not have the data yet or may be unreachable, so
+        // this is retried like other failures to reach a service
+        let data = self.arweave_adapter.tx_data(tx_id).await.map_err(|e| {
+            MappingError::Unknown(anyhow!(
+                "Failed to fetch the file of data source \"{}\": {}",
+                self.data_source_name,
+                e
+            ))
+        })?;
+
+        self.send_mapping_request(
+            logger,
+            o! {
+                "arweave_tx" => tx_id.clone(),
+                "bytes" => data.len(),
+            },
+            state,
+            handler,
+            MappingTrigger::File {
+                data,
+                handler: handler.clone(),
+            },
+            block,
+            proof_of_indexing,
+        )
+        .await
+    }
+
     async fn process_log(
         &self,
         logger: &Logger,
@@ -604,13 +892,16 @@ impl RuntimeHostTrait for RuntimeHost {
         log: &Arc<Log>,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, anyhow::Error> {
+    ) -> Result<BlockState, MappingError> {
         let data_source_name = &self.data_source_name;
-        let abi_name = &self.data_source_contract_abi.name;
-        let contract = &self.data_source_contract_abi.contract;
+        let contract_abi = self.contract_abi(block)?;
+        let abi_name = &contract_abi.name;
+        let contract = &contract_abi.contract;
 
         // If there are no matching handlers, fail processing the event
-        let potential_handlers = self.handlers_for_log(&log)?;
+        let potential_handlers = self
+            .handlers_for_log(&log)
+            .map_err(MappingError::Deterministic)?;
 
         // Map event handlers to (event handler, event ABI) pairs; fail if there are
         // handlers that don't exist in the contract ABI
@@ -633,7 +924,8 @@ impl RuntimeHostTrait for RuntimeHost {
                 })?;
                 Ok((event_handler, event_abi))
             })
-            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+            .collect::<Result<Vec<_>, anyhow::Error>>()
+            .map_err(MappingError::Deterministic)?;
 
         // Filter out handlers whose corresponding event ABIs cannot decode the
         // params (this is common for overloaded events that have the same topic0
@@ -666,6 +958,52 @@ impl RuntimeHostTrait for RuntimeHost {
             })
             .collect::<Vec<_>>();
 
+        // A log can often also be decoded as an anonymous event by accident, so handlers that
+        // match the event signature take precedence. Logs of anonymous events do not say which
+        // event they are, so the first handler whose event can decode the log handles it.
+        let is_anonymous = |handler: &MappingEventHandler| {
+            self.data_source_anonymous_event_handlers.contains(handler)
+        };
+
+        // Logs that no handler for their event can decode go to the wildcard
+        // handler, which takes precedence over handlers for anonymous events
+        if !matching_handlers
+            .iter()
+            .any(|(handler, _)| !is_anonymous(handler))
+        {
+            if let Some((handler, name, params)) = self.wildcard_handler_for_log(contract, log) {
+                return self
+                    .send_mapping_request(
+                        logger,
+                        o! {
+                            "signature" => &handler.event,
+                            "event" => &name,
+                            "address" => format!("{}", &log.address),
+                        },
+                        state,
+                        &handler.handler,
+                        MappingTrigger::Log {
+                            transaction: transaction.cheap_clone(),
+                            log: log.cheap_clone(),
+                            params,
+                            handler: handler.clone(),
+                            event_name: Some(name.clone()),
+                        },
+                        block,
+                        proof_of_indexing,
+                    )
+                    .await;
+            }
+        }
+        if matching_handlers
+            .iter()
+            .any(|(handler, _)| !is_anonymous(handler))
+        {
+            matching_handlers.retain(|(handler, _)| !is_anonymous(handler));
+        } else {
+            matching_handlers.truncate(1);
+        }
+
         if matching_handlers.is_empty() {
             warn!(
                 logger,
@@ -682,13 +1020,12 @@ impl RuntimeHostTrait for RuntimeHost {
         // Process the event with the matching handler
         let (event_handler, params) = matching_handlers.pop().unwrap();
 
-        ensure!(
-            matching_handlers.is_empty(),
-            format!(
+        if !matching_handlers.is_empty() {
+            return Err(MappingError::Deterministic(anyhow!(
                 "Multiple handlers defined for event `{}`, only one is supported",
                 &event_handler.event
-            )
-        );
+            )));
+        }
 
         self.send_mapping_request(
             logger,
@@ -703,6 +1040,7 @@ impl RuntimeHostTrait for RuntimeHost {
                 log: log.cheap_clone(),
                 params,
                 handler: event_handler.clone(),
+                event_name: None,
             },
             block,
             proof_of_indexing,
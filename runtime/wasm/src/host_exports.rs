@@ -1,3 +1,4 @@
+use crate::error::{DeterministicResultExt, HostExportError};
 use crate::UnresolvedContractCall;
 use bytes::Bytes;
 use ethabi::{Address, Token};
@@ -9,9 +10,10 @@ use graph::components::three_box::ThreeBoxAdapter;
 use graph::data::store;
 use graph::prelude::serde_json;
 use graph::prelude::{slog::b, slog::record_static, *};
+use graph::util::ethereum::decode_revert_reason;
 use semver::Version;
 use std::collections::HashMap;
-use std::ops::Deref;
+use std::convert::TryFrom;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 use web3::types::H160;
@@ -21,7 +23,7 @@ use graph_graphql::prelude::validate_entity;
 use crate::module::{WasmInstance, WasmInstanceContext};
 
 pub(crate) struct HostExports {
-    subgraph_id: SubgraphDeploymentId,
+    pub(crate) subgraph_id: SubgraphDeploymentId,
     pub(crate) api_version: Version,
     data_source_name: String,
     data_source_address: Option<Address>,
@@ -35,6 +37,9 @@ pub(crate) struct HostExports {
     templates: Arc<Vec<DataSourceTemplate>>,
     abis: Vec<MappingABI>,
     ethereum_adapter: Arc<dyn EthereumAdapter>,
+    /// Caches the blocks of the network; used to find the ancestors of
+    /// the block being handled without asking the Ethereum node
+    chain_store: Option<Arc<dyn ChainStore>>,
     pub(crate) link_resolver: Arc<dyn LinkResolver>,
     call_cache: Arc<dyn EthereumCallCache>,
     store: Arc<dyn crate::RuntimeStore>,
@@ -60,6 +65,7 @@ impl HostExports {
         templates: Arc<Vec<DataSourceTemplate>>,
         abis: Vec<MappingABI>,
         ethereum_adapter: Arc<dyn EthereumAdapter>,
+        chain_store: Option<Arc<dyn ChainStore>>,
         link_resolver: Arc<dyn LinkResolver>,
         store: Arc<dyn crate::RuntimeStore>,
         call_cache: Arc<dyn EthereumCallCache>,
@@ -79,6 +85,7 @@ impl HostExports {
             templates,
             abis,
             ethereum_adapter,
+            chain_store,
             link_resolver,
             call_cache,
             store,
@@ -93,7 +100,7 @@ impl HostExports {
         file_name: Option<String>,
         line_number: Option<u32>,
         column_number: Option<u32>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), HostExportError> {
         let message = message
             .map(|message| format!("message: {}", message))
             .unwrap_or_else(|| "no message".into());
@@ -109,28 +116,25 @@ impl HostExports {
             ),
             _ => unreachable!(),
         };
-        Err(anyhow::anyhow!(
+        Err(HostExportError::deterministic(anyhow::anyhow!(
             "Mapping aborted at {}, with {}",
             location,
             message
-        ))
+        )))
     }
 
     pub(crate) fn store_set(
         &self,
-        logger: &Logger,
         state: &mut BlockState,
         proof_of_indexing: &SharedProofOfIndexing,
         entity_type: String,
         entity_id: String,
         mut data: HashMap<String, Value>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), HostExportError> {
         use graph::prelude::failure::ResultExt;
 
-        if let Some(proof_of_indexing) = proof_of_indexing {
-            let mut proof_of_indexing = proof_of_indexing.deref().borrow_mut();
-            proof_of_indexing.write(
-                logger,
+        if proof_of_indexing.is_some() {
+            state.poi_events.push(
                 &self.causality_region,
                 &ProofOfIndexingEvent::SetEntity {
                     entity_type: &entity_type,
@@ -143,13 +147,13 @@ impl HostExports {
         // Automatically add an "id" value
         match data.insert("id".to_string(), Value::String(entity_id.clone())) {
             Some(ref v) if v != &Value::String(entity_id.clone()) => {
-                anyhow::bail!(
+                return Err(HostExportError::deterministic(anyhow::anyhow!(
                     "Value of {} attribute 'id' conflicts with ID passed to `store.set()`: \
                      {} != {}",
                     entity_type,
                     v,
                     entity_id,
-                );
+                )));
             }
             _ => (),
         }
@@ -160,9 +164,16 @@ impl HostExports {
             entity_id,
         };
         let entity = Entity::from(data);
-        let schema = self.store.input_schema(&self.subgraph_id).compat()?;
+        let schema = self
+            .store
+            .input_schema(&self.subgraph_id)
+            .compat()
+            .map_err(anyhow::Error::from)?;
         let is_valid = validate_entity(&schema.document, &key, &entity).is_ok();
-        state.entity_cache.set(key.clone(), entity).compat()?;
+        state
+            .entity_cache
+            .set(key.clone(), entity)
+            .map_err(anyhow::Error::from)?;
 
         // Validate the changes against the subgraph schema.
         // If the set of fields we have is already valid, avoid hitting the DB.
@@ -170,30 +181,28 @@ impl HostExports {
             && self
                 .store
                 .uses_relational_schema(&self.subgraph_id)
-                .compat()?
+                .compat()
+                .map_err(anyhow::Error::from)?
         {
             let entity = state
                 .entity_cache
                 .get(&key)
-                .compat()?
+                .map_err(anyhow::Error::from)?
                 .expect("we just stored this entity");
-            validate_entity(&schema.document, &key, &entity)?;
+            validate_entity(&schema.document, &key, &entity).deterministic()?;
         }
         Ok(())
     }
 
     pub(crate) fn store_remove(
         &self,
-        logger: &Logger,
         state: &mut BlockState,
         proof_of_indexing: &SharedProofOfIndexing,
         entity_type: String,
         entity_id: String,
     ) {
-        if let Some(proof_of_indexing) = proof_of_indexing {
-            let mut proof_of_indexing = proof_of_indexing.deref().borrow_mut();
-            proof_of_indexing.write(
-                logger,
+        if proof_of_indexing.is_some() {
+            state.poi_events.push(
                 &self.causality_region,
                 &ProofOfIndexingEvent::RemoveEntity {
                     entity_type: &entity_type,
@@ -224,17 +233,21 @@ impl HostExports {
         Ok(state.entity_cache.get(&store_key)?)
     }
 
-    /// Returns `Ok(None)` if the call was reverted.
+    /// Runs the call against the state of `block_ptr`. Returns `Ok(Err(reason))`
+    /// if the call was reverted, with the reason decoded from the revert
+    /// data and the ABI of the contract if possible. The result is only
+    /// cached if `block_is_final`.
     pub(crate) fn ethereum_call(
         &self,
         logger: &Logger,
-        block: &LightEthereumBlock,
+        block_ptr: EthereumBlockPointer,
+        block_is_final: bool,
         unresolved_call: UnresolvedContractCall,
-    ) -> Result<Option<Vec<Token>>, anyhow::Error> {
+    ) -> Result<Result<Vec<Token>, String>, HostExportError> {
         let start_time = Instant::now();
 
         // Obtain the path to the contract ABI
-        let contract = self
+        let abi = self
             .abis
             .iter()
             .find(|abi| abi.name == unresolved_call.contract_name)
@@ -244,9 +257,9 @@ impl HostExports {
                      of the subgraph manifest",
                     unresolved_call.contract_name
                 )
-            })?
-            .contract
-            .clone();
+            })
+            .deterministic()?;
+        let contract = abi.contract.clone();
 
         let function = match unresolved_call.function_signature {
             // Behavior for apiVersion < 0.0.4: look up function by name; for overloaded
@@ -259,7 +272,8 @@ impl HostExports {
                         "Unknown function \"{}::{}\" called from WASM runtime",
                         unresolved_call.contract_name, unresolved_call.function_name
                     )
-                })?,
+                })
+                .deterministic()?,
 
             // Behavior for apiVersion >= 0.0.04: look up function by signature of
             // the form `functionName(uint256,string) returns (bytes32,string)`; this
@@ -271,7 +285,8 @@ impl HostExports {
                         "Unknown function \"{}::{}\" called from WASM runtime",
                         unresolved_call.contract_name, unresolved_call.function_name
                     )
-                })?
+                })
+                .deterministic()?
                 .iter()
                 .find(|f| function_signature == &f.signature())
                 .with_context(|| {
@@ -282,14 +297,17 @@ impl HostExports {
                         unresolved_call.function_name,
                         function_signature,
                     )
-                })?,
+                })
+                .deterministic()?,
         };
 
         let call = EthereumContractCall {
             address: unresolved_call.contract_address.clone(),
-            block_ptr: block.into(),
+            block_ptr: Some(block_ptr),
+            block_is_final,
             function: function.clone(),
             args: unresolved_call.function_args.clone(),
+            immutable: abi.is_immutable_call(function),
         };
 
         // Run Ethereum call in tokio runtime
@@ -299,17 +317,36 @@ impl HostExports {
         let result = match block_on(future::lazy(move || {
             eth_adapter.contract_call(&logger1, call, call_cache)
         })) {
-            Ok(tokens) => Ok(Some(tokens)),
-            Err(EthereumContractCallError::Revert(reason)) => {
-                info!(logger, "Contract call reverted"; "reason" => reason);
-                Ok(None)
+            Ok(tokens) => Ok(Ok(tokens)),
+            Err(EthereumContractCallError::Revert(reason, data)) => {
+                let reason = decode_revert_reason(&data, &abi.errors).unwrap_or(reason);
+                info!(logger, "Contract call reverted";
+                      "reason" => &reason,
+                      "contract" => &unresolved_call.contract_name,
+                      "function" => &unresolved_call.function_name);
+                Ok(Err(reason))
+            }
+            Err(e) => {
+                // Failing to encode the arguments or decode the result will fail the same way
+                // every time, while the Ethereum node may recover from other errors.
+                let deterministic = match e {
+                    EthereumContractCallError::ABIError(_)
+                    | EthereumContractCallError::TypeError(..) => true,
+                    EthereumContractCallError::Web3Error(_)
+                    | EthereumContractCallError::Timeout
+                    | EthereumContractCallError::Revert(..) => false,
+                };
+                let e = anyhow::anyhow!(
+                    "Failed to call function \"{}\" of contract \"{}\": {}",
+                    unresolved_call.function_name,
+                    unresolved_call.contract_name,
+                    e
+                );
+                match deterministic {
+                    true => Err(HostExportError::Deterministic(e)),
+                    false => Err(HostExportError::Unknown(e)),
+                }
             }
-            Err(e) => Err(anyhow::anyhow!(
-                "Failed to call function \"{}\" of contract \"{}\": {}",
-                unresolved_call.function_name,
-                unresolved_call.contract_name,
-                e
-            )),
         };
 
         debug!(logger, "Contract call finished";
@@ -322,6 +359,120 @@ impl HostExports {
         result
     }
 
+    /// The block that a call for `block_number` made while handling a
+    /// trigger in `block` runs against. Calls can only look at the state of
+    /// `block` or of blocks before it
+    pub(crate) fn ethereum_call_block(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+        block_number: u64,
+    ) -> Result<EthereumBlockPointer, HostExportError> {
+        let block_ptr = EthereumBlockPointer::from(block);
+        if block_number > block_ptr.number {
+            return Err(HostExportError::deterministic(anyhow::anyhow!(
+                "Contract calls can not be made against block {} \
+                 while handling block {}",
+                block_number,
+                block_ptr.number
+            )));
+        }
+        if block_number == block_ptr.number {
+            return Ok(block_ptr);
+        }
+        if block_number + 1 == block_ptr.number {
+            return Ok(EthereumBlockPointer {
+                hash: block.parent_hash,
+                number: block_number,
+            });
+        }
+
+        // Older blocks are found by following the parent hashes of the block
+        // we are handling, so that the call is made against its chain even if
+        // the Ethereum node has since switched to another one. The chain
+        // store usually has all of them; what it lacks comes from the node
+        let parent_ptr = EthereumBlockPointer {
+            hash: block.parent_hash,
+            number: block_ptr.number - 1,
+        };
+        let offset = parent_ptr.number - block_number;
+        if let Some(chain_store) = &self.chain_store {
+            let ancestor = chain_store
+                .ancestor_block(parent_ptr, offset)
+                .map_err(|e| {
+                    HostExportError::Unknown(anyhow::anyhow!(
+                        "Failed to load block {} for contract call: {}",
+                        block_number,
+                        e
+                    ))
+                })?;
+            if let Some(ancestor) = ancestor {
+                return Ok(EthereumBlockPointer::from(&ancestor));
+            }
+        }
+
+        let mut ptr = parent_ptr;
+        while ptr.number > block_number {
+            let eth_adapter = self.ethereum_adapter.clone();
+            let logger1 = logger.clone();
+            let hash = ptr.hash;
+            let block = block_on(future::lazy(move || {
+                eth_adapter.block_by_hash(&logger1, hash)
+            }))
+            .map_err(|e| {
+                HostExportError::Unknown(anyhow::anyhow!(
+                    "Failed to load block {} for contract call: {}",
+                    ptr,
+                    e
+                ))
+            })?
+            .ok_or_else(|| {
+                HostExportError::Unknown(anyhow::anyhow!(
+                    "Block {} for contract call was not found",
+                    ptr
+                ))
+            })?;
+            ptr = EthereumBlockPointer {
+                hash: block.parent_hash,
+                number: ptr.number - 1,
+            };
+        }
+        Ok(ptr)
+    }
+
+    /// The number of the last block whose timestamp is at or before
+    /// `timestamp`, or `None` if there is no such block or it may be a
+    /// block after the one being handled.
+    pub(crate) fn ethereum_block_number_at_timestamp(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+        timestamp: BigInt,
+    ) -> Result<Option<u64>, HostExportError> {
+        let block_ptr = EthereumBlockPointer::from(block);
+        // Negative timestamps are before and huge ones after all blocks
+        let timestamp = match u64::try_from(&timestamp) {
+            Ok(timestamp) => timestamp,
+            Err(_) => return Ok(None),
+        };
+        if timestamp >= block.timestamp.as_u64() {
+            return Ok(Some(block_ptr.number).filter(|_| timestamp == block.timestamp.as_u64()));
+        }
+
+        let eth_adapter = self.ethereum_adapter.clone();
+        let logger1 = logger.clone();
+        block_on(future::lazy(move || {
+            eth_adapter.block_number_by_timestamp(&logger1, None, timestamp, Some(block_ptr.number))
+        }))
+        .map_err(|e| {
+            HostExportError::Unknown(anyhow::anyhow!(
+                "Failed to find the block at timestamp {}: {}",
+                timestamp,
+                e
+            ))
+        })
+    }
+
     /// Prints the module of `n` in hex.
     /// Integers are encoded using the least amount of digits (no leading zero digits).
     /// Their encoding may be of uneven length. The number zero encodes as "0x0".
@@ -336,10 +487,14 @@ impl HostExports {
         format!("0x{}", ::hex::encode(bytes).trim_start_matches('0'))
     }
 
-    pub(crate) fn ipfs_cat(&self, logger: &Logger, link: String) -> Result<Vec<u8>, anyhow::Error> {
-        use graph::prelude::failure::ResultExt;
-
-        Ok(block_on03(self.link_resolver.cat(logger, &Link { link })).compat()?)
+    /// Fails deterministically if the file is too large, and with an unknown
+    /// error if it could not be fetched.
+    pub(crate) fn ipfs_cat(
+        &self,
+        logger: &Logger,
+        link: String,
+    ) -> Result<Vec<u8>, HostExportError> {
+        block_on03(self.link_resolver.cat(logger, &Link { link })).map_err(ipfs_error)
     }
 
     // Read the IPFS file `link`, split it into JSON objects, and invoke the
@@ -356,14 +511,15 @@ impl HostExports {
         callback: &str,
         user_data: store::Value,
         flags: Vec<String>,
-    ) -> Result<Vec<BlockState>, anyhow::Error> {
+    ) -> Result<Vec<BlockState>, HostExportError> {
         use graph::prelude::failure::ResultExt;
 
         const JSON_FLAG: &str = "json";
-        anyhow::ensure!(
-            flags.contains(&JSON_FLAG.to_string()),
-            "Flags must contain 'json'"
-        );
+        if !flags.contains(&JSON_FLAG.to_string()) {
+            return Err(HostExportError::deterministic(anyhow::anyhow!(
+                "Flags must contain 'json'"
+            )));
+        }
 
         let host_metrics = module.host_metrics.clone();
         let valid_module = module.valid_module.clone();
@@ -381,10 +537,11 @@ impl HostExports {
 
         let result = {
             let mut stream: JsonValueStream =
-                block_on03(link_resolver.json_stream(&logger, &Link { link })).compat()?;
+                block_on03(link_resolver.json_stream(&logger, &Link { link }))
+                    .map_err(ipfs_error)?;
             let mut v = Vec::new();
             while let Some(sv) = block_on03(stream.next()) {
-                let sv = sv.compat()?;
+                let sv = sv.compat().map_err(anyhow::Error::from)?;
                 let module = WasmInstance::from_valid_module_with_ctx(
                     valid_module.clone(),
                     ctx.derive_with_empty_block_state(),
@@ -406,35 +563,74 @@ impl HostExports {
             }
             Ok(v)
         };
-        result.map_err(move |e: Error| anyhow::anyhow!("{}: {}", errmsg, e.to_string()))
+        result.map_err(move |e: HostExportError| match e {
+            HostExportError::Deterministic(e) => {
+                HostExportError::Deterministic(anyhow::anyhow!("{}: {:#}", errmsg, e))
+            }
+            HostExportError::Unknown(e) => {
+                HostExportError::Unknown(anyhow::anyhow!("{}: {:#}", errmsg, e))
+            }
+        })
     }
 
     /// Expects a decimal string.
-    pub(crate) fn json_to_i64(&self, json: String) -> Result<i64, anyhow::Error> {
-        i64::from_str(&json).with_context(|| format!("JSON `{}` cannot be parsed as i64", json))
+    pub(crate) fn json_to_i64(&self, json: String) -> Result<i64, HostExportError> {
+        i64::from_str(&json)
+            .with_context(|| format!("JSON `{}` cannot be parsed as i64", json))
+            .deterministic()
     }
 
     /// Expects a decimal string.
-    pub(crate) fn json_to_u64(&self, json: String) -> Result<u64, anyhow::Error> {
-        u64::from_str(&json).with_context(|| format!("JSON `{}` cannot be parsed as u64", json))
+    pub(crate) fn json_to_u64(&self, json: String) -> Result<u64, HostExportError> {
+        u64::from_str(&json)
+            .with_context(|| format!("JSON `{}` cannot be parsed as u64", json))
+            .deterministic()
     }
 
     /// Expects a decimal string.
-    pub(crate) fn json_to_f64(&self, json: String) -> Result<f64, anyhow::Error> {
-        f64::from_str(&json).with_context(|| format!("JSON `{}` cannot be parsed as f64", json))
+    pub(crate) fn json_to_f64(&self, json: String) -> Result<f64, HostExportError> {
+        f64::from_str(&json)
+            .with_context(|| format!("JSON `{}` cannot be parsed as f64", json))
+            .deterministic()
     }
 
     /// Expects a decimal string.
-    pub(crate) fn json_to_big_int(&self, json: String) -> Result<Vec<u8>, anyhow::Error> {
+    pub(crate) fn json_to_big_int(&self, json: String) -> Result<Vec<u8>, HostExportError> {
         let big_int = BigInt::from_str(&json)
-            .with_context(|| format!("JSON `{}` is not a decimal string", json))?;
+            .with_context(|| format!("JSON `{}` is not a decimal string", json))
+            .deterministic()?;
         Ok(big_int.to_signed_bytes_le())
     }
 
+    /// Decode `data` as the ABI encoding of a value of type `types`, which
+    /// is written like a Solidity type, e.g., `(address,uint256[])`. That
+    /// lets mappings decode the arguments in the input of a transaction.
+    /// Returns `None` if `data` is not a value of that type
+    pub(crate) fn ethereum_decode(
+        &self,
+        types: String,
+        data: Vec<u8>,
+    ) -> Result<Option<Token>, HostExportError> {
+        let param_type = ethabi::param_type::Reader::read(&types)
+            .map_err(|e| anyhow::anyhow!("`{}` is not a valid Ethereum type: {}", types, e))
+            .deterministic()?;
+        Ok(ethabi::decode(&[param_type], &data)
+            .ok()
+            .and_then(|mut tokens| tokens.pop()))
+    }
+
     pub(crate) fn crypto_keccak_256(&self, input: Vec<u8>) -> [u8; 32] {
         tiny_keccak::keccak256(&input)
     }
 
+    /// Pseudo-random bytes for `seed` that only depend on the hash of
+    /// `block`, so that all indexers get the same ones
+    pub(crate) fn block_random(&self, block: &LightEthereumBlock, seed: Vec<u8>) -> [u8; 32] {
+        let mut input = block.hash.unwrap_or_default().as_bytes().to_vec();
+        input.extend(seed);
+        tiny_keccak::keccak256(&input)
+    }
+
     pub(crate) fn big_int_plus(&self, x: BigInt, y: BigInt) -> BigInt {
         x + y
     }
@@ -447,13 +643,28 @@ impl HostExports {
         x * y
     }
 
-    pub(crate) fn big_int_divided_by(&self, x: BigInt, y: BigInt) -> Result<BigInt, anyhow::Error> {
-        anyhow::ensure!(y != 0.into(), "attempted to divide BigInt `{}` by zero", x);
+    pub(crate) fn big_int_divided_by(
+        &self,
+        x: BigInt,
+        y: BigInt,
+    ) -> Result<BigInt, HostExportError> {
+        if y == 0.into() {
+            return Err(HostExportError::deterministic(anyhow::anyhow!(
+                "attempted to divide BigInt `{}` by zero",
+                x
+            )));
+        }
         Ok(x / y)
     }
 
-    pub(crate) fn big_int_mod(&self, x: BigInt, y: BigInt) -> BigInt {
-        x % y
+    pub(crate) fn big_int_mod(&self, x: BigInt, y: BigInt) -> Result<BigInt, HostExportError> {
+        if y == 0.into() {
+            return Err(HostExportError::deterministic(anyhow::anyhow!(
+                "attempted to take the remainder of BigInt `{}` divided by zero",
+                x
+            )));
+        }
+        Ok(x % y)
     }
 
     /// Limited to a small exponent to avoid creating huge BigInts.
@@ -461,35 +672,81 @@ impl HostExports {
         x.pow(exponent)
     }
 
+    /// The integer square root, rounded down.
+    pub(crate) fn big_int_sqrt(&self, x: BigInt) -> Result<BigInt, HostExportError> {
+        if x < 0.into() {
+            return Err(HostExportError::deterministic(anyhow::anyhow!(
+                "attempted to take the square root of negative BigInt `{}`",
+                x
+            )));
+        }
+        Ok(x.sqrt())
+    }
+
+    pub(crate) fn big_int_bit_or(&self, x: BigInt, y: BigInt) -> BigInt {
+        x | y
+    }
+
+    pub(crate) fn big_int_bit_and(&self, x: BigInt, y: BigInt) -> BigInt {
+        x & y
+    }
+
+    /// Limited to a small number of bits to avoid creating huge BigInts.
+    pub(crate) fn big_int_left_shift(&self, x: BigInt, bits: u8) -> BigInt {
+        x << bits
+    }
+
+    pub(crate) fn big_int_right_shift(&self, x: BigInt, bits: u8) -> BigInt {
+        x >> bits
+    }
+
     /// Useful for IPFS hashes stored as bytes
     pub(crate) fn bytes_to_base58(&self, bytes: Vec<u8>) -> String {
         ::bs58::encode(&bytes).into_string()
     }
 
-    pub(crate) fn big_decimal_plus(&self, x: BigDecimal, y: BigDecimal) -> BigDecimal {
-        x + y
+    /// The results of all `BigDecimal` operations are rounded to
+    /// `BigDecimal::MAX_SIGNFICANT_DIGITS` significant digits. Results whose
+    /// exponent falls outside of `BigDecimal::MIN_EXP` and
+    /// `BigDecimal::MAX_EXP` are an error, so that mappings never see values
+    /// they could not pass back to the host.
+    pub(crate) fn big_decimal_plus(
+        &self,
+        x: BigDecimal,
+        y: BigDecimal,
+    ) -> Result<BigDecimal, HostExportError> {
+        checked_big_decimal(x + y)
     }
 
-    pub(crate) fn big_decimal_minus(&self, x: BigDecimal, y: BigDecimal) -> BigDecimal {
-        x - y
+    pub(crate) fn big_decimal_minus(
+        &self,
+        x: BigDecimal,
+        y: BigDecimal,
+    ) -> Result<BigDecimal, HostExportError> {
+        checked_big_decimal(x - y)
     }
 
-    pub(crate) fn big_decimal_times(&self, x: BigDecimal, y: BigDecimal) -> BigDecimal {
-        x * y
+    pub(crate) fn big_decimal_times(
+        &self,
+        x: BigDecimal,
+        y: BigDecimal,
+    ) -> Result<BigDecimal, HostExportError> {
+        checked_big_decimal(x * y)
     }
 
-    /// Maximum precision of 100 decimal digits.
     pub(crate) fn big_decimal_divided_by(
         &self,
         x: BigDecimal,
         y: BigDecimal,
-    ) -> Result<BigDecimal, anyhow::Error> {
-        anyhow::ensure!(
-            y != 0.into(),
-            format!("attempted to divide BigDecimal `{}` by zero", x)
-        );
+    ) -> Result<BigDecimal, HostExportError> {
+        if y == 0.into() {
+            return Err(HostExportError::deterministic(anyhow::anyhow!(
+                "attempted to divide BigDecimal `{}` by zero",
+                x
+            )));
+        }
 
-        Ok(x / y)
+        checked_big_decimal(x / y)
     }
 
     pub(crate) fn big_decimal_equals(&self, x: BigDecimal, y: BigDecimal) -> bool {
@@ -500,8 +757,10 @@ impl HostExports {
         x.to_string()
     }
 
-    pub(crate) fn big_decimal_from_string(&self, s: String) -> Result<BigDecimal, anyhow::Error> {
-        BigDecimal::from_str(&s).with_context(|| format!("string  is not a BigDecimal: '{}'", s))
+    pub(crate) fn big_decimal_from_string(&self, s: String) -> Result<BigDecimal, HostExportError> {
+        BigDecimal::from_str(&s)
+            .with_context(|| format!("string  is not a BigDecimal: '{}'", s))
+            .deterministic()
     }
 
     pub(crate) fn data_source_create(
@@ -511,7 +770,7 @@ impl HostExports {
         name: String,
         params: Vec<String>,
         context: Option<DataSourceContext>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), HostExportError> {
         info!(
             logger,
             "Create data source";
@@ -537,7 +796,8 @@ impl HostExports {
                         .collect::<Vec<_>>()
                         .join(", ")
                 )
-            })?
+            })
+            .deterministic()?
             .clone();
 
         // Remember that we need to create this data source
@@ -599,6 +859,17 @@ pub(crate) fn json_from_bytes(bytes: &Vec<u8>) -> Result<serde_json::Value, serd
     serde_json::from_reader(bytes.as_slice())
 }
 
+/// Like `HostExports::ethereum_decode`, but an invalid `types` string is an
+/// error just like `data` that does not match it
+pub(crate) fn ethereum_try_decode(types: &str, data: &[u8]) -> Result<Token, anyhow::Error> {
+    let param_type = ethabi::param_type::Reader::read(types)
+        .map_err(|e| anyhow::anyhow!("`{}` is not a valid Ethereum type: {}", types, e))?;
+    ethabi::decode(&[param_type], data)
+        .map_err(|e| anyhow::anyhow!("Failed to decode data as `{}`: {}", types, e))?
+        .pop()
+        .with_context(|| format!("Failed to decode data as `{}`", types))
+}
+
 pub(crate) fn string_to_h160(string: &str) -> Result<H160, anyhow::Error> {
     // `H160::from_str` takes a hex string with no leading `0x`.
     let s = string.trim_start_matches("0x");
@@ -636,10 +907,26 @@ fn block_on<I, ER>(future: impl Future<Item = I, Error = ER> + Send) -> Result<I
     block_on03(future.compat())
 }
 
+fn checked_big_decimal(x: BigDecimal) -> Result<BigDecimal, HostExportError> {
+    x.check_exponent().deterministic()?;
+    Ok(x)
+}
+
 fn block_on03<T>(future: impl futures03::Future<Output = T> + Send) -> T {
     graph::block_on_allow_panic(future)
 }
 
+/// A file that is too large will always be too large, while any other
+/// failure to read a file from IPFS may go away when the handler is retried.
+fn ipfs_error(e: failure::Error) -> HostExportError {
+    let too_large = e.downcast_ref::<FileTooLargeError>().is_some();
+    let e = anyhow::Error::from(e.compat());
+    match too_large {
+        true => HostExportError::Deterministic(e),
+        false => HostExportError::Unknown(e),
+    }
+}
+
 #[test]
 fn bytes_to_string_is_lossy() {
     assert_eq!(
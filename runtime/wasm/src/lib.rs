@@ -1,6 +1,9 @@
 mod asc_abi;
 mod to_from;
 
+/// Classification of errors returned by host exports.
+mod error;
+
 /// Public interface of the crate, receives triggers to be processed.
 mod host;
 pub use host::RuntimeHostBuilder;
@@ -14,6 +17,9 @@ mod module;
 /// Runtime-agnostic implementation of exports to WASM.
 mod host_exports;
 
+/// Runs mappings against an in-memory store for unit tests of subgraphs.
+pub mod testing;
+
 use graph::prelude::web3::types::Address;
 use graph::prelude::{Store, SubgraphDeploymentStore};
 
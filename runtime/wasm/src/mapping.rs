@@ -5,13 +5,44 @@ use futures03::channel::oneshot::Sender;
 use graph::components::ethereum::*;
 use graph::components::subgraph::SharedProofOfIndexing;
 use graph::prelude::*;
-use std::collections::BTreeMap;
-use std::sync::Arc;
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
 use std::thread;
 use std::time::Instant;
 use strum_macros::AsStaticStr;
+use tiny_keccak::keccak256;
 use web3::types::{Log, Transaction};
 
+lazy_static! {
+    /// Path to a wasmtime cache configuration file. When set, compiled
+    /// modules are cached on disk as described there and reused across
+    /// restarts of the node
+    static ref WASM_CACHE_CONFIG: Option<String> = env::var("GRAPH_WASM_CACHE_CONFIG").ok();
+
+    /// Modules that are currently in use, keyed by the hash of their code,
+    /// so that deployments with the same mapping share the compiled module
+    static ref VALID_MODULES: Mutex<HashMap<[u8; 32], Weak<ValidModule>>> =
+        Mutex::new(HashMap::new());
+
+    /// The maximum size of the linear memory of a mapping, in WASM pages.
+    /// It is set in megabytes with `GRAPH_MAPPING_MAX_MEMORY`; a handler
+    /// that needs more memory fails deterministically
+    pub(crate) static ref MAX_MEMORY_PAGES: Option<u32> = env::var("GRAPH_MAPPING_MAX_MEMORY")
+        .ok()
+        .map(|s| {
+            let mb = u32::from_str(&s)
+                .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_MAPPING_MAX_MEMORY"));
+            mb.saturating_mul(1024 * 1024 / WASM_PAGE_SIZE)
+        });
+}
+
+/// The size of a page of WASM linear memory in bytes
+pub(crate) const WASM_PAGE_SIZE: u32 = 64 * 1024;
+
 /// Spawn a wasm module in its own thread.
 pub fn spawn_module(
     raw_module: Vec<u8>,
@@ -21,7 +52,7 @@ pub fn spawn_module(
     runtime: tokio::runtime::Handle,
     timeout: Option<Duration>,
 ) -> Result<mpsc::Sender<MappingRequest>, anyhow::Error> {
-    let valid_module = Arc::new(ValidModule::new(&raw_module)?);
+    let valid_module = ValidModule::cached(&raw_module)?;
 
     // Create channel for event handling requests
     let (mapping_request_sender, mapping_request_receiver) = mpsc::channel(100);
@@ -64,11 +95,13 @@ pub fn spawn_module(
                             log,
                             params,
                             handler,
+                            event_name,
                         } => module.handle_ethereum_log(
                             handler.handler.as_str(),
                             transaction,
                             log,
                             params,
+                            event_name,
                         ),
                         MappingTrigger::Call {
                             transaction,
@@ -86,6 +119,12 @@ pub fn spawn_module(
                         MappingTrigger::Block { handler } => {
                             module.handle_ethereum_block(handler.handler.as_str())
                         }
+                        MappingTrigger::Entity { entity, handler } => {
+                            module.handle_entity(handler.handler.as_str(), entity)
+                        }
+                        MappingTrigger::File { data, handler } => {
+                            module.handle_file(handler.as_str(), data)
+                        }
                     };
                     section.end();
 
@@ -114,6 +153,8 @@ pub(crate) enum MappingTrigger {
         log: Arc<Log>,
         params: Vec<LogParam>,
         handler: MappingEventHandler,
+        /// The name of the event, for wildcard event handlers
+        event_name: Option<String>,
     },
     Call {
         transaction: Arc<Transaction>,
@@ -125,10 +166,18 @@ pub(crate) enum MappingTrigger {
     Block {
         handler: MappingBlockHandler,
     },
+    Entity {
+        entity: Entity,
+        handler: MappingEntityHandler,
+    },
+    File {
+        data: bytes::Bytes,
+        handler: String,
+    },
 }
 
 type MappingResponse = (
-    Result<BlockState, anyhow::Error>,
+    Result<BlockState, MappingError>,
     futures::Finished<Instant, Error>,
 );
 
@@ -154,7 +203,12 @@ impl MappingContext {
             logger: self.logger.clone(),
             host_exports: self.host_exports.clone(),
             block: self.block.clone(),
-            state: BlockState::new(self.state.entity_cache.store.clone(), Default::default()),
+            state: BlockState::new(self.state.entity_cache.store.clone(), Default::default())
+                .with_fork(
+                    self.state.entity_cache.fork.clone(),
+                    self.state.entity_cache.fork_block,
+                )
+                .with_block_finality(self.state.block_is_final),
             proof_of_indexing: self.proof_of_indexing.cheap_clone(),
         }
     }
@@ -186,14 +240,28 @@ impl ValidModule {
         config.interruptable(true); // For timeouts.
         config.cranelift_nan_canonicalization(true); // For NaN determinism.
         config.cranelift_opt_level(wasmtime::OptLevel::None);
+        if let Some(path) = WASM_CACHE_CONFIG.as_ref() {
+            config.cache_config_load(path)?;
+        }
         let engine = &wasmtime::Engine::new(&config);
-        let module = wasmtime::Module::from_binary(&engine, raw_module)?;
+        let raw_module = match *MAX_MEMORY_PAGES {
+            Some(max_pages) => Cow::Owned(limit_memory(raw_module, max_pages)?),
+            None => Cow::Borrowed(raw_module),
+        };
+        let module = wasmtime::Module::from_binary(&engine, &raw_module)?;
 
         let mut import_name_to_modules: BTreeMap<String, Vec<String>> = BTreeMap::new();
         for (name, module) in module
             .imports()
             .map(|import| (import.name(), import.module()))
         {
+            if let Some(reason) = nondeterministic_import(module, name) {
+                return Err(anyhow::anyhow!(
+                    "the mapping imports `{}`, which mappings can not use since {}",
+                    name,
+                    reason
+                ));
+            }
             import_name_to_modules
                 .entry(name.to_string())
                 .or_default()
@@ -205,4 +273,119 @@ impl ValidModule {
             import_name_to_modules,
         })
     }
+
+    /// Return the module for `raw_module`, reusing an already compiled
+    /// module with the same code if there is one.
+    pub fn cached(raw_module: &[u8]) -> Result<Arc<Self>, anyhow::Error> {
+        let hash = keccak256(raw_module);
+        let mut modules = VALID_MODULES.lock().unwrap();
+        if let Some(module) = modules.get(&hash).and_then(Weak::upgrade) {
+            return Ok(module);
+        }
+
+        let module = Arc::new(ValidModule::new(raw_module)?);
+        modules.retain(|_, module| module.strong_count() > 0);
+        modules.insert(hash, Arc::downgrade(&module));
+        Ok(module)
+    }
+}
+
+/// Why mappings can not import `name` from `module`, if they can not. These
+/// imports would make indexing nondeterministic, so that indexers would not
+/// agree on the data of a subgraph
+pub(crate) fn nondeterministic_import(module: &str, name: &str) -> Option<&'static str> {
+    let wasi = module.starts_with("wasi");
+    let now = "it reads the wall-clock time; use `block.timestamp()` instead";
+    match name {
+        // What AssemblyScript's `Date.now` imports, either as `env.Date.now`
+        // or as `Date.now` from the `Date` module
+        "Date.now" => Some(now),
+        "now" if module == "Date" => Some(now),
+        // What AssemblyScript's `Math.random` imports from `env` to seed
+        // itself; other modules may well have a function of that name
+        "seed" if module == "env" || module == "Math" || module == "Date" || wasi => {
+            Some("it seeds `Math.random`; use `block.random(seed)` instead")
+        }
+        "clock_time_get" | "random_get" if wasi => {
+            Some("WASI clocks and random numbers differ between indexers")
+        }
+        _ => None,
+    }
+}
+
+/// Rewrite `raw_module` so that its memories can not grow beyond
+/// `max_pages`, which wasmtime then enforces by failing `memory.grow`.
+/// Memories that start out larger than that keep their initial size as
+/// their maximum
+pub(crate) fn limit_memory(raw_module: &[u8], max_pages: u32) -> Result<Vec<u8>, anyhow::Error> {
+    const HEADER_LEN: usize = 8;
+    const MEMORY_SECTION: u8 = 5;
+
+    let malformed = || anyhow::anyhow!("WASM module is malformed");
+    let mut module = raw_module.get(..HEADER_LEN).ok_or_else(malformed)?.to_vec();
+    let mut rest = &raw_module[HEADER_LEN..];
+    while let Some(id) = rest.first().cloned() {
+        let (size, len) = read_leb(&rest[1..]).ok_or_else(malformed)?;
+        let end = 1 + len + size as usize;
+        let contents = rest.get(1 + len..end).ok_or_else(malformed)?;
+        if id == MEMORY_SECTION {
+            let contents = limit_memory_section(contents, max_pages).ok_or_else(malformed)?;
+            module.push(id);
+            write_leb(contents.len() as u32, &mut module);
+            module.extend_from_slice(&contents);
+        } else {
+            module.extend_from_slice(&rest[..end]);
+        }
+        rest = &rest[end..];
+    }
+    Ok(module)
+}
+
+fn limit_memory_section(contents: &[u8], max_pages: u32) -> Option<Vec<u8>> {
+    let (count, mut pos) = read_leb(contents)?;
+    let mut section = Vec::with_capacity(contents.len() + count as usize * 5);
+    write_leb(count, &mut section);
+    for _ in 0..count {
+        // Bit 0 of the flags says whether the memory has a maximum
+        let flags = *contents.get(pos)?;
+        let (min, len) = read_leb(contents.get(pos + 1..)?)?;
+        pos += 1 + len;
+        let max = if flags & 1 == 1 {
+            let (max, len) = read_leb(contents.get(pos..)?)?;
+            pos += len;
+            max.min(max_pages)
+        } else {
+            max_pages
+        };
+        section.push(flags | 1);
+        write_leb(min, &mut section);
+        write_leb(max.max(min), &mut section);
+    }
+    section.extend_from_slice(contents.get(pos..)?);
+    Some(section)
+}
+
+/// Read an unsigned LEB128 number from the start of `bytes` and return it
+/// together with the number of bytes it took up
+fn read_leb(bytes: &[u8]) -> Option<(u32, usize)> {
+    let mut value = 0u32;
+    for (i, byte) in bytes.iter().take(5).enumerate() {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn write_leb(mut value: u32, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
 }
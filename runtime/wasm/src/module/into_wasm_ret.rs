@@ -1,4 +1,5 @@
 use crate::asc_abi::AscPtr;
+use crate::error::HostExportError;
 use wasmtime::Trap;
 
 /// Helper trait for the `link!` macro.
@@ -6,6 +7,11 @@ pub(crate) trait IntoWasmRet {
     type Ret: wasmtime::WasmRet;
 
     fn into_wasm_ret(self) -> Self::Ret;
+
+    /// Whether this is an error that is not known to be deterministic.
+    fn is_nondeterministic_error(&self) -> bool {
+        false
+    }
 }
 
 impl IntoWasmRet for () {
@@ -57,13 +63,20 @@ impl<C> IntoWasmRet for AscPtr<C> {
     }
 }
 
-impl<T> IntoWasmRet for Result<T, Trap>
+impl<T> IntoWasmRet for Result<T, HostExportError>
 where
     T: IntoWasmRet,
     T::Ret: wasmtime::WasmTy,
 {
     type Ret = Result<T::Ret, Trap>;
     fn into_wasm_ret(self) -> Self::Ret {
-        self.map(|x| x.into_wasm_ret())
+        self.map(|x| x.into_wasm_ret()).map_err(Trap::from)
+    }
+
+    fn is_nondeterministic_error(&self) -> bool {
+        match self {
+            Err(e) => !e.is_deterministic(),
+            Ok(_) => false,
+        }
     }
 }
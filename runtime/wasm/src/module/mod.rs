@@ -9,16 +9,18 @@ use semver::Version;
 use wasmtime::{Memory, Trap};
 
 use crate::host_exports;
-use crate::mapping::MappingContext;
+use crate::mapping::{MappingContext, MAX_MEMORY_PAGES, WASM_PAGE_SIZE};
 use ethabi::LogParam;
 use graph::components::ethereum::*;
 use graph::data::store;
+use graph::data::subgraph::schema::SubgraphError;
 use graph::prelude::*;
 use web3::types::{Log, Transaction, U256};
 
 use crate::asc_abi::asc_ptr::*;
 use crate::asc_abi::class::*;
 use crate::asc_abi::*;
+use crate::error::{DeterministicResultExt, HostExportError};
 use crate::host_exports::HostExports;
 use crate::mapping::ValidModule;
 use crate::UnresolvedContractCall;
@@ -34,6 +36,17 @@ mod test;
 
 const TRAP_TIMEOUT: &str = "trap: interrupt";
 
+lazy_static! {
+    /// The maximum number of entity gets, sets and removes a single handler
+    /// invocation may perform. Handlers that perform more fail the subgraph.
+    static ref MAX_HANDLER_ENTITY_OPERATIONS: Option<u64> =
+        std::env::var("GRAPH_MAPPING_MAX_ENTITY_OPERATIONS")
+            .ok()
+            .map(|s| s.parse::<u64>().unwrap_or_else(|_| {
+                panic!("failed to parse env var GRAPH_MAPPING_MAX_ENTITY_OPERATIONS")
+            }));
+}
+
 /// Handle to a WASM instance, which is terminated if and only if this is dropped.
 pub(crate) struct WasmInstance {
     instance: wasmtime::Instance,
@@ -71,7 +84,7 @@ impl WasmInstance {
         handler_name: &str,
         value: &serde_json::Value,
         user_data: &store::Value,
-    ) -> Result<BlockState, anyhow::Error> {
+    ) -> Result<BlockState, MappingError> {
         let value = self.asc_new(value);
         let user_data = self.asc_new(user_data);
 
@@ -79,55 +92,62 @@ impl WasmInstance {
         let func = self
             .instance
             .get_func(handler_name)
-            .with_context(|| format!("function {} not found", handler_name))?
-            .get2()?;
+            .with_context(|| format!("function {} not found", handler_name))
+            .and_then(|func| func.get2())
+            .map_err(MappingError::Deterministic)?;
         func(value.wasm_ptr(), user_data.wasm_ptr())
-            .with_context(|| format!("Failed to handle callback '{}'", handler_name))?;
+            .map_err(|trap| self.classify_trap(handler_name, trap))
+            .map_err(|e| e.context(format!("Failed to handle callback '{}'", handler_name)))?;
 
         Ok(self.take_ctx().ctx.state)
     }
 
+    /// Call the event handler `handler_name` for `log`. Wildcard event
+    /// handlers also get the `event_name` of the event that decoded the log
     pub(crate) fn handle_ethereum_log(
         mut self,
         handler_name: &str,
         transaction: Arc<Transaction>,
         log: Arc<Log>,
         params: Vec<LogParam>,
-    ) -> Result<BlockState, anyhow::Error> {
+        event_name: Option<String>,
+    ) -> Result<BlockState, MappingError> {
         let block = self.instance_ctx().ctx.block.clone();
+        let api_version = self.instance_ctx().ctx.host_exports.api_version.clone();
 
         // Prepare an EthereumEvent for the WASM runtime
+        let event = EthereumEventData {
+            block: EthereumBlockData::from(block.as_ref()),
+            transaction: EthereumTransactionData::from(transaction.deref()),
+            address: log.address,
+            log_index: log.log_index.unwrap_or(U256::zero()),
+            transaction_log_index: log.log_index.unwrap_or(U256::zero()),
+            log_type: log.log_type.clone(),
+            params,
+        };
+
         // Decide on the destination type using the mapping
         // api version provided in the subgraph manifest
-        let event = if self.instance_ctx().ctx.host_exports.api_version >= Version::new(0, 0, 2) {
-            self.asc_new::<AscEthereumEvent<AscEthereumTransaction_0_0_2>, _>(&EthereumEventData {
-                block: EthereumBlockData::from(block.as_ref()),
-                transaction: EthereumTransactionData::from(transaction.deref()),
-                address: log.address,
-                log_index: log.log_index.unwrap_or(U256::zero()),
-                transaction_log_index: log.log_index.unwrap_or(U256::zero()),
-                log_type: log.log_type.clone(),
-                params,
-            })
+        let event = if let Some(name) = event_name {
+            self.asc_new::<AscEthereumWildcardEvent, _>(&EthereumWildcardEventData { event, name })
+                .erase()
+        } else if api_version >= Version::new(0, 0, 5) {
+            self.asc_new::<AscEthereumEvent<AscEthereumTransaction_0_0_2, AscEthereumBlock_0_0_5>, _>(
+                &event,
+            )
             .erase()
-        } else {
-            self.asc_new::<AscEthereumEvent<AscEthereumTransaction>, _>(&EthereumEventData {
-                block: EthereumBlockData::from(block.as_ref()),
-                transaction: EthereumTransactionData::from(transaction.deref()),
-                address: log.address,
-                log_index: log.log_index.unwrap_or(U256::zero()),
-                transaction_log_index: log.log_index.unwrap_or(U256::zero()),
-                log_type: log.log_type.clone(),
-                params,
-            })
+        } else if api_version >= Version::new(0, 0, 2) {
+            self.asc_new::<AscEthereumEvent<AscEthereumTransaction_0_0_2, AscEthereumBlock>, _>(
+                &event,
+            )
             .erase()
+        } else {
+            self.asc_new::<AscEthereumEvent<AscEthereumTransaction, AscEthereumBlock>, _>(&event)
+                .erase()
         };
 
-        // Invoke the event handler
-        self.invoke_handler(handler_name, event)?;
-
-        // Return the output state
-        Ok(self.take_ctx().ctx.state)
+        // Invoke the event handler and return the output state
+        self.handle_trigger(handler_name, event)
     }
 
     pub(crate) fn handle_ethereum_call(
@@ -137,7 +157,7 @@ impl WasmInstance {
         call: Arc<EthereumCall>,
         inputs: Vec<LogParam>,
         outputs: Vec<LogParam>,
-    ) -> Result<BlockState, anyhow::Error> {
+    ) -> Result<BlockState, MappingError> {
         let call = EthereumCallData {
             to: call.to,
             from: call.from,
@@ -145,30 +165,58 @@ impl WasmInstance {
             transaction: EthereumTransactionData::from(transaction.deref()),
             inputs,
             outputs,
+            error: call.error.clone(),
         };
-        let arg = if self.instance_ctx().ctx.host_exports.api_version >= Version::new(0, 0, 3) {
-            self.asc_new::<AscEthereumCall_0_0_3, _>(&call).erase()
+        let api_version = self.instance_ctx().ctx.host_exports.api_version.clone();
+        let arg = if api_version >= Version::new(0, 0, 5) {
+            self.asc_new::<AscEthereumCall_0_0_5, _>(&call).erase()
+        } else if api_version >= Version::new(0, 0, 3) {
+            self.asc_new::<AscEthereumCall_0_0_3<AscEthereumBlock>, _>(&call)
+                .erase()
         } else {
             self.asc_new::<AscEthereumCall, _>(&call).erase()
         };
 
-        self.invoke_handler(handler_name, arg)?;
-
-        Ok(self.take_ctx().ctx.state)
+        self.handle_trigger(handler_name, arg)
     }
 
     pub(crate) fn handle_ethereum_block(
         mut self,
         handler_name: &str,
-    ) -> Result<BlockState, anyhow::Error> {
+    ) -> Result<BlockState, MappingError> {
         let block = EthereumBlockData::from(self.instance_ctx().ctx.block.as_ref());
 
-        // Prepare an EthereumBlock for the WASM runtime
-        let arg = self.asc_new(&block);
+        // Prepare an EthereumBlock for the WASM runtime. Mappings with
+        // apiVersion 0.0.5 and later get the full block header
+        let arg = if self.instance_ctx().ctx.host_exports.api_version >= Version::new(0, 0, 5) {
+            self.asc_new::<AscEthereumBlock_0_0_5, _>(&block).erase()
+        } else {
+            self.asc_new::<AscEthereumBlock, _>(&block).erase()
+        };
 
-        self.invoke_handler(handler_name, arg)?;
+        self.handle_trigger(handler_name, arg)
+    }
 
-        Ok(self.take_ctx().ctx.state)
+    pub(crate) fn handle_entity(
+        mut self,
+        handler_name: &str,
+        entity: Entity,
+    ) -> Result<BlockState, MappingError> {
+        // Pass the entity from the source deployment to the WASM runtime
+        let arg = self.asc_new::<AscEntity, _>(&entity);
+
+        self.handle_trigger(handler_name, arg)
+    }
+
+    pub(crate) fn handle_file(
+        mut self,
+        handler_name: &str,
+        data: bytes::Bytes,
+    ) -> Result<BlockState, MappingError> {
+        // Pass the content of the file to the WASM runtime as `Bytes`
+        let arg = self.asc_new::<Uint8Array, _>(data.as_ref());
+
+        self.handle_trigger(handler_name, arg)
     }
 
     pub(crate) fn take_ctx(&mut self) -> WasmInstanceContext {
@@ -179,7 +227,6 @@ impl WasmInstance {
         std::cell::Ref::map(self.instance_ctx.borrow(), |i| i.as_ref().unwrap())
     }
 
-    #[cfg(test)]
     pub(crate) fn instance_ctx_mut(&self) -> std::cell::RefMut<'_, WasmInstanceContext> {
         std::cell::RefMut::map(self.instance_ctx.borrow_mut(), |i| i.as_mut().unwrap())
     }
@@ -189,26 +236,136 @@ impl WasmInstance {
         self.instance.get_func(func_name).unwrap()
     }
 
-    fn invoke_handler<C>(&mut self, handler: &str, arg: AscPtr<C>) -> Result<(), anyhow::Error> {
+    /// Runs the handler for a trigger. If the handler fails deterministically, its changes are
+    /// discarded and the error is recorded in the returned block state; it is up to the caller
+    /// to decide whether that fails the subgraph.
+    fn handle_trigger<C>(
+        mut self,
+        handler: &str,
+        arg: AscPtr<C>,
+    ) -> Result<BlockState, MappingError> {
+        self.instance_ctx_mut().ctx.state.enter_handler();
+
+        let result = self.invoke_handler(handler, arg);
+        self.instance_ctx().handler_stats.report(handler);
+
+        match result {
+            Ok(()) => {
+                let ctx = self.take_ctx().ctx;
+                let mut state = ctx.state;
+                state
+                    .exit_handler(&ctx.logger, &ctx.proof_of_indexing)
+                    .map_err(anyhow::Error::from)?;
+                Ok(state)
+            }
+            Err(MappingError::Deterministic(e)) => {
+                let ctx = self.take_ctx().ctx;
+                let error = SubgraphError {
+                    subgraph_id: ctx.host_exports.subgraph_id.clone(),
+                    message: format!("{:#}", e),
+                    block_ptr: Some(EthereumBlockPointer::from(ctx.block.as_ref())),
+                    handler: Some(handler.to_owned()),
+                };
+                let mut state = ctx.state;
+                state.exit_handler_and_discard_changes_due_to_error(error);
+                Ok(state)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn invoke_handler<C>(&mut self, handler: &str, arg: AscPtr<C>) -> Result<(), MappingError> {
         let func = self
             .instance
             .get_func(handler)
-            .with_context(|| format!("function {} not found", handler))?;
-
-        func.get1()?(arg.wasm_ptr()).map_err(|e| {
-            if e.to_string().contains(TRAP_TIMEOUT) {
-                anyhow::Error::context(
-                    e.into(),
-                    format!(
-                        "Handler '{}' hit the timeout of '{}' seconds",
-                        handler,
-                        self.instance_ctx().timeout.unwrap().as_secs()
-                    ),
-                )
-            } else {
-                anyhow::Error::context(e.into(), format!("Failed to invoke handler '{}'", handler))
+            .with_context(|| format!("function {} not found", handler))
+            .and_then(|func| func.get1())
+            .map_err(MappingError::Deterministic)?;
+
+        func(arg.wasm_ptr()).map_err(|trap| self.classify_trap(handler, trap))
+    }
+
+    /// Decides whether a trap raised while running `handler` would happen again if the handler
+    /// was retried. Timeouts and failures of host exports that depend on external resources are
+    /// not deterministic, everything else that makes the module trap is.
+    fn classify_trap(&self, handler: &str, trap: Trap) -> MappingError {
+        if trap.to_string().contains(TRAP_TIMEOUT) {
+            MappingError::Unknown(anyhow::Error::context(
+                trap.into(),
+                format!(
+                    "Handler '{}' hit the timeout of '{}' seconds",
+                    handler,
+                    self.instance_ctx().timeout.unwrap().as_secs()
+                ),
+            ))
+        } else {
+            // Running out of memory usually makes the AssemblyScript allocator trap with
+            // `unreachable`, which would not tell anybody what happened
+            let message = match *MAX_MEMORY_PAGES {
+                Some(max_pages) if self.instance_ctx().memory.size() >= max_pages => format!(
+                    "Handler '{}' ran out of memory, mappings can use at most {} MB",
+                    handler,
+                    max_pages as u64 * WASM_PAGE_SIZE as u64 / (1024 * 1024)
+                ),
+                _ => format!("Failed to invoke handler '{}'", handler),
+            };
+            let e = anyhow::Error::context(trap.into(), message);
+            match self.instance_ctx().nondeterministic_host_trap {
+                true => MappingError::Unknown(e),
+                false => MappingError::Deterministic(e),
             }
-        })
+        }
+    }
+}
+
+/// Counts what a handler does while it runs. A `WasmInstanceContext` only ever runs one handler.
+pub(crate) struct HandlerStats {
+    host_metrics: Arc<HostMetrics>,
+    pub(crate) host_fn_calls: u64,
+    pub(crate) entity_gets: u64,
+    pub(crate) entity_sets: u64,
+    pub(crate) entity_removes: u64,
+    /// The limit from `GRAPH_MAPPING_MAX_ENTITY_OPERATIONS`
+    pub(crate) max_entity_operations: Option<u64>,
+}
+
+impl HandlerStats {
+    fn new(host_metrics: Arc<HostMetrics>) -> Self {
+        HandlerStats {
+            host_metrics,
+            host_fn_calls: 0,
+            entity_gets: 0,
+            entity_sets: 0,
+            entity_removes: 0,
+            max_entity_operations: *MAX_HANDLER_ENTITY_OPERATIONS,
+        }
+    }
+
+    /// Count an entity operation. Fails deterministically once the handler
+    /// performed more than `GRAPH_MAPPING_MAX_ENTITY_OPERATIONS` of them,
+    /// since it will do the same every time it runs for this trigger.
+    fn entity_operation(&mut self, op: fn(&mut Self) -> &mut u64) -> Result<(), HostExportError> {
+        *op(self) += 1;
+        let total = self.entity_gets + self.entity_sets + self.entity_removes;
+        match self.max_entity_operations {
+            Some(max) if total > max => Err(HostExportError::deterministic(anyhow::anyhow!(
+                "Handler exceeded the limit of {} entity operations \
+                 ({} gets, {} sets, {} removes)",
+                max,
+                self.entity_gets,
+                self.entity_sets,
+                self.entity_removes,
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn report(&self, handler: &str) {
+        let metrics = &self.host_metrics;
+        metrics.observe_handler_host_fn_calls(self.host_fn_calls, handler);
+        metrics.observe_handler_entity_operations(self.entity_gets, handler, "get");
+        metrics.observe_handler_entity_operations(self.entity_sets, handler, "set");
+        metrics.observe_handler_entity_operations(self.entity_removes, handler, "remove");
     }
 }
 
@@ -230,9 +387,16 @@ pub(crate) struct WasmInstanceContext {
     pub(crate) host_metrics: Arc<HostMetrics>,
     pub(crate) timeout: Option<Duration>,
 
+    // Set when a host export fails with an error that is not known to be deterministic, so that
+    // the trap it causes is not mistaken for a deterministic failure of the handler.
+    pub(crate) nondeterministic_host_trap: bool,
+
     // Used by ipfs.map.
     pub(crate) timeout_stopwatch: Arc<std::sync::Mutex<TimeoutStopwatch>>,
 
+    // What the handler that is currently running did, for the per-handler metrics.
+    pub(crate) handler_stats: HandlerStats,
+
     // First free byte in the current arena. Set on the first call to `raw_new`.
     arena_start_ptr: i32,
 
@@ -318,10 +482,15 @@ impl WasmInstance {
                             }
 
                             let instance = instance.as_mut().unwrap();
+                            instance.handler_stats.host_fn_calls += 1;
                             let _section = instance.host_metrics.stopwatch.start_section($section);
-                            instance.$rust_name(
+                            let ret = instance.$rust_name(
                                 $($param.into()),*
-                            ).into_wasm_ret()
+                            );
+                            if ret.is_nondeterministic_error() {
+                                instance.nondeterministic_host_trap = true;
+                            }
+                            ret.into_wasm_ret()
                         }
                     )?;
                 }
@@ -364,19 +533,18 @@ impl WasmInstance {
                     }
 
                     let instance = instance.as_mut().unwrap();
+                    instance.handler_stats.host_fn_calls += 1;
                     let stopwatch = &instance.host_metrics.stopwatch;
                     let _section = stopwatch.start_section("host_export_ethereum_call");
 
-                    // For apiVersion >= 0.0.4 the call passed from the mapping includes the
-                    // function signature; subgraphs using an apiVersion < 0.0.4 don't pass
-                    // the the signature along with the call.
-                    let arg = if instance.ctx.host_exports.api_version >= Version::new(0, 0, 4) {
-                        instance.asc_get::<_, AscUnresolvedContractCall_0_0_4>(call_ptr.into())
-                    } else {
-                        instance.asc_get::<_, AscUnresolvedContractCall>(call_ptr.into())
-                    };
-
-                    let ret = instance.ethereum_call(arg)?.wasm_ptr();
+                    let arg = instance.contract_call(call_ptr);
+                    let ret = instance.ethereum_call(arg).map_err(|e| {
+                        if !e.is_deterministic() {
+                            instance.nondeterministic_host_trap = true;
+                        }
+                        Trap::from(e)
+                    })?;
+                    let ret = ret.wasm_ptr();
                     instance.host_metrics.observe_host_fn_execution_time(
                         start.elapsed().as_secs_f64(),
                         "ethereum_call",
@@ -386,6 +554,28 @@ impl WasmInstance {
             )?;
         }
 
+        link!(
+            "ethereum.callAtBlock",
+            ethereum_call_at_block,
+            "host_export_ethereum_call",
+            call_ptr,
+            block_number
+        );
+
+        link!(
+            "ethereum.callWithRevertReason",
+            ethereum_call_with_revert_reason,
+            "host_export_ethereum_call",
+            call_ptr
+        );
+
+        link!(
+            "ethereum.blockNumberAtTimestamp",
+            ethereum_block_number_at_timestamp,
+            "host_export_ethereum_block_number_at_timestamp",
+            timestamp_ptr
+        );
+
         link!("abort", abort, message_ptr, file_name_ptr, line, column);
 
         link!("store.get", store_get, "host_export_store_get", entity, id);
@@ -427,6 +617,17 @@ impl WasmInstance {
 
         link!("crypto.keccak256", crypto_keccak_256, ptr);
 
+        link!("block.timestamp", block_timestamp,);
+        link!("block.random", block_random, seed_ptr);
+
+        link!("ethereum.decode", ethereum_decode, types_ptr, data_ptr);
+        link!(
+            "ethereum.try_decode",
+            ethereum_try_decode,
+            types_ptr,
+            data_ptr
+        );
+
         link!("bigInt.plus", big_int_plus, x_ptr, y_ptr);
         link!("bigInt.minus", big_int_minus, x_ptr, y_ptr);
         link!("bigInt.times", big_int_times, x_ptr, y_ptr);
@@ -434,6 +635,11 @@ impl WasmInstance {
         link!("bigInt.dividedByDecimal", big_int_divided_by_decimal, x, y);
         link!("bigInt.mod", big_int_mod, x_ptr, y_ptr);
         link!("bigInt.pow", big_int_pow, x_ptr, exp);
+        link!("bigInt.sqrt", big_int_sqrt, x_ptr);
+        link!("bigInt.bitOr", big_int_bit_or, x_ptr, y_ptr);
+        link!("bigInt.bitAnd", big_int_bit_and, x_ptr, y_ptr);
+        link!("bigInt.leftShift", big_int_left_shift, x_ptr, bits);
+        link!("bigInt.rightShift", big_int_right_shift, x_ptr, bits);
 
         link!("bigDecimal.toString", big_decimal_to_string, ptr);
         link!("bigDecimal.fromString", big_decimal_from_string, ptr);
@@ -556,8 +762,10 @@ impl WasmInstanceContext {
             memory,
             ctx,
             valid_module,
+            handler_stats: HandlerStats::new(host_metrics.cheap_clone()),
             host_metrics,
             timeout,
+            nondeterministic_host_trap: false,
             timeout_stopwatch,
             arena_free_size: 0,
             arena_start_ptr: 0,
@@ -590,8 +798,10 @@ impl WasmInstanceContext {
             memory,
             ctx,
             valid_module,
+            handler_stats: HandlerStats::new(host_metrics.cheap_clone()),
             host_metrics,
             timeout,
+            nondeterministic_host_trap: false,
             timeout_stopwatch,
             arena_free_size: 0,
             arena_start_ptr: 0,
@@ -609,7 +819,7 @@ impl WasmInstanceContext {
         file_name_ptr: AscPtr<AscString>,
         line_number: u32,
         column_number: u32,
-    ) -> Result<(), Trap> {
+    ) -> Result<(), HostExportError> {
         let message = match message_ptr.is_null() {
             false => Some(self.asc_get(message_ptr)),
             true => None,
@@ -630,8 +840,7 @@ impl WasmInstanceContext {
             .ctx
             .host_exports
             .abort(message, file_name, line_number, column_number)
-            .unwrap_err()
-            .into())
+            .unwrap_err())
     }
 
     /// function store.set(entity: string, id: string, data: Entity): void
@@ -640,12 +849,13 @@ impl WasmInstanceContext {
         entity_ptr: AscPtr<AscString>,
         id_ptr: AscPtr<AscString>,
         data_ptr: AscPtr<AscEntity>,
-    ) -> Result<(), Trap> {
+    ) -> Result<(), HostExportError> {
         let entity = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
-        let data = self.try_asc_get(data_ptr)?;
+        let data = self.try_asc_get(data_ptr).deterministic()?;
+        self.handler_stats
+            .entity_operation(|stats| &mut stats.entity_sets)?;
         self.ctx.host_exports.store_set(
-            &self.ctx.logger,
             &mut self.ctx.state,
             &self.ctx.proof_of_indexing,
             entity,
@@ -656,16 +866,22 @@ impl WasmInstanceContext {
     }
 
     /// function store.remove(entity: string, id: string): void
-    fn store_remove(&mut self, entity_ptr: AscPtr<AscString>, id_ptr: AscPtr<AscString>) {
+    fn store_remove(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
         let entity = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
+        self.handler_stats
+            .entity_operation(|stats| &mut stats.entity_removes)?;
         self.ctx.host_exports.store_remove(
-            &self.ctx.logger,
             &mut self.ctx.state,
             &self.ctx.proof_of_indexing,
             entity,
             id,
         );
+        Ok(())
     }
 
     /// function store.get(entity: string, id: string): Entity | null
@@ -673,10 +889,12 @@ impl WasmInstanceContext {
         &mut self,
         entity_ptr: AscPtr<AscString>,
         id_ptr: AscPtr<AscString>,
-    ) -> Result<AscPtr<AscEntity>, Trap> {
+    ) -> Result<AscPtr<AscEntity>, HostExportError> {
         let start = Instant::now();
         let entity_ptr = self.asc_get(entity_ptr);
         let id_ptr = self.asc_get(id_ptr);
+        self.handler_stats
+            .entity_operation(|stats| &mut stats.entity_gets)?;
         let entity_option =
             self.ctx
                 .host_exports
@@ -703,17 +921,89 @@ impl WasmInstanceContext {
     fn ethereum_call(
         &mut self,
         call: UnresolvedContractCall,
-    ) -> Result<AscEnumArray<EthereumValueKind>, Trap> {
-        let result =
-            self.ctx
-                .host_exports
-                .ethereum_call(&self.ctx.logger, &self.ctx.block, call)?;
+    ) -> Result<AscEnumArray<EthereumValueKind>, HostExportError> {
+        let result = self.ctx.host_exports.ethereum_call(
+            &self.ctx.logger,
+            EthereumBlockPointer::from(self.ctx.block.as_ref()),
+            self.ctx.state.block_is_final,
+            call,
+        )?;
         Ok(match result {
-            Some(tokens) => self.asc_new(tokens.as_slice()),
+            Ok(tokens) => self.asc_new(tokens.as_slice()),
+            Err(_) => AscPtr::null(),
+        })
+    }
+
+    /// function ethereum.callWithRevertReason(call: SmartContractCall): Result<Array<Token>, string>
+    fn ethereum_call_with_revert_reason(
+        &mut self,
+        call_ptr: u32,
+    ) -> Result<
+        AscPtr<AscResult<Array<AscPtr<AscEnum<EthereumValueKind>>>, AscString>>,
+        HostExportError,
+    > {
+        let call = self.contract_call(call_ptr);
+        let result = self.ctx.host_exports.ethereum_call(
+            &self.ctx.logger,
+            EthereumBlockPointer::from(self.ctx.block.as_ref()),
+            self.ctx.state.block_is_final,
+            call,
+        )?;
+        Ok(self.asc_new(&result))
+    }
+
+    /// function ethereum.callAtBlock(call: SmartContractCall, blockNumber: i32): Array<Token> | null
+    fn ethereum_call_at_block(
+        &mut self,
+        call_ptr: u32,
+        block_number: u32,
+    ) -> Result<AscEnumArray<EthereumValueKind>, HostExportError> {
+        let call = self.contract_call(call_ptr);
+        let block_ptr = self.ctx.host_exports.ethereum_call_block(
+            &self.ctx.logger,
+            &self.ctx.block,
+            block_number.into(),
+        )?;
+        // Blocks before a final block are final, too
+        let result = self.ctx.host_exports.ethereum_call(
+            &self.ctx.logger,
+            block_ptr,
+            self.ctx.state.block_is_final,
+            call,
+        )?;
+        Ok(match result {
+            Ok(tokens) => self.asc_new(tokens.as_slice()),
+            Err(_) => AscPtr::null(),
+        })
+    }
+
+    /// function ethereum.blockNumberAtTimestamp(timestamp: BigInt): BigInt | null
+    fn ethereum_block_number_at_timestamp(
+        &mut self,
+        timestamp_ptr: AscPtr<AscBigInt>,
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
+        let number = self.ctx.host_exports.ethereum_block_number_at_timestamp(
+            &self.ctx.logger,
+            &self.ctx.block,
+            self.asc_get(timestamp_ptr),
+        )?;
+        Ok(match number {
+            Some(number) => self.asc_new(&BigInt::from(number)),
             None => AscPtr::null(),
         })
     }
 
+    fn contract_call(&self, call_ptr: u32) -> UnresolvedContractCall {
+        // For apiVersion >= 0.0.4 the call passed from the mapping includes the
+        // function signature; subgraphs using an apiVersion < 0.0.4 don't pass
+        // the the signature along with the call.
+        if self.ctx.host_exports.api_version >= Version::new(0, 0, 4) {
+            self.asc_get::<_, AscUnresolvedContractCall_0_0_4>(call_ptr.into())
+        } else {
+            self.asc_get::<_, AscUnresolvedContractCall>(call_ptr.into())
+        }
+    }
+
     /// function typeConversion.bytesToString(bytes: Bytes): string
     fn bytes_to_string(&mut self, bytes_ptr: AscPtr<Uint8Array>) -> AscPtr<AscString> {
         let string = host_exports::bytes_to_string(&self.ctx.logger, self.asc_get(bytes_ptr));
@@ -747,9 +1037,12 @@ impl WasmInstanceContext {
     }
 
     /// function typeConversion.stringToH160(s: String): H160
-    fn string_to_h160(&mut self, str_ptr: AscPtr<AscString>) -> Result<AscPtr<AscH160>, Trap> {
+    fn string_to_h160(
+        &mut self,
+        str_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscH160>, HostExportError> {
         let s: String = self.asc_get(str_ptr);
-        let h160 = host_exports::string_to_h160(&s)?;
+        let h160 = host_exports::string_to_h160(&s).deterministic()?;
         let h160_obj: AscPtr<AscH160> = self.asc_new(&h160);
         Ok(h160_obj)
     }
@@ -758,12 +1051,12 @@ impl WasmInstanceContext {
     fn json_from_bytes(
         &mut self,
         bytes_ptr: AscPtr<Uint8Array>,
-    ) -> Result<AscPtr<AscEnum<JsonValueKind>>, Trap> {
+    ) -> Result<AscPtr<AscEnum<JsonValueKind>>, HostExportError> {
         let bytes: Vec<u8> = self.asc_get(bytes_ptr);
 
-        let result = host_exports::json_from_bytes(&bytes).with_context(|| {
-            format!("Failed to parse JSON from byte array. Bytes: `{:?}`", bytes,)
-        })?;
+        let result = host_exports::json_from_bytes(&bytes)
+            .with_context(|| format!("Failed to parse JSON from byte array. Bytes: `{:?}`", bytes,))
+            .deterministic()?;
         Ok(self.asc_new(&result))
     }
 
@@ -771,7 +1064,7 @@ impl WasmInstanceContext {
     fn json_try_from_bytes(
         &mut self,
         bytes_ptr: AscPtr<Uint8Array>,
-    ) -> Result<AscPtr<AscResult<AscEnum<JsonValueKind>, bool>>, Trap> {
+    ) -> Result<AscPtr<AscResult<AscEnum<JsonValueKind>, bool>>, HostExportError> {
         let bytes: Vec<u8> = self.asc_get(bytes_ptr);
         let result = host_exports::json_from_bytes(&bytes).map_err(|e| {
             warn!(
@@ -789,7 +1082,10 @@ impl WasmInstanceContext {
     }
 
     /// function ipfs.cat(link: String): Bytes
-    fn ipfs_cat(&mut self, link_ptr: AscPtr<AscString>) -> Result<AscPtr<Uint8Array>, Trap> {
+    fn ipfs_cat(
+        &mut self,
+        link_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<Uint8Array>, HostExportError> {
         let link = self.asc_get(link_ptr);
         let ipfs_res = self.ctx.host_exports.ipfs_cat(&self.ctx.logger, link);
         match ipfs_res {
@@ -799,7 +1095,13 @@ impl WasmInstanceContext {
             }
 
             // Return null in case of error.
-            Err(e) => {
+            Err(HostExportError::Deterministic(e)) => {
+                info!(&self.ctx.logger, "IPFS file is too large for ipfs.cat, returning `null`";
+                                    "link" => self.asc_get::<String, _>(link_ptr),
+                                    "error" => e.to_string());
+                Ok(AscPtr::null())
+            }
+            Err(HostExportError::Unknown(e)) => {
                 info!(&self.ctx.logger, "Failed ipfs.cat, returning `null`";
                                     "link" => self.asc_get::<String, _>(link_ptr),
                                     "error" => e.to_string());
@@ -815,10 +1117,10 @@ impl WasmInstanceContext {
         callback: AscPtr<AscString>,
         user_data: AscPtr<AscEnum<StoreValueKind>>,
         flags: AscPtr<Array<AscPtr<AscString>>>,
-    ) -> Result<(), Trap> {
+    ) -> Result<(), HostExportError> {
         let link: String = self.asc_get(link_ptr);
         let callback: String = self.asc_get(callback);
-        let user_data: store::Value = self.try_asc_get(user_data)?;
+        let user_data: store::Value = self.try_asc_get(user_data).deterministic()?;
 
         let flags = self.asc_get(flags);
 
@@ -855,6 +1157,7 @@ impl WasmInstanceContext {
                 .state
                 .created_data_sources
                 .extend(output_state.created_data_sources);
+            self.ctx.state.poi_events.extend(output_state.poi_events);
         }
 
         Ok(())
@@ -862,26 +1165,29 @@ impl WasmInstanceContext {
 
     /// Expects a decimal string.
     /// function json.toI64(json: String): i64
-    fn json_to_i64(&mut self, json_ptr: AscPtr<AscString>) -> Result<i64, Trap> {
+    fn json_to_i64(&mut self, json_ptr: AscPtr<AscString>) -> Result<i64, HostExportError> {
         let number = self.ctx.host_exports.json_to_i64(self.asc_get(json_ptr))?;
         Ok(number)
     }
 
     /// Expects a decimal string.
     /// function json.toU64(json: String): u64
-    fn json_to_u64(&mut self, json_ptr: AscPtr<AscString>) -> Result<u64, Trap> {
+    fn json_to_u64(&mut self, json_ptr: AscPtr<AscString>) -> Result<u64, HostExportError> {
         Ok(self.ctx.host_exports.json_to_u64(self.asc_get(json_ptr))?)
     }
 
     /// Expects a decimal string.
     /// function json.toF64(json: String): f64
-    fn json_to_f64(&mut self, json_ptr: AscPtr<AscString>) -> Result<f64, Trap> {
+    fn json_to_f64(&mut self, json_ptr: AscPtr<AscString>) -> Result<f64, HostExportError> {
         Ok(self.ctx.host_exports.json_to_f64(self.asc_get(json_ptr))?)
     }
 
     /// Expects a decimal string.
     /// function json.toBigInt(json: String): BigInt
-    fn json_to_big_int(&mut self, json_ptr: AscPtr<AscString>) -> Result<AscPtr<AscBigInt>, Trap> {
+    fn json_to_big_int(
+        &mut self,
+        json_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
         let big_int = self
             .ctx
             .host_exports
@@ -890,11 +1196,51 @@ impl WasmInstanceContext {
         Ok(big_int_ptr)
     }
 
+    /// function ethereum.decode(types: String, data: Bytes): ethereum.Value | null
+    fn ethereum_decode(
+        &mut self,
+        types_ptr: AscPtr<AscString>,
+        data_ptr: AscPtr<Uint8Array>,
+    ) -> Result<AscPtr<AscEnum<EthereumValueKind>>, HostExportError> {
+        let token = self
+            .ctx
+            .host_exports
+            .ethereum_decode(self.asc_get(types_ptr), self.asc_get(data_ptr))?;
+        Ok(match token {
+            Some(token) => self.asc_new(&token),
+            None => AscPtr::null(),
+        })
+    }
+
+    /// function ethereum.try_decode(types: String, data: Bytes): Result<ethereum.Value, boolean>
+    fn ethereum_try_decode(
+        &mut self,
+        types_ptr: AscPtr<AscString>,
+        data_ptr: AscPtr<Uint8Array>,
+    ) -> Result<AscPtr<AscResult<AscEnum<EthereumValueKind>, bool>>, HostExportError> {
+        let types: String = self.asc_get(types_ptr);
+        let data: Vec<u8> = self.asc_get(data_ptr);
+        let result = host_exports::ethereum_try_decode(&types, &data).map_err(|e| {
+            warn!(
+                &self.ctx.logger,
+                "Failed to decode Ethereum value";
+                "types" => &types,
+                "data" => format!("0x{}", hex::encode(&data)),
+                "error" => format!("{}", e)
+            );
+
+            // Map decoding errors to boolean to match the
+            // `Result<ethereum.Value, boolean>` result type expected by mappings
+            true
+        });
+        Ok(self.asc_new(&result))
+    }
+
     /// function crypto.keccak256(input: Bytes): Bytes
     fn crypto_keccak_256(
         &mut self,
         input_ptr: AscPtr<Uint8Array>,
-    ) -> Result<AscPtr<Uint8Array>, Trap> {
+    ) -> Result<AscPtr<Uint8Array>, HostExportError> {
         let input = self
             .ctx
             .host_exports
@@ -903,12 +1249,27 @@ impl WasmInstanceContext {
         Ok(hash_ptr)
     }
 
+    /// function block.timestamp(): BigInt
+    fn block_timestamp(&mut self) -> AscPtr<AscBigInt> {
+        let timestamp = BigInt::from_unsigned_u256(&self.ctx.block.timestamp);
+        self.asc_new(&timestamp)
+    }
+
+    /// function block.random(seed: Bytes): Bytes
+    fn block_random(&mut self, seed_ptr: AscPtr<Uint8Array>) -> AscPtr<Uint8Array> {
+        let random = self
+            .ctx
+            .host_exports
+            .block_random(&self.ctx.block, self.asc_get(seed_ptr));
+        self.asc_new(random.as_ref())
+    }
+
     /// function bigInt.plus(x: BigInt, y: BigInt): BigInt
     fn big_int_plus(
         &mut self,
         x_ptr: AscPtr<AscBigInt>,
         y_ptr: AscPtr<AscBigInt>,
-    ) -> Result<AscPtr<AscBigInt>, Trap> {
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
         let result = self
             .ctx
             .host_exports
@@ -922,7 +1283,7 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigInt>,
         y_ptr: AscPtr<AscBigInt>,
-    ) -> Result<AscPtr<AscBigInt>, Trap> {
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
         let result = self
             .ctx
             .host_exports
@@ -936,7 +1297,7 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigInt>,
         y_ptr: AscPtr<AscBigInt>,
-    ) -> Result<AscPtr<AscBigInt>, Trap> {
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
         let result = self
             .ctx
             .host_exports
@@ -950,7 +1311,7 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigInt>,
         y_ptr: AscPtr<AscBigInt>,
-    ) -> Result<AscPtr<AscBigInt>, Trap> {
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
         let result = self
             .ctx
             .host_exports
@@ -964,12 +1325,12 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigInt>,
         y_ptr: AscPtr<AscBigDecimal>,
-    ) -> Result<AscPtr<AscBigDecimal>, Trap> {
+    ) -> Result<AscPtr<AscBigDecimal>, HostExportError> {
         let x = BigDecimal::new(self.asc_get::<BigInt, _>(x_ptr), 0);
         let result = self
             .ctx
             .host_exports
-            .big_decimal_divided_by(x, self.try_asc_get(y_ptr)?)?;
+            .big_decimal_divided_by(x, self.try_asc_get(y_ptr).deterministic()?)?;
         Ok(self.asc_new(&result))
     }
 
@@ -978,11 +1339,11 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigInt>,
         y_ptr: AscPtr<AscBigInt>,
-    ) -> Result<AscPtr<AscBigInt>, Trap> {
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
         let result = self
             .ctx
             .host_exports
-            .big_int_mod(self.asc_get(x_ptr), self.asc_get(y_ptr));
+            .big_int_mod(self.asc_get(x_ptr), self.asc_get(y_ptr))?;
         let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
         Ok(result_ptr)
     }
@@ -992,18 +1353,86 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigInt>,
         exp: u32,
-    ) -> Result<AscPtr<AscBigInt>, Trap> {
-        let exp = u8::try_from(exp).map_err(anyhow::Error::from)?;
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
+        let exp = u8::try_from(exp).deterministic()?;
         let result = self.ctx.host_exports.big_int_pow(self.asc_get(x_ptr), exp);
         let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
         Ok(result_ptr)
     }
 
+    /// function bigInt.sqrt(x: BigInt): BigInt
+    fn big_int_sqrt(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
+        let result = self.ctx.host_exports.big_int_sqrt(self.asc_get(x_ptr))?;
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
+    /// function bigInt.bitOr(x: BigInt, y: BigInt): BigInt
+    fn big_int_bit_or(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+        y_ptr: AscPtr<AscBigInt>,
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
+        let result = self
+            .ctx
+            .host_exports
+            .big_int_bit_or(self.asc_get(x_ptr), self.asc_get(y_ptr));
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
+    /// function bigInt.bitAnd(x: BigInt, y: BigInt): BigInt
+    fn big_int_bit_and(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+        y_ptr: AscPtr<AscBigInt>,
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
+        let result = self
+            .ctx
+            .host_exports
+            .big_int_bit_and(self.asc_get(x_ptr), self.asc_get(y_ptr));
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
+    /// function bigInt.leftShift(x: BigInt, bits: u8): BigInt
+    fn big_int_left_shift(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+        bits: u32,
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
+        let bits = u8::try_from(bits).deterministic()?;
+        let result = self
+            .ctx
+            .host_exports
+            .big_int_left_shift(self.asc_get(x_ptr), bits);
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
+    /// function bigInt.rightShift(x: BigInt, bits: u8): BigInt
+    fn big_int_right_shift(
+        &mut self,
+        x_ptr: AscPtr<AscBigInt>,
+        bits: u32,
+    ) -> Result<AscPtr<AscBigInt>, HostExportError> {
+        let bits = u8::try_from(bits).deterministic()?;
+        let result = self
+            .ctx
+            .host_exports
+            .big_int_right_shift(self.asc_get(x_ptr), bits);
+        let result_ptr: AscPtr<AscBigInt> = self.asc_new(&result);
+        Ok(result_ptr)
+    }
+
     /// function typeConversion.bytesToBase58(bytes: Bytes): string
     fn bytes_to_base58(
         &mut self,
         bytes_ptr: AscPtr<Uint8Array>,
-    ) -> Result<AscPtr<AscString>, Trap> {
+    ) -> Result<AscPtr<AscString>, HostExportError> {
         let result = self
             .ctx
             .host_exports
@@ -1016,11 +1445,11 @@ impl WasmInstanceContext {
     fn big_decimal_to_string(
         &mut self,
         big_decimal_ptr: AscPtr<AscBigDecimal>,
-    ) -> Result<AscPtr<AscString>, Trap> {
+    ) -> Result<AscPtr<AscString>, HostExportError> {
         let result = self
             .ctx
             .host_exports
-            .big_decimal_to_string(self.try_asc_get(big_decimal_ptr)?);
+            .big_decimal_to_string(self.try_asc_get(big_decimal_ptr).deterministic()?);
         Ok(self.asc_new(&result))
     }
 
@@ -1028,7 +1457,7 @@ impl WasmInstanceContext {
     fn big_decimal_from_string(
         &mut self,
         string_ptr: AscPtr<AscString>,
-    ) -> Result<AscPtr<AscBigDecimal>, Trap> {
+    ) -> Result<AscPtr<AscBigDecimal>, HostExportError> {
         let result = self
             .ctx
             .host_exports
@@ -1041,11 +1470,11 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigDecimal>,
         y_ptr: AscPtr<AscBigDecimal>,
-    ) -> Result<AscPtr<AscBigDecimal>, Trap> {
-        let result = self
-            .ctx
-            .host_exports
-            .big_decimal_plus(self.try_asc_get(x_ptr)?, self.try_asc_get(y_ptr)?);
+    ) -> Result<AscPtr<AscBigDecimal>, HostExportError> {
+        let result = self.ctx.host_exports.big_decimal_plus(
+            self.try_asc_get(x_ptr).deterministic()?,
+            self.try_asc_get(y_ptr).deterministic()?,
+        )?;
         Ok(self.asc_new(&result))
     }
 
@@ -1054,11 +1483,11 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigDecimal>,
         y_ptr: AscPtr<AscBigDecimal>,
-    ) -> Result<AscPtr<AscBigDecimal>, Trap> {
-        let result = self
-            .ctx
-            .host_exports
-            .big_decimal_minus(self.try_asc_get(x_ptr)?, self.try_asc_get(y_ptr)?);
+    ) -> Result<AscPtr<AscBigDecimal>, HostExportError> {
+        let result = self.ctx.host_exports.big_decimal_minus(
+            self.try_asc_get(x_ptr).deterministic()?,
+            self.try_asc_get(y_ptr).deterministic()?,
+        )?;
         Ok(self.asc_new(&result))
     }
 
@@ -1067,11 +1496,11 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigDecimal>,
         y_ptr: AscPtr<AscBigDecimal>,
-    ) -> Result<AscPtr<AscBigDecimal>, Trap> {
-        let result = self
-            .ctx
-            .host_exports
-            .big_decimal_times(self.try_asc_get(x_ptr)?, self.try_asc_get(y_ptr)?);
+    ) -> Result<AscPtr<AscBigDecimal>, HostExportError> {
+        let result = self.ctx.host_exports.big_decimal_times(
+            self.try_asc_get(x_ptr).deterministic()?,
+            self.try_asc_get(y_ptr).deterministic()?,
+        )?;
         Ok(self.asc_new(&result))
     }
 
@@ -1080,11 +1509,11 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigDecimal>,
         y_ptr: AscPtr<AscBigDecimal>,
-    ) -> Result<AscPtr<AscBigDecimal>, Trap> {
-        let result = self
-            .ctx
-            .host_exports
-            .big_decimal_divided_by(self.try_asc_get(x_ptr)?, self.try_asc_get(y_ptr)?)?;
+    ) -> Result<AscPtr<AscBigDecimal>, HostExportError> {
+        let result = self.ctx.host_exports.big_decimal_divided_by(
+            self.try_asc_get(x_ptr).deterministic()?,
+            self.try_asc_get(y_ptr).deterministic()?,
+        )?;
         Ok(self.asc_new(&result))
     }
 
@@ -1093,11 +1522,11 @@ impl WasmInstanceContext {
         &mut self,
         x_ptr: AscPtr<AscBigDecimal>,
         y_ptr: AscPtr<AscBigDecimal>,
-    ) -> Result<bool, Trap> {
-        Ok(self
-            .ctx
-            .host_exports
-            .big_decimal_equals(self.try_asc_get(x_ptr)?, self.try_asc_get(y_ptr)?))
+    ) -> Result<bool, HostExportError> {
+        Ok(self.ctx.host_exports.big_decimal_equals(
+            self.try_asc_get(x_ptr).deterministic()?,
+            self.try_asc_get(y_ptr).deterministic()?,
+        ))
     }
 
     /// function dataSource.create(name: string, params: Array<string>): void
@@ -1105,7 +1534,7 @@ impl WasmInstanceContext {
         &mut self,
         name_ptr: AscPtr<AscString>,
         params_ptr: AscPtr<Array<AscPtr<AscString>>>,
-    ) -> Result<(), Trap> {
+    ) -> Result<(), HostExportError> {
         let name: String = self.asc_get(name_ptr);
         let params: Vec<String> = self.asc_get(params_ptr);
         self.ctx.host_exports.data_source_create(
@@ -1124,10 +1553,10 @@ impl WasmInstanceContext {
         name_ptr: AscPtr<AscString>,
         params_ptr: AscPtr<Array<AscPtr<AscString>>>,
         context_ptr: AscPtr<AscEntity>,
-    ) -> Result<(), Trap> {
+    ) -> Result<(), HostExportError> {
         let name: String = self.asc_get(name_ptr);
         let params: Vec<String> = self.asc_get(params_ptr);
-        let context: HashMap<_, _> = self.try_asc_get(context_ptr)?;
+        let context: HashMap<_, _> = self.try_asc_get(context_ptr).deterministic()?;
         self.ctx.host_exports.data_source_create(
             &self.ctx.logger,
             &mut self.ctx.state,
@@ -1153,7 +1582,10 @@ impl WasmInstanceContext {
         self.asc_new(&self.ctx.host_exports.data_source_context())
     }
 
-    fn ens_name_by_hash(&mut self, hash_ptr: AscPtr<AscString>) -> Result<AscPtr<AscString>, Trap> {
+    fn ens_name_by_hash(
+        &mut self,
+        hash_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscString>, HostExportError> {
         let hash: String = self.asc_get(hash_ptr);
         let name = self.ctx.host_exports.ens_name_by_hash(&*hash)?;
         // map `None` to `null`, and `Some(s)` to a runtime string
@@ -1172,7 +1604,7 @@ impl WasmInstanceContext {
     fn arweave_transaction_data(
         &mut self,
         tx_id: AscPtr<AscString>,
-    ) -> Result<AscPtr<Uint8Array>, Trap> {
+    ) -> Result<AscPtr<Uint8Array>, HostExportError> {
         let tx_id: String = self.asc_get(tx_id);
         let data = self.ctx.host_exports.arweave_transaction_data(&tx_id);
         Ok(data
@@ -1181,7 +1613,10 @@ impl WasmInstanceContext {
     }
 
     /// function box.profile(address: string): JSONValue | null
-    fn box_profile(&mut self, address: AscPtr<AscString>) -> Result<AscPtr<AscJson>, Trap> {
+    fn box_profile(
+        &mut self,
+        address: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscJson>, HostExportError> {
         let address: String = self.asc_get(address);
         let profile = self.ctx.host_exports.box_profile(&address);
         Ok(profile
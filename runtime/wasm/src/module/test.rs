@@ -15,7 +15,7 @@ use graph_core::three_box::ThreeBoxAdapter;
 use graph_mock::MockMetricsRegistry;
 use test_store::STORE;
 
-use web3::types::{Address, H160};
+use web3::types::{Address, H160, H256};
 
 use super::*;
 
@@ -82,8 +82,13 @@ fn mock_data_source(path: &str) -> DataSource {
         network: Some(String::from("mainnet")),
         source: Source {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
+            addresses: vec![],
+            address_file: None,
             abi: String::from("123123"),
+            subgraph: None,
             start_block: 0,
+            implementations: vec![],
+            file: None,
         },
         mapping: Mapping {
             kind: String::from("ethereum/events"),
@@ -94,6 +99,8 @@ fn mock_data_source(path: &str) -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
+            handler: None,
             link: Link {
                 link: "link".to_owned(),
             },
@@ -115,6 +122,8 @@ fn mock_data_source(path: &str) -> DataSource {
                 event_handlers: vec![],
                 call_handlers: vec![],
                 block_handlers: vec![],
+                entity_handlers: vec![],
+                handler: None,
                 link: Link {
                     link: "link".to_owned(),
                 },
@@ -130,7 +139,22 @@ fn mock_host_exports(
     data_source: DataSource,
     store: Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
 ) -> HostExports {
-    let mock_ethereum_adapter = Arc::new(MockEthereumAdapter::default());
+    mock_host_exports_with_adapter(
+        subgraph_id,
+        data_source,
+        store,
+        Arc::new(MockEthereumAdapter::default()),
+        None,
+    )
+}
+
+fn mock_host_exports_with_adapter(
+    subgraph_id: SubgraphDeploymentId,
+    data_source: DataSource,
+    store: Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+    ethereum_adapter: Arc<MockEthereumAdapter>,
+    chain_store: Option<Arc<dyn ChainStore>>,
+) -> HostExports {
     let arweave_adapter = Arc::new(ArweaveAdapter::new("https://arweave.net".to_string()));
     let three_box_adapter = Arc::new(ThreeBoxAdapter::new("https://ipfs.3box.io/".to_string()));
 
@@ -143,7 +167,8 @@ fn mock_host_exports(
         data_source.context,
         Arc::new(data_source.templates),
         data_source.mapping.abis,
-        mock_ethereum_adapter,
+        ethereum_adapter,
+        chain_store,
         Arc::new(graph_core::LinkResolver::from(
             ipfs_api::IpfsClient::default(),
         )),
@@ -263,6 +288,178 @@ async fn json_parsing() {
     assert_eq!(output, "OK: foo");
 }
 
+#[tokio::test]
+async fn ethereum_try_decode() {
+    let mut module = test_module(
+        "ethereumTryDecode",
+        mock_data_source("wasm_test/abort.wasm"),
+    );
+
+    // Decode a value that matches the types
+    let value = Token::Tuple(vec![
+        Token::Address(Address::from_low_u64_be(1)),
+        Token::Uint(2.into()),
+    ]);
+    let data = ethabi::encode(&[value.clone()]);
+    assert_eq!(
+        value,
+        host_exports::ethereum_try_decode("(address,uint256)", &data).unwrap()
+    );
+
+    // Data that is too short and types that do not parse are errors
+    assert!(host_exports::ethereum_try_decode("(address,uint256)", &data[..40]).is_err());
+    assert!(host_exports::ethereum_try_decode("(address,nonsense)", &data).is_err());
+
+    // Mappings get either failure as an error result instead of a trap
+    for (types, data) in &[
+        ("(address,uint256)", &data[..]),
+        ("(address,uint256)", &data[..40]),
+        ("(address,nonsense)", &data[..]),
+    ] {
+        let types_ptr: AscPtr<AscString> = module.asc_new(*types);
+        let data_ptr: AscPtr<Uint8Array> = module.asc_new(*data);
+        let result = module
+            .instance_ctx_mut()
+            .ethereum_try_decode(types_ptr, data_ptr);
+        assert!(result.is_ok());
+    }
+}
+
+#[tokio::test]
+async fn transaction_input() {
+    let mut module = test_module("transactionInput", mock_data_source("wasm_test/abort.wasm"));
+
+    // `transfer(address,uint256)`
+    let to = Address::from_low_u64_be(7);
+    let args = ethabi::encode(&[Token::Address(to), Token::Uint(100.into())]);
+    let input = [&[0xa9, 0x05, 0x9c, 0xbb][..], &args].concat();
+    let transaction = Transaction {
+        transaction_index: Some(0.into()),
+        input: web3::types::Bytes(input.clone()),
+        ..Default::default()
+    };
+
+    // Event handlers get the whole input of the transaction from apiVersion
+    // 0.0.2 on
+    let asc_transaction: AscEthereumTransaction_0_0_2 =
+        EthereumTransactionData::from(&transaction).to_asc_obj(&mut module);
+    let asc_input: Vec<u8> = module.asc_get(asc_transaction.input);
+    assert_eq!(input, asc_input);
+
+    // and can decode the arguments after the function selector
+    let host_exports = module.instance_ctx().ctx.host_exports.clone();
+    let decoded = host_exports
+        .ethereum_decode("(address,uint256)".to_owned(), asc_input[4..].to_vec())
+        .unwrap();
+    assert_eq!(
+        Some(Token::Tuple(vec![
+            Token::Address(to),
+            Token::Uint(100.into())
+        ])),
+        decoded
+    );
+}
+
+#[tokio::test]
+async fn ethereum_decode() {
+    let module = test_module("ethereumDecode", mock_data_source("wasm_test/abort.wasm"));
+    let host_exports = module.instance_ctx().ctx.host_exports.clone();
+
+    let value = Token::Tuple(vec![
+        Token::Address(Address::from_low_u64_be(1)),
+        Token::Array(vec![Token::Uint(2.into()), Token::Uint(3.into())]),
+    ]);
+    let data = ethabi::encode(&[value.clone()]);
+    assert_eq!(
+        Some(value),
+        host_exports
+            .ethereum_decode("(address,uint256[])".to_owned(), data.clone())
+            .unwrap()
+    );
+
+    // Data that is too short for the types is not an error
+    assert_eq!(
+        None,
+        host_exports
+            .ethereum_decode("(address,uint256[])".to_owned(), data[..40].to_vec())
+            .unwrap()
+    );
+
+    // Invalid types fail the handler
+    let err = host_exports
+        .ethereum_decode("(address,nonsense)".to_owned(), data)
+        .unwrap_err();
+    assert!(err.is_deterministic());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn ethereum_call_block() {
+    // Block `n` has hash `1000 + n`, so that a lookup by number would not
+    // give the pointers that following the parent hashes gives
+    let hash = |number: u64| H256::from_low_u64_be(1000 + number);
+    let ptr = move |number: u64| EthereumBlockPointer {
+        hash: hash(number),
+        number,
+    };
+
+    // None of these blocks are in the chain store, so that the parent
+    // hashes of the blocks before the parent come from the Ethereum node
+    let mut ethereum_adapter = MockEthereumAdapter::default();
+    ethereum_adapter.expect_block_by_number().never();
+    ethereum_adapter
+        .expect_block_by_hash()
+        .times(2)
+        .returning(move |_, block_hash| {
+            let number = block_hash.to_low_u64_be() - 1000;
+            Box::new(future::ok(Some(LightEthereumBlock {
+                hash: Some(block_hash),
+                number: Some(number.into()),
+                parent_hash: hash(number - 1),
+                ..Default::default()
+            })))
+        });
+    let host_exports = mock_host_exports_with_adapter(
+        SubgraphDeploymentId::new("ethereumCallBlock").unwrap(),
+        mock_data_source("wasm_test/abort.wasm"),
+        STORE.clone(),
+        Arc::new(ethereum_adapter),
+        Some(STORE.clone() as Arc<dyn ChainStore>),
+    );
+    let block = LightEthereumBlock {
+        hash: Some(hash(10)),
+        number: Some(10.into()),
+        parent_hash: hash(9),
+        ..Default::default()
+    };
+    let logger = test_store::LOGGER.clone();
+
+    // Looking up blocks uses `block_on`, which must be called from a sync
+    // context
+    let runtime = tokio::runtime::Handle::current();
+    std::thread::spawn(move || {
+        runtime.enter(|| {
+            match host_exports.ethereum_call_block(&logger, &block, 11) {
+                Err(HostExportError::Deterministic(e)) => assert_eq!(
+                    "Contract calls can not be made against block 11 while handling block 10",
+                    e.to_string()
+                ),
+                other => panic!("expected a deterministic error but got {:?}", other),
+            }
+
+            for number in (7..=10).rev() {
+                assert_eq!(
+                    ptr(number),
+                    host_exports
+                        .ethereum_call_block(&logger, &block, number)
+                        .unwrap()
+                );
+            }
+        })
+    })
+    .join()
+    .unwrap()
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn ipfs_cat() {
     let ipfs = Arc::new(ipfs_api::IpfsClient::default());
@@ -550,6 +747,16 @@ async fn abort() {
         .contains("line 6, column 2, with message: not true"));
 }
 
+#[test]
+fn valid_modules_are_shared() {
+    let abort = std::fs::read("wasm_test/abort.wasm").unwrap();
+    let module = ValidModule::cached(&abort).unwrap();
+    assert!(Arc::ptr_eq(&module, &ValidModule::cached(&abort).unwrap()));
+
+    let other = std::fs::read("wasm_test/big_int_to_hex.wasm").unwrap();
+    assert!(!Arc::ptr_eq(&module, &ValidModule::cached(&other).unwrap()));
+}
+
 #[tokio::test]
 async fn bytes_to_base58() {
     let mut module = test_module(
@@ -603,6 +810,49 @@ async fn data_source_create() {
     };
 }
 
+#[tokio::test]
+async fn data_source_create_with_context() {
+    use graph::data::graphql::TryFromValue;
+    use graph::prelude::serde_json;
+    use std::convert::TryFrom;
+
+    let subgraph_id = SubgraphDeploymentId::new("DataSourceCreateWithContext").unwrap();
+    let store = STORE.clone();
+    let host_exports = mock_host_exports(
+        subgraph_id,
+        mock_data_source("wasm_test/data_source_create.wasm"),
+        store.clone(),
+    );
+    let mut state = BlockState::new(store, Default::default());
+
+    let context = entity! {
+        name: "pair",
+        count: 7,
+        total: scalar::BigInt::from(1_000_000_000_000u64),
+        token: scalar::Bytes::from(&[0x12u8, 0x34][..]),
+        enabled: true,
+    };
+    host_exports
+        .data_source_create(
+            &test_store::LOGGER,
+            &mut state,
+            String::from("example template"),
+            vec![String::from("0xc0a47dFe034B400B47bDaD5FecDa2621de6c4d95")],
+            Some(context.clone()),
+        )
+        .expect("data source can be created");
+
+    // The context is passed on to the new data source
+    let info = state.created_data_sources.pop().unwrap();
+    let data_source = DataSource::try_from(info).unwrap();
+    assert_eq!(data_source.context.as_ref(), Some(&context));
+
+    // The context keeps its types when it is persisted and loaded again
+    let stored = serde_json::to_string(&context).unwrap();
+    let loaded = Entity::try_from_value(&graphql_parser::query::Value::String(stored)).unwrap();
+    assert_eq!(loaded, context);
+}
+
 #[tokio::test]
 async fn ens_name_by_hash() {
     let mut module = test_module(
@@ -729,3 +979,159 @@ async fn detect_contract_calls() {
         true
     );
 }
+
+#[test]
+fn limit_module_memory() {
+    use crate::mapping::limit_memory;
+
+    let header = &b"\0asm\x01\0\0\0"[..];
+    let custom_section = &[0, 2, 1, b'x'][..];
+
+    // A memory without a maximum gets the limit as its maximum, and other
+    // sections are kept as they are
+    let module = [header, &[5, 3, 1, 0, 2], custom_section].concat();
+    assert_eq!(
+        [header, &[5, 4, 1, 1, 2, 16], custom_section].concat(),
+        limit_memory(&module, 16).unwrap()
+    );
+
+    // A maximum below the limit is kept, one above it is lowered
+    let module = [header, &[5, 4, 1, 1, 2, 8]].concat();
+    assert_eq!(module, limit_memory(&module, 16).unwrap());
+    let module = [header, &[5, 4, 1, 1, 2, 100]].concat();
+    assert_eq!(
+        [header, &[5, 4, 1, 1, 2, 16]].concat(),
+        limit_memory(&module, 16).unwrap()
+    );
+    let module = [header, &[5, 3, 1, 0, 2]].concat();
+    assert_eq!(
+        [header, &[5, 5, 1, 1, 2, 0xac, 0x02]].concat(),
+        limit_memory(&module, 300).unwrap()
+    );
+
+    // Memories never get a maximum below their initial size
+    let module = [header, &[5, 3, 1, 0, 20]].concat();
+    assert_eq!(
+        [header, &[5, 4, 1, 1, 20, 20]].concat(),
+        limit_memory(&module, 16).unwrap()
+    );
+
+    assert!(limit_memory(&[header, &[5, 3, 1]].concat(), 16).is_err());
+}
+
+#[test]
+fn reject_nondeterministic_imports() {
+    let header = &b"\0asm\x01\0\0\0"[..];
+    // One function type, `() -> f64`
+    let types = &[1, 5, 1, 0x60, 0, 1, 0x7c][..];
+    // Import a function of that type as `env.Date.now`
+    let imports = &[
+        2, 16, 1, 3, b'e', b'n', b'v', 8, b'D', b'a', b't', b'e', b'.', b'n', b'o', b'w', 0, 0,
+    ][..];
+
+    let err = ValidModule::new(&[header, types, imports].concat())
+        .err()
+        .expect("importing `Date.now` fails");
+    assert!(err.to_string().contains("`Date.now`"));
+
+    // `seed` is only the seed of `Math.random` in the host modules, and
+    // `now` only reads the time when it comes from `Date`
+    use crate::mapping::nondeterministic_import;
+    assert!(nondeterministic_import("env", "seed").is_some());
+    assert!(nondeterministic_import("Math", "seed").is_some());
+    assert!(nondeterministic_import("wasi_snapshot_preview1", "seed").is_some());
+    assert!(nondeterministic_import("index", "seed").is_none());
+    assert!(nondeterministic_import("Date", "now").is_some());
+    assert!(nondeterministic_import("env", "now").is_none());
+    assert!(nondeterministic_import("wasi_unstable", "random_get").is_some());
+    assert!(nondeterministic_import("env", "random_get").is_none());
+}
+
+#[tokio::test]
+async fn limit_entity_operations() {
+    let header = &b"\0asm\x01\0\0\0"[..];
+    // Function types `(i32, i32) -> ()`, `i32 -> i32` and `i32 -> ()`
+    let types = &[
+        1, 15, 3, 0x60, 2, 0x7f, 0x7f, 0, 0x60, 1, 0x7f, 1, 0x7f, 0x60, 1, 0x7f, 0,
+    ][..];
+    // Import `env.store.remove`
+    let imports = [
+        &[2, 20, 1, 3][..],
+        &b"env"[..],
+        &[12][..],
+        &b"store.remove"[..],
+        &[0, 0][..],
+    ]
+    .concat();
+    // `memory.allocate` and the handler, and one page of memory
+    let functions = &[3, 3, 2, 1, 2][..];
+    let memory = &[5, 3, 1, 0, 1][..];
+    let exports = [
+        &[7, 44, 3, 6][..],
+        &b"memory"[..],
+        &[2, 0, 15][..],
+        &b"memory.allocate"[..],
+        &[0, 1, 13][..],
+        &b"handleTrigger"[..],
+        &[0, 2][..],
+    ]
+    .concat();
+    // The handler removes `User` `x` twice
+    let code = &[
+        10, 22, 2, 5, 0, 0x41, 0x80, 0x08, 0x0b, 14, 0, 0x41, 8, 0x41, 24, 0x10, 0, 0x41, 8, 0x41,
+        24, 0x10, 0, 0x0b,
+    ][..];
+    // The strings `User` at 8 and `x` at 24
+    let data = &[
+        11, 29, 2, 0, 0x41, 8, 0x0b, 12, 4, 0, 0, 0, b'U', 0, b's', 0, b'e', 0, b'r', 0, 0, 0x41,
+        24, 0x0b, 6, 1, 0, 0, 0, b'x', 0,
+    ][..];
+
+    let mut data_source = mock_data_source("wasm_test/abort.wasm");
+    data_source.mapping.runtime = Arc::new(
+        [
+            header,
+            types,
+            &imports[..],
+            functions,
+            memory,
+            &exports[..],
+            code,
+            data,
+        ]
+        .concat(),
+    );
+    let mut module = test_module("limitEntityOperations", data_source);
+    module
+        .instance_ctx_mut()
+        .handler_stats
+        .max_entity_operations = Some(1);
+    module.instance_ctx_mut().ctx.block = Arc::new(LightEthereumBlock {
+        hash: Some(H256::from_low_u64_be(7)),
+        number: Some(7.into()),
+        ..Default::default()
+    });
+
+    // The second removal fails the handler deterministically, and the
+    // error names the handler and the block
+    let state = module
+        .handle_trigger("handleTrigger", AscPtr::<AscString>::null())
+        .unwrap();
+    assert_eq!(1, state.deterministic_errors.len());
+    let error = &state.deterministic_errors[0];
+    assert_eq!(Some("handleTrigger"), error.handler.as_deref());
+    assert_eq!(
+        Some(EthereumBlockPointer {
+            hash: H256::from_low_u64_be(7),
+            number: 7,
+        }),
+        error.block_ptr
+    );
+    assert!(
+        error
+            .message
+            .contains("Handler exceeded the limit of 1 entity operations"),
+        "{}",
+        error.message
+    );
+}
@@ -0,0 +1,889 @@
+//! A harness for unit tests of mappings. It runs the handlers of a compiled
+//! mapping with the same host exports that are used for indexing, but keeps
+//! entities in memory and answers `ethereum.call` with results that the test
+//! provides, so that tests need neither a database nor an Ethereum node.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Mutex, RwLock};
+
+use ethabi::{LogParam, Token};
+use graph::components::arweave::ArweaveAdapter;
+use graph::components::store::*;
+use graph::components::three_box::ThreeBoxAdapter;
+use graph::data::subgraph::schema::*;
+use graph::mock::MockEthereumAdapter;
+use graph::prelude::*;
+use graph_graphql::prelude::api_schema;
+use semver::Version;
+use web3::types::{Address, Log, Transaction, H256};
+
+use crate::host_exports::HostExports;
+use crate::mapping::{MappingContext, ValidModule};
+use crate::module::WasmInstance;
+
+/// A call to a contract function and the result the test wants it to have.
+struct MockedCall {
+    address: Address,
+    function: String,
+    args: Vec<Token>,
+    result: Vec<Token>,
+}
+
+/// Runs the handlers of one data source against an in-memory store.
+pub struct MappingTest {
+    logger: Logger,
+    store: Arc<MemoryStore>,
+    calls: Arc<Mutex<Vec<MockedCall>>>,
+    host_exports: Arc<HostExports>,
+    valid_module: Arc<ValidModule>,
+    host_metrics: Arc<HostMetrics>,
+}
+
+impl MappingTest {
+    /// Set up a test for the mapping of `data_source`, which must have been
+    /// resolved, i.e., have its ABIs and its compiled mapping loaded. The
+    /// entities the handlers write must be defined in `schema`.
+    pub fn new(
+        logger: Logger,
+        subgraph_id: SubgraphDeploymentId,
+        schema: &str,
+        data_source: DataSource,
+        registry: Arc<impl MetricsRegistry>,
+    ) -> Result<Self, anyhow::Error> {
+        let schema = Schema::parse(schema, subgraph_id.clone()).compat()?;
+        let store = Arc::new(MemoryStore::new(schema)?);
+        let calls: Arc<Mutex<Vec<MockedCall>>> = Default::default();
+
+        let mut ethereum_adapter = MockEthereumAdapter::default();
+        let mocked_calls = calls.clone();
+        ethereum_adapter
+            .expect_contract_call()
+            .returning(move |_, call, _| {
+                let calls = mocked_calls.lock().unwrap();
+                let result = calls
+                    .iter()
+                    .find(|mocked| {
+                        mocked.address == call.address
+                            && mocked.function == call.function.name
+                            && mocked.args == call.args
+                    })
+                    .map(|mocked| mocked.result.clone())
+                    .ok_or_else(|| {
+                        EthereumContractCallError::Revert(
+                            format!(
+                                "no result mocked for call of `{}` on {:x}",
+                                call.function.name, call.address
+                            ),
+                            vec![],
+                        )
+                    });
+                Box::new(future::result(result))
+            });
+
+        let valid_module = ValidModule::cached(data_source.mapping.runtime.as_ref())?;
+        let stopwatch =
+            StopwatchMetrics::new(logger.clone(), subgraph_id.clone(), registry.clone());
+        let host_metrics = Arc::new(HostMetrics::new(
+            registry,
+            subgraph_id.to_string(),
+            stopwatch,
+        ));
+        let host_exports = Arc::new(HostExports::new(
+            subgraph_id,
+            Version::parse(&data_source.mapping.api_version)?,
+            data_source.name,
+            data_source.source.address,
+            data_source.network.unwrap_or_default(),
+            data_source.context,
+            Arc::new(data_source.templates),
+            data_source.mapping.abis,
+            Arc::new(ethereum_adapter),
+            None,
+            Arc::new(Unavailable),
+            store.clone(),
+            store.clone(),
+            Arc::new(Unavailable),
+            Arc::new(Unavailable),
+        ));
+
+        Ok(MappingTest {
+            logger,
+            store,
+            calls,
+            host_exports,
+            valid_module,
+            host_metrics,
+        })
+    }
+
+    /// Make calls of `function` on the contract at `address` with `args`
+    /// return `result`. Calls that were not mocked revert.
+    pub fn mock_call(
+        &self,
+        address: Address,
+        function: &str,
+        args: Vec<Token>,
+        result: Vec<Token>,
+    ) {
+        self.calls.lock().unwrap().push(MockedCall {
+            address,
+            function: function.to_owned(),
+            args,
+            result,
+        })
+    }
+
+    /// Run the event handler `handler` for `log` with the already decoded
+    /// event parameters `params`.
+    pub fn handle_log(
+        &self,
+        handler: &str,
+        block: LightEthereumBlock,
+        transaction: Transaction,
+        log: Log,
+        params: Vec<LogParam>,
+    ) -> Result<Vec<EntityModification>, MappingError> {
+        let module = self.instance(block)?;
+        let state = module.handle_ethereum_log(
+            handler,
+            Arc::new(transaction),
+            Arc::new(log),
+            params,
+            None,
+        )?;
+        self.finish(state)
+    }
+
+    /// Run the call handler `handler` for `call`.
+    pub fn handle_call(
+        &self,
+        handler: &str,
+        block: LightEthereumBlock,
+        transaction: Transaction,
+        call: EthereumCall,
+        inputs: Vec<LogParam>,
+        outputs: Vec<LogParam>,
+    ) -> Result<Vec<EntityModification>, MappingError> {
+        let module = self.instance(block)?;
+        let state = module.handle_ethereum_call(
+            handler,
+            Arc::new(transaction),
+            Arc::new(call),
+            inputs,
+            outputs,
+        )?;
+        self.finish(state)
+    }
+
+    /// Run the block handler `handler` for `block`.
+    pub fn handle_block(
+        &self,
+        handler: &str,
+        block: LightEthereumBlock,
+    ) -> Result<Vec<EntityModification>, MappingError> {
+        let module = self.instance(block)?;
+        let state = module.handle_ethereum_block(handler)?;
+        self.finish(state)
+    }
+
+    /// Run the entity handler `handler` for a change of `entity` in the
+    /// source deployment.
+    pub fn handle_entity(
+        &self,
+        handler: &str,
+        block: LightEthereumBlock,
+        entity: Entity,
+    ) -> Result<Vec<EntityModification>, MappingError> {
+        let module = self.instance(block)?;
+        let state = module.handle_entity(handler, entity)?;
+        self.finish(state)
+    }
+
+    /// Run the handler `handler` of a `file/arweave` data source for a file
+    /// with content `data`.
+    pub fn handle_file(
+        &self,
+        handler: &str,
+        block: LightEthereumBlock,
+        data: Vec<u8>,
+    ) -> Result<Vec<EntityModification>, MappingError> {
+        let module = self.instance(block)?;
+        let state = module.handle_file(handler, data.into())?;
+        self.finish(state)
+    }
+
+    /// The entity as the handlers that ran so far left it.
+    pub fn entity(&self, entity_type: &str, id: &str) -> Option<Entity> {
+        self.store
+            .get(EntityKey {
+                subgraph_id: self.store.schema.id.clone(),
+                entity_type: entity_type.to_owned(),
+                entity_id: id.to_owned(),
+            })
+            .unwrap()
+    }
+
+    fn instance(&self, block: LightEthereumBlock) -> Result<WasmInstance, MappingError> {
+        let ctx = MappingContext {
+            logger: self.logger.clone(),
+            host_exports: self.host_exports.clone(),
+            block: Arc::new(block),
+            state: BlockState::new(self.store.clone(), Default::default()),
+            proof_of_indexing: None,
+        };
+        WasmInstance::from_valid_module_with_ctx(
+            self.valid_module.clone(),
+            ctx,
+            self.host_metrics.clone(),
+            None,
+        )
+        .map_err(MappingError::Unknown)
+    }
+
+    /// Write the changes of a handler to the store and return them. A
+    /// handler that failed deterministically makes no changes.
+    fn finish(&self, state: BlockState) -> Result<Vec<EntityModification>, MappingError> {
+        if let Some(SubgraphError { message, .. }) = state.deterministic_errors.first() {
+            return Err(MappingError::Deterministic(anyhow::anyhow!("{}", message)));
+        }
+
+        let ModificationsAndCache { modifications, .. } = state
+            .entity_cache
+            .as_modifications(self.store.as_ref())
+            .compat()
+            .map_err(anyhow::Error::from)?;
+        self.store.apply(&modifications);
+        Ok(modifications)
+    }
+}
+
+/// Keeps the entities of a mapping test.
+struct MemoryStore {
+    schema: Arc<Schema>,
+    api_schema: Arc<Schema>,
+    entities: RwLock<BTreeMap<EntityKey, Entity>>,
+}
+
+impl MemoryStore {
+    fn new(schema: Schema) -> Result<Self, anyhow::Error> {
+        let api_schema = Schema {
+            document: api_schema(&schema.document).map_err(|e| anyhow::anyhow!("{}", e))?,
+            ..schema.clone()
+        };
+        Ok(MemoryStore {
+            schema: Arc::new(schema),
+            api_schema: Arc::new(api_schema),
+            entities: RwLock::new(BTreeMap::new()),
+        })
+    }
+
+    fn apply(&self, modifications: &[EntityModification]) {
+        let mut entities = self.entities.write().unwrap();
+        for modification in modifications {
+            match modification {
+                EntityModification::Insert { key, data }
+                | EntityModification::Overwrite { key, data } => {
+                    entities.insert(key.clone(), data.clone());
+                }
+                EntityModification::Remove { key } => {
+                    entities.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn block_ptr(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+    ) -> Result<Option<EthereumBlockPointer>, Error> {
+        Err(unsupported("block_ptr"))
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        Ok(self.entities.read().unwrap().get(&key).cloned())
+    }
+
+    fn get_many(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        ids_for_type: BTreeMap<&str, Vec<&str>>,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        let entities = self.entities.read().unwrap();
+        let mut found = BTreeMap::new();
+        for (entity_type, ids) in ids_for_type {
+            let matches: Vec<_> = ids
+                .into_iter()
+                .filter_map(|id| {
+                    entities
+                        .get(&EntityKey {
+                            subgraph_id: subgraph_id.clone(),
+                            entity_type: entity_type.to_owned(),
+                            entity_id: id.to_owned(),
+                        })
+                        .cloned()
+                })
+                .collect();
+            if !matches.is_empty() {
+                found.insert(entity_type.to_owned(), matches);
+            }
+        }
+        Ok(found)
+    }
+
+    fn entity_changes_in_block(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _entity_types: &[String],
+        _block: BlockNumber,
+    ) -> Result<Vec<(String, Entity)>, StoreError> {
+        Err(StoreError::Unknown(unsupported("entity_changes_in_block")))
+    }
+
+    fn supports_proof_of_indexing<'a>(
+        &'a self,
+        _subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, bool> {
+        // Mapping tests do not compute proofs of indexing
+        Box::pin(async { Ok(false) })
+    }
+
+    fn get_proof_of_indexing<'a>(
+        &'a self,
+        _subgraph_id: &'a SubgraphDeploymentId,
+        _indexer: &'a Option<Address>,
+        _block_hash: H256,
+    ) -> DynTryFuture<'a, Option<[u8; 32]>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(
+            "queries in mapping tests".to_owned(),
+        ))
+    }
+
+    fn find_one(&self, _query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(
+            "queries in mapping tests".to_owned(),
+        ))
+    }
+
+    fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
+        Ok(None)
+    }
+
+    fn transact_block_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _block_ptr_to: EthereumBlockPointer,
+        _mods: Vec<EntityModification>,
+        _stopwatch: StopwatchMetrics,
+        _deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<bool, StoreError> {
+        Err(StoreError::Unknown(unsupported(
+            "transact_block_operations",
+        )))
+    }
+
+    fn transact_blocks_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _blocks: Vec<BlockModifications>,
+        _stopwatch: StopwatchMetrics,
+    ) -> Result<bool, StoreError> {
+        Err(StoreError::Unknown(unsupported(
+            "transact_blocks_operations",
+        )))
+    }
+
+    fn apply_metadata_operations(
+        &self,
+        _operations: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        Err(StoreError::Unknown(unsupported(
+            "apply_metadata_operations",
+        )))
+    }
+
+    fn build_entity_attribute_indexes(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _indexes: Vec<AttributeIndexDefinition>,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
+        Err(SubgraphAssignmentProviderError::Unknown(unsupported(
+            "build_entity_attribute_indexes",
+        )))
+    }
+
+    fn revert_block_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _block_ptr_from: EthereumBlockPointer,
+        _block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        Err(StoreError::Unknown(unsupported("revert_block_operations")))
+    }
+
+    fn subscribe(&self, _entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        // Handlers do not write through the store, so nothing ever changes
+        StoreEventStream::new(Box::new(stream::empty()))
+    }
+
+    fn create_subgraph_deployment(
+        &self,
+        _schema: &Schema,
+        _hints: &IndexerHints,
+        _ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        Err(StoreError::Unknown(unsupported(
+            "create_subgraph_deployment",
+        )))
+    }
+
+    fn create_subgraph_deployments(
+        &self,
+        _deployments: &[(Schema, IndexerHints)],
+        _ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        Err(StoreError::Unknown(unsupported(
+            "create_subgraph_deployments",
+        )))
+    }
+
+    fn start_subgraph_deployment(
+        &self,
+        _logger: &Logger,
+        _subgraph_id: &SubgraphDeploymentId,
+        _ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        Err(StoreError::Unknown(unsupported(
+            "start_subgraph_deployment",
+        )))
+    }
+
+    fn migrate_subgraph_deployment(
+        &self,
+        _logger: &Logger,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_ptr: &EthereumBlockPointer,
+    ) {
+        // There is nothing to migrate in memory
+    }
+
+    fn block_number(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_hash: H256,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        Err(StoreError::Unknown(unsupported("block_number")))
+    }
+
+    fn query_store(self: Arc<Self>, _: bool) -> Arc<dyn QueryStore + Send + Sync> {
+        self
+    }
+}
+
+impl QueryStore for MemoryStore {
+    fn find_query_values(
+        &self,
+        _query: EntityQuery,
+    ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(
+            "queries in mapping tests".to_owned(),
+        ))
+    }
+
+    fn count_children(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _windows: Vec<EntityWindow>,
+        _block: BlockNumber,
+    ) -> Result<BTreeMap<String, i64>, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(
+            "queries in mapping tests".to_owned(),
+        ))
+    }
+
+    fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        Store::subscribe(self, entities)
+    }
+
+    fn is_deployment_synced(&self, _id: SubgraphDeploymentId) -> Result<bool, Error> {
+        Ok(true)
+    }
+}
+
+impl SubgraphDeploymentStore for MemoryStore {
+    fn input_schema(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+        Ok(self.schema.clone())
+    }
+
+    fn api_schema(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+        Ok(self.api_schema.clone())
+    }
+
+    fn uses_relational_schema(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn network_name(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    fn has_non_fatal_errors(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block: Option<BlockNumber>,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn earliest_block(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<BlockNumber, Error> {
+        Ok(0)
+    }
+
+    fn deployment_storage(
+        &self,
+        _subgraph_ids: Option<Vec<SubgraphDeploymentId>>,
+    ) -> Result<Vec<DeploymentStorage>, Error> {
+        Err(unsupported("deployment_storage"))
+    }
+
+    fn query_allowlist(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<Arc<HashSet<String>>>, Error> {
+        Ok(None)
+    }
+
+    fn set_query_allowlist_enabled(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _enabled: bool,
+    ) -> Result<(), Error> {
+        Err(unsupported("set_query_allowlist_enabled"))
+    }
+
+    fn allow_query(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _hash: &str,
+        _query: &str,
+    ) -> Result<(), Error> {
+        Err(unsupported("allow_query"))
+    }
+
+    fn disallow_query(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _hash: &str,
+    ) -> Result<bool, Error> {
+        Err(unsupported("disallow_query"))
+    }
+
+    fn allowed_queries(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<AllowedQuery>, Error> {
+        Err(unsupported("allowed_queries"))
+    }
+
+    fn import_entities(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block: BlockNumber,
+        entities: Vec<(EntityKey, Entity)>,
+    ) -> Result<(), StoreError> {
+        let mut entities_by_key = self.entities.write().unwrap();
+        for (key, entity) in entities {
+            entities_by_key.insert(key, entity);
+        }
+        Ok(())
+    }
+    fn indexing_freshness(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<IndexingFreshness>, Error> {
+        Ok(None)
+    }
+}
+
+impl EthereumCallCache for MemoryStore {
+    fn get_call(
+        &self,
+        _contract_address: Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(None)
+    }
+
+    fn set_call(
+        &self,
+        _contract_address: Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+        _return_value: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_immutable_call(
+        &self,
+        _contract_address: Address,
+        _encoded_call: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(None)
+    }
+
+    fn set_immutable_call(
+        &self,
+        _contract_address: Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+        _return_value: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The error for what mapping tests can not do
+fn unsupported(operation: &str) -> Error {
+    format_err!("`{}` is not available in mapping tests", operation)
+}
+
+/// Stands in for IPFS, Arweave and 3box, which mapping tests can not use.
+struct Unavailable;
+
+#[async_trait]
+impl LinkResolver for Unavailable {
+    fn with_timeout(self, _timeout: Duration) -> Self {
+        self
+    }
+
+    fn with_max_file_size(self, _max_file_size: Option<u64>) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        Err(format_err!(
+            "IPFS is not available in mapping tests: {}",
+            link.link
+        ))
+    }
+
+    async fn json_stream(&self, _logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        Err(format_err!(
+            "IPFS is not available in mapping tests: {}",
+            link.link
+        ))
+    }
+}
+
+#[async_trait]
+impl ArweaveAdapter for Unavailable {
+    async fn tx_data(&self, tx_id: &str) -> Result<graph::bytes::Bytes, Error> {
+        Err(format_err!(
+            "Arweave is not available in mapping tests: {}",
+            tx_id
+        ))
+    }
+}
+
+#[async_trait]
+impl ThreeBoxAdapter for Unavailable {
+    async fn profile(
+        &self,
+        address: &str,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, Error> {
+        Err(format_err!(
+            "3box is not available in mapping tests: {}",
+            address
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graph_mock::MockMetricsRegistry;
+    use web3::types::U64;
+
+    use super::*;
+
+    /// A mapping with a handler that sets `User(1)` with name `Alice`, one
+    /// that does the same and then aborts, and a file handler that sets
+    /// `User(1)` with the file as its `data`. The strings and the entities
+    /// are laid out the way AssemblyScript lays them out
+    const MAPPING: &str = r#"
+        (module
+          (import "index" "store.set" (func $store_set (param i32 i32 i32)))
+          (import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+          (memory (export "memory") 2)
+          (global $heap (mut i32) (i32.const 1024))
+          (func (export "memory.allocate") (param $size i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $heap))
+            (global.set $heap
+              (i32.and
+                (i32.add (i32.add (local.get $ptr) (local.get $size)) (i32.const 7))
+                (i32.const -8)))
+            (local.get $ptr))
+          (func (export "handleBlock") (param $block i32)
+            (call $store_set (i32.const 8) (i32.const 24) (i32.const 160)))
+          (func (export "handleBlockAndAbort") (param $block i32)
+            (call $store_set (i32.const 8) (i32.const 24) (i32.const 160))
+            (call $abort (i32.const 72) (i32.const 0) (i32.const 0) (i32.const 0)))
+          (func (export "handleFile") (param $data i32)
+            (i32.store (i32.const 200) (local.get $data))
+            (call $store_set (i32.const 8) (i32.const 24) (i32.const 248)))
+          ;; Strings: "User", "1", "id", "name", "Alice", "boom"
+          (data (i32.const 8) "\04\00\00\00U\00s\00e\00r\00")
+          (data (i32.const 24) "\01\00\00\001\00")
+          (data (i32.const 32) "\02\00\00\00i\00d\00")
+          (data (i32.const 40) "\04\00\00\00n\00a\00m\00e\00")
+          (data (i32.const 56) "\05\00\00\00A\00l\00i\00c\00e\00")
+          (data (i32.const 72) "\04\00\00\00b\00o\00o\00m\00")
+          ;; String values "1" and "Alice"
+          (data (i32.const 88) "\00\00\00\00\00\00\00\00\18\00\00\00\00\00\00\00")
+          (data (i32.const 104) "\00\00\00\00\00\00\00\008\00\00\00\00\00\00\00")
+          ;; Map entries id -> "1" and name -> "Alice"
+          (data (i32.const 120) "\20\00\00\00\58\00\00\00")
+          (data (i32.const 128) "\28\00\00\00\68\00\00\00")
+          ;; The array of the entries, its buffer and the entity
+          (data (i32.const 136) "\08\00\00\00\00\00\00\00\78\00\00\00\80\00\00\00")
+          (data (i32.const 152) "\88\00\00\00\02\00\00\00")
+          (data (i32.const 160) "\98\00\00\00")
+          ;; The string "data" and a `Bytes` value whose payload the file
+          ;; handler sets to the file
+          (data (i32.const 176) "\04\00\00\00d\00a\00t\00a\00")
+          (data (i32.const 192) "\06\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00")
+          ;; Map entries id -> "1" and data -> the file, the array of the
+          ;; entries, its buffer and the entity
+          (data (i32.const 208) "\20\00\00\00\58\00\00\00")
+          (data (i32.const 216) "\b0\00\00\00\c0\00\00\00")
+          (data (i32.const 224) "\08\00\00\00\00\00\00\00\d0\00\00\00\d8\00\00\00")
+          (data (i32.const 240) "\e0\00\00\00\02\00\00\00")
+          (data (i32.const 248) "\f0\00\00\00"))
+    "#;
+
+    fn mapping_test() -> MappingTest {
+        let runtime = wat::parse_str(MAPPING).unwrap();
+        let data_source = DataSource {
+            kind: String::from("ethereum/contract"),
+            name: String::from("test"),
+            network: Some(String::from("mainnet")),
+            source: Source {
+                address: None,
+                addresses: vec![],
+                address_file: None,
+                abi: String::from("Contract"),
+                subgraph: None,
+                start_block: 0,
+                implementations: vec![],
+                file: None,
+            },
+            mapping: Mapping {
+                kind: String::from("ethereum/events"),
+                api_version: String::from("0.0.4"),
+                language: String::from("wasm/assemblyscript"),
+                entities: vec![],
+                abis: vec![],
+                event_handlers: vec![],
+                call_handlers: vec![],
+                block_handlers: vec![],
+                entity_handlers: vec![],
+                handler: None,
+                link: Link {
+                    link: "link".to_owned(),
+                },
+                runtime: Arc::new(runtime),
+            },
+            templates: vec![],
+            context: None,
+        };
+
+        MappingTest::new(
+            Logger::root(slog::Discard, o!()),
+            SubgraphDeploymentId::new("mappingTest").unwrap(),
+            "type User @entity { id: ID!, name: String, data: Bytes }",
+            data_source,
+            Arc::new(MockMetricsRegistry::new()),
+        )
+        .unwrap()
+    }
+
+    fn block(number: u64) -> LightEthereumBlock {
+        LightEthereumBlock {
+            hash: Some(H256::from_low_u64_be(number)),
+            number: Some(U64::from(number)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn handler_writes_entities() {
+        let test = mapping_test();
+        assert_eq!(None, test.entity("User", "1"));
+
+        let modifications = test.handle_block("handleBlock", block(1)).unwrap();
+        assert_eq!(1, modifications.len());
+
+        let user = test.entity("User", "1").unwrap();
+        assert_eq!(Some(&Value::from("Alice")), user.get("name"));
+
+        // The second time around, the entity is overwritten
+        let modifications = test.handle_block("handleBlock", block(2)).unwrap();
+        match modifications.as_slice() {
+            [EntityModification::Overwrite { .. }] => (),
+            other => panic!("expected an overwrite but got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failed_handler_makes_no_changes() {
+        let test = mapping_test();
+
+        match test.handle_block("handleBlockAndAbort", block(1)) {
+            Err(MappingError::Deterministic(e)) => {
+                assert!(e.to_string().contains("message: boom"), "{}", e)
+            }
+            other => panic!("expected a deterministic error but got {:?}", other),
+        }
+        assert_eq!(None, test.entity("User", "1"));
+    }
+
+    #[test]
+    fn file_handler_gets_file() {
+        let test = mapping_test();
+
+        let modifications = test
+            .handle_file("handleFile", block(1), b"{}".to_vec())
+            .unwrap();
+        assert_eq!(1, modifications.len());
+
+        let user = test.entity("User", "1").unwrap();
+        assert_eq!(Some(&Value::Bytes(b"{}".as_ref().into())), user.get("data"));
+    }
+
+    #[test]
+    fn unsupported_store_operations_fail() {
+        let test = mapping_test();
+        let id = test.store.schema.id.clone();
+
+        assert!(test.store.block_ptr(id.clone()).is_err());
+        assert!(test
+            .store
+            .revert_block_operations(
+                id,
+                EthereumBlockPointer::from((H256::zero(), 1u64)),
+                EthereumBlockPointer::from((H256::zero(), 0u64)),
+            )
+            .is_err());
+    }
+}
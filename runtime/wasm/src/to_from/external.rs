@@ -3,9 +3,10 @@ use std::collections::HashMap;
 
 use graph::components::ethereum::{
     EthereumBlockData, EthereumCallData, EthereumEventData, EthereumTransactionData,
+    EthereumWildcardEventData,
 };
 use graph::data::store;
-use graph::prelude::anyhow::{ensure, Error};
+use graph::prelude::anyhow::Error;
 use graph::prelude::serde_json;
 use graph::prelude::web3::types as web3;
 use graph::prelude::{BigDecimal, BigInt};
@@ -84,16 +85,7 @@ impl TryFromAscObj<AscBigDecimal> for BigDecimal {
         let big_decimal = BigDecimal::new(digits, i64::from_le_bytes(byte_array));
 
         // Validate the exponent.
-        let exp = -big_decimal.as_bigint_and_exponent().1;
-        let min_exp: i64 = BigDecimal::MIN_EXP.into();
-        let max_exp: i64 = BigDecimal::MAX_EXP.into();
-        ensure!(
-            min_exp <= exp && exp <= max_exp,
-            format!(
-                "big decimal exponent `{}` is outside the `{}` to `{}` range",
-                exp, min_exp, max_exp
-            )
-        );
+        big_decimal.check_exponent()?;
         Ok(big_decimal)
     }
 }
@@ -324,6 +316,40 @@ impl ToAscObj<AscEthereumBlock> for EthereumBlockData {
     }
 }
 
+impl ToAscObj<AscEthereumBlock_0_0_5> for EthereumBlockData {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumBlock_0_0_5 {
+        AscEthereumBlock_0_0_5 {
+            hash: heap.asc_new(&self.hash),
+            parent_hash: heap.asc_new(&self.parent_hash),
+            uncles_hash: heap.asc_new(&self.uncles_hash),
+            author: heap.asc_new(&self.author),
+            state_root: heap.asc_new(&self.state_root),
+            transactions_root: heap.asc_new(&self.transactions_root),
+            receipts_root: heap.asc_new(&self.receipts_root),
+            number: heap.asc_new(&BigInt::from(self.number)),
+            gas_used: heap.asc_new(&BigInt::from_unsigned_u256(&self.gas_used)),
+            gas_limit: heap.asc_new(&BigInt::from_unsigned_u256(&self.gas_limit)),
+            timestamp: heap.asc_new(&BigInt::from_unsigned_u256(&self.timestamp)),
+            difficulty: heap.asc_new(&BigInt::from_unsigned_u256(&self.difficulty)),
+            total_difficulty: heap.asc_new(&BigInt::from_unsigned_u256(&self.total_difficulty)),
+            size: self
+                .size
+                .map(|size| heap.asc_new(&BigInt::from_unsigned_u256(&size)))
+                .unwrap_or_else(|| AscPtr::null()),
+            nonce: self
+                .nonce
+                .map(|nonce| heap.asc_new(&nonce.0[..]))
+                .unwrap_or_else(|| AscPtr::null()),
+            mix_hash: self
+                .mix_hash
+                .map(|mix_hash| heap.asc_new(&mix_hash))
+                .unwrap_or_else(|| AscPtr::null()),
+            logs_bloom: heap.asc_new(&self.logs_bloom.0[..]),
+            extra_data: heap.asc_new(&*self.extra_data.0),
+        }
+    }
+}
+
 impl ToAscObj<AscEthereumTransaction> for EthereumTransactionData {
     fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumTransaction {
         AscEthereumTransaction {
@@ -359,11 +385,12 @@ impl ToAscObj<AscEthereumTransaction_0_0_2> for EthereumTransactionData {
     }
 }
 
-impl<T: AscType> ToAscObj<AscEthereumEvent<T>> for EthereumEventData
+impl<T: AscType, B: AscType> ToAscObj<AscEthereumEvent<T, B>> for EthereumEventData
 where
     EthereumTransactionData: ToAscObj<T>,
+    EthereumBlockData: ToAscObj<B>,
 {
-    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumEvent<T> {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumEvent<T, B> {
         AscEthereumEvent {
             address: heap.asc_new(&self.address),
             log_index: heap.asc_new(&BigInt::from_unsigned_u256(&self.log_index)),
@@ -374,13 +401,39 @@ where
                 .clone()
                 .map(|log_type| heap.asc_new(&log_type))
                 .unwrap_or_else(|| AscPtr::null()),
-            block: heap.asc_new(&self.block),
+            block: heap.asc_new::<B, EthereumBlockData>(&self.block),
             transaction: heap.asc_new::<T, EthereumTransactionData>(&self.transaction),
             params: heap.asc_new(self.params.as_slice()),
         }
     }
 }
 
+impl ToAscObj<AscEthereumWildcardEvent> for EthereumWildcardEventData {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumWildcardEvent {
+        let event: AscEthereumEvent<AscEthereumTransaction_0_0_2, AscEthereumBlock_0_0_5> =
+            self.event.to_asc_obj(heap);
+        let AscEthereumEvent {
+            address,
+            log_index,
+            transaction_log_index,
+            log_type,
+            block,
+            transaction,
+            params,
+        } = event;
+        AscEthereumWildcardEvent {
+            address,
+            log_index,
+            transaction_log_index,
+            log_type,
+            block,
+            transaction,
+            params,
+            name: heap.asc_new(self.name.as_str()),
+        }
+    }
+}
+
 impl ToAscObj<AscEthereumCall> for EthereumCallData {
     fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumCall {
         AscEthereumCall {
@@ -393,15 +446,36 @@ impl ToAscObj<AscEthereumCall> for EthereumCallData {
     }
 }
 
-impl ToAscObj<AscEthereumCall_0_0_3> for EthereumCallData {
-    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumCall_0_0_3 {
+impl<B: AscType> ToAscObj<AscEthereumCall_0_0_3<B>> for EthereumCallData
+where
+    EthereumBlockData: ToAscObj<B>,
+{
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumCall_0_0_3<B> {
         AscEthereumCall_0_0_3 {
+            to: heap.asc_new(&self.to),
+            from: heap.asc_new(&self.from),
+            block: heap.asc_new::<B, EthereumBlockData>(&self.block),
+            transaction: heap.asc_new(&self.transaction),
+            inputs: heap.asc_new(self.inputs.as_slice()),
+            outputs: heap.asc_new(self.outputs.as_slice()),
+        }
+    }
+}
+
+impl ToAscObj<AscEthereumCall_0_0_5> for EthereumCallData {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumCall_0_0_5 {
+        AscEthereumCall_0_0_5 {
             to: heap.asc_new(&self.to),
             from: heap.asc_new(&self.from),
             block: heap.asc_new(&self.block),
             transaction: heap.asc_new(&self.transaction),
             inputs: heap.asc_new(self.inputs.as_slice()),
             outputs: heap.asc_new(self.outputs.as_slice()),
+            error: self
+                .error
+                .as_ref()
+                .map(|error| heap.asc_new(error.as_str()))
+                .unwrap_or_else(|| AscPtr::null()),
         }
     }
 }
@@ -92,6 +92,12 @@ impl<C: AscType, T: ToAscObj<C>> ToAscObj<Array<AscPtr<C>>> for [T] {
     }
 }
 
+impl<C: AscType, T: ToAscObj<C>> ToAscObj<Array<AscPtr<C>>> for Vec<T> {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> Array<AscPtr<C>> {
+        self.as_slice().to_asc_obj(heap)
+    }
+}
+
 impl<C: AscType, T: FromAscObj<C>> FromAscObj<Array<AscPtr<C>>> for Vec<T> {
     fn from_asc_obj<H: AscHeap>(array: Array<AscPtr<C>>, heap: &H) -> Self {
         array
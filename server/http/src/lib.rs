@@ -8,10 +8,12 @@ extern crate http;
 extern crate hyper;
 extern crate serde;
 
+mod rate_limit;
 mod request;
 mod server;
 mod service;
 
+pub use self::rate_limit::RateLimiter;
 pub use self::request::GraphQLRequest;
 pub use self::server::GraphQLServer;
 pub use self::service::{GraphQLService, GraphQLServiceResponse};
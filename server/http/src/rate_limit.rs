@@ -0,0 +1,367 @@
+//! Limit how many queries clients can send to the GraphQL server, both in
+//! requests per second and in requests that are running at the same time.
+//! Clients are identified by their API key if they send a known one, and
+//! by their IP address otherwise.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use graph::prelude::lazy_static;
+use http::header::{HeaderMap, AUTHORIZATION};
+
+lazy_static! {
+    static ref IP_RATE_LIMIT: f64 = env_var("GRAPH_GRAPHQL_IP_RATE_LIMIT", 0.0);
+    static ref IP_CONCURRENCY_LIMIT: usize = env_var("GRAPH_GRAPHQL_IP_CONCURRENCY_LIMIT", 0);
+    static ref API_KEY_RATE_LIMIT: f64 = env_var("GRAPH_GRAPHQL_API_KEY_RATE_LIMIT", 0.0);
+    static ref API_KEY_CONCURRENCY_LIMIT: usize =
+        env_var("GRAPH_GRAPHQL_API_KEY_CONCURRENCY_LIMIT", 0);
+    static ref API_KEYS: HashSet<String> = env::var("GRAPH_GRAPHQL_API_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_owned)
+        .collect();
+    static ref TRUST_FORWARDED_FOR: bool = env_var("GRAPH_GRAPHQL_TRUST_FORWARDED_FOR", false);
+}
+
+fn env_var<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .map(|s| {
+            T::from_str(&s).unwrap_or_else(|_| panic!("failed to parse env var {}: `{}`", name, s))
+        })
+        .unwrap_or(default)
+}
+
+/// How long clients have to wait before trying again when they have too
+/// many requests running
+const CONCURRENCY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// How often to forget about clients that have not sent requests recently
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The limits for one client. A value of 0 means that there is no limit
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limit {
+    pub requests_per_second: f64,
+    pub concurrent_requests: usize,
+}
+
+impl Limit {
+    fn is_unlimited(&self) -> bool {
+        self.requests_per_second <= 0.0 && self.concurrent_requests == 0
+    }
+
+    /// How many requests a client can send at once after being idle
+    fn burst(&self) -> f64 {
+        self.requests_per_second.max(1.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Client {
+    Ip(IpAddr),
+    ApiKey(String),
+}
+
+/// Token bucket and number of running requests for one client
+struct Usage {
+    tokens: f64,
+    updated: Instant,
+    running: usize,
+}
+
+impl Usage {
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        if limit.requests_per_second > 0.0 {
+            let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * limit.requests_per_second).min(limit.burst());
+        }
+        self.updated = now;
+    }
+}
+
+struct Clients {
+    usage: HashMap<Client, Usage>,
+    swept: Instant,
+}
+
+pub struct RateLimiter {
+    ip: Limit,
+    api_key: Limit,
+    api_keys: HashSet<String>,
+    trust_forwarded_for: bool,
+    clients: Arc<Mutex<Clients>>,
+}
+
+/// A request that the rate limiter let through. It counts as running
+/// until the permit is dropped
+pub struct Permit {
+    running: Option<(Arc<Mutex<Clients>>, Client)>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some((clients, client)) = self.running.take() {
+            if let Some(usage) = clients.lock().unwrap().usage.get_mut(&client) {
+                usage.running = usage.running.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// A rate limiter that lets everything through
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(Limit::default(), Limit::default(), HashSet::new(), false)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(
+        ip: Limit,
+        api_key: Limit,
+        api_keys: HashSet<String>,
+        trust_forwarded_for: bool,
+    ) -> Self {
+        RateLimiter {
+            ip,
+            api_key,
+            api_keys,
+            trust_forwarded_for,
+            clients: Arc::new(Mutex::new(Clients {
+                usage: HashMap::new(),
+                swept: Instant::now(),
+            })),
+        }
+    }
+
+    /// A rate limiter with the limits configured in the environment
+    pub fn from_env() -> Self {
+        RateLimiter::new(
+            Limit {
+                requests_per_second: *IP_RATE_LIMIT,
+                concurrent_requests: *IP_CONCURRENCY_LIMIT,
+            },
+            Limit {
+                requests_per_second: *API_KEY_RATE_LIMIT,
+                concurrent_requests: *API_KEY_CONCURRENCY_LIMIT,
+            },
+            API_KEYS.clone(),
+            *TRUST_FORWARDED_FOR,
+        )
+    }
+
+    /// Identify the client that sent a request with `headers` from
+    /// `remote_ip`. An API key is passed as `Authorization: Bearer <key>`
+    /// and only counts if it is one of the configured keys
+    pub fn client(&self, headers: &HeaderMap, remote_ip: Option<IpAddr>) -> Option<Client> {
+        let api_key = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("Bearer "))
+            .map(|value| value["Bearer ".len()..].trim())
+            .filter(|key| self.api_keys.contains(*key));
+        if let Some(api_key) = api_key {
+            return Some(Client::ApiKey(api_key.to_owned()));
+        }
+
+        // Clients can send an `X-Forwarded-For` header of their own, so
+        // only the last entry, which the proxy appends, can be trusted
+        let forwarded_for = if self.trust_forwarded_for {
+            headers
+                .get_all("X-Forwarded-For")
+                .iter()
+                .last()
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|ip| IpAddr::from_str(ip.trim()).ok())
+        } else {
+            None
+        };
+        forwarded_for.or(remote_ip).map(Client::Ip)
+    }
+
+    fn limit(&self, client: &Client) -> &Limit {
+        match client {
+            Client::Ip(_) => &self.ip,
+            Client::ApiKey(_) => &self.api_key,
+        }
+    }
+
+    /// Let a request from `client` through, or return how long the client
+    /// should wait before trying again
+    pub fn acquire(&self, client: Option<Client>) -> Result<Permit, Duration> {
+        let client = match client {
+            Some(client) if !self.limit(&client).is_unlimited() => client,
+            _ => return Ok(Permit { running: None }),
+        };
+        let limit = *self.limit(&client);
+        let now = Instant::now();
+
+        let mut clients = self.clients.lock().unwrap();
+        if now.saturating_duration_since(clients.swept) > SWEEP_INTERVAL {
+            self.sweep(&mut clients, now);
+        }
+        let usage = clients
+            .usage
+            .entry(client.clone())
+            .or_insert_with(|| Usage {
+                tokens: limit.burst(),
+                updated: now,
+                running: 0,
+            });
+
+        if limit.concurrent_requests > 0 && usage.running >= limit.concurrent_requests {
+            return Err(CONCURRENCY_RETRY_AFTER);
+        }
+        if limit.requests_per_second > 0.0 {
+            usage.refill(&limit, now);
+            if usage.tokens < 1.0 {
+                return Err(Duration::from_secs_f64(
+                    (1.0 - usage.tokens) / limit.requests_per_second,
+                ));
+            }
+            usage.tokens -= 1.0;
+        }
+        usage.running += 1;
+        Ok(Permit {
+            running: Some((self.clients.clone(), client)),
+        })
+    }
+
+    /// Forget about clients that have no requests running and whose token
+    /// bucket is full again, since they are in the same state as clients
+    /// we have never seen
+    fn sweep(&self, clients: &mut Clients, now: Instant) {
+        clients.usage.retain(|client, usage| {
+            let limit = self.limit(client);
+            usage.refill(limit, now);
+            usage.running > 0 || usage.tokens < limit.burst()
+        });
+        clients.swept = now;
+    }
+}
+
+/// The value for a `Retry-After` header telling the client to wait at
+/// least `duration`
+pub fn retry_after(duration: Duration) -> String {
+    let secs = duration.as_secs() + if duration.subsec_nanos() > 0 { 1 } else { 0 };
+    secs.max(1).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::HeaderValue;
+
+    fn ip(last: u8) -> Option<Client> {
+        Some(Client::Ip(IpAddr::from([10, 0, 0, last])))
+    }
+
+    #[test]
+    fn limits_requests_per_second() {
+        let limiter = RateLimiter::new(
+            Limit {
+                requests_per_second: 2.0,
+                concurrent_requests: 0,
+            },
+            Limit::default(),
+            HashSet::new(),
+            false,
+        );
+        assert!(limiter.acquire(ip(1)).is_ok());
+        assert!(limiter.acquire(ip(1)).is_ok());
+        let wait = limiter
+            .acquire(ip(1))
+            .err()
+            .expect("third request is limited");
+        assert!(wait <= Duration::from_millis(500));
+        assert_eq!("1", retry_after(wait));
+
+        // Other clients have their own limit
+        assert!(limiter.acquire(ip(2)).is_ok());
+        // Requests without a known client are not limited
+        assert!(limiter.acquire(None).is_ok());
+    }
+
+    #[test]
+    fn limits_concurrent_requests() {
+        let limiter = RateLimiter::new(
+            Limit {
+                requests_per_second: 0.0,
+                concurrent_requests: 1,
+            },
+            Limit::default(),
+            HashSet::new(),
+            false,
+        );
+        let permit = limiter
+            .acquire(ip(1))
+            .expect("first request is let through");
+        assert_eq!(Some(CONCURRENCY_RETRY_AFTER), limiter.acquire(ip(1)).err());
+        drop(permit);
+        assert!(limiter.acquire(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn identifies_clients() {
+        let keys = vec!["secret".to_owned()].into_iter().collect();
+        let limiter = RateLimiter::new(Limit::default(), Limit::default(), keys, true);
+        let remote = IpAddr::from([10, 0, 0, 1]);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            Some(Client::Ip(remote)),
+            limiter.client(&headers, Some(remote))
+        );
+
+        headers.insert(
+            "X-Forwarded-For",
+            HeaderValue::from_static("10.0.0.2, 10.0.0.3"),
+        );
+        assert_eq!(ip(3), limiter.client(&headers, Some(remote)));
+
+        // Unknown API keys do not get around the limits for the IP address
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer guess"));
+        assert_eq!(ip(3), limiter.client(&headers, Some(remote)));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        assert_eq!(
+            Some(Client::ApiKey("secret".to_owned())),
+            limiter.client(&headers, Some(remote))
+        );
+    }
+
+    #[test]
+    fn ignores_spoofed_forwarded_for() {
+        let limiter = RateLimiter::new(Limit::default(), Limit::default(), HashSet::new(), true);
+        let remote = IpAddr::from([10, 0, 0, 1]);
+
+        // The client made up the first entries; the proxy appended the
+        // address the request came from
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Forwarded-For",
+            HeaderValue::from_static("10.0.0.7, 10.0.0.8, 10.0.0.4"),
+        );
+        assert_eq!(ip(4), limiter.client(&headers, Some(remote)));
+
+        // Proxies may also add a header of their own after the client's
+        headers.insert("X-Forwarded-For", HeaderValue::from_static("10.0.0.7"));
+        headers.append("X-Forwarded-For", HeaderValue::from_static("10.0.0.4"));
+        assert_eq!(ip(4), limiter.client(&headers, Some(remote)));
+
+        // Without trusting the header, the remote address counts
+        let limiter = RateLimiter::new(Limit::default(), Limit::default(), HashSet::new(), false);
+        assert_eq!(
+            Some(Client::Ip(remote)),
+            limiter.client(&headers, Some(remote))
+        );
+    }
+}
@@ -1,9 +1,11 @@
 use std::net::{Ipv4Addr, SocketAddrV4};
 
 use hyper;
+use hyper::server::conn::AddrStream;
 use hyper::service::make_service_fn;
 use hyper::Server;
 
+use crate::rate_limit::RateLimiter;
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
 
@@ -27,6 +29,7 @@ pub struct GraphQLServer<Q, S> {
     graphql_runner: Arc<Q>,
     store: Arc<S>,
     node_id: NodeId,
+    rate_limiter: Arc<RateLimiter>,
 }
 
 impl<Q, S> GraphQLServer<Q, S> {
@@ -53,6 +56,7 @@ impl<Q, S> GraphQLServer<Q, S> {
             graphql_runner,
             store,
             node_id,
+            rate_limiter: Arc::new(RateLimiter::from_env()),
         }
     }
 }
@@ -85,7 +89,8 @@ where
         let metrics = self.metrics.clone();
         let store = self.store.clone();
         let node_id = self.node_id.clone();
-        let new_service = make_service_fn(move |_| {
+        let rate_limiter = self.rate_limiter.clone();
+        let new_service = make_service_fn(move |conn: &AddrStream| {
             futures03::future::ok::<_, Error>(GraphQLService::new(
                 logger_for_service.clone(),
                 metrics.clone(),
@@ -93,6 +98,8 @@ where
                 store.clone(),
                 ws_port,
                 node_id.clone(),
+                rate_limiter.clone(),
+                Some(conn.remote_addr().ip()),
             ))
         });
 
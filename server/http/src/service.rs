@@ -1,11 +1,12 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::net::IpAddr;
 use std::ops::Deref;
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use graph::components::server::query::GraphQLServerError;
 use graph::data::subgraph::schema::{SubgraphEntity, SUBGRAPHS_ID};
@@ -14,6 +15,7 @@ use http::header;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
 
+use crate::rate_limit::{self, RateLimiter};
 use crate::request::GraphQLRequest;
 
 pub struct GraphQLServiceMetrics {
@@ -82,6 +84,9 @@ pub struct GraphQLService<Q, S> {
     store: Arc<S>,
     ws_port: u16,
     node_id: NodeId,
+    rate_limiter: Arc<RateLimiter>,
+    /// The IP address of the client, if it is known
+    remote_ip: Option<IpAddr>,
 }
 
 impl<Q, S> Clone for GraphQLService<Q, S> {
@@ -93,6 +98,8 @@ impl<Q, S> Clone for GraphQLService<Q, S> {
             store: self.store.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            remote_ip: self.remote_ip,
         }
     }
 }
@@ -110,6 +117,8 @@ where
         store: Arc<S>,
         ws_port: u16,
         node_id: NodeId,
+        rate_limiter: Arc<RateLimiter>,
+        remote_ip: Option<IpAddr>,
     ) -> Self {
         GraphQLService {
             logger,
@@ -118,6 +127,8 @@ where
             store,
             ws_port,
             node_id,
+            rate_limiter,
+            remote_ip,
         }
     }
 
@@ -320,7 +331,10 @@ where
             Ok(Response::builder()
                 .status(200)
                 .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
+                .header(
+                    "Access-Control-Allow-Headers",
+                    "Content-Type, User-Agent, Authorization",
+                )
                 .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
                 .body(Body::from(""))
                 .unwrap())
@@ -343,6 +357,21 @@ where
             })
     }
 
+    /// Handles requests from clients that exceeded their rate limit
+    fn handle_too_many_requests(&self, retry_after: Duration) -> GraphQLServiceResponse {
+        let retry_after = rate_limit::retry_after(retry_after);
+        async move {
+            Ok(Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(header::RETRY_AFTER, retry_after)
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Content-Type", "text/plain")
+                .body(Body::from("Too many requests"))
+                .unwrap())
+        }
+        .boxed()
+    }
+
     /// Handles 404s.
     fn handle_not_found(&self) -> GraphQLServiceResponse {
         async {
@@ -442,10 +471,23 @@ where
         let logger = self.logger.clone();
         let service = self.clone();
 
+        // Only queries count against the rate limits; the permit has to be
+        // kept until the query has finished
+        let permit = if req.method() == Method::POST {
+            let client = self.rate_limiter.client(req.headers(), self.remote_ip);
+            match self.rate_limiter.acquire(client) {
+                Ok(permit) => Some(permit),
+                Err(retry_after) => return self.handle_too_many_requests(retry_after),
+            }
+        } else {
+            None
+        };
+
         // Returning Err here will prevent the client from receiving any response.
         // Instead, we generate a Response with an error code and return Ok
         Box::pin(async move {
             let result = service.handle_call(req).await;
+            drop(permit);
             match result {
                 Ok(response) => Ok(response),
                 Err(err @ GraphQLServerError::ClientError(_)) => Ok(Response::builder()
@@ -492,6 +534,7 @@ mod tests {
 
     use super::GraphQLService;
     use super::GraphQLServiceMetrics;
+    use crate::RateLimiter;
 
     /// A simple stupid query runner for testing.
     pub struct TestGraphQlRunner;
@@ -538,8 +581,16 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service =
-            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            8001,
+            node_id,
+            Arc::new(RateLimiter::default()),
+            None,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
@@ -571,8 +622,16 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service =
-            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            8001,
+            node_id,
+            Arc::new(RateLimiter::default()),
+            None,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
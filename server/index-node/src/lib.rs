@@ -1,3 +1,4 @@
+mod progress;
 mod request;
 mod resolver;
 mod schema;
@@ -0,0 +1,108 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How far back samples are used to compute the sync rate of a deployment.
+const WINDOW: Duration = Duration::from_secs(600);
+
+/// The sync rate is only reported once the samples span at least this long.
+const MIN_SPAN: Duration = Duration::from_secs(1);
+
+/// Tracks how fast deployments sync by sampling their latest block whenever
+/// their indexing status is queried. Rates are therefore only available for
+/// deployments whose status is queried repeatedly, and they reflect the
+/// progress over the last ten minutes of queries.
+pub(crate) struct SyncRates {
+    samples: Mutex<HashMap<String, VecDeque<(Instant, u64)>>>,
+}
+
+impl SyncRates {
+    pub fn new() -> Self {
+        SyncRates {
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record that `deployment` has synced up to `block` and return its sync
+    /// rate in blocks per second, if there are enough samples to tell.
+    pub fn observe(&self, deployment: &str, block: u64) -> Option<f64> {
+        self.observe_at(deployment, block, Instant::now())
+    }
+
+    fn observe_at(&self, deployment: &str, block: u64, now: Instant) -> Option<f64> {
+        let mut samples = self.samples.lock().unwrap();
+        let samples = samples.entry(deployment.to_owned()).or_default();
+
+        // The deployment was rewound; earlier samples say nothing about
+        // how fast it syncs from here
+        if samples.back().map_or(false, |(_, last)| *last > block) {
+            samples.clear();
+        }
+        samples.push_back((now, block));
+
+        // Keep the newest sample that is older than the window so the samples
+        // span the whole window
+        while samples.len() > 2 && now.duration_since(samples[1].0) >= WINDOW {
+            samples.pop_front();
+        }
+
+        let (first_time, first_block) = samples.front()?;
+        let span = now.duration_since(*first_time);
+        if span < MIN_SPAN {
+            return None;
+        }
+        Some((block - first_block) as f64 / span.as_secs_f64())
+    }
+}
+
+/// The number of seconds it will take to sync from `latest` to `head` at
+/// `rate` blocks per second.
+pub(crate) fn seconds_to_sync(latest: u64, head: u64, rate: f64) -> Option<u64> {
+    if latest >= head {
+        Some(0)
+    } else if rate > 0.0 {
+        Some(((head - latest) as f64 / rate).ceil() as u64)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_over_window() {
+        let rates = SyncRates::new();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(None, rates.observe_at("a", 100, at(0)));
+        assert_eq!(Some(10.0), rates.observe_at("a", 200, at(10)));
+        assert_eq!(Some(15.0), rates.observe_at("a", 400, at(20)));
+        assert_eq!(None, rates.observe_at("b", 5, at(20)));
+
+        // Samples older than the window are dropped
+        rates.observe_at("a", 6000, at(700));
+        assert_eq!(Some(1.0), rates.observe_at("a", 6600, at(1300)));
+    }
+
+    #[test]
+    fn rewind_resets_rate() {
+        let rates = SyncRates::new();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        rates.observe_at("a", 100, at(0));
+        rates.observe_at("a", 200, at(10));
+        assert_eq!(None, rates.observe_at("a", 50, at(20)));
+        assert_eq!(Some(2.0), rates.observe_at("a", 70, at(30)));
+    }
+
+    #[test]
+    fn eta() {
+        assert_eq!(Some(0), seconds_to_sync(100, 100, 0.0));
+        assert_eq!(Some(4), seconds_to_sync(90, 100, 3.0));
+        assert_eq!(None, seconds_to_sync(90, 100, 0.0));
+    }
+}
@@ -1,13 +1,21 @@
 use graphql_parser::{query as q, schema as s};
 use std::collections::HashMap;
 
+use graph::components::subgraph::{Retry, RETRIES};
 use graph::data::graphql::{TryFromValue, ValueList, ValueMap};
 use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth, SUBGRAPHS_ID};
+use graph::log::recent::{LogLine, RECENT_LOGS};
 use graph::prelude::*;
 use graph_graphql::prelude::{object, ExecutionContext, IntoValue, ObjectOrInterface, Resolver};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use web3::types::{Address, H256};
 
+use crate::progress::{seconds_to_sync, SyncRates};
+
+lazy_static! {
+    static ref SYNC_RATES: SyncRates = SyncRates::new();
+}
+
 static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
     fragment deploymentStatus on SubgraphDeploymentDetail {
         id
@@ -33,6 +41,7 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
         earliestEthereumBlockNumber
         latestEthereumBlockHash
         latestEthereumBlockNumber
+        entityCount
         manifest {
             dataSources(first: 1) {
                 network
@@ -46,6 +55,8 @@ pub struct IndexNodeResolver<R, S> {
     logger: Logger,
     graphql_runner: Arc<R>,
     store: Arc<S>,
+    networks: Arc<Vec<NetworkInfo>>,
+    trigger_dry_run: Arc<dyn TriggerDryRun>,
 }
 
 /// The ID of a subgraph deployment assignment.
@@ -97,6 +108,41 @@ struct EthereumIndexingStatus {
     earliest_block: Option<EthereumBlock>,
     /// The latest block that the subgraph has synced to.
     latest_block: Option<EthereumBlock>,
+    /// How many blocks per second the subgraph has recently synced.
+    blocks_per_second: Option<f64>,
+    /// How long it will take the subgraph to reach the chain head at its
+    /// recent sync rate.
+    estimated_seconds_to_sync: Option<u64>,
+}
+
+impl EthereumIndexingStatus {
+    fn new(
+        subgraph: &str,
+        network: String,
+        chain_head_block: Option<EthereumBlock>,
+        earliest_block: Option<EthereumBlock>,
+        latest_block: Option<EthereumBlock>,
+    ) -> Self {
+        let blocks_per_second = latest_block
+            .as_ref()
+            .and_then(|latest| SYNC_RATES.observe(subgraph, latest.0.number));
+        let estimated_seconds_to_sync = match (&latest_block, &chain_head_block) {
+            (Some(latest), Some(head)) => seconds_to_sync(
+                latest.0.number,
+                head.0.number,
+                blocks_per_second.unwrap_or(0.0),
+            ),
+            _ => None,
+        };
+        EthereumIndexingStatus {
+            network,
+            chain_head_block,
+            earliest_block,
+            latest_block,
+            blocks_per_second,
+            estimated_seconds_to_sync,
+        }
+    }
 }
 
 /// Indexing status information for different chains (only Ethereum right now).
@@ -116,6 +162,8 @@ impl From<ChainIndexingStatus> for q::Value {
                 chainHeadBlock: inner.chain_head_block,
                 earliestBlock: inner.earliest_block,
                 latestBlock: inner.latest_block,
+                blocksPerSecond: inner.blocks_per_second.map(q::Value::Float),
+                estimatedSecondsToSync: inner.estimated_seconds_to_sync,
             },
         }
     }
@@ -135,6 +183,15 @@ struct IndexingStatusWithoutNode {
 
     /// Indexing status on different chains involved in the subgraph's data sources.
     chains: Vec<ChainIndexingStatus>,
+
+    /// The number of entities the subgraph currently stores.
+    entity_count: BigInt,
+
+    /// The last lines the subgraph's mappings logged on this node.
+    recent_logs: Vec<LogLine>,
+
+    /// When this node retries the subgraph after a non-deterministic error.
+    retry: Option<Retry>,
 }
 
 #[derive(Debug)]
@@ -151,6 +208,15 @@ struct IndexingStatus {
     /// Indexing status on different chains involved in the subgraph's data sources.
     chains: Vec<ChainIndexingStatus>,
 
+    /// The number of entities the subgraph currently stores.
+    entity_count: BigInt,
+
+    /// The last lines the subgraph's mappings logged on this node.
+    recent_logs: Vec<LogLine>,
+
+    /// When this node retries the subgraph after a non-deterministic error.
+    retry: Option<Retry>,
+
     /// ID of the Graph Node that the subgraph is indexed by.
     node: String,
 }
@@ -165,6 +231,9 @@ impl IndexingStatusWithoutNode {
             fatal_error: self.fatal_error,
             non_fatal_errors: self.non_fatal_errors,
             chains: self.chains,
+            entity_count: self.entity_count,
+            recent_logs: self.recent_logs,
+            retry: self.retry,
             node,
         }
     }
@@ -195,22 +264,35 @@ impl IndexingStatusWithoutNode {
 
 impl TryFromValue for IndexingStatusWithoutNode {
     fn try_from_value(value: &q::Value) -> Result<Self, Error> {
+        let subgraph: String = value.get_required("id")?;
+        let network = value
+            .get_required::<q::Value>("manifest")?
+            .get_required::<q::Value>("dataSources")?
+            .get_values::<q::Value>()?[0]
+            .get_required("network")?;
+        let chain = EthereumIndexingStatus::new(
+            &subgraph,
+            network,
+            Self::block_from_value(value, "ethereumHeadBlock")?,
+            Self::block_from_value(value, "earliestEthereumBlock")?,
+            Self::block_from_value(value, "latestEthereumBlock")?,
+        );
+        let id = SubgraphDeploymentId::new(subgraph.as_str()).ok();
+        let recent_logs = id
+            .as_ref()
+            .map(|id| RECENT_LOGS.get(id))
+            .unwrap_or_default();
+        let retry = id.as_ref().and_then(|id| RETRIES.get(id));
         Ok(Self {
-            subgraph: value.get_required("id")?,
+            subgraph,
             synced: value.get_required("synced")?,
             health: value.get_required("health")?,
             fatal_error: value.get_optional("fatalError")?,
             non_fatal_errors: value.get_required("nonFatalErrors")?,
-            chains: vec![ChainIndexingStatus::Ethereum(EthereumIndexingStatus {
-                network: value
-                    .get_required::<q::Value>("manifest")?
-                    .get_required::<q::Value>("dataSources")?
-                    .get_values::<q::Value>()?[0]
-                    .get_required("network")?,
-                chain_head_block: Self::block_from_value(value, "ethereumHeadBlock")?,
-                earliest_block: Self::block_from_value(value, "earliestEthereumBlock")?,
-                latest_block: Self::block_from_value(value, "latestEthereumBlock")?,
-            })],
+            chains: vec![ChainIndexingStatus::Ethereum(chain)],
+            entity_count: value.get_required("entityCount")?,
+            recent_logs,
+            retry,
         })
     }
 }
@@ -220,10 +302,13 @@ impl From<IndexingStatus> for q::Value {
         let IndexingStatus {
             subgraph,
             chains,
+            entity_count,
             fatal_error,
             health,
             node,
             non_fatal_errors,
+            recent_logs,
+            retry,
             synced,
         } = status;
 
@@ -254,6 +339,24 @@ impl From<IndexingStatus> for q::Value {
             .collect();
         let fatal_error_val = fatal_error.map_or(q::Value::Null, subgraph_error_to_value);
 
+        fn log_line_to_value(line: LogLine) -> q::Value {
+            let level = match line.level {
+                slog::Level::Critical => "critical",
+                slog::Level::Error => "error",
+                slog::Level::Warning => "warning",
+                slog::Level::Info => "info",
+                slog::Level::Debug | slog::Level::Trace => "debug",
+            };
+            object! {
+                __typename: "LogLine",
+                timestamp: line.timestamp,
+                level: q::Value::Enum(level.to_owned()),
+                dataSource: line.data_source,
+                message: line.message,
+                blockNumber: line.block_number,
+            }
+        }
+
         object! {
             __typename: "SubgraphIndexingStatus",
             subgraph: subgraph,
@@ -262,6 +365,23 @@ impl From<IndexingStatus> for q::Value {
             fatalError: fatal_error_val,
             nonFatalErrors: non_fatal_errors,
             chains: chains.into_iter().map(q::Value::from).collect::<Vec<_>>(),
+            entityCount: format!("{}", entity_count),
+            recentLogs: recent_logs.into_iter().map(log_line_to_value).collect::<Vec<_>>(),
+            retry: retry.map_or(q::Value::Null, |retry| object! {
+                __typename: "Retry",
+                attempt: retry.attempt as i32,
+                error: retry.error,
+                kind: retry.kind.map_or(q::Value::Null, |kind| {
+                    let kind = match kind {
+                        RpcErrorKind::ProviderTimeout => "providerTimeout",
+                        RpcErrorKind::ProviderError => "providerError",
+                        RpcErrorKind::DataInconsistency => "dataInconsistency",
+                    };
+                    q::Value::Enum(kind.to_owned())
+                }),
+                delaySeconds: retry.delay.as_secs() as i32,
+                nextAttemptAt: retry.next_attempt_at.to_rfc3339(),
+            }),
             node: node,
         }
     }
@@ -308,12 +428,20 @@ where
     R: GraphQlRunner,
     S: Store + SubgraphDeploymentStore,
 {
-    pub fn new(logger: &Logger, graphql_runner: Arc<R>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        graphql_runner: Arc<R>,
+        store: Arc<S>,
+        networks: Arc<Vec<NetworkInfo>>,
+        trigger_dry_run: Arc<dyn TriggerDryRun>,
+    ) -> Self {
         let logger = logger.new(o!("component" => "IndexNodeResolver"));
         Self {
             logger,
             graphql_runner,
             store,
+            networks,
+            trigger_dry_run,
         }
     }
 
@@ -559,6 +687,182 @@ where
         Ok(poi)
     }
 
+    fn resolve_networks(&self) -> Result<q::Value, QueryExecutionError> {
+        let networks = self
+            .networks
+            .iter()
+            .map(|network| {
+                let logger = self.logger.new(o!("network" => network.name.clone()));
+
+                let genesis_block = match network.chain_store.genesis_block_ptr() {
+                    Ok(ptr) => Some(EthereumBlock(ptr)),
+                    Err(e) => {
+                        error!(logger, "Failed to load genesis block"; "error" => e.to_string());
+                        None
+                    }
+                };
+                let chain_head_block = match network.chain_store.chain_head_ptr() {
+                    Ok(ptr) => ptr.map(EthereumBlock),
+                    Err(e) => {
+                        error!(logger, "Failed to load chain head"; "error" => e.to_string());
+                        None
+                    }
+                };
+
+                let (ingestion, ingestion_error) = match &network.ingestion_check {
+                    None => ("disabled", None),
+                    Some(check) => match graph::block_on_allow_panic(check.check()) {
+                        Ok(()) => ("healthy", None),
+                        Err(e) => ("unhealthy", Some(e)),
+                    },
+                };
+
+                object! {
+                    __typename: "Network",
+                    name: network.name.clone(),
+                    kind: network.kind.clone(),
+                    genesisBlock: genesis_block,
+                    chainHeadBlock: chain_head_block,
+                    providers: network.providers as i32,
+                    ingestion: q::Value::Enum(ingestion.to_owned()),
+                    ingestionError: ingestion_error,
+                }
+            })
+            .collect();
+        Ok(q::Value::List(networks))
+    }
+
+    fn resolve_deployment_storage(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        // Ids that are not valid can not match any deployment
+        let subgraphs = arguments
+            .get_optional::<Vec<String>>("subgraphs")
+            .expect("invalid subgraphs")
+            .map(|ids| {
+                ids.into_iter()
+                    .filter_map(|id| SubgraphDeploymentId::new(id).ok())
+                    .collect()
+            });
+
+        let deployments = self
+            .store
+            .deployment_storage(subgraphs)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+
+        fn table_to_value(table: TableStorage) -> q::Value {
+            object! {
+                __typename: "TableStorage",
+                entityType: table.entity_type,
+                table: table.table,
+                entityCount: format!("{}", table.entity_count),
+                versionCount: format!("{}", table.version_count),
+                tableBytes: format!("{}", table.table_bytes),
+                indexBytes: format!("{}", table.index_bytes),
+            }
+        }
+
+        Ok(q::Value::List(
+            deployments
+                .into_iter()
+                .map(|deployment| {
+                    object! {
+                        __typename: "DeploymentStorage",
+                        subgraph: deployment.subgraph.to_string(),
+                        totalBytes: format!("{}", deployment.total_bytes()),
+                        earliestBlock: deployment.earliest_block.map(|n| format!("{}", n)),
+                        latestBlock: deployment.latest_block.map(|n| format!("{}", n)),
+                        tables: deployment.tables.into_iter().map(table_to_value).collect::<Vec<_>>(),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_trigger_dry_run(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let invalid = |e: Error| QueryExecutionError::TriggerDryRunFailed(e.to_string());
+        let deployment = arguments
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .map_err(invalid)?;
+        let from = arguments
+            .get_required::<u64>("startBlock")
+            .map_err(invalid)?;
+        let to = arguments.get_required::<u64>("endBlock").map_err(invalid)?;
+
+        let counts =
+            graph::block_on_allow_panic(self.trigger_dry_run.trigger_counts(&deployment, from, to))
+                .map_err(invalid)?;
+
+        Ok(q::Value::List(
+            counts
+                .into_iter()
+                .map(|count| {
+                    object! {
+                        __typename: "HandlerTriggerCount",
+                        dataSource: count.data_source,
+                        kind: count.kind,
+                        filter: count.filter,
+                        handler: count.handler,
+                        triggers: format!("{}", count.triggers),
+                        blocks: format!("{}", count.blocks),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_block_by_timestamp(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let failed = |e: Error| QueryExecutionError::BlockByTimestampFailed(e.to_string());
+        let network_name = arguments
+            .get_required::<String>("network")
+            .map_err(failed)?;
+        let timestamp = arguments
+            .get_required::<BigInt>("timestamp")
+            .map_err(failed)?;
+        let network = self
+            .networks
+            .iter()
+            .find(|network| network.name == network_name)
+            .ok_or_else(|| failed(format_err!("unknown network `{}`", network_name)))?;
+        // Negative timestamps are before and huge ones after all blocks
+        let timestamp = match u64::try_from(&timestamp) {
+            Ok(timestamp) => timestamp,
+            Err(_) => return Ok(q::Value::Null),
+        };
+
+        let number = graph::block_on_allow_panic(
+            network
+                .ethereum_adapter
+                .block_number_by_timestamp(
+                    &self.logger,
+                    Some(network.chain_store.clone()),
+                    timestamp,
+                    None,
+                )
+                .compat(),
+        )
+        .map_err(failed)?;
+        let number = match number {
+            Some(number) => number,
+            None => return Ok(q::Value::Null),
+        };
+        let block = graph::block_on_allow_panic(
+            network
+                .ethereum_adapter
+                .block_pointer_from_number(&self.logger, network.chain_store.clone(), number)
+                .compat(),
+        )
+        .map_err(|e| failed(e.into()))?;
+        Ok(EthereumBlock(block).into())
+    }
+
     fn resolve_indexing_statuses_for_version(
         &self,
         arguments: &HashMap<&q::Name, q::Value>,
@@ -701,6 +1005,8 @@ where
             logger: self.logger.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            networks: self.networks.clone(),
+            trigger_dry_run: self.trigger_dry_run.clone(),
         }
     }
 }
@@ -763,6 +1069,19 @@ where
                 self.resolve_indexing_statuses_for_subgraph_name(arguments)
             }
 
+            // The top-level `networks` field
+            (None, "Network", "networks") => self.resolve_networks(),
+
+            // The top-level `deploymentStorage` field
+            (None, "DeploymentStorage", "deploymentStorage") => {
+                self.resolve_deployment_storage(arguments)
+            }
+
+            // The top-level `triggerDryRun` field
+            (None, "HandlerTriggerCount", "triggerDryRun") => {
+                self.resolve_trigger_dry_run(arguments)
+            }
+
             // Resolve fields of `Object` values (e.g. the `chains` field of `ChainIndexingStatus`)
             (value, _, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
@@ -787,6 +1106,9 @@ where
                 self.resolve_indexing_statuses_for_version(arguments, false)
             }
 
+            // The top-level `blockByTimestamp` field
+            (None, "blockByTimestamp") => self.resolve_block_by_timestamp(arguments),
+
             // Resolve fields of `Object` values (e.g. the `latestBlock` field of `EthereumBlock`)
             (value, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
@@ -26,6 +26,9 @@ pub struct IndexNodeServer<Q, S> {
     graphql_runner: Arc<Q>,
     store: Arc<S>,
     node_id: NodeId,
+    health_checks: Arc<Vec<Arc<dyn HealthCheck>>>,
+    networks: Arc<Vec<NetworkInfo>>,
+    trigger_dry_run: Arc<dyn TriggerDryRun>,
 }
 
 impl<Q, S> IndexNodeServer<Q, S> {
@@ -35,6 +38,9 @@ impl<Q, S> IndexNodeServer<Q, S> {
         graphql_runner: Arc<Q>,
         store: Arc<S>,
         node_id: NodeId,
+        health_checks: Vec<Arc<dyn HealthCheck>>,
+        networks: Vec<NetworkInfo>,
+        trigger_dry_run: Arc<dyn TriggerDryRun>,
     ) -> Self {
         let logger = logger_factory.component_logger(
             "IndexNodeServer",
@@ -50,6 +56,9 @@ impl<Q, S> IndexNodeServer<Q, S> {
             graphql_runner,
             store,
             node_id,
+            health_checks: Arc::new(health_checks),
+            networks: Arc::new(networks),
+            trigger_dry_run,
         }
     }
 }
@@ -80,12 +89,18 @@ where
         let graphql_runner = self.graphql_runner.clone();
         let store = self.store.clone();
         let node_id = self.node_id.clone();
+        let health_checks = self.health_checks.clone();
+        let networks = self.networks.clone();
+        let trigger_dry_run = self.trigger_dry_run.clone();
         let new_service = make_service_fn(move |_| {
             futures03::future::ok::<_, Error>(IndexNodeService::new(
                 logger_for_service.clone(),
                 graphql_runner.clone(),
                 store.clone(),
                 node_id.clone(),
+                health_checks.clone(),
+                networks.clone(),
+                trigger_dry_run.clone(),
             ))
         });
 
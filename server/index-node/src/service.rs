@@ -1,8 +1,10 @@
 use http::header;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
+use std::collections::BTreeMap;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
 
 use graph::components::server::query::GraphQLServerError;
 use graph::prelude::*;
@@ -12,16 +14,21 @@ use crate::request::IndexNodeRequest;
 use crate::resolver::IndexNodeResolver;
 use crate::schema::SCHEMA;
 
+/// How long a single health check may take before it counts as failed.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// An asynchronous response to a GraphQL request.
 pub type IndexNodeServiceResponse = DynTryFuture<'static, Response<Body>, GraphQLServerError>;
 
 /// A Hyper Service that serves GraphQL over a POST / endpoint.
-#[derive(Debug)]
 pub struct IndexNodeService<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
     node_id: NodeId,
+    health_checks: Arc<Vec<Arc<dyn HealthCheck>>>,
+    networks: Arc<Vec<NetworkInfo>>,
+    trigger_dry_run: Arc<dyn TriggerDryRun>,
 }
 
 impl<Q, S> Clone for IndexNodeService<Q, S> {
@@ -31,6 +38,9 @@ impl<Q, S> Clone for IndexNodeService<Q, S> {
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
             node_id: self.node_id.clone(),
+            health_checks: self.health_checks.clone(),
+            networks: self.networks.clone(),
+            trigger_dry_run: self.trigger_dry_run.clone(),
         }
     }
 }
@@ -43,12 +53,23 @@ where
     S: SubgraphDeploymentStore + Store,
 {
     /// Creates a new GraphQL service.
-    pub fn new(logger: Logger, graphql_runner: Arc<Q>, store: Arc<S>, node_id: NodeId) -> Self {
+    pub fn new(
+        logger: Logger,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        node_id: NodeId,
+        health_checks: Arc<Vec<Arc<dyn HealthCheck>>>,
+        networks: Arc<Vec<NetworkInfo>>,
+        trigger_dry_run: Arc<dyn TriggerDryRun>,
+    ) -> Self {
         IndexNodeService {
             logger,
             graphql_runner,
             store,
             node_id,
+            health_checks,
+            networks,
+            trigger_dry_run,
         }
     }
 
@@ -71,6 +92,41 @@ where
             .unwrap()
     }
 
+    /// Runs all health checks and reports the node as ready if they all
+    /// pass. The body lists the result of each check.
+    async fn handle_ready(&self) -> Response<Body> {
+        let results = futures03::future::join_all(self.health_checks.iter().map(|check| {
+            tokio::time::timeout(HEALTH_CHECK_TIMEOUT, check.check()).map(move |result| {
+                let result = result.unwrap_or_else(|_| Err("timed out".to_owned()));
+                (check.name().to_owned(), result)
+            })
+        }))
+        .await;
+
+        let ready = results.iter().all(|(_, result)| result.is_ok());
+        for (name, result) in &results {
+            if let Err(e) = result {
+                warn!(self.logger, "Health check failed"; "check" => name, "error" => e);
+            }
+        }
+
+        let checks: BTreeMap<_, _> = results
+            .into_iter()
+            .map(|(name, result)| (name, result.err().unwrap_or_else(|| "ok".to_owned())))
+            .collect();
+        let body = serde_json::json!({ "ready": ready, "checks": checks });
+
+        Response::builder()
+            .status(if ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            })
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
     fn handle_graphiql() -> Response<Body> {
         Self::serve_file(Self::graphiql_html())
     }
@@ -81,6 +137,8 @@ where
     ) -> Result<Response<Body>, GraphQLServerError> {
         let store = self.store.clone();
         let graphql_runner = self.graphql_runner.clone();
+        let networks = self.networks.clone();
+        let trigger_dry_run = self.trigger_dry_run.clone();
 
         // Obtain the schema for the index node GraphQL API
         let schema = SCHEMA.clone();
@@ -103,7 +161,13 @@ where
         let logger = self.logger.cheap_clone();
         let result = tokio::task::spawn_blocking(move || {
             let options = QueryExecutionOptions {
-                resolver: IndexNodeResolver::new(&logger, graphql_runner, store),
+                resolver: IndexNodeResolver::new(
+                    &logger,
+                    graphql_runner,
+                    store,
+                    networks,
+                    trigger_dry_run,
+                ),
                 logger,
                 deadline: None,
                 max_first: std::u32::MAX,
@@ -194,6 +258,9 @@ where
 
         match (method, path_segments.as_slice()) {
             (Method::GET, [""]) => Ok(Self::index()),
+            // The node is live as long as it answers requests
+            (Method::GET, ["health", "live"]) => Ok(Self::index()),
+            (Method::GET, ["health", "ready"]) => Ok(self.handle_ready().await),
             (Method::GET, ["graphiql.css"]) => {
                 Ok(Self::serve_file(include_str!("../assets/graphiql.css")))
             }
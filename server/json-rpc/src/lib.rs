@@ -8,7 +8,8 @@ use graph::prelude::futures03::SinkExt;
 use graph::prelude::serde_json;
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
 use jsonrpc_http_server::{
-    jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
+    hyper,
+    jsonrpc_core::{self, Compatibility, MetaIoHandler, Metadata, Params, Value},
     RestApi, Server, ServerBuilder,
 };
 use lazy_static::lazy_static;
@@ -17,6 +18,7 @@ use std::collections::BTreeMap;
 use std::env;
 use std::io;
 use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::PathBuf;
 
 lazy_static! {
     static ref EXTERNAL_HTTP_BASE_URL: Option<String> = env::var_os("EXTERNAL_HTTP_BASE_URL")
@@ -29,6 +31,34 @@ const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_REASSIGN_ERROR: i64 = 3;
+const JSON_RPC_PAUSE_ERROR: i64 = 4;
+const JSON_RPC_RESUME_ERROR: i64 = 5;
+const JSON_RPC_UNAUTHORIZED_ERROR: i64 = 6;
+const JSON_RPC_PROMOTE_ERROR: i64 = 7;
+const JSON_RPC_ALLOWLIST_ERROR: i64 = 8;
+const JSON_RPC_IMPORT_ERROR: i64 = 9;
+const JSON_RPC_PRIORITY_ERROR: i64 = 10;
+
+/// The token a request presented in its `Authorization: Bearer <token>`
+/// header.
+#[derive(Clone, Debug, Default)]
+struct Auth {
+    token: Option<String>,
+}
+
+impl Metadata for Auth {}
+
+impl Auth {
+    fn from_request(request: &hyper::Request<hyper::Body>) -> Self {
+        let token = request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("Bearer "))
+            .map(|value| value["Bearer ".len()..].trim().to_owned());
+        Auth { token }
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
@@ -42,6 +72,14 @@ struct SubgraphDeployParams {
     node_id: Option<NodeId>,
 }
 
+#[derive(Debug, Deserialize)]
+struct SubgraphDeployLocalParams {
+    name: SubgraphName,
+    /// The directory on the node that `graph build` wrote the subgraph to
+    directory: PathBuf,
+    node_id: Option<NodeId>,
+}
+
 #[derive(Debug, Deserialize)]
 struct SubgraphRemoveParams {
     name: SubgraphName,
@@ -53,15 +91,188 @@ struct SubgraphReassignParams {
     node_id: NodeId,
 }
 
+#[derive(Debug, Deserialize)]
+struct SubgraphPauseParams {
+    ipfs_hash: SubgraphDeploymentId,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphSetPriorityParams {
+    ipfs_hash: SubgraphDeploymentId,
+    priority: DeploymentPriority,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphPromoteParams {
+    name: SubgraphName,
+    /// Promote the pending version even if it has not synced yet
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphAllowlistModeParams {
+    ipfs_hash: SubgraphDeploymentId,
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphAllowlistAddParams {
+    ipfs_hash: SubgraphDeploymentId,
+    query: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphAllowlistRemoveParams {
+    ipfs_hash: SubgraphDeploymentId,
+    /// The hash that `subgraph_allowlist_add` returned for the query
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct SubgraphImportEntitiesParams {
+    ipfs_hash: SubgraphDeploymentId,
+    entity: String,
+    format: ImportFormat,
+    data: String,
+    block: BlockNumber,
+}
+
+// The data can be large, and is not worth logging
+impl std::fmt::Debug for SubgraphImportEntitiesParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SubgraphImportEntitiesParams")
+            .field("ipfs_hash", &self.ipfs_hash)
+            .field("entity", &self.entity)
+            .field("format", &self.format)
+            .field("data", &format!("<{} bytes>", self.data.len()))
+            .field("block", &self.block)
+            .finish()
+    }
+}
+
+/// One operation of a `subgraph_batch` request, given as the method and
+/// params of the request that would perform it on its own
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "params")]
+enum SubgraphBatchOperation {
+    #[serde(rename = "subgraph_create")]
+    Create(SubgraphCreateParams),
+    #[serde(rename = "subgraph_deploy")]
+    Deploy(SubgraphDeployParams),
+    #[serde(rename = "subgraph_remove")]
+    Remove(SubgraphRemoveParams),
+    #[serde(rename = "subgraph_reassign")]
+    Reassign(SubgraphReassignParams),
+    #[serde(rename = "subgraph_pause")]
+    Pause(SubgraphPauseParams),
+    #[serde(rename = "subgraph_resume")]
+    Resume(SubgraphPauseParams),
+    #[serde(rename = "subgraph_promote")]
+    Promote(SubgraphPromoteParams),
+}
+
+impl SubgraphBatchOperation {
+    fn method(&self) -> &'static str {
+        use SubgraphBatchOperation::*;
+        match self {
+            Create(_) => "subgraph_create",
+            Deploy(_) => "subgraph_deploy",
+            Remove(_) => "subgraph_remove",
+            Reassign(_) => "subgraph_reassign",
+            Pause(_) => "subgraph_pause",
+            Resume(_) => "subgraph_resume",
+            Promote(_) => "subgraph_promote",
+        }
+    }
+
+    /// The error code the operation's own method uses
+    fn error_code(&self) -> i64 {
+        use SubgraphBatchOperation::*;
+        match self {
+            Create(_) => JSON_RPC_CREATE_ERROR,
+            Deploy(_) => JSON_RPC_DEPLOY_ERROR,
+            Remove(_) => JSON_RPC_REMOVE_ERROR,
+            Reassign(_) => JSON_RPC_REASSIGN_ERROR,
+            Pause(_) => JSON_RPC_PAUSE_ERROR,
+            Resume(_) => JSON_RPC_RESUME_ERROR,
+            Promote(_) => JSON_RPC_PROMOTE_ERROR,
+        }
+    }
+
+    /// The scope a token needs to perform the operation on its own
+    fn scope(&self) -> AdminScope {
+        use SubgraphBatchOperation::*;
+        match self {
+            Create(_) | Deploy(_) | Promote(_) => AdminScope::Deploy,
+            Remove(_) | Reassign(_) | Pause(_) | Resume(_) => AdminScope::Admin,
+        }
+    }
+}
+
+impl From<SubgraphBatchOperation> for SubgraphOperation {
+    fn from(operation: SubgraphBatchOperation) -> Self {
+        use SubgraphBatchOperation::*;
+        match operation {
+            Create(params) => SubgraphOperation::Create { name: params.name },
+            Deploy(params) => SubgraphOperation::Deploy {
+                name: params.name,
+                hash: params.ipfs_hash,
+                node_id: params.node_id,
+            },
+            Remove(params) => SubgraphOperation::Remove { name: params.name },
+            Reassign(params) => SubgraphOperation::Reassign {
+                hash: params.ipfs_hash,
+                node_id: params.node_id,
+            },
+            Pause(params) => SubgraphOperation::Pause {
+                hash: params.ipfs_hash,
+            },
+            Resume(params) => SubgraphOperation::Resume {
+                hash: params.ipfs_hash,
+            },
+            Promote(params) => SubgraphOperation::Promote {
+                name: params.name,
+                force: params.force,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphBatchParams {
+    operations: Vec<SubgraphBatchOperation>,
+}
+
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
     http_port: u16,
     ws_port: u16,
-    node_id: NodeId,
+    tokens: AdminTokens,
     logger: Logger,
 }
 
 impl<R: SubgraphRegistrar> JsonRpcServer<R> {
+    /// Check that the token of a request to `method` grants `scope`.
+    fn authorize(
+        &self,
+        method: &str,
+        auth: &Auth,
+        scope: AdminScope,
+    ) -> Result<(), jsonrpc_core::Error> {
+        if self.tokens.allows(auth.token.as_deref(), scope) {
+            return Ok(());
+        }
+
+        warn!(self.logger, "Rejected unauthorized {} request", method;
+              "token_present" => auth.token.is_some());
+        Err(jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_UNAUTHORIZED_ERROR),
+            message: format!("not authorized to call {}", method),
+            data: None,
+        })
+    }
+
     /// Handler for the `subgraph_create` endpoint.
     async fn create_handler(
         &self,
@@ -90,11 +301,14 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
     ) -> Result<Value, jsonrpc_core::Error> {
         info!(&self.logger, "Received subgraph_deploy request"; "params" => format!("{:?}", params));
 
-        let node_id = params.node_id.clone().unwrap_or(self.node_id.clone());
         let routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
         match self
             .registrar
-            .create_subgraph_version(params.name.clone(), params.ipfs_hash.clone(), node_id)
+            .create_subgraph_version(
+                params.name.clone(),
+                params.ipfs_hash.clone(),
+                params.node_id.clone(),
+            )
             .await
         {
             Ok(_) => Ok(routes),
@@ -108,6 +322,39 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
         }
     }
 
+    /// Handler for the `subgraph_deploy_local` endpoint.
+    async fn deploy_local_handler(
+        &self,
+        params: SubgraphDeployLocalParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_deploy_local request"; "params" => format!("{:?}", params));
+
+        let mut routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
+        match self
+            .registrar
+            .create_subgraph_version_from_files(
+                params.name.clone(),
+                params.directory.clone(),
+                params.node_id.clone(),
+            )
+            .await
+        {
+            Ok(hash) => {
+                if let Value::Object(routes) = &mut routes {
+                    routes.insert("id".to_owned(), Value::String(hash.to_string()));
+                }
+                Ok(routes)
+            }
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_deploy_local",
+                e,
+                JSON_RPC_DEPLOY_ERROR,
+                params,
+            )),
+        }
+    }
+
     /// Handler for the `subgraph_remove` endpoint.
     async fn remove_handler(
         &self,
@@ -151,6 +398,282 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
             )),
         }
     }
+
+    /// Handler for the `subgraph_pause` endpoint.
+    async fn pause_handler(
+        &self,
+        params: SubgraphPauseParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_pause request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .pause_subgraph(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_pause",
+                e,
+                JSON_RPC_PAUSE_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_resume` endpoint.
+    async fn resume_handler(
+        &self,
+        params: SubgraphPauseParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_resume request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .resume_subgraph(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_resume",
+                e,
+                JSON_RPC_RESUME_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_set_priority` endpoint.
+    async fn set_priority_handler(
+        &self,
+        params: SubgraphSetPriorityParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_set_priority request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .set_subgraph_priority(params.ipfs_hash.clone(), params.priority)
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_set_priority",
+                e,
+                JSON_RPC_PRIORITY_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_promote` endpoint.
+    async fn promote_handler(
+        &self,
+        params: SubgraphPromoteParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_promote request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .promote_subgraph(params.name.clone(), params.force)
+            .await
+        {
+            Ok(_) => Ok(subgraph_routes(&params.name, self.http_port, self.ws_port)),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_promote",
+                e,
+                JSON_RPC_PROMOTE_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_batch` endpoint. Returns a list with a
+    /// `{ "result": ... }` or `{ "error": ... }` object for each operation,
+    /// where the result and the error are those the operation's own method
+    /// would return.
+    async fn batch_handler(
+        &self,
+        params: SubgraphBatchParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_batch request";
+              "operations" => params.operations.len());
+
+        let requests: Vec<_> = params
+            .operations
+            .iter()
+            .map(|operation| {
+                let routes = match operation {
+                    SubgraphBatchOperation::Deploy(params) => {
+                        subgraph_routes(&params.name, self.http_port, self.ws_port)
+                    }
+                    SubgraphBatchOperation::Promote(params) => {
+                        subgraph_routes(&params.name, self.http_port, self.ws_port)
+                    }
+                    _ => Value::Null,
+                };
+                (
+                    operation.method(),
+                    operation.error_code(),
+                    format!("{:?}", operation),
+                    routes,
+                )
+            })
+            .collect();
+        let results = self
+            .registrar
+            .apply_batch(
+                params
+                    .operations
+                    .into_iter()
+                    .map(SubgraphOperation::from)
+                    .collect(),
+            )
+            .await;
+
+        let results = requests
+            .into_iter()
+            .zip(results)
+            .map(|((method, code, params, routes), result)| match result {
+                Ok(SubgraphOperationOutcome::Created(result)) => {
+                    serde_json::json!({ "result": result })
+                }
+                Ok(SubgraphOperationOutcome::Done) => serde_json::json!({ "result": routes }),
+                Err(e) => {
+                    let error = json_rpc_error(&self.logger, method, e, code, params);
+                    serde_json::json!({ "error": error })
+                }
+            })
+            .collect();
+        Ok(Value::Array(results))
+    }
+
+    /// Handler for the `subgraph_allowlist_mode` endpoint.
+    async fn allowlist_mode_handler(
+        &self,
+        params: SubgraphAllowlistModeParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_allowlist_mode request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .set_query_allowlist(params.ipfs_hash.clone(), params.enabled)
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_allowlist_mode",
+                e,
+                JSON_RPC_ALLOWLIST_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_allowlist_add` endpoint. Returns the hash
+    /// that identifies the query.
+    async fn allowlist_add_handler(
+        &self,
+        params: SubgraphAllowlistAddParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_allowlist_add request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .allow_query(params.ipfs_hash.clone(), params.query.clone())
+            .await
+        {
+            Ok(hash) => Ok(serde_json::json!({ "hash": hash })),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_allowlist_add",
+                e,
+                JSON_RPC_ALLOWLIST_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_allowlist_remove` endpoint. Returns
+    /// whether the query was on the allowlist.
+    async fn allowlist_remove_handler(
+        &self,
+        params: SubgraphAllowlistRemoveParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_allowlist_remove request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .disallow_query(params.ipfs_hash.clone(), params.hash.clone())
+            .await
+        {
+            Ok(removed) => Ok(Value::Bool(removed)),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_allowlist_remove",
+                e,
+                JSON_RPC_ALLOWLIST_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_allowlist_list` endpoint.
+    async fn allowlist_list_handler(
+        &self,
+        params: SubgraphPauseParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_allowlist_list request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .allowed_queries(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(queries) => Ok(serde_json::to_value(queries).expect("invalid allowlist")),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_allowlist_list",
+                e,
+                JSON_RPC_ALLOWLIST_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_import_entities` endpoint. Returns how
+    /// many entities were imported.
+    async fn import_entities_handler(
+        &self,
+        params: SubgraphImportEntitiesParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_import_entities request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .import_entities(
+                params.ipfs_hash.clone(),
+                params.entity.clone(),
+                params.format,
+                params.data.clone(),
+                params.block,
+            )
+            .await
+        {
+            Ok(count) => Ok(serde_json::json!({ "count": count })),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_import_entities",
+                e,
+                JSON_RPC_IMPORT_ERROR,
+                params,
+            )),
+        }
+    }
 }
 
 impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
@@ -164,7 +687,7 @@ where
         http_port: u16,
         ws_port: u16,
         registrar: Arc<R>,
-        node_id: NodeId,
+        tokens: AdminTokens,
         logger: Logger,
     ) -> Result<Self::Server, io::Error> {
         let logger = logger.new(o!("component" => "JsonRpcServer"));
@@ -176,13 +699,20 @@ where
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
 
-        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+        if tokens.is_empty() {
+            warn!(
+                logger,
+                "No admin tokens are configured; the JSON-RPC admin server accepts all requests"
+            );
+        }
+
+        let mut handler = MetaIoHandler::with_compatibility(Compatibility::Both);
 
         let arc_self = Arc::new(JsonRpcServer {
             registrar,
             http_port,
             ws_port,
-            node_id,
+            tokens,
             logger,
         });
 
@@ -217,11 +747,12 @@ where
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_create", move |params: Params| {
+        handler.add_method_with_meta("subgraph_create", move |params: Params, auth: Auth| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    me.authorize("subgraph_create", &auth, AdminScope::Deploy)?;
                     let params = params.parse()?;
                     me.create_handler(params).await
                 }
@@ -233,11 +764,12 @@ where
         let me = arc_self.clone();
         let sender = task_sender.clone();
 
-        handler.add_method("subgraph_deploy", move |params: Params| {
+        handler.add_method_with_meta("subgraph_deploy", move |params: Params, auth: Auth| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    me.authorize("subgraph_deploy", &auth, AdminScope::Deploy)?;
                     let params = params.parse()?;
                     me.deploy_handler(params).await
                 }
@@ -248,11 +780,31 @@ where
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_remove", move |params: Params| {
+        handler.add_method_with_meta(
+            "subgraph_deploy_local",
+            move |params: Params, auth: Auth| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_deploy_local", &auth, AdminScope::Deploy)?;
+                        let params = params.parse()?;
+                        me.deploy_local_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_remove", move |params: Params, auth: Auth| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    me.authorize("subgraph_remove", &auth, AdminScope::Admin)?;
                     let params = params.parse()?;
                     me.remove_handler(params).await
                 }
@@ -263,11 +815,12 @@ where
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_reassign", move |params: Params| {
+        handler.add_method_with_meta("subgraph_reassign", move |params: Params, auth: Auth| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    me.authorize("subgraph_reassign", &auth, AdminScope::Admin)?;
                     let params = params.parse()?;
                     me.reassign_handler(params).await
                 }
@@ -276,7 +829,193 @@ where
             .compat()
         });
 
-        ServerBuilder::new(handler)
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_pause", move |params: Params, auth: Auth| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    me.authorize("subgraph_pause", &auth, AdminScope::Admin)?;
+                    let params = params.parse()?;
+                    me.pause_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_resume", move |params: Params, auth: Auth| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    me.authorize("subgraph_resume", &auth, AdminScope::Admin)?;
+                    let params = params.parse()?;
+                    me.resume_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_set_priority",
+            move |params: Params, auth: Auth| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_set_priority", &auth, AdminScope::Admin)?;
+                        let params = params.parse()?;
+                        me.set_priority_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_promote", move |params: Params, auth: Auth| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    me.authorize("subgraph_promote", &auth, AdminScope::Deploy)?;
+                    let params = params.parse()?;
+                    me.promote_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_batch", move |params: Params, auth: Auth| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params: SubgraphBatchParams = params.parse()?;
+                    // The batch needs the scope that the most privileged of
+                    // its operations needs on its own
+                    let scope = params
+                        .operations
+                        .iter()
+                        .map(SubgraphBatchOperation::scope)
+                        .max()
+                        .unwrap_or(AdminScope::Deploy);
+                    me.authorize("subgraph_batch", &auth, scope)?;
+                    me.batch_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_allowlist_mode",
+            move |params: Params, auth: Auth| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_allowlist_mode", &auth, AdminScope::Admin)?;
+                        let params = params.parse()?;
+                        me.allowlist_mode_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_allowlist_add",
+            move |params: Params, auth: Auth| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_allowlist_add", &auth, AdminScope::Admin)?;
+                        let params = params.parse()?;
+                        me.allowlist_add_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_allowlist_remove",
+            move |params: Params, auth: Auth| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_allowlist_remove", &auth, AdminScope::Admin)?;
+                        let params = params.parse()?;
+                        me.allowlist_remove_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_allowlist_list",
+            move |params: Params, auth: Auth| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_allowlist_list", &auth, AdminScope::Read)?;
+                        let params = params.parse()?;
+                        me.allowlist_list_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_import_entities",
+            move |params: Params, auth: Auth| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_import_entities", &auth, AdminScope::Admin)?;
+                        let params = params.parse()?;
+                        me.import_entities_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        ServerBuilder::with_meta_extractor(handler, Auth::from_request)
             // Enable REST API:
             // POST /<method>/<param1>/<param2>
             .rest_api(RestApi::Secure)
@@ -295,19 +1034,57 @@ fn json_rpc_error(
         "error" => format!("{:?}", e),
         "params" => format!("{:?}", params));
 
-    let message = if let SubgraphRegistrarError::Unknown(_) = e {
-        "internal error".to_owned()
-    } else {
-        e.to_string()
+    let (message, data) = match e {
+        SubgraphRegistrarError::Unknown(_) => ("internal error".to_owned(), None),
+        SubgraphRegistrarError::ManifestValidationError(errors) => (
+            format!(
+                "subgraph validation error: {}",
+                errors
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            Some(validation_errors(errors)),
+        ),
+        e => (e.to_string(), None),
     };
 
     jsonrpc_core::Error {
         code: jsonrpc_core::ErrorCode::ServerError(code),
         message,
-        data: None,
+        data,
     }
 }
 
+/// Turn validation errors into a list of `{ file, path, message }` objects
+/// so that clients can point users at the offending part of their subgraph.
+/// `file` and `path` are only present for errors with a known location.
+fn validation_errors(errors: Vec<SubgraphManifestValidationError>) -> Value {
+    let errors = errors
+        .into_iter()
+        .map(|e| {
+            let mut map = BTreeMap::new();
+            match e {
+                SubgraphManifestValidationError::InvalidDefinition {
+                    file,
+                    path,
+                    message,
+                } => {
+                    map.insert("file", file);
+                    map.insert("path", path);
+                    map.insert("message", message);
+                }
+                e => {
+                    map.insert("message", e.to_string());
+                }
+            }
+            map
+        })
+        .collect::<Vec<_>>();
+    jsonrpc_core::to_value(errors).unwrap()
+}
+
 pub fn parse_response(response: Value) -> Result<(), jsonrpc_core::Error> {
     // serde deserialization of the `id` field to an `Id` struct is somehow
     // incompatible with the `arbitrary-precision` feature which we use, so we
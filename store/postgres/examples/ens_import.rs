@@ -0,0 +1,63 @@
+extern crate clap;
+extern crate graph_store_postgres;
+
+use clap::App;
+use diesel::{Connection, PgConnection};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process::exit;
+
+use graph_store_postgres::ens;
+
+/// The number of names we read from the input before writing them
+const BATCH_SIZE: usize = 100_000;
+
+pub fn ensure<T, E: std::fmt::Display>(res: Result<T, E>, msg: &str) -> T {
+    match res {
+        Ok(ok) => ok,
+        Err(err) => {
+            eprintln!("{}:\n    {}", msg, err);
+            exit(1)
+        }
+    }
+}
+
+pub fn main() {
+    let args = App::new("ens_import")
+        .version("1.0")
+        .about("Import ENS names into the table used by `ens.nameByHash`")
+        .args_from_usage(
+            "<postgres-url>      'Location of the Postgres database'
+             <names>             'File with one ENS label per line'",
+        )
+        .get_matches();
+
+    let url = args.value_of("postgres-url").unwrap();
+    let path = args.value_of("names").unwrap();
+
+    let conn = ensure(
+        PgConnection::establish(url),
+        "Failed to connect to the database",
+    );
+    let file = ensure(File::open(path), "Failed to open names file");
+
+    let mut names = Vec::with_capacity(BATCH_SIZE);
+    let (mut read, mut added) = (0, 0);
+    for line in BufReader::new(file).lines() {
+        let line = ensure(line, "Failed to read names file");
+        let name = line.trim();
+        if name.is_empty() {
+            continue;
+        }
+        names.push(name.to_owned());
+        if names.len() == BATCH_SIZE {
+            read += names.len();
+            added += ensure(ens::insert_names(&conn, &names), "Failed to import names");
+            names.clear();
+        }
+    }
+    read += names.len();
+    added += ensure(ens::insert_names(&conn, &names), "Failed to import names");
+
+    println!("Read {} names and added {} new ones", read, added);
+}
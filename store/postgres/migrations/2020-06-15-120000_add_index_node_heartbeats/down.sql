@@ -0,0 +1 @@
+drop table index_node_heartbeats;
@@ -0,0 +1,6 @@
+-- Index nodes record a heartbeat here periodically so that the
+-- assignments of nodes that have died can be moved to other nodes
+create table index_node_heartbeats (
+  node_id        text primary key,
+  last_heartbeat timestamptz not null
+);
@@ -0,0 +1 @@
+drop table subgraph_files;
@@ -0,0 +1,6 @@
+-- The files of subgraphs that were deployed from local files instead of
+-- from IPFS, keyed by the hash of their contents
+create table subgraph_files (
+  hash    text primary key,
+  content bytea not null
+);
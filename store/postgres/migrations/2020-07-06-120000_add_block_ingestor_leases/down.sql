@@ -0,0 +1 @@
+drop table block_ingestor_leases;
@@ -0,0 +1,8 @@
+-- The index node that runs the block ingestor of a network holds a lease
+-- here and renews it while it is alive; once the lease has expired, another
+-- node can take over
+create table block_ingestor_leases (
+  network    text primary key,
+  node_id    text not null,
+  expires_at timestamptz not null
+);
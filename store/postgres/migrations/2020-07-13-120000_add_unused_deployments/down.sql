@@ -0,0 +1,2 @@
+drop table unused_deployments;
+drop table deployment_queries;
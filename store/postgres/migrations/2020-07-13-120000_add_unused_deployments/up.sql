@@ -0,0 +1,15 @@
+-- When a deployment was last queried, recorded at most every few minutes
+-- by each query node
+create table deployment_queries (
+  deployment      text primary key,
+  last_queried_at timestamptz not null
+);
+
+-- Deployments that no subgraph name points to and that were not queried
+-- recently. They are removed once they have been unused for a grace
+-- period; removed deployments stay here as a record of the decision
+create table unused_deployments (
+  deployment text primary key,
+  unused_at  timestamptz not null default now(),
+  removed_at timestamptz
+);
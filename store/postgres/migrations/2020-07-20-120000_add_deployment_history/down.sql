@@ -0,0 +1,3 @@
+alter table deployment_schemas
+  drop column history_blocks,
+  drop column earliest_block;
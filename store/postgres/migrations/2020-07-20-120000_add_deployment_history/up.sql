@@ -0,0 +1,5 @@
+-- How many blocks of history to keep for a deployment, and the earliest
+-- block for which queries are still answered correctly after pruning
+alter table deployment_schemas
+  add column history_blocks int4,
+  add column earliest_block int4 not null default 0;
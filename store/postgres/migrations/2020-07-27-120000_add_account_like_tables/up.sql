@@ -0,0 +1,4 @@
+-- The tables of a deployment that are stored and queried as account-like
+-- tables, where most rows are old versions of a few entities
+alter table deployment_schemas
+  add column account_like text[] not null default '{}';
@@ -0,0 +1,15 @@
+-- Deployments that only run the queries on their allowlist
+create table query_allowlist_deployments (
+  deployment text primary key,
+  enabled_at timestamptz not null default now()
+);
+
+-- The queries that deployments in allowlist mode run, identified by the
+-- hash of their normalized text
+create table query_allowlist (
+  deployment text not null,
+  hash       text not null,
+  query      text not null,
+  added_at   timestamptz not null default now(),
+  primary key (deployment, hash)
+);
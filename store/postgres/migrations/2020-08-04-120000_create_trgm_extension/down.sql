@@ -0,0 +1 @@
+-- No good reason to drop the pg_trgm extension again
@@ -0,0 +1,3 @@
+-- Trigram indexes from indexerHints need pg_trgm
+-- This requires superuser privileges
+create extension if not exists pg_trgm;
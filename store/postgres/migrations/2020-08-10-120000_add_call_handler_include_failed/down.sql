@@ -0,0 +1,2 @@
+alter table subgraphs.ethereum_call_handler_entity
+  drop column include_failed;
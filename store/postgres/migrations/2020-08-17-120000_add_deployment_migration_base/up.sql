@@ -0,0 +1,4 @@
+-- Like graft_base, this is purely informational once the deployment has
+-- taken over the storage of its base, since the base is removed then
+alter table subgraphs.subgraph_deployment
+  add column migration_base text;
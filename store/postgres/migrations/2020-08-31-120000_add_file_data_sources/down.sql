@@ -0,0 +1,4 @@
+alter table subgraphs.ethereum_contract_mapping
+  drop column handler;
+alter table subgraphs.ethereum_contract_source
+  drop column file;
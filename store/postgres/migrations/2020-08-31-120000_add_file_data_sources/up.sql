@@ -0,0 +1,4 @@
+alter table subgraphs.ethereum_contract_source
+  add column file text;
+alter table subgraphs.ethereum_contract_mapping
+  add column handler text;
@@ -0,0 +1,210 @@
+//! Inspect and repair the blocks that the chain store caches in the
+//! `ethereum_blocks` table. `BlockCache` uses its own connection so that it
+//! can be used from the command line without setting up a store.
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Nullable, Text};
+use diesel::{insert_into, sql_query};
+
+use graph::prelude::{format_err, serde_json, web3::types::H256, Error, EthereumBlock};
+
+/// Only look at blocks with numbers in `from..=to`
+#[derive(Clone, Copy, Debug)]
+pub struct BlockRange {
+    pub from: i64,
+    pub to: i64,
+}
+
+impl Default for BlockRange {
+    fn default() -> Self {
+        BlockRange {
+            from: 0,
+            to: i64::MAX,
+        }
+    }
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct Summary {
+    #[sql_type = "BigInt"]
+    pub blocks: i64,
+    /// The number of distinct block numbers
+    #[sql_type = "BigInt"]
+    pub numbers: i64,
+    #[sql_type = "Nullable<BigInt>"]
+    pub min_number: Option<i64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub max_number: Option<i64>,
+}
+
+/// A run of block numbers for which no block is cached
+#[derive(Debug, QueryableByName)]
+pub struct Gap {
+    #[sql_type = "BigInt"]
+    pub start: i64,
+    #[sql_type = "BigInt"]
+    pub end: i64,
+}
+
+/// Several blocks with the same number, usually left behind by a reorg
+#[derive(Debug, QueryableByName)]
+pub struct Duplicate {
+    #[sql_type = "BigInt"]
+    pub number: i64,
+    #[sql_type = "Array<Text>"]
+    pub hashes: Vec<String>,
+}
+
+/// A block whose parent is not cached even though blocks with the number
+/// of its parent are
+#[derive(Debug, QueryableByName)]
+pub struct BrokenLink {
+    #[sql_type = "BigInt"]
+    pub number: i64,
+    #[sql_type = "Text"]
+    pub hash: String,
+    #[sql_type = "Nullable<Text>"]
+    pub parent_hash: Option<String>,
+}
+
+/// The cached blocks of one network
+pub struct BlockCache {
+    conn: PgConnection,
+    network: String,
+}
+
+impl BlockCache {
+    pub fn connect(postgres_url: &str, network: &str) -> Result<Self, Error> {
+        Ok(BlockCache {
+            conn: PgConnection::establish(postgres_url)?,
+            network: network.to_owned(),
+        })
+    }
+
+    pub fn summary(&self, range: BlockRange) -> Result<Summary, Error> {
+        let query = "
+            select count(*) as blocks, count(distinct number) as numbers,
+                   min(number) as min_number, max(number) as max_number
+              from ethereum_blocks
+             where network_name = $1 and number between $2 and $3";
+        Ok(sql_query(query)
+            .bind::<Text, _>(&self.network)
+            .bind::<BigInt, _>(range.from)
+            .bind::<BigInt, _>(range.to)
+            .get_result::<Summary>(&self.conn)?)
+    }
+
+    pub fn gaps(&self, range: BlockRange) -> Result<Vec<Gap>, Error> {
+        let query = "
+            select number + 1 as start, next - 1 as end
+              from (select number, lead(number) over (order by number) as next
+                      from (select distinct number
+                              from ethereum_blocks
+                             where network_name = $1 and number between $2 and $3) n) n
+             where next > number + 1
+             order by number";
+        Ok(sql_query(query)
+            .bind::<Text, _>(&self.network)
+            .bind::<BigInt, _>(range.from)
+            .bind::<BigInt, _>(range.to)
+            .load::<Gap>(&self.conn)?)
+    }
+
+    /// The distinct numbers of the cached blocks in `range`
+    pub fn cached_numbers(&self, range: BlockRange) -> Result<Vec<i64>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl::*;
+
+        Ok(ethereum_blocks
+            .select(number)
+            .distinct()
+            .filter(network_name.eq(&self.network))
+            .filter(number.between(range.from, range.to))
+            .order(number)
+            .load::<i64>(&self.conn)?)
+    }
+
+    pub fn duplicates(&self, range: BlockRange) -> Result<Vec<Duplicate>, Error> {
+        let query = "
+            select number, array_agg(hash::text order by hash) as hashes
+              from ethereum_blocks
+             where network_name = $1 and number between $2 and $3
+             group by number
+            having count(*) > 1
+             order by number";
+        Ok(sql_query(query)
+            .bind::<Text, _>(&self.network)
+            .bind::<BigInt, _>(range.from)
+            .bind::<BigInt, _>(range.to)
+            .load::<Duplicate>(&self.conn)?)
+    }
+
+    pub fn broken_links(&self, range: BlockRange) -> Result<Vec<BrokenLink>, Error> {
+        let query = "
+            select b.number, b.hash::text as hash, b.parent_hash::text as parent_hash
+              from ethereum_blocks b
+             where b.network_name = $1 and b.number between $2 and $3
+               and exists (select 1 from ethereum_blocks p
+                            where p.network_name = $1 and p.number = b.number - 1)
+               and not exists (select 1 from ethereum_blocks p
+                                where p.network_name = $1 and p.hash = b.parent_hash)
+             order by b.number";
+        Ok(sql_query(query)
+            .bind::<Text, _>(&self.network)
+            .bind::<BigInt, _>(range.from)
+            .bind::<BigInt, _>(range.to)
+            .load::<BrokenLink>(&self.conn)?)
+    }
+
+    /// The hash of the network's chain head, if it has one
+    pub fn chain_head(&self) -> Result<Option<String>, Error> {
+        use crate::db_schema::ethereum_networks::dsl::*;
+
+        Ok(ethereum_networks
+            .select(head_block_hash)
+            .filter(name.eq(&self.network))
+            .first::<Option<String>>(&self.conn)
+            .optional()?
+            .flatten())
+    }
+
+    /// Delete the block with `block_hash` from the cache. Returns the number of
+    /// blocks that were deleted
+    pub fn delete_block(&self, block_hash: &H256) -> Result<usize, Error> {
+        use crate::db_schema::ethereum_blocks::dsl;
+
+        Ok(diesel::delete(
+            dsl::ethereum_blocks
+                .filter(dsl::network_name.eq(&self.network))
+                .filter(dsl::hash.eq(format!("{:x}", block_hash))),
+        )
+        .execute(&self.conn)?)
+    }
+
+    /// Write `block` to the cache, replacing what is cached for its hash
+    pub fn replace_block(&self, block: &EthereumBlock) -> Result<(), Error> {
+        use crate::db_schema::ethereum_blocks::dsl::*;
+
+        let block_hash = block
+            .block
+            .hash
+            .ok_or_else(|| format_err!("block has no hash"))?;
+        let block_number = block
+            .block
+            .number
+            .ok_or_else(|| format_err!("block has no number"))?;
+        let values = (
+            hash.eq(format!("{:x}", block_hash)),
+            number.eq(block_number.as_u64() as i64),
+            parent_hash.eq(format!("{:x}", block.block.parent_hash)),
+            network_name.eq(&self.network),
+            data.eq(serde_json::to_value(block)?),
+        );
+        insert_into(ethereum_blocks)
+            .values(values.clone())
+            .on_conflict(hash)
+            .do_update()
+            .set(values)
+            .execute(&self.conn)?;
+        Ok(())
+    }
+}
@@ -7,9 +7,10 @@ use diesel::sql_types::{Integer, Range};
 use std::io::Write;
 use std::ops::{Bound, RangeBounds, RangeFrom};
 
-use graph::prelude::BlockNumber;
+use graph::prelude::{BlockNumber, BLOCK_NUMBER_MAX};
 
 use crate::history_event::HistoryEvent;
+use crate::relational::Table;
 
 /// The name of the column in which we store the block range
 pub(crate) const BLOCK_RANGE_COLUMN: &str = "block_range";
@@ -83,6 +84,7 @@ impl ToSql<Range<Integer>, Pg> for BlockRange {
 /// of an entity
 #[derive(Constructor)]
 pub struct BlockRangeContainsClause<'a> {
+    table: &'a Table,
     table_prefix: &'a str,
     block: BlockNumber,
 }
@@ -91,9 +93,25 @@ impl<'a> QueryFragment<Pg> for BlockRangeContainsClause<'a> {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
         out.unsafe_to_cache_prepared();
 
-        out.push_sql(self.table_prefix);
-        out.push_identifier(BLOCK_RANGE_COLUMN)?;
-        out.push_sql(" @> ");
-        out.push_bind_param::<Integer, _>(&self.block)
+        if self.table.is_account_like {
+            // Spell out the bounds so that Postgres can use the index on
+            // `coalesce(upper(block_range), ..)` for account-like tables
+            // rather than going through all versions of an entity
+            out.push_sql("lower(");
+            out.push_sql(self.table_prefix);
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(") <= ");
+            out.push_bind_param::<Integer, _>(&self.block)?;
+            out.push_sql(" and coalesce(upper(");
+            out.push_sql(self.table_prefix);
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(&format!("), {}) > ", BLOCK_NUMBER_MAX));
+            out.push_bind_param::<Integer, _>(&self.block)
+        } else {
+            out.push_sql(self.table_prefix);
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(" @> ");
+            out.push_bind_param::<Integer, _>(&self.block)
+        }
     }
 }
@@ -1,6 +1,6 @@
 use diesel::pg::PgConnection;
 use diesel::prelude::RunQueryDsl;
-use diesel::sql_types::Text;
+use diesel::sql_types::{BigInt, Text};
 use std::collections::{HashMap, HashSet};
 
 use graph::prelude::StoreError;
@@ -74,3 +74,47 @@ fn get_text_columns(
         });
     Ok(map)
 }
+
+/// The size of a table and Postgres' estimates of how many rows and
+/// distinct ids it holds
+#[derive(Debug, QueryableByName)]
+pub struct TableSize {
+    #[sql_type = "Text"]
+    pub table_name: String,
+    #[sql_type = "BigInt"]
+    pub distinct_ids: i64,
+    #[sql_type = "BigInt"]
+    pub rows: i64,
+    #[sql_type = "BigInt"]
+    pub table_bytes: i64,
+    #[sql_type = "BigInt"]
+    pub index_bytes: i64,
+}
+
+/// The sizes of all tables in `schema`. Row counts come from the
+/// statistics that `analyze` collects, and are 0 for tables that have not
+/// been analyzed yet
+pub fn table_sizes(conn: &PgConnection, schema: &str) -> Result<Vec<TableSize>, StoreError> {
+    // A negative `n_distinct` is the number of distinct values as a
+    // fraction of the number of rows
+    const QUERY: &str = "
+        select c.relname::text as table_name,
+               coalesce((select case when s.n_distinct >= 0 then s.n_distinct
+                                     else -s.n_distinct * greatest(c.reltuples, 0) end
+                           from pg_stats s
+                          where s.schemaname = n.nspname
+                            and s.tablename = c.relname
+                            and s.attname = 'id'), 0)::bigint as distinct_ids,
+               greatest(c.reltuples, 0)::bigint as rows,
+               pg_table_size(c.oid) as table_bytes,
+               pg_indexes_size(c.oid) as index_bytes
+          from pg_class c, pg_namespace n
+         where n.oid = c.relnamespace
+           and n.nspname = $1
+           and c.relkind = 'r'
+         order by c.relname";
+
+    Ok(diesel::sql_query(QUERY)
+        .bind::<Text, _>(schema)
+        .load::<TableSize>(conn)?)
+}
@@ -1,5 +1,6 @@
 use diesel::pg::PgConnection;
 use diesel::r2d2::{self, event as e, ConnectionManager, HandleEvent, Pool};
+use diesel::{Connection, RunQueryDsl};
 
 use graph::prelude::*;
 use graph::util::security::SafeDisplay;
@@ -31,19 +32,26 @@ struct EventHandler {
 }
 
 impl EventHandler {
-    fn new(logger: Logger, registry: Arc<dyn MetricsRegistry>, wait_stats: PoolWaitStats) -> Self {
+    fn new(
+        logger: Logger,
+        pool_name: &str,
+        registry: Arc<dyn MetricsRegistry>,
+        wait_stats: PoolWaitStats,
+    ) -> Self {
+        let count_name = metric_name(pool_name, "connection_checkout_count");
         let count_gauge = registry
             .global_gauge(
-                String::from("store_connection_checkout_count"),
+                count_name.clone(),
                 String::from("The number of Postgres connections currently checked out"),
             )
-            .expect("failed to create `store_connection_checkout_count` counter");
+            .unwrap_or_else(|_| panic!("failed to create `{}` counter", count_name));
+        let wait_name = metric_name(pool_name, "connection_wait_time_ms");
         let wait_gauge = registry
             .global_gauge(
-                String::from("store_connection_wait_time_ms"),
+                wait_name.clone(),
                 String::from("Average connection wait time"),
             )
-            .expect("failed to create `store_connection_wait_time_ms` counter");
+            .unwrap_or_else(|_| panic!("failed to create `{}` counter", wait_name));
         EventHandler {
             logger,
             count_gauge,
@@ -86,7 +94,50 @@ impl HandleEvent for EventHandler {
     }
 }
 
+/// The name of the main pool. Its metrics keep the names they had before
+/// the pool was split up
+pub const MAIN_POOL: &str = "main";
+
+/// The name of a pool metric; the metrics of the main pool are called
+/// `store_<name>`, those of other pools `store_<pool>_<name>`
+fn metric_name(pool_name: &str, name: &str) -> String {
+    if pool_name == MAIN_POOL {
+        format!("store_{}", name)
+    } else {
+        format!("store_{}_{}", pool_name, name)
+    }
+}
+
+/// The pools on the main database server. Writes for indexing, GraphQL
+/// queries and subgraph metadata operations each get their own pool so
+/// that a burst of one kind of work can not take all connections away from
+/// the others. The pools may be clones of the same pool when they are not
+/// meant to be separate.
+#[derive(Clone)]
+pub struct ConnectionPools {
+    /// Used for indexing and everything that is not a query or a metadata
+    /// operation
+    pub main: Pool<ConnectionManager<PgConnection>>,
+    pub query: Pool<ConnectionManager<PgConnection>>,
+    pub metadata: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl ConnectionPools {
+    /// Use `pool` for all kinds of work
+    pub fn shared(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        ConnectionPools {
+            main: pool.clone(),
+            query: pool.clone(),
+            metadata: pool,
+        }
+    }
+}
+
+/// Create a pool of at most `pool_size` connections to `postgres_url`.
+/// `pool_name` distinguishes the metrics of different pools, and the
+/// checkout wait times of the pool are recorded in `wait_time`
 pub fn create_connection_pool(
+    pool_name: &str,
     postgres_url: String,
     pool_size: u32,
     logger: &Logger,
@@ -94,15 +145,22 @@ pub fn create_connection_pool(
     wait_time: PoolWaitStats,
 ) -> Pool<ConnectionManager<PgConnection>> {
     let logger_store = logger.new(o!("component" => "Store"));
-    let logger_pool = logger.new(o!("component" => "PostgresConnectionPool"));
+    let logger_pool =
+        logger.new(o!("component" => "PostgresConnectionPool", "pool" => pool_name.to_owned()));
+    let error_name = metric_name(pool_name, "connection_error_count");
     let error_counter = registry
         .global_counter(
-            String::from("store_connection_error_count"),
+            error_name.clone(),
             String::from("The number of Postgres connections errors"),
         )
-        .expect("failed to create `store_connection_error_count` counter");
+        .unwrap_or_else(|_| panic!("failed to create `{}` counter", error_name));
     let error_handler = Box::new(ErrorHandler(logger_pool.clone(), error_counter));
-    let event_handler = Box::new(EventHandler::new(logger_pool.clone(), registry, wait_time));
+    let event_handler = Box::new(EventHandler::new(
+        logger_pool.clone(),
+        pool_name,
+        registry,
+        wait_time,
+    ));
 
     // Connect to Postgres
     let conn_manager = ConnectionManager::new(postgres_url.clone());
@@ -129,7 +187,19 @@ pub fn create_connection_pool(
     info!(
         logger_store,
         "Connected to Postgres";
-        "url" => SafeDisplay(postgres_url.as_str())
+        "url" => SafeDisplay(postgres_url.as_str()),
+        "pool" => pool_name,
+        "pool_size" => pool_size,
     );
     pool
 }
+
+/// Open a single connection to `postgres_url` and run a trivial query on
+/// it, to check that the database can be reached without setting up a pool.
+pub fn check_connection(postgres_url: &str) -> Result<(), String> {
+    let conn = PgConnection::establish(postgres_url).map_err(|e| e.to_string())?;
+    diesel::sql_query("select 1")
+        .execute(&conn)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
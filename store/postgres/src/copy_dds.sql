@@ -41,13 +41,13 @@ with xlat as (
       from subgraphs.ethereum_contract_event_handler e, xlat x
      where left(e.id, 40) = x.id),
  md8 as (
-    insert into subgraphs.ethereum_contract_mapping(id, kind, api_version, language, file, entities, abis, block_handlers, call_handlers, event_handlers, block_range)
-    select (x.new_id || right(e.id, -40)) as id, kind, api_version, language, file, entities, (select array_agg(x.new_id || right(a.elt, -40)) from unnest(e.abis) a(elt)) as abis, (select array_agg(x.new_id || right(a.elt, -40)) from unnest(e.block_handlers) a(elt)) as block_handlers, (select array_agg(x.new_id || right(a.elt, -40)) from unnest(e.call_handlers) a(elt)) as call_handlers, (select array_agg(x.new_id || right(a.elt, -40)) from unnest(e.event_handlers) a(elt)) as event_handlers, block_range
+    insert into subgraphs.ethereum_contract_mapping(id, kind, api_version, language, file, entities, abis, block_handlers, call_handlers, event_handlers, handler, block_range)
+    select (x.new_id || right(e.id, -40)) as id, kind, api_version, language, file, entities, (select array_agg(x.new_id || right(a.elt, -40)) from unnest(e.abis) a(elt)) as abis, (select array_agg(x.new_id || right(a.elt, -40)) from unnest(e.block_handlers) a(elt)) as block_handlers, (select array_agg(x.new_id || right(a.elt, -40)) from unnest(e.call_handlers) a(elt)) as call_handlers, (select array_agg(x.new_id || right(a.elt, -40)) from unnest(e.event_handlers) a(elt)) as event_handlers, handler, block_range
       from subgraphs.ethereum_contract_mapping e, xlat x
      where left(e.id, 40) = x.id),
  md9 as (
-    insert into subgraphs.ethereum_contract_source(id, address, abi, start_block, block_range)
-    select (x.new_id || right(e.id, -40)) as id, address, abi, start_block, block_range
+    insert into subgraphs.ethereum_contract_source(id, address, abi, start_block, file, block_range)
+    select (x.new_id || right(e.id, -40)) as id, address, abi, start_block, file, block_range
       from subgraphs.ethereum_contract_source e, xlat x
      where left(e.id, 40) = x.id)
 insert into subgraphs.dynamic_ethereum_contract_data_source(id, kind, name,
@@ -0,0 +1,40 @@
+//! Maintain the node-local `ens_names` table that resolves ENS label hashes
+//! for the `ens.nameByHash` host export
+use diesel::pg::PgConnection;
+use diesel::{insert_into, ExpressionMethods, RunQueryDsl};
+
+use graph::prelude::{tiny_keccak::keccak256, web3::types::H256, StoreError};
+
+use crate::db_schema::ens_names;
+
+/// Postgres limits the number of bind variables in one statement to 65535;
+/// we use two per name
+const INSERT_BATCH_SIZE: usize = 10_000;
+
+/// Return the ENS label hash for `name` in the form that is stored in the
+/// `ens_names` table, i.e., as a `0x` prefixed hex string
+pub fn label_hash(name: &str) -> String {
+    format!("0x{:x}", H256::from(keccak256(name.as_bytes())))
+}
+
+/// Add `names` to the `ens_names` table. Names that are already known are
+/// ignored. Return the number of names that were added
+pub fn insert_names(conn: &PgConnection, names: &[String]) -> Result<usize, StoreError> {
+    let mut count = 0;
+    for chunk in names.chunks(INSERT_BATCH_SIZE) {
+        let rows = chunk
+            .iter()
+            .map(|name| {
+                (
+                    ens_names::hash.eq(label_hash(name)),
+                    ens_names::name.eq(name),
+                )
+            })
+            .collect::<Vec<_>>();
+        count += insert_into(ens_names::table)
+            .values(&rows)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(count)
+}
@@ -46,8 +46,9 @@ use graph::data::subgraph::schema::{POI_OBJECT, POI_TABLE, SUBGRAPHS_ID};
 use graph::prelude::{
     debug, format_err, info, serde_json, warn, AttributeIndexDefinition, BlockNumber, Entity,
     EntityChange, EntityChangeOperation, EntityCollection, EntityFilter, EntityKey,
-    EntityModification, EntityOrder, EntityRange, Error, EthereumBlockPointer, Logger,
-    QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, ValueType, BLOCK_NUMBER_MAX,
+    EntityModification, EntityOrder, EntityRange, EntityWindow, Error, EthereumBlockPointer,
+    IndexerHints, Logger, QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId,
+    ValueType, BLOCK_NUMBER_MAX,
 };
 
 use crate::block_range::block_number;
@@ -180,6 +181,16 @@ mod public {
             migrating -> Bool,
             /// See comment on DeploymentSchemaState
             state -> crate::entities::public::DeploymentSchemaStateMapping,
+            /// How many blocks of history the pruner keeps for this
+            /// deployment; all history is kept if this is null
+            history_blocks -> Nullable<Integer>,
+            /// The earliest block that queries can use. Pruning removes
+            /// the data needed to answer queries for earlier blocks
+            earliest_block -> Integer,
+            /// The names of the tables that are account-like, i.e., that
+            /// have many versions of few entities and need different
+            /// indexes and queries
+            account_like -> Array<Text>,
         }
     }
 }
@@ -231,6 +242,15 @@ struct Schema {
     /// locks. When the data is in place, the migration updates `version` to
     /// the new version we migrated to, and sets the state to `Ready`
     state: public::DeploymentSchemaState,
+    /// The number of blocks of history to keep from the `indexerHints` in
+    /// the manifest, or `None` to keep all history
+    history_blocks: Option<i32>,
+    /// Queries for blocks before this one are rejected since pruning has
+    /// removed the entity versions they would need
+    earliest_block: i32,
+    /// The tables that the `indexerHints` or the store maintenance marked
+    /// as account-like
+    account_like: Vec<String>,
 }
 
 /// Storage using JSONB for entities. All entities are stored in one table
@@ -262,6 +282,26 @@ impl Storage {
             Storage::Relational(layout) => &layout.subgraph,
         }
     }
+
+    /// The name of the database schema that holds the entities
+    pub(crate) fn schema_name(&self) -> &str {
+        match self {
+            Storage::Json(json) => &json.schema,
+            Storage::Relational(layout) => &layout.catalog.schema,
+        }
+    }
+
+    /// The entity type stored in `table`, if the table stores exactly one
+    pub(crate) fn entity_type(&self, table: &str) -> Option<String> {
+        match self {
+            Storage::Json(_) => None,
+            Storage::Relational(layout) => layout
+                .tables
+                .values()
+                .find(|t| t.name.as_str() == table)
+                .map(|t| t.object.clone()),
+        }
+    }
 }
 
 /// Helper struct to support a custom query for entity history
@@ -452,6 +492,21 @@ impl Connection<'_> {
         }
     }
 
+    pub(crate) fn find_changes(
+        &self,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<Vec<(String, Entity)>, StoreError> {
+        match &*self.storage {
+            Storage::Json(_) => Err(StoreError::QueryExecutionError(
+                "This subgraph uses JSONB storage, which does not keep track of \
+                 the block in which entities were changed"
+                    .to_owned(),
+            )),
+            Storage::Relational(layout) => layout.find_changes(&self.conn, entity_types, block),
+        }
+    }
+
     pub(crate) fn query<T: crate::relational_queries::FromEntityData>(
         &self,
         logger: &Logger,
@@ -486,6 +541,23 @@ impl Connection<'_> {
         }
     }
 
+    pub(crate) fn count_children(
+        &self,
+        windows: Vec<EntityWindow>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, i64>, QueryExecutionError> {
+        match &*self.storage {
+            Storage::Json(_) => Err(StoreError::QueryExecutionError(
+                "This subgraph uses JSONB storage, which does not support \
+                 counting derived fields. Redeploy a new version of this \
+                 subgraph to enable this feature."
+                    .to_owned(),
+            )
+            .into()),
+            Storage::Relational(layout) => layout.count_children(&self.conn, windows, block),
+        }
+    }
+
     pub(crate) fn conflicting_entity(
         &self,
         entity_id: &String,
@@ -562,6 +634,12 @@ impl Connection<'_> {
             .find(&self.conn, entity, id, BLOCK_NUMBER_MAX)
     }
 
+    /// Return the ids of the non-fatal errors recorded for this
+    /// connection's subgraph
+    pub(crate) fn non_fatal_errors(&self) -> Result<Vec<String>, StoreError> {
+        metadata::non_fatal_errors(&self.conn, self.storage.subgraph())
+    }
+
     pub(crate) fn delete(
         &self,
         key: &EntityKey,
@@ -810,7 +888,11 @@ impl Connection<'_> {
     /// It is an error if `deployment_schemas` already has an entry for this
     /// `subgraph_id`. Note that `self` must be a connection for the subgraph
     /// of subgraphs
-    pub(crate) fn create_schema(&self, schema: &SubgraphSchema) -> Result<(), StoreError> {
+    pub(crate) fn create_schema(
+        &self,
+        schema: &SubgraphSchema,
+        hints: &IndexerHints,
+    ) -> Result<(), StoreError> {
         use self::public::DeploymentSchemaState as s;
         use self::public::DeploymentSchemaVersion as v;
 
@@ -830,12 +912,17 @@ impl Connection<'_> {
             return Ok(());
         }
 
+        if let Some(base) = metadata::deployment_migration_base(&self.conn, &schema.id)? {
+            return self.take_over_schema(schema, hints, &base);
+        }
+
         // Create a schema for the deployment.
         let schemas: Vec<String> = diesel::insert_into(deployment_schemas::table)
             .values((
                 deployment_schemas::subgraph.eq(schema.id.to_string()),
                 deployment_schemas::version.eq(*GRAPH_STORAGE_SCHEME),
                 deployment_schemas::state.eq(s::Init),
+                deployment_schemas::history_blocks.eq(hints.history_blocks()),
             ))
             .returning(deployment_schemas::name)
             .get_results(self.conn.deref())?;
@@ -850,6 +937,14 @@ impl Connection<'_> {
             v::Relational => {
                 let layout =
                     Layout::create_relational_schema(&self.conn, schema, schema_name.to_owned())?;
+                self.conn
+                    .batch_execute(&layout.index_hints_ddl(&hints.indexes)?)?;
+                for entity in &hints.account_like {
+                    let table = layout.table_for_entity(entity)?;
+                    self.conn
+                        .batch_execute(&table.account_like_index_ddl(false))?;
+                    set_account_like(&self.conn, &schema.id, table.name.as_str())?;
+                }
                 // See if we are grafting and check that the graft is permissible
                 if let Some((base, _)) = metadata::deployment_graft(&self.conn, &schema.id)? {
                     match Storage::new(&self.conn, &base)? {
@@ -880,6 +975,14 @@ impl Connection<'_> {
                 Ok(())
             }
             v::Split => {
+                if !hints.indexes.is_empty()
+                    || hints.prune.is_some()
+                    || !hints.account_like.is_empty()
+                {
+                    return Err(StoreError::Unknown(format_err!(
+                        "JSONB storage does not support `indexerHints`",
+                    )));
+                }
                 if metadata::deployment_graft(&self.conn, &schema.id)?.is_some() {
                     return Err(StoreError::Unknown(format_err!(
                         "JSONB storage does not support grafting onto another subgraph",
@@ -890,6 +993,53 @@ impl Connection<'_> {
         }
     }
 
+    /// Give the new deployment for `schema` the storage of `base`, and
+    /// change the tables in it in place so that they fit `schema`. The
+    /// metadata of `base` is removed since it has no storage any more
+    fn take_over_schema(
+        &self,
+        schema: &SubgraphSchema,
+        hints: &IndexerHints,
+        base: &SubgraphDeploymentId,
+    ) -> Result<(), StoreError> {
+        if !hints.indexes.is_empty() || hints.prune.is_some() || !hints.account_like.is_empty() {
+            return Err(StoreError::Unknown(format_err!(
+                "`{}` keeps the storage of `{}` and can therefore not use `indexerHints`",
+                &schema.id,
+                base
+            )));
+        }
+        let base_layout = match Storage::new(&self.conn, base)? {
+            Storage::Relational(layout) => layout,
+            Storage::Json(_) => {
+                return Err(StoreError::Unknown(format_err!(
+                    "The subgraph `{}` can not be migrated since it uses JSONB storage",
+                    base
+                )))
+            }
+        };
+        let catalog = Catalog::new(&self.conn, base_layout.catalog.schema.clone())?;
+        let layout = Layout::new(schema, catalog, true)?;
+        let errors = layout.can_migrate_from(&base_layout);
+        if !errors.is_empty() {
+            return Err(StoreError::Unknown(format_err!(
+                "The subgraph `{}` can not be migrated to `{}` \
+                    because the schemas are incompatible:\n    - {}",
+                base,
+                &schema.id,
+                errors.join("\n    - ")
+            )));
+        }
+        let ddl = layout
+            .migration_ddl(&base_layout)
+            .map_err(|_| StoreError::Unknown(format_err!("failed to generate DDL for layout")))?;
+        self.conn.batch_execute(&ddl)?;
+
+        metadata::take_over_deployment(&self.conn, base, &schema.id)?;
+        crate::unused::remove_metadata(&self.conn, base.as_str())?;
+        Ok(())
+    }
+
     pub(crate) fn uses_relational_schema(&self) -> bool {
         match &*self.storage {
             Storage::Json(_) => false,
@@ -924,6 +1074,100 @@ fn find_schema(
         .optional()?)
 }
 
+/// The ids of all deployments that have a database schema, except for the
+/// subgraph of subgraphs
+pub(crate) fn deployment_ids(
+    conn: &diesel::pg::PgConnection,
+) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    Ok(deployment_schemas::table
+        .select(deployment_schemas::subgraph)
+        .filter(deployment_schemas::subgraph.ne(SUBGRAPHS_ID.as_str()))
+        .order(deployment_schemas::subgraph)
+        .load::<String>(conn)?
+        .into_iter()
+        .filter_map(|id| SubgraphDeploymentId::new(id).ok())
+        .collect())
+}
+
+/// Record that `table` in the deployment `subgraph` is account-like. The
+/// index that account-like tables need has to exist already. Returns
+/// `false` if the table was already marked
+pub(crate) fn set_account_like(
+    conn: &diesel::pg::PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    table: &str,
+) -> Result<bool, StoreError> {
+    let query = "update deployment_schemas
+                    set account_like = array_append(account_like, $2)
+                  where subgraph = $1
+                    and not $2 = any(account_like)";
+    let count = diesel::sql_query(query)
+        .bind::<Text, _>(subgraph.as_str())
+        .bind::<Text, _>(table)
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// The earliest block for which queries against `subgraph` still have all
+/// the data they need. It is only later than the start of the chain if the
+/// deployment has been pruned
+pub(crate) fn earliest_block(
+    conn: &diesel::pg::PgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<BlockNumber, StoreError> {
+    Ok(deployment_schemas::table
+        .select(deployment_schemas::earliest_block)
+        .filter(deployment_schemas::subgraph.eq(subgraph.as_str()))
+        .first::<BlockNumber>(conn)
+        .optional()?
+        .unwrap_or(0))
+}
+
+/// The deployments whose manifest limits how many blocks of history they
+/// keep, together with that number of blocks and their current earliest
+/// block
+pub(crate) fn pruned_deployments(
+    conn: &diesel::pg::PgConnection,
+) -> Result<Vec<(SubgraphDeploymentId, BlockNumber, BlockNumber)>, StoreError> {
+    Ok(deployment_schemas::table
+        .select((
+            deployment_schemas::subgraph,
+            deployment_schemas::history_blocks,
+            deployment_schemas::earliest_block,
+        ))
+        .filter(deployment_schemas::history_blocks.is_not_null())
+        .order(deployment_schemas::subgraph)
+        .load::<(String, Option<BlockNumber>, BlockNumber)>(conn)?
+        .into_iter()
+        .filter_map(|(id, history_blocks, earliest_block)| {
+            let id = SubgraphDeploymentId::new(id).ok()?;
+            Some((id, history_blocks?, earliest_block))
+        })
+        .collect())
+}
+
+/// Delete the entity versions of `subgraph` that are not visible at
+/// `earliest_block` or later, and remember that queries for earlier blocks
+/// can not be answered any more. Returns the number of deleted versions
+pub(crate) fn prune(
+    conn: &diesel::pg::PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    earliest_block: BlockNumber,
+) -> Result<usize, StoreError> {
+    conn.transaction(|| -> Result<usize, StoreError> {
+        let count = match Storage::new(conn, subgraph)? {
+            Storage::Relational(layout) => layout.prune(conn, earliest_block)?,
+            Storage::Json(_) => return Ok(0),
+        };
+        diesel::update(
+            deployment_schemas::table.filter(deployment_schemas::subgraph.eq(subgraph.as_str())),
+        )
+        .set(deployment_schemas::earliest_block.eq(earliest_block))
+        .execute(conn)?;
+        Ok(count)
+    })
+}
+
 fn supports_proof_of_indexing(
     conn: &diesel::pg::PgConnection,
     subgraph_id: &SubgraphDeploymentId,
@@ -1411,7 +1655,8 @@ impl Storage {
                 let subgraph_schema = metadata::subgraph_schema(conn, subgraph.to_owned())?;
                 let has_poi = supports_proof_of_indexing(conn, subgraph, &schema.name)?;
                 let catalog = Catalog::new(conn, schema.name)?;
-                let layout = Layout::new(&subgraph_schema, catalog, has_poi)?;
+                let mut layout = Layout::new(&subgraph_schema, catalog, has_poi)?;
+                layout.set_account_like(&schema.account_like);
                 Storage::Relational(layout)
             }
         };
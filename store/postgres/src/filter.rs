@@ -344,9 +344,21 @@ where
             }
             let op = " = ANY ";
 
+            // Lists that mix `Int` with big numbers are compared as numbers
+            let numeric = values.iter().any(|value| match value {
+                Value::BigInt(_) | Value::BigDecimal(_) => true,
+                _ => false,
+            });
+            if numeric {
+                return Ok(SqlValue::new_array(values).into_array_filter::<Numeric>(
+                    attribute,
+                    op,
+                    "::numeric",
+                ));
+            }
+
             match values[0] {
-                Value::BigInt(_) | Value::BigDecimal(_) => Ok(SqlValue::new_array(values)
-                    .into_array_filter::<Numeric>(attribute, op, "::numeric")),
+                Value::BigInt(_) | Value::BigDecimal(_) => unreachable!("checked above"),
                 Value::Bool(_) => Ok(SqlValue::new_array(values).into_array_filter::<Bool>(
                     attribute,
                     op,
@@ -437,5 +449,23 @@ where
                 }
             }
         }
+
+        // Subgraphs that use JSONB storage predate these filters
+        ContainsNoCase(_, value) => Err(UnsupportedFilter {
+            filter: "contains_nocase".to_owned(),
+            value,
+        }),
+        StartsWithNoCase(_, value) => Err(UnsupportedFilter {
+            filter: "starts_with_nocase".to_owned(),
+            value,
+        }),
+        EndsWithNoCase(_, value) => Err(UnsupportedFilter {
+            filter: "ends_with_nocase".to_owned(),
+            value,
+        }),
+        Matches(_, value) => Err(UnsupportedFilter {
+            filter: "matches".to_owned(),
+            value,
+        }),
     }
 }
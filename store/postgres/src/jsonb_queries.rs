@@ -125,6 +125,25 @@ impl<'a> FilterQuery<'a> {
         Ok(())
     }
 
+    /// Whether the query is across several entity types. Entities of
+    /// different types can have the same id
+    fn multiple_entity_types(&self) -> bool {
+        match &self.collection {
+            EntityCollection::All(entities) => entities.len() > 1,
+            EntityCollection::Window(windows) => {
+                windows
+                    .iter()
+                    .map(|window| &window.child_type)
+                    .collect::<HashSet<_>>()
+                    .len()
+                    > 1
+            }
+        }
+    }
+
+    /// Generate `[{sort_key} {direction} nulls last,] id [, entity]`, which
+    /// makes the order unique so that paging does not skip or repeat
+    /// entities
     fn order_by(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         if let Some(order) = &self.order {
             if &order.attribute == PRIMARY_KEY_COLUMN {
@@ -136,7 +155,11 @@ impl<'a> FilterQuery<'a> {
             out.push_sql(order.direction);
             out.push_sql(" nulls last, ");
         }
-        out.push_identifier(PRIMARY_KEY_COLUMN)
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        if self.multiple_entity_types() {
+            out.push_sql(", entity");
+        }
+        Ok(())
     }
 
     fn limit(&self, out: &mut AstPass<Pg>) {
@@ -20,25 +20,32 @@ extern crate postgres;
 extern crate serde;
 extern crate uuid;
 
+pub mod block_cache;
 mod block_range;
 mod catalog;
 mod chain_head_listener;
 pub mod connection_pool;
 mod db_schema;
+pub mod ens;
 mod entities;
 mod filter;
 mod functions;
 mod history_event;
 mod jsonb;
 mod jsonb_queries;
+mod maintenance;
 mod metadata;
+mod metrics;
 mod notification_listener;
+mod prune;
+mod query_allowlist;
 pub mod query_store;
 pub mod relational;
 mod relational_queries;
 mod sql_value;
 pub mod store;
 mod store_events;
+pub mod unused;
 
 #[cfg(debug_assertions)]
 pub mod db_schema_for_tests {
@@ -54,5 +61,8 @@ pub mod layout_for_tests {
 }
 
 pub use self::chain_head_listener::ChainHeadUpdateListener;
+pub use self::maintenance::StoreMaintenance;
+pub use self::prune::Pruner;
 pub use self::store::{Store, StoreConfig};
 pub use self::store_events::SubscriptionManager;
+pub use self::unused::UnusedDeploymentRemover;
@@ -0,0 +1,390 @@
+//! Keep the tables of deployments in shape for the way subgraphs write to
+//! them. Postgres' default autovacuum settings scale with the size of a
+//! table, which means that the large tables of busy subgraphs accumulate
+//! millions of dead rows before they are vacuumed, and that their planner
+//! statistics go stale after large backfills. It also finds account-like
+//! tables, where most rows are old versions of a few entities, and switches
+//! them to indexes and queries that do not have to go through all versions
+//! of an entity.
+use diesel::connection::SimpleConnection;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, PgConnection, RunQueryDsl};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use graph::prelude::{
+    debug, format_err, info, o, tokio, warn, CancelableError, Error, Logger, SubgraphDeploymentId,
+};
+
+use crate::entities::{self as e, Storage};
+use crate::relational::SqlName;
+use crate::store::Store;
+
+lazy_static! {
+    /// How often to look at the tables of all deployments, in seconds
+    static ref MAINTENANCE_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_STORE_MAINTENANCE_INTERVAL")
+            .unwrap_or("300".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_STORE_MAINTENANCE_INTERVAL")
+    );
+
+    /// Tables with fewer rows are left to the default autovacuum settings
+    static ref MAINTENANCE_MIN_ROWS: i64 = std::env::var("GRAPH_STORE_MAINTENANCE_MIN_ROWS")
+        .unwrap_or("100000".into())
+        .parse::<i64>()
+        .expect("invalid GRAPH_STORE_MAINTENANCE_MIN_ROWS");
+
+    /// Whether to look for account-like tables and switch them over
+    static ref ACCOUNT_LIKE_DETECTION: bool = std::env::var("GRAPH_ACCOUNT_LIKE_DETECTION")
+        .unwrap_or("false".into())
+        .parse::<bool>()
+        .expect("invalid GRAPH_ACCOUNT_LIKE_DETECTION");
+
+    /// Tables with fewer versions are never account-like
+    static ref ACCOUNT_LIKE_MIN_VERSIONS: i64 = std::env::var("GRAPH_ACCOUNT_LIKE_MIN_VERSIONS")
+        .unwrap_or("10000".into())
+        .parse::<i64>()
+        .expect("invalid GRAPH_ACCOUNT_LIKE_MIN_VERSIONS");
+
+    /// The largest ratio of entities to versions in an account-like table
+    static ref ACCOUNT_LIKE_MAX_UNIQUE_RATIO: f64 =
+        std::env::var("GRAPH_ACCOUNT_LIKE_MAX_UNIQUE_RATIO")
+            .unwrap_or("0.01".into())
+            .parse::<f64>()
+            .expect("invalid GRAPH_ACCOUNT_LIKE_MAX_UNIQUE_RATIO");
+}
+
+/// The smallest number of changed rows after which we let autovacuum
+/// vacuum a table
+const MIN_VACUUM_THRESHOLD: i64 = 10_000;
+
+#[derive(QueryableByName)]
+struct TableStats {
+    #[sql_type = "Text"]
+    subgraph: String,
+    #[sql_type = "Text"]
+    schema: String,
+    #[sql_type = "Text"]
+    table: String,
+    #[sql_type = "BigInt"]
+    live_rows: i64,
+    #[sql_type = "BigInt"]
+    dead_rows: i64,
+    /// The number of rows inserted, updated or deleted since the statistics
+    /// were last reset
+    #[sql_type = "BigInt"]
+    writes: i64,
+    #[sql_type = "BigInt"]
+    mods_since_analyze: i64,
+}
+
+/// A table that is not account-like yet, with the number of versions and
+/// entities in it according to its planner statistics
+#[derive(QueryableByName)]
+struct AccountLikeCandidate {
+    #[sql_type = "Text"]
+    subgraph: String,
+    #[sql_type = "Text"]
+    table: String,
+    #[sql_type = "BigInt"]
+    versions: i64,
+    #[sql_type = "BigInt"]
+    entities: i64,
+}
+
+impl TableStats {
+    fn qualified_name(&self) -> String {
+        format!("\"{}\".\"{}\"", self.schema, self.table)
+    }
+}
+
+/// What we did to a table the last time we looked at it
+struct TableState {
+    writes: i64,
+    /// The `autovacuum_vacuum_threshold` we set for the table, if any
+    vacuum_threshold: Option<i64>,
+}
+
+/// Periodically looks at the tables of all deployments and
+/// - sets their autovacuum thresholds according to how many rows the
+///   deployment writes, rather than how big the table is
+/// - runs `analyze` on tables that changed a lot since their statistics
+///   were last updated, for example because a deployment synced many blocks
+/// - reports live and dead rows of each deployment as metrics
+pub struct StoreMaintenance {
+    logger: Logger,
+    store: Store,
+    tables: HashMap<String, TableState>,
+}
+
+impl StoreMaintenance {
+    pub fn new(logger: &Logger, store: Store) -> Self {
+        StoreMaintenance {
+            logger: logger.new(o!("component" => "StoreMaintenance")),
+            store,
+            tables: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            tokio::time::delay_for(*MAINTENANCE_INTERVAL).await;
+            if let Err(e) = self.maintain().await {
+                warn!(self.logger, "Store maintenance failed"; "error" => e.to_string());
+            }
+        }
+    }
+
+    async fn maintain(&mut self) -> Result<(), Error> {
+        let stats = self
+            .store
+            .with_conn(|conn, _| table_stats(conn).map_err(CancelableError::from))
+            .await?;
+
+        let mut rows: HashMap<&str, (i64, i64)> = HashMap::new();
+        let mut actions = Vec::new();
+        for table in &stats {
+            let entry = rows.entry(table.subgraph.as_str()).or_default();
+            entry.0 += table.live_rows;
+            entry.1 += table.dead_rows;
+
+            let name = table.qualified_name();
+            let state = self.tables.entry(name.clone()).or_insert(TableState {
+                writes: table.writes,
+                vacuum_threshold: None,
+            });
+            // The counters go back to zero when the statistics are reset
+            let writes = (table.writes - state.writes).max(0);
+            state.writes = table.writes;
+
+            let threshold = vacuum_threshold(table.live_rows, writes);
+            let retune = match (threshold, state.vacuum_threshold) {
+                (Some(new), Some(old)) => new > 2 * old || 2 * new < old,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if retune {
+                state.vacuum_threshold = threshold;
+            }
+            let analyze = needs_analyze(table.live_rows, table.mods_since_analyze);
+            if retune || analyze {
+                actions.push((name, threshold.filter(|_| retune), analyze));
+            }
+        }
+
+        for (subgraph, (live_rows, dead_rows)) in rows {
+            if let Ok(subgraph) = SubgraphDeploymentId::new(subgraph) {
+                if let Ok(metrics) = self.store.deployment_metrics(&subgraph) {
+                    metrics.table_rows(live_rows, dead_rows);
+                }
+            }
+        }
+
+        if *ACCOUNT_LIKE_DETECTION {
+            self.detect_account_like().await?;
+        }
+
+        for (table, threshold, analyze) in actions {
+            if let Some(threshold) = threshold {
+                debug!(self.logger, "Setting autovacuum threshold";
+                       "table" => &table, "threshold" => threshold);
+            }
+            if analyze {
+                info!(self.logger, "Analyzing table after large changes"; "table" => &table);
+            }
+            let start = Instant::now();
+            let res = self
+                .store
+                .with_conn(move |conn, _| {
+                    maintain_table(conn, &table, threshold, analyze).map_err(CancelableError::from)
+                })
+                .await;
+            if let Err(e) = res {
+                warn!(self.logger, "Failed to maintain table"; "error" => e.to_string());
+            } else if analyze {
+                debug!(self.logger, "Finished analyzing table";
+                       "time_ms" => start.elapsed().as_millis());
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark the tables that look account-like according to their
+    /// statistics as account-like, after creating the index they need
+    async fn detect_account_like(&self) -> Result<(), Error> {
+        let candidates = self
+            .store
+            .with_conn(|conn, _| account_like_candidates(conn).map_err(CancelableError::from))
+            .await?;
+
+        for candidate in candidates
+            .into_iter()
+            .filter(|c| is_account_like(c.versions, c.entities))
+        {
+            let subgraph = match SubgraphDeploymentId::new(candidate.subgraph.clone()) {
+                Ok(subgraph) => subgraph,
+                Err(_) => continue,
+            };
+            let table = candidate.table.clone();
+            let id = subgraph.clone();
+            let res = self
+                .store
+                .with_conn(move |conn, _| {
+                    make_account_like(conn, &id, &table).map_err(CancelableError::from)
+                })
+                .await;
+            match res {
+                Ok(()) => {
+                    // Forget the cached layout so that queries pick the
+                    // change up
+                    self.store.forget_deployment(&subgraph);
+                    info!(self.logger, "Table is account-like";
+                          "deployment" => subgraph.as_str(),
+                          "table" => &candidate.table,
+                          "versions" => candidate.versions,
+                          "entities" => candidate.entities);
+                }
+                Err(e) => {
+                    warn!(self.logger, "Failed to make table account-like";
+                          "deployment" => subgraph.as_str(),
+                          "table" => &candidate.table,
+                          "error" => e.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn account_like_candidates(conn: &PgConnection) -> Result<Vec<AccountLikeCandidate>, Error> {
+    // A negative `n_distinct` is the number of distinct values as a
+    // fraction of the number of rows
+    let query = "
+        select ds.subgraph, c.relname::text as table,
+               c.reltuples::bigint as versions,
+               (case when s.n_distinct >= 0 then s.n_distinct
+                     else -s.n_distinct * c.reltuples end)::bigint as entities
+          from deployment_schemas ds, pg_namespace n, pg_class c, pg_stats s
+         where n.nspname = ds.name
+           and c.relnamespace = n.oid
+           and c.relkind = 'r'
+           and s.schemaname = n.nspname
+           and s.tablename = c.relname
+           and s.attname = 'id'
+           and ds.version = 'relational'
+           and ds.subgraph <> 'subgraphs'
+           and not c.relname = any(ds.account_like)
+           and c.reltuples >= $1";
+    Ok(sql_query(query)
+        .bind::<BigInt, _>(*ACCOUNT_LIKE_MIN_VERSIONS)
+        .load::<AccountLikeCandidate>(conn)?)
+}
+
+/// Create the index that account-like tables need without blocking writes
+/// to the table, and then mark the table as account-like
+fn make_account_like(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    table: &str,
+) -> Result<(), Error> {
+    let layout = match Storage::new(conn, subgraph)? {
+        Storage::Relational(layout) => layout,
+        Storage::Json(_) => return Ok(()),
+    };
+    let table = layout
+        .table(&SqlName::verbatim(table.to_owned()))
+        .ok_or_else(|| format_err!("unknown table {}", table))?;
+    conn.batch_execute(&table.account_like_index_ddl(true))?;
+    e::set_account_like(conn, subgraph, table.name.as_str())?;
+    Ok(())
+}
+
+/// Whether a table with `versions` rows that hold `entities` different
+/// entities is account-like
+fn is_account_like(versions: i64, entities: i64) -> bool {
+    versions >= *ACCOUNT_LIKE_MIN_VERSIONS
+        && (entities as f64) <= *ACCOUNT_LIKE_MAX_UNIQUE_RATIO * versions as f64
+}
+
+fn table_stats(conn: &PgConnection) -> Result<Vec<TableStats>, Error> {
+    let query = "
+        select ds.subgraph, s.schemaname::text as schema, s.relname::text as table,
+               s.n_live_tup as live_rows, s.n_dead_tup as dead_rows,
+               s.n_tup_ins + s.n_tup_upd + s.n_tup_del as writes,
+               s.n_mod_since_analyze as mods_since_analyze
+          from pg_stat_user_tables s, deployment_schemas ds
+         where s.schemaname = ds.name";
+    Ok(sql_query(query).load::<TableStats>(conn)?)
+}
+
+fn maintain_table(
+    conn: &PgConnection,
+    table: &str,
+    vacuum_threshold: Option<i64>,
+    analyze: bool,
+) -> Result<(), Error> {
+    if let Some(threshold) = vacuum_threshold {
+        // Vacuum and analyze after a fixed number of changed rows, regardless
+        // of the size of the table
+        conn.batch_execute(&format!(
+            "alter table {} set (autovacuum_vacuum_scale_factor = 0, \
+                                 autovacuum_vacuum_threshold = {}, \
+                                 autovacuum_analyze_scale_factor = 0, \
+                                 autovacuum_analyze_threshold = {})",
+            table, threshold, threshold
+        ))?;
+    }
+    if analyze {
+        conn.batch_execute(&format!("analyze {}", table))?;
+    }
+    Ok(())
+}
+
+/// The number of changed rows after which autovacuum should process a table
+/// with `live_rows` rows that saw `writes` row writes during the last
+/// maintenance interval, so that it gets vacuumed about once per interval.
+/// Returns `None` for tables that are small enough for the default settings.
+fn vacuum_threshold(live_rows: i64, writes: i64) -> Option<i64> {
+    if live_rows < *MAINTENANCE_MIN_ROWS {
+        return None;
+    }
+    // Postgres' default is to vacuum once 20% of the table has changed; we
+    // never wait longer than that
+    Some(writes.max(MIN_VACUUM_THRESHOLD).min(live_rows / 5))
+}
+
+/// Whether a table changed so much since it was last analyzed that its
+/// statistics can not be trusted any more. That happens after a deployment
+/// synced lots of blocks, and autovacuum might not get to the table in time
+fn needs_analyze(live_rows: i64, mods_since_analyze: i64) -> bool {
+    live_rows >= *MAINTENANCE_MIN_ROWS && mods_since_analyze > live_rows / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds() {
+        // Small tables are left alone
+        assert_eq!(None, vacuum_threshold(1_000, 500));
+        assert!(!needs_analyze(1_000, 100_000));
+
+        assert_eq!(Some(MIN_VACUUM_THRESHOLD), vacuum_threshold(1_000_000, 0));
+        assert_eq!(Some(50_000), vacuum_threshold(1_000_000, 50_000));
+        assert_eq!(Some(200_000), vacuum_threshold(1_000_000, 5_000_000));
+
+        assert!(!needs_analyze(1_000_000, 500_000));
+        assert!(needs_analyze(1_000_000, 500_001));
+    }
+
+    #[test]
+    fn account_like() {
+        assert!(!is_account_like(1_000, 1));
+        assert!(is_account_like(1_000_000, 100));
+        assert!(is_account_like(1_000_000, 10_000));
+        assert!(!is_account_like(1_000_000, 10_001));
+    }
+}
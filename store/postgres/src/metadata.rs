@@ -1,11 +1,13 @@
 //! Utilities for dealing with subgraph metadata
 use diesel::pg::PgConnection;
 use diesel::prelude::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::sql_query;
+use diesel::sql_types::Text;
 
 use graph::data::subgraph::schema::SubgraphManifestEntity;
 use graph::prelude::{
-    bigdecimal::ToPrimitive, format_err, web3::types::H256, BigDecimal, EthereumBlockPointer,
-    Schema, StoreError, SubgraphDeploymentId,
+    bigdecimal::ToPrimitive, format_err, web3::types::H256, BigDecimal, BlockNumber,
+    EthereumBlockPointer, Schema, StoreError, SubgraphDeploymentId,
 };
 
 // Diesel tables for some of the metadata
@@ -32,6 +34,21 @@ table! {
         graft_base -> Nullable<Text>,
         graft_block_hash -> Nullable<Binary>,
         graft_block_number -> Nullable<Numeric>,
+        non_fatal_errors -> Nullable<Array<Text>>,
+        migration_base -> Nullable<Text>,
+        block_range -> Range<Integer>,
+    }
+}
+
+table! {
+    subgraphs.subgraph_error (vid) {
+        vid -> BigInt,
+        id -> Text,
+        subgraph_id -> Text,
+        message -> Text,
+        block_number -> Nullable<Numeric>,
+        block_hash -> Nullable<Binary>,
+        handler -> Nullable<Text>,
         block_range -> Range<Integer>,
     }
 }
@@ -117,6 +134,83 @@ pub fn deployment_graft(
     }
 }
 
+/// The deployment whose storage the given subgraph takes over, if any
+pub fn deployment_migration_base(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<SubgraphDeploymentId>, StoreError> {
+    use subgraph_deployment as sd;
+
+    if id.is_meta() {
+        return Ok(None);
+    }
+    sd::table
+        .select(sd::migration_base)
+        .filter(sd::id.eq(id.as_str()))
+        .first::<Option<String>>(conn)?
+        .map(|base| {
+            SubgraphDeploymentId::new(base.clone()).map_err(|_| {
+                StoreError::Unknown(format_err!(
+                    "the base of a migration must be a valid subgraph id but is `{}`",
+                    base
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Hand the storage, the dynamic data sources and the indexing progress
+/// of `base` to `id`, which must have just been created
+pub fn take_over_deployment(
+    conn: &PgConnection,
+    base: &SubgraphDeploymentId,
+    id: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    for query in &[
+        "update deployment_schemas set subgraph = $1 where subgraph = $2",
+        "update subgraphs.dynamic_ethereum_contract_data_source
+            set deployment = $1
+          where deployment = $2",
+        "update subgraphs.subgraph_deployment d
+            set synced = b.synced,
+                earliest_ethereum_block_hash = b.earliest_ethereum_block_hash,
+                earliest_ethereum_block_number = b.earliest_ethereum_block_number,
+                latest_ethereum_block_hash = b.latest_ethereum_block_hash,
+                latest_ethereum_block_number = b.latest_ethereum_block_number,
+                entity_count = b.entity_count
+           from subgraphs.subgraph_deployment b
+          where d.id = $1 and b.id = $2
+            and upper_inf(d.block_range) and upper_inf(b.block_range)",
+    ] {
+        sql_query(*query)
+            .bind::<Text, _>(id.as_str())
+            .bind::<Text, _>(base.as_str())
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// The earliest and the latest block of the given subgraph
+pub fn deployment_blocks(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(Option<u64>, Option<u64>), StoreError> {
+    use subgraph_deployment as sd;
+
+    if id.is_meta() {
+        return Ok((None, None));
+    }
+    let (earliest, latest) = sd::table
+        .select((
+            sd::earliest_ethereum_block_number,
+            sd::latest_ethereum_block_number,
+        ))
+        .filter(sd::id.eq(id.as_str()))
+        .first::<(Option<BigDecimal>, Option<BigDecimal>)>(conn)?;
+    let number = |n: Option<BigDecimal>| n.and_then(|n| n.to_u64());
+    Ok((number(earliest), number(latest)))
+}
+
 pub fn subgraph_schema(
     conn: &PgConnection,
     id: SubgraphDeploymentId,
@@ -164,3 +258,43 @@ pub fn subgraph_network(
         .map(|x| x.flatten())
         .map_err(|e| e.into())
 }
+
+/// Return the ids of the non-fatal errors recorded for the given subgraph
+pub fn non_fatal_errors(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<String>, StoreError> {
+    use subgraph_deployment as sd;
+
+    if id.is_meta() {
+        return Ok(vec![]);
+    }
+    let errors = sd::table
+        .select(sd::non_fatal_errors)
+        .filter(sd::id.eq(id.as_str()))
+        .first::<Option<Vec<String>>>(conn)?;
+    Ok(errors.unwrap_or_default())
+}
+
+/// Check whether the given subgraph has any non-fatal errors. If `block`
+/// is given, only errors that happened at or before that block are
+/// considered
+pub fn has_non_fatal_errors(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: Option<BlockNumber>,
+) -> Result<bool, StoreError> {
+    use subgraph_error as e;
+
+    let ids = non_fatal_errors(conn, id)?;
+    if ids.is_empty() {
+        return Ok(false);
+    }
+
+    let mut query = e::table.filter(e::id.eq_any(ids)).into_boxed();
+    if let Some(block) = block {
+        query = query.filter(e::block_number.le(BigDecimal::from(block)));
+    }
+    let count = query.count().get_result::<i64>(conn)?;
+    Ok(count > 0)
+}
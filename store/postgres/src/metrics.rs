@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use graph::prelude::{Counter, Gauge, MetricsRegistry, PrometheusError, SubgraphDeploymentId};
+
+/// Metrics for the store operations of one deployment. They are global
+/// metrics, so that all stores that access a deployment update the same
+/// ones.
+pub(crate) struct DeploymentStoreMetrics {
+    entity_reads: Counter,
+    entity_writes: Counter,
+    blocks_written: Counter,
+    transact_secs: Counter,
+    pending_writes: Gauge,
+    reverts: Counter,
+    live_rows: Gauge,
+    dead_rows: Gauge,
+}
+
+impl DeploymentStoreMetrics {
+    pub fn new(
+        registry: &dyn MetricsRegistry,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<Self, PrometheusError> {
+        let counter = |name: &str, help: &str| {
+            registry.global_counter(format!("{}_store_{}", subgraph, name), help.to_owned())
+        };
+        let gauge = |name: &str, help: &str| {
+            registry.global_gauge(format!("{}_store_{}", subgraph, name), help.to_owned())
+        };
+        Ok(DeploymentStoreMetrics {
+            entity_reads: counter("entity_reads", "number of entities loaded by id")?,
+            entity_writes: counter("entity_writes", "number of entity modifications written")?,
+            blocks_written: counter("blocks_written", "number of blocks written")?,
+            transact_secs: counter(
+                "transact_secs",
+                "total time spent in transactions that write blocks",
+            )?,
+            pending_writes: gauge(
+                "pending_writes",
+                "number of block writes waiting for or holding a connection",
+            )?,
+            reverts: counter("reverts", "number of blocks reverted")?,
+            live_rows: gauge("live_rows", "estimated number of live rows in all tables")?,
+            dead_rows: gauge(
+                "dead_rows",
+                "estimated number of dead rows in all tables that wait to be vacuumed",
+            )?,
+        })
+    }
+
+    pub fn entities_read(&self, count: usize) {
+        self.entity_reads.inc_by(count as f64);
+    }
+
+    /// Count a block write as pending until the returned guard is dropped.
+    pub fn pending_write(&self) -> PendingWrite {
+        self.pending_writes.inc();
+        PendingWrite(self.pending_writes.clone())
+    }
+
+    pub fn blocks_written(&self, blocks: usize, modifications: usize, duration: Duration) {
+        self.blocks_written.inc_by(blocks as f64);
+        self.entity_writes.inc_by(modifications as f64);
+        self.transact_secs.inc_by(duration.as_secs_f64());
+    }
+
+    pub fn block_reverted(&self) {
+        self.reverts.inc();
+    }
+
+    pub fn table_rows(&self, live: i64, dead: i64) {
+        self.live_rows.set(live as f64);
+        self.dead_rows.set(dead as f64);
+    }
+}
+
+pub(crate) struct PendingWrite(Gauge);
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
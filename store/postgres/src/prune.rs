@@ -0,0 +1,104 @@
+//! Remove old entity versions from deployments whose manifest limits how
+//! much history they keep through `indexerHints.prune`. Versions that
+//! stopped being current more than `history_blocks` blocks before the
+//! deployment's latest block are deleted, and queries for blocks before
+//! that are rejected from then on. Since `history_blocks` is never smaller
+//! than the reorg threshold, reverts never need the deleted versions.
+use lazy_static::lazy_static;
+use std::time::Duration;
+
+use graph::prelude::{
+    info, o, tokio, warn, BlockNumber, CancelableError, Error, Logger, SubgraphDeploymentId,
+};
+
+use crate::entities as e;
+use crate::metadata;
+use crate::store::Store;
+
+lazy_static! {
+    /// How often to prune deployments, in seconds
+    static ref PRUNE_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_PRUNE_INTERVAL")
+            .unwrap_or("3600".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_PRUNE_INTERVAL")
+    );
+}
+
+/// Periodically prunes the deployments that ask for it
+pub struct Pruner {
+    logger: Logger,
+    store: Store,
+}
+
+impl Pruner {
+    pub fn new(logger: &Logger, store: Store) -> Self {
+        Pruner {
+            logger: logger.new(o!("component" => "Pruner")),
+            store,
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            tokio::time::delay_for(*PRUNE_INTERVAL).await;
+            if let Err(e) = self.prune().await {
+                warn!(self.logger, "Failed to prune deployments"; "error" => e.to_string());
+            }
+        }
+    }
+
+    async fn prune(&self) -> Result<(), Error> {
+        let deployments = self
+            .store
+            .with_conn(|conn, _| e::pruned_deployments(conn).map_err(CancelableError::from))
+            .await?;
+
+        for (id, history_blocks, earliest_block) in deployments {
+            let deployment = id.clone();
+            let pruned = self
+                .store
+                .with_conn(move |conn, _| {
+                    prune_deployment(conn, &deployment, history_blocks, earliest_block)
+                        .map_err(CancelableError::from)
+                })
+                .await;
+            match pruned {
+                Ok(Some((earliest_block, count))) => {
+                    info!(self.logger, "Pruned deployment";
+                          "deployment" => id.as_str(),
+                          "earliest_block" => earliest_block,
+                          "versions" => count);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(self.logger, "Failed to prune deployment";
+                          "deployment" => id.as_str(),
+                          "error" => e.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prune `id` so that it keeps `history_blocks` blocks of history. Returns
+/// the new earliest block and how many versions were deleted, or `None` if
+/// there was nothing to do
+fn prune_deployment(
+    conn: &diesel::pg::PgConnection,
+    id: &SubgraphDeploymentId,
+    history_blocks: BlockNumber,
+    earliest_block: BlockNumber,
+) -> Result<Option<(BlockNumber, usize)>, Error> {
+    let latest_block = match metadata::deployment_blocks(conn, id)?.1 {
+        Some(latest_block) => latest_block as BlockNumber,
+        None => return Ok(None),
+    };
+    let cutoff = latest_block - history_blocks;
+    if cutoff <= earliest_block {
+        return Ok(None);
+    }
+    let count = e::prune(conn, id, cutoff)?;
+    Ok(Some((cutoff, count)))
+}
@@ -0,0 +1,144 @@
+//! The allowlists of deployments that only run queries that were registered
+//! ahead of time. A deployment is in allowlist mode when it has a row in
+//! `query_allowlist_deployments`; the queries it runs are in
+//! `query_allowlist`, identified by their hash. The allowlist is kept even
+//! when allowlist mode is turned off, so that it can be turned back on
+//! without registering the queries again.
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Bool, Text};
+use diesel::{sql_query, RunQueryDsl};
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use graph::prelude::{AllowedQuery, Error, SubgraphDeploymentId};
+
+lazy_static! {
+    /// How long each store uses the allowlist of a deployment before it
+    /// reads it again, in seconds. Changes made through another node take
+    /// up to that long to take effect
+    pub(crate) static ref ALLOWLIST_REFRESH_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_QUERY_ALLOWLIST_REFRESH_INTERVAL")
+            .unwrap_or("30".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_QUERY_ALLOWLIST_REFRESH_INTERVAL")
+    );
+}
+
+#[derive(QueryableByName)]
+struct Enabled {
+    #[sql_type = "Bool"]
+    enabled: bool,
+}
+
+#[derive(QueryableByName)]
+struct Hash {
+    #[sql_type = "Text"]
+    hash: String,
+}
+
+#[derive(QueryableByName)]
+struct AllowedQueryRow {
+    #[sql_type = "Text"]
+    hash: String,
+    #[sql_type = "Text"]
+    query: String,
+    #[sql_type = "Text"]
+    added_at: String,
+}
+
+/// The hashes of the queries that `deployment` runs, or `None` if it is not
+/// in allowlist mode and runs any query
+pub(crate) fn allowlist(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+) -> Result<Option<HashSet<String>>, Error> {
+    let enabled = sql_query(
+        "select exists (select 1 from query_allowlist_deployments
+                         where deployment = $1) as enabled",
+    )
+    .bind::<Text, _>(deployment.as_str())
+    .get_result::<Enabled>(conn)?;
+    if !enabled.enabled {
+        return Ok(None);
+    }
+    let hashes = sql_query("select hash from query_allowlist where deployment = $1")
+        .bind::<Text, _>(deployment.as_str())
+        .load::<Hash>(conn)?;
+    Ok(Some(hashes.into_iter().map(|row| row.hash).collect()))
+}
+
+/// Turn allowlist mode for `deployment` on or off
+pub(crate) fn set_enabled(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+    enabled: bool,
+) -> Result<(), Error> {
+    let query = if enabled {
+        "insert into query_allowlist_deployments(deployment) values ($1)
+         on conflict(deployment) do nothing"
+    } else {
+        "delete from query_allowlist_deployments where deployment = $1"
+    };
+    sql_query(query)
+        .bind::<Text, _>(deployment.as_str())
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Put `query` with `hash` on the allowlist of `deployment`. Adding a
+/// query that is already on it does nothing
+pub(crate) fn add(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+    hash: &str,
+    query: &str,
+) -> Result<(), Error> {
+    sql_query(
+        "insert into query_allowlist(deployment, hash, query)
+         values ($1, $2, $3)
+         on conflict(deployment, hash) do nothing",
+    )
+    .bind::<Text, _>(deployment.as_str())
+    .bind::<Text, _>(hash)
+    .bind::<Text, _>(query)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Take the query with `hash` off the allowlist of `deployment`. Returns
+/// `false` if it was not on it
+pub(crate) fn remove(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+    hash: &str,
+) -> Result<bool, Error> {
+    let rows = sql_query("delete from query_allowlist where deployment = $1 and hash = $2")
+        .bind::<Text, _>(deployment.as_str())
+        .bind::<Text, _>(hash)
+        .execute(conn)?;
+    Ok(rows > 0)
+}
+
+/// The queries on the allowlist of `deployment`, oldest first
+pub(crate) fn list(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+) -> Result<Vec<AllowedQuery>, Error> {
+    let rows = sql_query(
+        "select hash, query, added_at::text as added_at
+           from query_allowlist
+          where deployment = $1
+          order by added_at, hash",
+    )
+    .bind::<Text, _>(deployment.as_str())
+    .load::<AllowedQueryRow>(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|row| AllowedQuery {
+            hash: row.hash,
+            query: row.query,
+            added_at: row.added_at,
+        })
+        .collect())
+}
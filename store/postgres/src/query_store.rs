@@ -29,13 +29,28 @@ impl QueryStoreTrait for QueryStore {
         &self,
         query: EntityQuery,
     ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError> {
+        self.store.record_query(&query.subgraph_id);
         let conn = self
             .store
-            .get_entity_conn(&query.subgraph_id, self.replica_id)
+            .get_query_conn(&query.subgraph_id, self.replica_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
         self.store.execute_query(&conn, query)
     }
 
+    fn count_children(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        windows: Vec<EntityWindow>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, i64>, QueryExecutionError> {
+        self.store.record_query(subgraph_id);
+        let conn = self
+            .store
+            .get_query_conn(subgraph_id, self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        conn.count_children(windows, block)
+    }
+
     fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
         assert!(self.for_subscription);
         assert_eq!(self.replica_id, ReplicaId::Main);
@@ -21,21 +21,23 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crate::relational_queries::{
-    self as rq, ClampRangeQuery, ConflictingEntityQuery, DeleteByPrefixQuery,
-    DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, FilterCollection, FilterQuery,
-    FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
+    self as rq, ChildCount, ClampRangeQuery, ConflictingEntityQuery, CountChildrenQuery,
+    DeleteByPrefixQuery, DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, FilterCollection,
+    FilterQuery, FindChangesQuery, FindManyQuery, FindQuery, InsertQuery, RevertClampQuery,
+    RevertRemoveQuery, UpdateQuery,
 };
-use graph::data::graphql::ext::{DocumentExt, ObjectTypeExt};
+use graph::data::graphql::ext::{DocumentExt, FieldExt, ObjectTypeExt};
 use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME};
 use graph::data::store::BYTES_SCALAR;
 use graph::data::subgraph::schema::{
     DynamicEthereumContractDataSourceEntity, POI_OBJECT, POI_TABLE,
 };
+use graph::data::subgraph::{HintValue, IndexMethod};
 use graph::prelude::{
-    format_err, info, BlockNumber, Entity, EntityChange, EntityChangeOperation, EntityCollection,
-    EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
-    QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, Value, ValueType,
-    BLOCK_NUMBER_MAX,
+    format_err, hex, info, AttributeCipher, BlockNumber, Entity, EntityChange,
+    EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityRange,
+    EntityWindow, EthereumBlockPointer, IndexHint, Logger, QueryExecutionError, StoreError,
+    StoreEvent, SubgraphDeploymentId, Value, ValueType, BLOCK_NUMBER_MAX,
 };
 
 use crate::block_range::{BLOCK_RANGE_COLUMN, BLOCK_UNVERSIONED};
@@ -180,6 +182,81 @@ pub struct Layout {
     pub enums: EnumMap,
     /// The query to count all entities
     pub count_query: String,
+    /// The attributes that are encrypted, if any
+    pub encryption: Option<Encryption>,
+}
+
+/// The attributes of a layout whose values an `AttributeCipher` encrypts
+#[derive(Clone)]
+pub struct Encryption {
+    cipher: Arc<dyn AttributeCipher>,
+    /// The encrypted attributes of each entity type
+    attributes: HashMap<String, HashSet<String>>,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("attributes", &self.attributes)
+            .finish()
+    }
+}
+
+impl Encryption {
+    fn encrypt(&self, subgraph: &SubgraphDeploymentId, value: Value) -> Result<Value, StoreError> {
+        match value {
+            Value::String(s) => {
+                let ciphertext = self.cipher.encrypt(subgraph, s.as_bytes())?;
+                Ok(Value::String(hex::encode(ciphertext)))
+            }
+            Value::Bytes(b) => {
+                let ciphertext = self.cipher.encrypt(subgraph, b.as_slice())?;
+                Ok(Value::Bytes(ciphertext.as_slice().into()))
+            }
+            Value::List(values) => values
+                .into_iter()
+                .map(|value| self.encrypt(subgraph, value))
+                .collect::<Result<_, _>>()
+                .map(Value::List),
+            value => Ok(value),
+        }
+    }
+
+    /// Decrypt the JSON representation of a value that the database
+    /// returns for a column of type `column_type`
+    fn decrypt(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        column_type: &ColumnType,
+        json: serde_json::Value,
+    ) -> Result<serde_json::Value, StoreError> {
+        use serde_json::Value as j;
+
+        let invalid = |e: hex::FromHexError| {
+            StoreError::Unknown(format_err!("encrypted value is not hex: {}", e))
+        };
+        match (json, column_type) {
+            (j::String(s), ColumnType::String) => {
+                let plaintext = self
+                    .cipher
+                    .decrypt(subgraph, &hex::decode(s).map_err(invalid)?)?;
+                String::from_utf8(plaintext).map(j::String).map_err(|e| {
+                    StoreError::Unknown(format_err!("decrypted value is not UTF-8: {}", e))
+                })
+            }
+            (j::String(s), ColumnType::Bytes) => {
+                let ciphertext = hex::decode(s.trim_start_matches("\\x")).map_err(invalid)?;
+                let plaintext = self.cipher.decrypt(subgraph, &ciphertext)?;
+                Ok(j::String(format!("\\x{}", hex::encode(plaintext))))
+            }
+            (j::Array(values), _) => values
+                .into_iter()
+                .map(|value| self.decrypt(subgraph, column_type, value))
+                .collect::<Result<_, _>>()
+                .map(j::Array),
+            (json, _) => Ok(json),
+        }
+    }
 }
 
 impl Layout {
@@ -213,12 +290,13 @@ impl Layout {
             )
             .collect::<Result<_, _>>()?;
 
-        // List of all object types that are not __SCHEMA__
+        // List of all object types that are not __SCHEMA__ or the type
+        // of the mutations
         let object_types = schema
             .document
             .get_object_type_definitions()
             .into_iter()
-            .filter(|obj_type| obj_type.name != SCHEMA_TYPE_NAME)
+            .filter(|obj_type| obj_type.name != SCHEMA_TYPE_NAME && !obj_type.is_mutation_type())
             .collect::<Vec<_>>();
 
         // For interfaces, check that all implementors use the same IdType
@@ -299,6 +377,7 @@ impl Layout {
             tables,
             enums,
             count_query,
+            encryption: None,
         })
     }
 
@@ -318,6 +397,7 @@ impl Layout {
                     column_type: ColumnType::Bytes,
                     fulltext_fields: None,
                     is_reference: false,
+                    is_unindexed: false,
                 },
                 Column {
                     name: SqlName::from(PRIMARY_KEY_COLUMN),
@@ -328,8 +408,10 @@ impl Layout {
                     column_type: ColumnType::String,
                     fulltext_fields: None,
                     is_reference: false,
+                    is_unindexed: false,
                 },
             ],
+            is_account_like: false,
             /// The position of this table in all the tables for this layout; this
             /// is really only needed for the tests to make the names of indexes
             /// predictable
@@ -414,6 +496,15 @@ impl Layout {
         metadata.revert_metadata(conn, &self.subgraph, block_to_revert)?;
         info!(logger, "Rewound subgraph to block {}", block.number;
               "time_ms" => start.elapsed().as_millis());
+
+        // 4. Update the planner statistics for the copied data; autovacuum
+        // would only get to that much later
+        let start = Instant::now();
+        for table in self.tables.values() {
+            conn.batch_execute(&format!("analyze {}", table.qualified_name))?;
+        }
+        info!(logger, "Analyzed {} tables", self.tables.len();
+              "time_ms" => start.elapsed().as_millis());
         Ok(())
     }
 
@@ -434,6 +525,52 @@ impl Layout {
             .collect()
     }
 
+    /// Determine if the tables of `base` can be changed in place to store
+    /// the data for `self`. That is only possible if `self` keeps all types,
+    /// attributes and enums of `base` exactly as they are, and only adds
+    /// types, enums, and nullable attributes. Returns a list of errors if
+    /// the tables can not be changed; an empty vector indicates that they
+    /// can
+    pub fn can_migrate_from(&self, base: &Layout) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (name, values) in &base.enums {
+            match self.enums.get(name) {
+                Some(dst_values) if dst_values == values => (),
+                Some(_) => errors.push(format!("The values of the enum {} were changed", name)),
+                None => errors.push(format!("The enum {} was removed", name)),
+            }
+        }
+        for src in base.tables.values() {
+            match self.table(&src.name) {
+                Some(dst) => errors.extend(dst.can_migrate_from(src)),
+                None => errors.push(format!("The type {} was removed", src.object)),
+            }
+        }
+        errors.sort();
+        errors
+    }
+
+    /// Generate the DDL that changes the tables of `base` so that they
+    /// store the data for `self`. The changes must have been checked with
+    /// `can_migrate_from`
+    pub fn migration_ddl(&self, base: &Layout) -> Result<String, fmt::Error> {
+        let mut out = String::new();
+        for (name, values) in &self.enums {
+            if !base.enums.contains_key(name) {
+                self.enum_ddl(&mut out, name, values)?;
+            }
+        }
+        let mut tables = self.tables.values().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.position);
+        for table in tables {
+            match base.table(&table.name) {
+                Some(src) => table.migration_ddl(&mut out, src, self)?,
+                None => table.as_ddl(&mut out, self)?,
+            }
+        }
+        Ok(out)
+    }
+
     /// Generate the DDL for the entire layout, i.e., all `create table`
     /// and `create index` etc. statements needed in the database schema
     ///
@@ -444,19 +581,7 @@ impl Layout {
 
         // Output enums first
         for (name, values) in &self.enums {
-            let mut sep = "";
-            let name = SqlName::from(name.as_str());
-            write!(
-                out,
-                "create type {}.{}\n    as enum (",
-                self.catalog.schema,
-                name.quoted()
-            )?;
-            for value in values.iter() {
-                write!(out, "{}'{}'", sep, value)?;
-                sep = ", "
-            }
-            writeln!(out, ");")?;
+            self.enum_ddl(&mut out, name, values)?;
         }
         // We sort tables here solely because the unit tests rely on
         // 'create table' statements appearing in a fixed order
@@ -470,6 +595,97 @@ impl Layout {
         Ok(out)
     }
 
+    fn enum_ddl(&self, out: &mut String, name: &str, values: &BTreeSet<String>) -> fmt::Result {
+        let mut sep = "";
+        let name = SqlName::from(name);
+        write!(
+            out,
+            "create type {}.{}\n    as enum (",
+            self.catalog.schema,
+            name.quoted()
+        )?;
+        for value in values.iter() {
+            write!(out, "{}'{}'", sep, value)?;
+            sep = ", "
+        }
+        writeln!(out, ");")
+    }
+
+    /// Generate the DDL for the additional indexes that the `indexerHints`
+    /// in a subgraph manifest ask for. Each hint becomes one multi-column
+    /// btree or trigram index, restricted to current versions of entities if
+    /// the hint asks for that, and to the rows matching its `where` clause.
+    /// Trigram indexes cover the whole string since the `ilike` and `~`
+    /// filters they serve look at all of it
+    pub fn index_hints_ddl(&self, hints: &[IndexHint]) -> Result<String, StoreError> {
+        let mut out = String::new();
+        for (i, hint) in hints.iter().enumerate() {
+            let table = self.table_for_entity(&hint.entity)?;
+            let mut columns = Vec::new();
+            for field in &hint.fields {
+                let column = table.column_for_field(field)?;
+                if hint.method == IndexMethod::Trigram {
+                    columns.push(format!("{} gin_trgm_ops", column.name.quoted()));
+                } else if column.is_text() {
+                    columns.push(format!(
+                        "left({}, {})",
+                        column.name.quoted(),
+                        STRING_PREFIX_SIZE
+                    ));
+                } else {
+                    columns.push(column.name.quoted());
+                }
+            }
+
+            let mut conditions = Vec::new();
+            if hint.current {
+                conditions.push(format!("{} @> {}", BLOCK_RANGE_COLUMN, BLOCK_NUMBER_MAX));
+            }
+            for (field, value) in &hint.filter {
+                let column = table.column_for_field(field)?;
+                let literal = match (&column.column_type, value) {
+                    (ColumnType::Bytes, HintValue::String(s))
+                    | (ColumnType::BytesId, HintValue::String(s)) => {
+                        format!("'\\x{}'", s.trim_start_matches("0x").replace("'", "''"))
+                    }
+                    (_, value) => format!("'{}'", value.to_string().replace("'", "''")),
+                };
+                conditions.push(format!("{} = {}", column.name.quoted(), literal));
+            }
+
+            out.push_str(&format!(
+                "create index hint_{table_index}_{hint_index}_{table_name}\n    \
+                 on {schema_name}.\"{table_name}\" using {method}({columns})",
+                table_index = table.position,
+                hint_index = i,
+                table_name = table.name,
+                schema_name = self.catalog.schema,
+                method = match hint.method {
+                    IndexMethod::Btree => "btree",
+                    IndexMethod::Trigram => "gin",
+                },
+                columns = columns.join(", "),
+            ));
+            if !conditions.is_empty() {
+                out.push_str(&format!("\n    where {}", conditions.join(" and ")));
+            }
+            out.push_str(";\n");
+        }
+        Ok(out)
+    }
+
+    /// Mark the tables with the given names as account-like
+    pub(crate) fn set_account_like(&mut self, tables: &[String]) {
+        for table in self.tables.values_mut() {
+            if tables
+                .iter()
+                .any(|name| name.as_str() == table.name.as_str())
+            {
+                Arc::make_mut(table).is_account_like = true;
+            }
+        }
+    }
+
     /// Find the table with the provided `name`. The name must exactly match
     /// the name of an existing table. No conversions of the name are done
     pub fn table(&self, name: &SqlName) -> Option<&Table> {
@@ -479,6 +695,70 @@ impl Layout {
             .map(|rc| rc.as_ref())
     }
 
+    /// Encrypt the attributes that `cipher` designates. Only attributes of
+    /// type `String` or `Bytes`, or lists of them, that are not the `id` and
+    /// do not reference other entities can be encrypted
+    pub fn set_cipher(&mut self, cipher: Arc<dyn AttributeCipher>) {
+        let attributes: HashMap<_, _> = self
+            .tables
+            .values()
+            .map(|table| {
+                let attributes: HashSet<_> = table
+                    .columns
+                    .iter()
+                    .filter(|column| column.is_encryptable())
+                    .filter(|column| cipher.encrypts(&self.subgraph, &table.object, &column.field))
+                    .map(|column| column.field.clone())
+                    .collect();
+                (table.object.clone(), attributes)
+            })
+            .filter(|(_, attributes)| !attributes.is_empty())
+            .collect();
+        self.encryption = if attributes.is_empty() {
+            None
+        } else {
+            Some(Encryption { cipher, attributes })
+        };
+    }
+
+    /// Encrypt the encrypted attributes of `entity`
+    fn encrypt(&self, entity_type: &str, mut entity: Entity) -> Result<Entity, StoreError> {
+        let encryption = match &self.encryption {
+            Some(encryption) => encryption,
+            None => return Ok(entity),
+        };
+        if let Some(attributes) = encryption.attributes.get(entity_type) {
+            for attribute in attributes {
+                if let Some(value) = entity.remove(attribute) {
+                    let value = encryption.encrypt(&self.subgraph, value)?;
+                    entity.insert(attribute.clone(), value);
+                }
+            }
+        }
+        Ok(entity)
+    }
+
+    /// Decrypt `json`, the value that the database returned for `column`
+    /// of `entity_type`, if the column is encrypted
+    pub(crate) fn decrypt(
+        &self,
+        entity_type: &str,
+        column: &Column,
+        json: serde_json::Value,
+    ) -> Result<serde_json::Value, StoreError> {
+        match &self.encryption {
+            Some(encryption)
+                if encryption
+                    .attributes
+                    .get(entity_type)
+                    .map_or(false, |attributes| attributes.contains(&column.field)) =>
+            {
+                encryption.decrypt(&self.subgraph, &column.column_type, json)
+            }
+            _ => Ok(json),
+        }
+    }
+
     pub fn table_for_entity(&self, entity: &str) -> Result<&Arc<Table>, StoreError> {
         self.tables
             .get(entity)
@@ -526,6 +806,37 @@ impl Layout {
         Ok(entities_for_type)
     }
 
+    pub fn find_changes(
+        &self,
+        conn: &PgConnection,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<Vec<(String, Entity)>, StoreError> {
+        if entity_types.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut tables = Vec::new();
+        for entity_type in entity_types {
+            tables.push(self.table_for_entity(entity_type)?.as_ref());
+        }
+        let mut changes = FindChangesQuery::new(tables, block)
+            .load::<EntityData>(conn)?
+            .into_iter()
+            .map(|data| {
+                let entity_type = data.entity_type();
+                data.deserialize_with_layout(self)
+                    .map(|entity| (entity_type, entity))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        changes.sort_by_cached_key(|(entity_type, entity)| {
+            (
+                entity_type.clone(),
+                entity.get("id").map(|id| id.to_string()),
+            )
+        });
+        Ok(changes)
+    }
+
     pub fn insert(
         &self,
         conn: &PgConnection,
@@ -534,6 +845,7 @@ impl Layout {
         block: BlockNumber,
     ) -> Result<(), StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
+        let entity = self.encrypt(&key.entity_type, entity)?;
         let query = InsertQuery::new(table, key, entity, block)?;
         query.execute(conn)?;
         Ok(())
@@ -546,6 +858,7 @@ impl Layout {
         entity: Entity,
     ) -> Result<(), StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
+        let entity = self.encrypt(&key.entity_type, entity)?;
         let query = InsertQuery::new(table, key, entity, BLOCK_UNVERSIONED)?;
         query.execute(conn)?;
         Ok(())
@@ -563,6 +876,37 @@ impl Layout {
             .map(|data| data.entity))
     }
 
+    /// Count the children in `windows` at `block`, and return the number
+    /// of children for each parent id. Parents without children are not
+    /// in the result
+    pub fn count_children(
+        &self,
+        conn: &PgConnection,
+        windows: Vec<EntityWindow>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, i64>, QueryExecutionError> {
+        let mut counts = BTreeMap::new();
+        if windows.is_empty() {
+            return Ok(counts);
+        }
+
+        let query = CountChildrenQuery::new(self, windows, block)?;
+        let query_clone = query.clone();
+        let child_counts = query.load::<ChildCount>(conn).map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "{}, query = {:?}",
+                e,
+                debug_query(&query_clone).to_string()
+            ))
+        })?;
+        // Children of an interface live in several tables, and we get one
+        // count per table
+        for child_count in child_counts {
+            *counts.entry(child_count.parent_id).or_insert(0) += child_count.count;
+        }
+        Ok(counts)
+    }
+
     /// order is a tuple (attribute, value_type, direction)
     pub fn query<T: crate::relational_queries::FromEntityData>(
         &self,
@@ -636,6 +980,7 @@ impl Layout {
     ) -> Result<(), StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
         ClampRangeQuery::new(table, key, block).execute(conn)?;
+        let entity = self.encrypt(&key.entity_type, entity)?;
         let query = InsertQuery::new(table, key, entity, block)?;
         query.execute(conn)?;
         Ok(())
@@ -666,6 +1011,7 @@ impl Layout {
                 entity.insert(column.field.clone(), Value::Null);
             }
         }
+        let entity = self.encrypt(&key.entity_type, entity)?;
         let query = UpdateQuery::new(table, key, &entity)?;
         Ok(query.execute(conn)?)
     }
@@ -744,6 +1090,34 @@ impl Layout {
         Ok((StoreEvent::new(changes), count))
     }
 
+    /// Delete all entity versions that stopped being current before
+    /// `earliest_block`. Afterwards, queries for blocks before
+    /// `earliest_block` can not be answered correctly any more, but queries
+    /// for later blocks, and reverts to them, are not affected. Returns the
+    /// number of versions that were deleted
+    pub fn prune(
+        &self,
+        conn: &PgConnection,
+        earliest_block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        assert!(!self.subgraph.is_meta());
+
+        let mut count = 0;
+        for table in self.tables.values() {
+            let query = format!(
+                "delete from {}.\"{}\" \
+                  where coalesce(upper({}), {}) <= {}",
+                self.catalog.schema,
+                table.name,
+                BLOCK_RANGE_COLUMN,
+                BLOCK_NUMBER_MAX,
+                earliest_block
+            );
+            count += diesel::sql_query(query).execute(conn)?;
+        }
+        Ok(count)
+    }
+
     /// Revert the metadata (dynamic data sources and related entities) for
     /// the given `subgraph`. This function can only be called on the `Layout`
     /// for the metadata subgraph.
@@ -952,6 +1326,9 @@ pub struct Column {
     pub column_type: ColumnType,
     pub fulltext_fields: Option<HashSet<String>>,
     is_reference: bool,
+    /// Whether the attribute is marked `@unindexed` in the schema; such
+    /// columns can not be filtered or sorted by and have no index
+    is_unindexed: bool,
 }
 
 impl Column {
@@ -987,6 +1364,7 @@ impl Column {
             field_type: field.field_type.clone(),
             fulltext_fields: None,
             is_reference,
+            is_unindexed: field.is_unindexed(),
         })
     }
 
@@ -1001,6 +1379,7 @@ impl Column {
             column_type: ColumnType::TSVector(def.config.clone()),
             fulltext_fields: Some(def.included_fields.clone()),
             is_reference: false,
+            is_unindexed: false,
         })
     }
 
@@ -1047,6 +1426,16 @@ impl Column {
         self.is_reference
     }
 
+    /// Whether an `AttributeCipher` can encrypt the values of this column
+    pub fn is_encryptable(&self) -> bool {
+        match self.column_type {
+            ColumnType::String | ColumnType::Bytes => {
+                !self.is_primary_key() && !self.is_reference() && !self.is_fulltext()
+            }
+            _ => false,
+        }
+    }
+
     pub fn is_primary_key(&self) -> bool {
         self.name.as_str() == PRIMARY_KEY_COLUMN
     }
@@ -1124,6 +1513,14 @@ pub struct Table {
     pub qualified_name: SqlName,
 
     pub columns: Vec<Column>,
+
+    /// Whether most rows in this table are old versions of comparatively
+    /// few entities, like account balances that change in every block.
+    /// Queries against such tables use a different block range clause
+    /// that lets them skip old versions with the index created by
+    /// `account_like_index_ddl`
+    pub(crate) is_account_like: bool,
+
     /// The position of this table in all the tables for this layout; this
     /// is really only needed for the tests to make the names of indexes
     /// predictable
@@ -1155,11 +1552,29 @@ impl Table {
             name: table_name.clone(),
             qualified_name: SqlName::qualified_name(&catalog.schema, &table_name),
             columns,
+            is_account_like: false,
             position,
         };
         Ok(table)
     }
 
+    /// The DDL for the index that account-like tables need. It sorts the
+    /// versions of each entity by when they stopped being current, so that
+    /// finding the version that is current at a recent block only needs to
+    /// look at the few versions that ended after it
+    pub(crate) fn account_like_index_ddl(&self, concurrently: bool) -> String {
+        format!(
+            "create index {concurrently}if not exists acct_{table_index}_{table_name}\n    \
+             on {qualified_name} using btree(id, coalesce(upper({block_range}), {block_max}));\n",
+            concurrently = if concurrently { "concurrently " } else { "" },
+            table_index = self.position,
+            table_name = self.name,
+            qualified_name = self.qualified_name,
+            block_range = BLOCK_RANGE_COLUMN,
+            block_max = BLOCK_NUMBER_MAX
+        )
+    }
+
     /// Find the column `name` in this table. The name must be in snake case,
     /// i.e., use SQL conventions
     pub fn column(&self, name: &SqlName) -> Option<&Column> {
@@ -1201,6 +1616,50 @@ impl Table {
             .collect()
     }
 
+    fn can_migrate_from(&self, source: &Self) -> Vec<String> {
+        let kept = source.columns.iter().filter_map(|scol| {
+            match self.columns.iter().find(|dcol| dcol.name == scol.name) {
+                None => Some(format!(
+                    "The attribute {}.{} was removed",
+                    self.object, scol.field
+                )),
+                Some(dcol)
+                    if dcol.column_type != scol.column_type
+                        || dcol.is_list() != scol.is_list()
+                        || dcol.is_nullable() != scol.is_nullable() =>
+                {
+                    Some(format!(
+                        "The attribute {}.{} has type {}, \
+                         but its type in the base is {}",
+                        self.object, dcol.field, dcol.field_type, scol.field_type
+                    ))
+                }
+                Some(_) => None,
+            }
+        });
+        let added = self
+            .columns
+            .iter()
+            .filter(|dcol| !source.columns.iter().any(|scol| scol.name == dcol.name))
+            .filter_map(|dcol| {
+                if dcol.is_fulltext() {
+                    Some(format!(
+                        "The fulltext field {}.{} is new, but existing entities \
+                         can not be added to it",
+                        self.object, dcol.field
+                    ))
+                } else if !dcol.is_nullable() {
+                    Some(format!(
+                        "The attribute {}.{} is new and must be nullable",
+                        self.object, dcol.field
+                    ))
+                } else {
+                    None
+                }
+            });
+        kept.chain(added).collect()
+    }
+
     pub fn primary_key(&self) -> &Column {
         self.columns
             .iter()
@@ -1263,54 +1722,110 @@ impl Table {
             schema_name = layout.catalog.schema,
             block_max = BLOCK_NUMBER_MAX)?;
 
-        // Create indexes. Skip columns whose type is an array of enum,
-        // since there is no good way to index them with Postgres 9.6.
-        // Once we move to Postgres 11, we can enable that
-        // (tracked in graph-node issue #1330)
-        for (i, column) in self
-            .columns
-            .iter()
-            .filter(|col| !(col.is_list() && col.is_enum()))
-            .enumerate()
-        {
-            let (method, index_expr) = if column.is_reference() && !column.is_list() {
-                // For foreign keys, index the key together with the block range
-                // since we almost always also have a block_range clause in
-                // queries that look for specific foreign keys
-                let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_RANGE_COLUMN);
-                ("gist", index_expr)
-            } else {
-                // Attributes that are plain strings are indexed with a BTree; but
-                // they can be too large for Postgres' limit on values that can go
-                // into a BTree. For those attributes, only index the first
-                // STRING_PREFIX_SIZE characters
-                let index_expr = if column.is_text() {
-                    format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
-                } else {
-                    column.name.quoted()
-                };
+        for column in &self.columns {
+            self.storage_ddl(out, column, layout)?;
+        }
+        for (i, column) in self.indexed_columns() {
+            self.attribute_index_ddl(out, i, column, layout)?;
+        }
+        writeln!(out)
+    }
 
-                let method = if column.is_list() || column.is_fulltext() {
-                    "gin"
-                } else {
-                    "btree"
-                };
+    /// Generate the DDL that adds the attributes of `self` that `source`
+    /// does not have to the table for `source`, together with their
+    /// indexes
+    fn migration_ddl(&self, out: &mut String, source: &Self, layout: &Layout) -> fmt::Result {
+        let is_new = |column: &Column| !source.columns.iter().any(|scol| scol.name == column.name);
+        for column in self.columns.iter().filter(|column| is_new(column)) {
+            write!(
+                out,
+                "alter table {}.\"{}\" add column {} {}{};\n",
+                layout.catalog.schema,
+                self.name,
+                column.name.quoted(),
+                column.sql_type(),
+                if column.is_list() { "[]" } else { "" }
+            )?;
+            self.storage_ddl(out, column, layout)?;
+        }
+        for (i, column) in self.indexed_columns().filter(|(_, column)| is_new(column)) {
+            self.attribute_index_ddl(out, i, column, layout)?;
+        }
+        Ok(())
+    }
 
-                (method, index_expr)
-            };
+    /// Large binary attributes that are never filtered by are mostly raw
+    /// data that does not compress well; store them out of line without
+    /// trying to compress them
+    fn storage_ddl(&self, out: &mut String, column: &Column, layout: &Layout) -> fmt::Result {
+        if column.is_unindexed && column.column_type == ColumnType::Bytes {
             write!(
                 out,
-                "create index attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
-                table_index = self.position,
-                table_name = self.name,
-                column_index = i,
-                column_name = column.name,
-                schema_name = layout.catalog.schema,
-                method = method,
-                index_expr = index_expr,
+                "alter table {}.\"{}\" alter column {} set storage external;\n",
+                layout.catalog.schema,
+                self.name,
+                column.name.quoted()
             )?;
         }
-        writeln!(out)
+        Ok(())
+    }
+
+    /// The columns that get an index, numbered for the names of their
+    /// indexes. Skip columns whose type is an array of enum, since there
+    /// is no good way to index them with Postgres 9.6. Once we move to
+    /// Postgres 11, we can enable that (tracked in graph-node issue #1330).
+    /// Also skip columns that the schema marks as `@unindexed`
+    fn indexed_columns(&self) -> impl Iterator<Item = (usize, &Column)> {
+        self.columns
+            .iter()
+            .filter(|col| !(col.is_list() && col.is_enum()))
+            .filter(|col| !col.is_unindexed)
+            .enumerate()
+    }
+
+    fn attribute_index_ddl(
+        &self,
+        out: &mut String,
+        i: usize,
+        column: &Column,
+        layout: &Layout,
+    ) -> fmt::Result {
+        let (method, index_expr) = if column.is_reference() && !column.is_list() {
+            // For foreign keys, index the key together with the block range
+            // since we almost always also have a block_range clause in
+            // queries that look for specific foreign keys
+            let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_RANGE_COLUMN);
+            ("gist", index_expr)
+        } else {
+            // Attributes that are plain strings are indexed with a BTree; but
+            // they can be too large for Postgres' limit on values that can go
+            // into a BTree. For those attributes, only index the first
+            // STRING_PREFIX_SIZE characters
+            let index_expr = if column.is_text() {
+                format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
+            } else {
+                column.name.quoted()
+            };
+
+            let method = if column.is_list() || column.is_fulltext() {
+                "gin"
+            } else {
+                "btree"
+            };
+
+            (method, index_expr)
+        };
+        write!(
+            out,
+            "create index attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
+            table_index = self.position,
+            table_name = self.name,
+            column_index = i,
+            column_name = column.name,
+            schema_name = layout.catalog.schema,
+            method = method,
+            index_expr = index_expr,
+        )
     }
 }
 
@@ -1399,6 +1914,37 @@ mod tests {
         assert_eq!(FORWARD_ENUM_SQL, sql);
     }
 
+    #[test]
+    fn unindexed_attributes() {
+        let layout = test_layout(
+            "type Tx @entity { id: ID!, input: Bytes! @unindexed, memo: String @unindexed }",
+        );
+        let sql = layout.as_ddl().expect("Failed to generate DDL");
+
+        assert!(
+            sql.contains("alter table rel.\"tx\" alter column \"input\" set storage external;\n")
+        );
+        assert!(!sql.contains("alter column \"memo\""));
+        assert!(sql.contains("create index attr_0_0_tx_id\n"));
+        assert!(!sql.contains("_tx_input"));
+        assert!(!sql.contains("_tx_memo"));
+    }
+
+    #[test]
+    fn account_like() {
+        let mut layout = test_layout(THING_GQL);
+        layout.set_account_like(&["thing".to_owned()]);
+
+        let thing = layout.table(&"thing".into()).unwrap();
+        assert!(thing.is_account_like);
+        assert!(!layout.table(&"scalar".into()).unwrap().is_account_like);
+        assert_eq!(
+            "create index concurrently if not exists acct_0_thing\n    \
+             on \"rel\".\"thing\" using btree(id, coalesce(upper(block_range), 2147483647));\n",
+            thing.account_like_index_ddl(true)
+        );
+    }
+
     #[test]
     fn forward_enum() {
         let layout = test_layout(FORWARD_ENUM_GQL);
@@ -1474,6 +2020,127 @@ mod tests {
         );
     }
 
+    #[test]
+    fn can_migrate_from() {
+        let base = test_layout(THING_GQL);
+        assert!(base.can_migrate_from(&base).is_empty());
+
+        // We allow adding types, enums and nullable attributes
+        let dest = test_layout(&THING_GQL.replace(
+            "bigThing: Thing!",
+            "bigThing: Thing!, note: String, shape: Shape }
+             enum Shape { round }
+             type Other { id: ID!, int: Int! ",
+        ));
+        assert!(dest.can_migrate_from(&base).is_empty());
+
+        // Everything else is a change that needs the data indexed again
+        let dest = test_layout(
+            "type Thing @entity { id: ID!, bigThing: Thing, count: Int! }
+             enum Color { yellow, red }",
+        );
+        assert_eq!(
+            vec![
+                "The attribute Thing.bigThing has type Thing, \
+                 but its type in the base is Thing!",
+                "The attribute Thing.count is new and must be nullable",
+                "The enum Size was removed",
+                "The type Scalar was removed",
+                "The values of the enum Color were changed",
+            ],
+            dest.can_migrate_from(&base)
+        );
+    }
+
+    #[test]
+    fn migration_ddl() {
+        let base = test_layout("type Thing @entity { id: ID!, name: String! }");
+        let dest = test_layout(
+            "type Thing @entity { id: ID!, name: String!, tags: [String!], owner: Owner }
+             type Owner @entity { id: ID! }",
+        );
+        let sql = dest.migration_ddl(&base).expect("Failed to generate DDL");
+        assert!(sql.contains("alter table rel.\"thing\" add column \"tags\" text[];\n"));
+        assert!(sql.contains("alter table rel.\"thing\" add column \"owner\" text;\n"));
+        assert!(sql.contains("create index attr_0_2_thing_tags\n"));
+        assert!(sql.contains("create index attr_0_3_thing_owner\n"));
+        assert!(sql.contains("create table rel.\"owner\" (\n"));
+        assert!(!sql.contains("add column \"name\""));
+        assert!(!sql.contains("create table rel.\"thing\""));
+    }
+
+    /// Reverses and flips the bytes of values; encrypts all attributes
+    /// whose name starts with `secret`
+    struct ReverseCipher;
+
+    impl AttributeCipher for ReverseCipher {
+        fn encrypts(&self, _: &SubgraphDeploymentId, _: &str, attribute: &str) -> bool {
+            attribute.starts_with("secret")
+        }
+
+        fn encrypt(
+            &self,
+            _: &SubgraphDeploymentId,
+            plaintext: &[u8],
+        ) -> Result<Vec<u8>, graph::prelude::Error> {
+            Ok(plaintext.iter().rev().map(|b| !b).collect())
+        }
+
+        fn decrypt(
+            &self,
+            _: &SubgraphDeploymentId,
+            ciphertext: &[u8],
+        ) -> Result<Vec<u8>, graph::prelude::Error> {
+            Ok(ciphertext.iter().rev().map(|b| !b).collect())
+        }
+    }
+
+    #[test]
+    fn encryption() {
+        use serde_json::json;
+
+        let mut layout = test_layout(
+            "type Thing @entity {
+               id: ID!, name: String!, secretName: String, secretKey: Bytes,
+               secretTags: [String!], secretCount: Int, secretOwner: Owner
+             }
+             type Owner @entity { id: ID! }",
+        );
+        layout.set_cipher(Arc::new(ReverseCipher));
+        let attributes = &layout.encryption.as_ref().unwrap().attributes;
+        let expected: HashSet<_> = vec!["secretName", "secretKey", "secretTags"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(Some(&expected), attributes.get("Thing"));
+        assert!(!attributes.contains_key("Owner"));
+
+        let mut entity = Entity::new();
+        entity.set("id", "one");
+        entity.set("name", "One");
+        entity.set("secretName", "Uno");
+        entity.set("secretKey", Value::Bytes(vec![1u8, 2].as_slice().into()));
+        entity.set("secretTags", vec![Value::from("a")]);
+        let encrypted = layout.encrypt("Thing", entity).unwrap();
+        assert_eq!(Some(&Value::from("One")), encrypted.get("name"));
+        assert_eq!(Some(&Value::from("9091aa")), encrypted.get("secretName"));
+        assert_eq!(
+            Some(&Value::Bytes(vec![0xfd, 0xfe].as_slice().into())),
+            encrypted.get("secretKey")
+        );
+
+        let table = layout.table_for_entity("Thing").unwrap();
+        let decrypt = |field: &str, json| {
+            let column = table.columns.iter().find(|c| c.field == field).unwrap();
+            layout.decrypt("Thing", column, json).unwrap()
+        };
+        assert_eq!(json!("Uno"), decrypt("secretName", json!("9091aa")));
+        assert_eq!(json!("\\x0102"), decrypt("secretKey", json!("\\xfdfe")));
+        assert_eq!(json!(["a"]), decrypt("secretTags", json!(["9e"])));
+        assert_eq!(json!(null), decrypt("secretName", json!(null)));
+        assert_eq!(json!("One"), decrypt("name", json!("One")));
+    }
+
     const THING_GQL: &str = "
         type Thing @entity {
             id: ID!
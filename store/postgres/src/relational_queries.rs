@@ -9,7 +9,7 @@ use diesel::pg::{Pg, PgConnection};
 use diesel::query_builder::{AstPass, QueryFragment, QueryId};
 use diesel::query_dsl::{LoadQuery, RunQueryDsl};
 use diesel::result::{Error as DieselError, QueryResult};
-use diesel::sql_types::{Array, Binary, Bool, Integer, Jsonb, Range, Text};
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Integer, Jsonb, Range, Text};
 use diesel::Connection;
 use lazy_static::lazy_static;
 use std::collections::{BTreeMap, HashSet};
@@ -27,6 +27,7 @@ use graph::prelude::{
 
 use crate::block_range::{
     BlockRange, BlockRangeContainsClause, BLOCK_RANGE_COLUMN, BLOCK_RANGE_CURRENT,
+    BLOCK_UNVERSIONED,
 };
 use crate::entities::STRING_PREFIX_SIZE;
 use crate::filter::UnsupportedFilter;
@@ -192,12 +193,20 @@ impl ForeignKeyClauses for Column {
 pub trait FromEntityData: Default + From<Entity> {
     type Value: FromColumnValue;
 
+    /// Whether to add the block at which a version of an entity was
+    /// written as the attribute `g$version_block`. GraphQL queries need
+    /// that for the `_version` field, but it must not end up in the
+    /// entities that mappings see
+    const WITH_VERSION_BLOCK: bool;
+
     fn insert_entity_data(&mut self, key: String, v: Self::Value);
 }
 
 impl FromEntityData for Entity {
     type Value = graph::prelude::Value;
 
+    const WITH_VERSION_BLOCK: bool = false;
+
     fn insert_entity_data(&mut self, key: String, v: Self::Value) {
         self.insert(key, v);
     }
@@ -206,6 +215,8 @@ impl FromEntityData for Entity {
 impl FromEntityData for BTreeMap<String, graphql_parser::query::Value> {
     type Value = graphql_parser::query::Value;
 
+    const WITH_VERSION_BLOCK: bool = true;
+
     fn insert_entity_data(&mut self, key: String, v: Self::Value) {
         self.insert(key, v);
     }
@@ -414,7 +425,14 @@ impl EntityData {
                     if key == "g$parent_id" {
                         let value = T::Value::from_column_value(&ColumnType::String, json)?;
                         out.insert_entity_data("g$parent_id".to_owned(), value);
+                    } else if key == BLOCK_RANGE_COLUMN && T::WITH_VERSION_BLOCK {
+                        if let Some(block) = version_block(&json) {
+                            let value =
+                                T::Value::from_column_value(&ColumnType::Int, block.into())?;
+                            out.insert_entity_data("g$version_block".to_owned(), value);
+                        }
                     } else if let Some(column) = table.column(&SqlName::verbatim(key)) {
+                        let json = layout.decrypt(&table.object, column, json)?;
                         let value = T::Value::from_column_value(&column.column_type, json)?;
                         if !value.is_null() {
                             out.insert_entity_data(column.field.clone(), value);
@@ -430,6 +448,22 @@ impl EntityData {
     }
 }
 
+/// The block at which a version of an entity was written, i.e., the lower
+/// bound of its block range. `to_jsonb` turns the range into a string like
+/// `[17,42)` or `[17,)`
+fn version_block(block_range: &serde_json::Value) -> Option<BlockNumber> {
+    let range = block_range.as_str()?;
+    let inclusive = range.starts_with('[');
+    let lower = range
+        .get(1..)?
+        .split(',')
+        .next()?
+        .parse::<BlockNumber>()
+        .ok()?;
+    let lower = if inclusive { lower } else { lower + 1 };
+    Some(lower).filter(|lower| *lower != BLOCK_UNVERSIONED)
+}
+
 /// A `QueryValue` makes it possible to bind a `Value` into a SQL query
 /// using the metadata from Column
 struct QueryValue<'a>(&'a Value, &'a ColumnType);
@@ -459,8 +493,15 @@ impl<'a> QueryFragment<Pg> for QueryValue<'a> {
                         .map_err(|e| DieselError::SerializationError(Box::new(e)))?;
                     out.push_bind_param::<Binary, _>(&bytes.as_slice())
                 }
+                // Compare numbers that we got as strings numerically, and
+                // never lexicographically
+                ColumnType::BigInt | ColumnType::BigDecimal => {
+                    out.push_bind_param::<Text, _>(s)?;
+                    out.push_sql("::numeric");
+                    Ok(())
+                }
                 _ => unreachable!(
-                    "only string, enum and tsvector columns have values of type string"
+                    "only string, enum, tsvector and numeric columns have values of type string"
                 ),
             },
             Value::Int(i) => out.push_bind_param::<Integer, _>(i),
@@ -722,7 +763,11 @@ impl<'a> QueryFilter<'a> {
             | StartsWith(attr, _)
             | NotStartsWith(attr, _)
             | EndsWith(attr, _)
-            | NotEndsWith(attr, _) => {
+            | NotEndsWith(attr, _)
+            | ContainsNoCase(attr, _)
+            | StartsWithNoCase(attr, _)
+            | EndsWithNoCase(attr, _)
+            | Matches(attr, _) => {
                 table.column_for_field(attr)?;
             }
         }
@@ -1020,6 +1065,37 @@ impl<'a> QueryFilter<'a> {
         }
         Ok(())
     }
+
+    /// Generate `{column} {op} {pattern}` for the filters that only apply
+    /// to strings, where `pattern` turns the value of the filter into the
+    /// pattern that `op` compares the column to
+    fn string_pattern(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        filter: &str,
+        op: &str,
+        pattern: impl Fn(&str) -> String,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        match value {
+            Value::String(s) if column.column_type == ColumnType::String && !column.is_list() => {
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(op);
+                out.push_bind_param::<Text, _>(&pattern(s))?;
+            }
+            _ => {
+                return Err(UnsupportedFilter {
+                    filter: filter.to_owned(),
+                    value: value.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
@@ -1056,6 +1132,22 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
             NotEndsWith(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not like ", false, out)?
             }
+
+            ContainsNoCase(attr, value) => {
+                let pattern = |s: &str| format!("%{}%", s);
+                self.string_pattern(attr, value, "contains_nocase", " ilike ", pattern, out)?
+            }
+            StartsWithNoCase(attr, value) => {
+                let pattern = |s: &str| format!("{}%", s);
+                self.string_pattern(attr, value, "starts_with_nocase", " ilike ", pattern, out)?
+            }
+            EndsWithNoCase(attr, value) => {
+                let pattern = |s: &str| format!("%{}", s);
+                self.string_pattern(attr, value, "ends_with_nocase", " ilike ", pattern, out)?
+            }
+            Matches(attr, value) => {
+                self.string_pattern(attr, value, "matches", " ~ ", str::to_owned, out)?
+            }
         }
         Ok(())
     }
@@ -1083,7 +1175,7 @@ impl<'a> QueryFragment<Pg> for FindQuery<'a> {
         out.push_sql(" e\n where ");
         self.table.primary_key().eq(&self.id, &mut out)?;
         out.push_sql(" and ");
-        BlockRangeContainsClause::new("e.", self.block).walk_ast(out)
+        BlockRangeContainsClause::new(self.table, "e.", self.block).walk_ast(out)
     }
 }
 
@@ -1137,7 +1229,7 @@ impl<'a> QueryFragment<Pg> for FindManyQuery<'a> {
                 .primary_key()
                 .is_in(&self.ids_for_type[table.object.as_str()], &mut out)?;
             out.push_sql(" and ");
-            BlockRangeContainsClause::new("e.", self.block).walk_ast(out.reborrow())?;
+            BlockRangeContainsClause::new(table, "e.", self.block).walk_ast(out.reborrow())?;
         }
         Ok(())
     }
@@ -1157,6 +1249,55 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FindManyQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FindManyQuery<'a> {}
 
+/// A query that finds the versions of entities that were written in
+/// `block`, i.e., whose block range starts at `block`
+#[derive(Debug, Clone, Constructor)]
+pub struct FindChangesQuery<'a> {
+    tables: Vec<&'a Table>,
+    block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for FindChangesQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select $object0 as entity, to_jsonb(e.*) as data
+        //      from schema.<table0> e where lower(e.block_range) = $block
+        //    union all
+        //    ...
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                out.push_sql("\nunion all\n");
+            }
+            out.push_sql("select ");
+            out.push_bind_param::<Text, _>(&table.object)?;
+            out.push_sql(" as entity, to_jsonb(e.*) as data\n");
+            out.push_sql("  from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" e\n where lower(e.");
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(") = ");
+            out.push_bind_param::<Integer, _>(&self.block)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for FindChangesQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityData> for FindChangesQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for FindChangesQuery<'a> {}
+
 #[derive(Debug, Clone)]
 pub struct InsertQuery<'a> {
     table: &'a Table,
@@ -1625,7 +1766,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(") as p(id) cross join lateral (select * from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
+        BlockRangeContainsClause::new(self.table, "c.", block).walk_ast(out.reborrow())?;
         limit.filter(out);
         out.push_sql(" and p.id = any(c.");
         out.push_identifier(column.name.as_str())?;
@@ -1661,7 +1802,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(") as p(id), ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
+        BlockRangeContainsClause::new(self.table, "c.", block).walk_ast(out.reborrow())?;
         limit.filter(out);
         out.push_sql(" and c.");
         out.push_identifier(column.name.as_str())?;
@@ -1702,7 +1843,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(") as p(id) cross join lateral (select * from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
+        BlockRangeContainsClause::new(self.table, "c.", block).walk_ast(out.reborrow())?;
         limit.filter(out);
         out.push_sql(" and p.id = c.");
         out.push_identifier(column.name.as_str())?;
@@ -1732,7 +1873,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(") as p(id), ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
+        BlockRangeContainsClause::new(self.table, "c.", block).walk_ast(out.reborrow())?;
         limit.filter(out);
         out.push_sql(" and p.id = c.");
         out.push_identifier(column.name.as_str())?;
@@ -1769,7 +1910,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(" cross join lateral (select * from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
+        BlockRangeContainsClause::new(self.table, "c.", block).walk_ast(out.reborrow())?;
         limit.filter(out);
         out.push_sql(" and c.id = any(p.child_ids)");
         self.and_filter(out.reborrow())?;
@@ -1798,7 +1939,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(")) as p(id, child_id), ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
+        BlockRangeContainsClause::new(self.table, "c.", block).walk_ast(out.reborrow())?;
         limit.filter(out);
         out.push_sql(" and ");
         out.push_sql("c.id = p.child_id");
@@ -1865,6 +2006,108 @@ impl<'a> FilterWindow<'a> {
     }
 }
 
+/// Count the children of a list of parents for one or more windows without
+/// loading them. Only windows where the children store the id of their
+/// parent, i.e., windows for `@derivedFrom` fields, can be counted
+#[derive(Debug, Clone)]
+pub struct CountChildrenQuery<'a> {
+    windows: Vec<FilterWindow<'a>>,
+    block: BlockNumber,
+}
+
+impl<'a> CountChildrenQuery<'a> {
+    pub fn new(
+        layout: &'a Layout,
+        windows: Vec<EntityWindow>,
+        block: BlockNumber,
+    ) -> Result<Self, QueryExecutionError> {
+        let windows = windows
+            .into_iter()
+            .map(|window| FilterWindow::new(layout, window, None))
+            .collect::<Result<Vec<_>, _>>()?;
+        for window in &windows {
+            if let TableLink::Parent(_) = window.link {
+                return Err(QueryExecutionError::NotSupported(format!(
+                    "can only count children of type `{}` that reference their parent",
+                    window.table.object
+                )));
+            }
+        }
+        Ok(CountChildrenQuery { windows, block })
+    }
+}
+
+impl<'a> QueryFragment<Pg> for CountChildrenQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //   select p.id as parent_id, count(*) as count
+        //     from unnest({parent_ids}) as p(id), children1 c
+        //    where p.id = c.{parent_field}    -- or p.id = any(c.{parent_field})
+        //      and c.block_range @> {block}
+        //    group by p.id
+        //   union all
+        //   select .. same for children2 ..
+        //
+        // Parent ids of type `bytea` are turned into the `0x..` strings
+        // that we use for them everywhere else
+        for (i, window) in self.windows.iter().enumerate() {
+            let column = match &window.link {
+                TableLink::Direct(column, _) => column,
+                TableLink::Parent(_) => unreachable!("CountChildrenQuery::new checks the links"),
+            };
+            if i > 0 {
+                out.push_sql("\nunion all\n");
+            }
+            match column.column_type().id_type() {
+                IdType::String => out.push_sql("select p.id"),
+                IdType::Bytes => out.push_sql("select '0x' || encode(p.id, 'hex')"),
+            }
+            out.push_sql(" as parent_id, count(*) as count\n  from unnest(");
+            column.bind_ids(&window.ids, &mut out)?;
+            out.push_sql(") as p(id), ");
+            out.push_sql(window.table.qualified_name.as_str());
+            out.push_sql(" c\n where ");
+            if column.is_list() {
+                out.push_sql("p.id = any(c.");
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(")");
+            } else {
+                out.push_sql("p.id = c.");
+                out.push_identifier(column.name.as_str())?;
+            }
+            out.push_sql(" and ");
+            BlockRangeContainsClause::new(window.table, "c.", self.block)
+                .walk_ast(out.reborrow())?;
+            out.push_sql("\n group by p.id");
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for CountChildrenQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+#[derive(QueryableByName)]
+pub struct ChildCount {
+    #[sql_type = "Text"]
+    pub parent_id: String,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+}
+
+impl<'a> LoadQuery<PgConnection, ChildCount> for CountChildrenQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<ChildCount>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for CountChildrenQuery<'a> {}
+
 /// This is a parallel to `EntityCollection`, but with entity type names
 /// and filters translated in a form ready for SQL generation
 #[derive(Debug, Clone)]
@@ -2062,6 +2305,24 @@ impl<'a> SortKey<'a> {
         }
     }
 
+    /// Generate
+    ///   order by [name direction], id, entity
+    /// for queries across several tables, where entities of different
+    /// types can have the same id. Without the entity type as the last
+    /// sort key, such entities come back in an arbitrary order, and paging
+    /// through them skips or repeats some of them
+    fn order_by_entity(&self, parent: bool, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        if parent {
+            self.order_by_parent(out)?;
+        } else {
+            self.order_by(out)?;
+        }
+        if !matches!(self, SortKey::None) {
+            out.push_sql(", entity");
+        }
+        Ok(())
+    }
+
     /// Generate
     ///   [name direction,] id
     fn sort_expr(
@@ -2179,7 +2440,7 @@ impl<'a> FilterQuery<'a> {
         out.push_sql(table.qualified_name.as_str());
         out.push_sql(" c");
         out.push_sql("\n where ");
-        BlockRangeContainsClause::new("c.", self.block).walk_ast(out.reborrow())?;
+        BlockRangeContainsClause::new(table, "c.", self.block).walk_ast(out.reborrow())?;
         if let Some(filter) = table_filter {
             out.push_sql(" and ");
             filter.walk_ast(out.reborrow())?;
@@ -2268,14 +2529,14 @@ impl<'a> FilterQuery<'a> {
         //    where {query_filter}
         //    union all
         //    ...
-        //    order by {sort_key}
+        //    order by {sort_key}, entity
         //    limit n offset m)
         // select m.entity, to_jsonb(c.*) as data, c.id, c.{sort_key}
         //   from {table} c, matches m
         //  where c.vid = m.vid and m.entity = '...'
         //  union all
         //  ...
-        //  order by c.{sort_key}
+        //  order by c.{sort_key}, entity
 
         // Step 1: build matches CTE
         out.push_sql("with matches as (");
@@ -2294,7 +2555,7 @@ impl<'a> FilterQuery<'a> {
             self.filtered_rows(table, filter, out.reborrow())?;
         }
         out.push_sql("\n ");
-        self.sort_key.order_by(&mut out)?;
+        self.sort_key.order_by_entity(false, &mut out)?;
         self.range.walk_ast(out.reborrow())?;
 
         out.push_sql(")\n");
@@ -2314,7 +2575,7 @@ impl<'a> FilterQuery<'a> {
             out.push_bind_param::<Text, _>(&table.object)?;
         }
         out.push_sql("\n ");
-        self.sort_key.order_by(&mut out)?;
+        self.sort_key.order_by_entity(false, &mut out)?;
         Ok(())
     }
 
@@ -2341,14 +2602,14 @@ impl<'a> FilterQuery<'a> {
         //            ({window.children_uniform("q")}
         //             union all
         //             ... range over all windows ...
-        //             order by c.{sort_key}
+        //             order by c.{sort_key}, entity
         //             limit $first skip $skip) c)
         //   select m.entity, to_jsonb(c.*) as data, m.parent_id
         //     from matches m, {window.child_table} c
         //    where c.vid = m.vid and m.entity = '{window.child_type}'
         //    union all
         //          ... range over all windows
-        //    order by parent_id, c.{sort_key}
+        //    order by parent_id, c.{sort_key}, entity
 
         // Step 1: build matches CTE
         out.push_sql("with matches as (");
@@ -2364,7 +2625,7 @@ impl<'a> FilterQuery<'a> {
             window.children_uniform(&self.sort_key, self.block, out.reborrow())?;
         }
         out.push_sql("\n");
-        self.sort_key.order_by(&mut out)?;
+        self.sort_key.order_by_entity(false, &mut out)?;
         self.range.walk_ast(out.reborrow())?;
         out.push_sql(") c)\n");
 
@@ -2395,7 +2656,7 @@ impl<'a> FilterQuery<'a> {
             out.push_sql("'");
         }
         out.push_sql("\n ");
-        self.sort_key.order_by_parent(&mut out)
+        self.sort_key.order_by_entity(true, &mut out)
     }
 }
 
@@ -41,6 +41,7 @@ impl ToSql<Numeric, Pg> for SqlValue {
             Value::BigInt(number) => {
                 <_ as ToSql<Numeric, Pg>>::to_sql(&scalar::BigDecimal::new(number.clone(), 0), out)
             }
+            Value::Int(i) => <_ as ToSql<Numeric, Pg>>::to_sql(&scalar::BigDecimal::from(*i), out),
             _ => panic!("Failed to convert attribute value to bigint in SQL"),
         }
     }
@@ -8,39 +8,43 @@ use graph::prelude::{CancelGuard, CancelHandle, CancelToken, CancelableError};
 use graph::spawn_blocking_async_allow_panic;
 use lazy_static::lazy_static;
 use lru_time_cache::LruCache;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::iter::FromIterator;
 use std::ops::Deref;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::Semaphore;
 
 use graph::components::store::{EntityCollection, QueryStore, Store as StoreTrait};
 use graph::components::subgraph::ProofOfIndexingFinisher;
 use graph::data::subgraph::schema::{
-    SubgraphDeploymentEntity, TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
+    SubgraphDeploymentEntity, SubgraphError, TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
 };
 use graph::prelude::{
     debug, ethabi, format_err, futures03, info, o, serde_json, tiny_keccak, tokio, trace, warn,
-    web3, AttributeIndexDefinition, BigInt, BlockNumber, ChainHeadUpdateListener as _,
-    ChainHeadUpdateStream, ChainStore, CheapClone, DynTryFuture, Entity, EntityKey,
-    EntityModification, EntityOrder, EntityQuery, EntityRange, Error, EthereumBlock,
-    EthereumBlockPointer, EthereumCallCache, EthereumNetworkIdentifier, Future, LightEthereumBlock,
-    Logger, MetadataOperation, MetricsRegistry, QueryExecutionError, Schema, StopwatchMetrics,
-    StoreError, StoreEvent, StoreEventStreamBox, Stream, SubgraphAssignmentProviderError,
-    SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphEntityPair, TransactionAbortError,
-    Value, BLOCK_NUMBER_MAX,
+    web3, AllowedQuery, AttributeCipher, AttributeIndexDefinition, BigInt, BlockModifications,
+    BlockNumber, ChainHeadUpdateListener as _, ChainHeadUpdateStream, ChainStore, CheapClone,
+    DeploymentStorage, DynTryFuture, Entity, EntityKey, EntityModification, EntityOrder,
+    EntityQuery, EntityRange, Error, EthereumBlock, EthereumBlockPointer, EthereumCallCache,
+    EthereumNetworkIdentifier, Future, IndexerHints, IndexingFreshness, LightEthereumBlock, Logger,
+    MetadataOperation, MetricsRegistry, NodeId, NodeLivenessStore, QueryExecutionError, Schema,
+    StopwatchMetrics, StoreError, StoreEvent, StoreEventStreamBox, Stream,
+    SubgraphAssignmentProviderError, SubgraphDeploymentId, SubgraphDeploymentStore,
+    SubgraphEntityPair, TableStorage, TransactionAbortError, Value, BLOCK_NUMBER_MAX,
 };
 
 use graph_graphql::prelude::api_schema;
 use web3::types::{Address, H256};
 
+use crate::catalog;
 use crate::chain_head_listener::ChainHeadUpdateListener;
+use crate::connection_pool::ConnectionPools;
 use crate::entities as e;
 use crate::functions::{attempt_chain_head_update, lookup_ancestor_block};
 use crate::history_event::HistoryEvent;
 use crate::metadata;
+use crate::metrics::DeploymentStoreMetrics;
 use crate::relational_queries::FromEntityData;
 use crate::store_events::SubscriptionManager;
 
@@ -133,6 +137,8 @@ fn initiate_schema(logger: &Logger, conn: &PgConnection, blocking_conn: &PgConne
 pub struct StoreConfig {
     pub postgres_url: String,
     pub network_name: String,
+    /// Encrypts the entity attributes it designates
+    pub attribute_cipher: Option<Arc<dyn AttributeCipher>>,
 }
 
 /// When connected to read replicas, this allows choosing which DB server to use for an operation.
@@ -168,7 +174,13 @@ pub struct StoreInner {
     chain_head_update_listener: Arc<ChainHeadUpdateListener>,
     network_name: String,
     genesis_block_ptr: EthereumBlockPointer,
+    /// The pool for indexing and everything that does not use one of the
+    /// more specific pools
     conn: Pool<ConnectionManager<PgConnection>>,
+    /// The pool for GraphQL queries against the main server
+    query_conn: Pool<ConnectionManager<PgConnection>>,
+    /// The pool for operations on subgraph metadata
+    metadata_conn: Pool<ConnectionManager<PgConnection>>,
     read_only_pools: Vec<Pool<ConnectionManager<PgConnection>>>,
     conn_round_robin_counter: AtomicUsize,
 
@@ -181,6 +193,18 @@ pub struct StoreInner {
     pub(crate) storage_cache: e::StorageCache,
 
     registry: Arc<dyn MetricsRegistry>,
+
+    /// The store metrics of the deployments this store has accessed
+    deployment_metrics: Mutex<HashMap<SubgraphDeploymentId, Arc<DeploymentStoreMetrics>>>,
+
+    /// When this store last recorded a query of each deployment
+    recorded_queries: Mutex<HashMap<SubgraphDeploymentId, Instant>>,
+
+    /// The query allowlist of each deployment, and when it was read
+    query_allowlists: Mutex<HashMap<SubgraphDeploymentId, (Instant, Option<Arc<HashSet<String>>>)>>,
+
+    /// Encrypts the entity attributes it designates
+    attribute_cipher: Option<Arc<dyn AttributeCipher>>,
 }
 
 /// A Store based on Diesel and Postgres.
@@ -203,7 +227,7 @@ impl Store {
         net_identifiers: EthereumNetworkIdentifier,
         chain_head_update_listener: Arc<ChainHeadUpdateListener>,
         subscriptions: Arc<SubscriptionManager>,
-        pool: Pool<ConnectionManager<PgConnection>>,
+        pools: ConnectionPools,
         read_only_pools: Vec<Pool<ConnectionManager<PgConnection>>>,
         registry: Arc<dyn MetricsRegistry>,
     ) -> Self {
@@ -211,6 +235,7 @@ impl Store {
         let logger = logger.new(o!("component" => "Store"));
 
         // Create the entities table (if necessary)
+        let pool = &pools.main;
         initiate_schema(&logger, &pool.get().unwrap(), &pool.get().unwrap());
 
         // Create the store
@@ -220,12 +245,18 @@ impl Store {
             chain_head_update_listener,
             network_name: config.network_name.clone(),
             genesis_block_ptr: (net_identifiers.genesis_block_hash, 0 as u64).into(),
-            conn: pool,
+            conn: pools.main,
+            query_conn: pools.query,
+            metadata_conn: pools.metadata,
             read_only_pools,
             conn_round_robin_counter: AtomicUsize::new(0),
             subgraph_cache: Mutex::new(LruCache::with_capacity(100)),
             storage_cache: e::make_storage_cache(),
             registry,
+            deployment_metrics: Mutex::new(HashMap::new()),
+            recorded_queries: Mutex::new(HashMap::new()),
+            query_allowlists: Mutex::new(HashMap::new()),
+            attribute_cipher: config.attribute_cipher,
         };
         let store = Store(Arc::new(store));
 
@@ -656,7 +687,7 @@ impl Store {
     ///   * This task will panic if the supplied closure panics
     ///   * This task will panic if the supplied closure returns Err(Cancelled)
     ///     when the supplied cancel token is not cancelled.
-    async fn with_conn<T: Send + 'static>(
+    pub(crate) async fn with_conn<T: Send + 'static>(
         &self,
         f: impl 'static
             + Send
@@ -763,14 +794,43 @@ impl Store {
     // Duplicated logic - this function may eventually go away.
     // See also 220c1ae9-3e8a-42d3-bcc5-b1244a69b8a9
     /// Deprecated. Use `with_entity_conn` instead
+    ///
+    /// Connections for the subgraph of subgraphs come from the metadata
+    /// pool, all others from the main pool
     pub(crate) fn get_entity_conn(
         &self,
         subgraph: &SubgraphDeploymentId,
         replica: ReplicaId,
+    ) -> Result<e::Connection, Error> {
+        let pool = if subgraph == &*SUBGRAPHS_ID {
+            &self.metadata_conn
+        } else {
+            &self.conn
+        };
+        self.entity_conn_from(pool, subgraph, replica)
+    }
+
+    /// Get a connection for running a GraphQL query against `subgraph`;
+    /// on the main server, it comes from the query pool
+    pub(crate) fn get_query_conn(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        replica: ReplicaId,
+    ) -> Result<e::Connection, Error> {
+        self.entity_conn_from(&self.query_conn, subgraph, replica)
+    }
+
+    /// Get an entity connection from `main_pool` if `replica` is the main
+    /// server, and from the read replica's pool otherwise
+    fn entity_conn_from(
+        &self,
+        main_pool: &Pool<ConnectionManager<PgConnection>>,
+        subgraph: &SubgraphDeploymentId,
+        replica: ReplicaId,
     ) -> Result<e::Connection, Error> {
         let start = Instant::now();
         let conn = match replica {
-            ReplicaId::Main => self.get_conn()?,
+            ReplicaId::Main => main_pool.get()?,
             ReplicaId::ReadOnly(idx) => self.read_only_conn(idx)?,
         };
         self.registry
@@ -784,6 +844,50 @@ impl Store {
         Ok(e::Connection::new(conn.into(), storage, metadata))
     }
 
+    pub(crate) fn deployment_metrics(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<Arc<DeploymentStoreMetrics>, Error> {
+        let mut cache = self.deployment_metrics.lock().unwrap();
+        if let Some(metrics) = cache.get(subgraph) {
+            return Ok(metrics.clone());
+        }
+        let metrics = Arc::new(DeploymentStoreMetrics::new(
+            self.registry.as_ref(),
+            subgraph,
+        )?);
+        cache.insert(subgraph.clone(), metrics.clone());
+        Ok(metrics)
+    }
+
+    /// Record that `subgraph` was queried so that it is not considered
+    /// unused. To keep queries fast, this writes to the database at most
+    /// every few minutes per deployment, and failures are only logged
+    pub(crate) fn record_query(&self, subgraph: &SubgraphDeploymentId) {
+        {
+            let mut recorded = self.recorded_queries.lock().unwrap();
+            match recorded.get(subgraph) {
+                Some(at) if at.elapsed() < crate::unused::QUERY_RECORD_INTERVAL => return,
+                _ => recorded.insert(subgraph.clone(), Instant::now()),
+            };
+        }
+        let result = self
+            .get_conn()
+            .and_then(|conn| crate::unused::record_query(&conn, subgraph));
+        if let Err(e) = result {
+            debug!(self.logger, "Failed to record query of deployment";
+                   "subgraph_id" => subgraph.to_string(), "error" => e.to_string());
+        }
+    }
+
+    /// Drop what this store caches about a deployment that was removed
+    pub(crate) fn forget_deployment(&self, subgraph: &SubgraphDeploymentId) {
+        self.storage_cache.lock().unwrap().remove(subgraph);
+        self.subgraph_cache.lock().unwrap().remove(subgraph);
+        self.recorded_queries.lock().unwrap().remove(subgraph);
+        self.query_allowlists.lock().unwrap().remove(subgraph);
+    }
+
     /// Return the storage for the subgraph. Since constructing a `Storage`
     /// object takes a bit of computation, we cache storage objects that do
     /// not have a pending migration in the Store, i.e., for the lifetime of
@@ -799,7 +903,13 @@ impl Store {
             return Ok(storage.clone());
         }
 
-        let storage = Arc::new(e::Storage::new(conn, subgraph)?);
+        let mut storage = e::Storage::new(conn, subgraph)?;
+        if let (Some(cipher), e::Storage::Relational(layout)) =
+            (&self.attribute_cipher, &mut storage)
+        {
+            layout.set_cipher(cipher.clone());
+        }
+        let storage = Arc::new(storage);
         if storage.is_cacheable() {
             &self
                 .storage_cache
@@ -1028,6 +1138,9 @@ impl StoreTrait for Store {
         let conn = self
             .get_entity_conn(&key.subgraph_id, ReplicaId::Main)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.deployment_metrics(&key.subgraph_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?
+            .entities_read(1);
         self.get_entity(&conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
     }
 
@@ -1042,9 +1155,23 @@ impl StoreTrait for Store {
         let conn = self
             .get_entity_conn(subgraph_id, ReplicaId::Main)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.deployment_metrics(subgraph_id)?
+            .entities_read(ids_for_type.values().map(Vec::len).sum());
         conn.find_many(ids_for_type, BLOCK_NUMBER_MAX)
     }
 
+    fn entity_changes_in_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<Vec<(String, Entity)>, StoreError> {
+        let conn = self
+            .get_entity_conn(subgraph_id, ReplicaId::Main)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        conn.find_changes(entity_types, block)
+    }
+
     fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
         let conn = self
             .get_entity_conn(&query.subgraph_id, ReplicaId::Main)
@@ -1091,11 +1218,30 @@ impl StoreTrait for Store {
         block_ptr_to: EthereumBlockPointer,
         mods: Vec<EntityModification>,
         stopwatch: StopwatchMetrics,
+        deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<bool, StoreError> {
+        self.transact_blocks_operations(
+            subgraph_id,
+            vec![BlockModifications {
+                block_ptr: block_ptr_to,
+                mods,
+                deterministic_errors,
+            }],
+            stopwatch,
+        )
+    }
+
+    fn transact_blocks_operations(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        blocks: Vec<BlockModifications>,
+        stopwatch: StopwatchMetrics,
     ) -> Result<bool, StoreError> {
         // All operations should apply only to entities in this subgraph or
         // the subgraph of subgraphs
-        if mods
+        if blocks
             .iter()
+            .flat_map(|block| block.mods.iter())
             .map(|modification| modification.entity_key())
             .any(|key| key.subgraph_id != subgraph_id && key.subgraph_id != *SUBGRAPHS_ID)
         {
@@ -1104,43 +1250,78 @@ impl StoreTrait for Store {
                  in the subgraph or in the subgraph of subgraphs"
             );
         }
+        assert!(
+            blocks
+                .windows(2)
+                .all(|pair| pair[0].block_ptr.number < pair[1].block_ptr.number),
+            "blocks must be sorted by block number"
+        );
+        let block_ptr_to = match blocks.last() {
+            Some(block) => block.block_ptr,
+            None => return Ok(false),
+        };
+
+        let metrics = self.deployment_metrics(&subgraph_id)?;
+        let _pending = metrics.pending_write();
+        let block_count = blocks.len();
+        let modification_count = blocks.iter().map(|block| block.mods.len()).sum();
 
         let econn = self.get_entity_conn(&subgraph_id, ReplicaId::Main)?;
 
+        let start = Instant::now();
         let (event, metadata_event, should_migrate) =
             econn.transaction(|| -> Result<_, StoreError> {
                 let block_ptr_from = Self::block_ptr_with_conn(&subgraph_id, &econn)?;
                 if let Some(ref block_ptr_from) = block_ptr_from {
-                    assert!(block_ptr_from.number < block_ptr_to.number);
+                    assert!(block_ptr_from.number < blocks[0].block_ptr.number);
                 }
 
-                // Ensure the history event exists in the database
-                let history_event = econn.create_history_event(block_ptr_to, &mods)?;
-
                 let should_migrate = econn.should_migrate(&subgraph_id, &block_ptr_to)?;
 
                 // Emit a store event for the changes we are about to make. We
                 // wait with sending it until we have done all our other work
                 // so that we do not hold a lock on the notification queue
                 // for longer than we have to
-                let event: StoreEvent = mods.iter().collect();
+                let event: StoreEvent = blocks.iter().flat_map(|block| block.mods.iter()).collect();
+
+                let mut deterministic_errors = Vec::new();
+                for block in blocks {
+                    // Ensure the history event exists in the database
+                    let history_event = econn.create_history_event(block.block_ptr, &block.mods)?;
+
+                    // Make the changes
+                    let section = stopwatch.start_section("apply_entity_modifications");
+                    self.apply_entity_modifications(
+                        &econn,
+                        block.mods,
+                        Some(&history_event),
+                        stopwatch.clone(),
+                    )?;
+                    section.end();
 
-                // Make the changes
-                let section = stopwatch.start_section("apply_entity_modifications");
-                self.apply_entity_modifications(&econn, mods, Some(&history_event), stopwatch)?;
-                section.end();
+                    deterministic_errors.extend(block.deterministic_errors);
+                }
 
                 // Update the subgraph block pointer, without an event source; this way
                 // no entity history is recorded for the block pointer update itself
-                let block_ptr_ops =
+                let mut metadata_ops =
                     SubgraphDeploymentEntity::update_ethereum_block_pointer_operations(
                         &subgraph_id,
                         block_ptr_to,
                     );
+                if !deterministic_errors.is_empty() {
+                    let non_fatal_errors = econn.non_fatal_errors()?;
+                    metadata_ops.extend(SubgraphDeploymentEntity::non_fatal_errors_operations(
+                        &subgraph_id,
+                        non_fatal_errors,
+                        deterministic_errors,
+                    ));
+                }
                 let metadata_event =
-                    self.apply_metadata_operations_with_conn(&econn, block_ptr_ops)?;
+                    self.apply_metadata_operations_with_conn(&econn, metadata_ops)?;
                 Ok((event, metadata_event, should_migrate))
             })?;
+        metrics.blocks_written(block_count, modification_count, start.elapsed());
 
         // Send the events separately, because NOTIFY uses a global DB lock.
         econn.transaction(|| {
@@ -1216,6 +1397,7 @@ impl StoreTrait for Store {
             econn.update_entity_count(count)?;
             Ok((event, metadata_event))
         })?;
+        self.deployment_metrics(&subgraph_id)?.block_reverted();
 
         // Send the events separately, because NOTIFY uses a global DB lock.
         econn.transaction(|| {
@@ -1231,12 +1413,28 @@ impl StoreTrait for Store {
     fn create_subgraph_deployment(
         &self,
         schema: &Schema,
+        hints: &IndexerHints,
+        ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| -> Result<(), StoreError> {
+            let event = self.apply_metadata_operations_with_conn(&econn, ops.clone())?;
+            econn.create_schema(schema, hints)?;
+            econn.send_store_event(&event)
+        })
+    }
+
+    fn create_subgraph_deployments(
+        &self,
+        deployments: &[(Schema, IndexerHints)],
         ops: Vec<MetadataOperation>,
     ) -> Result<(), StoreError> {
         let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
         econn.transaction(|| -> Result<(), StoreError> {
             let event = self.apply_metadata_operations_with_conn(&econn, ops.clone())?;
-            econn.create_schema(schema)?;
+            for (schema, hints) in deployments {
+                econn.create_schema(schema, hints)?;
+            }
             econn.send_store_event(&event)
         })
     }
@@ -1375,6 +1573,229 @@ impl SubgraphDeploymentStore for Store {
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
         Ok(self.subgraph_info(subgraph_id)?.network)
     }
+
+    fn has_non_fatal_errors(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+    ) -> Result<bool, Error> {
+        let conn = self.get_conn()?;
+        Ok(metadata::has_non_fatal_errors(&conn, subgraph_id, block)?)
+    }
+
+    fn earliest_block(&self, subgraph_id: &SubgraphDeploymentId) -> Result<BlockNumber, Error> {
+        let conn = self.get_conn()?;
+        Ok(e::earliest_block(&conn, subgraph_id)?)
+    }
+
+    fn deployment_storage(
+        &self,
+        subgraph_ids: Option<Vec<SubgraphDeploymentId>>,
+    ) -> Result<Vec<DeploymentStorage>, Error> {
+        let conn = self.get_conn()?;
+        let mut ids = e::deployment_ids(&conn)?;
+        if let Some(subgraph_ids) = subgraph_ids {
+            ids.retain(|id| subgraph_ids.contains(id));
+        }
+
+        ids.into_iter()
+            .map(|id| -> Result<DeploymentStorage, Error> {
+                let storage = self.storage(&conn, &id)?;
+                let tables = catalog::table_sizes(&conn, storage.schema_name())?
+                    .into_iter()
+                    .map(|size| TableStorage {
+                        entity_type: storage.entity_type(&size.table_name),
+                        table: size.table_name,
+                        entity_count: size.distinct_ids,
+                        version_count: size.rows,
+                        table_bytes: size.table_bytes,
+                        index_bytes: size.index_bytes,
+                    })
+                    .collect();
+                let (earliest_block, latest_block) = metadata::deployment_blocks(&conn, &id)?;
+                Ok(DeploymentStorage {
+                    subgraph: id,
+                    tables,
+                    earliest_block,
+                    latest_block,
+                })
+            })
+            .collect()
+    }
+    fn query_allowlist(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<Arc<HashSet<String>>>, Error> {
+        if let Some((read_at, allowlist)) = self.query_allowlists.lock().unwrap().get(subgraph_id) {
+            if read_at.elapsed() < *crate::query_allowlist::ALLOWLIST_REFRESH_INTERVAL {
+                return Ok(allowlist.clone());
+            }
+        }
+        let conn = self.get_conn()?;
+        let allowlist = crate::query_allowlist::allowlist(&conn, subgraph_id)?.map(Arc::new);
+        self.query_allowlists
+            .lock()
+            .unwrap()
+            .insert(subgraph_id.clone(), (Instant::now(), allowlist.clone()));
+        Ok(allowlist)
+    }
+
+    fn set_query_allowlist_enabled(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        let conn = self.get_conn()?;
+        crate::query_allowlist::set_enabled(&conn, subgraph_id, enabled)?;
+        self.query_allowlists.lock().unwrap().remove(subgraph_id);
+        Ok(())
+    }
+
+    fn allow_query(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        hash: &str,
+        query: &str,
+    ) -> Result<(), Error> {
+        let conn = self.get_conn()?;
+        crate::query_allowlist::add(&conn, subgraph_id, hash, query)?;
+        self.query_allowlists.lock().unwrap().remove(subgraph_id);
+        Ok(())
+    }
+
+    fn disallow_query(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        hash: &str,
+    ) -> Result<bool, Error> {
+        let conn = self.get_conn()?;
+        let removed = crate::query_allowlist::remove(&conn, subgraph_id, hash)?;
+        self.query_allowlists.lock().unwrap().remove(subgraph_id);
+        Ok(removed)
+    }
+
+    fn allowed_queries(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<AllowedQuery>, Error> {
+        let conn = self.get_conn()?;
+        crate::query_allowlist::list(&conn, subgraph_id)
+    }
+
+    fn import_entities(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: BlockNumber,
+        entities: Vec<(EntityKey, Entity)>,
+    ) -> Result<(), StoreError> {
+        assert!(
+            entities
+                .iter()
+                .all(|(key, _)| &key.subgraph_id == subgraph_id),
+            "import_entities must affect only entities in the subgraph"
+        );
+        if !self.uses_relational_schema(subgraph_id)? {
+            return Err(StoreError::Unknown(format_err!(
+                "entities can only be imported into subgraphs with relational storage"
+            )));
+        }
+        let earliest_block = self.earliest_block(subgraph_id)?;
+
+        let econn = self.get_entity_conn(subgraph_id, ReplicaId::Main)?;
+        let event = econn.transaction(|| -> Result<StoreEvent, StoreError> {
+            let latest_block = Self::block_ptr_with_conn(subgraph_id, &econn)?
+                .map(|ptr| ptr.number as BlockNumber);
+            match latest_block {
+                Some(latest_block) if earliest_block <= block && block <= latest_block => (),
+                _ => {
+                    return Err(StoreError::Unknown(format_err!(
+                        "subgraph {} has no history for block {}; it covers blocks {} to {:?}",
+                        subgraph_id,
+                        block,
+                        earliest_block,
+                        latest_block
+                    )))
+                }
+            }
+
+            let mods: Vec<_> = entities
+                .into_iter()
+                .map(|(key, data)| EntityModification::Insert { key, data })
+                .collect();
+            let event: StoreEvent = mods.iter().collect();
+
+            // Relational storage only uses the number of the block
+            let history_event = HistoryEvent::create_without_event_metadata(
+                subgraph_id.clone(),
+                EthereumBlockPointer::from((H256::zero(), block as u64)),
+            );
+            let mut count = 0;
+            for modification in mods {
+                if let EntityModification::Insert { key, data } = modification {
+                    if econn
+                        .find(&key.entity_type, &key.entity_id, BLOCK_NUMBER_MAX)?
+                        .is_some()
+                    {
+                        return Err(StoreError::Unknown(format_err!(
+                            "entity {}[{}] already exists",
+                            key.entity_type,
+                            key.entity_id
+                        )));
+                    }
+                    self.check_interface_entity_uniqueness(&econn, &key)?;
+                    econn.insert(&key, data, Some(&history_event))?;
+                    count += 1;
+                }
+            }
+            econn.update_entity_count(count)?;
+            Ok(event)
+        })?;
+
+        econn.transaction(|| econn.send_store_event(&event))
+    }
+
+    fn indexing_freshness(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<IndexingFreshness>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl as b;
+        use crate::db_schema::ethereum_networks::dsl as n;
+        use diesel::dsl::sql;
+        use diesel::sql_types::{Nullable, Text};
+
+        let network = match self.network_name(subgraph_id)? {
+            Some(network) => network,
+            None => return Ok(None),
+        };
+        let latest_block = match self.block_ptr(subgraph_id.clone())? {
+            Some(latest_block) => latest_block,
+            None => return Ok(None),
+        };
+        let conn = self.get_conn()?;
+        let chain_head_block_number = n::ethereum_networks
+            .select(n::head_block_number)
+            .filter(n::name.eq(&network))
+            .first::<Option<i64>>(&conn)
+            .optional()?
+            .and_then(|number| number);
+        let chain_head_block_number = match chain_head_block_number {
+            Some(number) => number as u64,
+            None => return Ok(None),
+        };
+        // The timestamp is stored as a hex string like `0x5f5e1000`
+        let latest_block_timestamp = b::ethereum_blocks
+            .select(sql::<Nullable<Text>>("data -> 'block' ->> 'timestamp'"))
+            .filter(b::hash.eq(format!("{:x}", latest_block.hash)))
+            .first::<Option<String>>(&conn)
+            .optional()?
+            .and_then(|timestamp| timestamp)
+            .and_then(|timestamp| u64::from_str_radix(timestamp.trim_start_matches("0x"), 16).ok());
+        Ok(Some(IndexingFreshness {
+            latest_block,
+            latest_block_timestamp,
+            chain_head_block_number,
+        }))
+    }
 }
 
 impl ChainStore for Store {
@@ -1536,6 +1957,45 @@ impl ChainStore for Store {
             .map_err(Error::from)
     }
 
+    fn ancestor_blocks(
+        &self,
+        block_hash: H256,
+        min_number: u64,
+    ) -> Result<Vec<EthereumBlock>, Error> {
+        use diesel::sql_types::{BigInt, Jsonb, Text};
+
+        #[derive(QueryableByName)]
+        struct BlockData {
+            #[sql_type = "Jsonb"]
+            data: serde_json::Value,
+        };
+
+        // Follow the parent hashes from `block_hash` as long as the
+        // parents are in the store
+        let query = "
+            with recursive chain(hash, parent_hash, number, data) as (
+                select hash, parent_hash, number, data
+                  from ethereum_blocks
+                 where network_name = $1
+                   and hash = $2
+                   and number >= $3
+                union all
+                select b.hash, b.parent_hash, b.number, b.data
+                  from ethereum_blocks b, chain c
+                 where b.network_name = $1
+                   and b.hash = c.parent_hash
+                   and b.number >= $3)
+            select data from chain order by number desc";
+        diesel::sql_query(query)
+            .bind::<Text, _>(&self.network_name)
+            .bind::<Text, _>(format!("{:x}", block_hash))
+            .bind::<BigInt, _>(min_number as i64)
+            .load::<BlockData>(&*self.get_conn()?)?
+            .into_iter()
+            .map(|BlockData { data }| serde_json::from_value(data).map_err(Error::from))
+            .collect()
+    }
+
     fn cleanup_cached_blocks(&self, ancestor_count: u64) -> Result<(BlockNumber, usize), Error> {
         use crate::db_schema::ethereum_blocks::dsl;
         use diesel::sql_types::{Integer, Text};
@@ -1553,7 +2013,8 @@ impl ChainStore for Store {
         // We also stay `ancestor_count` many blocks behind the head of the
         // chain since the block ingestor consults these blocks frequently
         //
-        // Only consider active subgraphs that have not failed
+        // Only consider active subgraphs that have not failed and are not
+        // paused
         let conn = self.get_conn()?;
         let query = "
             select coalesce(
@@ -1569,6 +2030,7 @@ impl ChainStore for Store {
                  where left(ds.id, 46) = d.id
                    and a.id = d.id
                    and not d.failed
+                   and not coalesce(a.paused, false)
                    and ds.network = $2) a;";
         let ancestor_count = i32::try_from(ancestor_count)
             .expect("ancestor_count fits into a signed 32 bit integer");
@@ -1622,6 +2084,114 @@ impl ChainStore for Store {
             .execute(&conn)
             .map_err(Error::from)
     }
+
+    fn inconsistent_block_numbers(&self, from: u64, to: u64) -> Result<Vec<u64>, Error> {
+        use diesel::sql_types::{BigInt, Text};
+
+        #[derive(QueryableByName)]
+        struct Number {
+            #[sql_type = "BigInt"]
+            number: i64,
+        };
+
+        let conn = self.get_conn()?;
+        let query = "
+            select number
+              from ethereum_blocks
+             where network_name = $1 and number between $2 and $3
+             group by number
+            having count(*) > 1
+             union
+            select b.number
+              from ethereum_blocks b, ethereum_blocks c
+             where b.network_name = $1 and b.number between $2 and $3
+               and c.network_name = $1 and c.number = b.number + 1
+               and not exists (select 1
+                                 from ethereum_blocks p
+                                where p.network_name = $1 and p.hash = c.parent_hash)
+             order by number";
+        Ok(diesel::sql_query(query)
+            .bind::<Text, _>(&self.network_name)
+            .bind::<BigInt, _>(from as i64)
+            .bind::<BigInt, _>(to as i64)
+            .load::<Number>(&conn)?
+            .into_iter()
+            .map(|Number { number }| number as u64)
+            .collect())
+    }
+
+    fn cached_blocks_around_timestamp(
+        &self,
+        timestamp: u64,
+    ) -> Result<(Option<(u64, u64)>, Option<(u64, u64)>), Error> {
+        use crate::db_schema::ethereum_blocks::dsl;
+        use diesel::dsl::{max, min, sql};
+        use diesel::sql_types::{Nullable, Text};
+
+        let conn = self.get_conn()?;
+
+        // The first cached block at or after `number`, with its timestamp
+        let block_from = |number: u64| -> Result<Option<(u64, u64)>, Error> {
+            let block = dsl::ethereum_blocks
+                .select((
+                    dsl::number,
+                    sql::<Nullable<Text>>("data -> 'block' ->> 'timestamp'"),
+                ))
+                .filter(dsl::network_name.eq(&self.network_name))
+                .filter(dsl::number.ge(number as i64))
+                .order(dsl::number)
+                .first::<(i64, Option<String>)>(&conn)
+                .optional()?;
+            match block {
+                Some((number, Some(timestamp))) => {
+                    let timestamp = u64::from_str_radix(timestamp.trim_start_matches("0x"), 16)
+                        .map_err(|e| format_err!("invalid timestamp of block {}: {}", number, e))?;
+                    Ok(Some((number as u64, timestamp)))
+                }
+                Some((number, None)) => Err(format_err!("block {} has no timestamp", number)),
+                None => Ok(None),
+            }
+        };
+
+        let (first, last) = dsl::ethereum_blocks
+            .select((min(dsl::number), max(dsl::number)))
+            .filter(dsl::network_name.eq(&self.network_name))
+            .first::<(Option<i64>, Option<i64>)>(&conn)?;
+        let (mut low, mut high) = match (first, last) {
+            (Some(first), Some(last)) => (first as u64, last as u64),
+            _ => return Ok((None, None)),
+        };
+
+        // Timestamps grow with block numbers, which lets us bisect the
+        // numbers of the cached blocks, even though the cache has gaps
+        let mut before = None;
+        let mut after = None;
+        while low <= high {
+            let mid = low + (high - low) / 2;
+            match block_from(mid)? {
+                Some((number, block_timestamp)) if number <= high => {
+                    if block_timestamp <= timestamp {
+                        before = Some((number, block_timestamp));
+                        low = number + 1;
+                    } else {
+                        after = Some((number, block_timestamp));
+                        if mid == 0 {
+                            break;
+                        }
+                        high = mid - 1;
+                    }
+                }
+                // No cached blocks between `mid` and `high`
+                _ => {
+                    if mid == 0 {
+                        break;
+                    }
+                    high = mid - 1;
+                }
+            }
+        }
+        Ok((before, after))
+    }
 }
 
 impl EthereumCallCache for Store {
@@ -1631,34 +2201,13 @@ impl EthereumCallCache for Store {
         encoded_call: &[u8],
         block: EthereumBlockPointer,
     ) -> Result<Option<Vec<u8>>, Error> {
-        use crate::db_schema::{eth_call_cache, eth_call_meta};
-        use diesel::dsl::sql;
+        use crate::db_schema::eth_call_cache;
 
         let id = contract_call_id(&contract_address, encoded_call, &block);
-        let conn = &*self.get_conn()?;
-        if let Some(call_output) = conn.transaction::<_, Error, _>(|| {
-            if let Some((return_value, update_accessed_at)) = eth_call_cache::table
-                .find(id.as_ref())
-                .inner_join(eth_call_meta::table)
-                .select((
-                    eth_call_cache::return_value,
-                    sql("CURRENT_DATE > eth_call_meta.accessed_at"),
-                ))
-                .get_result(conn)
-                .optional()?
-            {
-                if update_accessed_at {
-                    update(eth_call_meta::table.find(contract_address.as_ref()))
-                        .set(eth_call_meta::accessed_at.eq(sql("CURRENT_DATE")))
-                        .execute(conn)?;
-                }
-                Ok(Some(return_value))
-            } else {
-                Ok(None)
-            }
-        })? {
+        if let Some(call_output) = self.cached_call(&id, contract_address)? {
             Ok(Some(call_output))
         } else {
+            let conn = &*self.get_conn()?;
             // No entry with the new id format, try the old one.
             let old_id = old_contract_call_id(&contract_address, &encoded_call, &block);
             if let Some(return_value) = eth_call_cache::table
@@ -1673,7 +2222,7 @@ impl EthereumCallCache for Store {
                 trace!(self.logger, "Updating eth call cache entry");
 
                 // Migrate to the new format by re-inserting the call and deleting the old entry.
-                self.set_call(contract_address, encoded_call, block, &return_value)?;
+                self.insert_call(&id, contract_address, block, &return_value)?;
                 diesel::delete(eth_call_cache::table.filter(dsl::id.eq(old_id.as_ref())))
                     .execute(conn)?;
                 Ok(Some(return_value))
@@ -1689,16 +2238,86 @@ impl EthereumCallCache for Store {
         encoded_call: &[u8],
         block: EthereumBlockPointer,
         return_value: &[u8],
+    ) -> Result<(), Error> {
+        let id = contract_call_id(&contract_address, encoded_call, &block);
+        self.insert_call(&id, contract_address, block, return_value)
+    }
+
+    fn get_immutable_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let id = immutable_contract_call_id(&contract_address, encoded_call);
+        self.cached_call(&id, contract_address)
+    }
+
+    fn set_immutable_call(
+        &self,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        return_value: &[u8],
+    ) -> Result<(), Error> {
+        // An empty result means that the contract did not exist yet in
+        // `block`, which is not true for later blocks
+        if return_value.is_empty() {
+            return Ok(());
+        }
+        let id = immutable_contract_call_id(&contract_address, encoded_call);
+        self.insert_call(&id, contract_address, block, return_value)
+    }
+}
+
+impl Store {
+    /// The cached return value of the call with `id`
+    fn cached_call(
+        &self,
+        id: &[u8],
+        contract_address: ethabi::Address,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        use crate::db_schema::{eth_call_cache, eth_call_meta};
+        use diesel::dsl::sql;
+
+        let conn = &*self.get_conn()?;
+        conn.transaction::<_, Error, _>(|| {
+            if let Some((return_value, update_accessed_at)) = eth_call_cache::table
+                .find(id)
+                .inner_join(eth_call_meta::table)
+                .select((
+                    eth_call_cache::return_value,
+                    sql("CURRENT_DATE > eth_call_meta.accessed_at"),
+                ))
+                .get_result(conn)
+                .optional()?
+            {
+                if update_accessed_at {
+                    update(eth_call_meta::table.find(contract_address.as_ref()))
+                        .set(eth_call_meta::accessed_at.eq(sql("CURRENT_DATE")))
+                        .execute(conn)?;
+                }
+                Ok(Some(return_value))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    fn insert_call(
+        &self,
+        id: &[u8],
+        contract_address: ethabi::Address,
+        block: EthereumBlockPointer,
+        return_value: &[u8],
     ) -> Result<(), Error> {
         use crate::db_schema::{eth_call_cache, eth_call_meta};
         use diesel::dsl::sql;
 
-        let id = contract_call_id(&contract_address, encoded_call, &block);
         let conn = &*self.get_conn()?;
         conn.transaction(|| {
             insert_into(eth_call_cache::table)
                 .values((
-                    eth_call_cache::id.eq(id.as_ref()),
+                    eth_call_cache::id.eq(id),
                     eth_call_cache::contract_address.eq(contract_address.as_ref()),
                     eth_call_cache::block_number.eq(block.number as i32),
                     eth_call_cache::return_value.eq(return_value),
@@ -1722,6 +2341,113 @@ impl EthereumCallCache for Store {
     }
 }
 
+impl NodeLivenessStore for Store {
+    fn heartbeat(&self, node: &NodeId) -> Result<(), StoreError> {
+        use diesel::sql_types::Text;
+
+        let conn = self.get_conn()?;
+        diesel::sql_query(
+            "insert into index_node_heartbeats(node_id, last_heartbeat)
+             values ($1, now())
+             on conflict(node_id) do update set last_heartbeat = now()",
+        )
+        .bind::<Text, _>(node.to_string())
+        .execute(&conn)?;
+        Ok(())
+    }
+
+    fn heartbeat_ages(&self) -> Result<Vec<(NodeId, Duration)>, StoreError> {
+        use diesel::sql_types::{Double, Text};
+
+        #[derive(QueryableByName)]
+        struct Heartbeat {
+            #[sql_type = "Text"]
+            node_id: String,
+            #[sql_type = "Double"]
+            age: f64,
+        }
+
+        // Compute the age in the database so that the clocks of the nodes
+        // do not matter
+        let conn = self.get_conn()?;
+        diesel::sql_query(
+            "select node_id,
+                    extract(epoch from now() - last_heartbeat)::float8 as age
+               from index_node_heartbeats",
+        )
+        .load::<Heartbeat>(&conn)?
+        .into_iter()
+        .map(|Heartbeat { node_id, age }| {
+            let node = NodeId::new(node_id.clone())
+                .map_err(|()| format_err!("invalid node id in heartbeats: {}", node_id))?;
+            Ok((node, Duration::from_secs_f64(age.max(0.0))))
+        })
+        .collect()
+    }
+
+    fn acquire_ingestor_lease(
+        &self,
+        network: &str,
+        node: &NodeId,
+        duration: Duration,
+    ) -> Result<bool, StoreError> {
+        use diesel::sql_types::{Double, Text};
+
+        // Like heartbeats, expiry is judged by the database's clock. The
+        // update only happens if the row's holder is `node` or its lease
+        // has expired, so that exactly one node wins a race for the lease
+        let conn = self.get_conn()?;
+        let rows = diesel::sql_query(
+            "insert into block_ingestor_leases(network, node_id, expires_at)
+             values ($1, $2, now() + $3 * interval '1 second')
+             on conflict(network) do update
+                set node_id = excluded.node_id, expires_at = excluded.expires_at
+              where block_ingestor_leases.node_id = excluded.node_id
+                 or block_ingestor_leases.expires_at < now()",
+        )
+        .bind::<Text, _>(network)
+        .bind::<Text, _>(node.to_string())
+        .bind::<Double, _>(duration.as_secs_f64())
+        .execute(&conn)?;
+        Ok(rows > 0)
+    }
+}
+
+impl SubgraphFileStore for Store {
+    fn put_subgraph_file(&self, hash: &str, content: &[u8]) -> Result<(), StoreError> {
+        use diesel::sql_types::{Binary, Text};
+
+        let conn = self.get_conn()?;
+        diesel::sql_query(
+            "insert into subgraph_files(hash, content) values ($1, $2)
+             on conflict(hash) do nothing",
+        )
+        .bind::<Text, _>(hash)
+        .bind::<Binary, _>(content)
+        .execute(&conn)?;
+        Ok(())
+    }
+
+    fn subgraph_file(&self, hash: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        use diesel::sql_types::{Binary, Text};
+
+        #[derive(QueryableByName)]
+        struct File {
+            #[sql_type = "Binary"]
+            content: Vec<u8>,
+        }
+
+        let conn = self.get_conn()?;
+        Ok(
+            diesel::sql_query("select content from subgraph_files where hash = $1")
+                .bind::<Text, _>(hash)
+                .get_result::<File>(&conn)
+                .optional()?
+                .map(|file| file.content),
+        )
+    }
+}
+
 /// Deprecated format for the contract call id.
 fn old_contract_call_id(
     contract_address: &ethabi::Address,
@@ -1752,6 +2478,15 @@ fn contract_call_id(
     *hash.finalize().as_bytes()
 }
 
+/// The id of a call that returns the same for every block, like
+/// `contract_call_id` but without the block hash.
+fn immutable_contract_call_id(contract_address: &ethabi::Address, encoded_call: &[u8]) -> [u8; 32] {
+    let mut hash = blake3::Hasher::new();
+    hash.update(encoded_call);
+    hash.update(contract_address.as_ref());
+    *hash.finalize().as_bytes()
+}
+
 /// Delete all entities. This function exists solely for integration tests
 /// and should never be called from any other code. Unfortunately, Rust makes
 /// it very hard to export items just for testing
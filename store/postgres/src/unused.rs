@@ -0,0 +1,382 @@
+//! Find deployments that nothing uses any more and remove them to reclaim
+//! disk space. A deployment is unused when no subgraph has it as its
+//! current or pending version, it is not assigned to a node, and it was not
+//! queried during the grace period. Unused deployments are first recorded
+//! in `unused_deployments`, and only removed once they have been unused for
+//! the grace period. A deployment that gets used again before then is
+//! forgotten and kept.
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Double, Nullable, Text};
+use diesel::{sql_query, Connection, RunQueryDsl};
+use lazy_static::lazy_static;
+use std::time::Duration;
+
+use graph::prelude::{
+    debug, info, o, tokio, warn, CancelableError, Error, Logger, SubgraphDeploymentId,
+};
+
+use crate::store::Store;
+
+lazy_static! {
+    /// Whether index nodes remove unused deployments in the background
+    static ref REMOVE_UNUSED_DEPLOYMENTS: bool =
+        std::env::var("GRAPH_REMOVE_UNUSED_DEPLOYMENTS")
+            .unwrap_or("false".into())
+            .parse::<bool>()
+            .expect("invalid GRAPH_REMOVE_UNUSED_DEPLOYMENTS");
+
+    /// How long a deployment has to be unused before it is removed, in
+    /// seconds. Deployments queried during that time are not unused
+    static ref GRACE_PERIOD: Duration = Duration::from_secs(
+        std::env::var("GRAPH_UNUSED_DEPLOYMENT_GRACE_PERIOD")
+            .unwrap_or("604800".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_UNUSED_DEPLOYMENT_GRACE_PERIOD")
+    );
+}
+
+/// How often the background task looks for unused deployments
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How often each store records that a deployment was queried
+pub(crate) const QUERY_RECORD_INTERVAL: Duration = Duration::from_secs(300);
+
+/// The tables with the static metadata of a deployment, whose ids start
+/// with the id of the deployment, and of its dynamic data sources, whose
+/// ids start with the id of the data source
+const METADATA_TABLES: &[&str] = &[
+    "subgraph_deployment",
+    "subgraph_deployment_assignment",
+    "subgraph_manifest",
+    "ethereum_contract_data_source",
+    "ethereum_contract_data_source_template",
+    "ethereum_contract_data_source_template_source",
+    "ethereum_contract_source",
+    "ethereum_contract_mapping",
+    "ethereum_contract_abi",
+    "ethereum_contract_event_handler",
+    "ethereum_call_handler_entity",
+    "ethereum_block_handler_entity",
+    "ethereum_block_handler_filter_entity",
+];
+
+#[derive(Debug, QueryableByName)]
+pub struct UnusedDeployment {
+    #[sql_type = "Text"]
+    pub deployment: String,
+    #[sql_type = "Text"]
+    pub unused_at: String,
+    #[sql_type = "Nullable<Text>"]
+    pub removed_at: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct Deployment {
+    #[sql_type = "Text"]
+    deployment: String,
+}
+
+/// The condition for the deployment in `column` to be used by a subgraph
+/// name or a node
+fn in_use(column: &str) -> String {
+    format!(
+        "(exists (select 1
+                    from subgraphs.subgraph s, subgraphs.subgraph_version v
+                   where v.deployment = {d}
+                     and v.id in (s.current_version, s.pending_version)
+                     and upper_inf(s.block_range)
+                     and upper_inf(v.block_range))
+          or exists (select 1
+                       from subgraphs.subgraph_deployment_assignment a
+                      where a.id = {d}
+                        and upper_inf(a.block_range)))",
+        d = column
+    )
+}
+
+impl Deployment {
+    fn ids(rows: Vec<Deployment>) -> Vec<String> {
+        rows.into_iter().map(|row| row.deployment).collect()
+    }
+}
+
+pub(crate) fn record_query(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+) -> Result<(), Error> {
+    sql_query(
+        "insert into deployment_queries(deployment, last_queried_at)
+         values ($1, now())
+         on conflict(deployment) do update
+            set last_queried_at = excluded.last_queried_at",
+    )
+    .bind::<Text, _>(deployment.as_str())
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Forget the unused deployments that are used again. Returns their ids
+fn forget_used(conn: &PgConnection) -> Result<Vec<String>, Error> {
+    let query = format!(
+        "delete from unused_deployments u
+          where u.removed_at is null
+            and ({}
+                 or exists (select 1 from deployment_queries q
+                             where q.deployment = u.deployment
+                               and q.last_queried_at > u.unused_at))
+         returning u.deployment",
+        in_use("u.deployment")
+    );
+    Ok(Deployment::ids(sql_query(query).load(conn)?))
+}
+
+/// Record the deployments that became unused. Returns their ids
+fn record_unused(conn: &PgConnection, grace_period: Duration) -> Result<Vec<String>, Error> {
+    // A deployment that was removed before and deployed again starts over
+    let query = format!(
+        "insert into unused_deployments(deployment)
+         select ds.subgraph
+           from deployment_schemas ds
+          where ds.subgraph <> 'subgraphs'
+            and not {}
+            and not exists (select 1 from deployment_queries q
+                             where q.deployment = ds.subgraph
+                               and q.last_queried_at > now() - $1 * interval '1 second')
+         on conflict(deployment) do update
+            set unused_at = excluded.unused_at, removed_at = null
+          where unused_deployments.removed_at is not null
+         returning deployment",
+        in_use("ds.subgraph")
+    );
+    Ok(Deployment::ids(
+        sql_query(query)
+            .bind::<Double, _>(grace_period.as_secs_f64())
+            .load(conn)?,
+    ))
+}
+
+/// The unused deployments whose grace period has passed
+fn expired(conn: &PgConnection, grace_period: Duration) -> Result<Vec<String>, Error> {
+    let query = "
+        select deployment
+          from unused_deployments
+         where removed_at is null
+           and unused_at < now() - $1 * interval '1 second'
+         order by unused_at";
+    Ok(Deployment::ids(
+        sql_query(query)
+            .bind::<Double, _>(grace_period.as_secs_f64())
+            .load(conn)?,
+    ))
+}
+
+/// Drop the data and metadata of an unused deployment. Returns `false` if
+/// the deployment is not recorded as unused or is used again
+fn remove(conn: &PgConnection, deployment: &str) -> Result<bool, Error> {
+    conn.transaction(|| {
+        let query = format!(
+            "select u.deployment
+               from unused_deployments u
+              where u.deployment = $1
+                and u.removed_at is null
+                and not {}
+                for update",
+            in_use("u.deployment")
+        );
+        let unused = sql_query(query)
+            .bind::<Text, _>(deployment)
+            .load::<Deployment>(conn)?;
+        if unused.is_empty() {
+            return Ok(false);
+        }
+
+        #[derive(QueryableByName)]
+        struct SchemaName {
+            #[sql_type = "Text"]
+            name: String,
+        }
+        let schemas = sql_query("select name from deployment_schemas where subgraph = $1")
+            .bind::<Text, _>(deployment)
+            .load::<SchemaName>(conn)?;
+        for schema in schemas {
+            conn.batch_execute(&format!("drop schema if exists {} cascade", schema.name))?;
+        }
+        sql_query("delete from deployment_schemas where subgraph = $1")
+            .bind::<Text, _>(deployment)
+            .execute(conn)?;
+        remove_metadata(conn, deployment)?;
+        sql_query("update unused_deployments set removed_at = now() where deployment = $1")
+            .bind::<Text, _>(deployment)
+            .execute(conn)?;
+        Ok(true)
+    })
+}
+
+/// Delete everything that is recorded about `deployment` apart from its
+/// storage
+pub(crate) fn remove_metadata(conn: &PgConnection, deployment: &str) -> Result<(), Error> {
+    // Deployment ids are 46 characters long, and the ids of dynamic data
+    // sources 40. Their metadata must go before the data sources that lead
+    // to it
+    for table in METADATA_TABLES {
+        sql_query(format!(
+            "delete from subgraphs.{table} m
+              where left(m.id, 46) = $1
+                 or left(m.id, 40) in (select d.id
+                                         from subgraphs.dynamic_ethereum_contract_data_source d
+                                        where d.deployment = $1)",
+            table = table
+        ))
+        .bind::<Text, _>(deployment)
+        .execute(conn)?;
+    }
+    for query in &[
+        "delete from subgraphs.dynamic_ethereum_contract_data_source where deployment = $1",
+        "delete from subgraphs.subgraph_error where subgraph_id = $1",
+        "delete from subgraphs.subgraph_version where deployment = $1",
+        "delete from deployment_queries where deployment = $1",
+        "delete from query_allowlist where deployment = $1",
+        "delete from query_allowlist_deployments where deployment = $1",
+    ] {
+        sql_query(*query)
+            .bind::<Text, _>(deployment)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Work with the unused deployments from the command line, using its own
+/// connection
+pub struct UnusedDeployments {
+    conn: PgConnection,
+}
+
+impl UnusedDeployments {
+    pub fn connect(postgres_url: &str) -> Result<Self, Error> {
+        Ok(UnusedDeployments {
+            conn: PgConnection::establish(postgres_url)?,
+        })
+    }
+
+    /// The deployments that are unused, and with `all` also those that
+    /// were removed
+    pub fn list(&self, all: bool) -> Result<Vec<UnusedDeployment>, Error> {
+        let query = "
+            select deployment, unused_at::text as unused_at, removed_at::text as removed_at
+              from unused_deployments
+             where $1 or removed_at is null
+             order by unused_at";
+        Ok(sql_query(query)
+            .bind::<diesel::sql_types::Bool, _>(all)
+            .load::<UnusedDeployment>(&self.conn)?)
+    }
+
+    /// Look for deployments that became unused or are used again, like the
+    /// background task does. Returns the ids of the forgotten and the newly
+    /// recorded deployments
+    pub fn record(&self) -> Result<(Vec<String>, Vec<String>), Error> {
+        let forgotten = forget_used(&self.conn)?;
+        let recorded = record_unused(&self.conn, *GRACE_PERIOD)?;
+        Ok((forgotten, recorded))
+    }
+
+    /// The unused deployments whose grace period has passed
+    pub fn expired(&self) -> Result<Vec<String>, Error> {
+        expired(&self.conn, *GRACE_PERIOD)
+    }
+
+    /// Remove an unused deployment, even if its grace period has not
+    /// passed yet. Returns `false` if it is not recorded as unused or is
+    /// used again
+    pub fn remove(&self, deployment: &str) -> Result<bool, Error> {
+        remove(&self.conn, deployment)
+    }
+
+    /// Forget that a deployment is unused. It is recorded again, with a new
+    /// grace period, the next time unused deployments are looked for unless
+    /// it gets used in the meantime. Returns `false` if it was not recorded
+    /// as unused or has already been removed
+    pub fn restore(&self, deployment: &str) -> Result<bool, Error> {
+        let rows = sql_query(
+            "delete from unused_deployments where deployment = $1 and removed_at is null",
+        )
+        .bind::<Text, _>(deployment)
+        .execute(&self.conn)?;
+        Ok(rows > 0)
+    }
+}
+
+/// Periodically records deployments that became unused, forgets those
+/// that are used again, and removes those that have been unused for the
+/// grace period
+pub struct UnusedDeploymentRemover {
+    logger: Logger,
+    store: Store,
+}
+
+impl UnusedDeploymentRemover {
+    /// Whether `GRAPH_REMOVE_UNUSED_DEPLOYMENTS` asks for the removal of
+    /// unused deployments
+    pub fn enabled() -> bool {
+        *REMOVE_UNUSED_DEPLOYMENTS
+    }
+
+    pub fn new(logger: &Logger, store: Store) -> Self {
+        UnusedDeploymentRemover {
+            logger: logger.new(o!("component" => "UnusedDeploymentRemover")),
+            store,
+        }
+    }
+
+    pub async fn run(self) {
+        info!(self.logger, "Removing unused deployments";
+              "grace_period_s" => GRACE_PERIOD.as_secs());
+        loop {
+            tokio::time::delay_for(CHECK_INTERVAL).await;
+            if let Err(e) = self.check().await {
+                warn!(self.logger, "Failed to look for unused deployments"; "error" => e.to_string());
+            }
+        }
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        let grace_period = *GRACE_PERIOD;
+        let (forgotten, recorded, expired) = self
+            .store
+            .with_conn(move |conn, _| {
+                let forgotten = forget_used(conn)?;
+                let recorded = record_unused(conn, grace_period)?;
+                let expired = expired(conn, grace_period)?;
+                Ok((forgotten, recorded, expired)).map_err(CancelableError::from)
+            })
+            .await?;
+
+        for deployment in forgotten {
+            info!(self.logger, "Deployment is used again and will be kept";
+                  "deployment" => deployment);
+        }
+        for deployment in recorded {
+            info!(self.logger, "Deployment is unused and will be removed after the grace period";
+                  "deployment" => deployment,
+                  "grace_period_s" => grace_period.as_secs());
+        }
+        for deployment in expired {
+            let id = deployment.clone();
+            let removed = self
+                .store
+                .with_conn(move |conn, _| remove(conn, &id).map_err(CancelableError::from))
+                .await?;
+            if removed {
+                if let Ok(id) = SubgraphDeploymentId::new(deployment.clone()) {
+                    self.store.forget_deployment(&id);
+                }
+                info!(self.logger, "Removed unused deployment"; "deployment" => deployment);
+            } else {
+                debug!(self.logger, "Deployment is used again and was not removed";
+                       "deployment" => deployment);
+            }
+        }
+        Ok(())
+    }
+}
@@ -223,3 +223,31 @@ fn block_hashes_by_number() {
         Ok(())
     })
 }
+
+#[test]
+fn inconsistent_block_numbers() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_ONE_SIBLING,
+        &*BLOCK_TWO,
+        &*BLOCK_THREE_NO_PARENT,
+        &*BLOCK_FOUR,
+    ];
+    run_test(chain, move |store| -> Result<(), ()> {
+        // Block one has a sibling, block two is not the parent of the
+        // cached block three, and that is not the parent of block four
+        let numbers = store.inconsistent_block_numbers(0, 10).unwrap();
+        assert_eq!(vec![1, 2, 3], numbers);
+
+        let numbers = store.inconsistent_block_numbers(2, 2).unwrap();
+        assert_eq!(vec![2], numbers);
+
+        store
+            .confirm_block_hash(1, &BLOCK_ONE.block_hash())
+            .unwrap();
+        let numbers = store.inconsistent_block_numbers(0, 1).unwrap();
+        assert!(numbers.is_empty());
+        Ok(())
+    })
+}
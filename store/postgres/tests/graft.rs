@@ -2,6 +2,7 @@ use diesel::pg::PgConnection;
 use diesel::*;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use test_store::*;
 
@@ -11,7 +12,7 @@ use graph::data::subgraph::schema::*;
 use graph::data::subgraph::*;
 use graph::prelude::*;
 use graph_store_postgres::Store as DieselStore;
-use web3::types::H256;
+use web3::types::{Address, H256};
 
 const USER_GQL: &str = "
 enum Color { yellow, red, blue, green }
@@ -54,6 +55,41 @@ type User @entity {
 }
 ";
 
+const MIGRATE_GQL: &str = "
+enum Color { yellow, red, blue, green }
+
+interface ColorAndAge {
+    id: ID!,
+    age: Int,
+    favorite_color: Color
+}
+
+type User implements ColorAndAge @entity {
+    id: ID!,
+    name: String,
+    bin_name: Bytes,
+    email: String,
+    age: Int,
+    seconds_age: BigInt,
+    weight: BigDecimal,
+    coffee: Boolean,
+    favorite_color: Color,
+    nickname: String
+}
+
+type Person implements ColorAndAge @entity {
+    id: ID!,
+    name: String,
+    age: Int,
+    favorite_color: Color
+}
+
+type Pet @entity {
+    id: ID!,
+    name: String
+}
+";
+
 const USER: &str = "User";
 
 macro_rules! block_pointer {
@@ -80,6 +116,10 @@ lazy_static! {
             "b98fb783b49de5652097a989414c767824dff7e7fd765a63b493772511db81c1",
             2
         ),
+        block_pointer!(
+            "7347afe69254df06729e123610b00b8b11f15cfae3241f9366fb113aec07489c",
+            3
+        ),
     ];
 }
 
@@ -127,14 +167,17 @@ fn insert_test_data(store: Arc<DieselStore>) {
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        migrate: None,
         templates: vec![],
+        features: BTreeSet::new(),
+        indexer_hints: IndexerHints::default(),
     };
 
     // Create SubgraphDeploymentEntity
     let ops =
         SubgraphDeploymentEntity::new(&manifest, false, None).create_operations(&*TEST_SUBGRAPH_ID);
     store
-        .create_subgraph_deployment(&TEST_SUBGRAPH_SCHEMA, ops)
+        .create_subgraph_deployment(&TEST_SUBGRAPH_SCHEMA, &IndexerHints::default(), ops)
         .unwrap();
 
     let test_entity_1 = create_test_entity(
@@ -323,3 +366,159 @@ fn graft() {
         Ok(())
     })
 }
+
+#[test]
+fn migrate() {
+    run_test(move |store| -> Result<(), ()> {
+        // Only relational storage can be taken over
+        if !*USING_RELATIONAL_STORAGE {
+            return Ok(());
+        }
+
+        const SUBGRAPH: &str = "migrated";
+        let subgraph_id = SubgraphDeploymentId::new(SUBGRAPH).unwrap();
+
+        // Give the base a proof of indexing and a dynamic data source
+        let poi_key = |subgraph_id: &SubgraphDeploymentId| EntityKey {
+            subgraph_id: subgraph_id.clone(),
+            entity_type: POI_OBJECT.to_owned(),
+            entity_id: "ethereum/mainnet".to_owned(),
+        };
+        let mut poi = Entity::new();
+        poi.set("id", "ethereum/mainnet");
+        poi.set(
+            "digest",
+            Value::Bytes(scalar::Bytes::from_str("0102030405").unwrap()),
+        );
+        let data_source_id = DynamicEthereumContractDataSourceEntity::make_id();
+        let data_source = DynamicEthereumContractDataSourceEntity::from((
+            &*TEST_SUBGRAPH_ID,
+            &mock_data_source(),
+            &BLOCKS[3],
+        ));
+        let mut ops = vec![EntityOperation::Set {
+            key: poi_key(&TEST_SUBGRAPH_ID),
+            data: poi.clone(),
+        }];
+        ops.extend(data_source.write_entity_operations(&data_source_id));
+        transact_entity_operations(&store, TEST_SUBGRAPH_ID.clone(), BLOCKS[3], ops).unwrap();
+
+        // The registrar only lets a new version take over the storage of
+        // a paused one
+        store
+            .apply_metadata_operations(
+                SubgraphDeploymentAssignmentEntity::new(NodeId::new("test").unwrap())
+                    .paused(true)
+                    .write_operations(&TEST_SUBGRAPH_ID),
+            )
+            .unwrap();
+
+        let schema = Schema::parse(MIGRATE_GQL, subgraph_id.clone()).unwrap();
+        let manifest = SubgraphManifest {
+            id: subgraph_id.clone(),
+            location: "/ipfs/migrated".to_owned(),
+            spec_version: "1".to_owned(),
+            description: None,
+            repository: None,
+            schema: schema.clone(),
+            data_sources: vec![],
+            graft: None,
+            migrate: None,
+            templates: vec![],
+            features: BTreeSet::new(),
+            indexer_hints: IndexerHints::default(),
+        };
+        let ops = SubgraphDeploymentEntity::new(&manifest, false, None)
+            .migrate(Some(TEST_SUBGRAPH_ID.clone()))
+            .create_operations(&subgraph_id);
+        store
+            .create_subgraph_deployment(&schema, &IndexerHints::default(), ops)
+            .expect("the new version can take over the storage of the base");
+
+        // The new version continues where the base stopped
+        assert_eq!(
+            Some(BLOCKS[3]),
+            store.block_ptr(subgraph_id.clone()).unwrap()
+        );
+
+        // The entities of the base are now those of the new version
+        let query = EntityQuery::new(
+            subgraph_id.clone(),
+            BLOCK_NUMBER_MAX,
+            EntityCollection::All(vec![USER.to_owned()]),
+        )
+        .order(EntityOrder::Ascending("id".to_string(), ValueType::String));
+        let entities = store
+            .find(query)
+            .expect("store.find failed to execute query");
+        let ids = entities
+            .iter()
+            .map(|entity| entity.id().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["1", "2", "3"], ids);
+        assert_eq!(
+            Some(&Value::from("teeko@email.com")),
+            entities[2].get("email")
+        );
+
+        // The new entity type starts out empty
+        let query = EntityQuery::new(
+            subgraph_id.clone(),
+            BLOCK_NUMBER_MAX,
+            EntityCollection::All(vec!["Pet".to_owned()]),
+        );
+        assert!(store.find(query).unwrap().is_empty());
+
+        // So are the proof of indexing and the dynamic data source
+        assert_eq!(Some(poi), store.get(poi_key(&subgraph_id)).unwrap());
+        let data_source = store
+            .get(DynamicEthereumContractDataSourceEntity::key(data_source_id))
+            .unwrap()
+            .expect("the dynamic data source survives the migration");
+        assert_eq!(Some(&Value::from(SUBGRAPH)), data_source.get("deployment"));
+
+        // Nothing is left of the base
+        assert!(store
+            .get(SubgraphDeploymentEntity::key(TEST_SUBGRAPH_ID.clone()))
+            .unwrap()
+            .is_none());
+
+        Ok(())
+    })
+}
+
+fn mock_data_source() -> DataSource {
+    DataSource {
+        kind: String::from("ethereum/contract"),
+        name: String::from("example data source"),
+        network: Some(String::from("mainnet")),
+        source: Source {
+            address: Some(Address::from_low_u64_be(1)),
+            addresses: vec![],
+            address_file: None,
+            abi: String::from("123123"),
+            subgraph: None,
+            start_block: 0,
+            implementations: vec![],
+            file: None,
+        },
+        mapping: Mapping {
+            kind: String::from("ethereum/events"),
+            api_version: String::from("0.1.0"),
+            language: String::from("wasm/assemblyscript"),
+            entities: vec![],
+            abis: vec![],
+            event_handlers: vec![],
+            call_handlers: vec![],
+            block_handlers: vec![],
+            entity_handlers: vec![],
+            handler: None,
+            link: Link {
+                link: "link".to_owned(),
+            },
+            runtime: Arc::new(Vec::new()),
+        },
+        templates: vec![],
+        context: None,
+    }
+}
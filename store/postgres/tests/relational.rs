@@ -736,6 +736,47 @@ fn find_interface() {
     );
 }
 
+#[test]
+fn find_interface_with_shared_ids() {
+    run_test(|conn, layout| -> Result<(), ()> {
+        insert_pet(conn, layout, "Ferret", "pluto", "Pluto");
+        insert_pet(conn, layout, "Dog", "pluto", "Pluto");
+        insert_pet(conn, layout, "Cat", "pluto", "Pluto");
+
+        // Entities that tie on the sort key and the id come back ordered
+        // by their type, so that paging returns each of them once
+        let mut typenames = Vec::new();
+        for skip in 0..3 {
+            let query = query(vec!["Cat", "Dog", "Ferret"])
+                .asc("name")
+                .first(1)
+                .skip(skip);
+            let entities = layout
+                .query::<Entity>(
+                    &*LOGGER,
+                    conn,
+                    query.collection,
+                    query.filter,
+                    query.order,
+                    query.range,
+                    BLOCK_NUMBER_MAX,
+                )
+                .expect("layout.query failed to execute query");
+            typenames.extend(
+                entities
+                    .into_iter()
+                    .map(|entity| entity.get("__typename").cloned()),
+            );
+        }
+        let expected: Vec<_> = vec!["Cat", "Dog", "Ferret"]
+            .into_iter()
+            .map(|typename| Some(Value::from(typename)))
+            .collect();
+        assert_eq!(expected, typenames);
+        Ok(())
+    })
+}
+
 #[test]
 fn find_string_contains() {
     test_find(
@@ -877,6 +918,41 @@ fn find_string_not_ends_with() {
     )
 }
 
+#[test]
+fn find_string_nocase() {
+    test_find(
+        vec!["2"],
+        user_query().filter(EntityFilter::ContainsNoCase(
+            "name".to_owned(),
+            "NDI".into(),
+        )),
+    );
+    test_find(
+        vec!["3"],
+        user_query().filter(EntityFilter::StartsWithNoCase(
+            "name".to_owned(),
+            "shaq".into(),
+        )),
+    );
+    test_find(
+        vec!["1"],
+        user_query().filter(EntityFilter::EndsWithNoCase(
+            "name".to_owned(),
+            "ONO".into(),
+        )),
+    )
+}
+
+#[test]
+fn find_string_matches() {
+    test_find(
+        vec!["2", "1"],
+        user_query()
+            .filter(EntityFilter::Matches("name".to_owned(), "^[CJ].*n".into()))
+            .asc("name"),
+    )
+}
+
 #[test]
 fn find_string_in() {
     test_find(
@@ -1147,6 +1223,46 @@ fn find_int_not_in() {
     )
 }
 
+#[test]
+fn find_bigint_greater_than() {
+    // Compared as strings, "883612800" would be greater than "1000000000"
+    test_find(
+        vec!["2", "1"],
+        user_query()
+            .filter(EntityFilter::GreaterThan(
+                "seconds_age".to_owned(),
+                Value::BigInt(BigInt::from(1000000000)),
+            ))
+            .asc("name"),
+    )
+}
+
+#[test]
+fn find_bigint_in() {
+    test_find(
+        vec!["3", "1"],
+        user_query()
+            .filter(EntityFilter::In(
+                "seconds_age".to_owned(),
+                vec![
+                    Value::BigInt(BigInt::from(883612800)),
+                    Value::String("2114359200".to_owned()),
+                ],
+            ))
+            .desc("name"),
+    );
+    test_find(
+        vec!["2"],
+        user_query().filter(EntityFilter::NotIn(
+            "seconds_age".to_owned(),
+            vec![
+                Value::BigInt(BigInt::from(883612800)),
+                Value::BigInt(BigInt::from(2114359200u64)),
+            ],
+        )),
+    )
+}
+
 #[test]
 fn find_bool_equal() {
     test_find(
@@ -1274,6 +1390,13 @@ fn find_order_by_int() {
     test_find(vec!["1", "2", "3"], user_query().desc("age"));
 }
 
+#[test]
+fn find_order_by_bool() {
+    // Users with the same value are ordered by their id
+    test_find(vec!["1", "3", "2"], user_query().asc("coffee"));
+    test_find(vec!["2", "1", "3"], user_query().desc("coffee"));
+}
+
 #[test]
 fn find_order_by_string() {
     test_find(vec!["2", "1", "3"], user_query().asc("name"));
@@ -4,7 +4,7 @@ use graph_mock::MockMetricsRegistry;
 use graphql_parser::schema as s;
 use hex_literal::hex;
 use lazy_static::lazy_static;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::str::FromStr;
 use std::time::Duration;
 use test_store::*;
@@ -14,6 +14,7 @@ use graph::data::store::scalar;
 use graph::data::subgraph::schema::*;
 use graph::data::subgraph::*;
 use graph::prelude::*;
+use graph_store_postgres::ens;
 use graph_store_postgres::layout_for_tests::STRING_PREFIX_SIZE;
 use graph_store_postgres::Store as DieselStore;
 use web3::types::{Address, H256};
@@ -160,14 +161,17 @@ fn insert_test_data(store: Arc<DieselStore>) {
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        migrate: None,
         templates: vec![],
+        features: BTreeSet::new(),
+        indexer_hints: IndexerHints::default(),
     };
 
     // Create SubgraphDeploymentEntity
     let ops =
         SubgraphDeploymentEntity::new(&manifest, false, None).create_operations(&*TEST_SUBGRAPH_ID);
     store
-        .create_subgraph_deployment(&TEST_SUBGRAPH_SCHEMA, ops)
+        .create_subgraph_deployment(&TEST_SUBGRAPH_SCHEMA, &IndexerHints::default(), ops)
         .unwrap();
 
     let test_entity_1 = create_test_entity(
@@ -548,6 +552,80 @@ fn partially_update_existing() {
     })
 }
 
+#[test]
+fn transact_several_blocks() {
+    run_test(|store| -> Result<(), ()> {
+        let entity_key = EntityKey {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_type: USER.to_owned(),
+            entity_id: "7".to_owned(),
+        };
+        let mut data = match create_test_entity(
+            "7",
+            USER,
+            "Wanjon",
+            "wanawana@email.com",
+            76 as i32,
+            111.7,
+            true,
+            Some("green"),
+        ) {
+            EntityOperation::Set { data, .. } => data,
+            _ => unreachable!(),
+        };
+        data.set("__typename", USER);
+        let mut updated = data.clone();
+        updated.set("name", "Wanawana");
+
+        let stopwatch = StopwatchMetrics::new(
+            Logger::root(slog::Discard, o!()),
+            TEST_SUBGRAPH_ID.clone(),
+            Arc::new(MockMetricsRegistry::new()),
+        );
+        store
+            .transact_blocks_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                vec![
+                    BlockModifications {
+                        block_ptr: *TEST_BLOCK_3_PTR,
+                        mods: vec![EntityModification::Insert {
+                            key: entity_key.clone(),
+                            data: data.clone(),
+                        }],
+                        deterministic_errors: vec![],
+                    },
+                    BlockModifications {
+                        block_ptr: *TEST_BLOCK_4_PTR,
+                        mods: vec![EntityModification::Overwrite {
+                            key: entity_key.clone(),
+                            data: updated.clone(),
+                        }],
+                        deterministic_errors: vec![],
+                    },
+                ],
+                stopwatch,
+            )
+            .unwrap();
+        assert_eq!(
+            Some(*TEST_BLOCK_4_PTR),
+            store.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap()
+        );
+        assert_eq!(Some(updated), store.get(entity_key.clone()).unwrap());
+
+        // The changes of each block are recorded at that block
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_4_PTR,
+                *TEST_BLOCK_3_PTR,
+            )
+            .unwrap();
+        assert_eq!(Some(data), store.get(entity_key).unwrap());
+
+        Ok(())
+    })
+}
+
 fn test_find(expected_entity_ids: Vec<&str>, query: EntityQuery) {
     let expected_entity_ids: Vec<String> =
         expected_entity_ids.into_iter().map(str::to_owned).collect();
@@ -1406,8 +1484,13 @@ fn mock_data_source() -> DataSource {
         network: Some(String::from("mainnet")),
         source: Source {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
+            addresses: vec![],
+            address_file: None,
             abi: String::from("123123"),
+            subgraph: None,
             start_block: 0,
+            implementations: vec![],
+            file: None,
         },
         mapping: Mapping {
             kind: String::from("ethereum/events"),
@@ -1418,6 +1501,8 @@ fn mock_data_source() -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
+            handler: None,
             link: Link {
                 link: "link".to_owned(),
             },
@@ -1439,6 +1524,8 @@ fn mock_data_source() -> DataSource {
                 event_handlers: vec![],
                 call_handlers: vec![],
                 block_handlers: vec![],
+                entity_handlers: vec![],
+                handler: None,
                 link: Link {
                     link: "link".to_owned(),
                 },
@@ -1601,13 +1688,18 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             schema: schema.clone(),
             data_sources: vec![],
             graft: None,
+            migrate: None,
             templates: vec![],
+            features: BTreeSet::new(),
+            indexer_hints: IndexerHints::default(),
         };
 
         // Create SubgraphDeploymentEntity
         let ops = SubgraphDeploymentEntity::new(&manifest, false, Some(*TEST_BLOCK_0_PTR))
             .create_operations(&subgraph_id);
-        store.create_subgraph_deployment(&schema, ops).unwrap();
+        store
+            .create_subgraph_deployment(&schema, &IndexerHints::default(), ops)
+            .unwrap();
 
         // Create store subscriptions
         let meta_subscription =
@@ -1942,6 +2034,7 @@ fn handle_large_string_with_index() {
                     make_insert_op(TWO, &other_text),
                 ],
                 stopwatch_metrics,
+                vec![],
             )
             .expect("Failed to insert large text");
 
@@ -2225,6 +2318,58 @@ fn find_at_block() {
     }
 }
 
+#[test]
+fn entity_changes_in_block() {
+    // These tests only make sense with relational storage
+    if !*USING_RELATIONAL_STORAGE {
+        return;
+    }
+
+    run_test(|store| -> Result<(), ()> {
+        let changes = |block: BlockNumber| {
+            store
+                .entity_changes_in_block(&*TEST_SUBGRAPH_ID, &[USER.to_owned()], block)
+                .expect("entity_changes_in_block failed")
+                .into_iter()
+                .map(|(entity_type, entity)| {
+                    (
+                        entity_type,
+                        entity.id().unwrap(),
+                        entity.get("email").cloned().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                (
+                    USER.to_owned(),
+                    "2".to_owned(),
+                    Value::from("dinici@email.com")
+                ),
+                (
+                    USER.to_owned(),
+                    "3".to_owned(),
+                    Value::from("queensha@email.com")
+                ),
+            ],
+            changes(1)
+        );
+        assert_eq!(
+            vec![(
+                USER.to_owned(),
+                "3".to_owned(),
+                Value::from("teeko@email.com")
+            )],
+            changes(2)
+        );
+        assert!(changes(3).is_empty());
+
+        Ok(())
+    });
+}
+
 #[test]
 fn cleanup_cached_blocks() {
     run_test(|store| -> Result<(), ()> {
@@ -2237,3 +2382,98 @@ fn cleanup_cached_blocks() {
         Ok(())
     })
 }
+
+#[test]
+fn non_fatal_errors() {
+    run_test(|store| -> Result<(), ()> {
+        assert!(!store
+            .has_non_fatal_errors(&*TEST_SUBGRAPH_ID, None)
+            .expect("can check for errors"));
+
+        let error = SubgraphError {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            message: "handler failed".to_owned(),
+            block_ptr: Some(*TEST_BLOCK_3_PTR),
+            handler: Some("handleEvent".to_owned()),
+        };
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let stopwatch_metrics = StopwatchMetrics::new(
+            Logger::root(slog::Discard, o!()),
+            TEST_SUBGRAPH_ID.clone(),
+            metrics_registry.clone(),
+        );
+        store
+            .transact_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_3_PTR,
+                vec![],
+                stopwatch_metrics,
+                vec![error],
+            )
+            .expect("Failed to record error");
+
+        assert!(store
+            .has_non_fatal_errors(&*TEST_SUBGRAPH_ID, None)
+            .expect("can check for errors"));
+        assert!(store
+            .has_non_fatal_errors(&*TEST_SUBGRAPH_ID, Some(3))
+            .expect("can check for errors"));
+        assert!(!store
+            .has_non_fatal_errors(&*TEST_SUBGRAPH_ID, Some(2))
+            .expect("can check for errors"));
+        Ok(())
+    })
+}
+
+#[test]
+fn import_ens_names() {
+    run_test(|store| -> Result<(), ()> {
+        let hash = "0x7f0c1b04d1a4926f9c635a030eeb611d4c26e5e73291b32a1c7a4ac56935b5b3";
+        assert_eq!(hash, ens::label_hash("dealdrafts"));
+
+        let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+        let names = vec!["dealdrafts".to_owned(), "dealdrafts".to_owned()];
+        ens::insert_names(&conn, &names).expect("names can be imported");
+
+        assert_eq!(
+            Some("dealdrafts".to_owned()),
+            store.find_ens_name(hash).expect("name lookup succeeds")
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn deployment_storage() {
+    run_test(|store| -> Result<(), ()> {
+        let storage = store
+            .deployment_storage(Some(vec![TEST_SUBGRAPH_ID.clone()]))
+            .expect("can get deployment storage");
+        assert_eq!(1, storage.len());
+        let storage = &storage[0];
+        assert_eq!(*TEST_SUBGRAPH_ID, storage.subgraph);
+        assert_eq!(
+            store
+                .block_ptr(TEST_SUBGRAPH_ID.clone())
+                .unwrap()
+                .map(|ptr| ptr.number),
+            storage.latest_block
+        );
+
+        let users = storage
+            .tables
+            .iter()
+            .find(|table| table.entity_type.as_deref() == Some(USER))
+            .expect("there is a table for users");
+        assert!(users.table_bytes > 0);
+        assert!(storage.total_bytes() >= users.table_bytes + users.index_bytes);
+
+        // Unknown deployments are ignored
+        let unknown = SubgraphDeploymentId::new("QmNotADeployment").unwrap();
+        assert!(store
+            .deployment_storage(Some(vec![unknown]))
+            .expect("can get deployment storage")
+            .is_empty());
+        Ok(())
+    })
+}
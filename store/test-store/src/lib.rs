@@ -9,10 +9,11 @@ use graph_graphql::prelude::{
     execute_query, Query as PreparedQuery, QueryExecutionOptions, StoreResolver,
 };
 use graph_mock::MockMetricsRegistry;
-use graph_store_postgres::connection_pool::create_connection_pool;
+use graph_store_postgres::connection_pool::{create_connection_pool, ConnectionPools, MAIN_POOL};
 use graph_store_postgres::{ChainHeadUpdateListener, Store, StoreConfig, SubscriptionManager};
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeSet;
 use std::env;
 use std::sync::{Mutex, RwLock};
 use std::time::Instant;
@@ -58,6 +59,7 @@ lazy_static! {
                 };
                 let conn_pool_size: u32 = 20;
                 let postgres_conn_pool = create_connection_pool(
+                    MAIN_POOL,
                     postgres_url.clone(),
                     conn_pool_size,
                     &logger,
@@ -78,12 +80,13 @@ lazy_static! {
                     StoreConfig {
                         postgres_url,
                         network_name: NETWORK_NAME.to_owned(),
+                        attribute_cipher: None,
                     },
                     &logger,
                     net_identifiers,
                     chain_head_update_listener,
                     subscriptions,
-                    postgres_conn_pool,
+                    ConnectionPools::shared(postgres_conn_pool),
                     Vec::new(),
                     registry.clone(),
                 ))
@@ -130,7 +133,10 @@ fn create_subgraph(
         schema: schema.clone(),
         data_sources: vec![],
         graft: None,
+        migrate: None,
         templates: vec![],
+        features: BTreeSet::new(),
+        indexer_hints: IndexerHints::default(),
     };
 
     let ops = SubgraphDeploymentEntity::new(&manifest, false, None)
@@ -139,7 +145,7 @@ fn create_subgraph(
         .into_iter()
         .map(|op| op.into())
         .collect();
-    STORE.create_subgraph_deployment(&schema, ops)?;
+    STORE.create_subgraph_deployment(&schema, &IndexerHints::default(), ops)?;
     STORE.start_subgraph_deployment(&*LOGGER, &subgraph_id, vec![])
 }
 
@@ -178,7 +184,7 @@ pub fn transact_entity_operations(
         subgraph_id.clone(),
         metrics_registry.clone(),
     );
-    store.transact_block_operations(subgraph_id, block_ptr_to, mods, stopwatch_metrics)
+    store.transact_block_operations(subgraph_id, block_ptr_to, mods, stopwatch_metrics, vec![])
 }
 
 pub fn insert_ens_name(hash: &str, name: &str) {
@@ -394,12 +400,13 @@ fn execute_subgraph_query_internal(
     let logger = Logger::root(slog::Discard, o!());
     let query = return_err!(PreparedQuery::new(query, max_complexity, 100));
     let mut result = QueryResult::empty();
-    for (bc, selection_set) in return_err!(query.block_constraint()) {
+    for ((bc, error_policy), selection_set) in return_err!(query.block_constraint()) {
         let logger = logger.clone();
         let (resolver, _block_ptr) = return_err!(StoreResolver::at_block(
             &logger,
             STORE.clone(),
             bc,
+            error_policy,
             &query.schema.id
         ));
         result.append(